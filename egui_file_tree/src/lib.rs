@@ -12,7 +12,7 @@
 //!
 //! ## 基本使用
 //!
-//! ```rust
+//! ```rust,ignore
 //! use egui_file_tree::FileTree;
 //! use std::path::PathBuf;
 //!
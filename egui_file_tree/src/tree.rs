@@ -71,8 +71,8 @@ impl FileTree {
     }
 
     /// 展开指定路径
-    pub fn expand_path(&mut self, path: &PathBuf) {
-        self.expanded.insert(path.clone());
+    pub fn expand_path(&mut self, path: &Path) {
+        self.expanded.insert(path.to_path_buf());
     }
 
     /// 折叠指定路径
@@ -113,21 +113,20 @@ impl FileTree {
         events: &mut Vec<FileTreeEvent>,
     ) {
         // 如果是根目录且indent_level为0，显示"../"选项
-        if indent_level == 0 && dir_path == self.root_path.as_path() {
-            if dir_path.parent().is_some() {
-                ui.horizontal(|ui| {
-                    ui.add_space(indent_level as f32 * 20.0);
-                    ui.add_space(16.0); // 占位，对齐展开按钮
+        if indent_level == 0 && dir_path == self.root_path.as_path() && dir_path.parent().is_some()
+        {
+            ui.horizontal(|ui| {
+                ui.add_space(indent_level as f32 * 20.0);
+                ui.add_space(16.0); // 占位，对齐展开按钮
                     
-                    let label_text = "📁 ../";
-                    let response = ui.selectable_label(false, label_text)
-                        .on_hover_cursor(CursorIcon::PointingHand);
+                let label_text = "📁 ../";
+                let response = ui.selectable_label(false, label_text)
+                    .on_hover_cursor(CursorIcon::PointingHand);
                     
-                    if response.clicked() {
-                        events.push(FileTreeEvent::NavigateToParent);
-                    }
-                });
-            }
+                if response.clicked() {
+                    events.push(FileTreeEvent::NavigateToParent);
+                }
+            });
         }
 
         // 读取目录内容
@@ -173,7 +172,7 @@ impl FileTree {
 
             let path_buf = path.clone();
             let is_expanded = self.expanded.contains(&path_buf);
-            let is_selected = self.selected.as_ref().map_or(false, |s| s == &path_buf);
+            let is_selected = self.selected.as_ref() == Some(&path_buf);
 
             ui.horizontal(|ui| {
                 // 缩进
@@ -209,12 +208,13 @@ impl FileTree {
                     if let Some(ext) = path_buf.extension() {
                         if ext == "midiclip" {
                             // 检测鼠标按下
-                            if response.is_pointer_button_down_on() {
-                                if self.dragging_path.is_none() && self.drag_start.is_none() {
-                                    // 记录拖拽开始位置和文件路径
-                                    if let Some(pointer) = response.interact_pointer_pos() {
-                                        self.drag_start = Some((path_buf.clone(), pointer));
-                                    }
+                            if response.is_pointer_button_down_on()
+                                && self.dragging_path.is_none()
+                                && self.drag_start.is_none()
+                            {
+                                // 记录拖拽开始位置和文件路径
+                                if let Some(pointer) = response.interact_pointer_pos() {
+                                    self.drag_start = Some((path_buf.clone(), pointer));
                                 }
                             }
                             
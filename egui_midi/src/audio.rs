@@ -2,6 +2,9 @@ use crossbeam_channel::{unbounded, Receiver, Sender};
 use rodio::{OutputStream, OutputStreamHandle, Source};
 use std::time::Duration;
 
+/// 内置引擎的弯音范围（± 半音），与通用 MIDI 的默认值一致
+pub const PITCH_BEND_RANGE_SEMITONES: f32 = 2.0;
+
 /// 宿主可替换的播放后端抽象。
 pub trait PlaybackBackend {
     fn note_on(&self, key: u8, velocity: u8);
@@ -9,6 +12,14 @@ pub trait PlaybackBackend {
     fn all_notes_off(&self);
     fn set_volume(&self, volume: f32);
     fn set_pitch_shift(&self, semitones: f32);
+    /// 播放时 CC 曲线的值（取整后变化时才调用）；默认忽略，内置的正弦波引擎也不处理控制器
+    fn control_change(&self, _cc: u8, _value: u8) {}
+    /// 播放时弯音曲线的值（-8192..8191，变化时才调用）；默认忽略
+    fn pitch_bend(&self, _value: i16) {}
+    /// 节拍器的一声（`accented` 为小节第一拍）；默认忽略
+    fn click(&self, _accented: bool) {}
+    /// 节拍器音量（0.0..=1.0），与音符音量分开；默认忽略
+    fn set_click_volume(&self, _volume: f32) {}
 }
 
 pub trait PlaybackObserver: Send + Sync {
@@ -23,6 +34,12 @@ pub struct AudioEngine {
     sender: Sender<AudioMessage>,
 }
 
+impl Default for AudioEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AudioEngine {
     pub fn new() -> Self {
         Self::with_config(SineSynthConfig::default())
@@ -68,6 +85,18 @@ impl PlaybackBackend for AudioEngine {
     fn set_pitch_shift(&self, semitones: f32) {
         self.dispatch(AudioMessage::SetPitchShift(semitones));
     }
+
+    fn pitch_bend(&self, value: i16) {
+        self.dispatch(AudioMessage::PitchBend(value));
+    }
+
+    fn click(&self, accented: bool) {
+        self.dispatch(AudioMessage::Click { accented });
+    }
+
+    fn set_click_volume(&self, volume: f32) {
+        self.dispatch(AudioMessage::SetClickVolume(volume));
+    }
 }
 
 /// 空实现，允许宿主禁用音频输出。
@@ -112,6 +141,9 @@ enum AudioMessage {
     AllNotesOff,
     SetVolume(f32),
     SetPitchShift(f32),
+    PitchBend(i16),
+    Click { accented: bool },
+    SetClickVolume(f32),
 }
 
 struct PolyphonicSynth {
@@ -121,6 +153,9 @@ struct PolyphonicSynth {
     volume: f32,
     config: SineSynthConfig,
     pitch_shift: f32,
+    pitch_bend: f32, // 当前弯音（半音）
+    clicks: Vec<Click>,
+    click_volume: f32,
 }
 
 impl PolyphonicSynth {
@@ -132,6 +167,21 @@ impl PolyphonicSynth {
             volume: 0.5,
             config,
             pitch_shift: 0.0,
+            pitch_bend: 0.0,
+            clicks: Vec::new(),
+            click_volume: 0.5,
+        }
+    }
+
+    /// 预听移调与弯音叠加后的总移调
+    fn total_shift(&self) -> f32 {
+        self.pitch_shift + self.pitch_bend
+    }
+
+    fn retune_voices(&mut self) {
+        let shift = self.total_shift();
+        for voice in &mut self.voices {
+            voice.set_pitch_shift(shift);
         }
     }
 
@@ -148,7 +198,7 @@ impl PolyphonicSynth {
                         velocity,
                         self.sample_rate,
                         &self.config,
-                        self.pitch_shift,
+                        self.total_shift(),
                     ));
                 }
                 AudioMessage::NoteOff { key } => {
@@ -166,9 +216,17 @@ impl PolyphonicSynth {
                 }
                 AudioMessage::SetPitchShift(semi) => {
                     self.pitch_shift = semi.clamp(-24.0, 24.0);
-                    for voice in &mut self.voices {
-                        voice.set_pitch_shift(self.pitch_shift);
-                    }
+                    self.retune_voices();
+                }
+                AudioMessage::PitchBend(value) => {
+                    self.pitch_bend = value.clamp(-8192, 8191) as f32 / 8192.0 * PITCH_BEND_RANGE_SEMITONES;
+                    self.retune_voices();
+                }
+                AudioMessage::Click { accented } => {
+                    self.clicks.push(Click::new(accented, self.sample_rate));
+                }
+                AudioMessage::SetClickVolume(vol) => {
+                    self.click_volume = vol.clamp(0.0, 1.0);
                 }
             }
         }
@@ -181,7 +239,7 @@ impl Iterator for PolyphonicSynth {
     fn next(&mut self) -> Option<Self::Item> {
         self.process_messages();
 
-        if self.voices.is_empty() {
+        if self.voices.is_empty() && self.clicks.is_empty() {
             return Some(0.0);
         }
 
@@ -190,8 +248,13 @@ impl Iterator for PolyphonicSynth {
             mix += voice.next_sample(self.sample_rate);
             !voice.is_finished()
         });
+        let mut click = 0.0;
+        self.clicks.retain_mut(|blip| {
+            click += blip.next_sample(self.sample_rate);
+            !blip.is_finished()
+        });
 
-        Some((mix * self.volume * 0.7).tanh())
+        Some((mix * self.volume * 0.7 + click * self.click_volume).tanh())
     }
 }
 
@@ -255,6 +318,43 @@ impl Voice {
     }
 }
 
+/// 节拍器的一声：指数衰减的短正弦，重音更高更响
+struct Click {
+    phase: f32,
+    frequency: f32,
+    amplitude: f32,
+    remaining: u32,
+    decay: f32,
+}
+
+impl Click {
+    const LENGTH_MS: f32 = 40.0;
+
+    fn new(accented: bool, sample_rate: u32) -> Self {
+        let samples = (Self::LENGTH_MS / 1000.0 * sample_rate as f32) as u32;
+        Self {
+            phase: 0.0,
+            frequency: if accented { 1760.0 } else { 1320.0 },
+            amplitude: if accented { 1.0 } else { 0.6 },
+            remaining: samples,
+            // 在整个长度内衰减到约 1%
+            decay: (0.01f32).powf(1.0 / samples.max(1) as f32),
+        }
+    }
+
+    fn next_sample(&mut self, sample_rate: u32) -> f32 {
+        let sample = (self.phase * 2.0 * std::f32::consts::PI).sin() * self.amplitude;
+        self.phase = (self.phase + self.frequency / sample_rate as f32).fract();
+        self.amplitude *= self.decay;
+        self.remaining = self.remaining.saturating_sub(1);
+        sample
+    }
+
+    fn is_finished(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
 struct Oscillator {
     phase: f32,
     base_frequency: f32,
@@ -0,0 +1,195 @@
+//! 示例与测试用的演示内容
+//!
+//! 内容完全由种子决定：同一种子总是生成相同的音符与曲线（ID 除外），压力场景在测试和基准里一个函数调用即可复现。
+
+use crate::structure::{MidiState, Note};
+
+/// `generate` 使用的默认种子
+pub const DEFAULT_SEED: u64 = 0x5EED;
+
+/// 压力测试内容的音符数
+pub const STRESS_NOTE_COUNT: usize = 10_000;
+
+/// 通用 MIDI 鼓组音高
+const KICK: u8 = 36;
+const SNARE: u8 = 38;
+const CLOSED_HAT: u8 = 42;
+const OPEN_HAT: u8 = 46;
+const CRASH: u8 = 49;
+
+/// C 大调和弦进行（C - Am - F - G），每个和弦一小节
+const PAD_CHORDS: [[u8; 4]; 4] = [[48, 60, 64, 67], [45, 57, 60, 64], [41, 57, 60, 65], [43, 55, 59, 62]];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DemoKind {
+    /// 两个八度的 C 大调音阶，先上行再下行
+    CMajorScale,
+    /// 一万个音符的密集随机图案
+    DenseStress,
+    /// 通用 MIDI 鼓组上的八小节鼓点（通道 10）
+    DrumGroove,
+    /// 长音和弦垫，带调制轮与表情曲线
+    SustainedPads,
+}
+
+impl DemoKind {
+    pub const ALL: [DemoKind; 4] = [
+        DemoKind::CMajorScale,
+        DemoKind::DenseStress,
+        DemoKind::DrumGroove,
+        DemoKind::SustainedPads,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DemoKind::CMajorScale => "C Major Scale",
+            DemoKind::DenseStress => "Dense Stress (10k notes)",
+            DemoKind::DrumGroove => "Drum Groove",
+            DemoKind::SustainedPads => "Sustained Pads",
+        }
+    }
+}
+
+/// 用默认种子生成演示内容
+pub fn generate(kind: DemoKind) -> MidiState {
+    generate_with_seed(kind, DEFAULT_SEED)
+}
+
+/// 用指定种子生成演示内容；音符按起点排序
+pub fn generate_with_seed(kind: DemoKind, seed: u64) -> MidiState {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let mut state = MidiState::default();
+    state.track.track_name = Some(kind.label().to_owned());
+    match kind {
+        DemoKind::CMajorScale => scale(&mut state, &mut rng),
+        DemoKind::DenseStress => stress(&mut state, &mut rng),
+        DemoKind::DrumGroove => drums(&mut state, &mut rng),
+        DemoKind::SustainedPads => pads(&mut state, &mut rng),
+    }
+    state.notes.sort_by_key(|note| (note.start, note.key));
+    state
+}
+
+fn scale(state: &mut MidiState, rng: &mut fastrand::Rng) {
+    let tpb = state.ticks_per_beat as u64;
+    let up: Vec<u8> = (0..15).map(|degree| 60 + [0, 2, 4, 5, 7, 9, 11][degree % 7] + 12 * (degree / 7) as u8).collect();
+    let keys = up.iter().chain(up.iter().rev().skip(1));
+    for (index, key) in keys.enumerate() {
+        let velocity = 80 + rng.u8(0..=16);
+        state.notes.push(Note::new(index as u64 * tpb, tpb - tpb / 8, *key, velocity));
+    }
+}
+
+fn stress(state: &mut MidiState, rng: &mut fastrand::Rng) {
+    state.bpm = 140.0;
+    // 每个十六分音符位置放四个音符
+    let step = (state.ticks_per_beat as u64 / 4).max(1);
+    for index in 0..STRESS_NOTE_COUNT {
+        let start = (index / 4) as u64 * step;
+        let duration = step * rng.u64(1..=8);
+        state.notes.push(Note::new(start, duration, rng.u8(24..=108), rng.u8(40..=127)));
+    }
+}
+
+fn drums(state: &mut MidiState, rng: &mut fastrand::Rng) {
+    state.bpm = 96.0;
+    state.track.channel = 9;
+    let step = (state.ticks_per_beat as u64 / 4).max(1);
+    let length = step / 2;
+    for bar in 0..8u64 {
+        let bar_start = bar * 16 * step;
+        let mut hit = |step_index: u64, key: u8, velocity: u8| {
+            state.notes.push(Note::new(bar_start + step_index * step, length, key, velocity));
+        };
+        if bar == 0 {
+            hit(0, CRASH, 110);
+        }
+        hit(0, KICK, 115);
+        hit(8, KICK, 110);
+        hit(if rng.bool() { 10 } else { 7 }, KICK, 95);
+        hit(4, SNARE, 112);
+        hit(12, SNARE, 112);
+        for step_index in (0..16).step_by(2) {
+            if step_index == 14 && bar % 4 == 3 {
+                hit(step_index, OPEN_HAT, 90);
+            } else {
+                let accent = if step_index % 4 == 0 { 90 } else { 65 };
+                hit(step_index, CLOSED_HAT, accent + rng.u8(0..=10));
+            }
+        }
+        for ghost in [3, 7, 11, 15] {
+            if rng.f32() < 0.2 {
+                hit(ghost, SNARE, rng.u8(30..=45));
+            }
+        }
+    }
+}
+
+fn pads(state: &mut MidiState, rng: &mut fastrand::Rng) {
+    state.bpm = 80.0;
+    let measure = state.ticks_per_measure();
+    for bar in 0..8u64 {
+        for key in PAD_CHORDS[bar as usize % PAD_CHORDS.len()] {
+            state.notes.push(Note::new(bar * measure, measure, key, rng.u8(60..=80)));
+        }
+    }
+    let modulation = state.add_cc_lane(1);
+    let expression = state.add_cc_lane(11);
+    for lane in &mut state.curves {
+        if lane.id == modulation {
+            for bar in 0..=8u64 {
+                lane.insert_point(bar * measure, rng.u8(20..=100) as f32);
+            }
+        } else if lane.id == expression {
+            // 每小节一次渐强渐弱
+            for bar in 0..8u64 {
+                lane.insert_point(bar * measure, 60.0);
+                lane.insert_point(bar * measure + measure / 2, 110.0);
+            }
+            lane.insert_point(8 * measure, 60.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::CurveLaneType;
+
+    fn fingerprint(state: &MidiState) -> Vec<(u64, u64, u8, u8)> {
+        state.notes.iter().map(|n| (n.start, n.duration, n.key, n.velocity)).collect()
+    }
+
+    #[test]
+    fn content_is_deterministic_per_seed() {
+        for kind in DemoKind::ALL {
+            assert_eq!(fingerprint(&generate(kind)), fingerprint(&generate(kind)), "{kind:?}");
+        }
+        assert_ne!(
+            fingerprint(&generate_with_seed(DemoKind::DenseStress, 1)),
+            fingerprint(&generate_with_seed(DemoKind::DenseStress, 2))
+        );
+    }
+
+    #[test]
+    fn kinds_produce_their_characteristic_content() {
+        let scale = generate(DemoKind::CMajorScale);
+        assert_eq!(scale.notes.len(), 29);
+        assert!(scale.notes.iter().all(|n| [0, 2, 4, 5, 7, 9, 11].contains(&(n.key % 12))));
+
+        assert_eq!(generate(DemoKind::DenseStress).notes.len(), STRESS_NOTE_COUNT);
+
+        let drums = generate(DemoKind::DrumGroove);
+        assert_eq!(drums.track.channel, 9);
+        assert!(drums.notes.iter().all(|n| [KICK, SNARE, CLOSED_HAT, OPEN_HAT, CRASH].contains(&n.key)));
+
+        let pads = generate(DemoKind::SustainedPads);
+        let cc_lanes: Vec<_> = pads.curves.iter().filter_map(|lane| lane.lane_type.cc_number()).collect();
+        assert_eq!(cc_lanes, vec![1, 11]);
+        assert!(pads
+            .curves
+            .iter()
+            .filter(|lane| matches!(lane.lane_type, CurveLaneType::ControlChange(_)))
+            .all(|lane| lane.points.len() > 8));
+    }
+}
@@ -1,17 +1,21 @@
-use crate::structure::{BatchTransformType, CurveLaneId, CurvePointId, MidiState, Note, NoteId};
+use crate::structure::{
+    rescale_tick, ArpPattern, BatchTransformType, CurveClip, CurveInterpolation, CurveLaneId, CurvePointId, KeySignature, MarkerId, MidiState, MusicalTime, Note, NoteGroup,
+    NoteGroupId, NoteId, OverlapPolicy, RampCurve, SmallString, ValidationIssue,
+};
+use egui::{Color32, Key, KeyboardShortcut, Modifiers, Visuals};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::collections::BTreeMap;
+use std::ops::{Range, RangeInclusive};
 
 /// 宿主可描述的吸附模式
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum SnapMode {
+    #[default]
     Absolute,
     Relative,
 }
 
-impl Default for SnapMode {
-    fn default() -> Self {
-        SnapMode::Absolute
-    }
-}
 
 /// 外部控制的传输/播放状态
 #[derive(Clone, Copy, Debug, Default)]
@@ -21,9 +25,652 @@ pub struct TransportState {
     pub bpm_override: Option<f32>,
 }
 
+/// 128 个 MIDI 音高的位集合，用于限制可编辑的音高行
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BitSet128(pub u128);
+
+impl BitSet128 {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn full() -> Self {
+        Self(u128::MAX)
+    }
+
+    pub fn insert(&mut self, key: u8) {
+        if key < 128 {
+            self.0 |= 1 << key;
+        }
+    }
+
+    pub fn remove(&mut self, key: u8) {
+        if key < 128 {
+            self.0 &= !(1 << key);
+        }
+    }
+
+    pub fn contains(&self, key: u8) -> bool {
+        key < 128 && self.0 & (1 << key) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// 距离 `key` 最近的成员（相同距离时取较低的音高），超出 `max_distance` 则返回 `None`
+    pub fn nearest(&self, key: u8, max_distance: u8) -> Option<u8> {
+        let key = key.min(127) as i16;
+        (0..=max_distance as i16).find_map(|distance| {
+            [key - distance, key + distance]
+                .into_iter()
+                .find(|candidate| (0..128).contains(candidate) && self.contains(*candidate as u8))
+                .map(|candidate| candidate as u8)
+        })
+    }
+}
+
+impl FromIterator<u8> for BitSet128 {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for key in iter {
+            set.insert(key);
+        }
+        set
+    }
+}
+
+/// 钢琴卷帘背景中的参考音频波形（只用于对照，不参与交互）
+///
+/// `peaks` 为等时长分桶的 `(最小值, 最大值)`，取值范围 -1.0..=1.0，
+/// 整体覆盖 `0..duration_seconds`。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReferenceWaveform {
+    pub peaks: Vec<(f32, f32)>,
+    pub duration_seconds: f32,
+    /// 只在 `(低音高, 高音高)` 范围内绘制；`None` 时占满整个卷帘高度
+    pub key_band: Option<(u8, u8)>,
+}
+
+impl ReferenceWaveform {
+    pub fn new(peaks: Vec<(f32, f32)>, duration_seconds: f32) -> Self {
+        Self {
+            peaks,
+            duration_seconds,
+            key_band: None,
+        }
+    }
+
+    /// 由单声道采样计算 `buckets` 个峰值分桶
+    pub fn from_samples(samples: &[f32], sample_rate: u32, buckets: usize) -> Self {
+        let buckets = buckets.clamp(1, samples.len().max(1));
+        let chunk = samples.len().div_ceil(buckets).max(1);
+        let peaks = samples
+            .chunks(chunk)
+            .map(|chunk| {
+                chunk.iter().fold((0.0f32, 0.0f32), |(lo, hi), &s| {
+                    (lo.min(s.clamp(-1.0, 1.0)), hi.max(s.clamp(-1.0, 1.0)))
+                })
+            })
+            .collect();
+        Self::new(peaks, samples.len() as f32 / sample_rate.max(1) as f32)
+    }
+
+    /// `[start, end)` 秒范围内峰值的包络，范围内没有分桶时返回 `None`
+    pub fn peak_range(&self, start: f32, end: f32) -> Option<(f32, f32)> {
+        if self.peaks.is_empty() || self.duration_seconds <= 0.0 || end <= 0.0 || start >= self.duration_seconds {
+            return None;
+        }
+        let per_bucket = self.duration_seconds / self.peaks.len() as f32;
+        let first = ((start.max(0.0) / per_bucket) as usize).min(self.peaks.len() - 1);
+        let last = ((end / per_bucket).ceil() as usize).clamp(first + 1, self.peaks.len());
+        Some(
+            self.peaks[first..last]
+                .iter()
+                .fold((0.0f32, 0.0f32), |(lo, hi), &(min, max)| (lo.min(min), hi.max(max))),
+        )
+    }
+}
+
+/// General MIDI 打击乐音色表（通道 10 上的音高 35..=81）
+pub const GM_DRUM_MAP: [(u8, &str); 47] = [
+    (35, "Kick 2"),
+    (36, "Kick"),
+    (37, "Side Stick"),
+    (38, "Snare"),
+    (39, "Clap"),
+    (40, "Snare 2"),
+    (41, "Low Floor Tom"),
+    (42, "Closed Hi-Hat"),
+    (43, "High Floor Tom"),
+    (44, "Pedal Hi-Hat"),
+    (45, "Low Tom"),
+    (46, "Open Hi-Hat"),
+    (47, "Low-Mid Tom"),
+    (48, "Hi-Mid Tom"),
+    (49, "Crash"),
+    (50, "High Tom"),
+    (51, "Ride"),
+    (52, "China"),
+    (53, "Ride Bell"),
+    (54, "Tambourine"),
+    (55, "Splash"),
+    (56, "Cowbell"),
+    (57, "Crash 2"),
+    (58, "Vibraslap"),
+    (59, "Ride 2"),
+    (60, "High Bongo"),
+    (61, "Low Bongo"),
+    (62, "Mute High Conga"),
+    (63, "Open High Conga"),
+    (64, "Low Conga"),
+    (65, "High Timbale"),
+    (66, "Low Timbale"),
+    (67, "High Agogo"),
+    (68, "Low Agogo"),
+    (69, "Cabasa"),
+    (70, "Maracas"),
+    (71, "Short Whistle"),
+    (72, "Long Whistle"),
+    (73, "Short Guiro"),
+    (74, "Long Guiro"),
+    (75, "Claves"),
+    (76, "High Wood Block"),
+    (77, "Low Wood Block"),
+    (78, "Mute Cuica"),
+    (79, "Open Cuica"),
+    (80, "Mute Triangle"),
+    (81, "Open Triangle"),
+];
+
+/// 鼓映射：侧边栏按音高显示鼓件名称，音符画成固定宽度的菱形，且不能拖动边缘改变时值
+///
+/// 只影响显示与交互，打开或关闭都不会修改音符数据。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrumMap {
+    pub names: BTreeMap<u8, String>,
+    /// 隐藏没有名称的音高行，压缩视图（默认关闭）
+    pub hide_unmapped: bool,
+}
+
+impl DrumMap {
+    pub fn new<S: Into<String>>(names: impl IntoIterator<Item = (u8, S)>) -> Self {
+        Self {
+            names: names.into_iter().map(|(key, name)| (key.min(127), name.into())).collect(),
+            hide_unmapped: false,
+        }
+    }
+
+    /// 内置的 General MIDI 鼓映射（`GM_DRUM_MAP`）
+    pub fn general_midi() -> Self {
+        Self::new(GM_DRUM_MAP)
+    }
+
+    pub fn name(&self, key: u8) -> Option<&str> {
+        self.names.get(&key).map(String::as_str)
+    }
+}
+
+/// 可以绑定键盘快捷键的编辑器操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShortcutAction {
+    Copy,
+    Cut,
+    Paste,
+    Duplicate,
+    Delete,
+    Undo,
+    Redo,
+    SelectSamePitch,
+    SelectInLoop,
+    InvertSelection,
+    Group,
+    Ungroup,
+    /// 按当前吸附间隔量化选中音符的起点（默认未绑定）
+    Quantize,
+    ZoomToFit,
+    ZoomToSelection,
+    TogglePlayback,
+    /// 选中音符前移一个吸附间隔；步进输入开启时改为步进光标后退一步
+    NudgeEarlier,
+    /// 选中音符后移一个吸附间隔；步进输入开启时改为前进一步（休止）
+    NudgeLater,
+    /// 选中音符前移 1 tick
+    NudgeEarlierFine,
+    /// 选中音符后移 1 tick
+    NudgeLaterFine,
+    TransposeUp,
+    TransposeDown,
+    OctaveUp,
+    OctaveDown,
+    LoopEarlier,
+    LoopLater,
+    LoopLonger,
+    LoopShorter,
+    /// 按相对位置试听选中的音符，不影响走带
+    PlaySelection,
+    /// 把循环区设为选中音符的范围（向外取整到小节线）
+    LoopToSelection,
+    /// 播放头回到开头
+    SeekToStart,
+    /// 播放头跳到最后一个音符的终点
+    SeekToEnd,
+    /// 播放头跳到上一条小节线（按拍号）
+    PreviousBar,
+    /// 播放头跳到下一条小节线
+    NextBar,
+    /// 播放头后退一拍
+    BeatEarlier,
+    /// 播放头前进一拍
+    BeatLater,
+    /// 播放头跳到上一个标记
+    PreviousMarker,
+    /// 播放头跳到下一个标记
+    NextMarker,
+}
+
+impl ShortcutAction {
+    pub const ALL: [ShortcutAction; 38] = [
+        ShortcutAction::Copy,
+        ShortcutAction::Cut,
+        ShortcutAction::Paste,
+        ShortcutAction::Duplicate,
+        ShortcutAction::Delete,
+        ShortcutAction::Undo,
+        ShortcutAction::Redo,
+        ShortcutAction::SelectSamePitch,
+        ShortcutAction::SelectInLoop,
+        ShortcutAction::InvertSelection,
+        ShortcutAction::Group,
+        ShortcutAction::Ungroup,
+        ShortcutAction::Quantize,
+        ShortcutAction::ZoomToFit,
+        ShortcutAction::ZoomToSelection,
+        ShortcutAction::TogglePlayback,
+        ShortcutAction::NudgeEarlier,
+        ShortcutAction::NudgeLater,
+        ShortcutAction::NudgeEarlierFine,
+        ShortcutAction::NudgeLaterFine,
+        ShortcutAction::TransposeUp,
+        ShortcutAction::TransposeDown,
+        ShortcutAction::OctaveUp,
+        ShortcutAction::OctaveDown,
+        ShortcutAction::LoopEarlier,
+        ShortcutAction::LoopLater,
+        ShortcutAction::LoopLonger,
+        ShortcutAction::LoopShorter,
+        ShortcutAction::PlaySelection,
+        ShortcutAction::LoopToSelection,
+        ShortcutAction::SeekToStart,
+        ShortcutAction::SeekToEnd,
+        ShortcutAction::PreviousBar,
+        ShortcutAction::NextBar,
+        ShortcutAction::BeatEarlier,
+        ShortcutAction::BeatLater,
+        ShortcutAction::PreviousMarker,
+        ShortcutAction::NextMarker,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShortcutAction::Copy => "Copy",
+            ShortcutAction::Cut => "Cut",
+            ShortcutAction::Paste => "Paste",
+            ShortcutAction::Duplicate => "Duplicate",
+            ShortcutAction::Delete => "Delete",
+            ShortcutAction::Undo => "Undo",
+            ShortcutAction::Redo => "Redo",
+            ShortcutAction::SelectSamePitch => "Select Same Pitch",
+            ShortcutAction::SelectInLoop => "Select in Loop",
+            ShortcutAction::InvertSelection => "Invert Selection",
+            ShortcutAction::Group => "Group",
+            ShortcutAction::Ungroup => "Ungroup",
+            ShortcutAction::Quantize => "Quantize",
+            ShortcutAction::ZoomToFit => "Zoom to Fit",
+            ShortcutAction::ZoomToSelection => "Zoom to Selection",
+            ShortcutAction::TogglePlayback => "Play / Pause",
+            ShortcutAction::NudgeEarlier => "Nudge Earlier",
+            ShortcutAction::NudgeLater => "Nudge Later",
+            ShortcutAction::NudgeEarlierFine => "Nudge Earlier by 1 Tick",
+            ShortcutAction::NudgeLaterFine => "Nudge Later by 1 Tick",
+            ShortcutAction::TransposeUp => "Transpose Up",
+            ShortcutAction::TransposeDown => "Transpose Down",
+            ShortcutAction::OctaveUp => "Octave Up",
+            ShortcutAction::OctaveDown => "Octave Down",
+            ShortcutAction::LoopEarlier => "Loop Earlier",
+            ShortcutAction::LoopLater => "Loop Later",
+            ShortcutAction::LoopLonger => "Loop Longer",
+            ShortcutAction::LoopShorter => "Loop Shorter",
+            ShortcutAction::PlaySelection => "Play Selection",
+            ShortcutAction::LoopToSelection => "Set Loop to Selection",
+            ShortcutAction::SeekToStart => "Go to Start",
+            ShortcutAction::SeekToEnd => "Go to End",
+            ShortcutAction::PreviousBar => "Previous Bar",
+            ShortcutAction::NextBar => "Next Bar",
+            ShortcutAction::BeatEarlier => "Back One Beat",
+            ShortcutAction::BeatLater => "Forward One Beat",
+            ShortcutAction::PreviousMarker => "Previous Marker",
+            ShortcutAction::NextMarker => "Next Marker",
+        }
+    }
+}
+
+/// 键盘快捷键表：按键组合到 `ShortcutAction` 的映射
+///
+/// 默认值与编辑器内置的快捷键一致。一个操作可以绑定多个按键（例如 Delete 与 Backspace），
+/// 移除某个操作的全部绑定即可把该按键让给宿主应用。修饰键需要完全匹配（Ctrl 与 Cmd 视为同一个）；
+/// 不带 Ctrl/Cmd 的快捷键在文本框获得焦点时不会触发。
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShortcutMap {
+    bindings: Vec<(KeyboardShortcut, ShortcutAction)>,
+}
+
+impl ShortcutMap {
+    /// 不含任何绑定的快捷键表
+    pub fn empty() -> Self {
+        Self { bindings: Vec::new() }
+    }
+
+    /// 把 `shortcut` 绑定到 `action`；该按键原有的绑定会被替换
+    pub fn bind(&mut self, action: ShortcutAction, shortcut: KeyboardShortcut) {
+        self.unbind_shortcut(shortcut);
+        self.bindings.push((shortcut, action));
+    }
+
+    /// 移除 `action` 的全部绑定
+    pub fn unbind(&mut self, action: ShortcutAction) {
+        self.bindings.retain(|(_, bound)| *bound != action);
+    }
+
+    pub fn unbind_shortcut(&mut self, shortcut: KeyboardShortcut) {
+        self.bindings.retain(|(bound, _)| *bound != shortcut);
+    }
+
+    /// `action` 当前绑定的全部按键，按绑定顺序
+    pub fn shortcuts(&self, action: ShortcutAction) -> impl Iterator<Item = KeyboardShortcut> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| *bound == action)
+            .map(|(shortcut, _)| *shortcut)
+    }
+
+    /// 按下 `key`（当时的修饰键为 `modifiers`）时触发的操作
+    pub fn action(&self, modifiers: Modifiers, key: Key) -> Option<ShortcutAction> {
+        self.bindings
+            .iter()
+            .find(|(shortcut, _)| shortcut.logical_key == key && modifiers.matches_exact(shortcut.modifiers))
+            .map(|(_, action)| *action)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (KeyboardShortcut, ShortcutAction)> + '_ {
+        self.bindings.iter().copied()
+    }
+}
+
+impl Default for ShortcutMap {
+    fn default() -> Self {
+        use ShortcutAction::*;
+        let command = Modifiers::COMMAND;
+        let command_shift = Modifiers::COMMAND | Modifiers::SHIFT;
+        let bindings = [
+            (command, Key::C, Copy),
+            (command, Key::X, Cut),
+            (command, Key::V, Paste),
+            (command, Key::D, Duplicate),
+            (Modifiers::NONE, Key::Delete, Delete),
+            (Modifiers::NONE, Key::Backspace, Delete),
+            (command, Key::Z, Undo),
+            (command_shift, Key::Z, Redo),
+            (command, Key::Y, Redo),
+            (command_shift, Key::P, SelectSamePitch),
+            (command, Key::L, SelectInLoop),
+            (command_shift, Key::L, LoopToSelection),
+            (command, Key::I, InvertSelection),
+            (command, Key::G, Group),
+            (command_shift, Key::G, Ungroup),
+            (Modifiers::NONE, Key::F, ZoomToFit),
+            (Modifiers::SHIFT, Key::F, ZoomToSelection),
+            (Modifiers::NONE, Key::Space, TogglePlayback),
+            (Modifiers::SHIFT, Key::Space, PlaySelection),
+            (Modifiers::NONE, Key::Home, SeekToStart),
+            (Modifiers::NONE, Key::End, SeekToEnd),
+            (Modifiers::NONE, Key::PageUp, PreviousBar),
+            (Modifiers::NONE, Key::PageDown, NextBar),
+            (Modifiers::SHIFT, Key::PageUp, PreviousMarker),
+            (Modifiers::SHIFT, Key::PageDown, NextMarker),
+            (Modifiers::NONE, Key::Comma, BeatEarlier),
+            (Modifiers::NONE, Key::Period, BeatLater),
+            (Modifiers::NONE, Key::ArrowLeft, NudgeEarlier),
+            (Modifiers::NONE, Key::ArrowRight, NudgeLater),
+            (Modifiers::ALT, Key::ArrowLeft, NudgeEarlierFine),
+            (Modifiers::ALT, Key::ArrowRight, NudgeLaterFine),
+            (Modifiers::NONE, Key::ArrowUp, TransposeUp),
+            (Modifiers::NONE, Key::ArrowDown, TransposeDown),
+            (Modifiers::SHIFT, Key::ArrowUp, OctaveUp),
+            (Modifiers::SHIFT, Key::ArrowDown, OctaveDown),
+            (command_shift, Key::ArrowLeft, LoopEarlier),
+            (command_shift, Key::ArrowRight, LoopLater),
+            (command_shift, Key::ArrowUp, LoopLonger),
+            (command_shift, Key::ArrowDown, LoopShorter),
+        ];
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(modifiers, key, action)| (KeyboardShortcut::new(modifiers, key), action))
+                .collect(),
+        }
+    }
+}
+
+/// 编辑器绘制用到的全部颜色
+///
+/// `MidiEditorOptions::theme` 为 `None` 时每帧按宿主当前的 `egui::Visuals` 推导（`from_visuals`）；
+/// 只想改个别颜色时可以在推导结果上覆盖：`MidiEditorTheme { note: .., ..MidiEditorTheme::from_visuals(&visuals) }`。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiEditorTheme {
+    /// 卷帘、时间轴与侧边栏的底色，也是导出图片的底色
+    pub background: Color32,
+    /// 力度区域的底色
+    pub lane_background: Color32,
+    pub measure_line: Color32,
+    pub beat_line: Color32,
+    pub subdivision_line: Color32,
+    /// 音高行之间的水平线
+    pub row_line: Color32,
+    /// 时间轴与网格之间的分隔线
+    pub separator: Color32,
+    /// 时间轴上的小节号
+    pub timeline_text: Color32,
+    pub note: Color32,
+    pub selected_note: Color32,
+    /// 音符描边（选中时加粗）、曲线点描边与直线工具的预览线
+    pub note_outline: Color32,
+    /// 按力度着色时的低端与高端颜色，中间为 `note`
+    pub velocity_low: Color32,
+    pub velocity_high: Color32,
+    /// 音符上方的标签文字
+    pub note_label: Color32,
+    /// 幽灵音符与其他轨道的只读音符
+    pub ghost_note: Color32,
+    /// 从其他编辑器拖入时的落点预览
+    pub drop_preview: Color32,
+    pub selection_box: Color32,
+    pub playhead: Color32,
+    /// 循环起止标记与边界线
+    pub loop_marker: Color32,
+    /// 循环区域的半透明覆盖
+    pub loop_region: Color32,
+    pub tempo_marker: Color32,
+    pub step_cursor: Color32,
+    /// 可编辑范围之外的遮罩
+    pub out_of_bounds: Color32,
+    /// 音阶高亮：主音行、音阶内的行与音阶外的行的覆盖色
+    pub scale_root_row: Color32,
+    pub scale_row: Color32,
+    pub out_of_scale_row: Color32,
+    /// 侧边键盘上主音的色条
+    pub scale_root: Color32,
+    /// 不允许放置音符的行：底色与斜线
+    pub disallowed_row: Color32,
+    pub disallowed_hatch: Color32,
+    pub waveform: Color32,
+    pub white_key: Color32,
+    pub black_key: Color32,
+    pub key_outline: Color32,
+    /// 鼓映射侧边栏：有名称的行、没有名称的行与名称文字
+    pub drum_row: Color32,
+    pub unmapped_drum_row: Color32,
+    pub drum_label: Color32,
+    pub toast_background: Color32,
+    pub toast_text: Color32,
+    pub minimap_background: Color32,
+    pub minimap_note: Color32,
+    pub minimap_viewport: Color32,
+    pub minimap_viewport_outline: Color32,
+    /// 钢琴卷帘与曲线区域之间的分隔条
+    pub splitter: Color32,
+    pub curve_background: Color32,
+    /// 曲线区域中按值四等分的水平线
+    pub curve_grid_line: Color32,
+    /// 双极性曲线的 0 值线
+    pub curve_zero_line: Color32,
+    pub curve_line: Color32,
+    pub curve_point: Color32,
+    pub selected_curve_point: Color32,
+}
+
+impl Default for MidiEditorTheme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl MidiEditorTheme {
+    /// 深色主题（默认）
+    pub fn dark() -> Self {
+        Self {
+            background: Color32::from_rgb(27, 27, 27),
+            lane_background: Color32::from_rgb(20, 20, 20),
+            measure_line: Color32::from_rgb(210, 210, 210),
+            beat_line: Color32::from_rgb(140, 140, 140),
+            subdivision_line: Color32::from_rgb(90, 90, 90),
+            row_line: Color32::from_rgb(90, 90, 90),
+            separator: Color32::from_rgb(130, 130, 130),
+            timeline_text: Color32::GRAY,
+            note: Color32::from_rgb(100, 200, 100),
+            selected_note: Color32::from_rgb(150, 250, 150),
+            note_outline: Color32::WHITE,
+            velocity_low: Color32::from_rgb(70, 110, 220),
+            velocity_high: Color32::from_rgb(235, 80, 60),
+            note_label: Color32::from_gray(210),
+            ghost_note: Color32::from_rgba_premultiplied(70, 70, 70, 110),
+            drop_preview: Color32::from_rgba_unmultiplied(150, 250, 150, 70),
+            selection_box: Color32::from_rgb(120, 200, 255),
+            playhead: Color32::from_rgba_premultiplied(100, 200, 255, 128),
+            loop_marker: Color32::from_rgb(100, 150, 255),
+            loop_region: Color32::from_rgba_unmultiplied(100, 150, 255, 60),
+            tempo_marker: Color32::from_rgb(230, 170, 90),
+            step_cursor: Color32::from_rgb(255, 120, 70),
+            out_of_bounds: Color32::from_black_alpha(110),
+            scale_root_row: Color32::from_white_alpha(16),
+            scale_row: Color32::from_white_alpha(7),
+            out_of_scale_row: Color32::from_black_alpha(70),
+            scale_root: Color32::from_rgb(230, 160, 60),
+            disallowed_row: Color32::from_black_alpha(60),
+            disallowed_hatch: Color32::from_white_alpha(18),
+            waveform: Color32::from_rgba_premultiplied(40, 55, 75, 70),
+            white_key: Color32::WHITE,
+            black_key: Color32::BLACK,
+            key_outline: Color32::GRAY,
+            drum_row: Color32::from_gray(70),
+            unmapped_drum_row: Color32::from_gray(35),
+            drum_label: Color32::WHITE,
+            toast_background: Color32::from_black_alpha(200),
+            toast_text: Color32::WHITE,
+            minimap_background: Color32::from_gray(30),
+            minimap_note: Color32::from_rgb(100, 160, 230),
+            minimap_viewport: Color32::from_white_alpha(30),
+            minimap_viewport_outline: Color32::from_white_alpha(160),
+            splitter: Color32::from_rgb(100, 100, 100),
+            curve_background: Color32::from_rgb(40, 40, 40),
+            curve_grid_line: Color32::from_rgb(50, 50, 50),
+            curve_zero_line: Color32::from_rgb(110, 110, 110),
+            curve_line: Color32::from_rgb(100, 200, 100),
+            curve_point: Color32::from_rgb(150, 250, 150),
+            selected_curve_point: Color32::from_rgb(250, 220, 120),
+        }
+    }
+
+    /// 浅色主题：网格线与描边改用深色，覆盖色改为压暗而不是提亮
+    pub fn light() -> Self {
+        Self {
+            background: Color32::from_rgb(248, 248, 248),
+            lane_background: Color32::from_rgb(236, 236, 236),
+            measure_line: Color32::from_rgb(110, 110, 110),
+            beat_line: Color32::from_rgb(170, 170, 170),
+            subdivision_line: Color32::from_rgb(215, 215, 215),
+            row_line: Color32::from_rgb(222, 222, 222),
+            separator: Color32::from_rgb(160, 160, 160),
+            timeline_text: Color32::from_gray(80),
+            note: Color32::from_rgb(90, 180, 95),
+            selected_note: Color32::from_rgb(40, 130, 55),
+            note_outline: Color32::from_gray(40),
+            velocity_low: Color32::from_rgb(60, 100, 210),
+            velocity_high: Color32::from_rgb(220, 70, 50),
+            note_label: Color32::from_gray(60),
+            ghost_note: Color32::from_black_alpha(40),
+            drop_preview: Color32::from_rgba_unmultiplied(40, 160, 60, 80),
+            selection_box: Color32::from_rgb(30, 120, 220),
+            playhead: Color32::from_rgba_unmultiplied(0, 120, 220, 180),
+            loop_marker: Color32::from_rgb(50, 100, 220),
+            loop_region: Color32::from_rgba_unmultiplied(50, 100, 220, 40),
+            tempo_marker: Color32::from_rgb(200, 120, 30),
+            step_cursor: Color32::from_rgb(230, 90, 40),
+            out_of_bounds: Color32::from_black_alpha(50),
+            scale_root_row: Color32::from_rgba_unmultiplied(230, 160, 60, 40),
+            scale_row: Color32::TRANSPARENT,
+            out_of_scale_row: Color32::from_black_alpha(25),
+            scale_root: Color32::from_rgb(220, 140, 40),
+            disallowed_row: Color32::from_black_alpha(30),
+            disallowed_hatch: Color32::from_black_alpha(40),
+            waveform: Color32::from_rgba_unmultiplied(60, 90, 130, 50),
+            white_key: Color32::WHITE,
+            black_key: Color32::from_gray(40),
+            key_outline: Color32::from_gray(160),
+            drum_row: Color32::from_gray(225),
+            unmapped_drum_row: Color32::from_gray(200),
+            drum_label: Color32::from_gray(30),
+            toast_background: Color32::from_black_alpha(200),
+            toast_text: Color32::WHITE,
+            minimap_background: Color32::from_gray(225),
+            minimap_note: Color32::from_rgb(50, 110, 200),
+            minimap_viewport: Color32::from_black_alpha(20),
+            minimap_viewport_outline: Color32::from_black_alpha(140),
+            splitter: Color32::from_gray(190),
+            curve_background: Color32::from_rgb(240, 240, 240),
+            curve_grid_line: Color32::from_rgb(218, 218, 218),
+            curve_zero_line: Color32::from_rgb(150, 150, 150),
+            curve_line: Color32::from_rgb(40, 140, 60),
+            curve_point: Color32::from_rgb(60, 170, 80),
+            selected_curve_point: Color32::from_rgb(220, 150, 30),
+        }
+    }
+
+    /// 按宿主的 `Visuals` 选深色或浅色主题，底色取宿主的面板底色
+    pub fn from_visuals(visuals: &Visuals) -> Self {
+        let base = if visuals.dark_mode { Self::dark() } else { Self::light() };
+        Self {
+            background: visuals.panel_fill,
+            ..base
+        }
+    }
+}
+
 /// 外部宿主可监听的编辑事件
 #[derive(Clone, Debug)]
 pub enum EditorEvent {
+    /// 整个状态被替换（`replace_state`、撤销、重做）；普通编辑发出下面更细的事件，
+    /// 需要每次编辑都收到完整状态时打开 `MidiEditorOptions::state_replaced_on_edit`
     StateReplaced(MidiState),
     NoteAdded(Note),
     NoteDeleted(Note),
@@ -42,6 +689,8 @@ pub enum EditorEvent {
         loop_start_tick: u64,
         loop_end_tick: u64,
         loop_progress: f32, // 0.0 to 1.0, position within loop region
+        playback_rate: f32, // 试听用的播放速率，1.0 为原速
+        musical_time: MusicalTime, // current_tick 对应的 "小节.拍.tick"
     },
     CurveLaneAdded(CurveLaneId),
     CurveLaneRemoved(CurveLaneId),
@@ -57,6 +706,74 @@ pub enum EditorEvent {
         lane_id: CurveLaneId,
         point_id: CurvePointId,
     },
+    GroupCreated(NoteGroupId),
+    GroupDissolved(NoteGroupId),
+    /// 音符被双击（在 `MidiEditorOptions::note_double_click` 的动作之前发出）
+    NoteDoubleClicked(NoteId),
+    MarkerAdded {
+        marker_id: MarkerId,
+    },
+    /// 标记拖动结束（`tick` 为新位置）
+    MarkerMoved {
+        marker_id: MarkerId,
+        tick: u64,
+    },
+    MarkerRemoved {
+        marker_id: MarkerId,
+    },
+    /// 一次粘贴对曲线的全部修改（替代逐点事件）
+    CurvePointsPasted {
+        lane_id: CurveLaneId,
+        removed: Vec<CurvePointId>,
+        added: Vec<CurvePointId>,
+    },
+    /// 一次 `ResolveOverlaps` 修改或删除的音符数（没有重叠时为 0）
+    OverlapsResolved {
+        count: usize,
+    },
+    /// 一次 `ValidateState` 找到的问题（没有问题时为空）
+    ValidationFinished {
+        issues: Vec<ValidationIssue>,
+    },
+    /// 电脑键盘弹奏的音符按下/松开（`tick` 为当时的播放头位置），宿主可据此录制
+    LiveNoteInput {
+        key: u8,
+        velocity: u8,
+        pressed: bool,
+        tick: u64,
+    },
+    /// 撤销/重做栈的深度变化（每帧、每条命令与每个撤销组结束时至多一次），宿主可据此启用或禁用自己的撤销菜单
+    HistoryChanged {
+        undo_depth: usize,
+        redo_depth: usize,
+    },
+    /// 缩放或滚动使音符网格的可见范围改变（每帧至多一次），宿主可据此同步另一个编辑器的视图
+    ViewChanged {
+        visible_tick_range: Range<u64>,
+        visible_key_range: RangeInclusive<u8>,
+    },
+    /// `is_dirty` 变化（与 `HistoryChanged` 同时检查，`mark_saved` 后也会检查），宿主可据此在标题中显示 “*”
+    DirtyChanged(bool),
+    /// 一批音符被一次修改（人性化、批量变换等不逐个发出 `NoteUpdated` 的编辑）
+    NotesChanged(Vec<NoteId>),
+    /// 速度（`bpm` 或速度表）改变
+    TempoChanged,
+    TimeSignatureChanged {
+        numer: u8,
+        denom: u8,
+    },
+    KeySignatureChanged(KeySignature),
+    /// 歌词、文本事件被添加、修改或删除
+    TextEventsChanged,
+    /// 每拍 tick 数改变，所有位置已按比例换算
+    TicksPerBeatChanged {
+        from: u16,
+        to: u16,
+    },
+    /// 曲线通道本身被修改（例如启用或停用）
+    CurveChanged(CurveLaneId),
+    /// `edit_state` 以任意方式修改了状态，需要时通过 `midi_state()` 读取
+    StateEdited,
 }
 
 /// 宿主可推送到编辑器的命令
@@ -67,21 +784,61 @@ pub enum EditorCommand {
     AppendNotes(Vec<Note>),
     ClearNotes,
     SeekSeconds(f32),
+    /// 跳到 `tick`，先松开所有发声的音（`all_notes_off`）
+    SeekToTick(u64),
+    /// 紧急停止所有声音（`all_notes_off`）并清空编辑器记录的发声状态，不改变播放状态
+    Panic,
     SetPlayback(bool),
     CenterOnKey(u8),
     SetBpm(f32),
+    /// 修改 `tick` 所在速度段的速度
+    SetTempoAt {
+        tick: u64,
+        bpm: f32,
+    },
     SetTimeSignature(u8, u8),
+    SetKeySignature(KeySignature),
+    /// 在 `tick` 处添加标记，`color` 为 `None` 时使用 `Marker::DEFAULT_COLOR`
+    AddMarker {
+        tick: u64,
+        label: String,
+        color: Option<[u8; 3]>,
+    },
+    MoveMarker {
+        marker_id: MarkerId,
+        tick: u64,
+    },
+    RemoveMarker {
+        marker_id: MarkerId,
+    },
+    /// 更改每拍 tick 数并按比例换算所有位置，见 `MidiEditor::rescale_ticks_per_beat`
+    RescaleTicksPerBeat(u16),
     SetVolume(f32),
     SetLoop {
         enabled: bool,
         start_tick: u64,
         end_tick: u64,
     },
+    /// 循环区整体按自身长度后移（`forward`）或前移，起点不小于 0
+    ShiftLoop { forward: bool },
+    /// 以起点为锚把循环长度加倍（`double`）或减半，至少 1 tick
+    ScaleLoop { double: bool },
+    /// 把循环区设为选中音符的范围（向外取整到小节线）并开启循环
+    SetLoopToSelection,
+    /// 以 tick 为单位的自定义吸附间隔（同时清除 `SetSnapResolution` 设置的时值）
     SetSnap {
         interval: u64,
         mode: SnapMode,
     },
+    /// 按音符时值吸附，随曲子的 `ticks_per_beat` 换算；`None` 改用自定义的 tick 间隔
+    SetSnapResolution(Option<(SnapResolution, SnapModifier)>),
     OverrideTransport(Option<TransportState>),
+    /// 添加 CC 曲线（同一控制器已有曲线时只切换到它）
+    AddCurveLane {
+        cc_number: u8,
+    },
+    /// 添加弯音曲线（已存在时只切换到它）
+    AddPitchBendLane,
     AddCurvePoint {
         lane_id: CurveLaneId,
         tick: u64,
@@ -97,17 +854,705 @@ pub enum EditorCommand {
         lane_id: CurveLaneId,
         point_id: CurvePointId,
     },
+    /// 设置曲线点到下一个点的插值方式，`None` 沿用曲线的插值方式
+    SetCurvePointInterpolation {
+        lane_id: CurveLaneId,
+        point_id: CurvePointId,
+        interpolation: Option<CurveInterpolation>,
+    },
     ToggleCurveLaneEnabled {
         lane_id: CurveLaneId,
     },
+    /// 将选中音符向 `interval` 网格量化，`strength`（0.0..=1.0）为移向网格的比例；时值至少 1 tick
+    Quantize {
+        targets: QuantizeTargets,
+        strength: f32,
+        interval: u64,
+    },
+    /// 给选中音符加上随机的起点（±`time_range` tick）与力度（±`velocity_range`）偏移；
+    /// `delay_only` 时起点只向后偏移，给定 `seed` 时结果可复现
     HumanizeNotes {
         time_range: u64,
         velocity_range: u8,
+        delay_only: bool,
+        seed: Option<u64>,
     },
     BatchTransform {
         transform_type: BatchTransformType,
         value: f64,
     },
+    /// 消除同音高的重叠音符：有选区时只处理选中的音符，否则处理全部
+    ResolveOverlaps {
+        policy: OverlapPolicy,
+    },
+    /// 检查状态（`MidiState::validate_with`，`max_tick` 为音符终点上限，启用循环时一并检查循环区），
+    /// 选中有问题的音符并通过 `ValidationFinished` 报告
+    ValidateState {
+        max_tick: Option<u64>,
+    },
+    /// 粘合选中的同音高音符：间隔小于 `max_gap`（`None` 时为吸附间隔）的相邻音符合并为一个，保留第一个音符的力度
+    GlueNotes {
+        max_gap: Option<u64>,
+    },
+    /// 延长或截短选中的音符，使其终点接上下一个起点更晚的选中音符（不论音高），
+    /// 再加上 `overlap_ticks`（正数重叠、负数留空）；最后的音符保持原时值
+    Legato {
+        overlap_ticks: i64,
+    },
+    /// 将选中的每个和弦（起点相同的音符）替换为覆盖原时值的琶音，见 `structure::arpeggiate`
+    Arpeggiate {
+        pattern: ArpPattern,
+        rate_ticks: u64,
+        octaves: u8,
+        gate: f32,
+    },
+    /// 按起点从最早到最晚的选中音符，把力度从 `start_velocity` 渐变到 `end_velocity`
+    VelocityRamp {
+        start_velocity: u8,
+        end_velocity: u8,
+        curve: RampCurve,
+    },
+    /// 以 `anchor` 为基准把选中音符的起点偏移与时值乘以 `ratio`；`include_curves` 时选区时间范围内的曲线点一起伸缩
+    TimeStretchSelection {
+        ratio: f64,
+        anchor: StretchAnchor,
+        include_curves: bool,
+    },
+    /// 以 `axis_key` 为轴镜像选中音符的音高（`2 * axis_key - key`，限制在 0..=127）
+    InvertPitch {
+        axis_key: u8,
+    },
+    /// 把选中的音符复制到选区之后（偏移为选区跨度向上取整到吸附间隔），并选中副本；不影响剪贴板
+    DuplicateSelection,
+    /// 按查询替换选区（只包含可编辑轨道上的音符），发出 `SelectionChanged`
+    SelectNotes(SelectionQuery),
+    /// 为选中的音符设置标签（`None` 清除）
+    SetNoteLabel(Option<SmallString>),
+    /// 限制可编辑区域（tick 区间 `[start, end)`），`None` 取消限制
+    SetEditBounds(Option<(u64, u64)>),
+    /// 设置音阶高亮（`(主音音级, 音阶)`），`None` 关闭
+    SetScaleHighlight(Option<(u8, ScaleKind)>),
+    /// 开关节拍器
+    SetMetronome(bool),
+    /// 设置播放速率（0.25..=2.0），只改变走带推进的快慢，不改变曲子的速度
+    SetPlaybackRate(f32),
+    /// 开关录音模式（播放中通过 `feed_live_note_on/off` 输入的音符写入编辑器）
+    SetRecording(bool),
+    /// 开关步进输入模式
+    SetStepInput(bool),
+    /// 步进输入：在步进光标处插入一个吸附长度的音符并前进一步（未开启步进输入时忽略）
+    StepInput {
+        key: u8,
+        velocity: u8,
+    },
+    /// 步进输入休止：光标前进一步而不插入音符
+    StepRest,
+    ZoomToFit,
+    /// 缩放到选中音符的时间与音高范围
+    ZoomToSelection,
+    /// 在 `tick` 粘贴剪贴板（一个撤销步骤）；`key_anchor` 为 `Some` 时整体移调，使最低的音符落在该音高上
+    PasteAt {
+        tick: u64,
+        key_anchor: Option<u8>,
+        mode: PasteMode,
+    },
+    /// 直接设置缩放（每拍、每行的像素）与滚动偏移（像素，向右、向下滚动时为负），
+    /// 与 `MidiEditorOptions` 中同名字段含义相同；超出范围的值在下一帧按视图大小限制
+    SetView {
+        zoom_x: f32,
+        zoom_y: f32,
+        scroll_x: f32,
+        scroll_y: f32,
+    },
+    /// 水平滚动，使 `tick` 位于音符网格左边缘
+    ScrollToTick(u64),
+    /// 纵向滚动最少的距离使该音高完全可见（已可见时不动）；需要居中时使用 `CenterOnKey`
+    ScrollToKey(u8),
+    /// 按选中音符的相对位置试听选区，不影响走带与播放状态
+    PlaySelection,
+}
+
+/// 跨编辑器拖放音符时载荷统一使用的 tick 精度
+pub const NOTE_DRAG_TICKS_PER_BEAT: u16 = 480;
+
+/// 在编辑器之间拖放音符的 egui `DragAndDrop` 载荷
+///
+/// 音符按 [`NOTE_DRAG_TICKS_PER_BEAT`] 归一化，起点相对于最早的音符（从 0 开始）。
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoteDragPayload {
+    pub notes: Vec<Note>,
+    /// 指针抓取点相对于载荷起点的 tick 偏移
+    pub anchor_tick: u64,
+    /// 指针抓取的音符音高，落点音高偏移以此为基准
+    pub anchor_key: u8,
+}
+
+impl NoteDragPayload {
+    /// 从编辑器内的音符构建载荷，`anchor_tick` 为源编辑器中的绝对 tick
+    pub fn from_notes(notes: &[Note], ticks_per_beat: u16, anchor_tick: u64, anchor_key: u8) -> Self {
+        let origin = notes.iter().map(|n| n.start).min().unwrap_or(0);
+        let rescale = |tick: u64| rescale_tick(tick, ticks_per_beat, NOTE_DRAG_TICKS_PER_BEAT);
+        let mut notes: Vec<Note> = notes
+            .iter()
+            .map(|n| {
+                let start = rescale(n.start - origin);
+                let end = rescale(n.start + n.duration - origin);
+                Note { start, duration: end.saturating_sub(start).max(1), ..*n }
+            })
+            .collect();
+        notes.sort_by_key(|n| n.start);
+        Self {
+            notes,
+            anchor_tick: rescale(anchor_tick.saturating_sub(origin)),
+            anchor_key,
+        }
+    }
+
+    /// 按目标编辑器的 tick 精度返回音符（起点仍从 0 开始）
+    pub fn notes_for(&self, ticks_per_beat: u16) -> Vec<Note> {
+        let rescale = |tick: u64| rescale_tick(tick, NOTE_DRAG_TICKS_PER_BEAT, ticks_per_beat);
+        self.notes
+            .iter()
+            .map(|n| {
+                let start = rescale(n.start);
+                Note { start, duration: rescale(n.start + n.duration).saturating_sub(start).max(1), ..*n }
+            })
+            .collect()
+    }
+
+    /// 按目标编辑器的 tick 精度返回抓取点偏移
+    pub fn anchor_tick_for(&self, ticks_per_beat: u16) -> u64 {
+        rescale_tick(self.anchor_tick, NOTE_DRAG_TICKS_PER_BEAT, ticks_per_beat)
+    }
+}
+
+/// 编辑器剪贴板的可序列化内容，宿主可借此在编辑器之间（或经系统剪贴板）复制粘贴
+///
+/// 音符、分组与曲线片段一起携带，tick 精度由 `ticks_per_beat` 描述。
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClipboardPayload {
+    pub ticks_per_beat: u16,
+    pub notes: Vec<Note>,
+    #[serde(default)]
+    pub groups: Vec<NoteGroup>,
+    #[serde(default)]
+    pub curve: Option<CurveClip>,
+    /// 随音符一起复制的各曲线片段（起点为最早音符的起点），粘贴到同类曲线
+    #[serde(default)]
+    pub curves: Vec<CurveClip>,
+    /// 复制时源编辑器的速度，仅供参考（粘贴按拍换算，不受速度影响）
+    #[serde(default)]
+    pub bpm: Option<f32>,
+}
+
+/// 写入系统剪贴板的文本以此开头，粘贴时只解析带此前缀的文本
+const CLIPBOARD_TEXT_PREFIX: &str = "egui_midi-clipboard-v1:";
+
+impl ClipboardPayload {
+    /// 序列化为写入系统剪贴板的文本（带前缀的紧凑 JSON）
+    pub fn to_clipboard_text(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        format!("{CLIPBOARD_TEXT_PREFIX}{json}")
+    }
+
+    /// 解析 `to_clipboard_text` 写出的文本；其他应用复制的普通文本返回 `None`
+    pub fn from_clipboard_text(text: &str) -> Option<Self> {
+        let json = text.trim().strip_prefix(CLIPBOARD_TEXT_PREFIX)?;
+        serde_json::from_str(json).ok()
+    }
+
+    /// 换算到目标编辑器的 tick 精度
+    pub fn rescaled(&self, ticks_per_beat: u16) -> Self {
+        let rescale = |tick: u64| rescale_tick(tick, self.ticks_per_beat, ticks_per_beat);
+        Self {
+            ticks_per_beat,
+            notes: self
+                .notes
+                .iter()
+                .map(|n| {
+                    let start = rescale(n.start);
+                    Note { start, duration: rescale(n.start + n.duration).saturating_sub(start).max(1), ..*n }
+                })
+                .collect(),
+            groups: self.groups.clone(),
+            curve: self.curve.as_ref().map(|clip| clip.rescaled(rescale)),
+            curves: self.curves.iter().map(|clip| clip.rescaled(rescale)).collect(),
+            bpm: self.bpm,
+        }
+    }
+}
+
+/// 合并导入时内容的放置位置
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImportPlacement {
+    /// 导入内容的 tick 0 对齐到播放头
+    #[default]
+    Playhead,
+    /// 保持原有位置（tick 0 对齐到 tick 0）
+    Start,
+}
+
+/// `MidiEditor::import_notes` 的合并选项
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImportMergeOptions {
+    pub placement: ImportPlacement,
+    /// 导入内容的 TPB 与当前不同时按比例换算 tick（关闭时按原 tick 数放置）
+    pub rescale_ticks: bool,
+    /// 整体移调（半音），移出 0..=127 的音符被丢弃
+    pub transpose: i8,
+    /// 是否同时导入曲线：同类型的通道合并点，没有的通道新建
+    pub include_curves: bool,
+}
+
+impl Default for ImportMergeOptions {
+    fn default() -> Self {
+        Self {
+            placement: ImportPlacement::default(),
+            rescale_ticks: true,
+            transpose: 0,
+            include_curves: false,
+        }
+    }
+}
+
+/// 侧边键盘试听的力度曲线：按键内的水平点击位置映射为力度
+///
+/// 左边缘对应 `min`，右边缘对应 `max`，中间按 `t^exponent` 插值；
+/// `exponent > 1` 时低力度区更宽，`< 1` 时高力度区更宽。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyVelocityCurve {
+    pub min: u8,
+    pub max: u8,
+    pub exponent: f32,
+}
+
+impl Default for KeyVelocityCurve {
+    fn default() -> Self {
+        Self {
+            min: 30,
+            max: 127,
+            exponent: 1.0,
+        }
+    }
+}
+
+impl KeyVelocityCurve {
+    /// `t` 为按键内的相对位置（0.0 = 左边缘，1.0 = 右边缘），超出范围时截断
+    pub fn velocity_at(&self, t: f32) -> u8 {
+        let t = if t.is_finite() { t.clamp(0.0, 1.0) } else { 0.0 };
+        let shaped = t.powf(self.exponent.max(0.01));
+        let (min, max) = (self.min.min(127) as f32, self.max.min(127) as f32);
+        (min + (max - min) * shaped).round().clamp(1.0, 127.0) as u8
+    }
+}
+
+/// 钢琴卷帘下方区域显示的内容
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LowerLaneView {
+    /// 曲线（力度、CC、弯音等）
+    #[default]
+    Curves,
+    /// 每个音符一根力度柱，拖动设置该音符的力度
+    NoteVelocity,
+}
+
+impl LowerLaneView {
+    pub const ALL: [LowerLaneView; 2] = [LowerLaneView::Curves, LowerLaneView::NoteVelocity];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LowerLaneView::Curves => "Curves",
+            LowerLaneView::NoteVelocity => "Note Velocity",
+        }
+    }
+}
+
+/// 曲线区域中拖动的作用
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CurveTool {
+    /// 点击添加点、拖动移动点
+    #[default]
+    Point,
+    /// 按吸附精度沿指针写入点，覆盖经过的已有点
+    Pencil,
+    /// 用起点与终点两个点构成的斜坡替换其间的点
+    Line,
+}
+
+impl CurveTool {
+    pub const ALL: [CurveTool; 3] = [CurveTool::Point, CurveTool::Pencil, CurveTool::Line];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CurveTool::Point => "Point",
+            CurveTool::Pencil => "Pencil",
+            CurveTool::Line => "Line",
+        }
+    }
+}
+
+/// 钢琴卷帘中左键拖动的作用
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NoteTool {
+    /// 选择、移动与调整音符（配合修饰键新建或删除）
+    #[default]
+    Select,
+    /// 沿水平拖动按吸附精度在按下的音高上逐格写入音符，跳过已有该音高音符的格子
+    Draw,
+    /// 删除拖动经过的音符
+    Erase,
+    /// 在按下的位置切开音符；竖直拖动时切开切线经过的所有音高上的音符
+    Slice,
+}
+
+impl NoteTool {
+    pub const ALL: [NoteTool; 4] = [NoteTool::Select, NoteTool::Draw, NoteTool::Erase, NoteTool::Slice];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NoteTool::Select => "Select",
+            NoteTool::Draw => "Draw",
+            NoteTool::Erase => "Erase",
+            NoteTool::Slice => "Slice",
+        }
+    }
+}
+
+/// 双击音符的动作；无论哪种都会先发出 `EditorEvent::NoteDoubleClicked`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NoteDoubleClickAction {
+    /// 只发出事件，由宿主决定
+    #[default]
+    EventOnly,
+    /// 在音符旁打开属性小窗，编辑起点、时值、音高与力度（Enter 提交、Escape 取消）
+    Properties,
+    /// 删除音符
+    Delete,
+}
+
+impl NoteDoubleClickAction {
+    pub const ALL: [NoteDoubleClickAction; 3] =
+        [NoteDoubleClickAction::EventOnly, NoteDoubleClickAction::Properties, NoteDoubleClickAction::Delete];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NoteDoubleClickAction::EventOnly => "Event only",
+            NoteDoubleClickAction::Properties => "Properties",
+            NoteDoubleClickAction::Delete => "Delete",
+        }
+    }
+}
+
+/// 粘贴音符时如何对待目标位置已有的音符
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PasteMode {
+    /// 直接加入，保留已有音符
+    #[default]
+    Insert,
+    /// 先删除当前轨道上起点位于粘贴范围内的音符
+    Replace,
+    /// 跳过与已有音符起点和音高都相同的音符
+    MergeSkipDuplicates,
+}
+
+impl PasteMode {
+    pub const ALL: [PasteMode; 3] = [PasteMode::Insert, PasteMode::Replace, PasteMode::MergeSkipDuplicates];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PasteMode::Insert => "Insert",
+            PasteMode::Replace => "Replace",
+            PasteMode::MergeSkipDuplicates => "Merge (skip duplicates)",
+        }
+    }
+}
+
+/// `MidiEditor::export_loop_smf` 失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopExportError {
+    /// 循环区为空（终点不晚于起点）
+    EmptyLoop,
+    /// 循环区内没有音符
+    NoNotes,
+}
+
+impl fmt::Display for LoopExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopExportError::EmptyLoop => write!(f, "循环区为空"),
+            LoopExportError::NoNotes => write!(f, "循环区内没有音符"),
+        }
+    }
+}
+
+impl std::error::Error for LoopExportError {}
+
+/// Shift+点击新建音符时使用的时值
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NoteLength {
+    /// 当前吸附间隔（吸附关闭时为一拍）
+    #[default]
+    Snap,
+    /// 固定的音符时值，按曲子的 `ticks_per_beat` 换算
+    Fixed(SnapResolution, SnapModifier),
+    /// 最近一次新建或调整过时值的音符的时值（还没有时同 `Snap`）
+    LastUsed,
+}
+
+impl NoteLength {
+    pub fn label(&self) -> String {
+        match self {
+            NoteLength::Snap => "Snap".to_owned(),
+            NoteLength::Fixed(resolution, modifier) => format!("{}{}", resolution.label(), modifier.suffix()),
+            NoteLength::LastUsed => "Last used".to_owned(),
+        }
+    }
+}
+
+/// `EditorCommand::SelectNotes` 的选择条件
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionQuery {
+    /// 所有该音高的音符
+    SamePitch(u8),
+    /// 起点位于循环区域 `loop_start_tick..loop_end_tick` 内的音符
+    InLoopRegion,
+    /// 反选
+    Invert,
+}
+
+/// 量化作用于音符的哪一端
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum QuantizeTargets {
+    /// 只移动起点，时值不变
+    #[default]
+    Starts,
+    /// 只移动终点（改变时值），起点不变
+    Ends,
+    /// 起点与终点分别吸附
+    Both,
+}
+
+impl QuantizeTargets {
+    pub const ALL: [QuantizeTargets; 3] = [QuantizeTargets::Starts, QuantizeTargets::Ends, QuantizeTargets::Both];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            QuantizeTargets::Starts => "Starts",
+            QuantizeTargets::Ends => "Ends",
+            QuantizeTargets::Both => "Starts and ends",
+        }
+    }
+}
+
+/// 时间伸缩的基准点，基准处的位置保持不变
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StretchAnchor {
+    /// 选区中最早的音符起点
+    #[default]
+    SelectionStart,
+    /// 播放头
+    Playhead,
+    /// 选区起点所在小节的开头
+    BarStart,
+}
+
+impl StretchAnchor {
+    pub const ALL: [StretchAnchor; 3] = [StretchAnchor::SelectionStart, StretchAnchor::Playhead, StretchAnchor::BarStart];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StretchAnchor::SelectionStart => "Selection start",
+            StretchAnchor::Playhead => "Playhead",
+            StretchAnchor::BarStart => "Bar start",
+        }
+    }
+}
+
+/// 吸附网格的音符时值（全音符..六十四分音符），使用时才按 `ticks_per_beat` 换算成 tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SnapResolution {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    ThirtySecond,
+    SixtyFourth,
+}
+
+impl SnapResolution {
+    pub const ALL: [Self; 7] = [
+        Self::Whole,
+        Self::Half,
+        Self::Quarter,
+        Self::Eighth,
+        Self::Sixteenth,
+        Self::ThirtySecond,
+        Self::SixtyFourth,
+    ];
+
+    /// 分母：全音符为 1，十六分音符为 16
+    pub fn denominator(self) -> u64 {
+        match self {
+            Self::Whole => 1,
+            Self::Half => 2,
+            Self::Quarter => 4,
+            Self::Eighth => 8,
+            Self::Sixteenth => 16,
+            Self::ThirtySecond => 32,
+            Self::SixtyFourth => 64,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Whole => "1/1",
+            Self::Half => "1/2",
+            Self::Quarter => "1/4",
+            Self::Eighth => "1/8",
+            Self::Sixteenth => "1/16",
+            Self::ThirtySecond => "1/32",
+            Self::SixtyFourth => "1/64",
+        }
+    }
+
+    /// 按四分音符为 `ticks_per_beat` 换算出的 tick 数（四舍五入，至少 1）
+    pub fn ticks(self, ticks_per_beat: u16, modifier: SnapModifier) -> u64 {
+        let (numerator, denominator) = match modifier {
+            SnapModifier::Straight => (1, 1),
+            SnapModifier::Triplet => (2, 3),
+            SnapModifier::Dotted => (3, 2),
+        };
+        let whole = ticks_per_beat.max(1) as u64 * 4 * numerator;
+        let divisor = self.denominator() * denominator;
+        ((whole + divisor / 2) / divisor).max(1)
+    }
+}
+
+/// 吸附时值的变体：普通、三连音（2/3 长）或附点（3/2 长）
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum SnapModifier {
+    #[default]
+    Straight,
+    Triplet,
+    Dotted,
+}
+
+impl SnapModifier {
+    pub const ALL: [Self; 3] = [Self::Straight, Self::Triplet, Self::Dotted];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Straight => "Straight",
+            Self::Triplet => "Triplet",
+            Self::Dotted => "Dotted",
+        }
+    }
+
+    /// 附在时值后的简写："1/8T"、"1/4."
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Self::Straight => "",
+            Self::Triplet => "T",
+            Self::Dotted => ".",
+        }
+    }
+}
+
+/// 钢琴卷帘音阶高亮使用的音阶类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScaleKind {
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MelodicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+}
+
+impl ScaleKind {
+    pub const ALL: [ScaleKind; 11] = [
+        ScaleKind::Major,
+        ScaleKind::NaturalMinor,
+        ScaleKind::HarmonicMinor,
+        ScaleKind::MelodicMinor,
+        ScaleKind::Dorian,
+        ScaleKind::Phrygian,
+        ScaleKind::Lydian,
+        ScaleKind::Mixolydian,
+        ScaleKind::MajorPentatonic,
+        ScaleKind::MinorPentatonic,
+        ScaleKind::Blues,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScaleKind::Major => "Major",
+            ScaleKind::NaturalMinor => "Natural Minor",
+            ScaleKind::HarmonicMinor => "Harmonic Minor",
+            ScaleKind::MelodicMinor => "Melodic Minor",
+            ScaleKind::Dorian => "Dorian",
+            ScaleKind::Phrygian => "Phrygian",
+            ScaleKind::Lydian => "Lydian",
+            ScaleKind::Mixolydian => "Mixolydian",
+            ScaleKind::MajorPentatonic => "Major Pentatonic",
+            ScaleKind::MinorPentatonic => "Minor Pentatonic",
+            ScaleKind::Blues => "Blues",
+        }
+    }
+
+    /// 相对主音的半音数（升序，从 0 开始）
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleKind::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            ScaleKind::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleKind::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleKind::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleKind::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Blues => &[0, 3, 5, 6, 7, 10],
+        }
+    }
+
+    /// 以 `root`（音级，按 12 取模）为主音时属于该音阶的全部音高
+    pub fn keys(&self, root: u8) -> BitSet128 {
+        let mut pitch_classes = [false; 12];
+        for interval in self.intervals() {
+            pitch_classes[(root as usize + *interval as usize) % 12] = true;
+        }
+        (0..=127u8).filter(|key| pitch_classes[*key as usize % 12]).collect()
+    }
+
+    /// `key` 处或以下最近的音阶音的级数，以及 `key` 高出它的半音数
+    ///
+    /// 级数从 MIDI 0 之下的第一个主音起算（该主音为 0 级），因此对 0..=127 的音高总是非负的。
+    pub fn degree_below(&self, root: u8, key: u8) -> (i32, u8) {
+        let intervals = self.intervals();
+        let relative = key as i32 + 12 - (root % 12) as i32;
+        let within = (relative % 12) as u8;
+        let index = intervals.iter().rposition(|interval| *interval <= within).unwrap_or(0);
+        (
+            relative / 12 * intervals.len() as i32 + index as i32,
+            within - intervals[index],
+        )
+    }
+
+    /// 级数对应的音高（与 [`degree_below`](Self::degree_below) 的编号一致），超出 0..=127 时返回 `None`
+    pub fn key_at_degree(&self, root: u8, degree: i32) -> Option<u8> {
+        let intervals = self.intervals();
+        let count = intervals.len() as i32;
+        let key = (root % 12) as i32 - 12 + degree.div_euclid(count) * 12 + intervals[degree.rem_euclid(count) as usize] as i32;
+        u8::try_from(key).ok().filter(|key| *key <= 127)
+    }
 }
 
 /// 初始化与运行时的视图配置
@@ -115,6 +1560,9 @@ pub enum EditorCommand {
 pub struct MidiEditorOptions {
     pub zoom_x: f32,
     pub zoom_y: f32,
+    /// 按音符时值吸附（默认十六分音符），随曲子的 `ticks_per_beat` 换算；`None` 时使用 `snap_interval`
+    pub snap_resolution: Option<(SnapResolution, SnapModifier)>,
+    /// 自定义的吸附间隔（tick），只在 `snap_resolution` 为 `None` 时生效
     pub snap_interval: u64,
     pub snap_mode: SnapMode,
     pub swing_ratio: f32,
@@ -122,14 +1570,88 @@ pub struct MidiEditorOptions {
     pub preview_pitch_shift: f32,
     pub loop_enabled: bool,
     pub loop_start_tick: u64,
-    pub loop_end_tick: u64,
+    /// 循环终点；`None` 时为从起点开始的一个完整小节（按当前拍号）
+    pub loop_end_tick: Option<u64>,
     pub manual_scroll_x: f32,
     pub manual_scroll_y: f32,
     /// 可选：启动时将视图滚动到某个音高
     pub center_on_key: Option<u8>,
     /// 是否启用 Space 键播放/暂停功能（默认启用）
-    /// 如果宿主应用需要处理 Space 键，可以设置为 false
+    /// 如果宿主应用需要处理 Space 键，可以设置为 false（等同于忽略 `shortcuts` 中 `TogglePlayback` 的绑定）
     pub enable_space_playback: bool,
+    /// 键盘快捷键表；移除某个操作的绑定即可把按键留给宿主应用
+    pub shortcuts: ShortcutMap,
+    /// 按住这些修饰键拖动音符时拖动的是副本，原音符留在原处（默认 Ctrl/Cmd+Alt，`Modifiers::NONE` 关闭）
+    pub duplicate_drag_modifiers: Modifiers,
+    /// 播放中新建或移动到播放头下方的音符是否立即发声（默认关闭）
+    pub trigger_notes_under_playhead: bool,
+    /// 粘贴曲线片段时是否清除片段范围内已有的点（默认开启）
+    pub curve_paste_overwrite: bool,
+    /// Ctrl/Cmd+V、菜单粘贴与 "Paste here" 使用的粘贴方式
+    pub paste_mode: PasteMode,
+    /// 复制时把音符写入系统剪贴板（`ClipboardPayload::to_clipboard_text`），Ctrl/Cmd+V 时优先粘贴系统剪贴板中的音符（默认开启）
+    pub system_clipboard: bool,
+    /// Shift+点击新建音符的时值（默认跟随吸附间隔）
+    pub note_length: NoteLength,
+    /// Shift+点击新建音符的力度（1..=127，默认 100）
+    pub default_velocity: u8,
+    /// 选区变化时以和弦形式短暂试听新选中的音符（最多 8 个音，默认关闭）；拖动音符时仍由拖动试听负责
+    pub audition_on_select: bool,
+    /// 选中试听的发声时长（秒，默认 0.4）
+    pub audition_duration: f32,
+    /// 点击侧边键盘试听时使用的力度曲线
+    pub key_velocity_curve: KeyVelocityCurve,
+    /// 悬停在音符上多久（秒）后显示音高、位置、时值等提示；`None` 关闭（默认）
+    pub hover_tooltip_delay: Option<f32>,
+    /// 统计编辑器发出的重绘请求次数（调试用，默认关闭），通过 `MidiEditor::repaint_request_count` 读取；
+    /// 空闲时（未播放、无动画、无拖拽）计数应保持不变
+    pub debug_repaint_counter: bool,
+    /// 未被 `take_events` 取走的事件最多保留多少条（默认 4096，0 不限制）；超出时丢弃最旧的并记录警告，监听器不受影响
+    pub max_pending_events: usize,
+    /// 每次编辑（`edit_state`、速度、拍号、人性化等）在细粒度事件之后再附带一份完整状态的 `StateReplaced`（默认关闭）；
+    /// 状态较大时每次都要克隆全部音符与曲线，只为依赖旧行为的宿主保留
+    pub state_replaced_on_edit: bool,
+    /// 复制时剪贴板最多保存的音符数（默认 100000，0 不限制）；超出时只保留最早的音符
+    pub max_clipboard_notes: usize,
+    /// 撤销与重做历史各自最多保存的步数（默认 64，至少 1）
+    pub max_undo_entries: usize,
+    /// 音阶高亮：`(主音音级 0..12, 音阶)`，音阶内外的音高行以不同底色区分，侧边键盘标出主音；`None` 关闭（默认）
+    pub scale_highlight: Option<(u8, ScaleKind)>,
+    /// 设置了音阶高亮时，垂直拖动音符与检查器的音高滑块按音阶级数移动而不是按半音（按住 Alt 临时关闭；默认关闭）
+    pub snap_to_scale: bool,
+    /// 用电脑键盘弹奏（默认关闭）：Z..M 行为低八度、Q..P 行为高八度，`-` / `=` 整体升降八度；
+    /// 文本框有焦点或按住 Ctrl/Cmd/Alt 时不响应
+    pub computer_keyboard_input: bool,
+    /// 录音时是否把音符的起点与终点吸附到吸附网格（默认关闭）
+    pub record_quantize: bool,
+    /// 播放时在每拍发出节拍器声（小节第一拍为重音，默认关闭），通过 `PlaybackBackend::click` 发出
+    pub metronome: bool,
+    /// 节拍器音量（0.0..=1.0，默认 0.5），与音符音量分开调节
+    pub metronome_volume: f32,
+    /// 播放速率（0.25..=2.0，默认 1.0）：按倍数推进走带，用于放慢试听快速的段落；`MidiState::bpm` 不变，跟随外部同步时不生效
+    pub playback_rate: f32,
+    /// 在足够大的音符矩形内显示音高名称（"C4"、"F#3"，默认关闭）
+    pub show_note_names: bool,
+    /// 在足够大的音符矩形内显示力度（默认关闭）
+    pub show_note_velocities: bool,
+    /// 按力度给音符着色（1..=127 映射到蓝 → 绿 → 红的渐变，默认关闭）；选中状态仍由加粗的白色描边表示
+    pub color_by_velocity: bool,
+    /// 按力度着色时使用力度曲线生效后的值（`MidiState::apply_velocity_curve_to_note`），即实际播放的力度（默认关闭）
+    pub color_by_effective_velocity: bool,
+    /// 在钢琴卷帘上方显示整首曲子的概览条，可拖动其中的视口矩形滚动（拖动左右边缘缩放），默认关闭
+    pub show_minimap: bool,
+    /// 在钢琴卷帘下方显示事件列表：按起点、时值、音高或力度排序的音符表格，可直接拖动数值编辑（默认关闭）
+    pub show_event_list: bool,
+    /// 中央 C（音高 60）显示的八度编号：默认 4（"C4"），部分宿主习惯 3（"C3"）；影响所有音高名称
+    pub middle_c_octave: i8,
+    /// 拖动（移动、调整时值或新建）音符结束时或合并导入音符后，截短或删除被它覆盖的同音高音符，改动与该编辑合为一步撤销（默认关闭）
+    pub auto_trim_overlaps: bool,
+    /// 双击音符的动作（默认只发出 `NoteDoubleClicked`）
+    pub note_double_click: NoteDoubleClickAction,
+    /// 鼓映射模式（`DrumMap::general_midi()` 为内置的 GM 映射）；`None` 为普通钢琴卷帘（默认）
+    pub drum_map: Option<DrumMap>,
+    /// 绘制用的颜色；`None` 时每帧按宿主当前的 `egui::Visuals` 推导（默认）
+    pub theme: Option<MidiEditorTheme>,
 }
 
 impl Default for MidiEditorOptions {
@@ -137,6 +1659,7 @@ impl Default for MidiEditorOptions {
         Self {
             zoom_x: 100.0,
             zoom_y: 20.0,
+            snap_resolution: Some((SnapResolution::Sixteenth, SnapModifier::Straight)),
             snap_interval: 120,
             snap_mode: SnapMode::Absolute,
             swing_ratio: 0.0,
@@ -144,11 +1667,46 @@ impl Default for MidiEditorOptions {
             preview_pitch_shift: 0.0,
             loop_enabled: false,
             loop_start_tick: 0,
-            loop_end_tick: 1920,
+            loop_end_tick: None,
             manual_scroll_x: 0.0,
             manual_scroll_y: 0.0,
             center_on_key: Some(60),
             enable_space_playback: true,
+            shortcuts: ShortcutMap::default(),
+            duplicate_drag_modifiers: Modifiers::COMMAND | Modifiers::ALT,
+            trigger_notes_under_playhead: false,
+            curve_paste_overwrite: true,
+            paste_mode: PasteMode::Insert,
+            system_clipboard: true,
+            note_length: NoteLength::Snap,
+            default_velocity: 100,
+            audition_on_select: false,
+            audition_duration: 0.4,
+            key_velocity_curve: KeyVelocityCurve::default(),
+            hover_tooltip_delay: None,
+            debug_repaint_counter: false,
+            max_pending_events: 4096,
+            state_replaced_on_edit: false,
+            max_clipboard_notes: 100_000,
+            max_undo_entries: 64,
+            scale_highlight: None,
+            snap_to_scale: false,
+            computer_keyboard_input: false,
+            record_quantize: false,
+            metronome: false,
+            metronome_volume: 0.5,
+            playback_rate: 1.0,
+            show_note_names: false,
+            show_note_velocities: false,
+            color_by_velocity: false,
+            color_by_effective_velocity: false,
+            show_minimap: false,
+            show_event_list: false,
+            middle_c_octave: 4,
+            auto_trim_overlaps: false,
+            note_double_click: NoteDoubleClickAction::default(),
+            drum_map: None,
+            theme: None,
         }
     }
 }
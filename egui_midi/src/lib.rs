@@ -1,4 +1,9 @@
 pub mod audio;
+pub mod demo;
 pub mod editor;
+pub mod loader;
+pub mod palette_picker;
 pub mod structure;
+pub mod sync;
+pub mod tooltip;
 pub mod ui;
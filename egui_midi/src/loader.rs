@@ -0,0 +1,273 @@
+use crate::structure::{ImportWarning, MidiState, SmfImportOptions, ValidationIssue};
+use crossbeam_channel::{unbounded, Receiver, TryRecvError};
+use midly::Smf;
+use std::fmt;
+
+/// 后台导入使用的模式，与同步接口一一对应
+#[derive(Clone, Copy, Debug)]
+pub enum SmfLoadMode {
+    /// 同 `MidiState::from_smf_with_options`，不收集警告
+    Standard(SmfImportOptions),
+    /// 同 `MidiState::from_smf_lenient`
+    Lenient,
+    /// 同 `MidiState::from_smf_strict`
+    Strict,
+}
+
+/// 后台导入的结果
+#[derive(Clone, Debug)]
+pub struct SmfLoaded {
+    pub state: MidiState,
+    /// 宽松模式下跳过或修补的内容，其他模式为空
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// 后台导入失败的原因：解析错误与严格模式的校验错误沿用同步接口的类型
+#[derive(Debug)]
+pub enum SmfLoadError {
+    /// `Smf::parse` 失败
+    Parse(midly::Error),
+    /// 严格模式下不是单轨单通道（`MidiState::from_smf_strict` 的错误）
+    Invalid(ValidationIssue),
+    /// 工作线程没有给出结果就退出了
+    Interrupted,
+}
+
+impl fmt::Display for SmfLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmfLoadError::Parse(err) => write!(f, "无法解析 MIDI 文件：{err}"),
+            SmfLoadError::Invalid(err) => write!(f, "{err}"),
+            SmfLoadError::Interrupted => write!(f, "导入线程意外退出"),
+        }
+    }
+}
+
+impl std::error::Error for SmfLoadError {}
+
+/// 进度中分给 `Smf::parse` 的份额：解析本身无法报告中间进度，完成时一次跳到这里
+const PARSE_SHARE: f32 = 0.1;
+
+enum LoaderMessage {
+    Progress(f32),
+    Finished(Box<Result<SmfLoaded, SmfLoadError>>),
+}
+
+/// 在工作线程上解析并转换 SMF，界面线程每帧调用 `poll` 取回进度与结果
+///
+/// ```ignore
+/// // 打开文件时
+/// self.loader = Some(MidiLoader::spawn(bytes, SmfLoadMode::Lenient));
+/// // update() 中
+/// if let Some(loader) = &mut self.loader {
+///     match loader.poll() {
+///         Some(Ok(loaded)) => editor.replace_state(loaded.state),
+///         Some(Err(err)) => log::error!("{err}"),
+///         None => ctx.request_repaint(), // 仍在导入，显示 loader.progress()
+///     }
+/// }
+/// ```
+pub struct MidiLoader {
+    receiver: Receiver<LoaderMessage>,
+    progress: f32,
+    finished: bool,
+}
+
+impl MidiLoader {
+    /// 启动工作线程导入 `bytes`
+    pub fn spawn(bytes: Vec<u8>, mode: SmfLoadMode) -> Self {
+        let (sender, receiver) = unbounded();
+        let progress_sender = sender.clone();
+        let spawned = std::thread::Builder::new()
+            .name("midi-loader".into())
+            .spawn(move || {
+                // 只在整数百分比变化时发送，避免大文件刷满通道
+                let mut last_percent = 0;
+                let mut report = |fraction: f32| {
+                    let percent = (fraction * 100.0) as u32;
+                    if percent > last_percent {
+                        last_percent = percent;
+                        let _ = progress_sender.send(LoaderMessage::Progress(fraction));
+                    }
+                };
+                let result = load_smf(&bytes, mode, &mut report);
+                let _ = sender.send(LoaderMessage::Finished(Box::new(result)));
+            });
+        if let Err(err) = spawned {
+            log::error!("MidiLoader: failed to spawn worker thread: {err}");
+        }
+        Self {
+            receiver,
+            progress: 0.0,
+            finished: false,
+        }
+    }
+
+    /// 导入进度（0.0..=1.0）：文件解析完成前为 0，解析完成后为 0.1，其余部分按已转换的事件比例增长
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    /// 结果是否已经由 `poll` 取走
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// 取回工作线程的消息并更新进度；导入完成时返回一次结果，之后总是 `None`
+    pub fn poll(&mut self) -> Option<Result<SmfLoaded, SmfLoadError>> {
+        if self.finished {
+            return None;
+        }
+        loop {
+            match self.receiver.try_recv() {
+                Ok(LoaderMessage::Progress(fraction)) => self.progress = fraction,
+                Ok(LoaderMessage::Finished(result)) => {
+                    self.progress = 1.0;
+                    self.finished = true;
+                    return Some(*result);
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    return Some(Err(SmfLoadError::Interrupted));
+                }
+            }
+        }
+    }
+
+    /// 阻塞等待导入完成（测试或命令行工具使用）
+    pub fn wait(self) -> Result<SmfLoaded, SmfLoadError> {
+        loop {
+            match self.receiver.recv() {
+                Ok(LoaderMessage::Progress(_)) => {}
+                Ok(LoaderMessage::Finished(result)) => return *result,
+                Err(_) => return Err(SmfLoadError::Interrupted),
+            }
+        }
+    }
+}
+
+/// 工作线程上的实际导入；`report` 收到整体进度，解析占前 [`PARSE_SHARE`]，转换占其余部分
+fn load_smf(bytes: &[u8], mode: SmfLoadMode, report: &mut dyn FnMut(f32)) -> Result<SmfLoaded, SmfLoadError> {
+    let smf = Smf::parse(bytes).map_err(SmfLoadError::Parse)?;
+    report(PARSE_SHARE);
+    let total = smf.tracks.iter().map(Vec::len).sum::<usize>().max(1);
+    let mut progress = |processed: usize| report(PARSE_SHARE + (1.0 - PARSE_SHARE) * processed as f32 / total as f32);
+    match mode {
+        SmfLoadMode::Standard(options) => Ok(SmfLoaded {
+            state: MidiState::import_smf(&smf, options, &mut Vec::new(), &mut progress),
+            warnings: Vec::new(),
+        }),
+        SmfLoadMode::Lenient => {
+            let (state, warnings) = MidiState::from_smf_lenient_with_progress(&smf, &mut progress);
+            Ok(SmfLoaded { state, warnings })
+        }
+        SmfLoadMode::Strict => MidiState::from_smf_strict_with_progress(&smf, &mut progress)
+            .map(|state| SmfLoaded {
+                state,
+                warnings: Vec::new(),
+            })
+            .map_err(SmfLoadError::Invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::{export_single_track, CurveLane, CurveLaneType, CurvePoint, MidiTrack, Note, TempoChange};
+
+    fn smf_bytes(notes: Vec<Note>, tracks: Vec<MidiTrack>) -> Vec<u8> {
+        export_single_track(&MidiState {
+            notes,
+            tracks,
+            ..MidiState::default()
+        })
+    }
+
+    /// 去掉所有 `id` 字段后的 JSON：两次导入分配的 ID 不同，其余内容应完全一致
+    fn without_ids(state: &MidiState) -> serde_json::Value {
+        fn strip(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.remove("id");
+                    map.values_mut().for_each(strip);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(state).unwrap();
+        strip(&mut value);
+        value
+    }
+
+    #[test]
+    fn background_load_matches_the_sync_api() {
+        // 两条轨道、速度变化、CC 曲线与非默认分辨率，宽松导入会报告非单轨
+        let notes: Vec<Note> = (0..10_000u64)
+            .map(|i| Note { track: (i % 2) as u16, ..Note::new(i * 240, 240, 40 + (i % 48) as u8, 100) })
+            .collect();
+        let mut cc = CurveLane::new(CurveLaneType::ControlChange(7));
+        cc.points = vec![CurvePoint::new(0, 100.0), CurvePoint::new(1920, 64.0)];
+        let mut state = MidiState {
+            notes,
+            tracks: vec![MidiTrack::default(), MidiTrack::default()],
+            ticks_per_beat: 960,
+            tempo_changes: vec![TempoChange { tick: 3840, bpm: 90.0 }],
+            ..MidiState::default()
+        };
+        state.curves.push(cc);
+        let bytes = export_single_track(&state);
+        let smf = Smf::parse(&bytes).unwrap();
+        let (expected, expected_warnings) = MidiState::from_smf_lenient(&smf);
+
+        let mut loader = MidiLoader::spawn(bytes, SmfLoadMode::Lenient);
+        let mut progress = Vec::new();
+        let loaded = loop {
+            if let Some(result) = loader.poll() {
+                break result.unwrap();
+            }
+            progress.push(loader.progress());
+            std::thread::yield_now();
+        };
+        assert!(loader.is_finished() && loader.poll().is_none());
+        assert_eq!(loader.progress(), 1.0);
+        assert!(progress.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        assert_eq!(without_ids(&loaded.state), without_ids(&expected));
+        assert_eq!(loaded.state.notes.len(), 10_000);
+        assert_eq!(loaded.state.ticks_per_beat, 960);
+        assert_eq!(loaded.state.tempo_changes.len(), 1);
+        assert!(!expected_warnings.is_empty());
+        assert_eq!(loaded.warnings, expected_warnings);
+    }
+
+    #[test]
+    fn progress_reports_the_parse_phase_before_conversion() {
+        let notes: Vec<Note> = (0..1_000u64).map(|i| Note::new(i * 120, 120, 60, 100)).collect();
+        let bytes = smf_bytes(notes, Vec::new());
+        let mut reported = Vec::new();
+        load_smf(&bytes, SmfLoadMode::Lenient, &mut |fraction| reported.push(fraction)).unwrap();
+        assert_eq!(reported[0], PARSE_SHARE);
+        assert_eq!(*reported.last().unwrap(), 1.0);
+        assert!(reported.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn background_load_reports_the_sync_error_types() {
+        assert!(matches!(
+            MidiLoader::spawn(b"not a midi file".to_vec(), SmfLoadMode::Lenient).wait(),
+            Err(SmfLoadError::Parse(_))
+        ));
+
+        let notes = vec![Note::new(0, 480, 60, 100), Note { track: 1, ..Note::new(0, 480, 64, 100) }];
+        let bytes = smf_bytes(notes, vec![MidiTrack::default(), MidiTrack::default()]);
+        let strict = MidiState::from_smf_strict(&Smf::parse(&bytes).unwrap()).unwrap_err();
+        match MidiLoader::spawn(bytes.clone(), SmfLoadMode::Strict).wait() {
+            Err(SmfLoadError::Invalid(issue)) => assert_eq!(issue, strict),
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+        let loaded = MidiLoader::spawn(bytes, SmfLoadMode::Lenient).wait().unwrap();
+        assert!(loaded.warnings.iter().any(|w| matches!(w, ImportWarning::NotSingleTrack(_))));
+    }
+}
@@ -0,0 +1,225 @@
+//! 轻量调色板取色控件
+//!
+//! 预设色块 + 最近使用的颜色 + 可展开的完整 egui 取色器。最近使用的颜色保存在 egui memory 中
+//! （启用 egui 的 `persistence` 特性时会随应用状态一起持久化），同一个 `Id` 下的取色器共享记录。
+//!
+//! ```no_run
+//! # fn demo(ui: &mut egui::Ui, color: &mut egui::Color32) {
+//! // 色块按钮，点击弹出调色板
+//! if egui_midi::palette_picker::palette_button(ui, "theme_accent", color).changed() {
+//!     // 应用新颜色
+//! }
+//! // 或直接嵌入菜单中
+//! ui.menu_button("Color", |ui| {
+//!     if egui_midi::palette_picker::palette_picker(ui, "theme_accent", color) {
+//!         ui.close_menu();
+//!     }
+//! });
+//! # }
+//! ```
+
+use egui::color_picker::{color_picker_color32, Alpha};
+use egui::{Color32, Id, Response, Sense, Stroke, Ui, Vec2};
+use serde::{Deserialize, Serialize};
+
+/// 最近使用颜色的最大数量
+pub const MAX_RECENT_COLORS: usize = 8;
+
+/// 预设色板（两行，每行 8 个）
+pub const PRESET_COLORS: [Color32; 16] = [
+    Color32::from_rgb(230, 90, 90),
+    Color32::from_rgb(235, 150, 80),
+    Color32::from_rgb(230, 200, 80),
+    Color32::from_rgb(150, 210, 90),
+    Color32::from_rgb(100, 200, 100),
+    Color32::from_rgb(80, 200, 170),
+    Color32::from_rgb(90, 170, 230),
+    Color32::from_rgb(150, 150, 250),
+    Color32::from_rgb(190, 120, 230),
+    Color32::from_rgb(230, 120, 190),
+    Color32::from_rgb(160, 110, 80),
+    Color32::from_rgb(120, 140, 110),
+    Color32::from_rgb(90, 120, 150),
+    Color32::from_rgb(110, 110, 130),
+    Color32::from_rgb(170, 170, 170),
+    Color32::from_rgb(230, 230, 230),
+];
+
+const SWATCH_SIZE: f32 = 16.0;
+
+/// 最近使用的颜色，最新的在前
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentColors {
+    colors: Vec<[u8; 4]>,
+}
+
+impl RecentColors {
+    /// 记录一次使用：已有的颜色移到最前，超过上限时丢弃最旧的
+    pub fn push(&mut self, color: Color32) {
+        let rgba = color.to_array();
+        self.colors.retain(|c| *c != rgba);
+        self.colors.insert(0, rgba);
+        self.colors.truncate(MAX_RECENT_COLORS);
+    }
+
+    pub fn colors(&self) -> Vec<Color32> {
+        self.colors
+            .iter()
+            .map(|[r, g, b, a]| Color32::from_rgba_premultiplied(*r, *g, *b, *a))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// 读取 `id` 下保存的记录
+    pub fn load(ctx: &egui::Context, id: Id) -> Self {
+        ctx.data_mut(|d| d.get_persisted::<Self>(id)).unwrap_or_default()
+    }
+
+    pub fn store(self, ctx: &egui::Context, id: Id) {
+        ctx.data_mut(|d| d.insert_persisted(id, self));
+    }
+}
+
+/// 调色板内容（预设、最近使用、"Custom…"），适合放在菜单或弹出框中
+///
+/// 点击色块时返回 `true`；在展开的完整取色器中拖动时每次变化也返回 `true`，
+/// 松开指针后该颜色才计入最近使用。
+pub fn palette_picker(ui: &mut Ui, id_salt: impl std::hash::Hash, color: &mut Color32) -> bool {
+    let id = Id::new(("palette_picker", id_salt));
+    let recent_id = id.with("recent");
+    let custom_open_id = id.with("custom_open");
+    let custom_dirty_id = id.with("custom_dirty");
+
+    let mut picked = None;
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing = Vec2::splat(3.0);
+        for row in PRESET_COLORS.chunks(8) {
+            ui.horizontal(|ui| {
+                for swatch_color in row {
+                    if swatch(ui, *swatch_color, *swatch_color == *color).clicked() {
+                        picked = Some(*swatch_color);
+                    }
+                }
+            });
+        }
+
+        let recent = RecentColors::load(ui.ctx(), recent_id);
+        if !recent.is_empty() {
+            ui.label("Recent");
+            ui.horizontal(|ui| {
+                for recent_color in recent.colors() {
+                    if swatch(ui, recent_color, recent_color == *color).clicked() {
+                        picked = Some(recent_color);
+                    }
+                }
+            });
+        }
+    });
+
+    let mut changed = false;
+    if let Some(new_color) = picked {
+        *color = new_color;
+        let mut recent = RecentColors::load(ui.ctx(), recent_id);
+        recent.push(new_color);
+        recent.store(ui.ctx(), recent_id);
+        changed = true;
+    }
+
+    let mut custom_open = ui.data(|d| d.get_temp::<bool>(custom_open_id)).unwrap_or(false);
+    if ui.selectable_label(custom_open, "Custom…").clicked() {
+        custom_open = !custom_open;
+        ui.data_mut(|d| d.insert_temp(custom_open_id, custom_open));
+    }
+    if custom_open && color_picker_color32(ui, color, Alpha::Opaque) {
+        ui.data_mut(|d| d.insert_temp(custom_dirty_id, true));
+        changed = true;
+    }
+    if ui.data(|d| d.get_temp::<bool>(custom_dirty_id)).unwrap_or(false) && !ui.input(|i| i.pointer.any_down()) {
+        ui.data_mut(|d| d.remove::<bool>(custom_dirty_id));
+        let mut recent = RecentColors::load(ui.ctx(), recent_id);
+        recent.push(*color);
+        recent.store(ui.ctx(), recent_id);
+    }
+
+    changed
+}
+
+/// 当前颜色的色块按钮，点击后在下方弹出 [`palette_picker`]
+///
+/// 颜色改变时返回的 `Response` 会被标记为 `changed()`。
+pub fn palette_button(ui: &mut Ui, id_salt: impl std::hash::Hash + Clone, color: &mut Color32) -> Response {
+    let mut response = swatch(ui, *color, false);
+    let popup_id = Id::new(("palette_button", id_salt.clone())).with(response.id);
+    if response.clicked() {
+        ui.memory_mut(|m| m.toggle_popup(popup_id));
+    }
+    let changed = egui::popup_below_widget(
+        ui,
+        popup_id,
+        &response,
+        egui::PopupCloseBehavior::CloseOnClickOutside,
+        |ui| palette_picker(ui, id_salt, color),
+    )
+    .unwrap_or(false);
+    if changed {
+        response.mark_changed();
+    }
+    response
+}
+
+fn swatch(ui: &mut Ui, color: Color32, selected: bool) -> Response {
+    let (rect, response) = ui.allocate_exact_size(Vec2::splat(SWATCH_SIZE), Sense::click());
+    if ui.is_rect_visible(rect) {
+        let stroke = if selected || response.hovered() {
+            Stroke::new(2.0, ui.visuals().strong_text_color())
+        } else {
+            Stroke::new(1.0, Color32::from_gray(60))
+        };
+        ui.painter().rect_filled(rect, 2.0, color);
+        ui.painter().rect_stroke(rect, 2.0, stroke);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_colors_move_to_front_and_are_capped() {
+        let mut recent = RecentColors::default();
+        for color in PRESET_COLORS.iter().take(MAX_RECENT_COLORS + 2) {
+            recent.push(*color);
+        }
+        let colors = recent.colors();
+        assert_eq!(colors.len(), MAX_RECENT_COLORS);
+        assert_eq!(colors[0], PRESET_COLORS[MAX_RECENT_COLORS + 1]);
+        assert!(!colors.contains(&PRESET_COLORS[0]));
+
+        recent.push(PRESET_COLORS[5]);
+        let colors = recent.colors();
+        assert_eq!(colors.len(), MAX_RECENT_COLORS);
+        assert_eq!(colors[0], PRESET_COLORS[5]);
+        assert_eq!(colors.iter().filter(|c| **c == PRESET_COLORS[5]).count(), 1);
+    }
+
+    #[test]
+    fn recent_colors_are_shared_through_egui_memory() {
+        let ctx = egui::Context::default();
+        let id = Id::new("recent");
+        assert!(RecentColors::load(&ctx, id).is_empty());
+
+        let mut recent = RecentColors::load(&ctx, id);
+        recent.push(Color32::RED);
+        recent.store(&ctx, id);
+        let mut recent = RecentColors::load(&ctx, id);
+        recent.push(Color32::BLUE);
+        recent.store(&ctx, id);
+
+        assert_eq!(RecentColors::load(&ctx, id).colors(), vec![Color32::BLUE, Color32::RED]);
+        assert!(RecentColors::load(&ctx, Id::new("other")).is_empty());
+    }
+}
@@ -1,12 +1,28 @@
 use midly::{MetaMessage, Smf, TrackEventKind};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::ops::{Range, RangeInclusive};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 static NOTE_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 static CURVE_LANE_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 static CURVE_POINT_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+static NOTE_GROUP_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+static MARKER_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+/// 音符分组在 SMF 中的 sequencer-specific 标记（0x7D 为非商业用途厂商 ID）
+const NOTE_GROUPS_SMF_TAG: &[u8] = b"\x7Degui_midi:groups";
+
+/// 新建分组时循环使用的颜色
+const NOTE_GROUP_PALETTE: [[u8; 3]; 6] = [
+    [255, 170, 60],
+    [90, 170, 255],
+    [230, 90, 200],
+    [250, 230, 80],
+    [80, 220, 200],
+    [255, 110, 110],
+];
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct NoteId(pub u64);
@@ -17,6 +33,78 @@ impl NoteId {
     }
 }
 
+/// 内联存储的短字符串，最多 [`SmallString::CAPACITY`] 字节，超出部分在字符边界处截断
+///
+/// 用于音符标签（演奏法等），使 `Note` 保持 `Copy`。序列化为普通字符串。
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SmallString {
+    len: u8,
+    bytes: [u8; SmallString::CAPACITY],
+}
+
+impl SmallString {
+    pub const CAPACITY: usize = 23;
+
+    pub fn new(text: &str) -> Self {
+        let mut len = text.len().min(Self::CAPACITY);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..len].copy_from_slice(&text.as_bytes()[..len]);
+        Self { len: len as u8, bytes }
+    }
+
+    /// 去掉首尾空白，空字符串返回 `None`
+    pub fn non_empty(text: &str) -> Option<Self> {
+        let text = text.trim();
+        (!text.is_empty()).then(|| Self::new(text))
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl std::ops::Deref for SmallString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for SmallString {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl fmt::Debug for SmallString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SmallString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for SmallString {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for SmallString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Ok(Self::new(&text))
+    }
+}
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Note {
     pub id: NoteId,
@@ -24,6 +112,12 @@ pub struct Note {
     pub duration: u64, // Ticks
     pub key: u8,       // MIDI note number (0-127)
     pub velocity: u8,  // 0-127
+    /// 演奏法等标签（如 "stacc."），导出为音符起点处的 SMF 文本事件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<SmallString>,
+    /// 所属轨道在 `MidiState::tracks` 中的下标；单轨状态下总是 0
+    #[serde(default)]
+    pub track: u16,
 }
 
 impl Note {
@@ -38,6 +132,8 @@ impl Note {
             duration,
             key,
             velocity,
+            label: None,
+            track: 0,
         }
     }
 }
@@ -64,29 +160,114 @@ impl CurvePointId {
 pub enum CurveLaneType {
     Velocity,
     Pitch,
+    /// 控制器曲线（CC 编号 0-127），播放时发送、导出为 Control Change 事件
+    ControlChange(u8),
+    /// 弯音曲线（-8192..8191，0 为不弯音），播放时发送、导出为 14 位 Pitch Bend 事件
+    PitchBend,
 }
 
 impl CurveLaneType {
     pub fn value_range(&self) -> (f32, f32) {
         match self {
-            CurveLaneType::Velocity => (0.0, 127.0),
+            CurveLaneType::Velocity | CurveLaneType::ControlChange(_) => (0.0, 127.0),
             CurveLaneType::Pitch => (-12.0, 12.0),
+            CurveLaneType::PitchBend => (-8192.0, 8191.0),
+        }
+    }
+
+    pub fn default_name(&self) -> String {
+        match self {
+            CurveLaneType::Velocity => "Velocity".to_owned(),
+            CurveLaneType::Pitch => "Pitch".to_owned(),
+            CurveLaneType::PitchBend => "Pitch Bend".to_owned(),
+            CurveLaneType::ControlChange(cc) => match controller_name(*cc) {
+                Some(name) => format!("{name} (CC{cc})"),
+                None => format!("CC{cc}"),
+            },
         }
     }
 
-    pub fn default_name(&self) -> &'static str {
+    /// 控制器曲线的 CC 编号
+    pub fn cc_number(&self) -> Option<u8> {
         match self {
-            CurveLaneType::Velocity => "Velocity",
-            CurveLaneType::Pitch => "Pitch",
+            CurveLaneType::ControlChange(cc) => Some(*cc),
+            _ => None,
+        }
+    }
+
+    /// 开关型控制器（CC64-69，如延音踏板）：只有 0（抬起）与 127（踩下）两个值
+    pub fn is_switch(&self) -> bool {
+        matches!(self, CurveLaneType::ControlChange(64..=69))
+    }
+
+    /// 新建曲线的插值方式：开关型控制器为阶梯，其余为线性
+    pub fn default_interpolation(&self) -> CurveInterpolation {
+        if self.is_switch() {
+            CurveInterpolation::Step
+        } else {
+            CurveInterpolation::Linear
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// 曲线点之间的插值方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CurveInterpolation {
+    /// 点之间线性过渡
+    #[default]
+    Linear,
+    /// 保持前一个点的值直到下一个点（阶梯）
+    Step,
+    /// 点之间平滑过渡（三次缓入缓出，两端斜率为 0）
+    Smooth,
+}
+
+impl CurveInterpolation {
+    pub const ALL: [CurveInterpolation; 3] = [CurveInterpolation::Step, CurveInterpolation::Linear, CurveInterpolation::Smooth];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CurveInterpolation::Linear => "Linear",
+            CurveInterpolation::Step => "Step",
+            CurveInterpolation::Smooth => "Smooth",
+        }
+    }
+
+    /// `from` 到 `to` 之间进度 `alpha`（0-1）处的值
+    pub fn interpolate(&self, from: f32, to: f32, alpha: f32) -> f32 {
+        let alpha = alpha.clamp(0.0, 1.0);
+        let weight = match self {
+            CurveInterpolation::Step => 0.0,
+            CurveInterpolation::Linear => alpha,
+            CurveInterpolation::Smooth => alpha * alpha * (3.0 - 2.0 * alpha),
+        };
+        from + (to - from) * weight
+    }
+}
+
+/// 常用控制器的名称
+pub fn controller_name(cc: u8) -> Option<&'static str> {
+    Some(match cc {
+        1 => "Mod Wheel",
+        2 => "Breath",
+        7 => "Volume",
+        10 => "Pan",
+        11 => "Expression",
+        64 => "Sustain",
+        71 => "Resonance",
+        74 => "Cutoff",
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CurvePoint {
     pub id: CurvePointId,
     pub tick: u64,
     pub value: f32,
+    /// 从该点到下一个点的插值方式；为 `None` 时沿用曲线的 [`CurveLane::interpolation`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpolation: Option<CurveInterpolation>,
 }
 
 impl CurvePoint {
@@ -95,17 +276,20 @@ impl CurvePoint {
             id: CurvePointId::next(),
             tick,
             value,
+            interpolation: None,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CurveLane {
     pub id: CurveLaneId,
     pub lane_type: CurveLaneType,
     pub name: String,
     pub enabled: bool,
     pub points: Vec<CurvePoint>,
+    #[serde(default)]
+    pub interpolation: CurveInterpolation,
 }
 
 impl CurveLane {
@@ -113,9 +297,10 @@ impl CurveLane {
         Self {
             id: CurveLaneId::next(),
             lane_type,
-            name: lane_type.default_name().to_owned(),
+            name: lane_type.default_name(),
             enabled: true,
             points: Vec::new(),
+            interpolation: lane_type.default_interpolation(),
         }
     }
 
@@ -130,9 +315,7 @@ impl CurveLane {
     pub fn update_point(&mut self, point_id: CurvePointId, tick: u64, value: f32) -> Option<()> {
         if let Some(point) = self.points.iter_mut().find(|p| p.id == point_id) {
             point.tick = tick;
-            point.value = value;
-            let (min, max) = self.lane_type.value_range();
-            point.value = point.value.clamp(min, max);
+            point.value = Self::constrain_value(self.lane_type, value);
             self.sort_points();
             return Some(());
         }
@@ -146,43 +329,310 @@ impl CurveLane {
         None
     }
 
-    pub fn value_at(&self, tick: u64) -> Option<f32> {
-        if self.points.is_empty() {
-            return None;
+    /// `point` 到下一个点之间实际使用的插值方式
+    pub fn segment_interpolation(&self, point: &CurvePoint) -> CurveInterpolation {
+        point.interpolation.unwrap_or(self.interpolation)
+    }
+
+    /// 设置点的插值方式（`None` 沿用曲线的插值方式），返回修改前的值
+    pub fn set_point_interpolation(
+        &mut self,
+        point_id: CurvePointId,
+        interpolation: Option<CurveInterpolation>,
+    ) -> Option<Option<CurveInterpolation>> {
+        let point = self.points.iter_mut().find(|p| p.id == point_id)?;
+        Some(std::mem::replace(&mut point.interpolation, interpolation))
+    }
+
+    /// 铅笔工具的一步：删除 `from_tick` 与 `tick` 之间（含两端）除 `keep` 以外的点，再在 `tick` 处写入 `value`。
+    ///
+    /// `keep` 为同一笔画上一步写入的点；它恰好位于 `tick` 时只改写它的值。返回 `(删除的点, 新增的点)`
+    pub fn draw_point(
+        &mut self,
+        from_tick: u64,
+        tick: u64,
+        value: f32,
+        keep: Option<CurvePointId>,
+    ) -> (Vec<CurvePointId>, Option<CurvePointId>) {
+        let range = from_tick.min(tick)..=from_tick.max(tick);
+        let mut removed = Vec::new();
+        self.points.retain(|p| {
+            let overwritten = range.contains(&p.tick) && Some(p.id) != keep;
+            if overwritten {
+                removed.push(p.id);
+            }
+            !overwritten
+        });
+        let value = Self::constrain_value(self.lane_type, value);
+        if let Some(point) = self.points.iter_mut().find(|p| Some(p.id) == keep && p.tick == tick) {
+            point.value = value;
+            return (removed, None);
+        }
+        (removed, Some(self.insert_point(tick, value).id))
+    }
+
+    /// 直线工具：删除两端之间（含两端）的点，写入两端的点形成斜坡（两端在同一 tick 时只写终点）。返回 `(删除的点, 新增的点)`
+    pub fn replace_with_ramp(&mut self, start: (u64, f32), end: (u64, f32)) -> (Vec<CurvePointId>, Vec<CurvePointId>) {
+        let (start, end) = if start.0 <= end.0 { (start, end) } else { (end, start) };
+        let mut removed = Vec::new();
+        self.points.retain(|p| {
+            let inside = p.tick >= start.0 && p.tick <= end.0;
+            if inside {
+                removed.push(p.id);
+            }
+            !inside
+        });
+        let mut added = Vec::new();
+        if start.0 < end.0 {
+            added.push(self.insert_point(start.0, start.1).id);
+        }
+        added.push(self.insert_point(end.0, end.1).id);
+        (removed, added)
+    }
+
+    /// 删除 `[start, end]` 内不改变曲线形状的多余点：线性段上与前后点共线、阶梯段上与前一个点同值，
+    /// 或前后都与它同值的点（误差在取值范围的 0.5% 以内）。同一 tick 上的跳变保留。返回删除的点
+    pub fn thin_points(&mut self, start: u64, end: u64) -> Vec<CurvePointId> {
+        let (min, max) = self.lane_type.value_range();
+        let tolerance = (max - min) * 0.005;
+        let mut removed = Vec::new();
+        let mut previous = 0;
+        for index in 1..self.points.len() {
+            let (prev, point) = (&self.points[previous], &self.points[index]);
+            let next = self.points.get(index + 1);
+            let inside = point.tick >= start && point.tick <= end;
+            if inside && prev.tick < point.tick && self.is_redundant(prev, point, next, tolerance) {
+                removed.push(point.id);
+            } else {
+                previous = index;
+            }
+        }
+        self.points.retain(|p| !removed.contains(&p.id));
+        removed
+    }
+
+    /// 删除 `point` 后从 `prev` 直接过渡到 `next` 是否仍得到相同的曲线
+    fn is_redundant(&self, prev: &CurvePoint, point: &CurvePoint, next: Option<&CurvePoint>, tolerance: f32) -> bool {
+        let same = |a: f32, b: f32| (a - b).abs() <= tolerance;
+        let Some(next) = next else {
+            return same(prev.value, point.value);
+        };
+        if next.tick == point.tick {
+            return false;
         }
-        if self.points.len() == 1 {
-            return Some(self.points[0].value);
+        if same(prev.value, point.value) && same(point.value, next.value) {
+            return true;
         }
-        if tick <= self.points[0].tick {
-            return Some(self.points[0].value);
+        match (self.segment_interpolation(prev), self.segment_interpolation(point)) {
+            (CurveInterpolation::Linear, CurveInterpolation::Linear) => {
+                let alpha = (point.tick - prev.tick) as f32 / (next.tick - prev.tick) as f32;
+                same(prev.value + (next.value - prev.value) * alpha, point.value)
+            }
+            (CurveInterpolation::Step, CurveInterpolation::Step) => same(prev.value, point.value),
+            _ => false,
         }
-        if tick >= self.points.last()?.tick {
-            return Some(self.points.last()?.value);
+    }
+
+    /// 曲线在 `tick` 处的值：点之间按每段的插值方式过渡（阶梯段保持前一个点的值），
+    /// 第一个点之前与最后一个点之后保持端点的值。
+    ///
+    /// 同一 tick 上有多个点时形成跳变：从该 tick 起取最后一个点（按插入顺序）的值。
+    pub fn value_at(&self, tick: u64) -> Option<f32> {
+        let first = self.points.first()?;
+        let index = self.points.partition_point(|p| p.tick <= tick);
+        if index == 0 {
+            return Some(first.value);
         }
-        let mut iter = self.points.iter().peekable();
-        while let Some(current) = iter.next() {
-            if let Some(next) = iter.peek() {
-                if tick >= current.tick && tick <= next.tick {
-                    let span = (next.tick - current.tick).max(1) as f32;
-                    let alpha = (tick - current.tick) as f32 / span;
-                    return Some(current.value + (next.value - current.value) * alpha);
+        let current = &self.points[index - 1];
+        let Some(next) = self.points.get(index) else {
+            return Some(current.value);
+        };
+        let alpha = (tick - current.tick) as f32 / (next.tick - current.tick) as f32;
+        Some(self.segment_interpolation(current).interpolate(current.value, next.value, alpha))
+    }
+
+    /// 控制器曲线导出的 `(tick, 值)` 事件：每个点处一个事件，非阶梯段点之间的过渡每 `resolution` tick
+    /// 取样一次；只在取整后的值改变时写出，同一 tick 的多个点只写最后一个。
+    /// 开关型控制器只写出 127/0
+    pub fn control_change_events(&self, resolution: u64) -> Vec<(u64, u8)> {
+        let switch = self.lane_type.is_switch();
+        self.sampled_events(resolution, |value| match switch {
+            true if value >= 64.0 => 127,
+            true => 0,
+            false => value.round().clamp(0.0, 127.0) as u8,
+        })
+    }
+
+    /// 弯音曲线导出的 `(tick, 值)` 事件，取样规则与 [`Self::control_change_events`] 相同
+    pub fn pitch_bend_events(&self, resolution: u64) -> Vec<(u64, i16)> {
+        self.sampled_events(resolution, |value| value.round().clamp(-8192.0, 8191.0) as i16)
+    }
+
+    fn sampled_events<T: Copy + PartialEq>(&self, resolution: u64, quantize: impl Fn(f32) -> T) -> Vec<(u64, T)> {
+        let resolution = resolution.max(1);
+        let mut events: Vec<(u64, T)> = Vec::new();
+        let mut emit = |tick: u64, value: f32| {
+            let value = quantize(value);
+            if events.last().is_none_or(|(_, last)| *last != value) {
+                events.push((tick, value));
+            }
+        };
+        for (index, point) in self.points.iter().enumerate() {
+            let Some(next) = self.points.get(index + 1) else {
+                emit(point.tick, point.value);
+                break;
+            };
+            if next.tick == point.tick {
+                continue;
+            }
+            emit(point.tick, point.value);
+            if self.segment_interpolation(point) == CurveInterpolation::Step {
+                continue;
+            }
+            let mut tick = point.tick + resolution;
+            while tick < next.tick {
+                if let Some(value) = self.value_at(tick) {
+                    emit(tick, value);
                 }
+                tick += resolution;
             }
         }
-        self.points.last().map(|p| p.value)
+        events
     }
 
     fn clamp_point(&self, point: &mut CurvePoint) {
-        let (min, max) = self.lane_type.value_range();
-        point.value = point.value.clamp(min, max);
+        point.value = Self::constrain_value(self.lane_type, point.value);
+    }
+
+    /// 限制到曲线的取值范围；开关型控制器取 0 或 127
+    fn constrain_value(lane_type: CurveLaneType, value: f32) -> f32 {
+        if lane_type.is_switch() {
+            return if value >= 64.0 { 127.0 } else { 0.0 };
+        }
+        let (min, max) = lane_type.value_range();
+        value.clamp(min, max)
     }
 
     fn sort_points(&mut self) {
         self.points.sort_by(|a, b| a.tick.cmp(&b.tick).then_with(|| a.id.0.cmp(&b.id.0)));
     }
+
+    /// 复制指定的点，tick 相对于最早的点
+    pub fn copy_points(&self, point_ids: &[CurvePointId]) -> Option<CurveClip> {
+        let first = self.points.iter().filter(|p| point_ids.contains(&p.id)).map(|p| p.tick).min()?;
+        self.copy_points_anchored(point_ids, first)
+    }
+
+    /// 复制指定的点并保留它们与 `anchor`（如一起复制的音符起点）的相对位置：
+    /// 片段从 `anchor` 与最早的点中较早者开始，早于 `anchor` 的部分记为 [`CurveClip::lead`]
+    pub fn copy_points_anchored(&self, point_ids: &[CurvePointId], anchor: u64) -> Option<CurveClip> {
+        let points: Vec<&CurvePoint> = self.points.iter().filter(|p| point_ids.contains(&p.id)).collect();
+        let origin = points.iter().map(|p| p.tick).min()?.min(anchor);
+        let end = points.iter().map(|p| p.tick).max()?;
+        Some(CurveClip {
+            lane_type: self.lane_type,
+            span: end - origin,
+            points: points.iter().map(|p| (p.tick - origin, p.value)).collect(),
+            lead: anchor - origin,
+        })
+    }
+
+    /// 复制 `[start, end]` 范围内的点，tick 相对于 `start`
+    pub fn copy_range(&self, start: u64, end: u64) -> Option<CurveClip> {
+        let points: Vec<(u64, f32)> = self
+            .points
+            .iter()
+            .filter(|p| p.tick >= start && p.tick <= end)
+            .map(|p| (p.tick - start, p.value))
+            .collect();
+        (!points.is_empty()).then(|| CurveClip {
+            lane_type: self.lane_type,
+            span: end.saturating_sub(start),
+            points,
+            lead: 0,
+        })
+    }
+
+    /// 在 `at_tick` 处粘贴曲线片段，数值按源/目标曲线的取值范围线性换算。
+    ///
+    /// `overwrite` 为 true 时先删除 `[at_tick, at_tick + span]` 内已有的点。
+    /// 返回 `(删除的点, 新增的点)`。
+    pub fn paste_clip(
+        &mut self,
+        clip: &CurveClip,
+        at_tick: u64,
+        overwrite: bool,
+    ) -> (Vec<CurvePointId>, Vec<CurvePointId>) {
+        let mut removed = Vec::new();
+        if overwrite {
+            let end = at_tick + clip.span;
+            self.points.retain(|p| {
+                let inside = p.tick >= at_tick && p.tick <= end;
+                if inside {
+                    removed.push(p.id);
+                }
+                !inside
+            });
+        }
+        let (src_min, src_max) = clip.lane_type.value_range();
+        let (dst_min, dst_max) = self.lane_type.value_range();
+        let added = clip
+            .points
+            .iter()
+            .map(|(offset, value)| {
+                let normalized = (value - src_min) / (src_max - src_min);
+                self.insert_point(at_tick + offset, dst_min + normalized * (dst_max - dst_min)).id
+            })
+            .collect();
+        (removed, added)
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// 曲线片段剪贴板，点的 tick 相对于片段起点
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurveClip {
+    /// 来源曲线类型，粘贴到其他类型的曲线时用于数值换算
+    pub lane_type: CurveLaneType,
+    /// 片段长度（tick），覆盖粘贴时清除的范围
+    pub span: u64,
+    pub points: Vec<(u64, f32)>,
+    /// 片段起点早于一起复制的音符起点的 tick 数；粘贴时片段落在音符落点之前这么多，
+    /// 单独复制曲线时为 0
+    #[serde(default)]
+    pub lead: u64,
+}
+
+impl CurveClip {
+    /// 用 `rescale` 换算跨度与点的 tick（如换算到另一个 tick 精度）
+    pub fn rescaled(&self, rescale: impl Fn(u64) -> u64) -> Self {
+        Self {
+            lane_type: self.lane_type,
+            span: rescale(self.span),
+            points: self.points.iter().map(|(tick, value)| (rescale(*tick), *value)).collect(),
+            lead: rescale(self.lead),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NoteGroupId(pub u64);
+
+impl NoteGroupId {
+    pub fn next() -> Self {
+        NoteGroupId(NOTE_GROUP_ID_COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// 命名的音符分组（乐句），成员一起选择与移动
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoteGroup {
+    pub id: NoteGroupId,
+    pub name: String,
+    pub note_ids: Vec<NoteId>,
+    pub color: [u8; 3],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TrackMeta {
     pub channel: u8,
     pub program: Option<u8>,
@@ -203,14 +653,183 @@ impl Default for TrackMeta {
     }
 }
 
+/// 多轨状态中的一条轨道；音符通过 `Note::track` 归属于它
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MidiTrack {
+    pub name: Option<String>,
+    pub channel: u8,
+    pub program: Option<u8>,
+}
+
+impl MidiTrack {
+    /// 显示用名称，未命名时为 "Track N"（从 1 开始）
+    pub fn display_name(&self, index: usize) -> String {
+        match self.name.as_deref().filter(|name| !name.is_empty()) {
+            Some(name) => name.to_string(),
+            None => format!("Track {}", index + 1),
+        }
+    }
+}
+
+/// 调号：升号（正数）或降号（负数）的个数与大小调，对应 SMF 的 Key Signature 元事件
+///
+/// 决定音高名称用升号还是降号拼写（"C#" 或 "Db"），默认 C 大调，使用升号。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeySignature {
+    /// -7..=7
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    pub fn new(sharps: i8, minor: bool) -> Self {
+        Self { sharps: sharps.clamp(-7, 7), minor }
+    }
+
+    /// 所有调号：每个升降号个数先大调后小调，按降号最多到升号最多排列
+    pub fn all() -> impl Iterator<Item = KeySignature> {
+        (-7..=7).flat_map(|sharps| [Self::new(sharps, false), Self::new(sharps, true)])
+    }
+
+    /// 有降号时音高名称用降号拼写
+    pub fn uses_flats(&self) -> bool {
+        self.sharps < 0
+    }
+
+    /// 主音的音级（0 为 C）
+    pub fn tonic(&self) -> u8 {
+        let major = (self.sharps as i32 * 7).rem_euclid(12) as u8;
+        if self.minor {
+            (major + 9) % 12
+        } else {
+            major
+        }
+    }
+
+    /// "Eb major"、"F# minor"
+    pub fn label(&self) -> String {
+        let tonic = crate::tooltip::pitch_class_name(self.tonic(), *self);
+        format!("{tonic} {}", if self.minor { "minor" } else { "major" })
+    }
+}
+
+/// 以 "小节.拍.tick" 表示的位置：小节与拍从 1 开始，拍的长度按拍号分母计算（6/8 的拍为八分音符）
+///
+/// 由 `MidiState::musical_time` 换算；显示为 "5.2.000"。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct MusicalTime {
+    pub bar: u64,
+    pub beat: u64,
+    pub tick: u64,
+}
+
+impl fmt::Display for MusicalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{:03}", self.bar, self.beat, self.tick)
+    }
+}
+
+/// 文本类元事件的种类（SMF 的 Marker 元事件导入为 [`Marker`]）
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TextEventKind {
+    #[default]
+    Lyric,
+    Text,
+}
+
+impl TextEventKind {
+    pub const ALL: [TextEventKind; 2] = [TextEventKind::Lyric, TextEventKind::Text];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TextEventKind::Lyric => "Lyric",
+            TextEventKind::Text => "Text",
+        }
+    }
+}
+
+/// 歌词或文本元事件；文本保存原始字节（不一定是 UTF-8），原样导出
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEvent {
+    pub tick: u64,
+    pub kind: TextEventKind,
+    pub text: Vec<u8>,
+    /// 导入时所在的音符轨道（`MidiState::tracks` 的下标），多轨导出时写回该轨道
+    #[serde(default)]
+    pub track: u16,
+}
+
+impl TextEvent {
+    pub fn new(tick: u64, kind: TextEventKind, text: &str) -> Self {
+        Self { tick, kind, text: text.as_bytes().to_vec(), track: 0 }
+    }
+
+    /// 显示用文本，无效的 UTF-8 字节显示为替换字符
+    pub fn text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.text)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct MarkerId(pub u64);
+
+impl MarkerId {
+    pub fn next() -> Self {
+        MarkerId(MARKER_ID_COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// 时间轴上的命名标记，导出为 SMF 的 Marker 元事件（颜色只保存在编辑器状态中）
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub id: MarkerId,
+    pub tick: u64,
+    pub label: String,
+    pub color: [u8; 3],
+}
+
+impl Marker {
+    /// 从 SMF 导入的标记使用的颜色
+    pub const DEFAULT_COLOR: [u8; 3] = [230, 180, 80];
+
+    pub fn new(tick: u64, label: impl Into<String>, color: [u8; 3]) -> Self {
+        Self { id: MarkerId::next(), tick, label: label.into(), color }
+    }
+}
+
+/// 速度变化点：从 `tick` 起以 `bpm` 播放，直到下一个变化点
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub bpm: f32,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MidiState {
     pub notes: Vec<Note>,
     pub curves: Vec<CurveLane>,
     pub ticks_per_beat: u16,
+    /// 起始速度（tick 0 处）
     pub bpm: f32,
+    /// 起始速度之后的速度变化，按 tick 升序且都大于 0；为空时整首曲子保持 `bpm`
+    #[serde(default)]
+    pub tempo_changes: Vec<TempoChange>,
     pub time_signature: (u8, u8),
+    /// 调号，只影响音高名称的拼写与音阶高亮的默认主音
+    #[serde(default)]
+    pub key_signature: KeySignature,
     pub track: TrackMeta,
+    #[serde(default)]
+    pub groups: Vec<NoteGroup>,
+    /// 导入时每条含音符的 SMF 轨道对应一项；多于一项时 `to_smf` 写出 format 1 文件
+    #[serde(default)]
+    pub tracks: Vec<MidiTrack>,
+    /// 歌词与文本元事件，按 tick 升序（同一 tick 保持文件中的顺序）
+    #[serde(default)]
+    pub text_events: Vec<TextEvent>,
+    /// 时间轴标记，按 tick 升序
+    #[serde(default)]
+    pub markers: Vec<Marker>,
 }
 
 impl Default for MidiState {
@@ -220,82 +839,199 @@ impl Default for MidiState {
             curves: vec![Self::default_velocity_lane()],
             ticks_per_beat: 480,
             bpm: 120.0,
+            tempo_changes: Vec::new(),
             time_signature: (4, 4),
+            key_signature: KeySignature::default(),
             track: TrackMeta::default(),
+            groups: Vec::new(),
+            tracks: Vec::new(),
+            text_events: Vec::new(),
+            markers: Vec::new(),
+        }
+    }
+}
+
+/// `MidiState::to_json` 写出的格式版本，格式不兼容地变化时递增
+pub const MIDI_STATE_JSON_VERSION: u32 = 1;
+
+/// JSON 文件的外层：版本号与状态本身
+#[derive(Serialize)]
+struct MidiStateJsonRef<'a> {
+    version: u32,
+    state: &'a MidiState,
+}
+
+#[derive(Deserialize)]
+struct MidiStateJson {
+    version: u32,
+    state: serde_json::Value,
+}
+
+impl MidiState {
+    /// 序列化为带版本号的 JSON（`{"version": 1, "state": {...}}`），音符、曲线与分组的 ID 原样保留
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&MidiStateJsonRef { version: MIDI_STATE_JSON_VERSION, state: self })
+    }
+
+    /// 解析 `to_json` 写出的文本；ID 原样恢复，并推进 ID 计数器，之后新建的音符等不会与其冲突
+    pub fn from_json(json: &str) -> Result<Self, MidiJsonError> {
+        let file: MidiStateJson = serde_json::from_str(json).map_err(MidiJsonError::Parse)?;
+        if file.version > MIDI_STATE_JSON_VERSION {
+            return Err(MidiJsonError::UnsupportedVersion(file.version));
         }
+        let state: Self = serde_json::from_value(file.state).map_err(MidiJsonError::Parse)?;
+        state.reserve_ids();
+        Ok(state)
+    }
+
+    /// 把各 ID 计数器推进到已有 ID 之后
+    fn reserve_ids(&self) {
+        let reserve = |counter: &AtomicU64, max: Option<u64>| {
+            if let Some(max) = max {
+                counter.fetch_max(max + 1, Ordering::Relaxed);
+            }
+        };
+        reserve(&NOTE_ID_COUNTER, self.notes.iter().map(|n| n.id.0).max());
+        reserve(&CURVE_LANE_ID_COUNTER, self.curves.iter().map(|c| c.id.0).max());
+        reserve(&CURVE_POINT_ID_COUNTER, self.curves.iter().flat_map(|c| &c.points).map(|p| p.id.0).max());
+        reserve(&NOTE_GROUP_ID_COUNTER, self.groups.iter().map(|g| g.id.0).max());
+        reserve(&MARKER_ID_COUNTER, self.markers.iter().map(|m| m.id.0).max());
     }
 }
 
+/// SMF 导入选项
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SmfImportOptions {
+    /// 将与音符起点 tick 相同的文本事件（同一轨道内）作为音符标签恢复；
+    /// 同一 tick 有多个音符时按音高从低到高依次匹配
+    pub text_events_as_labels: bool,
+}
+
 impl MidiState {
     pub fn from_smf(smf: &Smf) -> Self {
+        Self::from_smf_with_options(smf, SmfImportOptions::default())
+    }
+
+    pub fn from_smf_with_options(smf: &Smf, options: SmfImportOptions) -> Self {
+        Self::import_smf(smf, options, &mut Vec::new(), &mut |_| {})
+    }
+
+    /// 宽松导入：能恢复的内容尽量恢复，跳过或修补的部分以警告列出，不会因多轨或混合通道而失败
+    pub fn from_smf_lenient(smf: &Smf) -> (Self, Vec<ImportWarning>) {
+        Self::from_smf_lenient_with_progress(smf, &mut |_| {})
+    }
+
+    pub(crate) fn from_smf_lenient_with_progress(
+        smf: &Smf,
+        progress: &mut dyn FnMut(usize),
+    ) -> (Self, Vec<ImportWarning>) {
+        let mut warnings = Vec::new();
+        let state = Self::import_smf(smf, SmfImportOptions::default(), &mut warnings, progress);
+        if let Err(err) = state.validate_single_track() {
+            warnings.push(ImportWarning::NotSingleTrack(err));
+        }
+        (state, warnings)
+    }
+
+    /// 导入的实际实现：悬空的 note-on 在轨道末尾结束，同一音高重复按下时先结束前一个音符，
+    /// 零时长音符被丢弃；这些修补与不支持的事件都记录到 `warnings`。
+    /// `progress` 每处理 `IMPORT_PROGRESS_STEP` 个事件以及结束时收到已处理的事件数
+    pub(crate) fn import_smf(
+        smf: &Smf,
+        options: SmfImportOptions,
+        warnings: &mut Vec<ImportWarning>,
+        progress: &mut dyn FnMut(usize),
+    ) -> Self {
         let mut notes = Vec::new();
         let ticks_per_beat = match smf.header.timing {
             midly::Timing::Metrical(t) => t.as_int(),
             _ => 480, // Default fallback
         };
-        let mut bpm = 120.0;
+        let mut tempo_events: Vec<(u64, f32)> = Vec::new();
+        let mut cc_events: std::collections::BTreeMap<u8, Vec<(u64, u8)>> = std::collections::BTreeMap::new();
+        let mut bend_events: Vec<(u64, i16)> = Vec::new();
         let mut time_sig = (4, 4);
+        let mut key_signature: Option<(u64, KeySignature)> = None;
         let mut track_meta = TrackMeta::default();
         let mut tracks_with_notes = 0;
         let mut reference_channel: Option<u8> = None;
         let mut single_channel = true;
         let mut program = None;
+        let mut encoded_groups: Option<Vec<u8>> = None;
+        let mut tracks = Vec::new();
+        let mut text_events = Vec::new();
+        let mut markers = Vec::new();
+        let mut unsupported_meta: std::collections::BTreeMap<&'static str, usize> = std::collections::BTreeMap::new();
+        let mut unsupported_events: std::collections::BTreeMap<&'static str, usize> = std::collections::BTreeMap::new();
+        let mut processed = 0;
 
-        // Simplified loading: merge all MIDI tracks into a single note lane.
+        // 所有音符都放在同一个列表中，`Note::track` 记录其来源轨道
         for track in &smf.tracks {
             let mut current_ticks = 0;
+            let mut midi_track = MidiTrack::default();
+            let mut track_channel: Option<u8> = None;
             let mut active_notes: HashMap<(u8, u8), (u64, u8)> = HashMap::new();
             let mut track_has_notes = false;
+            let track_first_note = notes.len();
+            let mut label_events: Vec<(u64, &[u8])> = Vec::new();
+            let mut track_text_events: Vec<TextEvent> = Vec::new();
 
             for event in track {
                 current_ticks += event.delta.as_int() as u64;
+                processed += 1;
+                if processed % IMPORT_PROGRESS_STEP == 0 {
+                    progress(processed);
+                }
 
                 match event.kind {
                     TrackEventKind::Midi { channel, message } => {
                         let channel_val = channel.as_int();
                         match message {
-                            midly::MidiMessage::NoteOn { key, vel } => {
+                            midly::MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
                                 let key_val = key.as_int();
-                                if vel.as_int() > 0 {
-                                    active_notes.insert(
-                                        (channel_val, key_val),
-                                        (current_ticks, vel.as_int()),
-                                    );
-                                } else {
-                                    // NoteOn with velocity 0 is NoteOff
-                                    if let Some((start, velocity)) =
-                                        active_notes.remove(&(channel_val, key_val))
-                                    {
-                                        track_has_notes = true;
-                                        notes.push(Note::new(
-                                            start,
-                                            current_ticks - start,
-                                            key_val,
-                                            velocity,
-                                        ));
-                                    }
+                                if let Some((start, velocity)) = active_notes.insert(
+                                    (channel_val, key_val),
+                                    (current_ticks, vel.as_int()),
+                                ) {
+                                    warnings.push(ImportWarning::OverlappingNote { tick: current_ticks, key: key_val });
+                                    track_has_notes |=
+                                        close_note(&mut notes, start, current_ticks, key_val, velocity, warnings);
                                 }
                             }
-                            midly::MidiMessage::NoteOff { key, .. } => {
+                            // NoteOn with velocity 0 is NoteOff
+                            midly::MidiMessage::NoteOn { key, .. } | midly::MidiMessage::NoteOff { key, .. } => {
                                 let key_val = key.as_int();
                                 if let Some((start, velocity)) =
                                     active_notes.remove(&(channel_val, key_val))
                                 {
-                                    track_has_notes = true;
-                                    notes.push(Note::new(
-                                        start,
-                                        current_ticks - start,
-                                        key_val,
-                                        velocity,
-                                    ));
+                                    track_has_notes |=
+                                        close_note(&mut notes, start, current_ticks, key_val, velocity, warnings);
+                                } else {
+                                    warnings.push(ImportWarning::OrphanNoteOff { tick: current_ticks, key: key_val });
                                 }
                             }
                             midly::MidiMessage::ProgramChange { program: prog } => {
                                 program = Some(prog.as_int());
                                 track_meta.channel = channel_val;
+                                midi_track.program = Some(prog.as_int());
+                            }
+                            midly::MidiMessage::Controller { controller, value } => {
+                                cc_events
+                                    .entry(controller.as_int())
+                                    .or_default()
+                                    .push((current_ticks, value.as_int()));
+                            }
+                            midly::MidiMessage::PitchBend { bend } => {
+                                bend_events.push((current_ticks, bend.as_int()));
+                            }
+                            midly::MidiMessage::Aftertouch { .. } => {
+                                *unsupported_events.entry("Polyphonic Aftertouch").or_default() += 1;
+                            }
+                            midly::MidiMessage::ChannelAftertouch { .. } => {
+                                *unsupported_events.entry("Channel Aftertouch").or_default() += 1;
                             }
-                            _ => {}
                         }
+                        track_channel.get_or_insert(channel_val);
 
                         if let Some(reference) = reference_channel {
                             if reference != channel_val {
@@ -307,46 +1043,170 @@ impl MidiState {
                     }
                     TrackEventKind::Meta(meta) => match meta {
                         MetaMessage::Tempo(value) => {
-                            let micros_per_quarter = value.as_int() as f32;
-                            if micros_per_quarter > 0.0 {
-                                bpm = 60_000_000.0 / micros_per_quarter;
+                            let micros_per_quarter = value.as_int();
+                            if micros_per_quarter > 0 {
+                                tempo_events.push((current_ticks, micros_to_bpm(micros_per_quarter)));
                             }
                         }
                         MetaMessage::TimeSignature(numer, denom, ..) => {
+                            // 只支持单一拍号，中途的拍号变化会覆盖开头的拍号
+                            if current_ticks > 0 {
+                                *unsupported_meta.entry("Time Signature change").or_default() += 1;
+                            }
                             time_sig = (numer, 2u8.pow(denom as u32));
                         }
+                        MetaMessage::KeySignature(sharps, minor) => {
+                            // 只支持单一调号，取最早的一个；中途的调号变化只影响拼写，不导入
+                            if key_signature.is_some_and(|(tick, _)| tick <= current_ticks) {
+                                *unsupported_meta.entry("Key Signature change").or_default() += 1;
+                            } else {
+                                key_signature = Some((current_ticks, KeySignature::new(sharps, minor)));
+                            }
+                        }
                         MetaMessage::TrackName(name) => {
-                            track_meta.track_name = Some(
-                                String::from_utf8_lossy(name.as_ref())
-                                    .trim_matches(char::from(0))
-                                    .to_string(),
-                            );
+                            let name = String::from_utf8_lossy(name)
+                                .trim_matches(char::from(0))
+                                .to_string();
+                            midi_track.name = Some(name.clone());
+                            track_meta.track_name = Some(name);
+                        }
+                        MetaMessage::SequencerSpecific(data) => {
+                            if let Some(payload) = data.strip_prefix(NOTE_GROUPS_SMF_TAG) {
+                                encoded_groups = Some(payload.to_vec());
+                            } else {
+                                *unsupported_meta.entry("Sequencer Specific").or_default() += 1;
+                            }
+                        }
+                        MetaMessage::Text(text) if options.text_events_as_labels => {
+                            label_events.push((current_ticks, text));
+                        }
+                        MetaMessage::Text(text) | MetaMessage::Lyric(text) => {
+                            let kind = match meta {
+                                MetaMessage::Lyric(_) => TextEventKind::Lyric,
+                                _ => TextEventKind::Text,
+                            };
+                            track_text_events.push(TextEvent { tick: current_ticks, kind, text: text.to_vec(), track: 0 });
+                        }
+                        MetaMessage::Marker(text) => {
+                            markers.push(Marker::new(current_ticks, String::from_utf8_lossy(text), Marker::DEFAULT_COLOR));
+                        }
+                        MetaMessage::EndOfTrack => {}
+                        other => {
+                            *unsupported_meta.entry(meta_kind_name(&other)).or_default() += 1;
                         }
-                        _ => {}
                     },
-                    _ => {}
+                    TrackEventKind::SysEx(_) | TrackEventKind::Escape(_) => {
+                        *unsupported_events.entry("System Exclusive").or_default() += 1;
+                    }
+                }
+            }
+
+            // 轨道结束时仍未松开的音符在轨道末尾结束
+            let mut dangling: Vec<_> = active_notes.into_iter().collect();
+            dangling.sort_by_key(|((channel, key), (start, _))| (*start, *channel, *key));
+            for ((_, key), (start, velocity)) in dangling {
+                warnings.push(ImportWarning::OrphanNoteOn { tick: start, key });
+                track_has_notes |= close_note(&mut notes, start, current_ticks, key, velocity, warnings);
+            }
+
+            // 没有匹配到音符的文本事件作为普通文本事件保留
+            for (tick, text) in label_events {
+                let target = notes[track_first_note..]
+                    .iter_mut()
+                    .filter(|note| note.start == tick && note.label.is_none())
+                    .min_by_key(|note| note.key);
+                match (target, SmallString::non_empty(&String::from_utf8_lossy(text))) {
+                    (Some(note), Some(label)) => note.label = Some(label),
+                    _ => track_text_events.push(TextEvent {
+                        tick,
+                        kind: TextEventKind::Text,
+                        text: text.to_vec(),
+                        track: 0,
+                    }),
                 }
             }
+            if track_has_notes {
+                for event in &mut track_text_events {
+                    event.track = tracks.len() as u16;
+                }
+            }
+            text_events.append(&mut track_text_events);
 
             if track_has_notes {
                 tracks_with_notes += 1;
+                for note in &mut notes[track_first_note..] {
+                    note.track = tracks.len() as u16;
+                }
+                midi_track.channel = track_channel.unwrap_or(0);
+                tracks.push(midi_track);
             }
         }
 
-        notes.sort_by(|a, b| a.start.cmp(&b.start));
+        progress(processed);
+        notes.sort_by_key(|a| a.start);
+        text_events.sort_by_key(|event: &TextEvent| event.tick);
+        markers.sort_by_key(|marker: &Marker| marker.tick);
 
         track_meta.channel = reference_channel.unwrap_or(track_meta.channel);
         track_meta.program = program;
         track_meta.single_channel = single_channel;
         track_meta.tracks_with_notes = tracks_with_notes;
 
+        let groups = encoded_groups
+            .map(|bytes| decode_note_groups(&bytes, &notes))
+            .unwrap_or_default();
+
+        warnings.extend(
+            unsupported_meta
+                .into_iter()
+                .map(|(kind, count)| ImportWarning::UnsupportedMeta { kind, count }),
+        );
+        warnings.extend(
+            unsupported_events
+                .into_iter()
+                .map(|(kind, count)| ImportWarning::UnsupportedEvent { kind, count }),
+        );
+
+        // 速度事件可能分散在多条轨道中；同一 tick 以最后读到的为准，tick 0 之前没有事件时为 120
+        tempo_events.sort_by_key(|(tick, _)| *tick);
+        let mut bpm = 120.0;
+        let mut tempo_changes: Vec<TempoChange> = Vec::new();
+        for (tick, value) in tempo_events {
+            if tick == 0 {
+                bpm = value;
+            } else if let Some(last) = tempo_changes.last_mut().filter(|last| last.tick == tick) {
+                last.bpm = value;
+            } else {
+                tempo_changes.push(TempoChange { tick, bpm: value });
+            }
+        }
+
+        // 控制器事件是阶跃的：与上一次变化相隔超过取样间隔时补一个保持旧值的点（与新值同 tick），
+        // 导出时两点之间就不会被插值成斜坡；密集的控制器数据原样保留
+        let mut curves = vec![Self::default_velocity_lane()];
+        let resolution = (ticks_per_beat as u64 / 16).max(1);
+        for (cc, events) in cc_events {
+            let events = events.into_iter().map(|(tick, value)| (tick, value as f32)).collect();
+            curves.push(Self::stepped_lane(CurveLaneType::ControlChange(cc), events, resolution));
+        }
+        if !bend_events.is_empty() {
+            let events = bend_events.into_iter().map(|(tick, value)| (tick, value as f32)).collect();
+            curves.push(Self::stepped_lane(CurveLaneType::PitchBend, events, resolution));
+        }
+
         Self {
             notes,
-            curves: vec![Self::default_velocity_lane()],
+            curves,
             ticks_per_beat,
             bpm,
+            tempo_changes,
             time_signature: time_sig,
+            key_signature: key_signature.map_or_else(KeySignature::default, |(_, key)| key),
             track: track_meta,
+            groups,
+            tracks,
+            text_events,
+            markers,
         }
     }
 
@@ -354,6 +1214,379 @@ impl MidiState {
         CurveLane::new(CurveLaneType::Velocity)
     }
 
+    /// 由阶跃的控制器或弯音事件重建曲线；开关型控制器直接得到阶梯曲线
+    fn stepped_lane(lane_type: CurveLaneType, mut events: Vec<(u64, f32)>, resolution: u64) -> CurveLane {
+        events.sort_by_key(|(tick, _)| *tick);
+        let mut lane = CurveLane::new(lane_type);
+        let mut previous: Option<(u64, f32)> = None;
+        for (tick, value) in events {
+            // 半踩踏板等中间值在开关型控制器上归为踩下或抬起
+            let value = CurveLane::constrain_value(lane_type, value);
+            if let Some((previous_tick, previous_value)) = previous {
+                if previous_value == value {
+                    continue;
+                }
+                if lane.interpolation != CurveInterpolation::Step && tick - previous_tick > resolution {
+                    lane.insert_point(tick, previous_value);
+                }
+            }
+            lane.insert_point(tick, value);
+            previous = Some((tick, value));
+        }
+        lane
+    }
+
+    /// 每小节的 tick 数（由拍号决定）
+    pub fn ticks_per_measure(&self) -> u64 {
+        let tpb = self.ticks_per_beat.max(1) as u64;
+        let numer = self.time_signature.0.max(1) as u64;
+        let denom = self.time_signature.1.max(1) as u64;
+        (tpb * numer * 4).saturating_div(denom).max(tpb)
+    }
+
+    /// 更改每拍 tick 数（PPQ），按比例换算所有位置，使音乐上的位置与时值不变
+    ///
+    /// 音符的起点与终点分别四舍五入后再求时值（至少 1 tick），相邻的音符换算后仍然相接；
+    /// 放大后再缩小回原值时得到与原来完全相同的 tick。缩小时落到同一 tick 的曲线点或速度变化只保留较晚的一个。
+    pub fn rescale_ticks_per_beat(&mut self, new_tpb: u16) {
+        let (from, to) = (self.ticks_per_beat.max(1), new_tpb.max(1));
+        if from == to {
+            self.ticks_per_beat = to;
+            return;
+        }
+        let rescale = |tick: u64| rescale_tick(tick, from, to);
+        for note in &mut self.notes {
+            let start = rescale(note.start);
+            let end = rescale(note.start + note.duration);
+            note.start = start;
+            note.duration = end.saturating_sub(start).max(1);
+        }
+        self.notes.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.id.0.cmp(&b.id.0)));
+        for lane in &mut self.curves {
+            for point in &mut lane.points {
+                point.tick = rescale(point.tick);
+            }
+            lane.points.dedup_by(|later, earlier| {
+                let same = later.tick == earlier.tick;
+                if same {
+                    *earlier = later.clone();
+                }
+                same
+            });
+        }
+        for change in &mut self.tempo_changes {
+            change.tick = rescale(change.tick);
+        }
+        self.tempo_changes.dedup_by(|later, earlier| {
+            let same = later.tick == earlier.tick;
+            if same {
+                *earlier = *later;
+            }
+            same
+        });
+        for event in &mut self.text_events {
+            event.tick = rescale(event.tick);
+        }
+        for marker in &mut self.markers {
+            marker.tick = rescale(marker.tick);
+        }
+        // 落到 tick 0 的速度变化成为起始速度
+        if let Some(first) = self.tempo_changes.first().copied().filter(|change| change.tick == 0) {
+            self.bpm = first.bpm;
+            self.tempo_changes.remove(0);
+        }
+        self.ticks_per_beat = to;
+    }
+
+    /// 截取 `range` 内的片段，`range.start` 成为 tick 0：跨越边界的音符被裁到边界上，
+    /// 曲线只保留范围内的点，并在起点补上该处的值；起点处的速度成为起始速度
+    pub fn slice(&self, range: Range<u64>) -> MidiState {
+        let (lo, hi) = (range.start, range.end.max(range.start));
+        let notes: Vec<Note> = self
+            .notes
+            .iter()
+            .filter(|n| n.start < hi && n.start + n.duration > lo)
+            .map(|n| {
+                let start = n.start.max(lo);
+                let end = (n.start + n.duration).min(hi);
+                Note { start: start - lo, duration: (end - start).max(1), ..*n }
+            })
+            .collect();
+        let curves = self
+            .curves
+            .iter()
+            .map(|lane| {
+                let mut sliced = CurveLane { points: Vec::new(), ..lane.clone() };
+                sliced.points.extend(
+                    lane.points
+                        .iter()
+                        .filter(|p| (lo..hi).contains(&p.tick))
+                        .map(|p| CurvePoint { tick: p.tick - lo, ..p.clone() }),
+                );
+                if sliced.points.first().is_none_or(|p| p.tick > 0) {
+                    if let Some(value) = lane.value_at(lo) {
+                        sliced.insert_point(0, value);
+                    }
+                }
+                sliced
+            })
+            .collect();
+        let tempo_changes = self
+            .tempo_changes
+            .iter()
+            .filter(|change| change.tick > lo && change.tick < hi)
+            .map(|change| TempoChange { tick: change.tick - lo, bpm: change.bpm })
+            .collect();
+        let text_events = self
+            .text_events
+            .iter()
+            .filter(|event| (lo..hi).contains(&event.tick))
+            .map(|event| TextEvent { tick: event.tick - lo, ..event.clone() })
+            .collect();
+        let markers = self
+            .markers
+            .iter()
+            .filter(|marker| (lo..hi).contains(&marker.tick))
+            .map(|marker| Marker { tick: marker.tick - lo, ..marker.clone() })
+            .collect();
+        let kept: HashSet<NoteId> = notes.iter().map(|n| n.id).collect();
+        let groups = self
+            .groups
+            .iter()
+            .map(|g| NoteGroup { note_ids: g.note_ids.iter().copied().filter(|id| kept.contains(id)).collect(), ..g.clone() })
+            .filter(|g| !g.note_ids.is_empty())
+            .collect();
+        MidiState {
+            notes,
+            curves,
+            ticks_per_beat: self.ticks_per_beat,
+            bpm: self.tempo_at(lo),
+            tempo_changes,
+            time_signature: self.time_signature,
+            key_signature: self.key_signature,
+            track: self.track.clone(),
+            groups,
+            tracks: self.tracks.clone(),
+            text_events,
+            markers,
+        }
+    }
+
+    /// 添加 CC 曲线并返回其 ID；同一控制器的曲线已存在时直接返回它
+    pub fn add_cc_lane(&mut self, cc: u8) -> CurveLaneId {
+        self.add_curve_lane(CurveLaneType::ControlChange(cc.min(127)))
+    }
+
+    /// `tick` 处延音踏板（启用的 CC64 曲线）是否踩下
+    pub fn sustain_at(&self, tick: u64) -> bool {
+        self.curves
+            .iter()
+            .filter(|lane| lane.enabled && lane.lane_type == CurveLaneType::ControlChange(64))
+            .any(|lane| lane.value_at(tick).is_some_and(|value| value >= 64.0))
+    }
+
+    /// 添加指定类型的曲线并返回其 ID；同类曲线已存在时直接返回它
+    pub fn add_curve_lane(&mut self, lane_type: CurveLaneType) -> CurveLaneId {
+        if let Some(lane) = self.curves.iter().find(|lane| lane.lane_type == lane_type) {
+            return lane.id;
+        }
+        let lane = CurveLane::new(lane_type);
+        let id = lane.id;
+        self.curves.push(lane);
+        id
+    }
+
+    /// 复制每条曲线在 `[start, end)` 内的点，tick 相对于 `start`；范围内没有点的曲线不包含在内
+    pub fn copy_curve_range(&self, start: u64, end: u64) -> Vec<CurveClip> {
+        if end <= start {
+            return Vec::new();
+        }
+        self.curves.iter().filter_map(|lane| lane.copy_range(start, end - 1)).collect()
+    }
+
+    /// 删除每条曲线在 `[start, end)` 内的点，返回 `(曲线, 点)`
+    pub fn remove_curve_range(&mut self, start: u64, end: u64) -> Vec<(CurveLaneId, CurvePointId)> {
+        let mut removed = Vec::new();
+        for lane in &mut self.curves {
+            lane.points.retain(|p| {
+                let inside = p.tick >= start && p.tick < end;
+                if inside {
+                    removed.push((lane.id, p.id));
+                }
+                !inside
+            });
+        }
+        removed
+    }
+
+    /// 所有启用的 CC 与弯音曲线导出的事件，斜坡每十六分之一拍取样一次
+    fn control_change_track_events<'a>(&self, channel: u8) -> Vec<(u64, TrackEventKind<'a>)> {
+        let resolution = (self.ticks_per_beat as u64 / 16).max(1);
+        let mut events: Vec<(u64, TrackEventKind<'a>)> = self
+            .curves
+            .iter()
+            .filter(|lane| lane.enabled)
+            .filter_map(|lane| Some((lane.lane_type.cc_number()?, lane)))
+            .flat_map(|(cc, lane)| {
+                lane.control_change_events(resolution).into_iter().map(move |(tick, value)| {
+                    (
+                        tick,
+                        TrackEventKind::Midi {
+                            channel: channel.into(),
+                            message: midly::MidiMessage::Controller {
+                                controller: cc.into(),
+                                value: value.into(),
+                            },
+                        },
+                    )
+                })
+            })
+            .collect();
+        let bend_lanes = self
+            .curves
+            .iter()
+            .filter(|lane| lane.enabled && lane.lane_type == CurveLaneType::PitchBend);
+        for lane in bend_lanes {
+            events.extend(lane.pitch_bend_events(resolution).into_iter().map(|(tick, value)| {
+                (
+                    tick,
+                    TrackEventKind::Midi {
+                        channel: channel.into(),
+                        message: midly::MidiMessage::PitchBend {
+                            bend: midly::PitchBend::from_int(value),
+                        },
+                    },
+                )
+            }));
+        }
+        events
+    }
+
+    /// 每拍的 tick 数（拍号分母对应的音符时值）
+    fn ticks_per_meter_beat(&self) -> u64 {
+        (self.ticks_per_beat.max(1) as u64 * 4 / self.time_signature.1.max(1) as u64).max(1)
+    }
+
+    /// `tick` 对应的 "小节.拍.tick" 位置
+    ///
+    /// 目前整首曲子只有一个拍号；之后加入拍号变化时在这里按变化点分段换算，调用方不必改动。
+    pub fn musical_time(&self, tick: u64) -> MusicalTime {
+        let beat_ticks = self.ticks_per_meter_beat();
+        let measure_ticks = beat_ticks * self.time_signature.0.max(1) as u64;
+        let in_measure = tick % measure_ticks;
+        MusicalTime {
+            bar: tick / measure_ticks + 1,
+            beat: in_measure / beat_ticks + 1,
+            tick: in_measure % beat_ticks,
+        }
+    }
+
+    /// `musical_time` 的逆运算；小节或拍为 0 时按 1 处理，超出拍长的 tick 顺延
+    pub fn musical_time_to_tick(&self, time: MusicalTime) -> u64 {
+        let beat_ticks = self.ticks_per_meter_beat();
+        let measure_ticks = beat_ticks * self.time_signature.0.max(1) as u64;
+        time.bar.saturating_sub(1) * measure_ticks + time.beat.saturating_sub(1) * beat_ticks + time.tick
+    }
+
+    /// 解析 "小节.拍.tick"（也接受 ":" 分隔，拍与 tick 可省略，如 "5.2"）为 tick
+    ///
+    /// 不含分隔符的文本返回 `None`，由调用方按原始 tick 数解析；小节或拍为 0 时同样返回 `None`。
+    pub fn parse_musical_time(&self, text: &str) -> Option<u64> {
+        let text = text.trim();
+        if !text.contains(['.', ':']) {
+            return None;
+        }
+        let mut parts = text.split(['.', ':']).map(|part| part.trim().parse::<u64>().ok());
+        let bar = parts.next()??;
+        let beat = parts.next().unwrap_or(Some(1))?;
+        let tick = parts.next().unwrap_or(Some(0))?;
+        if parts.next().is_some() || bar == 0 || beat == 0 {
+            return None;
+        }
+        Some(self.musical_time_to_tick(MusicalTime { bar, beat, tick }))
+    }
+
+    /// `tick` 处生效的速度
+    pub fn tempo_at(&self, tick: u64) -> f32 {
+        let index = self.tempo_changes.partition_point(|change| change.tick <= tick);
+        match index {
+            0 => self.bpm,
+            _ => self.tempo_changes[index - 1].bpm,
+        }
+    }
+
+    /// 按 tick 插入标记，排在同一 tick 已有标记之后
+    pub fn insert_marker(&mut self, marker: Marker) {
+        let index = self.markers.partition_point(|m| m.tick <= marker.tick);
+        self.markers.insert(index, marker);
+    }
+
+    /// 按 tick 插入文本事件，排在同一 tick 已有事件之后，返回其下标
+    pub fn insert_text_event(&mut self, event: TextEvent) -> usize {
+        let index = self.text_events.partition_point(|e| e.tick <= event.tick);
+        self.text_events.insert(index, event);
+        index
+    }
+
+    /// 设置 `tick` 所在速度段的速度；`tick` 在第一个变化点之前时修改起始速度
+    pub fn set_tempo_at(&mut self, tick: u64, bpm: f32) {
+        let index = self.tempo_changes.partition_point(|change| change.tick <= tick);
+        match index {
+            0 => self.bpm = bpm,
+            _ => self.tempo_changes[index - 1].bpm = bpm,
+        }
+    }
+
+    /// 在 `tick` 处插入速度变化（已存在时覆盖）；tick 0 修改起始速度
+    pub fn insert_tempo_change(&mut self, tick: u64, bpm: f32) {
+        if tick == 0 {
+            self.bpm = bpm;
+            return;
+        }
+        match self.tempo_changes.binary_search_by_key(&tick, |change| change.tick) {
+            Ok(index) => self.tempo_changes[index].bpm = bpm,
+            Err(index) => self.tempo_changes.insert(index, TempoChange { tick, bpm }),
+        }
+    }
+
+    /// 速度段 `(起始 tick, 速度)`，第一段从 tick 0 开始
+    fn tempo_segments(&self) -> impl Iterator<Item = (u64, f32)> + '_ {
+        std::iter::once((0, self.bpm)).chain(self.tempo_changes.iter().map(|change| (change.tick, change.bpm)))
+    }
+
+    /// 将 tick（可带小数）换算为秒，逐段累加速度图
+    pub fn ticks_to_seconds(&self, ticks: f64) -> f64 {
+        let tpb = self.ticks_per_beat.max(1) as f64;
+        let mut seconds = 0.0;
+        let mut segments = self.tempo_segments().peekable();
+        while let Some((start, bpm)) = segments.next() {
+            let end = segments.peek().map(|(tick, _)| *tick as f64).unwrap_or(f64::INFINITY).min(ticks);
+            if end <= start as f64 {
+                break;
+            }
+            seconds += (end - start as f64) * 60.0 / (bpm.max(1.0) as f64 * tpb);
+        }
+        seconds
+    }
+
+    /// 将秒换算为 tick（带小数），`ticks_to_seconds` 的逆运算
+    pub fn seconds_to_ticks(&self, seconds: f64) -> f64 {
+        let tpb = self.ticks_per_beat.max(1) as f64;
+        let mut elapsed = 0.0;
+        let mut segments = self.tempo_segments().peekable();
+        while let Some((start, bpm)) = segments.next() {
+            let ticks_per_second = bpm.max(1.0) as f64 * tpb / 60.0;
+            let remaining = seconds.max(0.0) - elapsed;
+            match segments.peek() {
+                Some((next, _)) if remaining >= (*next - start) as f64 / ticks_per_second => {
+                    elapsed += (*next - start) as f64 / ticks_per_second;
+                }
+                _ => return start as f64 + remaining * ticks_per_second,
+            }
+        }
+        0.0
+    }
+
     pub fn get_velocity_at(&self, tick: u64) -> Option<u8> {
         for curve in &self.curves {
             if curve.lane_type == CurveLaneType::Velocity && curve.enabled {
@@ -374,19 +1607,26 @@ impl MidiState {
     }
 
     pub fn to_smf(&self) -> Smf<'static> {
-        use midly::{
-            Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
-        };
+        self.build_smf(None)
+    }
 
-        let mut track: Vec<TrackEvent<'static>> = Vec::new();
+    /// 是否含多条轨道（导出为 format 1）
+    pub fn is_multi_track(&self) -> bool {
+        self.tracks.len() > 1
+    }
+
+    /// 生成 SMF：单轨状态写一条轨道（format 0），多轨状态写 format 1，第一条轨道只含速度、拍号、调号与标记；
+    /// 提供 `arena` 时写出歌词、标记与文本事件，音符标签写为文本事件、轨道名称写为 TrackName
+    fn build_smf<'a>(&self, arena: Option<&'a midly::Arena>) -> Smf<'a> {
+        use midly::{Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+        let mut conductor: Vec<TrackEvent<'a>> = Vec::new();
         // Meta events for tempo and time signature at start.
-        track.push(TrackEvent {
+        conductor.push(TrackEvent {
             delta: 0.into(),
-            kind: TrackEventKind::Meta(MetaMessage::Tempo(midly::num::u24::from(
-                (60_000_000.0 / self.bpm.max(1.0)) as u32,
-            ))),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(bpm_to_micros(self.bpm))),
         });
-        track.push(TrackEvent {
+        conductor.push(TrackEvent {
             delta: 0.into(),
             kind: TrackEventKind::Meta(MetaMessage::TimeSignature(
                 self.time_signature.0,
@@ -402,24 +1642,155 @@ impl MidiState {
                 8,
             )),
         });
-        if let Some(program) = self.track.program {
+        if self.key_signature != KeySignature::default() {
+            conductor.push(TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(MetaMessage::KeySignature(self.key_signature.sharps, self.key_signature.minor)),
+            });
+        }
+
+        if !self.is_multi_track() {
+            let mut track = conductor;
+            let mut events = self.tempo_change_events();
+            if let Some(arena) = arena {
+                events.extend(self.marker_meta_events(arena));
+                events.extend(self.text_meta_events(arena, |_| true));
+            }
+            events.extend(self.control_change_track_events(self.track.channel));
+            track.extend(self.note_track_events(self.notes.iter(), self.track.channel, self.track.program, events, arena));
+            return Smf {
+                header: Header {
+                    format: Format::SingleTrack,
+                    timing: Timing::Metrical(self.ticks_per_beat.into()),
+                },
+                tracks: vec![track],
+            };
+        }
+
+        let mut conductor_events = self.tempo_change_events();
+        if let Some(arena) = arena {
+            conductor_events.extend(self.marker_meta_events(arena));
+        }
+        append_timed_events(&mut conductor, conductor_events);
+        conductor.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        let mut tracks = vec![conductor];
+        let last = self.tracks.len() - 1;
+        for (index, meta) in self.tracks.iter().enumerate() {
+            let mut track: Vec<TrackEvent<'a>> = Vec::new();
+            if let (Some(arena), Some(name)) = (arena, meta.name.as_deref()) {
+                track.push(TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::TrackName(arena.add(name.as_bytes()))),
+                });
+            }
+            // 轨道下标越界的音符（宿主直接写入的）归入最后一条轨道
+            let notes = self
+                .notes
+                .iter()
+                .filter(|n| n.track as usize == index || (index == last && n.track as usize > last));
+            // CC 曲线不区分轨道，写入第一条轨道
+            let mut events = if index == 0 { self.control_change_track_events(meta.channel) } else { Vec::new() };
+            if let Some(arena) = arena {
+                events.extend(self.text_meta_events(arena, |event| {
+                    event.track as usize == index || (index == last && event.track as usize > last)
+                }));
+            }
+            track.extend(self.note_track_events(notes, meta.channel, meta.program, events, arena));
+            tracks.push(track);
+        }
+        Smf {
+            header: Header {
+                format: Format::Parallel,
+                timing: Timing::Metrical(self.ticks_per_beat.into()),
+            },
+            tracks,
+        }
+    }
+
+    /// 满足 `filter` 的歌词与文本元事件，字节分配在 `arena` 中
+    fn text_meta_events<'a>(
+        &self,
+        arena: &'a midly::Arena,
+        filter: impl Fn(&TextEvent) -> bool,
+    ) -> Vec<(u64, TrackEventKind<'a>)> {
+        self.text_events
+            .iter()
+            .filter(|event| filter(event))
+            .map(|event| {
+                let text: &'a [u8] = arena.add(&event.text);
+                let meta = match event.kind {
+                    TextEventKind::Lyric => MetaMessage::Lyric(text),
+                    TextEventKind::Text => MetaMessage::Text(text),
+                };
+                (event.tick, TrackEventKind::Meta(meta))
+            })
+            .collect()
+    }
+
+    /// 标记的 Marker 元事件，字节分配在 `arena` 中
+    fn marker_meta_events<'a>(&self, arena: &'a midly::Arena) -> Vec<(u64, TrackEventKind<'a>)> {
+        self.markers
+            .iter()
+            .map(|marker| {
+                let text: &'a [u8] = arena.add(marker.label.as_bytes());
+                (marker.tick, TrackEventKind::Meta(MetaMessage::Marker(text)))
+            })
+            .collect()
+    }
+
+    /// tick 0 之后的速度变化事件
+    fn tempo_change_events<'a>(&self) -> Vec<(u64, TrackEventKind<'a>)> {
+        self.tempo_changes
+            .iter()
+            .map(|change| (change.tick, TrackEventKind::Meta(MetaMessage::Tempo(bpm_to_micros(change.bpm)))))
+            .collect()
+    }
+
+    /// 一条轨道的音色切换、标签与音符事件（以 EndOfTrack 结束）；`events` 中已有的事件
+    /// （速度变化、控制器）在同一 tick 排在音符之前
+    fn note_track_events<'a, 'n>(
+        &self,
+        notes: impl Iterator<Item = &'n Note> + Clone,
+        channel: u8,
+        program: Option<u8>,
+        mut events: Vec<(u64, TrackEventKind<'a>)>,
+        arena: Option<&'a midly::Arena>,
+    ) -> Vec<midly::TrackEvent<'a>> {
+        use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
+
+        let mut track: Vec<TrackEvent<'a>> = Vec::new();
+        if let Some(program) = program {
             track.push(TrackEvent {
                 delta: 0.into(),
                 kind: TrackEventKind::Midi {
-                    channel: self.track.channel.into(),
+                    channel: channel.into(),
                     message: MidiMessage::ProgramChange {
                         program: program.into(),
                     },
                 },
             });
         }
-        let mut events: Vec<(u64, TrackEventKind<'static>)> = Vec::new();
-        for note in &self.notes {
+        if let Some(arena) = arena {
+            // 同一 tick 的标签按音高从低到高写出，与导入时的匹配顺序一致
+            let mut labelled: Vec<(u64, u8, SmallString)> = notes
+                .clone()
+                .filter_map(|n| n.label.map(|label| (n.start, n.key, label)))
+                .collect();
+            labelled.sort_by_key(|(start, key, _)| (*start, *key));
+            for (start, _, label) in labelled {
+                let text: &'a [u8] = arena.add(label.as_bytes());
+                events.push((start, TrackEventKind::Meta(MetaMessage::Text(text))));
+            }
+        }
+        for note in notes {
             let velocity = self.apply_velocity_curve_to_note(note);
             events.push((
                 note.start,
                 TrackEventKind::Midi {
-                    channel: self.track.channel.into(),
+                    channel: channel.into(),
                     message: MidiMessage::NoteOn {
                         key: note.key.into(),
                         vel: velocity.into(),
@@ -429,7 +1800,7 @@ impl MidiState {
             events.push((
                 note.start + note.duration,
                 TrackEventKind::Midi {
-                    channel: self.track.channel.into(),
+                    channel: channel.into(),
                     message: MidiMessage::NoteOff {
                         key: note.key.into(),
                         vel: 0.into(),
@@ -437,55 +1808,172 @@ impl MidiState {
                 },
             ));
         }
-        events.sort_by_key(|(t, _)| *t);
-
-        let mut last_tick = 0;
-        for (tick, kind) in events {
-            let delta = tick.saturating_sub(last_tick);
-            last_tick = tick;
-            let delta_ticks = u32::try_from(delta).unwrap_or(u32::MAX);
-            track.push(TrackEvent {
-                delta: delta_ticks.into(),
-                kind,
-            });
-        }
+        append_timed_events(&mut track, events);
         track.push(TrackEvent {
             delta: 0.into(),
             kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
         });
+        track
+    }
 
-        Smf {
-            header: Header {
-                format: Format::SingleTrack,
-                timing: Timing::Metrical(self.ticks_per_beat.into()),
-            },
-            tracks: vec![track],
+    /// 与 [`to_smf`](Self::to_smf) 相同，但额外写入编辑器扩展数据（音符分组、音符标签），
+    /// 这些事件的字节分配在 `arena` 中。
+    pub fn to_smf_in<'a>(&self, arena: &'a midly::Arena) -> Smf<'a> {
+        let mut smf: Smf<'a> = self.build_smf(Some(arena));
+        if let Some(encoded) = encode_note_groups(&self.groups, &self.notes) {
+            let mut data = NOTE_GROUPS_SMF_TAG.to_vec();
+            data.extend_from_slice(&encoded);
+            let data: &'a [u8] = arena.add(&data);
+            // Tick 0, right after tempo and time signature
+            smf.tracks[0].insert(
+                2,
+                midly::TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::SequencerSpecific(data)),
+                },
+            );
+        }
+        smf
+    }
+
+    /// 检查状态中可能需要提醒用户的问题（不检查循环区与最大 tick），见 [`validate_with`](Self::validate_with)
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        self.validate_with(&ValidationOptions::default())
+    }
+
+    /// 检查零时长、力度为 0、同音高重叠、超出 `options.max_tick` 的音符，超出曲线取值范围的曲线点，
+    /// 以及 `options.loop_range` 终点不晚于起点的循环区；按上述顺序返回
+    pub fn validate_with(&self, options: &ValidationOptions) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        for note in &self.notes {
+            let (note_id, tick, key) = (note.id, note.start, note.key);
+            if note.duration == 0 {
+                issues.push(ValidationIssue::ZeroDurationNote { note_id, tick, key });
+            }
+            if note.velocity == 0 {
+                issues.push(ValidationIssue::ZeroVelocityNote { note_id, tick, key });
+            }
+        }
+        issues.extend(
+            self.find_overlaps()
+                .into_iter()
+                .map(|(earlier, later)| ValidationIssue::OverlappingNotes { earlier, later }),
+        );
+        if let Some(max_tick) = options.max_tick {
+            for note in self.notes.iter().filter(|n| n.start + n.duration > max_tick) {
+                let end = note.start + note.duration;
+                issues.push(ValidationIssue::NoteBeyondLimit { note_id: note.id, end, max_tick });
+            }
         }
+        for lane in &self.curves {
+            let (min, max) = lane.lane_type.value_range();
+            for point in lane.points.iter().filter(|p| !(min..=max).contains(&p.value)) {
+                issues.push(ValidationIssue::CurvePointOutOfRange { lane_id: lane.id, point_id: point.id, tick: point.tick });
+            }
+        }
+        if let Some((start, end)) = options.loop_range.filter(|(start, end)| end <= start) {
+            issues.push(ValidationIssue::InvalidLoop { start, end });
+        }
+        issues
     }
 
-    pub fn validate_single_track(&self) -> Result<(), MidiValidationError> {
+    pub fn validate_single_track(&self) -> Result<(), ValidationIssue> {
         if self.track.tracks_with_notes > 1 {
-            return Err(MidiValidationError::MultipleTracks {
+            return Err(ValidationIssue::MultipleTracks {
                 tracks: self.track.tracks_with_notes,
             });
         }
         if !self.track.single_channel {
-            return Err(MidiValidationError::MixedChannels);
+            return Err(ValidationIssue::MixedChannels);
         }
         Ok(())
     }
 
-    pub fn from_smf_strict(smf: &Smf) -> Result<Self, MidiValidationError> {
-        let state = Self::from_smf(smf);
+    pub fn from_smf_strict(smf: &Smf) -> Result<Self, ValidationIssue> {
+        Self::from_smf_strict_with_progress(smf, &mut |_| {})
+    }
+
+    pub(crate) fn from_smf_strict_with_progress(
+        smf: &Smf,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<Self, ValidationIssue> {
+        let state = Self::import_smf(smf, SmfImportOptions::default(), &mut Vec::new(), progress);
         state.validate_single_track()?;
         Ok(state)
     }
 
-    pub fn to_single_track_smf(&self) -> Result<Smf<'static>, MidiValidationError> {
+    pub fn to_single_track_smf(&self) -> Result<Smf<'static>, ValidationIssue> {
         self.validate_single_track()?;
         Ok(self.to_smf())
     }
 
+    /// 单轨导出，并保留编辑器扩展数据，见 [`to_smf_in`](Self::to_smf_in)
+    pub fn to_single_track_smf_in<'a>(
+        &self,
+        arena: &'a midly::Arena,
+    ) -> Result<Smf<'a>, ValidationIssue> {
+        self.validate_single_track()?;
+        Ok(self.to_smf_in(arena))
+    }
+
+    /// 返回包含该音符的分组
+    pub fn group_of(&self, note_id: NoteId) -> Option<&NoteGroup> {
+        self.groups.iter().find(|g| g.note_ids.contains(&note_id))
+    }
+
+    /// 用给定音符新建分组；音符会先从原有分组中移出，一个音符最多属于一个分组。
+    /// 返回新分组以及因此变空而解散的旧分组。
+    pub fn create_group(
+        &mut self,
+        name: impl Into<String>,
+        note_ids: &[NoteId],
+    ) -> (NoteGroupId, Vec<NoteGroupId>) {
+        for group in &mut self.groups {
+            group.note_ids.retain(|id| !note_ids.contains(id));
+        }
+        let dissolved = self.remove_empty_groups();
+        let color = NOTE_GROUP_PALETTE[self.groups.len() % NOTE_GROUP_PALETTE.len()];
+        let group = NoteGroup {
+            id: NoteGroupId::next(),
+            name: name.into(),
+            note_ids: note_ids.to_vec(),
+            color,
+        };
+        let id = group.id;
+        self.groups.push(group);
+        (id, dissolved)
+    }
+
+    pub fn remove_group(&mut self, id: NoteGroupId) -> Option<NoteGroup> {
+        let idx = self.groups.iter().position(|g| g.id == id)?;
+        Some(self.groups.remove(idx))
+    }
+
+    /// 去掉分组中已不存在的音符，并解散所有成员都被删除的分组，返回被解散的分组 ID
+    pub fn prune_groups(&mut self) -> Vec<NoteGroupId> {
+        if self.groups.is_empty() {
+            return Vec::new();
+        }
+        let existing: std::collections::HashSet<NoteId> = self.notes.iter().map(|n| n.id).collect();
+        for group in &mut self.groups {
+            group.note_ids.retain(|id| existing.contains(id));
+        }
+        self.remove_empty_groups()
+    }
+
+    fn remove_empty_groups(&mut self) -> Vec<NoteGroupId> {
+        let mut dissolved = Vec::new();
+        self.groups.retain(|group| {
+            if group.note_ids.is_empty() {
+                dissolved.push(group.id);
+                false
+            } else {
+                true
+            }
+        });
+        dissolved
+    }
+
     /// Helper method to apply a closure to selected notes identified by their IDs
     fn apply_to_selected_notes<F>(&mut self, note_ids: &[NoteId], mut f: F)
     where
@@ -504,30 +1992,252 @@ impl MidiState {
         }
     }
 
+    /// 满足 `filter` 全部条件的音符，按起点排序
+    pub fn query_notes(&self, filter: &NoteFilter) -> Vec<NoteId> {
+        self.notes.iter().filter(|n| filter.matches(n)).map(|n| n.id).collect()
+    }
+
+    /// 同一轨道上同一音高且时间上重叠的音符对 `(较早的, 较晚的)`，按较早音符的起点排序
+    pub fn find_overlaps(&self) -> Vec<(NoteId, NoteId)> {
+        let mut pairs = Vec::new();
+        for group in self.same_pitch_groups(None) {
+            for (index, earlier) in group.iter().enumerate() {
+                let end = earlier.start + earlier.duration;
+                for later in group[index + 1..].iter().take_while(|later| later.start < end) {
+                    pairs.push((earlier.id, later.id));
+                }
+            }
+        }
+        pairs.sort_by_key(|(earlier, _)| self.notes.iter().find(|n| n.id == *earlier).map(|n| n.start));
+        pairs
+    }
+
+    /// 按 `policy` 消除同音高重叠，`scope` 不为 `None` 时只处理其中的音符
+    ///
+    /// 返回 `(修改前后的音符, 删除的音符)`；起点相同的重复音符无论哪种策略都只保留较长的一个。
+    pub fn resolve_overlaps(&mut self, scope: Option<&BTreeSet<NoteId>>, policy: OverlapPolicy) -> (Vec<(Note, Note)>, Vec<Note>) {
+        let mut updated: HashMap<NoteId, (Note, Note)> = HashMap::new();
+        let mut removed: Vec<Note> = Vec::new();
+        for group in self.same_pitch_groups(scope) {
+            let mut survivor = group[0];
+            for next in group.into_iter().skip(1) {
+                let end = survivor.start + survivor.duration;
+                if next.start >= end {
+                    survivor = next;
+                    continue;
+                }
+                let (keep, drop) = if next.start == survivor.start {
+                    if next.duration > survivor.duration { (next, survivor) } else { (survivor, next) }
+                } else {
+                    match policy {
+                        OverlapPolicy::TrimEarlier => {
+                            let trimmed = Note { duration: next.start - survivor.start, ..survivor };
+                            updated.entry(survivor.id).or_insert((survivor, trimmed)).1 = trimmed;
+                            survivor = next;
+                            continue;
+                        }
+                        OverlapPolicy::DeleteShorter if next.duration > survivor.duration => (next, survivor),
+                        OverlapPolicy::DeleteShorter => (survivor, next),
+                        OverlapPolicy::MergeIntoOne => {
+                            let merged = Note {
+                                duration: end.max(next.start + next.duration) - survivor.start,
+                                velocity: survivor.velocity.max(next.velocity),
+                                ..survivor
+                            };
+                            updated.entry(survivor.id).or_insert((survivor, merged)).1 = merged;
+                            (merged, next)
+                        }
+                    }
+                };
+                updated.remove(&drop.id);
+                removed.push(self.notes.iter().find(|n| n.id == drop.id).copied().unwrap_or(drop));
+                survivor = keep;
+            }
+        }
+        let removed_ids: HashSet<NoteId> = removed.iter().map(|n| n.id).collect();
+        self.notes.retain(|note| !removed_ids.contains(&note.id));
+        for note in &mut self.notes {
+            if let Some((_, after)) = updated.get(&note.id) {
+                *note = *after;
+            }
+        }
+        let mut updated: Vec<(Note, Note)> = updated.into_values().filter(|(before, after)| before != after).collect();
+        updated.sort_by_key(|(before, _)| (before.start, before.key));
+        (updated, removed)
+    }
+
+    /// 将 `note_ids` 中同音高、间隔小于 `max_gap` 的相邻音符（含重叠与首尾相接的）粘合为一个音符
+    ///
+    /// 粘合后的音符从最早的起点延续到最晚的终点，保留第一个音符的力度与其他属性；三个以上的连串合并为一个。
+    /// 返回 `(修改前后的音符, 删除的音符)`。
+    pub fn glue_notes(&mut self, note_ids: &[NoteId], max_gap: u64) -> (Vec<(Note, Note)>, Vec<Note>) {
+        let scope: BTreeSet<NoteId> = note_ids.iter().copied().collect();
+        let mut updated = Vec::new();
+        let mut removed = Vec::new();
+        for group in self.same_pitch_groups(Some(&scope)) {
+            let mut first = group[0];
+            let mut glued = first;
+            for next in group.into_iter().skip(1) {
+                let end = glued.start + glued.duration;
+                if next.start < end + max_gap.max(1) {
+                    glued.duration = end.max(next.start + next.duration) - glued.start;
+                    removed.push(next);
+                } else {
+                    if glued != first {
+                        updated.push((first, glued));
+                    }
+                    first = next;
+                    glued = next;
+                }
+            }
+            if glued != first {
+                updated.push((first, glued));
+            }
+        }
+        let removed_ids: HashSet<NoteId> = removed.iter().map(|n| n.id).collect();
+        self.notes.retain(|note| !removed_ids.contains(&note.id));
+        for (_, after) in &updated {
+            if let Some(note) = self.notes.iter_mut().find(|n| n.id == after.id) {
+                *note = *after;
+            }
+        }
+        (updated, removed)
+    }
+
+    /// 按 (轨道, 音高) 分组并按起点排序的音符，`scope` 限定参与的音符
+    fn same_pitch_groups(&self, scope: Option<&BTreeSet<NoteId>>) -> Vec<Vec<Note>> {
+        let mut groups: BTreeMap<(u16, u8), Vec<Note>> = BTreeMap::new();
+        for note in self.notes.iter().filter(|n| scope.is_none_or(|ids| ids.contains(&n.id))) {
+            groups.entry((note.track, note.key)).or_default().push(*note);
+        }
+        groups
+            .into_values()
+            .map(|mut group| {
+                group.sort_by_key(|n| (n.start, std::cmp::Reverse(n.duration)));
+                group
+            })
+            .collect()
+    }
+
+    /// 按起点在最早与最晚的选中音符之间插值设置力度（起点相同的音符力度相同），返回修改前后的音符
+    pub fn velocity_ramp(&mut self, note_ids: &[NoteId], start_velocity: u8, end_velocity: u8, curve: RampCurve) -> Vec<(Note, Note)> {
+        let ids: HashSet<NoteId> = note_ids.iter().copied().collect();
+        let starts = self.notes.iter().filter(|n| ids.contains(&n.id)).map(|n| n.start);
+        let (Some(first), Some(last)) = (starts.clone().min(), starts.max()) else {
+            return Vec::new();
+        };
+        let span = (last - first) as f64;
+        let mut changes = Vec::new();
+        for note in self.notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+            let t = if span > 0.0 { (note.start - first) as f64 / span } else { 0.0 };
+            let value = start_velocity as f64 + (end_velocity as f64 - start_velocity as f64) * curve.apply(t);
+            let before = *note;
+            note.velocity = (value.round() as i64).clamp(1, 127) as u8;
+            if *note != before {
+                changes.push((before, *note));
+            }
+        }
+        changes
+    }
+
+    /// 以 `anchor` 为基准把选中音符的起点偏移与时值乘以 `ratio`（四舍五入到 tick，时值至少为 1），返回修改前后的音符
+    pub fn time_stretch(&mut self, note_ids: &[NoteId], anchor: u64, ratio: f64) -> Vec<(Note, Note)> {
+        if !(ratio.is_finite() && ratio > 0.0) {
+            return Vec::new();
+        }
+        let ids: HashSet<NoteId> = note_ids.iter().copied().collect();
+        let mut changes = Vec::new();
+        for note in self.notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+            let before = *note;
+            note.start = stretch_tick(note.start, anchor, ratio);
+            note.duration = ((note.duration as f64 * ratio).round() as u64).max(1);
+            if *note != before {
+                changes.push((before, *note));
+            }
+        }
+        if !changes.is_empty() {
+            self.notes.sort_by_key(|n| n.start);
+        }
+        changes
+    }
+
+    /// 以 `anchor` 为基准伸缩 `[start, end]` 内的曲线点，返回被移动的点
+    pub fn time_stretch_curves(&mut self, range: (u64, u64), anchor: u64, ratio: f64) -> Vec<(CurveLaneId, CurvePointId)> {
+        if !(ratio.is_finite() && ratio > 0.0) {
+            return Vec::new();
+        }
+        let mut moved = Vec::new();
+        for lane in &mut self.curves {
+            for point in lane.points.iter_mut().filter(|p| (range.0..=range.1).contains(&p.tick)) {
+                let tick = stretch_tick(point.tick, anchor, ratio);
+                if tick != point.tick {
+                    point.tick = tick;
+                    moved.push((lane.id, point.id));
+                }
+            }
+            lane.sort_points();
+        }
+        moved
+    }
+
+    /// 以 `axis_key` 为轴镜像音高（`2 * axis_key - key`，限制在 0..=127），返回修改前后的音符
+    pub fn invert_pitch(&mut self, note_ids: &[NoteId], axis_key: u8) -> Vec<(Note, Note)> {
+        let ids: HashSet<NoteId> = note_ids.iter().copied().collect();
+        let mut changes = Vec::new();
+        for note in self.notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+            let before = *note;
+            note.key = (2 * axis_key as i16 - note.key as i16).clamp(0, 127) as u8;
+            if *note != before {
+                changes.push((before, *note));
+            }
+        }
+        changes
+    }
+
     /// Humanize selected notes by adding random variations to timing and velocity
-    pub fn humanize_notes(&mut self, note_ids: &[NoteId], time_range: u64, velocity_range: u8) {
+    ///
+    /// `delay_only` 时起点只向后偏移；给定 `seed` 时结果可复现。返回实际改变的音符数。
+    pub fn humanize_notes(
+        &mut self,
+        note_ids: &[NoteId],
+        time_range: u64,
+        velocity_range: u8,
+        delay_only: bool,
+        seed: Option<u64>,
+    ) -> usize {
+        let mut rng = seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+        let mut changed = 0;
         self.apply_to_selected_notes(note_ids, |note| {
-            // Add random time offset
-            let time_offset = if time_range > 0 {
-                let range = time_range as i64;
-                fastrand::i64(-range..=range)
-            } else {
-                0
-            };
-            note.start = (note.start as i64 + time_offset).max(0) as u64;
-            
-            // Add random velocity offset
-            let velocity_offset = if velocity_range > 0 {
-                let range = velocity_range as i8;
-                fastrand::i8(-range..=range)
-            } else {
-                0
-            };
-            note.velocity = ((note.velocity as i16 + velocity_offset as i16).max(0).min(127)) as u8;
+            let before = *note;
+            humanize_note(note, &mut rng, time_range, velocity_range, delay_only);
+            changed += usize::from(*note != before);
         });
         
         // Re-sort notes after time changes
         self.notes.sort_by_key(|n| n.start);
+        changed
+    }
+
+    /// 以相同参数执行 `humanize_notes` 会改变的音符数（不修改状态，需给定种子才与实际结果一致）
+    pub fn humanize_preview_count(
+        &self,
+        note_ids: &[NoteId],
+        time_range: u64,
+        velocity_range: u8,
+        delay_only: bool,
+        seed: u64,
+    ) -> usize {
+        let ids: HashSet<NoteId> = note_ids.iter().copied().collect();
+        let mut rng = fastrand::Rng::with_seed(seed);
+        self.notes
+            .iter()
+            .filter(|n| ids.contains(&n.id))
+            .filter(|n| {
+                let mut note = **n;
+                humanize_note(&mut note, &mut rng, time_range, velocity_range, delay_only);
+                note != **n
+            })
+            .count()
     }
 
     /// Apply batch transform to selected notes
@@ -537,13 +2247,27 @@ impl MidiState {
         transform_type: BatchTransformType,
         value: f64,
     ) {
-        let needs_sort = matches!(transform_type, BatchTransformType::VelocityOffset | BatchTransformType::DurationScale);
+        let needs_sort = matches!(
+            transform_type,
+            BatchTransformType::VelocityOffset | BatchTransformType::DurationScale | BatchTransformType::StartOffset
+        );
         
         self.apply_to_selected_notes(note_ids, |note| {
             match transform_type {
                 BatchTransformType::VelocityOffset => {
                     let new_velocity = (note.velocity as f64 + value).round() as i16;
-                    note.velocity = new_velocity.max(0).min(127) as u8;
+                    note.velocity = new_velocity.clamp(1, 127) as u8;
+                }
+                BatchTransformType::SetVelocity => {
+                    note.velocity = value.round().clamp(1.0, 127.0) as u8;
+                }
+                BatchTransformType::CompressVelocity { center } => {
+                    let center = f64::from(center);
+                    let new_velocity = center + (f64::from(note.velocity) - center) * value.max(0.0);
+                    note.velocity = new_velocity.round().clamp(1.0, 127.0) as u8;
+                }
+                BatchTransformType::StartOffset => {
+                    note.start = (note.start as f64 + value).round().max(0.0) as u64;
                 }
                 BatchTransformType::DurationScale => {
                     let new_duration = (note.duration as f64 * value).round() as i64;
@@ -551,7 +2275,7 @@ impl MidiState {
                 }
                 BatchTransformType::PitchOffset => {
                     let new_key = (note.key as f64 + value).round() as i16;
-                    note.key = new_key.max(0).min(127) as u8;
+                    note.key = new_key.clamp(0, 127) as u8;
                 }
             }
         });
@@ -569,36 +2293,544 @@ pub enum BatchTransformType {
     VelocityOffset,
     DurationScale,
     PitchOffset,
+    /// 把力度设为 `value`
+    SetVelocity,
+    /// 把力度到 `center` 的距离乘以 `value`（小于 1 时压缩动态，大于 1 时扩展）
+    CompressVelocity { center: u8 },
+    /// 把起点移动 `value` tick，不早于 0
+    StartOffset,
 }
 
+impl BatchTransformType {
+    pub const ALL: [BatchTransformType; 6] = [
+        BatchTransformType::VelocityOffset,
+        BatchTransformType::DurationScale,
+        BatchTransformType::PitchOffset,
+        BatchTransformType::SetVelocity,
+        BatchTransformType::CompressVelocity { center: 64 },
+        BatchTransformType::StartOffset,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BatchTransformType::VelocityOffset => "Velocity Offset",
+            BatchTransformType::DurationScale => "Duration Scale",
+            BatchTransformType::PitchOffset => "Pitch Offset",
+            BatchTransformType::SetVelocity => "Set Velocity",
+            BatchTransformType::CompressVelocity { .. } => "Compress Velocity",
+            BatchTransformType::StartOffset => "Start Offset",
+        }
+    }
+
+    /// 切换到该变换时 `value` 的初始值（不改变音符的值，`SetVelocity` 除外）
+    pub fn default_value(&self) -> f64 {
+        match self {
+            BatchTransformType::VelocityOffset | BatchTransformType::PitchOffset | BatchTransformType::StartOffset => 0.0,
+            BatchTransformType::DurationScale => 1.0,
+            BatchTransformType::SetVelocity => 100.0,
+            BatchTransformType::CompressVelocity { .. } => 0.5,
+        }
+    }
+}
+
+/// 力度渐变的曲线
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RampCurve {
+    #[default]
+    Linear,
+    /// 开始平缓、结尾陡峭
+    Exponential,
+}
+
+impl RampCurve {
+    pub const ALL: [RampCurve; 2] = [RampCurve::Linear, RampCurve::Exponential];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RampCurve::Linear => "Linear",
+            RampCurve::Exponential => "Exponential",
+        }
+    }
+
+    /// 将进度 `t`（0.0..=1.0）映射为渐变比例，两端恰好为 0 与 1
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            RampCurve::Linear => t,
+            RampCurve::Exponential => ((3.0 * t).exp() - 1.0) / (3.0f64.exp() - 1.0),
+        }
+    }
+}
+
+/// 消除同音高重叠的方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// 截短较早的音符，使其在较晚的音符起点结束
+    TrimEarlier,
+    /// 删除两者中较短的音符（等长时删除较晚的）
+    DeleteShorter,
+    /// 合并为一个音符：从最早的起点到最晚的终点，力度取较大者
+    MergeIntoOne,
+}
+
+impl OverlapPolicy {
+    pub const ALL: [OverlapPolicy; 3] = [OverlapPolicy::TrimEarlier, OverlapPolicy::DeleteShorter, OverlapPolicy::MergeIntoOne];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OverlapPolicy::TrimEarlier => "Trim earlier note",
+            OverlapPolicy::DeleteShorter => "Delete shorter note",
+            OverlapPolicy::MergeIntoOne => "Merge into one",
+        }
+    }
+}
+
+/// `MidiState::query_notes` 的筛选条件，`None` 的条件不参与筛选
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoteFilter {
+    pub velocity: Option<RangeInclusive<u8>>,
+    pub key: Option<RangeInclusive<u8>>,
+    pub duration: Option<RangeInclusive<u64>>,
+}
+
+impl NoteFilter {
+    pub fn matches(&self, note: &Note) -> bool {
+        self.velocity.as_ref().is_none_or(|r| r.contains(&note.velocity))
+            && self.key.as_ref().is_none_or(|r| r.contains(&note.key))
+            && self.duration.as_ref().is_none_or(|r| r.contains(&note.duration))
+    }
+}
+
+/// 琶音的音高顺序
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArpPattern {
+    Up,
+    Down,
+    /// 上行后下行，两端的音不重复
+    UpDown,
+    Random,
+}
+
+impl ArpPattern {
+    pub const ALL: [ArpPattern; 4] = [ArpPattern::Up, ArpPattern::Down, ArpPattern::UpDown, ArpPattern::Random];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ArpPattern::Up => "Up",
+            ArpPattern::Down => "Down",
+            ArpPattern::UpDown => "Up-Down",
+            ArpPattern::Random => "Random",
+        }
+    }
+}
+
+/// 把按 `from_tpb` 计的 tick 换算为按 `to_tpb` 计，四舍五入到最近的 tick
+pub fn rescale_tick(tick: u64, from_tpb: u16, to_tpb: u16) -> u64 {
+    let (from, to) = (from_tpb.max(1) as u128, to_tpb.max(1) as u128);
+    ((tick as u128 * to + from / 2) / from).min(u64::MAX as u128) as u64
+}
+
+/// 将音符按起点分组为和弦，每个和弦在自身跨度内展开为琶音（单个音符即为连续重复）
+///
+/// 和弦里重复的音高只保留时值最长的那个；和弦的跨度为其中最长的时值，但不超过下一个和弦的起点，
+/// 因此重叠的选区不会生成互相叠加的琶音。`octaves` 为覆盖的八度数（至少 1），超出 127 的音高被丢弃；
+/// 每一步间隔 `rate_ticks`，时值为 `rate_ticks * gate`（至少 1 tick）。新音符沿用对应原音符的力度、标签与轨道。
+pub fn arpeggiate(
+    notes: &[Note],
+    pattern: ArpPattern,
+    rate_ticks: u64,
+    octaves: u8,
+    gate: f32,
+    rng: &mut fastrand::Rng,
+) -> Vec<Note> {
+    if rate_ticks == 0 {
+        return Vec::new();
+    }
+    let mut chords: BTreeMap<u64, BTreeMap<u8, Note>> = BTreeMap::new();
+    for note in notes {
+        let chord = chords.entry(note.start).or_default();
+        match chord.get(&note.key) {
+            Some(kept) if kept.duration >= note.duration => {}
+            _ => {
+                chord.insert(note.key, *note);
+            }
+        }
+    }
+    let starts: Vec<u64> = chords.keys().copied().collect();
+    let step_length = ((rate_ticks as f32 * gate.clamp(0.0, 1.0)).round() as u64).max(1);
+    let mut generated = Vec::new();
+    for (index, (start, chord)) in chords.into_iter().enumerate() {
+        let longest = chord.values().map(|note| note.duration).max().unwrap_or(0);
+        let end = match starts.get(index + 1) {
+            Some(next) => (start + longest).min(*next),
+            None => start + longest,
+        };
+        // 升序排列的和弦音，逐个八度向上复制
+        let mut up: Vec<(u8, Note)> = Vec::new();
+        for octave in 0..octaves.max(1) {
+            for source in chord.values() {
+                let key = source.key as u16 + 12 * octave as u16;
+                if key <= 127 {
+                    up.push((key as u8, *source));
+                }
+            }
+        }
+        let sequence: Vec<(u8, Note)> = match pattern {
+            ArpPattern::Up | ArpPattern::Random => up,
+            ArpPattern::Down => up.into_iter().rev().collect(),
+            ArpPattern::UpDown => {
+                let down = up.iter().rev().skip(1).take(up.len().saturating_sub(2)).copied().collect::<Vec<_>>();
+                up.into_iter().chain(down).collect()
+            }
+        };
+        if sequence.is_empty() {
+            continue;
+        }
+        let mut tick = start;
+        let mut step = 0;
+        while tick < end {
+            let (key, source) = match pattern {
+                ArpPattern::Random => sequence[rng.usize(..sequence.len())],
+                _ => sequence[step % sequence.len()],
+            };
+            generated.push(Note {
+                label: source.label,
+                track: source.track,
+                ..Note::new(tick, step_length.min(end - tick), key, source.velocity)
+            });
+            tick += rate_ticks;
+            step += 1;
+        }
+    }
+    generated
+}
+
+/// `MidiState::validate_with` 的可选检查
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidationOptions {
+    /// 音符终点不得超过的 tick，`None` 不检查
+    pub max_tick: Option<u64>,
+    /// 宿主的循环区 `(start, end)`，`None` 不检查
+    pub loop_range: Option<(u64, u64)>,
+}
+
+/// `MidiState::validate` 报告的问题；严格单轨导入/导出（`from_smf_strict` 等）也用它报告错误
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum MidiValidationError {
+pub enum ValidationIssue {
+    /// 时长为零的音符
+    ZeroDurationNote { note_id: NoteId, tick: u64, key: u8 },
+    /// 力度为 0 的音符（多数合成器把它当作 note-off）
+    ZeroVelocityNote { note_id: NoteId, tick: u64, key: u8 },
+    /// 同一轨道上同一音高且时间上重叠的两个音符
+    OverlappingNotes { earlier: NoteId, later: NoteId },
+    /// 终点超过 `ValidationOptions::max_tick` 的音符
+    NoteBeyondLimit { note_id: NoteId, end: u64, max_tick: u64 },
+    /// 超出曲线类型取值范围（`CurveLaneType::value_range`）的曲线点
+    CurvePointOutOfRange { lane_id: CurveLaneId, point_id: CurvePointId, tick: u64 },
+    /// 终点不晚于起点的循环区
+    InvalidLoop { start: u64, end: u64 },
     MultipleTracks { tracks: usize },
     MixedChannels,
 }
 
-impl fmt::Display for MidiValidationError {
+impl ValidationIssue {
+    /// 与该问题有关的音符
+    pub fn note_ids(&self) -> Vec<NoteId> {
+        match self {
+            ValidationIssue::ZeroDurationNote { note_id, .. }
+            | ValidationIssue::ZeroVelocityNote { note_id, .. }
+            | ValidationIssue::NoteBeyondLimit { note_id, .. } => vec![*note_id],
+            ValidationIssue::OverlappingNotes { earlier, later } => vec![*earlier, *later],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// 单轨检查的错误，现与 [`ValidationIssue`] 为同一类型
+pub type MidiValidationError = ValidationIssue;
+
+impl fmt::Display for ValidationIssue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            MidiValidationError::MultipleTracks { tracks } => {
+            ValidationIssue::ZeroDurationNote { tick, key, .. } => {
+                write!(f, "tick {tick} 处的音符 {key} 时长为零")
+            }
+            ValidationIssue::ZeroVelocityNote { tick, key, .. } => {
+                write!(f, "tick {tick} 处的音符 {key} 力度为 0")
+            }
+            ValidationIssue::OverlappingNotes { .. } => write!(f, "同一音高的两个音符重叠"),
+            ValidationIssue::NoteBeyondLimit { end, max_tick, .. } => {
+                write!(f, "音符在 tick {end} 结束，超过上限 {max_tick}")
+            }
+            ValidationIssue::CurvePointOutOfRange { tick, .. } => {
+                write!(f, "tick {tick} 处的曲线点超出取值范围")
+            }
+            ValidationIssue::InvalidLoop { start, end } => {
+                write!(f, "循环区终点 {end} 不晚于起点 {start}")
+            }
+            ValidationIssue::MultipleTracks { tracks } => {
                 write!(f, "MIDI 包含 {tracks} 条含音符的轨道，超出单轨要求")
             }
-            MidiValidationError::MixedChannels => {
+            ValidationIssue::MixedChannels => {
                 write!(f, "MIDI 轨道包含多个通道，无法保证单轨一致性")
             }
         }
     }
 }
 
-impl std::error::Error for MidiValidationError {}
+impl std::error::Error for ValidationIssue {}
+
+/// `MidiState::from_json` 的错误
+#[derive(Debug)]
+pub enum MidiJsonError {
+    /// 不是合法的 JSON，或字段不符合 `MidiState` 的结构
+    Parse(serde_json::Error),
+    /// 由更新版本写出、无法读取的格式
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for MidiJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiJsonError::Parse(err) => write!(f, "无法解析 MIDI 状态 JSON：{err}"),
+            MidiJsonError::UnsupportedVersion(version) => {
+                write!(f, "MIDI 状态 JSON 版本 {version} 高于支持的版本 {MIDI_STATE_JSON_VERSION}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MidiJsonError {}
+
+/// 宽松导入（`MidiState::from_smf_lenient`）跳过或修补的内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportWarning {
+    /// 到轨道末尾都没有松开的音符，已在轨道末尾结束
+    OrphanNoteOn { tick: u64, key: u8 },
+    /// 没有对应 note-on 的 note-off，已忽略
+    OrphanNoteOff { tick: u64, key: u8 },
+    /// 同一音高在松开前再次按下，前一个音符在此处结束
+    OverlappingNote { tick: u64, key: u8 },
+    /// 按下与松开在同一 tick 的音符，已丢弃
+    ZeroDurationNote { tick: u64, key: u8 },
+    /// 编辑器不支持的元事件，按类型合计
+    UnsupportedMeta { kind: &'static str, count: usize },
+    /// 编辑器不支持的通道或系统消息，按类型合计
+    UnsupportedEvent { kind: &'static str, count: usize },
+    /// 文件不满足单轨要求（严格导入会因此失败）
+    NotSingleTrack(ValidationIssue),
+}
+
+impl fmt::Display for ImportWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportWarning::OrphanNoteOn { tick, key } => {
+                write!(f, "tick {tick} 处的音符 {key} 没有松开，已延续到轨道末尾")
+            }
+            ImportWarning::OrphanNoteOff { tick, key } => {
+                write!(f, "tick {tick} 处音符 {key} 的 note-off 没有对应的 note-on，已忽略")
+            }
+            ImportWarning::OverlappingNote { tick, key } => {
+                write!(f, "音符 {key} 在 tick {tick} 处重叠，前一个音符在此结束")
+            }
+            ImportWarning::ZeroDurationNote { tick, key } => {
+                write!(f, "tick {tick} 处的音符 {key} 时长为零，已丢弃")
+            }
+            ImportWarning::UnsupportedMeta { kind, count } => {
+                write!(f, "跳过了 {count} 个不支持的元事件：{kind}")
+            }
+            ImportWarning::UnsupportedEvent { kind, count } => {
+                write!(f, "跳过了 {count} 个不支持的事件：{kind}")
+            }
+            ImportWarning::NotSingleTrack(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// 把 `tick` 到 `anchor` 的距离乘以 `ratio`，结果不早于 0
+fn stretch_tick(tick: u64, anchor: u64, ratio: f64) -> u64 {
+    (anchor as f64 + (tick as f64 - anchor as f64) * ratio).round().max(0.0) as u64
+}
+
+/// 给单个音符加上随机的起点与力度偏移；`delay_only` 时起点只向后偏移
+fn humanize_note(note: &mut Note, rng: &mut fastrand::Rng, time_range: u64, velocity_range: u8, delay_only: bool) {
+    if time_range > 0 {
+        let range = time_range.min(i64::MAX as u64) as i64;
+        let offset = if delay_only { rng.i64(0..=range) } else { rng.i64(-range..=range) };
+        note.start = (note.start as i64 + offset).max(0) as u64;
+    }
+    if velocity_range > 0 {
+        let range = i16::from(velocity_range);
+        note.velocity = (i16::from(note.velocity) + rng.i16(-range..=range)).clamp(1, 127) as u8;
+    }
+}
+
+/// 导入时结束一个音符；零时长的音符被丢弃并记录警告，返回是否加入了音符
+fn close_note(
+    notes: &mut Vec<Note>,
+    start: u64,
+    end: u64,
+    key: u8,
+    velocity: u8,
+    warnings: &mut Vec<ImportWarning>,
+) -> bool {
+    if end <= start {
+        warnings.push(ImportWarning::ZeroDurationNote { tick: start, key });
+        return false;
+    }
+    notes.push(Note::new(start, end - start, key, velocity));
+    true
+}
+
+/// 不支持的元事件在导入警告中的名称
+fn meta_kind_name(meta: &MetaMessage) -> &'static str {
+    match meta {
+        MetaMessage::TrackNumber(_) => "Sequence Number",
+        MetaMessage::Text(_) => "Text",
+        MetaMessage::Copyright(_) => "Copyright",
+        MetaMessage::TrackName(_) => "Track Name",
+        MetaMessage::InstrumentName(_) => "Instrument Name",
+        MetaMessage::Lyric(_) => "Lyric",
+        MetaMessage::Marker(_) => "Marker",
+        MetaMessage::CuePoint(_) => "Cue Point",
+        MetaMessage::ProgramName(_) => "Program Name",
+        MetaMessage::DeviceName(_) => "Device Name",
+        MetaMessage::MidiChannel(_) => "MIDI Channel Prefix",
+        MetaMessage::MidiPort(_) => "MIDI Port",
+        MetaMessage::EndOfTrack => "End of Track",
+        MetaMessage::Tempo(_) => "Tempo",
+        MetaMessage::SmpteOffset(_) => "SMPTE Offset",
+        MetaMessage::TimeSignature(..) => "Time Signature",
+        MetaMessage::KeySignature(..) => "Key Signature",
+        MetaMessage::SequencerSpecific(_) => "Sequencer Specific",
+        MetaMessage::Unknown(..) => "Unknown",
+    }
+}
+
+/// 分组编码：每个分组依次为名称长度(u8)、UTF-8 名称、RGB、成员数(u16 LE)，
+/// 成员以 (起点 u64 LE, 音高 u8) 标识，导入时按此匹配重新生成的音符。
+/// 名称超过 255 字节时在字符边界处截断。
+fn encode_note_groups(groups: &[NoteGroup], notes: &[Note]) -> Option<Vec<u8>> {
+    if groups.is_empty() {
+        return None;
+    }
+    let by_id: HashMap<NoteId, &Note> = notes.iter().map(|n| (n.id, n)).collect();
+    let mut out = Vec::new();
+    for group in groups {
+        let mut name_len = group.name.len().min(u8::MAX as usize);
+        while !group.name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+        let name = &group.name.as_bytes()[..name_len];
+        let members: Vec<&Note> = group
+            .note_ids
+            .iter()
+            .filter_map(|id| by_id.get(id).copied())
+            .take(u16::MAX as usize)
+            .collect();
+        out.push(name.len() as u8);
+        out.extend_from_slice(name);
+        out.extend_from_slice(&group.color);
+        out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        for note in members {
+            out.extend_from_slice(&note.start.to_le_bytes());
+            out.push(note.key);
+        }
+    }
+    Some(out)
+}
+
+fn decode_note_groups(bytes: &[u8], notes: &[Note]) -> Vec<NoteGroup> {
+    // 同一起点与音高可能有多个音符：按音符顺序排列，同一分组内依次取用
+    let mut by_position: HashMap<(u64, u8), Vec<NoteId>> = HashMap::new();
+    for note in notes {
+        by_position.entry((note.start, note.key)).or_default().push(note.id);
+    }
+    let mut groups = Vec::new();
+    let mut rest = bytes;
+    let mut take = |len: usize| -> Option<&[u8]> {
+        if rest.len() < len {
+            return None;
+        }
+        let (head, tail) = rest.split_at(len);
+        rest = tail;
+        Some(head)
+    };
+    while let Some(len) = take(1) {
+        let Some(name) = take(len[0] as usize) else { break };
+        let name = String::from_utf8_lossy(name).into_owned();
+        let Some(color) = take(3) else { break };
+        let color = [color[0], color[1], color[2]];
+        let Some(count) = take(2) else { break };
+        let count = u16::from_le_bytes([count[0], count[1]]);
+        let mut note_ids = Vec::new();
+        let mut used: HashMap<(u64, u8), usize> = HashMap::new();
+        for _ in 0..count {
+            let Some(member) = take(9) else { break };
+            let position = (u64::from_le_bytes(member[..8].try_into().unwrap_or_default()), member[8]);
+            let next = used.entry(position).or_default();
+            if let Some(id) = by_position.get(&position).and_then(|ids| ids.get(*next)) {
+                note_ids.push(*id);
+                *next += 1;
+            }
+        }
+        if !note_ids.is_empty() {
+            groups.push(NoteGroup {
+                id: NoteGroupId::next(),
+                name,
+                note_ids,
+                color,
+            });
+        }
+    }
+    groups
+}
+
+/// 按 tick 排序（同一 tick 保持原有顺序）后以增量时间追加到轨道末尾，
+/// 轨道中已有的事件视为都在 tick 0
+fn append_timed_events<'a>(track: &mut Vec<midly::TrackEvent<'a>>, mut events: Vec<(u64, TrackEventKind<'a>)>) {
+    events.sort_by_key(|(t, _)| *t);
+    let mut last_tick = 0;
+    for (tick, kind) in events {
+        let delta = tick.saturating_sub(last_tick);
+        last_tick = tick;
+        let delta_ticks = u32::try_from(delta).unwrap_or(u32::MAX);
+        track.push(midly::TrackEvent {
+            delta: delta_ticks.into(),
+            kind,
+        });
+    }
+}
+
+/// 导入时每处理多少个事件报告一次进度
+const IMPORT_PROGRESS_STEP: usize = 4096;
+
+/// SMF 速度事件（每四分音符的微秒数）换算为 BPM
+fn micros_to_bpm(micros_per_quarter: u32) -> f32 {
+    (60_000_000.0 / micros_per_quarter as f64) as f32
+}
+
+/// BPM 换算为 SMF 速度事件；四舍五入到整数微秒，导入的速度再次导出时与原文件一致
+fn bpm_to_micros(bpm: f32) -> midly::num::u24 {
+    let micros = (60_000_000.0 / bpm.max(1.0) as f64).round() as u32;
+    midly::num::u24::from(micros.min(0xFF_FFFF))
+}
 
 pub fn load_single_track(bytes: &[u8]) -> Result<MidiState, midly::Error> {
+    load_single_track_with_options(bytes, SmfImportOptions::default())
+}
+
+pub fn load_single_track_with_options(
+    bytes: &[u8],
+    options: SmfImportOptions,
+) -> Result<MidiState, midly::Error> {
     let smf = Smf::parse(bytes)?;
-    Ok(MidiState::from_smf(&smf))
+    Ok(MidiState::from_smf_with_options(&smf, options))
 }
 
 pub fn export_single_track(state: &MidiState) -> Vec<u8> {
-    let smf = state.to_smf();
+    let arena = midly::Arena::new();
+    let smf = state.to_smf_in(&arena);
     let mut out = Vec::new();
     smf.write_std(&mut out).expect("Writing SMF failed");
     out
@@ -659,10 +2891,351 @@ mod tests {
         let err = MidiState::from_smf_strict(&smf).unwrap_err();
         assert!(matches!(
             err,
-            MidiValidationError::MultipleTracks { tracks: 2 }
+            ValidationIssue::MultipleTracks { tracks: 2 }
         ));
     }
 
+    #[test]
+    fn multi_track_files_keep_their_tracks_through_export() {
+        let mut bass = build_simple_note_track(1, 40);
+        bass.insert(
+            0,
+            TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(MetaMessage::TrackName(b"Bass")),
+            },
+        );
+        let conductor = vec![TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        }];
+        let smf = smf_with_tracks(vec![
+            conductor,
+            build_simple_note_track(0, 60),
+            bass,
+            build_simple_note_track(2, 67),
+            build_simple_note_track(3, 72),
+        ]);
+        let mut state = MidiState::from_smf(&smf);
+        assert_eq!(state.tracks.len(), 4);
+        assert_eq!(
+            state.tracks[1],
+            MidiTrack { name: Some("Bass".to_string()), channel: 1, program: None }
+        );
+        assert_eq!(state.tracks[3].display_name(3), "Track 4");
+        let mut tagged: Vec<(u16, u8)> = state.notes.iter().map(|n| (n.track, n.key)).collect();
+        tagged.sort();
+        assert_eq!(tagged, vec![(0, 60), (1, 40), (2, 67), (3, 72)]);
+
+        // 只编辑第三条轨道，导出后各轨道保持分开
+        state.notes.push(Note { track: 2, ..Note::new(240, 120, 69, 90) });
+        let arena = midly::Arena::new();
+        let exported = state.to_smf_in(&arena);
+        assert_eq!(exported.header.format, Format::Parallel);
+        assert_eq!(exported.tracks.len(), 5);
+
+        let loaded = MidiState::from_smf(&exported);
+        assert_eq!(loaded.tracks, state.tracks);
+        let keys_of = |state: &MidiState, track: u16| {
+            let mut keys: Vec<u8> = state.notes.iter().filter(|n| n.track == track).map(|n| n.key).collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys_of(&loaded, 2), vec![67, 69]);
+        for track in 0..4 {
+            assert_eq!(keys_of(&loaded, track), keys_of(&state, track));
+        }
+        assert!(loaded.to_single_track_smf().is_err());
+    }
+
+    fn tempo_events(smf: &Smf) -> Vec<(u64, u32)> {
+        let mut events = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(value)) = event.kind {
+                    events.push((tick, value.as_int()));
+                }
+            }
+        }
+        events
+    }
+
+    fn controller_events(smf: &Smf, cc: u8) -> Vec<(u64, u8)> {
+        let mut events = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Midi { message: midly::MidiMessage::Controller { controller, value }, .. } = event.kind {
+                    if controller.as_int() == cc {
+                        events.push((tick, value.as_int()));
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn cc_lanes_round_trip_through_smf() {
+        let mut state = MidiState { notes: vec![Note::new(0, 480, 60, 100)], ..MidiState::default() };
+        let lane_id = state.add_cc_lane(1);
+        assert_eq!(state.add_cc_lane(1), lane_id);
+        let lane = state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        lane.insert_point(0, 0.0);
+        lane.insert_point(960, 127.0);
+        // 同一 tick 的两个点形成跳变，且曲线延伸到最后一个音符之后
+        lane.insert_point(1920, 127.0);
+        lane.insert_point(1920, 20.0);
+        assert_eq!(lane.value_at(1919), Some(127.0));
+        assert_eq!(lane.value_at(1920), Some(20.0));
+        assert_eq!(lane.value_at(5000), Some(20.0));
+
+        let exported = state.to_smf();
+        let events = controller_events(&exported, 1);
+        assert_eq!(events.first(), Some(&(0, 0)));
+        assert!(events.contains(&(960, 127)));
+        assert_eq!(events.last(), Some(&(1920, 20)));
+        assert!(events.windows(2).all(|pair| pair[0].0 < pair[1].0 && pair[0].1 != pair[1].1));
+
+        // 重新导入后曲线形状不变，再次导出得到相同的事件
+        let loaded = MidiState::from_smf(&exported);
+        let lane = loaded.curves.iter().find(|lane| lane.lane_type == CurveLaneType::ControlChange(1)).unwrap();
+        for tick in [0, 480, 959, 960, 1500, 1919, 1920, 4000] {
+            let original = state.curves.iter().find(|lane| lane.id == lane_id).unwrap().value_at(tick).unwrap();
+            assert!((lane.value_at(tick).unwrap() - original).abs() <= 4.0, "tick {tick}");
+        }
+        assert_eq!(lane.value_at(1919), Some(127.0));
+        assert_eq!(controller_events(&loaded.to_smf(), 1), events);
+    }
+
+    #[test]
+    fn sustain_lanes_are_stepped_switches_in_smf() {
+        let mut state = MidiState { notes: vec![Note::new(0, 480, 60, 100)], ..MidiState::default() };
+        let lane_id = state.add_cc_lane(64);
+        let lane = state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        assert_eq!(lane.interpolation, CurveInterpolation::Step);
+        lane.insert_point(0, 127.0);
+        lane.insert_point(960, 0.0);
+        lane.insert_point(1440, 100.0);
+        assert_eq!(lane.value_at(959), Some(127.0));
+        assert!(state.sustain_at(500));
+        assert!(!state.sustain_at(1000));
+        assert!(state.sustain_at(5000));
+
+        let exported = state.to_smf();
+        assert_eq!(controller_events(&exported, 64), vec![(0, 127), (960, 0), (1440, 127)]);
+        let loaded = MidiState::from_smf(&exported);
+        let lane = loaded.curves.iter().find(|lane| lane.lane_type == CurveLaneType::ControlChange(64)).unwrap();
+        assert_eq!(lane.interpolation, CurveInterpolation::Step);
+        assert_eq!(lane.points.len(), 3);
+        assert_eq!(controller_events(&loaded.to_smf(), 64), controller_events(&exported, 64));
+    }
+
+    #[test]
+    fn per_point_interpolation_shapes_each_segment() {
+        let mut lane = CurveLane::new(CurveLaneType::ControlChange(1));
+        let first = lane.insert_point(960, 0.0).id;
+        let second = lane.insert_point(1920, 100.0).id;
+        lane.insert_point(2880, 0.0);
+        assert_eq!(lane.value_at(0), Some(0.0));
+        assert_eq!(lane.value_at(1440), Some(50.0));
+        assert_eq!(lane.value_at(5000), Some(0.0));
+
+        assert_eq!(lane.set_point_interpolation(first, Some(CurveInterpolation::Step)), Some(None));
+        assert_eq!(lane.set_point_interpolation(second, Some(CurveInterpolation::Smooth)), Some(None));
+        assert_eq!(lane.value_at(1919), Some(0.0));
+        assert_eq!(lane.value_at(1920), Some(100.0));
+        // 平滑段在中点与线性相同，靠近两端时比线性更平缓
+        assert_eq!(lane.value_at(2400), Some(50.0));
+        assert!(lane.value_at(2040).unwrap() > 75.0 + 10.0);
+        assert!(lane.value_at(2760).unwrap() < 25.0 - 10.0);
+
+        let events = lane.control_change_events(120);
+        assert!(!events.iter().any(|(tick, _)| (961..1920).contains(tick)));
+        assert!(events.iter().filter(|(tick, _)| (1921..2880).contains(tick)).count() > 4);
+    }
+
+    #[test]
+    fn thinning_keeps_the_curve_shape() {
+        let mut lane = CurveLane::new(CurveLaneType::ControlChange(1));
+        for (tick, value) in [(0, 0.0), (120, 10.0), (240, 20.0), (360, 30.0), (480, 30.0), (600, 30.0), (720, 0.0)] {
+            lane.insert_point(tick, value);
+        }
+        lane.insert_point(720, 90.0);
+        let before: Vec<f32> = (0..=800).step_by(40).map(|tick| lane.value_at(tick).unwrap()).collect();
+
+        let removed = lane.thin_points(0, 720);
+        assert_eq!(removed.len(), 3);
+        let ticks: Vec<u64> = lane.points.iter().map(|p| p.tick).collect();
+        assert_eq!(ticks, vec![0, 360, 600, 720, 720]);
+        for (tick, expected) in (0..=800).step_by(40).zip(before) {
+            assert!((lane.value_at(tick).unwrap() - expected).abs() < 1e-3, "tick {tick}");
+        }
+    }
+
+    fn pitch_bend_events(smf: &Smf) -> Vec<(u64, i16)> {
+        let mut events = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Midi { message: midly::MidiMessage::PitchBend { bend }, .. } = event.kind {
+                    events.push((tick, bend.as_int()));
+                }
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn pitch_bend_glissando_survives_a_save_load_cycle() {
+        let mut state = MidiState { notes: vec![Note::new(0, 1920, 60, 100)], ..MidiState::default() };
+        let lane_id = state.add_curve_lane(CurveLaneType::PitchBend);
+        let lane = state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        lane.insert_point(0, -9000.0);
+        lane.insert_point(960, 8191.0);
+        lane.insert_point(1920, 0.0);
+        assert_eq!(lane.value_at(0), Some(-8192.0));
+
+        let mut bytes = Vec::new();
+        state.to_smf().write_std(&mut bytes).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        let events = pitch_bend_events(&smf);
+        assert_eq!(events.first(), Some(&(0, -8192)));
+        assert!(events.contains(&(960, 8191)));
+        assert_eq!(events.last(), Some(&(1920, 0)));
+        assert!(events.len() > 32);
+
+        let loaded = MidiState::from_smf(&smf);
+        let lane = loaded.curves.iter().find(|lane| lane.lane_type == CurveLaneType::PitchBend).unwrap();
+        let original = state.curves.iter().find(|lane| lane.id == lane_id).unwrap();
+        for tick in [0, 240, 480, 960, 1200, 1920, 3000] {
+            assert!((lane.value_at(tick).unwrap() - original.value_at(tick).unwrap()).abs() <= 2.0, "tick {tick}");
+        }
+        assert_eq!(pitch_bend_events(&loaded.to_smf()), events);
+    }
+
+    #[test]
+    fn tempo_changes_round_trip_and_drive_time_conversion() {
+        let tempo = |delta: u32, micros: u32| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(micros.into())),
+        };
+        // 120 → 140 → 96 → 173.3 BPM（最后一个无法整除，检验微秒取整）
+        let conductor = vec![
+            tempo(0, 500_000),
+            tempo(1920, 428_571),
+            tempo(1920, 625_000),
+            tempo(960, 346_220),
+            TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) },
+        ];
+        let original = smf_with_tracks(vec![conductor, build_simple_note_track(0, 60)]);
+        let state = MidiState::from_smf(&original);
+        assert_eq!(state.bpm, 120.0);
+        let ticks: Vec<u64> = state.tempo_changes.iter().map(|c| c.tick).collect();
+        assert_eq!(ticks, vec![1920, 3840, 4800]);
+        assert_eq!(state.tempo_at(3839), state.tempo_changes[0].bpm);
+
+        // 单轨（format 0）与多轨（format 1）导出都保留全部速度事件
+        assert_eq!(tempo_events(&state.to_smf()), tempo_events(&original));
+        let mut multi = state.clone();
+        multi.tracks = vec![MidiTrack::default(), MidiTrack { channel: 1, ..MidiTrack::default() }];
+        assert_eq!(tempo_events(&multi.to_smf()), tempo_events(&original));
+        assert_eq!(MidiState::from_smf(&state.to_smf()).tempo_changes, state.tempo_changes);
+
+        // 4 拍 120 BPM（2 秒）之后进入 140 BPM
+        assert!((state.ticks_to_seconds(1920.0) - 2.0).abs() < 1e-9);
+        let seconds = state.ticks_to_seconds(2400.0);
+        assert!((seconds - (2.0 + 0.428_571)).abs() < 1e-6);
+        assert!((state.seconds_to_ticks(seconds) - 2400.0).abs() < 1e-6);
+        assert!((state.seconds_to_ticks(state.ticks_to_seconds(6000.0)) - 6000.0).abs() < 1e-6);
+
+        let mut edited = state.clone();
+        edited.set_tempo_at(2000, 150.0);
+        assert_eq!(edited.tempo_changes[0].bpm, 150.0);
+        edited.set_tempo_at(100, 90.0);
+        assert_eq!(edited.bpm, 90.0);
+        assert_eq!(edited.tempo_changes.len(), 3);
+    }
+
+    #[test]
+    fn note_groups_round_trip_through_smf() {
+        let mut state = MidiState::default();
+        let a = Note::new(0, 240, 60, 100);
+        let b = Note::new(240, 240, 64, 100);
+        let c = Note::new(480, 240, 67, 100);
+        state.notes = vec![a, b, c];
+        state.create_group("Riff", &[a.id, c.id]);
+
+        let bytes = export_single_track(&state);
+        let loaded = load_single_track(&bytes).unwrap();
+        assert_eq!(loaded.groups.len(), 1);
+        assert_eq!(loaded.groups[0].name, "Riff");
+        let members: Vec<(u64, u8)> = loaded.groups[0]
+            .note_ids
+            .iter()
+            .filter_map(|id| loaded.notes.iter().find(|n| n.id == *id))
+            .map(|n| (n.start, n.key))
+            .collect();
+        assert_eq!(members, vec![(0, 60), (480, 67)]);
+    }
+
+    #[test]
+    fn long_group_names_are_truncated_on_a_char_boundary() {
+        let mut state = MidiState::default();
+        let a = Note::new(0, 240, 60, 100);
+        let b = Note::new(240, 240, 64, 100);
+        state.notes = vec![a, b];
+        // 85 个三字节字符共 255 字节，前面多一个字节后第 85 个字符跨过上限
+        let name = format!("x{}", "乐".repeat(100));
+        state.create_group(&name, &[a.id, b.id]);
+
+        let loaded = load_single_track(&export_single_track(&state)).unwrap();
+        assert_eq!(loaded.groups[0].name, format!("x{}", "乐".repeat(84)));
+        assert_eq!(loaded.groups[0].note_ids.len(), 2);
+    }
+
+    #[test]
+    fn note_labels_round_trip_as_text_events_when_enabled() {
+        let state = MidiState {
+            notes: vec![
+                Note { label: SmallString::non_empty("pizz."), ..Note::new(0, 240, 64, 100) },
+                Note { label: SmallString::non_empty("stacc."), ..Note::new(0, 240, 60, 100) },
+                Note::new(240, 240, 62, 100),
+                Note { label: SmallString::non_empty(" ten. "), ..Note::new(480, 240, 67, 100) },
+            ],
+            ..MidiState::default()
+        };
+
+        let bytes = export_single_track(&state);
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(MidiState::from_smf(&smf).notes.iter().filter(|n| n.label.is_some()).count(), 0);
+
+        let loaded = MidiState::from_smf_with_options(&smf, SmfImportOptions { text_events_as_labels: true });
+        let mut labels: Vec<(u64, u8, Option<String>)> = loaded
+            .notes
+            .iter()
+            .map(|n| (n.start, n.key, n.label.map(|l| l.to_string())))
+            .collect();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                (0, 60, Some("stacc.".to_string())),
+                (0, 64, Some("pizz.".to_string())),
+                (240, 62, None),
+                (480, 67, Some("ten.".to_string())),
+            ]
+        );
+
+        let long = SmallString::new("sul ponticello con sordino");
+        assert_eq!(long.len(), SmallString::CAPACITY);
+        assert_eq!(SmallString::new("ééééééééééééé").len(), 22);
+    }
+
     #[test]
     fn strict_import_rejects_mixed_channels() {
         let mut track = build_simple_note_track(0, 60);
@@ -681,6 +3254,457 @@ mod tests {
         );
         let smf = smf_with_tracks(vec![track]);
         let err = MidiState::from_smf_strict(&smf).unwrap_err();
-        assert_eq!(err, MidiValidationError::MixedChannels);
+        assert_eq!(err, ValidationIssue::MixedChannels);
+    }
+
+    #[test]
+    fn lenient_import_recovers_notes_and_reports_what_was_patched() {
+        let event = |delta: u32, kind: TrackEventKind<'static>| TrackEvent { delta: delta.into(), kind };
+        let midi = |channel: u8, message: MidiMessage| TrackEventKind::Midi { channel: channel.into(), message };
+        let on = |key: u8| MidiMessage::NoteOn { key: key.into(), vel: 100.into() };
+        let off = |key: u8| MidiMessage::NoteOff { key: key.into(), vel: 0.into() };
+        let track = vec![
+            event(0, TrackEventKind::Meta(MetaMessage::CuePoint(b"cue"))),
+            event(0, midi(0, on(60))),
+            event(0, midi(0, on(72))),
+            event(0, midi(0, off(72))),
+            event(240, midi(0, on(60))),
+            event(240, midi(0, off(60))),
+            event(0, midi(0, off(64))),
+            event(0, midi(1, on(67))),
+            event(0, TrackEventKind::Meta(MetaMessage::CuePoint(b"cue"))),
+            event(480, midi(0, MidiMessage::ChannelAftertouch { vel: 40.into() })),
+            event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ];
+        let smf = smf_with_tracks(vec![track]);
+        assert_eq!(MidiState::from_smf_strict(&smf).unwrap_err(), ValidationIssue::MixedChannels);
+
+        let (state, warnings) = MidiState::from_smf_lenient(&smf);
+        let notes: Vec<_> = state.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+        assert_eq!(notes, vec![(0, 240, 60), (240, 240, 60), (480, 480, 67)]);
+        assert_eq!(
+            warnings,
+            vec![
+                ImportWarning::ZeroDurationNote { tick: 0, key: 72 },
+                ImportWarning::OverlappingNote { tick: 240, key: 60 },
+                ImportWarning::OrphanNoteOff { tick: 480, key: 64 },
+                ImportWarning::OrphanNoteOn { tick: 480, key: 67 },
+                ImportWarning::UnsupportedMeta { kind: "Cue Point", count: 2 },
+                ImportWarning::UnsupportedEvent { kind: "Channel Aftertouch", count: 1 },
+                ImportWarning::NotSingleTrack(ValidationIssue::MixedChannels),
+            ]
+        );
+        assert_eq!(
+            warnings[3].to_string(),
+            "tick 480 处的音符 67 没有松开，已延续到轨道末尾"
+        );
+    }
+
+    #[test]
+    fn curve_clip_pastes_with_overwrite_and_rescales_across_lanes() {
+        let mut velocity = CurveLane::new(CurveLaneType::Velocity);
+        velocity.insert_point(100, 0.0);
+        velocity.insert_point(200, 127.0);
+        velocity.insert_point(900, 64.0);
+        let clip = velocity.copy_range(100, 300).unwrap();
+        assert_eq!(clip.span, 200);
+        assert_eq!(clip.points, vec![(0, 0.0), (100, 127.0)]);
+
+        let (removed, added) = velocity.paste_clip(&clip, 850, true);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(added.len(), 2);
+        assert_eq!(velocity.points.iter().map(|p| p.tick).collect::<Vec<_>>(), vec![100, 200, 850, 950]);
+
+        let mut pitch = CurveLane::new(CurveLaneType::Pitch);
+        pitch.paste_clip(&clip, 0, false);
+        assert_eq!(pitch.points.iter().map(|p| p.value).collect::<Vec<_>>(), vec![-12.0, 12.0]);
+    }
+
+    #[test]
+    fn arpeggiate_walks_patterns_and_resolves_overlaps() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let keys = |notes: &[Note]| notes.iter().map(|n| n.key).collect::<Vec<_>>();
+        // 重复的 E 只保留较长的那个，其力度沿用到琶音
+        let chord = [
+            Note::new(0, 960, 60, 100),
+            Note::new(0, 480, 64, 70),
+            Note::new(0, 960, 64, 90),
+            Note::new(0, 960, 67, 100),
+        ];
+        let up = arpeggiate(&chord, ArpPattern::Up, 240, 1, 0.5, &mut rng);
+        assert_eq!(keys(&up), vec![60, 64, 67, 60]);
+        assert_eq!(up.iter().map(|n| (n.start, n.duration)).collect::<Vec<_>>(), vec![(0, 120), (240, 120), (480, 120), (720, 120)]);
+        assert_eq!(up[1].velocity, 90);
+
+        let down = arpeggiate(&chord, ArpPattern::Down, 120, 2, 1.0, &mut rng);
+        assert_eq!(keys(&down), vec![79, 76, 72, 67, 64, 60, 79, 76]);
+        let up_down = arpeggiate(&chord, ArpPattern::UpDown, 120, 1, 1.0, &mut rng);
+        assert_eq!(keys(&up_down), vec![60, 64, 67, 64, 60, 64, 67, 64]);
+        let random = arpeggiate(&chord, ArpPattern::Random, 120, 1, 1.0, &mut rng);
+        assert!(random.iter().all(|n| [60, 64, 67].contains(&n.key)));
+
+        // 和弦在下一个和弦的起点截止，末步被截短；单个音符变成重复
+        let overlapping = [Note::new(0, 960, 60, 100), Note::new(600, 300, 72, 100)];
+        let notes = arpeggiate(&overlapping, ArpPattern::Up, 240, 1, 1.0, &mut rng);
+        assert_eq!(
+            notes.iter().map(|n| (n.start, n.duration, n.key)).collect::<Vec<_>>(),
+            vec![(0, 240, 60), (240, 240, 60), (480, 120, 60), (600, 240, 72), (840, 60, 72)]
+        );
+    }
+
+    #[test]
+    fn overlaps_are_found_per_pitch_and_resolved_by_policy() {
+        let a = Note::new(0, 480, 60, 80);
+        let b = Note::new(240, 480, 60, 100);
+        let c = Note::new(600, 120, 60, 90);
+        let dup = Note::new(960, 120, 62, 90);
+        let dup_long = Note::new(960, 240, 62, 90);
+        let other_pitch = Note::new(100, 480, 64, 90);
+        let state = MidiState {
+            notes: vec![a, other_pitch, b, c, dup, dup_long],
+            ..MidiState::default()
+        };
+        assert_eq!(state.find_overlaps(), vec![(a.id, b.id), (b.id, c.id), (dup_long.id, dup.id)]);
+
+        let mut trimmed = state.clone();
+        let (updated, removed) = trimmed.resolve_overlaps(None, OverlapPolicy::TrimEarlier);
+        assert_eq!(updated.iter().map(|(_, after)| (after.id, after.duration)).collect::<Vec<_>>(), vec![(a.id, 240), (b.id, 360)]);
+        assert_eq!(removed, vec![dup]);
+        assert!(trimmed.find_overlaps().is_empty());
+
+        let mut shorter = state.clone();
+        let (updated, removed) = shorter.resolve_overlaps(None, OverlapPolicy::DeleteShorter);
+        assert!(updated.is_empty());
+        // 等长时删除较晚的 b，之后 c 不再与 a 重叠
+        assert_eq!(removed.iter().map(|n| n.id).collect::<Vec<_>>(), vec![b.id, dup.id]);
+
+        let mut merged = state.clone();
+        let (updated, removed) = merged.resolve_overlaps(None, OverlapPolicy::MergeIntoOne);
+        assert_eq!(updated.len(), 1);
+        assert_eq!((updated[0].1.start, updated[0].1.duration, updated[0].1.velocity), (0, 720, 100));
+        assert_eq!(removed.len(), 3);
+
+        // 限定范围：只处理范围内的音符
+        let mut scoped = state.clone();
+        let (updated, removed) = scoped.resolve_overlaps(Some(&BTreeSet::from([b.id, c.id])), OverlapPolicy::TrimEarlier);
+        assert_eq!(updated.iter().map(|(before, _)| before.id).collect::<Vec<_>>(), vec![b.id]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn query_notes_combines_the_enabled_criteria() {
+        let ghost = Note::new(0, 120, 38, 30);
+        let accent = Note::new(120, 120, 38, 120);
+        let long_ghost = Note::new(240, 960, 60, 35);
+        let state = MidiState { notes: vec![ghost, accent, long_ghost], ..MidiState::default() };
+
+        assert_eq!(state.query_notes(&NoteFilter::default()).len(), 3);
+        let quiet = NoteFilter { velocity: Some(1..=39), ..NoteFilter::default() };
+        assert_eq!(state.query_notes(&quiet), vec![ghost.id, long_ghost.id]);
+        let quiet_short = NoteFilter { duration: Some(1..=480), ..quiet.clone() };
+        assert_eq!(state.query_notes(&quiet_short), vec![ghost.id]);
+        let quiet_high = NoteFilter { key: Some(48..=127), ..quiet };
+        assert_eq!(state.query_notes(&quiet_high), vec![long_ghost.id]);
+    }
+
+    #[test]
+    fn batch_transforms_clamp_velocity_and_start_at_the_boundaries() {
+        let soft = Note::new(0, 120, 60, 1);
+        let mid = Note::new(100, 120, 62, 80);
+        let loud = Note::new(480, 120, 64, 127);
+        let state = MidiState { notes: vec![soft, mid, loud], ..MidiState::default() };
+        let ids = [soft.id, mid.id, loud.id];
+        let transformed = |transform_type, value| {
+            let mut state = state.clone();
+            state.batch_transform_notes(&ids, transform_type, value);
+            state.notes.iter().map(|n| (n.start, n.velocity)).collect::<Vec<_>>()
+        };
+
+        let velocities = |notes: Vec<(u64, u8)>| notes.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+        assert_eq!(velocities(transformed(BatchTransformType::SetVelocity, 0.0)), vec![1, 1, 1]);
+        assert_eq!(velocities(transformed(BatchTransformType::SetVelocity, 200.0)), vec![127, 127, 127]);
+        assert_eq!(velocities(transformed(BatchTransformType::VelocityOffset, -127.0)), vec![1, 1, 1]);
+
+        // 到中心的距离乘以系数：0 全部压到中心，2 扩展后两端被截断
+        let compress = |center, factor| velocities(transformed(BatchTransformType::CompressVelocity { center }, factor));
+        assert_eq!(compress(64, 0.5), vec![33, 72, 96]);
+        assert_eq!(compress(64, 0.0), vec![64, 64, 64]);
+        assert_eq!(compress(64, 2.0), vec![1, 96, 127]);
+        assert_eq!(compress(64, 1.0), vec![1, 80, 127]);
+
+        // 起点不早于 0，移动后重新按起点排序
+        assert_eq!(transformed(BatchTransformType::StartOffset, -200.0), vec![(0, 1), (0, 80), (280, 127)]);
+        assert_eq!(transformed(BatchTransformType::StartOffset, 30.0), vec![(30, 1), (130, 80), (510, 127)]);
+    }
+
+    #[test]
+    fn slice_clips_notes_and_curves_to_the_range_and_rebases_it() {
+        let before = Note::new(0, 480, 60, 100);
+        let crossing_in = Note::new(1600, 640, 62, 100);
+        let inside = Note::new(2400, 240, 64, 100);
+        let crossing_out = Note::new(3600, 480, 65, 100);
+        let after = Note::new(3840, 480, 67, 100);
+        let mut state = MidiState {
+            notes: vec![before, crossing_in, inside, crossing_out, after],
+            tempo_changes: vec![TempoChange { tick: 960, bpm: 90.0 }, TempoChange { tick: 2880, bpm: 150.0 }],
+            ..MidiState::default()
+        };
+        let lane = state.add_cc_lane(1);
+        let lane = state.curves.iter_mut().find(|l| l.id == lane).unwrap();
+        lane.insert_point(0, 0.0);
+        lane.insert_point(3840, 100.0);
+        lane.insert_point(2880, 50.0);
+
+        let slice = state.slice(1920..3840);
+        let spans: Vec<(u64, u64, u8)> = slice.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+        assert_eq!(spans, vec![(0, 320, 62), (480, 240, 64), (1680, 240, 65)]);
+        assert_eq!(slice.bpm, 90.0);
+        assert_eq!(slice.tempo_changes, vec![TempoChange { tick: 960, bpm: 150.0 }]);
+
+        // 起点补上插值得到的值，范围外的点被丢弃
+        let cc = slice.curves.iter().find(|l| l.lane_type == CurveLaneType::ControlChange(1)).unwrap();
+        let points: Vec<(u64, f32)> = cc.points.iter().map(|p| (p.tick, p.value)).collect();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1], (960, 50.0));
+        assert_eq!(points[0].0, 0);
+        assert_eq!(Some(points[0].1), state.curves.iter().find(|l| l.id == cc.id).unwrap().value_at(1920));
+    }
+
+    #[test]
+    fn musical_time_follows_the_meter_and_parses_back() {
+        let mut state = MidiState::default();
+        let at = |state: &MidiState, tick| state.musical_time(tick).to_string();
+        assert_eq!(at(&state, 0), "1.1.000");
+        assert_eq!(at(&state, 2400), "2.2.000");
+        assert_eq!(at(&state, 8280), "5.2.120");
+        state.time_signature = (3, 4);
+        assert_eq!(at(&state, 1500), "2.1.060");
+        // 6/8 的拍为八分音符
+        state.time_signature = (6, 8);
+        assert_eq!(at(&state, 1680), "2.2.000");
+
+        for tick in [0, 1680, 2039, 123_456] {
+            assert_eq!(state.parse_musical_time(&at(&state, tick)), Some(tick));
+        }
+        state.time_signature = (4, 4);
+        assert_eq!(state.parse_musical_time("5.2.0"), Some(7680 + 480));
+        assert_eq!(state.parse_musical_time("3:2"), Some(4320));
+        assert_eq!(state.parse_musical_time(" 2. "), None);
+        // 不含分隔符的文本留给调用方按 tick 解析
+        assert_eq!(state.parse_musical_time("960"), None);
+        assert_eq!(state.parse_musical_time("0.1.000"), None);
+        assert_eq!(state.parse_musical_time("1.2.3.4"), None);
+    }
+
+    #[test]
+    fn rescaling_ticks_per_beat_keeps_musical_positions_and_round_trips() {
+        let mut state = MidiState {
+            notes: vec![Note::new(0, 480, 60, 100), Note::new(480, 240, 62, 90), Note::new(721, 119, 64, 80)],
+            tempo_changes: vec![TempoChange { tick: 1920, bpm: 90.0 }],
+            ..MidiState::default()
+        };
+        state.curves[0].insert_point(241, 0.5);
+        let original = state.clone();
+
+        state.rescale_ticks_per_beat(960);
+        assert_eq!(state.ticks_per_beat, 960);
+        let notes: Vec<(u64, u64)> = state.notes.iter().map(|n| (n.start, n.duration)).collect();
+        assert_eq!(notes, vec![(0, 960), (960, 480), (1442, 238)]);
+        assert_eq!(state.tempo_changes[0].tick, 3840);
+        assert!(state.curves[0].points.iter().any(|p| p.tick == 482));
+
+        state.rescale_ticks_per_beat(480);
+        assert_eq!(state.notes, original.notes);
+        assert_eq!(state.curves[0].points, original.curves[0].points);
+        assert_eq!(state.tempo_changes, original.tempo_changes);
+
+        // 大幅缩小时时值至少 1 tick，落到 tick 0 的速度变化成为起始速度
+        state.tempo_changes = vec![TempoChange { tick: 1, bpm: 100.0 }, TempoChange { tick: 960, bpm: 80.0 }];
+        state.notes = vec![Note::new(0, 7, 60, 100), Note::new(7, 5, 60, 100), Note::new(12, 1, 60, 100)];
+        state.rescale_ticks_per_beat(24);
+        let notes: Vec<(u64, u64)> = state.notes.iter().map(|n| (n.start, n.duration)).collect();
+        assert_eq!(notes, vec![(0, 1), (0, 1), (1, 1)]);
+        assert_eq!(state.bpm, 100.0);
+        assert_eq!(state.tempo_changes, vec![TempoChange { tick: 48, bpm: 80.0 }]);
+        assert_eq!(rescale_tick(30, 480, 24), 2);
+    }
+
+    #[test]
+    fn key_signature_round_trips_through_smf_and_spells_note_names() {
+        let e_flat_minor = KeySignature::new(-6, true);
+        assert_eq!(e_flat_minor.tonic(), 3);
+        assert_eq!(e_flat_minor.label(), "Eb minor");
+        assert_eq!(KeySignature::new(3, false).label(), "A major");
+        assert_eq!(KeySignature::all().count(), 30);
+
+        let state = MidiState { key_signature: e_flat_minor, notes: vec![Note::new(0, 480, 63, 100)], ..MidiState::default() };
+        let mut bytes = Vec::new();
+        state.to_smf().write_std(&mut bytes).unwrap();
+        let imported = MidiState::from_smf(&Smf::parse(&bytes).unwrap());
+        assert_eq!(imported.key_signature, e_flat_minor);
+
+        // 没有调号元事件的文件为 C 大调，C 大调导出时不写调号
+        let plain = MidiState::default();
+        let mut bytes = Vec::new();
+        plain.to_smf().write_std(&mut bytes).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert!(!smf.tracks[0].iter().any(|e| matches!(e.kind, TrackEventKind::Meta(MetaMessage::KeySignature(..)))));
+        assert_eq!(MidiState::from_smf(&smf).key_signature, KeySignature::default());
+    }
+
+    #[test]
+    fn json_round_trip_keeps_ids_curves_and_tick_resolution() {
+        let mut lane = CurveLane::new(CurveLaneType::ControlChange(11));
+        lane.insert_point(0, 64.0);
+        lane.insert_point(1920, 100.0);
+        let mut labelled = Note::new(960, 240, 64, 90);
+        labelled.label = Some(SmallString::new("stacc."));
+        // 宿主保存的文件可能来自 ID 已经很大的会话
+        labelled.id = NoteId(1 << 40);
+        let notes = vec![Note::new(0, 960, 60, 100), labelled];
+        let state = MidiState {
+            ticks_per_beat: 960,
+            bpm: 98.5,
+            tempo_changes: vec![TempoChange { tick: 3840, bpm: 140.0 }],
+            time_signature: (6, 8),
+            key_signature: KeySignature::new(-3, false),
+            groups: vec![NoteGroup {
+                id: NoteGroupId::next(),
+                name: "Riff".to_string(),
+                note_ids: notes.iter().map(|n| n.id).collect(),
+                color: [10, 20, 30],
+            }],
+            notes,
+            curves: vec![MidiState::default_velocity_lane(), lane],
+            ..MidiState::default()
+        };
+
+        let json = state.to_json().unwrap();
+        assert!(json.starts_with(r#"{"version":1,"state":{"notes":"#));
+        let restored = MidiState::from_json(&json).unwrap();
+        assert_eq!(restored.notes, state.notes);
+        assert_eq!(restored.curves, state.curves);
+        assert_eq!(restored.groups, state.groups);
+        assert_eq!(restored.ticks_per_beat, 960);
+        assert_eq!(restored.bpm, 98.5);
+        assert_eq!(restored.tempo_changes, state.tempo_changes);
+        assert_eq!(restored.time_signature, (6, 8));
+        assert_eq!(restored.key_signature, state.key_signature);
+        assert_eq!(restored.to_json().unwrap(), json);
+
+        // 之后新建的 ID 不会与恢复的 ID 冲突
+        assert!(NoteId::next() > labelled.id);
+
+        assert!(matches!(MidiState::from_json("not json"), Err(MidiJsonError::Parse(_))));
+        let future = json.replacen(r#""version":1"#, r#""version":2"#, 1);
+        assert!(matches!(MidiState::from_json(&future), Err(MidiJsonError::UnsupportedVersion(2))));
+    }
+
+    /// 按轨道列出文本类元事件：(轨道下标, 绝对 tick, 种类, 字节)
+    fn text_meta_of<'a>(smf: &Smf<'a>) -> Vec<(usize, u64, &'static str, &'a [u8])> {
+        let mut found = Vec::new();
+        for (index, track) in smf.tracks.iter().enumerate() {
+            let mut tick = 0;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(meta @ (MetaMessage::Lyric(text) | MetaMessage::Marker(text) | MetaMessage::Text(text))) =
+                    event.kind
+                {
+                    found.push((index, tick, meta_kind_name(&meta), text));
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn text_events_import_and_reexport_byte_for_byte() {
+        let event = |delta: u32, kind: TrackEventKind<'static>| TrackEvent { delta: delta.into(), kind };
+        let midi = |message: MidiMessage| TrackEventKind::Midi { channel: 0.into(), message };
+        let conductor = vec![
+            event(0, TrackEventKind::Meta(MetaMessage::Marker(b"Verse"))),
+            event(1920, TrackEventKind::Meta(MetaMessage::Marker(b"Chorus"))),
+            event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ];
+        let piano = vec![
+            event(0, midi(MidiMessage::NoteOn { key: 48.into(), vel: 100.into() })),
+            event(480, midi(MidiMessage::NoteOff { key: 48.into(), vel: 0.into() })),
+            event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ];
+        // 歌词不一定是 UTF-8（这里是 Shift-JIS 的「あ」）
+        let vocal = vec![
+            event(0, TrackEventKind::Meta(MetaMessage::Lyric(b"\x82\xa0"))),
+            event(0, TrackEventKind::Meta(MetaMessage::Text(b"breath"))),
+            event(0, midi(MidiMessage::NoteOn { key: 64.into(), vel: 100.into() })),
+            event(480, midi(MidiMessage::NoteOff { key: 64.into(), vel: 0.into() })),
+            event(0, TrackEventKind::Meta(MetaMessage::Lyric(b"la"))),
+            event(0, midi(MidiMessage::NoteOn { key: 65.into(), vel: 100.into() })),
+            event(480, midi(MidiMessage::NoteOff { key: 65.into(), vel: 0.into() })),
+            event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ];
+        let smf = smf_with_tracks(vec![conductor, piano, vocal]);
+        let (mut state, warnings) = MidiState::from_smf_lenient(&smf);
+        assert!(warnings.iter().all(|w| !matches!(w, ImportWarning::UnsupportedMeta { .. })));
+        let imported: Vec<(u64, TextEventKind, u16)> =
+            state.text_events.iter().map(|e| (e.tick, e.kind, e.track)).collect();
+        assert_eq!(
+            imported,
+            vec![(0, TextEventKind::Lyric, 1), (0, TextEventKind::Text, 1), (480, TextEventKind::Lyric, 1)]
+        );
+        assert_eq!(state.text_events[0].text(), "\u{fffd}\u{fffd}");
+        let markers: Vec<(u64, &str)> = state.markers.iter().map(|m| (m.tick, m.label.as_str())).collect();
+        assert_eq!(markers, vec![(0, "Verse"), (1920, "Chorus")]);
+
+        // 只编辑音符后重新导出，文本事件的字节、位置与轨道都不变
+        state.notes.iter_mut().for_each(|n| n.velocity = 80);
+        let arena = midly::Arena::new();
+        let exported = state.to_smf_in(&arena);
+        assert_eq!(text_meta_of(&exported), text_meta_of(&smf));
+        let reloaded = MidiState::from_smf(&exported);
+        assert_eq!(reloaded.text_events, state.text_events);
+        assert_eq!(reloaded.markers.len(), 2);
+
+        // 单轨状态把所有文本事件写入唯一的轨道；不带 arena 的导出不写文本
+        let single = MidiState { tracks: Vec::new(), ..state.clone() };
+        let exported = single.to_smf_in(&arena);
+        assert_eq!(text_meta_of(&exported).len(), 5);
+        assert!(text_meta_of(&single.to_smf()).is_empty());
+    }
+
+    #[test]
+    fn validate_reports_note_curve_and_loop_issues() {
+        let ok = Note::new(0, 480, 60, 100);
+        let empty = Note { duration: 0, ..Note::new(480, 1, 62, 100) };
+        let silent = Note { velocity: 0, ..Note::new(960, 480, 64, 100) };
+        let overlapping = Note::new(240, 480, 60, 100);
+        let late = Note::new(1800, 480, 67, 100);
+        let mut lane = CurveLane::new(CurveLaneType::PitchBend);
+        let out_of_range = CurvePoint::new(120, 9000.0);
+        lane.points = vec![CurvePoint::new(0, 0.0), out_of_range.clone()];
+        let state = MidiState {
+            notes: vec![ok, overlapping, empty, silent, late],
+            curves: vec![lane.clone()],
+            ..MidiState::default()
+        };
+
+        let default_issues = state.validate();
+        assert_eq!(
+            default_issues,
+            vec![
+                ValidationIssue::ZeroDurationNote { note_id: empty.id, tick: 480, key: 62 },
+                ValidationIssue::ZeroVelocityNote { note_id: silent.id, tick: 960, key: 64 },
+                ValidationIssue::OverlappingNotes { earlier: ok.id, later: overlapping.id },
+                ValidationIssue::CurvePointOutOfRange { lane_id: lane.id, point_id: out_of_range.id, tick: 120 },
+            ]
+        );
+
+        let options = ValidationOptions { max_tick: Some(1920), loop_range: Some((960, 960)) };
+        let issues = state.validate_with(&options);
+        assert!(issues.contains(&ValidationIssue::NoteBeyondLimit { note_id: late.id, end: 2280, max_tick: 1920 }));
+        assert_eq!(issues.last(), Some(&ValidationIssue::InvalidLoop { start: 960, end: 960 }));
+        assert_eq!(issues.len(), default_issues.len() + 2);
+        assert_eq!(issues[2].note_ids(), vec![ok.id, overlapping.id]);
+
+        assert!(MidiState { notes: vec![ok], ..MidiState::default() }.validate().is_empty());
     }
 }
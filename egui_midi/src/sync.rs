@@ -0,0 +1,126 @@
+//! 外部同步源
+//!
+//! 设置同步源后，编辑器不再用帧间隔推进时间，而是每帧读取同步源的位置与播放状态。
+//! 编辑器按同步源的拍位置跟随：`current_time() * bpm() / 60` 拍，再换算到编辑器自身的速度。
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// 外部时钟来源（MIDI Clock、宿主 DAW 等）
+pub trait SyncSource {
+    /// 当前位置（秒，按 `bpm()` 计）
+    fn current_time(&self) -> f64;
+    fn is_playing(&self) -> bool;
+    fn bpm(&self) -> f32;
+
+    /// 当前位置（拍）
+    fn current_beats(&self) -> f64 {
+        self.current_time() * self.bpm() as f64 / 60.0
+    }
+}
+
+/// MIDI Clock 每拍的时钟数
+const CLOCKS_PER_BEAT: f64 = 24.0;
+const NO_CLOCK: u64 = u64::MAX;
+
+/// 可跨线程共享的传输状态，MIDI 输入线程写入，编辑器读取
+///
+/// 位置以拍存储，速度由相邻 Clock 消息的间隔估算。
+pub struct AtomicTransport {
+    playing: AtomicBool,
+    beats_bits: AtomicU64,
+    bpm_bits: AtomicU32,
+    last_clock_micros: AtomicU64,
+}
+
+impl AtomicTransport {
+    pub fn new(bpm: f32) -> Self {
+        Self {
+            playing: AtomicBool::new(false),
+            beats_bits: AtomicU64::new(0f64.to_bits()),
+            bpm_bits: AtomicU32::new(bpm.max(1.0).to_bits()),
+            last_clock_micros: AtomicU64::new(NO_CLOCK),
+        }
+    }
+
+    pub fn set_playing(&self, playing: bool) {
+        self.playing.store(playing, Ordering::Release);
+    }
+
+    pub fn set_bpm(&self, bpm: f32) {
+        self.bpm_bits.store(bpm.max(1.0).to_bits(), Ordering::Release);
+    }
+
+    pub fn position_beats(&self) -> f64 {
+        f64::from_bits(self.beats_bits.load(Ordering::Acquire))
+    }
+
+    pub fn set_position_beats(&self, beats: f64) {
+        self.beats_bits.store(beats.max(0.0).to_bits(), Ordering::Release);
+    }
+
+    /// 处理一条 MIDI 实时/系统公共消息，可直接在 midir 的输入回调中调用
+    ///
+    /// 支持 Clock (0xF8)、Start (0xFA)、Continue (0xFB)、Stop (0xFC) 与 Song Position Pointer (0xF2)，
+    /// 其他消息被忽略。`timestamp_micros` 为消息到达时间（微秒，单调递增）。
+    pub fn handle_midi_message(&self, timestamp_micros: u64, message: &[u8]) {
+        match message {
+            [0xF8, ..] => {
+                let last = self.last_clock_micros.swap(timestamp_micros, Ordering::AcqRel);
+                if last != NO_CLOCK && timestamp_micros > last {
+                    let interval = (timestamp_micros - last) as f64 / 1_000_000.0;
+                    let measured = (60.0 / (interval * CLOCKS_PER_BEAT)) as f32;
+                    // 平滑抖动
+                    let bpm = f32::from_bits(self.bpm_bits.load(Ordering::Acquire));
+                    self.set_bpm(bpm * 0.9 + measured * 0.1);
+                }
+                if self.playing.load(Ordering::Acquire) {
+                    self.set_position_beats(self.position_beats() + 1.0 / CLOCKS_PER_BEAT);
+                }
+            }
+            [0xFA, ..] => {
+                self.set_position_beats(0.0);
+                self.last_clock_micros.store(NO_CLOCK, Ordering::Release);
+                self.set_playing(true);
+            }
+            [0xFB, ..] => {
+                self.last_clock_micros.store(NO_CLOCK, Ordering::Release);
+                self.set_playing(true);
+            }
+            [0xFC, ..] => self.set_playing(false),
+            [0xF2, lsb, msb, ..] => {
+                let sixteenths = (*lsb as u32 & 0x7F) | ((*msb as u32 & 0x7F) << 7);
+                self.set_position_beats(sixteenths as f64 / 4.0);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for AtomicTransport {
+    fn default() -> Self {
+        Self::new(120.0)
+    }
+}
+
+/// 以共享的 [`AtomicTransport`] 作为同步源
+#[derive(Clone)]
+pub struct TransportSync(pub Arc<AtomicTransport>);
+
+impl SyncSource for TransportSync {
+    fn current_time(&self) -> f64 {
+        self.0.position_beats() * 60.0 / self.bpm() as f64
+    }
+
+    fn is_playing(&self) -> bool {
+        self.0.playing.load(Ordering::Acquire)
+    }
+
+    fn bpm(&self) -> f32 {
+        f32::from_bits(self.0.bpm_bits.load(Ordering::Acquire))
+    }
+
+    fn current_beats(&self) -> f64 {
+        self.0.position_beats()
+    }
+}
@@ -0,0 +1,137 @@
+//! 悬停提示的文本内容
+//!
+//! 只做纯粹的格式化，不读文件也不依赖 UI 状态，便于单独测试；`egui_track` 的剪辑提示复用这里的位置格式。
+
+use crate::structure::{KeySignature, MidiState, Note};
+
+const PITCH_CLASSES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+const PITCH_CLASSES_FLAT: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"];
+
+/// 音高名称的写法：调号决定黑键用升号还是降号，`middle_c_octave` 为中央 C（60）的八度编号
+///
+/// 不同的宿主对 60 的叫法不同（C4 或 C3 等），由 `MidiEditorOptions::middle_c_octave` 设置。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PitchNaming {
+    pub key_signature: KeySignature,
+    pub middle_c_octave: i8,
+}
+
+impl Default for PitchNaming {
+    fn default() -> Self {
+        Self { key_signature: KeySignature::default(), middle_c_octave: 4 }
+    }
+}
+
+/// 音高名称，默认中央 C（60）为 "C4"；侧边键盘、音符名称、提示与事件列表都通过这里格式化
+pub fn format_pitch(key: u8, naming: PitchNaming) -> String {
+    let octave = key as i32 / 12 - 5 + naming.middle_c_octave as i32;
+    format!("{}{}", pitch_class_name(key, naming.key_signature), octave)
+}
+
+/// 不带八度的音级名称（"C"、"C#" 或 "Db"……）
+pub fn pitch_class_name(key: u8, key_signature: KeySignature) -> &'static str {
+    if key_signature.uses_flats() {
+        PITCH_CLASSES_FLAT[key as usize % 12]
+    } else {
+        PITCH_CLASSES[key as usize % 12]
+    }
+}
+
+/// 将 tick 格式化为 "小节.拍.tick"（小节与拍从 1 开始，拍的长度按拍号分母计算）；有 `MidiState` 时用 `MidiState::musical_time`
+pub fn format_bar_beat_tick(tick: u64, ticks_per_beat: u16, time_signature: (u8, u8)) -> String {
+    let beat_ticks = (ticks_per_beat.max(1) as u64 * 4 / time_signature.1.max(1) as u64).max(1);
+    let measure_ticks = beat_ticks * time_signature.0.max(1) as u64;
+    let bar = tick / measure_ticks + 1;
+    let in_measure = tick % measure_ticks;
+    format!("{}.{}.{:03}", bar, in_measure / beat_ticks + 1, in_measure % beat_ticks)
+}
+
+/// 时值名称（"1/4"、"dotted 1/8"、"1/8 triplet"）；不是标准时值时返回 `None`
+pub fn note_value_name(duration: u64, ticks_per_beat: u16) -> Option<String> {
+    let whole = ticks_per_beat as u64 * 4;
+    if duration == 0 || whole == 0 {
+        return None;
+    }
+    for denom in [1u64, 2, 4, 8, 16, 32, 64] {
+        if !whole.is_multiple_of(denom) {
+            break;
+        }
+        let base = whole / denom;
+        if duration == base {
+            return Some(format!("1/{denom}"));
+        }
+        if base.is_multiple_of(2) && duration == base * 3 / 2 {
+            return Some(format!("dotted 1/{denom}"));
+        }
+        if base.is_multiple_of(3) && duration == base * 2 / 3 {
+            return Some(format!("1/{denom} triplet"));
+        }
+    }
+    None
+}
+
+/// 音符的悬停提示：音高、起点、时值、力度与通道
+pub fn note_tooltip(note: &Note, state: &MidiState, naming: PitchNaming) -> String {
+    let length = match note_value_name(note.duration, state.ticks_per_beat) {
+        Some(value) => format!("{} ticks ({value})", note.duration),
+        None => format!("{} ticks", note.duration),
+    };
+    format!(
+        "{} ({})\nStart {}\nLength {}\nVelocity {}\nChannel {}",
+        format_pitch(note.key, naming),
+        note.key,
+        state.musical_time(note.start),
+        length,
+        note.velocity,
+        state.track.channel as u16 + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_follow_the_meter() {
+        assert_eq!(format_bar_beat_tick(0, 480, (4, 4)), "1.1.000");
+        assert_eq!(format_bar_beat_tick(2400, 480, (4, 4)), "2.2.000");
+        assert_eq!(format_bar_beat_tick(1500, 480, (3, 4)), "2.1.060");
+        // 6/8 的拍为八分音符
+        assert_eq!(format_bar_beat_tick(1680, 480, (6, 8)), "2.2.000");
+    }
+
+    #[test]
+    fn note_values_cover_dotted_and_triplets() {
+        assert_eq!(note_value_name(480, 480).as_deref(), Some("1/4"));
+        assert_eq!(note_value_name(1920, 480).as_deref(), Some("1/1"));
+        assert_eq!(note_value_name(360, 480).as_deref(), Some("dotted 1/8"));
+        assert_eq!(note_value_name(160, 480).as_deref(), Some("1/8 triplet"));
+        assert_eq!(note_value_name(500, 480), None);
+    }
+
+    #[test]
+    fn note_tooltip_lists_musical_context() {
+        let mut state = MidiState::default();
+        state.track.channel = 9;
+        let note = Note::new(2400, 360, 61, 90);
+        assert_eq!(
+            note_tooltip(&note, &state, PitchNaming::default()),
+            "C#4 (61)\nStart 2.2.000\nLength 360 ticks (dotted 1/8)\nVelocity 90\nChannel 10"
+        );
+        assert_eq!(format_pitch(0, PitchNaming::default()), "C-1");
+
+        // 降号调里黑键拼写为降号
+        let flats = PitchNaming { key_signature: KeySignature::new(-3, false), ..PitchNaming::default() };
+        assert!(note_tooltip(&note, &state, flats).starts_with("Db4 (61)"));
+        assert_eq!(format_pitch(70, PitchNaming { key_signature: KeySignature::new(2, false), ..PitchNaming::default() }), "A#4");
+    }
+
+    #[test]
+    fn middle_c_octave_shifts_every_octave_number() {
+        let naming = |middle_c_octave| PitchNaming { middle_c_octave, ..PitchNaming::default() };
+        assert_eq!(format_pitch(60, naming(4)), "C4");
+        assert_eq!(format_pitch(60, naming(3)), "C3");
+        assert_eq!(format_pitch(0, naming(3)), "C-2");
+        assert_eq!(format_pitch(127, naming(5)), "G10");
+    }
+}
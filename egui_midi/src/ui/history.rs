@@ -0,0 +1,298 @@
+//! Undo history stored as differences between consecutive states.
+//!
+//! 每个撤销步骤只保存改动过的音符与曲线点（改动前后各一份），速度、拍号、分组、文本事件、标记等较小的字段改动时整份保存。
+//! 编辑器另外维护一份与当前状态同步的基准副本：步骤结束时把它与当前状态比较得出差异，
+//! 再把差异应用到基准上，因此编辑时不需要克隆整个 `MidiState`。
+
+use crate::structure::{
+    CurveLane, CurveLaneId, CurvePoint, CurvePointId, KeySignature, MidiState, MidiTrack, Note, NoteGroup, NoteId, TempoChange,
+    Marker, TextEvent, TrackMeta,
+};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// 按 id 比较的列表元素；`order_key` 是编辑器排序时使用的顺序（起点，再按 id）
+trait Keyed: Clone + PartialEq {
+    type Id: Copy + Eq + Hash;
+    fn id(&self) -> Self::Id;
+    fn order_key(&self) -> (u64, u64);
+}
+
+impl Keyed for Note {
+    type Id = NoteId;
+    fn id(&self) -> NoteId {
+        self.id
+    }
+    fn order_key(&self) -> (u64, u64) {
+        (self.start, self.id.0)
+    }
+}
+
+impl Keyed for CurvePoint {
+    type Id = CurvePointId;
+    fn id(&self) -> CurvePointId {
+        self.id
+    }
+    fn order_key(&self) -> (u64, u64) {
+        (self.tick, self.id.0)
+    }
+}
+
+/// 一个列表的改动：`before` 为被删除或修改的元素的旧值，`after` 为新增或修改的元素的新值
+#[derive(Clone, Debug)]
+struct ListDelta<T: Keyed> {
+    before: Vec<T>,
+    after: Vec<T>,
+    /// 元素的 id 顺序没有变化，只是原地替换了部分元素
+    in_place: bool,
+    /// 改动前后的列表不是按 `order_key` 排好序时，记录完整的 id 顺序以便原样恢复
+    before_order: Option<Vec<T::Id>>,
+    after_order: Option<Vec<T::Id>>,
+}
+
+impl<T: Keyed> ListDelta<T> {
+    fn between(old: &[T], new: &[T]) -> Self {
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        if old.len() == new.len() && old.iter().zip(new).all(|(a, b)| a.id() == b.id()) {
+            for (a, b) in old.iter().zip(new) {
+                if a != b {
+                    before.push(a.clone());
+                    after.push(b.clone());
+                }
+            }
+            return Self { before, after, in_place: true, before_order: None, after_order: None };
+        }
+        let old_by_id: HashMap<T::Id, &T> = old.iter().map(|item| (item.id(), item)).collect();
+        let new_by_id: HashMap<T::Id, &T> = new.iter().map(|item| (item.id(), item)).collect();
+        before.extend(old.iter().filter(|a| new_by_id.get(&a.id()) != Some(a)).cloned());
+        after.extend(new.iter().filter(|b| old_by_id.get(&b.id()) != Some(b)).cloned());
+        Self {
+            before,
+            after,
+            in_place: false,
+            before_order: explicit_order(old),
+            after_order: explicit_order(new),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty() && self.before_order.is_none() && self.after_order.is_none()
+    }
+
+    fn undo(&self, items: &mut Vec<T>) {
+        apply(items, &self.after, &self.before, self.in_place, self.before_order.as_deref());
+    }
+
+    fn redo(&self, items: &mut Vec<T>) {
+        apply(items, &self.before, &self.after, self.in_place, self.after_order.as_deref());
+    }
+
+    fn size_estimate(&self) -> usize {
+        (self.before.len() + self.after.len()) * std::mem::size_of::<T>()
+            + [&self.before_order, &self.after_order]
+                .iter()
+                .map(|order| order.as_ref().map_or(0, |ids| ids.len() * std::mem::size_of::<T::Id>()))
+                .sum::<usize>()
+    }
+}
+
+fn explicit_order<T: Keyed>(items: &[T]) -> Option<Vec<T::Id>> {
+    let sorted = items.windows(2).all(|w| w[0].order_key() <= w[1].order_key());
+    (!sorted).then(|| items.iter().map(Keyed::id).collect())
+}
+
+fn apply<T: Keyed>(items: &mut Vec<T>, remove: &[T], insert: &[T], in_place: bool, order: Option<&[T::Id]>) {
+    if in_place {
+        let replacements: HashMap<T::Id, &T> = insert.iter().map(|item| (item.id(), item)).collect();
+        for item in items.iter_mut() {
+            if let Some(replacement) = replacements.get(&item.id()) {
+                *item = (*replacement).clone();
+            }
+        }
+        return;
+    }
+    let removed: HashSet<T::Id> = remove.iter().map(Keyed::id).collect();
+    items.retain(|item| !removed.contains(&item.id()));
+    items.extend(insert.iter().cloned());
+    match order {
+        Some(order) => {
+            let rank: HashMap<T::Id, usize> = order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+            items.sort_by_key(|item| rank.get(&item.id()).copied().unwrap_or(usize::MAX));
+        }
+        None => items.sort_by_key(Keyed::order_key),
+    }
+}
+
+/// 曲线的改动：曲线本身（增删、改名、启用等）没变时只记录各曲线的点，否则整份保存全部曲线
+#[derive(Clone, Debug)]
+enum CurvesDelta {
+    Points(Vec<(CurveLaneId, ListDelta<CurvePoint>)>),
+    Lanes { before: Vec<CurveLane>, after: Vec<CurveLane> },
+}
+
+impl CurvesDelta {
+    fn between(old: &[CurveLane], new: &[CurveLane]) -> Self {
+        let same_lanes = old.len() == new.len()
+            && old.iter().zip(new).all(|(a, b)| {
+                a.id == b.id
+                    && a.lane_type == b.lane_type
+                    && a.name == b.name
+                    && a.enabled == b.enabled
+                    && a.interpolation == b.interpolation
+            });
+        if !same_lanes {
+            return CurvesDelta::Lanes { before: old.to_vec(), after: new.to_vec() };
+        }
+        CurvesDelta::Points(
+            old.iter()
+                .zip(new)
+                .map(|(a, b)| (a.id, ListDelta::between(&a.points, &b.points)))
+                .filter(|(_, delta)| !delta.is_empty())
+                .collect(),
+        )
+    }
+
+    fn apply(&self, curves: &mut Vec<CurveLane>, undo: bool) {
+        match self {
+            CurvesDelta::Points(lanes) => {
+                for (lane_id, delta) in lanes {
+                    if let Some(lane) = curves.iter_mut().find(|lane| lane.id == *lane_id) {
+                        if undo {
+                            delta.undo(&mut lane.points);
+                        } else {
+                            delta.redo(&mut lane.points);
+                        }
+                    }
+                }
+            }
+            CurvesDelta::Lanes { before, after } => {
+                *curves = if undo { before.clone() } else { after.clone() };
+            }
+        }
+    }
+
+    fn size_estimate(&self) -> usize {
+        match self {
+            CurvesDelta::Points(lanes) => lanes.iter().map(|(_, delta)| delta.size_estimate()).sum(),
+            CurvesDelta::Lanes { before, after } => before
+                .iter()
+                .chain(after)
+                .map(|lane| std::mem::size_of::<CurveLane>() + lane.points.len() * std::mem::size_of::<CurvePoint>())
+                .sum(),
+        }
+    }
+}
+
+/// 音符与曲线以外的字段，改动时整份保存
+#[derive(Clone, Debug)]
+struct StateMeta {
+    ticks_per_beat: u16,
+    bpm: f32,
+    tempo_changes: Vec<TempoChange>,
+    time_signature: (u8, u8),
+    key_signature: KeySignature,
+    track: TrackMeta,
+    groups: Vec<NoteGroup>,
+    tracks: Vec<MidiTrack>,
+    text_events: Vec<TextEvent>,
+    markers: Vec<Marker>,
+}
+
+impl StateMeta {
+    fn of(state: &MidiState) -> Self {
+        Self {
+            ticks_per_beat: state.ticks_per_beat,
+            bpm: state.bpm,
+            tempo_changes: state.tempo_changes.clone(),
+            time_signature: state.time_signature,
+            key_signature: state.key_signature,
+            track: state.track.clone(),
+            groups: state.groups.clone(),
+            tracks: state.tracks.clone(),
+            text_events: state.text_events.clone(),
+            markers: state.markers.clone(),
+        }
+    }
+
+    fn same(a: &MidiState, b: &MidiState) -> bool {
+        a.ticks_per_beat == b.ticks_per_beat
+            && a.bpm == b.bpm
+            && a.tempo_changes == b.tempo_changes
+            && a.time_signature == b.time_signature
+            && a.key_signature == b.key_signature
+            && a.track == b.track
+            && a.groups == b.groups
+            && a.tracks == b.tracks
+            && a.text_events == b.text_events
+            && a.markers == b.markers
+    }
+
+    fn restore(&self, state: &mut MidiState) {
+        state.ticks_per_beat = self.ticks_per_beat;
+        state.bpm = self.bpm;
+        state.tempo_changes = self.tempo_changes.clone();
+        state.time_signature = self.time_signature;
+        state.key_signature = self.key_signature;
+        state.track = self.track.clone();
+        state.groups = self.groups.clone();
+        state.tracks = self.tracks.clone();
+        state.text_events = self.text_events.clone();
+        state.markers = self.markers.clone();
+    }
+
+    fn size_estimate(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.tempo_changes.len() * std::mem::size_of::<TempoChange>()
+            + self
+                .groups
+                .iter()
+                .map(|g| std::mem::size_of::<NoteGroup>() + g.note_ids.len() * std::mem::size_of::<NoteId>())
+                .sum::<usize>()
+            + self.tracks.len() * std::mem::size_of::<MidiTrack>()
+            + self.text_events.iter().map(|e| std::mem::size_of::<TextEvent>() + e.text.len()).sum::<usize>()
+            + self.markers.iter().map(|m| std::mem::size_of::<Marker>() + m.label.len()).sum::<usize>()
+    }
+}
+
+/// 一个撤销步骤前后两个状态之间的差异，可以双向应用
+#[derive(Clone, Debug)]
+pub(crate) struct StateDelta {
+    notes: ListDelta<Note>,
+    curves: CurvesDelta,
+    meta: Option<Box<(StateMeta, StateMeta)>>,
+}
+
+impl StateDelta {
+    pub(crate) fn between(old: &MidiState, new: &MidiState) -> Self {
+        Self {
+            notes: ListDelta::between(&old.notes, &new.notes),
+            curves: CurvesDelta::between(&old.curves, &new.curves),
+            meta: (!StateMeta::same(old, new)).then(|| Box::new((StateMeta::of(old), StateMeta::of(new)))),
+        }
+    }
+
+    /// 把改动后的状态恢复为改动前
+    pub(crate) fn undo(&self, state: &mut MidiState) {
+        self.notes.undo(&mut state.notes);
+        self.curves.apply(&mut state.curves, true);
+        if let Some(meta) = &self.meta {
+            meta.0.restore(state);
+        }
+    }
+
+    /// 把改动前的状态重新变为改动后
+    pub(crate) fn redo(&self, state: &mut MidiState) {
+        self.notes.redo(&mut state.notes);
+        self.curves.apply(&mut state.curves, false);
+        if let Some(meta) = &self.meta {
+            meta.1.restore(state);
+        }
+    }
+
+    pub(crate) fn size_estimate(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.notes.size_estimate()
+            + self.curves.size_estimate()
+            + self.meta.as_ref().map_or(0, |meta| meta.0.size_estimate() + meta.1.size_estimate())
+    }
+}
@@ -1,12 +1,97 @@
 use crate::audio::{PlaybackBackend, PlaybackObserver};
-use crate::editor::{EditorCommand, EditorEvent, MidiEditorOptions, SnapMode, TransportState};
-use crate::structure::{BatchTransformType, CurveLaneId, CurvePointId, CurveLaneType, MidiState, Note, NoteId};
+use crate::editor::{
+    BitSet128, ClipboardPayload, CurveTool, DrumMap, EditorCommand, EditorEvent, ImportMergeOptions, ImportPlacement,
+    KeyVelocityCurve, LoopExportError, LowerLaneView, MidiEditorOptions, MidiEditorTheme, NoteDoubleClickAction, NoteLength, NoteTool, PasteMode, SnapModifier, SnapResolution, QuantizeTargets, SelectionQuery,
+    NoteDragPayload, ReferenceWaveform, ScaleKind, ShortcutAction, ShortcutMap, SnapMode, StretchAnchor, TransportState,
+};
+use crate::sync::SyncSource;
+use crate::tooltip;
+use history::StateDelta;
+use crate::structure::{
+    arpeggiate, rescale_tick, ArpPattern, BatchTransformType, CurveClip, CurveInterpolation, CurveLane, CurveLaneId, CurvePoint, CurvePointId, CurveLaneType, KeySignature, Marker, MarkerId, MidiState, Note,
+    NoteFilter, NoteGroup, NoteGroupId, NoteId, OverlapPolicy, RampCurve, SmallString, TextEvent, TextEventKind, ValidationIssue,
+    ValidationOptions,
+};
 use egui::*;
 use midly::Smf;
-use std::collections::BTreeSet;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::{Range, RangeInclusive};
 use std::sync::Arc;
 
+mod history;
+mod render;
+
+/// 音符标签只在行高不小于该值时绘制
+const NOTE_LABEL_MIN_ZOOM_Y: f32 = 12.0;
+
+/// 音符内的音高名称与力度只在音符矩形不窄于该值（且行高满足 `NOTE_LABEL_MIN_ZOOM_Y`）时绘制
+const NOTE_TEXT_MIN_WIDTH: f32 = 28.0;
+
+/// 概览条的高度、按 tick 划分的列数，以及视口矩形边缘的拖动命中宽度
+const MINIMAP_HEIGHT: f32 = 28.0;
+const MINIMAP_COLUMNS: usize = 512;
+const MINIMAP_EDGE_GRAB: f32 = 4.0;
+
+/// 受限音高时，吸附到允许音高的最大距离（半音）
+const ALLOWED_KEY_SNAP_RANGE: u8 = 12;
+
+/// 钢琴卷帘左侧键盘宽度与顶部时间轴高度
+const PIANO_KEY_WIDTH: f32 = 60.0;
+const TIMELINE_HEIGHT: f32 = 30.0;
+
+/// 时间轴上标记旗子的命中半宽（像素）
+const MARKER_GRAB: f32 = 5.0;
+
+/// 电脑键盘弹奏的力度与低八度行的默认起始音高（C3）
+const COMPUTER_KEYBOARD_VELOCITY: u8 = 100;
+const COMPUTER_KEYBOARD_DEFAULT_BASE: u8 = 48;
+
+/// 力度柱上下留出的空白，以及按下时命中力度柱的最大水平距离
+const VELOCITY_LANE_PADDING: f32 = 4.0;
+const VELOCITY_BAR_HIT_RADIUS: f32 = 6.0;
+
+/// 播放速率的范围
+const PLAYBACK_RATE_RANGE: (f32, f32) = (0.25, 2.0);
+
+/// 敲击测速：最多按最近几次敲击计算，间隔超过该秒数重新开始，至少敲击几次才设置速度
+const TAP_TEMPO_MAX_TAPS: usize = 8;
+const TAP_TEMPO_TIMEOUT: f64 = 2.0;
+const TAP_TEMPO_MIN_TAPS: usize = 4;
+
+/// 单次试听（如音高镜像后）的发声时长（秒）
+const PREVIEW_ONCE_SECONDS: f64 = 0.3;
+
+/// 选中试听最多同时发声的音数
+const AUDITION_MAX_VOICES: usize = 8;
+/// "Play selection" 最多排程的音符数
+const AUDITION_MAX_NOTES: usize = 512;
+
+/// 事件列表面板的高度与列宽
+const EVENT_LIST_HEIGHT: f32 = 200.0;
+const EVENT_LIST_COLUMN_WIDTH: f32 = 90.0;
+
 type PlaybackHandle = Arc<dyn PlaybackBackend>;
+type EventListener = Box<dyn FnMut(&EditorEvent)>;
+
+/// 试听中的一个音，时间为 `ui` 的输入时钟（秒）
+#[derive(Clone, Copy, Debug)]
+struct AuditionVoice {
+    key: u8,
+    velocity: u8,
+    on_at: f64,
+    off_at: f64,
+    sounding: bool,
+}
+
+/// 复制拖动的进度：按下时只做标记，第一次真正移动时才生成副本，单击不会留下重叠的副本
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum DuplicateDrag {
+    #[default]
+    Off,
+    Armed,
+    Copied,
+}
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum DragAction {
@@ -17,6 +102,36 @@ pub enum DragAction {
     Create,
     LoopEdit,
     PlayheadSeek,
+    MarkerMove,
+}
+
+/// 撤销/重做栈中的一步，只保存这一步前后状态的差异
+#[derive(Clone, Debug)]
+pub struct UndoEntry {
+    /// `begin_undo_group` 指定的名称；普通编辑为 `None`
+    pub label: Option<String>,
+    /// 撤销栈顶仍在进行的步骤为 `None`，在下一次压栈、撤销或重做时与基准比较得出
+    change: Option<StateDelta>,
+    /// 这一步之后状态的修订号
+    revision: u64,
+}
+
+/// 正在进行的撤销组；`pushed` 表示组内第一次编辑已压入快照
+struct UndoGroup {
+    label: String,
+    depth: usize,
+    pushed: bool,
+}
+
+/// 侧边键盘试听的状态
+///
+/// 按下按键进入 `Held`；按住期间指针纵向滑到其他按键时先 note_off 旧键再 note_on 新键（滑奏），
+/// 在任何位置松开都回到 `Idle`。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyPreviewState {
+    #[default]
+    Idle,
+    Held { key: u8, velocity: u8 },
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -26,16 +141,208 @@ enum LoopEditMode {
     Move,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
-enum LaneType {
+/// 外部拖放的幽灵预览，仅在下一帧绘制一次
+struct DropPreview {
+    notes: Vec<Note>,
+    at_tick: u64,
+    key_offset: i16,
+}
+
+/// 叠在可编辑音符之下的参考内容，音符保留原有的 tick 精度
+struct GhostLayer {
+    notes: Vec<Note>,
+    ticks_per_beat: u16,
+}
+
+/// 一次录音（一次播放期间的全部输入），整体只占一个撤销步骤
+#[derive(Default)]
+struct RecordTake {
+    held: Vec<(u8, NoteId)>, // 按住的输入音高与正在录制的音符
+    last_tick: u64,
+    snapshot_pushed: bool,
+}
+
+/// 事件列表可排序的列
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EventListColumn {
+    Start,
+    Duration,
     Pitch,
     Velocity,
 }
 
-#[allow(dead_code)]
+impl EventListColumn {
+    const ALL: [EventListColumn; 4] = [
+        EventListColumn::Start,
+        EventListColumn::Duration,
+        EventListColumn::Pitch,
+        EventListColumn::Velocity,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            EventListColumn::Start => "Start",
+            EventListColumn::Duration => "Length",
+            EventListColumn::Pitch => "Pitch",
+            EventListColumn::Velocity => "Velocity",
+        }
+    }
+}
+
+/// 概览条的音符标记：按 tick 分成固定列数，记录每列的最低与最高音高
+///
+/// 只在音符修订号或音符数量变化时重建，而不是每帧重建。
+struct MinimapCache {
+    revision: u64,
+    note_count: usize,
+    total_ticks: u64,
+    columns: Vec<Option<(u8, u8)>>,
+}
+
+impl MinimapCache {
+    fn build(notes: &[Note], revision: u64, ticks_per_measure: u64) -> Self {
+        let end = notes.iter().map(|n| n.start + n.duration).max().unwrap_or(0);
+        let total_ticks = (end + ticks_per_measure).max(ticks_per_measure * 4).max(1);
+        let column = |tick: u64| ((tick as u128 * MINIMAP_COLUMNS as u128 / total_ticks as u128) as usize).min(MINIMAP_COLUMNS - 1);
+        let mut columns = vec![None; MINIMAP_COLUMNS];
+        for note in notes {
+            let last = column(note.start + note.duration.saturating_sub(1));
+            for slot in &mut columns[column(note.start)..=last] {
+                *slot = Some(slot.map_or((note.key, note.key), |(low, high): (u8, u8)| (low.min(note.key), high.max(note.key))));
+            }
+        }
+        Self {
+            revision,
+            note_count: notes.len(),
+            total_ticks,
+            columns,
+        }
+    }
+}
+
+/// 钢琴卷帘音符主体的网格缓存
+///
+/// 视口附近的音符合并为一个 `Mesh`，顶点位于内容坐标（相对音符网格原点，不含滚动）。
+///
+/// 覆盖可见范围左右各一屏；平移时只在绘制前整体平移，视口移出 `ticks` 范围、
+/// `NoteMeshKey` 变化或范围内的音符内容变化时才重建。
+struct NoteMeshCache {
+    key: NoteMeshKey,
+    /// 网格包含的 tick 范围
+    ticks: (u64, u64),
+    /// 范围内音符的内容哈希
+    ///
+    /// `state` 是公开字段，宿主可能直接修改音符而不经过编辑器，因此按内容判断是否重建，
+    /// 不依赖编辑器内部的修订号。
+    notes_hash: u64,
+    mesh: Mesh,
+}
+
+/// 决定音符网格形状的视图设置；按生效后力度着色时依赖曲线，不缓存
+#[derive(Clone, PartialEq)]
+struct NoteMeshKey {
+    zoom: Vec2,
+    ticks_per_beat: u16,
+    rows: [Option<u8>; 128],
+    active_track: Option<u16>, // 多轨状态下可编辑的轨道
+    color_by_velocity: bool,
+    theme: MidiEditorTheme,
+}
+
+/// 音符中影响网格的字段（位置、音高、力度、轨道）的哈希
+fn note_mesh_hash(notes: &[Note]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for note in notes {
+        (note.id.0, note.start, note.duration, note.key, note.velocity, note.track).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// 在概览条上拖动的作用：平移视口，或拖动视口的左/右边缘缩放
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MinimapDrag {
+    Pan,
+    LeftEdge,
+    RightEdge,
+}
+
+/// `zoom_to_fit` / `zoom_to_selection` 的缩放范围
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ZoomTarget {
+    All,
+    Selection,
+}
+
+/// 钢琴卷帘的行布局：行号自顶向下，普通模式下第 `127 - key` 行；鼓映射隐藏未命名的音高时只保留有名称的行
+struct RowLayout {
+    keys: Vec<u8>,              // 行号 -> 音高
+    rows: [Option<u8>; 128],    // 音高 -> 行号，隐藏的音高为 None
+}
+
+impl RowLayout {
+    fn new(drum_map: Option<&DrumMap>) -> Self {
+        let mut keys: Vec<u8> = (0..=127u8).rev().collect();
+        if let Some(map) = drum_map.filter(|map| map.hide_unmapped && !map.names.is_empty()) {
+            keys.retain(|key| map.names.contains_key(key));
+        }
+        let mut rows = [None; 128];
+        for (row, &key) in keys.iter().enumerate() {
+            rows[key as usize] = Some(row as u8);
+        }
+        Self { keys, rows }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn row_of(&self, key: u8) -> Option<usize> {
+        self.rows[key.min(127) as usize].map(usize::from)
+    }
+
+    /// 行号（可带小数，自顶部算起）所在行的音高，超出范围时取最近的一行
+    fn key_at(&self, row: f32) -> u8 {
+        self.keys[(row.floor().max(0.0) as usize).min(self.keys.len() - 1)]
+    }
+}
+
+/// 钢琴卷帘右上角的短暂提示，首次绘制时开始计时
+struct Toast {
+    message: String,
+    expires_at: Option<f64>,
+}
+
+/// 曲线区域中铅笔或直线工具的一次拖动
+struct CurveStroke {
+    lane_id: CurveLaneId,
+    start: (u64, f32),
+    last: (u64, f32),
+    last_point: Option<CurvePointId>, // 铅笔上一步写入的点
+    touched: RangeInclusive<u64>,
+    removed: Vec<CurvePointId>,
+    added: Vec<CurvePointId>,
+}
+
+/// 绘制、擦除或切片工具的一次拖动；松开时成批发出事件
+struct NoteStroke {
+    key: u8, // 绘制工具写入的音高，切片工具切线的起点音高
+    last_tick: i64,
+    cut: u64, // 切片工具的切点
+    added: Vec<Note>,
+    removed: Vec<Note>,
+    sliced: Vec<(Note, NoteId)>, // 被切开的音符切开前的样子与切出的后半段
+}
+
+/// 双击音符打开的属性小窗，编辑的是草稿，提交时才写回音符
+struct NotePropertiesPopup {
+    note_id: NoteId,
+    open_pos: Pos2, // 打开小窗的双击位置，该处的点击不会关闭小窗
+    draft: Note,
+    focus_pending: bool,
+}
+
 struct LaneEditState {
-    lane: LaneType,
     anchor: NoteId,
     originals: Vec<(NoteId, Note)>,
 }
@@ -56,70 +363,220 @@ pub struct MidiEditor {
     pub current_time: f32, // in seconds
     pub last_update: f64,
     pub last_tick: u64, // For sequencer tracking
+    sounding_notes: HashMap<NoteId, u8>, // Notes the sequencer has started, keyed to the key that was sent
+    sent_cc_values: HashMap<u8, u8>, // 播放时每个控制器最后发送的值
+    sent_pitch_bend: Option<i16>,    // 播放时最后发送的弯音值
+    sustained_keys: BTreeSet<u8>,    // 音符已结束、因延音踏板踩下而推迟 note_off 的键
 
     // Interaction state
     pub selected_notes: BTreeSet<NoteId>,
+    active_track: u16,  // 多轨状态下可编辑的轨道，其他轨道显示为幽灵音符
+    hidden_tracks: BTreeSet<u16>,  // 隐藏的轨道既不显示也不播放
     pub selection_box_start: Option<Pos2>,
     pub selection_box_end: Option<Pos2>,
     pub drag_start_pos: Option<Pos2>,
+    dragging_marker: Option<(MarkerId, u64)>,  // 正在拖动的标记及其原位置
     pub is_dragging_note: bool,
     pub is_resizing_note: bool, // True if resizing (dragging right edge)
-    pub active_key_note: Option<u8>, // Track which key is currently being pressed for preview
+    pub key_preview: KeyPreviewState, // Sidebar key currently held for preview
     pub is_panning: bool,
     pub pan_start: Option<Pos2>,
     pub pan_start_scroll: Option<Vec2>,
     pub drag_action: DragAction,
     pub drag_preview_key: Option<u8>,
+    pub preview_release_at: Option<f64>, // 单次试听（如音高镜像后）到此时间松开 `drag_preview_key`
+    pub audition_on_select: bool,
+    pub audition_duration: f32,
+    audition_voices: Vec<AuditionVoice>, // 选中试听与 "Play selection" 排程的音，与走带无关
+    auditioned_selection: BTreeSet<NoteId>, // 上次检查选中试听时的选区
     pub drag_original_start: Option<u64>,
     pub drag_original_duration: Option<u64>,
     pub drag_original_key: Option<u8>,
     pub drag_pointer_offset_ticks: Option<i64>,
     pub drag_original_notes: Vec<(NoteId, Note)>,
     pub drag_primary_anchor: Option<NoteId>,
+    drag_duplicate: DuplicateDrag,
+    pub note_tool: NoteTool,
+    note_stroke: Option<NoteStroke>,
+    pub note_double_click: NoteDoubleClickAction,
+    note_properties: Option<NotePropertiesPopup>,
     pub drag_original_loop_start: Option<u64>,
     pub drag_original_loop_end: Option<u64>,
     loop_edit_mode: Option<LoopEditMode>,
+    drop_preview: Option<DropPreview>,
 
     // Config
-    pub snap_interval: u64, // Ticks (e.g., 480 for quarter note)
+    /// 按音符时值吸附，见 `MidiEditorOptions::snap_resolution`；`None` 时使用 `snap_interval`
+    pub snap_resolution: Option<(SnapResolution, SnapModifier)>,
+    pub snap_interval: u64, // 自定义吸附间隔（tick），0 为不吸附
     pub snap_mode: SnapMode,
     pub swing_ratio: f32,
     pub volume: f32,
+    pub metronome_enabled: bool,
+    metronome_volume: f32,
+    playback_rate: f32,
     pub preview_pitch_shift: f32,
     pub loop_enabled: bool,
     pub loop_start_tick: u64,
     pub loop_end_tick: u64,
+    pub trigger_notes_under_playhead: bool,
+    pub key_velocity_curve: KeyVelocityCurve,
+    pub hover_tooltip_delay: Option<f32>,
+    note_tooltip_hover: Option<(NoteId, f64)>,  // 悬停的音符及开始悬停的时间
+    edit_bounds: Option<(u64, u64)>,
+    allowed_keys: Option<BitSet128>,
+    scale_highlight: Option<(u8, ScaleKind)>,
+    pub snap_to_scale: bool,
+    pub computer_keyboard_input: bool,
+    recording: bool,
+    record_take: Option<RecordTake>,
+    pub record_quantize: bool,
+    computer_keyboard_base: u8,                   // 低八度行 Z 键对应的音高
+    computer_keyboard_held: Vec<(Key, u8)>,       // 按住的键及其发出的音高（升降八度后仍按原音高松开）
+    reference_waveform: Option<ReferenceWaveform>,
+    ghost: Option<GhostLayer>,
+    step_input: bool,
+    step_cursor: u64,
+    step_chord_held: bool,     // 按住 Shift 输入和弦中，松开时光标才前进
+    step_modifier_down: bool,  // 本帧 Shift 是否按下
+    pub show_reference_waveform: bool,
+    pub show_note_names: bool,
+    pub show_note_velocities: bool,
+    pub color_by_velocity: bool,
+    pub color_by_effective_velocity: bool,
+    pub show_minimap: bool,
+    pub show_event_list: bool,
+    pub middle_c_octave: i8,
+    pub auto_trim_overlaps: bool,
+    event_list_sort: (EventListColumn, bool), // 事件列表的排序列与是否降序
+    /// 鼓映射模式，见 `MidiEditorOptions::drum_map`
+    pub drum_map: Option<DrumMap>,
+    /// 绘制用的颜色，见 `MidiEditorOptions::theme`
+    pub theme: Option<MidiEditorTheme>,
+    palette: MidiEditorTheme, // 本帧实际使用的颜色：`theme` 或由宿主 Visuals 推导
+    notes_revision: u64, // 音符增删改或整体替换时递增，概览条与音符网格据此判断是否需要重建
+    note_mesh: Option<NoteMeshCache>,
+    // NoteId -> `state.notes` 下标；`state` 是公开字段，查到的下标会先核对，不符时重建
+    note_index: RefCell<HashMap<NoteId, usize>>,
+    minimap: Option<MinimapCache>,
+    minimap_drag: Option<MinimapDrag>,
+    minimap_rect: Option<Rect>,
+    zoom_request: Option<ZoomTarget>, // 下一帧按视图大小执行的缩放
+    toast: Option<Toast>,
+    debug_repaint_counter: bool,
+    repaint_requests: u64,
+    note_mesh_rebuilds: u64,
+    max_pending_events: usize,
+    state_replaced_on_edit: bool, // 见 `MidiEditorOptions::state_replaced_on_edit`
+    max_clipboard_notes: usize,
+    max_undo_entries: usize,
+    events_overflowed: bool,  // 自上次 take_events 以来是否已丢弃过事件（只警告一次）
+    piano_roll_rect: Option<Rect>,
+    velocity_lane_rect: Option<Rect>,
 
     // Integration
     pub transport_override: Option<TransportState>,
+    sync: Option<Box<dyn SyncSource>>,
     pub pending_events: Vec<EditorEvent>,
-    event_listener: Option<Box<dyn FnMut(&EditorEvent)>>,
+    event_listener: Option<EventListener>,
     pub clipboard: Vec<Note>,
-    pub undo_stack: Vec<MidiState>,
-    pub redo_stack: Vec<MidiState>,
+    pub clipboard_groups: Vec<NoteGroup>,
+    pub curve_clipboard: Option<CurveClip>,
+    pub clipboard_curves: Vec<CurveClip>, // 随音符复制的各曲线片段，起点为最早音符的起点
+    pub curve_paste_overwrite: bool,
+    pub paste_mode: PasteMode,
+    pub system_clipboard: bool,
+    clipboard_export_pending: bool, // 本帧复制过，帧末写入系统剪贴板
+    pub undo_stack: Vec<UndoEntry>,
+    pub redo_stack: Vec<UndoEntry>,
+    /// 最近一个撤销步骤开始时的状态，随撤销/重做同步更新；与 `state` 比较得出进行中步骤的差异
+    history_base: MidiState,
+    undo_group: Option<UndoGroup>,
+    reported_history: (usize, usize),
+    reported_view: Option<(Range<u64>, RangeInclusive<u8>)>,
+    /// 下一个撤销步骤使用的修订号
+    next_revision: u64,
+    /// 撤销栈为空时的修订号：最近被挤出栈底的步骤或最近一次 `replace_state` 的修订号
+    history_floor: u64,
+    saved_revision: u64,
+    reported_dirty: bool,
     pub drag_changed_note: bool,
-    #[allow(dead_code)]
     lane_edit_state: Option<LaneEditState>,
-    #[allow(dead_code)]
     lane_edit_changed: bool,
     
     // Curve editing state
     pub selected_curve_lane: Option<CurveLaneId>,
     pub dragging_curve_point: Option<(CurveLaneId, CurvePointId)>,
+    pub selected_curve_points: Vec<CurvePointId>,
+    curve_context_tick: Option<u64>,
+    curve_context_point: Option<CurvePointId>, // 右键菜单针对的曲线点
+    pub curve_tool: CurveTool,
+    curve_stroke: Option<CurveStroke>,
+    curve_lane_rect: Option<Rect>,
+    custom_cc_number: u8, // 曲线选择栏中自定义 CC 的编号
     pub curve_lane_height: f32,
     pub curve_lane_visible: bool,
+    pub lower_lane_view: LowerLaneView, // 分隔条下方显示曲线还是音符力度柱
     pub dragging_splitter: bool,
     
     // Batch transform dialog state
     pub show_batch_transform_dialog: bool,
     pub batch_transform_type: crate::structure::BatchTransformType,
     pub batch_transform_value: f64,
+    pub batch_ramp_selected: bool, // 对话框中选中了 "Velocity Ramp"（通过 `EditorCommand::VelocityRamp` 应用）
+    pub batch_ramp_start: u8,
+    pub batch_ramp_end: u8,
+    pub batch_ramp_curve: RampCurve,
+    pub batch_stretch_selected: bool, // 对话框中选中了 "Time Stretch"（通过 `EditorCommand::TimeStretchSelection` 应用）
+    pub batch_stretch_ratio: f64,
+    pub batch_stretch_anchor: StretchAnchor,
+    pub batch_stretch_curves: bool,
     pub swing_menu_ratio: f32,
     pub swing_original_notes: Vec<(NoteId, u64)>, // Store original positions when starting swing adjustment
+    pub arp_octaves: u8,
+    pub arp_gate: f32,
+
+    // Quantize dialog state
+    pub show_quantize_dialog: bool,
+    pub quantize_targets: QuantizeTargets,
+    pub quantize_strength: f32,
+    pub quantize_interval: u64,
+
+    // Humanize dialog state（时间范围可按 tick 或吸附间隔的比例设置）
+    pub show_humanize_dialog: bool,
+    pub humanize_time_ticks: u64,
+    pub humanize_time_fraction: f32,
+    pub humanize_time_relative: bool,
+    pub humanize_velocity_range: u8,
+    pub humanize_delay_only: bool,
+    pub humanize_seed: u64,
+
+    // Ticks per beat dialog state
+    pub show_tpb_dialog: bool,
+    pub tpb_dialog_value: u16,
+
+    // 检查器中待添加的文本事件
+    text_event_draft: (TextEventKind, String),
+
+    // Invert pitch dialog state
+    pub show_invert_dialog: bool,
+    pub invert_axis_key: u8,
+
+    // Select by... dialog state（每个条件一个开关与闭区间）
+    pub show_select_by_dialog: bool,
+    pub select_by_velocity: (bool, u8, u8),
+    pub select_by_key: (bool, u8, u8),
+    pub select_by_duration: (bool, u64, u64),
+
+    // Note label dialog state
+    pub show_label_dialog: bool,
+    pub label_dialog_text: String,
     
     // Context menu state
     pub context_menu_pos: Option<Pos2>,
     pub context_menu_open_pos: Option<Pos2>, // Track the position where menu was opened
+    pub context_menu_note: Option<NoteId>, // 右键菜单打开时指针下的音符
+    context_menu_anchor: Option<(u64, u8)>, // 右键处吸附后的 tick 与音高，供 "Paste here" 使用
     pub splitter_ratio: f32, // Ratio of piano roll height (0.0-1.0)
     
     // Playback settings dialog
@@ -127,6 +584,14 @@ pub struct MidiEditor {
     
     // Shortcut configuration
     pub enable_space_playback: bool,
+    shortcuts: ShortcutMap,
+    pub duplicate_drag_modifiers: Modifiers,
+    pub note_length: NoteLength,
+    pub default_velocity: u8,
+    last_note_length: Option<u64>, // 最近一次新建或调整过时值的音符的时值，供 `NoteLength::LastUsed` 使用
+    tap_times: Vec<f64>, // 当前这轮敲击测速的时间戳（秒），最多 `TAP_TEMPO_MAX_TAPS` 个
+    tap_bpm: Option<f32>, // 这轮敲击算出的速度，显示在 "Tap" 按钮的提示里
+    tap_undo_revision: Option<u64>, // 这轮敲击压入的撤销步骤，同一轮的后续敲击并入该步骤
 }
 
 impl MidiEditor {
@@ -149,10 +614,9 @@ impl MidiEditor {
     }
 
     fn base_with_state(state: MidiState, playback: Option<PlaybackHandle>) -> Self {
-        let loop_default = (state.ticks_per_beat as u64)
-            .saturating_mul(4)
-            .max(state.ticks_per_beat as u64);
+        let loop_default = state.ticks_per_measure();
         Self {
+            history_base: state.clone(),
             state,
             playback,
             playback_observer: None,
@@ -164,59 +628,197 @@ impl MidiEditor {
             current_time: 0.0,
             last_update: 0.0,
             last_tick: 0,
+            sounding_notes: HashMap::new(),
+            sent_cc_values: HashMap::new(),
+            sent_pitch_bend: None,
+            sustained_keys: BTreeSet::new(),
             selected_notes: BTreeSet::new(),
+            active_track: 0,
+            hidden_tracks: BTreeSet::new(),
             selection_box_start: None,
             selection_box_end: None,
             drag_start_pos: None,
+            dragging_marker: None,
             is_dragging_note: false,
             is_resizing_note: false,
-            active_key_note: None,
+            key_preview: KeyPreviewState::Idle,
             is_panning: false,
             pan_start: None,
             pan_start_scroll: None,
             drag_action: DragAction::None,
             drag_preview_key: None,
+            preview_release_at: None,
+            audition_on_select: false,
+            audition_duration: 0.4,
+            audition_voices: Vec::new(),
+            auditioned_selection: BTreeSet::new(),
             drag_original_start: None,
             drag_original_duration: None,
             drag_original_key: None,
             drag_pointer_offset_ticks: None,
             drag_original_notes: Vec::new(),
             drag_primary_anchor: None,
+            drag_duplicate: DuplicateDrag::Off,
+            note_tool: NoteTool::Select,
+            note_stroke: None,
+            note_double_click: NoteDoubleClickAction::default(),
+            note_properties: None,
             drag_original_loop_start: None,
             drag_original_loop_end: None,
             loop_edit_mode: None,
+            drop_preview: None,
+            snap_resolution: Some((SnapResolution::Sixteenth, SnapModifier::Straight)),
             snap_interval: 120,
             snap_mode: SnapMode::Absolute,
             swing_ratio: 0.0,
             volume: 0.5,
+            metronome_enabled: false,
+            metronome_volume: 0.5,
+            playback_rate: 1.0,
             preview_pitch_shift: 0.0,
             loop_enabled: false,
             loop_start_tick: 0,
             loop_end_tick: loop_default,
+            trigger_notes_under_playhead: false,
+            key_velocity_curve: KeyVelocityCurve::default(),
+            hover_tooltip_delay: None,
+            note_tooltip_hover: None,
+            edit_bounds: None,
+            allowed_keys: None,
+            scale_highlight: None,
+            snap_to_scale: false,
+            reference_waveform: None,
+            computer_keyboard_input: false,
+            recording: false,
+            record_take: None,
+            record_quantize: false,
+            computer_keyboard_base: COMPUTER_KEYBOARD_DEFAULT_BASE,
+            computer_keyboard_held: Vec::new(),
+            ghost: None,
+            step_input: false,
+            step_cursor: 0,
+            step_chord_held: false,
+            step_modifier_down: false,
+            show_reference_waveform: true,
+            show_note_names: false,
+            show_note_velocities: false,
+            color_by_velocity: false,
+            color_by_effective_velocity: false,
+            show_minimap: false,
+            show_event_list: false,
+            middle_c_octave: 4,
+            auto_trim_overlaps: false,
+            event_list_sort: (EventListColumn::Start, false),
+            drum_map: None,
+            theme: None,
+            palette: MidiEditorTheme::default(),
+            notes_revision: 0,
+            note_mesh: None,
+            note_index: RefCell::new(HashMap::new()),
+            minimap: None,
+            minimap_drag: None,
+            minimap_rect: None,
+            zoom_request: None,
+            toast: None,
+            debug_repaint_counter: false,
+            repaint_requests: 0,
+            note_mesh_rebuilds: 0,
+            max_pending_events: 4096,
+            state_replaced_on_edit: false,
+            max_clipboard_notes: 100_000,
+            max_undo_entries: 64,
+            events_overflowed: false,
+            piano_roll_rect: None,
+            velocity_lane_rect: None,
             transport_override: None,
+            sync: None,
             pending_events: Vec::new(),
             event_listener: None,
             clipboard: Vec::new(),
+            clipboard_groups: Vec::new(),
+            curve_clipboard: None,
+            clipboard_curves: Vec::new(),
+            curve_paste_overwrite: true,
+            paste_mode: PasteMode::Insert,
+            system_clipboard: true,
+            clipboard_export_pending: false,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
+            undo_group: None,
+            reported_history: (0, 0),
+            reported_view: None,
+            next_revision: 1,
+            history_floor: 0,
+            saved_revision: 0,
+            reported_dirty: false,
             drag_changed_note: false,
             lane_edit_state: None,
             lane_edit_changed: false,
             selected_curve_lane: None,
             dragging_curve_point: None,
+            selected_curve_points: Vec::new(),
+            curve_context_tick: None,
+            curve_context_point: None,
+            curve_tool: CurveTool::Point,
+            curve_stroke: None,
+            curve_lane_rect: None,
+            custom_cc_number: 1,
             curve_lane_height: 120.0,
             curve_lane_visible: true,
+            lower_lane_view: LowerLaneView::Curves,
             dragging_splitter: false,
             splitter_ratio: 0.7, // 70% for piano roll, 30% for curve editor
             show_batch_transform_dialog: false,
             batch_transform_type: BatchTransformType::VelocityOffset,
             batch_transform_value: 0.0,
+            batch_ramp_selected: false,
+            batch_ramp_start: 40,
+            batch_ramp_end: 110,
+            batch_ramp_curve: RampCurve::Linear,
+            batch_stretch_selected: false,
+            batch_stretch_ratio: 2.0,
+            batch_stretch_anchor: StretchAnchor::SelectionStart,
+            batch_stretch_curves: true,
             swing_menu_ratio: 0.0,
+            arp_octaves: 1,
+            arp_gate: 0.9,
             swing_original_notes: Vec::new(),
+            show_quantize_dialog: false,
+            quantize_targets: QuantizeTargets::Starts,
+            quantize_strength: 1.0,
+            quantize_interval: 120,
+            show_humanize_dialog: false,
+            humanize_time_ticks: 10,
+            humanize_time_fraction: 0.1,
+            humanize_time_relative: true,
+            humanize_velocity_range: 5,
+            humanize_delay_only: false,
+            humanize_seed: fastrand::u64(..),
+            show_tpb_dialog: false,
+            tpb_dialog_value: 480,
+            text_event_draft: (TextEventKind::Lyric, String::new()),
+            show_invert_dialog: false,
+            invert_axis_key: 60,
+            show_select_by_dialog: false,
+            select_by_velocity: (true, 1, 40),
+            select_by_key: (false, 0, 127),
+            select_by_duration: (false, 1, 480),
+            show_label_dialog: false,
+            label_dialog_text: String::new(),
             context_menu_pos: None,
             context_menu_open_pos: None,
+            context_menu_note: None,
+            context_menu_anchor: None,
             show_playback_settings: false,
             enable_space_playback: true, // Default enabled
+            shortcuts: ShortcutMap::default(),
+            duplicate_drag_modifiers: Modifiers::COMMAND | Modifiers::ALT,
+            note_length: NoteLength::Snap,
+            default_velocity: 100,
+            last_note_length: None,
+            tap_times: Vec::new(),
+            tap_bpm: None,
+            tap_undo_revision: None,
         }
     }
 
@@ -225,6 +827,7 @@ impl MidiEditor {
         self.zoom_y = options.zoom_y;
         self.manual_scroll_x = options.manual_scroll_x;
         self.manual_scroll_y = options.manual_scroll_y;
+        self.snap_resolution = options.snap_resolution;
         self.snap_interval = options.snap_interval.max(1);
         self.snap_mode = options.snap_mode;
         // TODO: Implement swing rhythm feature
@@ -233,15 +836,77 @@ impl MidiEditor {
         self.preview_pitch_shift = options.preview_pitch_shift.clamp(-24.0, 24.0);
         self.loop_enabled = options.loop_enabled;
         self.loop_start_tick = options.loop_start_tick;
-        self.loop_end_tick = options.loop_end_tick.max(self.loop_start_tick + 1);
+        self.loop_end_tick = options
+            .loop_end_tick
+            .unwrap_or(self.loop_start_tick + self.state.ticks_per_measure())
+            .max(self.loop_start_tick + 1);
+        self.metronome_enabled = options.metronome;
+        self.metronome_volume = options.metronome_volume.clamp(0.0, 1.0);
+        self.playback_rate = options.playback_rate.clamp(PLAYBACK_RATE_RANGE.0, PLAYBACK_RATE_RANGE.1);
         if let Some(playback) = &self.playback {
             playback.set_volume(self.volume * 2.0);
             playback.set_pitch_shift(self.preview_pitch_shift);
+            playback.set_click_volume(self.metronome_volume);
         }
         if let Some(key) = options.center_on_key {
             self.center_on_key(key);
         }
         self.enable_space_playback = options.enable_space_playback;
+        self.shortcuts = options.shortcuts.clone();
+        self.duplicate_drag_modifiers = options.duplicate_drag_modifiers;
+        self.note_length = options.note_length;
+        self.default_velocity = options.default_velocity.clamp(1, 127);
+        self.trigger_notes_under_playhead = options.trigger_notes_under_playhead;
+        self.curve_paste_overwrite = options.curve_paste_overwrite;
+        self.paste_mode = options.paste_mode;
+        self.system_clipboard = options.system_clipboard;
+        self.key_velocity_curve = options.key_velocity_curve;
+        self.hover_tooltip_delay = options.hover_tooltip_delay;
+        self.audition_on_select = options.audition_on_select;
+        self.audition_duration = options.audition_duration.max(0.0);
+        self.debug_repaint_counter = options.debug_repaint_counter;
+        self.max_pending_events = options.max_pending_events;
+        self.state_replaced_on_edit = options.state_replaced_on_edit;
+        self.max_clipboard_notes = options.max_clipboard_notes;
+        self.max_undo_entries = options.max_undo_entries.max(1);
+        self.set_scale_highlight(options.scale_highlight);
+        self.snap_to_scale = options.snap_to_scale;
+        self.computer_keyboard_input = options.computer_keyboard_input;
+        self.record_quantize = options.record_quantize;
+        self.show_note_names = options.show_note_names;
+        self.show_note_velocities = options.show_note_velocities;
+        self.color_by_velocity = options.color_by_velocity;
+        self.color_by_effective_velocity = options.color_by_effective_velocity;
+        self.show_minimap = options.show_minimap;
+        self.show_event_list = options.show_event_list;
+        self.middle_c_octave = options.middle_c_octave;
+        self.auto_trim_overlaps = options.auto_trim_overlaps;
+        self.note_double_click = options.note_double_click;
+        self.drum_map = options.drum_map.clone();
+        self.theme = options.theme;
+        self.trim_undo_stack();
+        Self::trim_front(&mut self.redo_stack, self.max_undo_entries);
+    }
+
+    /// 启用 `debug_repaint_counter` 后编辑器累计发出的重绘请求次数
+    pub fn repaint_request_count(&self) -> u64 {
+        self.repaint_requests
+    }
+
+    /// 音符网格累计重建的次数；平移视图时应沿用缓存，用于检查绘制开销
+    pub fn note_mesh_rebuild_count(&self) -> u64 {
+        self.note_mesh_rebuilds
+    }
+
+    /// 请求重绘：`after` 为 `None` 时下一帧立即重绘，否则在给定时长后重绘
+    fn request_repaint(&mut self, ctx: &Context, after: Option<std::time::Duration>) {
+        match after {
+            Some(delay) => ctx.request_repaint_after(delay),
+            None => ctx.request_repaint(),
+        }
+        if self.debug_repaint_counter {
+            self.repaint_requests += 1;
+        }
     }
 
     pub fn set_event_listener<F>(&mut self, listener: F)
@@ -251,12 +916,117 @@ impl MidiEditor {
         self.event_listener = Some(Box::new(listener));
     }
 
+    /// 把另一个 MIDI 内容合并进当前编辑器（而不是替换），返回导入的音符数
+    ///
+    /// 整个合并是一次撤销；每个导入的音符发出 `NoteAdded`，导入的音符成为新的选区。
+    /// 与粘贴一样遵守编辑范围（整体平移进范围）和允许音高（吸附到最近的允许音高，附近没有时丢弃）；
+    /// 打开 `auto_trim_overlaps` 时与拖动一样截短或删除被导入音符覆盖的同音高音符。
+    pub fn import_notes(&mut self, state: MidiState, options: ImportMergeOptions) -> usize {
+        let (source_tpb, target_tpb) = (state.ticks_per_beat, self.state.ticks_per_beat);
+        let scale = |tick: u64| {
+            if options.rescale_ticks {
+                rescale_tick(tick, source_tpb, target_tpb)
+            } else {
+                tick
+            }
+        };
+        let templates: Vec<Note> = state
+            .notes
+            .iter()
+            .filter_map(|note| {
+                let key = note.key as i16 + options.transpose as i16;
+                let key = u8::try_from(key).ok().filter(|key| *key <= 127)?;
+                let start = scale(note.start);
+                let duration = scale(note.start + note.duration).saturating_sub(start).max(1);
+                Some(Note { label: note.label, ..Note::new(start, duration, key, note.velocity) })
+            })
+            .collect();
+        let has_curves = options.include_curves && state.curves.iter().any(|lane| !lane.points.is_empty());
+        if templates.is_empty() && !has_curves {
+            return 0;
+        }
+
+        let base = match options.placement {
+            ImportPlacement::Playhead => self.current_tick_position(),
+            ImportPlacement::Start => 0,
+        };
+        let min_start = templates.iter().map(|n| n.start).min().unwrap_or(0);
+        let block_start = self.clamp_start_to_bounds(base + min_start, Self::block_span(&templates));
+        let offset = block_start.saturating_sub(min_start);
+
+        self.push_undo_snapshot();
+        let previous = self.selected_notes.clone();
+        self.selected_notes.clear();
+        let mut imported = 0;
+        for template in templates {
+            let Some(key) = self.constrain_key(template.key) else {
+                continue;
+            };
+            let note = Note { key, start: template.start + offset, track: self.active_track, ..template };
+            self.state.notes.push(note);
+            self.emit_note_added(note);
+            self.selected_notes.insert(note.id);
+            imported += 1;
+        }
+        self.sort_notes();
+        if self.auto_trim_overlaps {
+            let imported_ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+            self.trim_overlaps_under(&imported_ids);
+        }
+        self.sync_sounding_notes();
+        if has_curves {
+            for lane in &state.curves {
+                self.import_curve_lane(lane, offset, &scale);
+            }
+        }
+        self.notify_selection_changed(previous);
+        imported
+    }
+
+    /// 合并导入一条曲线：已有同类型通道时加入其中，否则新建通道
+    fn import_curve_lane(&mut self, source: &CurveLane, offset: u64, scale: &impl Fn(u64) -> u64) {
+        if source.points.is_empty() {
+            return;
+        }
+        match self.state.curves.iter_mut().find(|lane| lane.lane_type == source.lane_type) {
+            Some(lane) => {
+                let added = source
+                    .points
+                    .iter()
+                    .map(|point| lane.insert_point(scale(point.tick) + offset, point.value).id)
+                    .collect();
+                let lane_id = lane.id;
+                self.emit_event(EditorEvent::CurvePointsPasted { lane_id, removed: Vec::new(), added });
+            }
+            None => {
+                let mut lane = CurveLane {
+                    name: source.name.clone(),
+                    enabled: source.enabled,
+                    interpolation: source.interpolation,
+                    ..CurveLane::new(source.lane_type)
+                };
+                for point in &source.points {
+                    lane.insert_point(scale(point.tick) + offset, point.value);
+                }
+                let lane_id = lane.id;
+                self.state.curves.push(lane);
+                self.emit_event(EditorEvent::CurveLaneAdded(lane_id));
+            }
+        }
+    }
+
     pub fn replace_state(&mut self, state: MidiState) {
         self.state = state;
         self.selected_notes.clear();
+        self.active_track = 0;
+        self.hidden_tracks.clear();
+        self.sync_sounding_notes();
         self.emit_state_replaced();
         self.undo_stack.clear();
         self.redo_stack.clear();
+        self.history_base = self.state.clone();
+        self.history_floor = self.next_revision;
+        self.next_revision += 1;
     }
 
     pub fn snapshot_state(&self) -> MidiState {
@@ -267,10 +1037,81 @@ impl MidiEditor {
         &self.state
     }
 
+    /// 可编辑的轨道（`Note::track`）；单轨状态下所有音符都可编辑
+    pub fn active_track(&self) -> u16 {
+        self.active_track
+    }
+
+    /// 切换可编辑的轨道：其他轨道的音符移出选区，目标轨道若被隐藏则重新显示
+    pub fn set_active_track(&mut self, track: u16) {
+        if track as usize >= self.state.tracks.len().max(1) || track == self.active_track {
+            return;
+        }
+        self.active_track = track;
+        self.hidden_tracks.remove(&track);
+        let previous = self.selected_notes.clone();
+        let editable: BTreeSet<NoteId> = self
+            .state
+            .notes
+            .iter()
+            .filter(|n| n.track == track)
+            .map(|n| n.id)
+            .collect();
+        self.selected_notes.retain(|id| editable.contains(id));
+        self.notify_selection_changed(previous);
+    }
+
+    /// 显示或隐藏一条轨道；隐藏的轨道不绘制也不播放，可编辑的轨道不能隐藏
+    pub fn set_track_visible(&mut self, track: u16, visible: bool) {
+        if visible {
+            self.hidden_tracks.remove(&track);
+        } else if track != self.active_track {
+            self.hidden_tracks.insert(track);
+            self.release_stale_sounding_notes();
+        }
+    }
+
+    pub fn is_track_visible(&self, track: u16) -> bool {
+        !self.hidden_tracks.contains(&track)
+    }
+
+    /// 撤销、重做可能减少轨道数，越界时回到第一条轨道
+    fn clamp_active_track(&mut self) {
+        if self.active_track as usize >= self.state.tracks.len().max(1) {
+            self.active_track = 0;
+        }
+        self.hidden_tracks.retain(|track| (*track as usize) < self.state.tracks.len());
+    }
+
+    /// 音符是否属于可编辑的轨道
+    fn is_note_editable(&self, note: &Note) -> bool {
+        !self.state.is_multi_track() || note.track == self.active_track
+    }
+
+    /// 音符的填充色：按力度着色时映射（可选为力度曲线生效后的）力度，否则区分选中状态
+    fn note_fill(&self, theme: &MidiEditorTheme, note: &Note, selected: bool) -> Color32 {
+        if !self.color_by_velocity {
+            return render::note_fill(theme, selected);
+        }
+        let velocity = if self.color_by_effective_velocity {
+            self.state.apply_velocity_curve_to_note(note)
+        } else {
+            note.velocity
+        };
+        render::velocity_fill(theme, velocity)
+    }
+
+    /// 音符是否应发声（所在轨道未隐藏）
+    fn is_note_audible(&self, note: &Note) -> bool {
+        !self.hidden_tracks.contains(&note.track)
+    }
+
     pub fn edit_state<F: FnOnce(&mut MidiState)>(&mut self, f: F) {
         self.push_undo_snapshot();
         f(&mut self.state);
-        self.emit_state_replaced();
+        self.sync_sounding_notes();
+        self.prune_note_groups();
+        self.emit_edit_event(EditorEvent::StateEdited);
     }
 
     pub fn set_bpm(&mut self, bpm: f32) {
@@ -278,8 +1119,59 @@ impl MidiEditor {
         if (self.state.bpm - clamped).abs() > f32::EPSILON {
             self.push_undo_snapshot();
             self.state.bpm = clamped;
-            self.pending_events
-                .push(EditorEvent::StateReplaced(self.state.clone()));
+            self.emit_edit_event(EditorEvent::TempoChanged);
+        }
+    }
+
+    /// 敲击测速：记录一次敲击（`now` 为秒，例如 `ui.input(|i| i.time)`），返回本轮算出的速度
+    ///
+    /// 与上一次敲击相隔超过 2 秒时重新开始一轮。取最近 8 次敲击的间隔，去掉与中位数相差超过 25% 的间隔后求平均；
+    /// 从第 4 次敲击起把结果设为播放头处的速度（与工具栏 BPM 相同），同一轮的敲击合并为一个撤销步骤。
+    pub fn tap_tempo(&mut self, now: f64) -> Option<f32> {
+        if self.tap_times.last().is_some_and(|&last| now <= last || now - last > TAP_TEMPO_TIMEOUT) {
+            self.tap_times.clear();
+            self.tap_bpm = None;
+            self.tap_undo_revision = None;
+        }
+        self.tap_times.push(now);
+        if self.tap_times.len() > TAP_TEMPO_MAX_TAPS {
+            self.tap_times.remove(0);
+        }
+        if self.tap_times.len() < TAP_TEMPO_MIN_TAPS {
+            return None;
+        }
+        let mut intervals: Vec<f64> = self.tap_times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        intervals.sort_by(|a, b| a.total_cmp(b));
+        let median = intervals[intervals.len() / 2];
+        let kept: Vec<f64> = intervals.into_iter().filter(|interval| (interval - median).abs() <= median * 0.25).collect();
+        let average = kept.iter().sum::<f64>() / kept.len() as f64;
+        let bpm = ((60.0 / average) as f32).clamp(20.0, 400.0);
+        self.tap_bpm = Some(bpm);
+
+        let tick = self.current_tick_position();
+        let continuing = self.tap_undo_revision.is_some_and(|revision| {
+            self.undo_stack.last().is_some_and(|entry| entry.revision == revision && entry.change.is_none())
+        });
+        if continuing {
+            self.state.set_tempo_at(tick, bpm);
+            self.emit_edit_event(EditorEvent::TempoChanged);
+        } else {
+            let before = self.undo_stack.last().map(|entry| entry.revision);
+            self.set_tempo_at(tick, bpm);
+            // 速度没有变化时不会压入撤销步骤，此时不能并入别的步骤
+            let after = self.undo_stack.last().map(|entry| entry.revision);
+            self.tap_undo_revision = after.filter(|_| after != before);
+        }
+        Some(bpm)
+    }
+
+    /// 设置 `tick` 所在速度段的速度（工具栏 BPM 编辑播放头处的速度）
+    pub fn set_tempo_at(&mut self, tick: u64, bpm: f32) {
+        let clamped = bpm.clamp(20.0, 400.0);
+        if (self.state.tempo_at(tick) - clamped).abs() > f32::EPSILON {
+            self.push_undo_snapshot();
+            self.state.set_tempo_at(tick, clamped);
+            self.emit_edit_event(EditorEvent::TempoChanged);
         }
     }
 
@@ -289,3043 +1181,10044 @@ impl MidiEditor {
         if self.state.time_signature != (numer, denom) {
             self.push_undo_snapshot();
             self.state.time_signature = (numer, denom);
-            self.pending_events
-                .push(EditorEvent::StateReplaced(self.state.clone()));
+            self.emit_edit_event(EditorEvent::TimeSignatureChanged { numer, denom });
         }
     }
 
-    pub fn set_volume(&mut self, volume: f32) {
-        let normalized = volume.clamp(0.0, 1.0);
-        if (self.volume - normalized).abs() > f32::EPSILON {
-            self.volume = normalized;
-            if let Some(playback) = &self.playback {
-                playback.set_volume(self.volume * 2.0);
-            }
-        }
+    /// 当前的音高名称写法：状态里的调号与 `MidiEditorOptions::middle_c_octave`
+    pub fn pitch_naming(&self) -> tooltip::PitchNaming {
+        tooltip::PitchNaming { key_signature: self.state.key_signature, middle_c_octave: self.middle_c_octave }
     }
 
-    pub fn set_snap_interval(&mut self, tick_span: u64) {
-        if tick_span != 0 {
-            self.snap_interval = tick_span;
+    /// 设置调号（影响音高名称的拼写），可以撤销
+    pub fn set_key_signature(&mut self, key_signature: KeySignature) {
+        if self.state.key_signature != key_signature {
+            self.push_undo_snapshot();
+            self.state.key_signature = key_signature;
+            self.emit_edit_event(EditorEvent::KeySignatureChanged(key_signature));
         }
     }
 
-    pub fn load_from_smf(&mut self, smf: &Smf) {
-        self.replace_state(MidiState::from_smf(smf));
+    /// 添加歌词或文本事件，排在同一 tick 已有事件之后；可以撤销，返回其在 `text_events` 中的下标
+    pub fn add_text_event(&mut self, event: TextEvent) -> usize {
+        self.push_undo_snapshot();
+        let index = self.state.insert_text_event(event);
+        self.emit_edit_event(EditorEvent::TextEventsChanged);
+        index
     }
 
-    pub fn export_smf(&self) -> Smf<'static> {
-        self.state.to_smf()
+    /// 替换 `text_events[index]`（tick 改变时重新排序），可以撤销
+    pub fn set_text_event(&mut self, index: usize, event: TextEvent) {
+        let Some(current_tick) = self.state.text_events.get(index).filter(|e| **e != event).map(|e| e.tick) else {
+            return;
+        };
+        self.push_undo_snapshot();
+        if current_tick == event.tick {
+            self.state.text_events[index] = event;
+        } else {
+            self.state.text_events.remove(index);
+            self.state.insert_text_event(event);
+        }
+        self.emit_edit_event(EditorEvent::TextEventsChanged);
     }
 
-    pub fn insert_note(&mut self, note: Note) -> NoteId {
+    /// 删除 `text_events[index]`，可以撤销
+    pub fn remove_text_event(&mut self, index: usize) -> Option<TextEvent> {
+        if index >= self.state.text_events.len() {
+            return None;
+        }
         self.push_undo_snapshot();
-        self.state.notes.push(note);
-        self.sort_notes();
-        self.emit_note_added(note);
-        note.id
+        let removed = self.state.text_events.remove(index);
+        self.emit_edit_event(EditorEvent::TextEventsChanged);
+        Some(removed)
     }
 
-    pub fn remove_notes(&mut self, ids: impl IntoIterator<Item = NoteId>) {
-        use std::collections::HashSet;
-        let targets: HashSet<_> = ids.into_iter().collect();
-        if targets.is_empty() {
-            return;
-        }
+    /// 在 `tick` 处添加标记，可以撤销
+    pub fn add_marker(&mut self, tick: u64, label: impl Into<String>, color: [u8; 3]) -> MarkerId {
+        let marker = Marker::new(tick, label, color);
+        let marker_id = marker.id;
         self.push_undo_snapshot();
-        let mut removed = Vec::new();
-        self.state.notes.retain(|note| {
-            if targets.contains(&note.id) {
-                removed.push(*note);
-                false
-            } else {
-                true
-            }
-        });
-        for note in removed {
-            self.emit_note_deleted(note);
-            self.selected_notes.remove(&note.id);
-        }
+        self.state.insert_marker(marker);
+        self.emit_event(EditorEvent::MarkerAdded { marker_id });
+        marker_id
     }
 
-    pub fn clear(&mut self) {
-        if self.state.notes.is_empty() {
+    /// 把标记移到 `tick`（保持按 tick 排序），可以撤销
+    pub fn move_marker(&mut self, marker_id: MarkerId, tick: u64) {
+        if !self.state.markers.iter().any(|m| m.id == marker_id && m.tick != tick) {
             return;
         }
         self.push_undo_snapshot();
-        // Collect notes to emit events before clearing to avoid unnecessary clone
-        let notes_to_delete: Vec<Note> = self.state.notes.iter().copied().collect();
-        for note in notes_to_delete {
-            self.emit_note_deleted(note);
-        }
-        self.state.notes.clear();
-        self.selected_notes.clear();
+        self.place_marker(marker_id, tick);
+        self.emit_event(EditorEvent::MarkerMoved { marker_id, tick });
     }
 
-    pub fn undo(&mut self) -> bool {
-        if let Some(previous) = self.undo_stack.pop() {
-            self.redo_stack.push(self.state.clone());
-            self.state = previous;
-            self.emit_state_replaced();
-            self.selected_notes.clear();
-            return true;
-        }
-        false
+    /// 删除标记，可以撤销
+    pub fn remove_marker(&mut self, marker_id: MarkerId) -> Option<Marker> {
+        let index = self.state.markers.iter().position(|m| m.id == marker_id)?;
+        self.push_undo_snapshot();
+        let removed = self.state.markers.remove(index);
+        self.emit_event(EditorEvent::MarkerRemoved { marker_id });
+        Some(removed)
     }
 
-    pub fn redo(&mut self) -> bool {
-        if let Some(next) = self.redo_stack.pop() {
-            self.undo_stack.push(self.state.clone());
-            self.state = next;
-            self.emit_state_replaced();
-            self.selected_notes.clear();
-            return true;
+    /// 改变标记的 tick 并重新插入到有序位置，不记录撤销也不发事件
+    fn place_marker(&mut self, marker_id: MarkerId, tick: u64) {
+        if let Some(index) = self.state.markers.iter().position(|m| m.id == marker_id) {
+            let mut marker = self.state.markers.remove(index);
+            marker.tick = tick;
+            self.state.insert_marker(marker);
         }
-        false
     }
 
-    pub fn set_playback_backend(&mut self, backend: Option<PlaybackHandle>) {
-        self.playback = backend;
-        if let Some(playback) = &self.playback {
-            playback.set_volume(self.volume * 2.0);
-            playback.set_pitch_shift(self.preview_pitch_shift);
+    /// 时间轴上 `x` 附近的标记（按屏幕距离取最近的一个）
+    fn marker_near_x(&self, x: f32, note_offset_x: f32) -> Option<MarkerId> {
+        self.state
+            .markers
+            .iter()
+            .map(|m| (m.id, (note_offset_x + m.tick as f32 / self.state.ticks_per_beat as f32 * self.zoom_x - x).abs()))
+            .filter(|(_, distance)| *distance <= MARKER_GRAB)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id)
+    }
+
+    /// 结束标记拖动：位置改变时发出 `MarkerMoved`，否则丢弃按下时记录的撤销快照
+    fn finish_marker_drag(&mut self) {
+        let Some((marker_id, original_tick)) = self.dragging_marker.take() else {
+            return;
+        };
+        self.drag_action = DragAction::None;
+        match self.state.markers.iter().find(|m| m.id == marker_id) {
+            Some(marker) if marker.tick != original_tick => {
+                let tick = marker.tick;
+                self.emit_event(EditorEvent::MarkerMoved { marker_id, tick });
+                // 立即结束这一步：同一帧卷帘的拖动收尾会撤回尚未结束的快照
+                self.close_undo_step();
+            }
+            _ => {
+                self.discard_undo_snapshot();
+            }
         }
     }
 
-    pub fn set_playback_observer(&mut self, observer: Option<Arc<dyn PlaybackObserver>>) {
-        self.playback_observer = observer;
+    /// 更改每拍 tick 数（PPQ）并按比例换算所有位置（`MidiState::rescale_ticks_per_beat`），
+    /// 循环区、自定义吸附间隔、剪贴板等编辑器里以 tick 计的值一并换算；可以撤销
+    pub fn rescale_ticks_per_beat(&mut self, new_tpb: u16) {
+        let (from, to) = (self.state.ticks_per_beat, new_tpb.max(1));
+        if from == to {
+            return;
+        }
+        self.push_undo_snapshot();
+        self.state.rescale_ticks_per_beat(to);
+        self.sort_notes();
+        self.rescale_editor_ticks(from, to);
+        self.sync_sounding_notes();
+        self.emit_edit_event(EditorEvent::TicksPerBeatChanged { from, to });
+        self.emit_transport_event();
     }
 
-    pub fn take_events(&mut self) -> Vec<EditorEvent> {
-        std::mem::take(&mut self.pending_events)
+    /// 换算编辑器自身保存的 tick 值；撤销或重做改变了 PPQ 时同样调用
+    fn rescale_editor_ticks(&mut self, from: u16, to: u16) {
+        let rescale = |tick: u64| rescale_tick(tick, from, to);
+        self.loop_start_tick = rescale(self.loop_start_tick);
+        self.loop_end_tick = rescale(self.loop_end_tick).max(self.loop_start_tick + 1);
+        self.last_tick = rescale(self.last_tick);
+        self.step_cursor = rescale(self.step_cursor);
+        self.edit_bounds = self.edit_bounds.map(|(start, end)| (rescale(start), rescale(end).max(rescale(start) + 1)));
+        if self.snap_interval > 1 {
+            self.snap_interval = rescale(self.snap_interval).max(1);
+        }
+        self.quantize_interval = rescale(self.quantize_interval).max(1);
+        self.humanize_time_ticks = rescale(self.humanize_time_ticks);
+        self.last_note_length = self.last_note_length.map(|length| rescale(length).max(1));
+        for note in &mut self.clipboard {
+            let start = rescale(note.start);
+            note.duration = rescale(note.start + note.duration).saturating_sub(start).max(1);
+            note.start = start;
+        }
+        for clip in self.clipboard_curves.iter_mut().chain(&mut self.curve_clipboard) {
+            *clip = clip.rescaled(rescale);
+        }
     }
 
-    pub fn set_transport_state(&mut self, state: Option<TransportState>) {
-        self.transport_override = state;
+    /// 循环区整体按自身长度后移或前移（起点不小于 0），发出 `TransportChanged`
+    pub fn shift_loop(&mut self, forward: bool) {
+        let length = self.loop_end_tick.saturating_sub(self.loop_start_tick).max(1);
+        let start = if forward {
+            self.loop_start_tick.saturating_add(length)
+        } else {
+            self.loop_start_tick.saturating_sub(length)
+        };
+        self.set_loop_range(start, start.saturating_add(length));
     }
 
-    pub fn center_on_c4(&mut self) {
-        self.center_on_key(60);
+    /// 以起点为锚把循环长度加倍或减半（至少 1 tick），发出 `TransportChanged`
+    pub fn scale_loop(&mut self, double: bool) {
+        let length = self.loop_end_tick.saturating_sub(self.loop_start_tick).max(1);
+        let length = if double { length.saturating_mul(2) } else { (length / 2).max(1) };
+        self.set_loop_range(self.loop_start_tick, self.loop_start_tick.saturating_add(length));
     }
 
-    pub fn center_on_key(&mut self, key: u8) {
-        let key = key.min(127);
-        let position_from_top = (127 - key) as f32 * self.zoom_y;
-        let approximate_view_half = 300.0;
-        let desired_offset = position_from_top - approximate_view_half;
-        self.manual_scroll_y = -desired_offset.max(0.0);
+    /// 把循环区设为选中音符的范围，向外取整到小节线，并开启循环；没有选区时不变
+    pub fn set_loop_to_selection(&mut self) {
+        let notes = self.selected_notes_snapshot();
+        let (Some(start), Some(end)) = (
+            notes.iter().map(|n| n.start).min(),
+            notes.iter().map(|n| n.start + n.duration).max(),
+        ) else {
+            return;
+        };
+        let measure = self.state.ticks_per_measure().max(1);
+        let end = end.max(start + 1).div_ceil(measure) * measure;
+        if !self.loop_enabled {
+            self.loop_enabled = true;
+            self.emit_transport_event();
+        }
+        self.set_loop_range(start / measure * measure, end);
     }
 
-    fn seek_to_seconds(&mut self, seconds: f32) {
-        let seconds = seconds.max(0.0);
-        self.current_time = seconds;
-        if self.state.ticks_per_beat > 0 {
-            let seconds_per_beat = 60.0 / self.state.bpm.max(1.0);
-            let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-            self.last_tick = (self.current_time / seconds_per_tick) as u64;
-        } else {
-            self.last_tick = 0;
+    /// 只导出与循环区相交的音符与曲线点：循环起点成为 tick 0，跨越边界的音符被裁到边界上
+    pub fn export_loop_smf(&self) -> Result<Smf<'static>, LoopExportError> {
+        if self.loop_end_tick <= self.loop_start_tick {
+            return Err(LoopExportError::EmptyLoop);
         }
-        self.emit_transport_event();
+        let slice = self.state.slice(self.loop_start_tick..self.loop_end_tick);
+        if slice.notes.is_empty() {
+            return Err(LoopExportError::NoNotes);
+        }
+        Ok(slice.to_smf())
     }
 
-    fn stop_playback_backend(&mut self) {
-        if let Some(playback) = &self.playback {
-            playback.all_notes_off();
+    fn set_loop_range(&mut self, start_tick: u64, end_tick: u64) {
+        if (start_tick, end_tick) != (self.loop_start_tick, self.loop_end_tick) {
+            self.loop_start_tick = start_tick;
+            self.loop_end_tick = end_tick;
+            self.emit_transport_event();
         }
     }
 
-    fn emit_event(&mut self, event: EditorEvent) {
-        if let Some(listener) = &mut self.event_listener {
-            listener(&event);
+    pub fn set_volume(&mut self, volume: f32) {
+        let normalized = volume.clamp(0.0, 1.0);
+        if (self.volume - normalized).abs() > f32::EPSILON {
+            self.volume = normalized;
+            if let Some(playback) = &self.playback {
+                playback.set_volume(self.volume * 2.0);
+            }
         }
-        self.pending_events.push(event);
     }
 
-    fn emit_state_replaced(&mut self) {
-        self.emit_event(EditorEvent::StateReplaced(self.state.clone()));
+    /// 节拍器音量（0.0..=1.0），与音符音量分开
+    pub fn set_metronome_volume(&mut self, volume: f32) {
+        self.metronome_volume = volume.clamp(0.0, 1.0);
+        if let Some(playback) = &self.playback {
+            playback.set_click_volume(self.metronome_volume);
+        }
     }
 
-    fn emit_transport_event(&mut self) {
-        let loop_progress = if self.loop_enabled && self.loop_end_tick > self.loop_start_tick {
-            let loop_duration = self.loop_end_tick - self.loop_start_tick;
-            if loop_duration > 0 {
-                let position_in_loop = if self.last_tick >= self.loop_start_tick {
-                    self.last_tick - self.loop_start_tick
-                } else {
-                    0
-                };
-                (position_in_loop as f32 / loop_duration as f32).clamp(0.0, 1.0)
-            } else {
-                0.0
-            }
-        } else {
-            0.0
-        };
-        
-        self.emit_event(EditorEvent::TransportChanged {
-            current_time: self.current_time,
-            current_tick: self.last_tick,
-            loop_enabled: self.loop_enabled,
-            loop_start_tick: self.loop_start_tick,
-            loop_end_tick: self.loop_end_tick,
-            loop_progress,
-        });
+    pub fn metronome_volume(&self) -> f32 {
+        self.metronome_volume
     }
 
-    pub fn apply_command(&mut self, command: EditorCommand) {
-        match command {
-            EditorCommand::ReplaceState(state) => self.replace_state(state),
-            EditorCommand::SetNotes(notes) => {
-                self.edit_state(|state| {
-                    state.notes = notes;
-                    state.notes.sort_by_key(|n| n.start);
-                });
-            }
-            EditorCommand::AppendNotes(mut notes) => {
-                if notes.is_empty() {
-                    return;
-                }
-                self.edit_state(|state| {
-                    state.notes.append(&mut notes);
-                    state.notes.sort_by_key(|n| n.start);
-                });
-            }
-            EditorCommand::ClearNotes => self.clear(),
-            EditorCommand::SeekSeconds(seconds) => {
-                self.seek_to_seconds(seconds);
-            }
-            EditorCommand::SetPlayback(is_playing) => {
-                if self.is_playing != is_playing {
-                    self.is_playing = is_playing;
-                    if !self.is_playing {
-                        self.stop_playback_backend();
-                        self.notify_playback_stopped();
-                    } else {
-                        self.notify_playback_started();
-                    }
-                    self.emit_event(EditorEvent::PlaybackStateChanged {
-                        is_playing: self.is_playing,
-                    });
-                }
-            }
-            EditorCommand::CenterOnKey(key) => self.center_on_key(key),
-            EditorCommand::SetBpm(bpm) => self.set_bpm(bpm),
-            EditorCommand::SetTimeSignature(numer, denom) => self.set_time_signature(numer, denom),
-            EditorCommand::SetVolume(volume) => self.set_volume(volume),
-            EditorCommand::SetLoop {
-                enabled,
-                start_tick,
-                end_tick,
-            } => {
-                self.loop_enabled = enabled;
-                self.loop_start_tick = start_tick;
-                self.loop_end_tick = end_tick.max(start_tick + 1);
-            }
-            EditorCommand::SetSnap { interval, mode } => {
-                self.snap_interval = interval.max(1);
-                self.snap_mode = mode;
-            }
-            EditorCommand::OverrideTransport(state) => {
-                self.set_transport_state(state);
-            }
-            EditorCommand::AddCurvePoint { lane_id, tick, value } => {
-                self.push_undo_snapshot();
-                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
-                    let point = lane.insert_point(tick, value);
-                    self.emit_event(EditorEvent::CurvePointAdded {
-                        lane_id,
-                        point_id: point.id,
-                    });
-                }
-            }
-            EditorCommand::UpdateCurvePoint {
-                lane_id,
-                point_id,
-                tick,
-                value,
-            } => {
-                self.push_undo_snapshot();
-                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
-                    if lane.update_point(point_id, tick, value).is_some() {
-                        self.emit_event(EditorEvent::CurvePointUpdated {
-                            lane_id,
-                            point_id,
-                        });
-                    }
-                }
-            }
-            EditorCommand::RemoveCurvePoint { lane_id, point_id } => {
-                self.push_undo_snapshot();
-                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
-                    if lane.remove_point(point_id).is_some() {
-                        self.emit_event(EditorEvent::CurvePointRemoved {
-                            lane_id,
-                            point_id,
-                        });
-                    }
-                }
-            }
-            EditorCommand::ToggleCurveLaneEnabled { lane_id } => {
-                self.push_undo_snapshot();
-                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
-                    lane.enabled = !lane.enabled;
-                }
-            }
-            EditorCommand::HumanizeNotes {
-                time_range,
-                velocity_range,
-            } => {
-                if !self.selected_notes.is_empty() {
-                    self.push_undo_snapshot();
-                    let note_ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
-                    self.state.humanize_notes(&note_ids, time_range, velocity_range);
-                    self.emit_state_replaced();
-                }
-            }
-            EditorCommand::BatchTransform {
-                transform_type,
-                value,
-            } => {
-                if !self.selected_notes.is_empty() {
-                    self.push_undo_snapshot();
-                    let note_ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
-                    self.state.batch_transform_notes(&note_ids, transform_type, value);
-                    self.emit_state_replaced();
-                }
-            }
+    /// 播放速率（0.25..=2.0）：走带按 `dt * rate` 推进，曲子的速度不变；发出 `TransportChanged`
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        let rate = rate.clamp(PLAYBACK_RATE_RANGE.0, PLAYBACK_RATE_RANGE.1);
+        if rate != self.playback_rate {
+            self.playback_rate = rate;
+            self.emit_transport_event();
         }
     }
 
-    fn preview_note_on(&mut self, key: u8, velocity: u8) {
-        if let Some(playback) = &self.playback {
-            if let Some(prev) = self.drag_preview_key.take() {
-                playback.note_off(prev);
-            }
-            playback.note_on(key, velocity);
-            self.drag_preview_key = Some(key);
-        }
+    pub fn playback_rate(&self) -> f32 {
+        self.playback_rate
     }
 
-    fn preview_note_off(&mut self) {
-        if let Some(prev) = self.drag_preview_key.take() {
-            if let Some(playback) = &self.playback {
-                playback.note_off(prev);
-            }
+    /// 改用以 tick 为单位的自定义吸附间隔（0 被忽略）
+    pub fn set_snap_interval(&mut self, tick_span: u64) {
+        if tick_span != 0 {
+            self.snap_resolution = None;
+            self.snap_interval = tick_span;
         }
     }
 
-    fn emit_note_added(&mut self, note: Note) {
-        self.emit_event(EditorEvent::NoteAdded(note));
+    /// 当前生效的吸附间隔（tick）：音符时值按曲子的 `ticks_per_beat` 换算，或自定义的 `snap_interval`；0 为不吸附
+    pub fn snap_ticks(&self) -> u64 {
+        match self.snap_resolution {
+            Some((resolution, modifier)) => resolution.ticks(self.state.ticks_per_beat, modifier),
+            None => self.snap_interval,
+        }
     }
 
-    fn emit_note_deleted(&mut self, note: Note) {
-        self.emit_event(EditorEvent::NoteDeleted(note));
+    /// 三连音吸附时网格细分线也按三连音划分
+    fn triplet_grid(&self) -> bool {
+        matches!(self.snap_resolution, Some((_, SnapModifier::Triplet)))
     }
 
-    fn emit_note_updated(&mut self, before: Note, after: Note) {
-        if before != after {
-            self.emit_event(EditorEvent::NoteUpdated { before, after });
+    /// 吸附设置的简写："1/16"、"1/8T"、"Free" 或自定义的 "90 ticks"
+    fn snap_label(&self) -> String {
+        match self.snap_resolution {
+            Some((resolution, modifier)) => format!("{}{}", resolution.label(), modifier.suffix()),
+            None if self.snap_interval <= 1 => "Free".to_owned(),
+            None => format!("{} ticks", self.snap_interval),
         }
     }
 
-    fn finalize_note_drag_if_needed(&mut self) {
-        if self.drag_changed_note {
-            let originals = self.drag_original_notes.clone();
-            for (id, before) in originals {
-                if let Some(idx) = self.note_index_by_id(id) {
-                    let after = self.state.notes[idx];
-                    self.emit_note_updated(before, after);
-                }
-            }
-        }
-        self.drag_original_notes.clear();
-        self.drag_primary_anchor = None;
-        self.drag_changed_note = false;
+    pub fn load_from_smf(&mut self, smf: &Smf) {
+        self.replace_state(MidiState::from_smf(smf));
     }
 
-    #[allow(dead_code)]
-    fn finalize_lane_edit(&mut self) {
-        if let Some(state) = self.lane_edit_state.take() {
-            if self.lane_edit_changed {
-                for (id, before) in state.originals {
-                    if let Some(idx) = self.note_index_by_id(id) {
-                        let after = self.state.notes[idx];
-                        if before != after {
-                            self.emit_note_updated(before, after);
-                        }
-                    }
-                }
+    pub fn export_smf(&self) -> Smf<'static> {
+        self.state.to_smf()
+    }
+
+    /// Renders the piano roll for `tick_range` × `key_range` into an offscreen image.
+    ///
+    /// Uses the same grid/note painting as [`MidiEditor::ui`] (text is omitted) and
+    /// ignores the current scroll, zoom and selection, so output depends only on the state.
+    /// Colors come from `theme`, or the dark theme when it is `None` (the host's visuals are not consulted).
+    /// With `show_velocity_lane` the bottom quarter of the image shows velocity stems.
+    pub fn render_to_image(
+        &self,
+        width: usize,
+        height: usize,
+        tick_range: Range<u64>,
+        key_range: RangeInclusive<u8>,
+        show_velocity_lane: bool,
+    ) -> ColorImage {
+        let tpb = self.state.ticks_per_beat.max(1) as u64;
+        let lane_height = if show_velocity_lane { (height as f32 * 0.25).round() } else { 0.0 };
+        let roll_rect = Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32 - lane_height));
+        let span_beats = tick_range.end.saturating_sub(tick_range.start).max(1) as f32 / tpb as f32;
+        let zoom_x = roll_rect.width() / span_beats;
+        let (low_key, high_key) = (*key_range.start(), (*key_range.end()).min(127));
+        let zoom_y = roll_rect.height() / (high_key.saturating_sub(low_key) as f32 + 1.0);
+        let note_offset_x = -(tick_range.start as f32 / tpb as f32) * zoom_x;
+        let keys_top = -((127 - high_key) as f32) * zoom_y;
+        let theme = self.theme.unwrap_or_default();
+
+        let mut shapes = Vec::new();
+        render::vertical_grid(
+            &mut shapes,
+            &theme,
+            false,
+            note_offset_x,
+            zoom_x,
+            tpb,
+            self.state.ticks_per_measure(),
+            tick_range.start as i64,
+            tick_range.end as i64,
+            roll_rect.x_range(),
+            roll_rect.y_range(),
+        );
+        render::horizontal_grid(&mut shapes, &theme, keys_top, zoom_y, 128, roll_rect.x_range(), roll_rect.y_range());
+
+        let lane_rect = Rect::from_min_max(Pos2::new(0.0, roll_rect.max.y), Pos2::new(width as f32, height as f32));
+        if show_velocity_lane {
+            shapes.push(Shape::rect_filled(lane_rect, 0.0, theme.lane_background));
+        }
+        for note in &self.state.notes {
+            if note.start + note.duration < tick_range.start || note.start > tick_range.end {
+                continue;
+            }
+            let x = note_offset_x + (note.start as f32 / tpb as f32) * zoom_x;
+            if key_range.contains(&note.key) {
+                let y = keys_top + (127 - note.key) as f32 * zoom_y;
+                let w = ((note.duration as f32 / tpb as f32) * zoom_x).max(5.0);
+                let note_rect = Rect::from_min_size(Pos2::new(x, y), Vec2::new(w, zoom_y));
+                render::note(&mut shapes, &theme, note_rect, self.note_fill(&theme, note, false), false);
+            }
+            if show_velocity_lane {
+                render::velocity_stem(&mut shapes, x, note.velocity, lane_rect.shrink2(Vec2::new(0.0, VELOCITY_LANE_PADDING)), theme.note);
             }
         }
-        self.lane_edit_changed = false;
+
+        render::rasterize(&shapes, [width, height], theme.background)
     }
 
-    fn notify_selection_changed(&mut self, previous: BTreeSet<NoteId>) {
-        if previous != self.selected_notes {
-            self.emit_event(EditorEvent::SelectionChanged(
-                self.selected_notes.iter().copied().collect(),
-            ));
-        }
+    /// PNG-encoded variant of [`MidiEditor::render_to_image`].
+    #[cfg(feature = "png")]
+    pub fn render_to_png(
+        &self,
+        width: usize,
+        height: usize,
+        tick_range: Range<u64>,
+        key_range: RangeInclusive<u8>,
+        show_velocity_lane: bool,
+    ) -> image::ImageResult<Vec<u8>> {
+        let color_image = self.render_to_image(width, height, tick_range, key_range, show_velocity_lane);
+        let rgba: Vec<u8> = color_image
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+            .collect();
+        let mut bytes = Vec::new();
+        image::write_buffer_with_format(
+            &mut std::io::Cursor::new(&mut bytes),
+            &rgba,
+            width as u32,
+            height as u32,
+            image::ExtendedColorType::Rgba8,
+            image::ImageFormat::Png,
+        )?;
+        Ok(bytes)
     }
 
-    fn set_single_selection(&mut self, note_id: NoteId) {
-        let prev = self.selected_notes.clone();
-        self.selected_notes.clear();
-        self.selected_notes.insert(note_id);
-        self.notify_selection_changed(prev);
+    /// 限制可编辑区域为 `[start, end)`（tick），`None` 取消限制。
+    ///
+    /// 区域外显示为暗色；新建音符被拒绝，拖动、粘贴和拖放落点被夹到区域内。
+    pub fn set_edit_bounds(&mut self, bounds: Option<(u64, u64)>) {
+        self.edit_bounds = bounds.filter(|(start, end)| end > start);
     }
 
-    fn toggle_selection(&mut self, note_id: NoteId) {
-        let prev = self.selected_notes.clone();
-        if !self.selected_notes.insert(note_id) {
-            self.selected_notes.remove(&note_id);
-        }
-        self.notify_selection_changed(prev);
+    pub fn edit_bounds(&self) -> Option<(u64, u64)> {
+        self.edit_bounds
     }
 
-    fn extend_selection(&mut self, note_id: NoteId) {
-        if self.selected_notes.contains(&note_id) {
-            return;
+    /// 下一帧调整缩放与滚动，使可编辑区域（无限制时为全部音符的时间范围）在水平方向充满视图，
+    /// 并在垂直方向显示全部音符的音高范围。宿主载入文件后可调用它让内容立即可见。
+    pub fn zoom_to_fit(&mut self) {
+        self.zoom_request = Some(ZoomTarget::All);
+    }
+
+    /// 下一帧调整缩放与滚动，使选中音符的时间与音高范围充满视图；没有选中音符时不变。
+    pub fn zoom_to_selection(&mut self) {
+        if !self.selected_notes.is_empty() {
+            self.zoom_request = Some(ZoomTarget::Selection);
         }
-        let prev = self.selected_notes.clone();
-        self.selected_notes.insert(note_id);
-        self.notify_selection_changed(prev);
     }
 
-    pub fn ui(&mut self, ui: &mut Ui) {
-        let total_height = ui.available_height();
-        ui.set_min_height(total_height);
+    /// 将长度为 `span` 的块的起点夹到可编辑区域内
+    fn clamp_start_to_bounds(&self, start: u64, span: u64) -> u64 {
+        match self.edit_bounds {
+            Some((lo, hi)) => start.clamp(lo, hi.saturating_sub(span).max(lo)),
+            None => start,
+        }
+    }
 
-        ui.horizontal(|ui| {
-            ui.set_min_height(total_height);
-            ui.vertical(|ui| {
-                ui.set_min_height(total_height);
-                self.ui_toolbar(ui);
-                ui.separator();
-                
-                // Allocate space for piano roll and curve lanes with draggable splitter
-                // Account for bottom status bar (typically 25-30 pixels)
-                let status_bar_height = 25.0;
-                let available_height_raw = ui.available_height();
-                let remaining_height = (available_height_raw - status_bar_height).max(0.0);
-                let min_piano_height = 200.0;
-                let min_curve_height = 80.0;
-                
-                // Calculate heights based on splitter ratio
-                let piano_roll_height = if self.curve_lane_visible {
-                    (remaining_height * self.splitter_ratio).max(min_piano_height).min(remaining_height)
-                } else {
-                    remaining_height
-                };
-                let curve_height = if self.curve_lane_visible {
-                    (remaining_height - piano_roll_height).max(0.0)
-                } else {
-                    0.0
-                };
-                
-                // Piano roll area
-                let piano_rect = ui.allocate_ui_with_layout(
-                    Vec2::new(ui.available_width(), piano_roll_height),
-                    Layout::top_down(Align::LEFT),
-                    |ui| {
-                        self.ui_piano_roll(ui);
-                    }
-                ).response.rect;
-                
-                // Draggable splitter (only if curve editor is visible)
-                if self.curve_lane_visible {
-                    let splitter_height = 4.0;
-                    let splitter_rect = Rect::from_min_size(
-                        Pos2::new(piano_rect.min.x, piano_rect.max.y),
-                        Vec2::new(piano_rect.width(), splitter_height)
-                    );
-                    
-                    let splitter_response = ui.allocate_rect(splitter_rect, Sense::click_and_drag());
-                    let painter = ui.painter_at(splitter_rect);
-                    
-                    // Draw splitter
-                    painter.rect_filled(splitter_rect, 0.0, Color32::from_rgb(100, 100, 100));
-                    painter.rect_stroke(splitter_rect, 0.0, Stroke::new(1.0, Color32::from_rgb(150, 150, 150)));
-                    
-                    // Handle dragging
-                    if splitter_response.drag_started() {
-                        self.dragging_splitter = true;
-                    }
-                    if self.dragging_splitter {
-                        if let Some(pointer) = ui.input(|i| i.pointer.hover_pos()) {
-                            let new_ratio = ((pointer.y - piano_rect.min.y) / remaining_height)
-                                .clamp(min_piano_height / remaining_height, 1.0 - min_curve_height / remaining_height);
-                            self.splitter_ratio = new_ratio;
-                        }
-                        if ui.input(|i| i.pointer.any_released()) {
-                            self.dragging_splitter = false;
-                        }
-                        ui.ctx().set_cursor_icon(CursorIcon::ResizeVertical);
-                    } else if splitter_response.hovered() {
-                        ui.ctx().set_cursor_icon(CursorIcon::ResizeVertical);
-                    }
-                    
-                    // Curve lanes area
-                    ui.allocate_ui_with_layout(
-                        Vec2::new(ui.available_width(), curve_height),
-                        Layout::top_down(Align::LEFT),
-                        |ui| {
-                            self.ui_curve_lanes(ui);
-                        }
-                    );
-                }
-            });
-            ui.separator();
-            self.ui_inspector(ui, total_height);
+    /// 限制可放置音符的音高（`None` 取消限制）
+    ///
+    /// 新建、拖动、粘贴、拖放和移调都会吸附到最近的允许音高，附近没有允许音高时操作被拒绝；
+    /// 不允许的行显示为斜线阴影。该限制只属于当前编辑会话，不写入 `MidiState`。
+    pub fn set_allowed_keys(&mut self, keys: Option<BitSet128>) {
+        self.allowed_keys = keys;
+    }
+
+    pub fn allowed_keys(&self) -> Option<BitSet128> {
+        self.allowed_keys
+    }
+
+    /// 设置音阶高亮（`(主音音级, 音阶)`，主音按 12 取模），`None` 关闭
+    ///
+    /// 只改变卷帘底色与侧边键盘的主音标记，不限制音符放置（需要限制时配合 `set_allowed_keys`）。
+    pub fn set_scale_highlight(&mut self, scale: Option<(u8, ScaleKind)>) {
+        self.scale_highlight = scale.map(|(root, kind)| (root % 12, kind));
+    }
+
+    pub fn scale_highlight(&self) -> Option<(u8, ScaleKind)> {
+        self.scale_highlight
+    }
+
+    /// 上一帧绘制使用的颜色：`theme`，或由宿主当时的 `egui::Visuals` 推导的主题；宿主可用它绘制风格一致的叠加层
+    pub fn current_theme(&self) -> MidiEditorTheme {
+        self.palette
+    }
+
+    pub fn shortcuts(&self) -> &ShortcutMap {
+        &self.shortcuts
+    }
+
+    /// 运行时修改快捷键绑定，例如 `editor.shortcuts_mut().unbind(ShortcutAction::Delete)`
+    pub fn shortcuts_mut(&mut self) -> &mut ShortcutMap {
+        &mut self.shortcuts
+    }
+
+    /// 吸附到音阶时使用的音阶：需要启用 `snap_to_scale` 并设置了音阶高亮
+    fn scale_snap(&self, disable_snap: bool) -> Option<(u8, ScaleKind)> {
+        self.scale_highlight.filter(|_| self.snap_to_scale && !disable_snap)
+    }
+
+    /// 整组移动音高时 `key` 的新音高：锚点从 `anchor` 移到 `pointer_key`
+    ///
+    /// 吸附到音阶时锚点落在离指针最近的音阶音上，其他音符按相同的级数移动，
+    /// 不在音阶内的音符保留高出下方音阶音的半音数；否则所有音符平移相同的半音数。
+    fn dragged_key(&self, key: u8, anchor: u8, pointer_key: u8, disable_snap: bool) -> u8 {
+        if pointer_key == anchor {
+            return key;
+        }
+        let layout = self.row_layout();
+        if layout.len() < 128 {
+            // 隐藏了行的鼓映射：按可见行移动，而不是按半音
+            let (Some(row), Some(from), Some(to)) = (layout.row_of(key), layout.row_of(anchor), layout.row_of(pointer_key)) else {
+                return key;
+            };
+            return layout.key_at((row as i64 + to as i64 - from as i64).max(0) as f32);
+        }
+        let Some((root, kind)) = self.scale_snap(disable_snap) else {
+            return (key as i16 + pointer_key as i16 - anchor as i16).clamp(0, 127) as u8;
+        };
+        let target = kind.keys(root).nearest(pointer_key, 12).unwrap_or(pointer_key);
+        if key == anchor {
+            return target;
+        }
+        let degree_delta = kind.degree_below(root, target).0 - kind.degree_below(root, anchor).0;
+        let (degree, above) = kind.degree_below(root, key);
+        match kind.key_at_degree(root, degree + degree_delta) {
+            Some(base) => base.saturating_add(above).min(127),
+            None if degree_delta < 0 => 0,
+            None => 127,
+        }
+    }
+
+    /// 设置钢琴卷帘背景中的参考音频波形（`None` 移除）
+    ///
+    /// 波形按秒定位，随起始速度换算到 tick，因此修改速度后仍与音频对齐（速度图中的后续变化不参与换算）；它只用于对照，不响应任何交互。
+    /// 可通过工具栏 ⚙ 菜单或 `show_reference_waveform` 隐藏。
+    pub fn set_reference_waveform(&mut self, waveform: Option<ReferenceWaveform>) {
+        self.reference_waveform = waveform;
+    }
+
+    pub fn reference_waveform(&self) -> Option<&ReferenceWaveform> {
+        self.reference_waveform.as_ref()
+    }
+
+    /// 在可编辑音符之下以半透明灰色显示另一份 MIDI 内容（`None` 移除），例如相邻剪辑的和弦
+    ///
+    /// 幽灵音符按拍对齐（不同的 TPB 自动换算，速度差异不参与换算），不能被选中或拖动，也不会播放或导出；
+    /// `replace_state` 不会清除它。
+    pub fn set_ghost_state(&mut self, state: Option<MidiState>) {
+        self.ghost = state.map(|state| {
+            let mut notes = state.notes;
+            notes.sort_by_key(|note| note.start);
+            GhostLayer { notes, ticks_per_beat: state.ticks_per_beat.max(1) }
         });
+    }
 
-        // Handle playback logic (only if Space key is enabled)
-        if self.enable_space_playback && ui.input(|i| i.key_pressed(Key::Space)) {
-            self.is_playing = !self.is_playing;
-            if self.is_playing {
-                self.last_update = ui.input(|i| i.time);
-                let seconds_per_beat = 60.0 / self.state.bpm;
-                let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-                self.last_tick = (self.current_time / seconds_per_tick) as u64;
-                self.notify_playback_started();
-            } else {
-                self.stop_playback_backend();
-                self.notify_playback_stopped();
+    /// 开关录音模式
+    ///
+    /// 录音且播放时，`feed_live_note_on/off`（以及电脑键盘弹奏）在播放头处写入音符，按住期间音符随播放头延长；
+    /// 停止播放或关闭录音时，仍按住的音符在停止处结束。一次播放期间录入的全部音符只占一个撤销步骤。
+    pub fn set_recording(&mut self, enabled: bool) {
+        if !enabled {
+            self.finish_recording_take();
+        }
+        self.recording = enabled;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// 外部输入（MIDI 设备等）的按下；未在录音或未播放时忽略
+    pub fn feed_live_note_on(&mut self, key: u8, velocity: u8) {
+        if !self.recording || !self.is_playing {
+            return;
+        }
+        let tick = self.current_tick_position();
+        self.feed_live_note_off(key);
+        self.start_recorded_note(key, velocity, tick);
+    }
+
+    /// 外部输入的松开，音符在当前播放头处结束
+    pub fn feed_live_note_off(&mut self, key: u8) {
+        let Some(take) = &mut self.record_take else {
+            return;
+        };
+        let Some(index) = take.held.iter().position(|(held, _)| *held == key) else {
+            return;
+        };
+        let (_, id) = take.held.remove(index);
+        let tick = self.current_tick_position();
+        self.close_recorded_note(id, tick);
+    }
+
+    fn start_recorded_note(&mut self, key: u8, velocity: u8, tick: u64) {
+        if self.edit_bounds.is_some_and(|(lo, hi)| tick < lo || tick >= hi) {
+            return;
+        }
+        let Some(pitch) = self.constrain_key(key) else {
+            return;
+        };
+        let take = self.record_take.get_or_insert_with(RecordTake::default);
+        take.last_tick = tick;
+        if !take.snapshot_pushed {
+            take.snapshot_pushed = true;
+            self.push_undo_snapshot();
+        }
+        let note = Note { track: self.active_track, ..Note::new(tick, 1, pitch, velocity.clamp(1, 127)) };
+        if let Some(take) = &mut self.record_take {
+            take.held.push((key, note.id));
+        }
+        self.state.notes.push(note);
+        self.sort_notes();
+        self.emit_note_added(note);
+    }
+
+    /// 结束录制中的音符；开启 `record_quantize` 时起点与终点吸附到网格
+    fn close_recorded_note(&mut self, id: NoteId, end_tick: u64) {
+        let interval = self.snap_ticks();
+        let quantize = self.record_quantize && interval > 0;
+        let snap = |tick: u64| self.snap_value(tick as i64).max(0) as u64;
+        let bounds = self.edit_bounds;
+        let Some(before) = self.note_by_id(id) else {
+            return;
+        };
+        let (mut start, mut end) = (before.start, end_tick.max(before.start + 1));
+        if quantize {
+            start = snap(start);
+            end = snap(end).max(start + interval);
+        }
+        if let Some((_, hi)) = bounds {
+            start = start.min(hi - 1);
+            end = end.min(hi);
+        }
+        let Some(note) = self.note_mut_by_id(id) else {
+            return;
+        };
+        note.start = start;
+        note.duration = end - start;
+        let after = *note;
+        self.sort_notes();
+        self.emit_note_updated(before, after);
+    }
+
+    /// 播放中每帧调用：按住的音符延长到播放头；播放头回跳（循环）时在跳转前结束并从新位置重新开始
+    fn update_recording(&mut self) {
+        let tick = self.current_tick_position();
+        let Some(take) = &mut self.record_take else {
+            return;
+        };
+        if tick < take.last_tick {
+            let last_tick = take.last_tick;
+            let held = std::mem::take(&mut take.held);
+            for (key, id) in held {
+                let velocity = self.note_by_id(id).map_or(100, |note| note.velocity);
+                self.close_recorded_note(id, last_tick);
+                self.start_recorded_note(key, velocity, tick);
+            }
+        } else {
+            let held: Vec<NoteId> = take.held.iter().map(|(_, id)| *id).collect();
+            let end = self.edit_bounds.map_or(tick, |(_, hi)| tick.min(hi));
+            for id in held {
+                if let Some(note) = self.note_mut_by_id(id) {
+                    note.duration = end.saturating_sub(note.start).max(1);
+                }
             }
-            self.emit_event(EditorEvent::PlaybackStateChanged {
-                is_playing: self.is_playing,
-            });
         }
+        if let Some(take) = &mut self.record_take {
+            take.last_tick = tick;
+        }
+    }
 
-        if self.is_playing {
-            ui.ctx().request_repaint();
-            let now = ui.input(|i| i.time);
-            let dt = now - self.last_update;
-            self.last_update = now;
+    /// 结束本次录音：仍按住的音符在最后录到的位置结束（停止按钮会把播放头移回起点）
+    fn finish_recording_take(&mut self) {
+        let Some(take) = self.record_take.take() else {
+            return;
+        };
+        for (_, id) in take.held {
+            self.close_recorded_note(id, take.last_tick);
+        }
+    }
 
-            if dt > 0.0 && dt < 1.0 {
-                // Avoid large jumps
-                self.current_time += dt as f32;
-                self.update_sequencer();
+    /// 开关步进输入模式
+    ///
+    /// 开启后按下侧边键盘的按键（或 `EditorCommand::StepInput`）在步进光标处插入一个吸附长度的音符，光标前进一步；
+    /// → 键为休止（只前进），← 键后退一步；按住 Shift 输入的音符组成和弦，松开 Shift 后光标才前进。
+    /// 点击时间轴可移动步进光标。
+    pub fn set_step_input(&mut self, enabled: bool) {
+        self.step_input = enabled;
+        self.step_chord_held = false;
+    }
+
+    pub fn step_input_enabled(&self) -> bool {
+        self.step_input
+    }
+
+    pub fn step_cursor(&self) -> u64 {
+        self.step_cursor
+    }
+
+    pub fn set_step_cursor(&mut self, tick: u64) {
+        self.step_cursor = tick;
+        self.step_chord_held = false;
+    }
+
+    /// 步进长度：吸附间隔，关闭吸附时为一拍
+    fn step_length(&self) -> u64 {
+        match self.snap_ticks() {
+            0 => self.state.ticks_per_beat.max(1) as u64,
+            snap => snap,
+        }
+    }
+
+    /// 在步进光标处插入音符；和弦输入中不前进
+    fn step_input_note(&mut self, key: u8, velocity: u8) {
+        if !self.step_input {
+            return;
+        }
+        let start = self.step_cursor;
+        let mut duration = self.step_length();
+        if let Some((lo, hi)) = self.edit_bounds {
+            if start < lo || start >= hi {
+                self.show_toast("Step cursor is outside the editable region");
+                return;
             }
+            duration = duration.min(hi - start);
+        }
+        let Some(key) = self.constrain_key(key) else {
+            self.show_toast("No allowed pitch near this key");
+            return;
+        };
+        let note = Note { track: self.active_track, ..Note::new(start, duration, key, velocity.clamp(1, 127)) };
+        self.insert_note(note);
+        if self.step_modifier_down {
+            self.step_chord_held = true;
         } else {
-            self.last_update = ui.input(|i| i.time);
-            // Update last_tick to match current_time so when we start playing we don't skip or retrigger weirdly
-            // But if we scrub, we might want to silence notes.
+            self.step_cursor += self.step_length();
         }
+    }
 
-        self.handle_shortcuts(ui.ctx());
-        
-        // Context menu for piano roll
-        if let Some(menu_pos) = self.context_menu_pos {
-            let menu_response = egui::Area::new(egui::Id::new("piano_roll_context_menu"))
-                .order(egui::Order::Foreground)
-                .fixed_pos(menu_pos)
-                .show(ui.ctx(), |ui| {
-                    egui::Frame::popup(ui.style()).show(ui, |ui| {
-                        let has_selection = !self.selected_notes.is_empty();
-                        
-                        // Set minimum width for all buttons to ensure consistent width
-                        ui.set_min_width(200.0);
-                        
-                        // Quantize to snap grid
-                        if ui.add_enabled(has_selection && self.snap_interval > 0, egui::Button::new("Quantize to snap grid")
-                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
-                            self.swing_original_notes.clear();
-                            self.swing_menu_ratio = 0.0;
-                            self.quantize_selected_notes();
-                            self.context_menu_pos = None;
-                            self.context_menu_open_pos = None;
-                        }
-                        
-                        ui.separator();
-                        
-                        // Snap Mode and Snap Interval buttons side by side
-                        ui.horizontal(|ui| {
-                            // Snap Mode submenu (adaptive width)
-                            ui.menu_button("Snap Mode", |ui| {
-                                if ui.selectable_label(self.snap_mode == SnapMode::Absolute, "Absolute").clicked() {
-                                    self.apply_command(EditorCommand::SetSnap {
-                                        interval: self.snap_interval,
-                                        mode: SnapMode::Absolute,
-                                    });
-                                    self.context_menu_pos = None;
-                                    self.context_menu_open_pos = None;
-                                }
-                                if ui.selectable_label(self.snap_mode == SnapMode::Relative, "Relative").clicked() {
-                                    self.apply_command(EditorCommand::SetSnap {
-                                        interval: self.snap_interval,
-                                        mode: SnapMode::Relative,
-                                    });
-                                    self.context_menu_pos = None;
-                                    self.context_menu_open_pos = None;
-                                }
-                            });
-                            
-                            // Snap Interval submenu (adaptive width)
-                            ui.menu_button("Snap Interval", |ui| {
-                                let intervals = vec![
-                                    (480 * 4, "1/1"),
-                                    (480 * 2, "1/2"),
-                                    (480, "1/4"),
-                                    (240, "1/8"),
-                                    (120, "1/16"),
-                                    (0, "Free"),
-                                ];
-                                
-                                for (interval, label) in intervals {
-                                    let is_selected = self.snap_interval == interval;
-                                    if ui.selectable_label(is_selected, label).clicked() {
-                                        self.swing_original_notes.clear();
-                                        self.swing_menu_ratio = 0.0;
-                                        self.apply_command(EditorCommand::SetSnap {
-                                            interval: interval.max(1),
-                                            mode: self.snap_mode,
-                                        });
-                                        self.context_menu_pos = None;
-                                        self.context_menu_open_pos = None;
-                                    }
-                                }
-                            });
-                        });
-                        
-                        ui.separator();
-                        
-                        // Humanize
-                        if ui.add_enabled(has_selection, egui::Button::new("Humanize")
-                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+    /// 休止（`forward`）或后退一步；结束正在输入的和弦
+    fn step_move(&mut self, forward: bool) {
+        if !self.step_input {
+            return;
+        }
+        self.step_chord_held = false;
+        let step = self.step_length();
+        self.step_cursor = if forward { self.step_cursor + step } else { self.step_cursor.saturating_sub(step) };
+    }
+
+    /// 每帧记录 Shift 状态；松开 Shift 时结束和弦并前进一步
+    fn update_step_modifier(&mut self, shift: bool) {
+        self.step_modifier_down = shift;
+        if self.step_chord_held && !shift {
+            self.step_chord_held = false;
+            self.step_cursor += self.step_length();
+        }
+    }
+
+    /// 当前的幽灵音符（按起点排序，使用幽灵内容自身的 tick 精度）
+    pub fn ghost_notes(&self) -> &[Note] {
+        self.ghost.as_ref().map_or(&[], |ghost| &ghost.notes)
+    }
+
+    /// 上一帧钢琴卷帘（含左侧键盘与顶部时间轴）的屏幕矩形，第一次调用 `ui()` 之前为 `None`
+    pub fn piano_roll_rect(&self) -> Option<Rect> {
+        self.piano_roll_rect
+    }
+
+    /// 音符网格中 (tick, 音高) 所在行中线的屏幕坐标，基于上一帧的布局和当前缩放/滚动
+    ///
+    /// 在第一次调用 `ui()` 之前，或该音高所在行被鼓映射隐藏时返回 `None`。宿主可用它绘制叠加层或合成输入。
+    pub fn tick_key_to_screen(&self, tick: u64, key: u8) -> Option<Pos2> {
+        let rect = self.piano_roll_rect?;
+        let row = self.row_layout().row_of(key)?;
+        let y = rect.min.y
+            + TIMELINE_HEIGHT
+            + self.manual_scroll_y
+            + row as f32 * self.zoom_y
+            + self.zoom_y * 0.5;
+        Some(Pos2::new(self.tick_to_screen_x(rect, tick), y))
+    }
+
+    /// 音符力度柱区域中 (tick, 力度) 的屏幕坐标，基于上一帧的布局；该区域未显示过时返回 `None`
+    pub fn velocity_lane_to_screen(&self, tick: u64, velocity: u8) -> Option<Pos2> {
+        let rect = self.velocity_lane_rect?;
+        let bars = rect.shrink2(Vec2::new(0.0, VELOCITY_LANE_PADDING));
+        let y = bars.max.y - bars.height() * velocity.min(127) as f32 / 127.0;
+        Some(Pos2::new(self.tick_to_screen_x(rect, tick), y))
+    }
+
+    /// 曲线区域中 (tick, 值) 的屏幕坐标，值按当前显示曲线的取值范围换算；该区域未显示过时返回 `None`
+    pub fn curve_lane_to_screen(&self, tick: u64, value: f32) -> Option<Pos2> {
+        let rect = self.curve_lane_rect?;
+        let lane_id = self.displayed_curve_lane()?;
+        let (min, max) = self.state.curves.iter().find(|c| c.id == lane_id)?.lane_type.value_range();
+        let y = rect.max.y - (value.clamp(min, max) - min) / (max - min) * rect.height();
+        Some(Pos2::new(self.tick_to_screen_x(rect, tick), y))
+    }
+
+    /// 概览条中 tick 位置（垂直居中）的屏幕坐标；概览条未显示过时返回 `None`
+    pub fn minimap_tick_to_screen(&self, tick: u64) -> Option<Pos2> {
+        let rect = self.minimap_rect?;
+        let total = self.minimap.as_ref()?.total_ticks;
+        let strip = Self::minimap_strip(rect);
+        Some(Pos2::new(strip.min.x + tick as f32 / total as f32 * strip.width(), strip.center().y))
+    }
+
+    /// 侧边键盘上某个按键内的屏幕坐标，`t` 为水平相对位置（0.0 = 左边缘，1.0 = 右边缘）
+    pub fn piano_key_to_screen(&self, key: u8, t: f32) -> Option<Pos2> {
+        let rect = self.piano_roll_rect?;
+        let y = self.tick_key_to_screen(0, key)?.y;
+        Some(Pos2::new(rect.min.x + PIANO_KEY_WIDTH * t.clamp(0.0, 1.0), y))
+    }
+
+    /// 顶部时间轴中 tick 位置（垂直居中）的屏幕坐标
+    pub fn timeline_tick_to_screen(&self, tick: u64) -> Option<Pos2> {
+        let rect = self.piano_roll_rect?;
+        Some(Pos2::new(self.tick_to_screen_x(rect, tick), rect.min.y + TIMELINE_HEIGHT * 0.5))
+    }
+
+    fn row_layout(&self) -> RowLayout {
+        RowLayout::new(self.drum_map.as_ref())
+    }
+
+    fn tick_to_screen_x(&self, rect: Rect, tick: u64) -> f32 {
+        rect.min.x
+            + PIANO_KEY_WIDTH
+            + self.manual_scroll_x
+            + tick as f32 / self.state.ticks_per_beat as f32 * self.zoom_x
+    }
+
+    /// 将音高吸附到允许集合，附近没有允许音高时返回 `None`
+    fn constrain_key(&self, key: u8) -> Option<u8> {
+        match self.allowed_keys {
+            Some(allowed) => allowed.nearest(key, ALLOWED_KEY_SNAP_RANGE),
+            None => Some(key.min(127)),
+        }
+    }
+
+    fn show_toast(&mut self, message: impl Into<String>) {
+        self.toast = Some(Toast {
+            message: message.into(),
+            expires_at: None,
+        });
+    }
+
+    pub fn insert_note(&mut self, note: Note) -> NoteId {
+        self.push_undo_snapshot();
+        self.state.notes.push(note);
+        self.sort_notes();
+        self.sync_sounding_notes();
+        self.emit_note_added(note);
+        note.id
+    }
+
+    /// 在下一帧绘制外部拖入音符的幽灵预览。
+    ///
+    /// `notes` 使用本编辑器的 tick 精度且起点从 0 开始，落点为 `at_tick`，音高整体偏移 `key_offset`。
+    /// 宿主需要在拖动期间每帧调用。
+    pub fn show_drop_preview(&mut self, notes: &[Note], at_tick: u64, key_offset: i16) {
+        let at_tick = self.clamp_start_to_bounds(at_tick, Self::block_span(notes));
+        self.drop_preview = Some(DropPreview {
+            notes: notes.to_vec(),
+            at_tick,
+            key_offset,
+        });
+    }
+
+    /// 将外部拖入的音符插入到 `at_tick`，作为一个撤销步骤，并选中新音符。
+    pub fn accept_external_drop(&mut self, notes: &[Note], at_tick: u64, key_offset: i16) -> Vec<NoteId> {
+        self.drop_preview = None;
+        if notes.is_empty() {
+            return Vec::new();
+        }
+        let at_tick = self.clamp_start_to_bounds(at_tick, Self::block_span(notes));
+        self.push_undo_snapshot();
+        let previous = self.selected_notes.clone();
+        self.selected_notes.clear();
+        let mut inserted = Vec::with_capacity(notes.len());
+        for template in notes {
+            let Some(key) = self.constrain_key((template.key as i16 + key_offset).clamp(0, 127) as u8) else {
+                continue;
+            };
+            let new_note = Note {
+                label: template.label,
+                track: self.active_track,
+                ..Note::new(template.start + at_tick, template.duration.max(1), key, template.velocity)
+            };
+            self.state.notes.push(new_note);
+            self.emit_note_added(new_note);
+            self.selected_notes.insert(new_note.id);
+            inserted.push(new_note.id);
+        }
+        self.sort_notes();
+        self.sync_sounding_notes();
+        self.notify_selection_changed(previous);
+        inserted
+    }
+
+    pub fn remove_notes(&mut self, ids: impl IntoIterator<Item = NoteId>) {
+        use std::collections::HashSet;
+        let targets: HashSet<_> = ids.into_iter().collect();
+        if targets.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        let mut removed = Vec::new();
+        self.state.notes.retain(|note| {
+            if targets.contains(&note.id) {
+                removed.push(*note);
+                false
+            } else {
+                true
+            }
+        });
+        self.sync_sounding_notes();
+        for note in removed {
+            self.emit_note_deleted(note);
+            self.selected_notes.remove(&note.id);
+        }
+        self.prune_note_groups();
+    }
+
+    pub fn clear(&mut self) {
+        if self.state.notes.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        // Collect notes to emit events before clearing to avoid unnecessary clone
+        let notes_to_delete: Vec<Note> = self.state.notes.to_vec();
+        for note in notes_to_delete {
+            self.emit_note_deleted(note);
+        }
+        self.state.notes.clear();
+        self.selected_notes.clear();
+        self.sync_sounding_notes();
+        self.prune_note_groups();
+    }
+
+    /// 将当前选中的音符编为一个新分组（Ctrl+G）
+    pub fn group_selected_notes(&mut self) -> Option<NoteGroupId> {
+        if self.selected_notes.is_empty() {
+            return None;
+        }
+        self.push_undo_snapshot();
+        let ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+        let name = format!("Group {}", self.state.groups.len() + 1);
+        let (id, dissolved) = self.state.create_group(name, &ids);
+        for old in dissolved {
+            self.emit_event(EditorEvent::GroupDissolved(old));
+        }
+        self.emit_event(EditorEvent::GroupCreated(id));
+        Some(id)
+    }
+
+    /// 解散选中音符所属的分组（Ctrl+Shift+G）
+    pub fn ungroup_selected_notes(&mut self) {
+        let group_ids: BTreeSet<NoteGroupId> = self
+            .selected_notes
+            .iter()
+            .filter_map(|id| self.state.group_of(*id).map(|g| g.id))
+            .collect();
+        if group_ids.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        for id in group_ids {
+            if self.state.remove_group(id).is_some() {
+                self.emit_event(EditorEvent::GroupDissolved(id));
+            }
+        }
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.close_undo_step();
+        if let Some(entry) = self.undo_stack.pop() {
+            let tpb = self.state.ticks_per_beat;
+            if let Some(change) = &entry.change {
+                change.undo(&mut self.state);
+                change.undo(&mut self.history_base);
+            }
+            if self.state.ticks_per_beat != tpb {
+                self.rescale_editor_ticks(tpb, self.state.ticks_per_beat);
+            }
+            self.redo_stack.push(entry);
+            Self::trim_front(&mut self.redo_stack, self.max_undo_entries);
+            self.restart_undo_group();
+            self.clamp_active_track();
+            self.sync_sounding_notes();
+            self.emit_state_replaced();
+            self.selected_notes.clear();
+            return true;
+        }
+        false
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.close_undo_step();
+        if let Some(entry) = self.redo_stack.pop() {
+            let tpb = self.state.ticks_per_beat;
+            if let Some(change) = &entry.change {
+                change.redo(&mut self.state);
+                change.redo(&mut self.history_base);
+            }
+            if self.state.ticks_per_beat != tpb {
+                self.rescale_editor_ticks(tpb, self.state.ticks_per_beat);
+            }
+            self.undo_stack.push(entry);
+            self.trim_undo_stack();
+            self.restart_undo_group();
+            self.clamp_active_track();
+            self.sync_sounding_notes();
+            self.emit_state_replaced();
+            self.selected_notes.clear();
+            return true;
+        }
+        false
+    }
+
+    /// 替换播放后端；替换前先让旧后端松开所有音
+    pub fn set_playback_backend(&mut self, backend: Option<PlaybackHandle>) {
+        self.panic();
+        self.playback = backend;
+        if let Some(playback) = &self.playback {
+            playback.set_volume(self.volume * 2.0);
+            playback.set_pitch_shift(self.preview_pitch_shift);
+            playback.set_click_volume(self.metronome_volume);
+        }
+    }
+
+    pub fn set_playback_observer(&mut self, observer: Option<Arc<dyn PlaybackObserver>>) {
+        self.playback_observer = observer;
+    }
+
+    pub fn take_events(&mut self) -> Vec<EditorEvent> {
+        self.events_overflowed = false;
+        std::mem::take(&mut self.pending_events)
+    }
+
+    /// 编辑器持有的主要数据（当前状态、撤销/重做历史、剪贴板、待取事件）的大致字节数，供诊断使用
+    pub fn memory_usage_estimate(&self) -> usize {
+        let history: usize = self
+            .undo_stack
+            .iter()
+            .chain(self.redo_stack.iter())
+            .filter_map(|entry| entry.change.as_ref())
+            .map(StateDelta::size_estimate)
+            .sum::<usize>()
+            + state_size_estimate(&self.history_base);
+        let events: usize = self
+            .pending_events
+            .iter()
+            .map(|event| match event {
+                EditorEvent::StateReplaced(state) => state_size_estimate(state),
+                _ => 0,
+            })
+            .sum::<usize>()
+            + self.pending_events.len() * std::mem::size_of::<EditorEvent>();
+        let clipboard = self.clipboard.len() * std::mem::size_of::<Note>()
+            + self
+                .clipboard_groups
+                .iter()
+                .map(|g| std::mem::size_of::<NoteGroup>() + g.note_ids.len() * std::mem::size_of::<NoteId>())
+                .sum::<usize>()
+            + self
+                .curve_clipboard
+                .iter()
+                .chain(&self.clipboard_curves)
+                .map(|clip| clip.points.len() * std::mem::size_of::<(u64, f32)>())
+                .sum::<usize>();
+        state_size_estimate(&self.state) + history + events + clipboard
+    }
+
+    pub fn set_transport_state(&mut self, state: Option<TransportState>) {
+        self.transport_override = state;
+    }
+
+    /// 设置外部同步源（`None` 恢复用帧间隔推进时间）
+    ///
+    /// 同步期间播放状态与播放头每帧从同步源读取，按拍位置经本编辑器的速度图换算为秒，
+    /// 编辑器内部的循环跳转被停用（由同步源决定位置）。
+    pub fn set_sync(&mut self, sync: Option<Box<dyn SyncSource>>) {
+        self.sync = sync;
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.sync.is_some()
+    }
+
+    /// 从同步源读取播放状态和位置，并据此触发音符
+    fn follow_sync(&mut self) {
+        let Some(sync) = &self.sync else {
+            return;
+        };
+        let playing = sync.is_playing();
+        let time = self.state.ticks_to_seconds(sync.current_beats() * self.state.ticks_per_beat as f64) as f32;
+
+        if playing != self.is_playing {
+            self.is_playing = playing;
+            if playing {
+                self.seek_to_seconds(time);
+                self.notify_playback_started();
+            } else {
+                self.stop_playback_backend();
+                self.notify_playback_stopped();
+            }
+            self.emit_event(EditorEvent::PlaybackStateChanged { is_playing: playing });
+        }
+
+        if self.is_playing {
+            if time < self.current_time {
+                // 同步源向后跳转（循环或 Song Position Pointer）
+                self.stop_playback_backend();
+                self.seek_to_seconds(time);
+            } else {
+                self.current_time = time;
+                self.update_sequencer();
+            }
+        } else if time != self.current_time {
+            self.seek_to_seconds(time);
+        }
+    }
+
+    pub fn center_on_c4(&mut self) {
+        self.center_on_key(60);
+    }
+
+    pub fn center_on_key(&mut self, key: u8) {
+        let Some(row) = self.row_layout().row_of(key) else {
+            return;
+        };
+        let position_from_top = row as f32 * self.zoom_y;
+        let approximate_view_half = 300.0;
+        let desired_offset = position_from_top - approximate_view_half;
+        self.manual_scroll_y = -desired_offset.max(0.0);
+    }
+
+    /// 设置缩放（每拍、每行的像素）与滚动偏移（像素，向右、向下滚动时为负）
+    pub fn set_view(&mut self, zoom_x: f32, zoom_y: f32, scroll_x: f32, scroll_y: f32) {
+        self.zoom_x = zoom_x.clamp(10.0, 500.0);
+        self.zoom_y = zoom_y.clamp(5.0, 50.0);
+        self.manual_scroll_x = scroll_x.min(0.0);
+        self.manual_scroll_y = scroll_y.min(0.0);
+    }
+
+    /// 水平滚动，使 `tick` 位于音符网格左边缘
+    pub fn scroll_to_tick(&mut self, tick: u64) {
+        self.manual_scroll_x = -(tick as f32 / self.state.ticks_per_beat.max(1) as f32) * self.zoom_x;
+    }
+
+    /// 纵向滚动最少的距离使该音高所在行完全可见；第一次调用 `ui()` 之前不知道视图高度，改为居中
+    pub fn scroll_to_key(&mut self, key: u8) {
+        let Some(row) = self.row_layout().row_of(key) else {
+            return;
+        };
+        let Some(rect) = self.piano_roll_rect else {
+            self.center_on_key(key);
+            return;
+        };
+        let view_height = rect.height() - TIMELINE_HEIGHT;
+        let top = row as f32 * self.zoom_y;
+        if top + self.manual_scroll_y < 0.0 {
+            self.manual_scroll_y = -top;
+        } else if top + self.zoom_y + self.manual_scroll_y > view_height {
+            self.manual_scroll_y = (view_height - top - self.zoom_y).min(0.0);
+        }
+    }
+
+    /// 上一帧音符网格中可见的 tick 范围与音高范围（按当前缩放/滚动计算）；第一次调用 `ui()` 之前为 `None`
+    pub fn visible_range(&self) -> Option<(Range<u64>, RangeInclusive<u8>)> {
+        let rect = self.piano_roll_rect?;
+        let tpb = self.state.ticks_per_beat.max(1) as f32;
+        let view = rect.size() - Vec2::new(PIANO_KEY_WIDTH, TIMELINE_HEIGHT);
+        let start = (-self.manual_scroll_x / self.zoom_x * tpb).max(0.0);
+        let end = start + view.x.max(0.0) / self.zoom_x * tpb;
+        let layout = self.row_layout();
+        let top_row = -self.manual_scroll_y / self.zoom_y;
+        let bottom_row = top_row + (view.y / self.zoom_y - 0.001).max(0.0);
+        let (high, low) = (layout.key_at(top_row), layout.key_at(bottom_row));
+        Some((start.round() as u64..end.round() as u64, low.min(high)..=high.max(low)))
+    }
+
+    fn emit_view_if_changed(&mut self) {
+        let Some(view) = self.visible_range() else {
+            return;
+        };
+        if self.reported_view.as_ref() != Some(&view) {
+            self.reported_view = Some(view.clone());
+            let (visible_tick_range, visible_key_range) = view;
+            self.emit_event(EditorEvent::ViewChanged { visible_tick_range, visible_key_range });
+        }
+    }
+
+    fn seek_to_seconds(&mut self, seconds: f32) {
+        let seconds = seconds.max(0.0);
+        self.current_time = seconds;
+        self.last_tick = self.current_tick_position();
+        self.sync_sounding_notes();
+        self.emit_transport_event();
+    }
+
+    /// 跳到 `tick`：先松开所有发声的音（`all_notes_off`），播放中从该处起的音符照常触发；发出 `TransportChanged`
+    pub fn seek_to_tick(&mut self, tick: u64) {
+        self.stop_playback_backend();
+        // 多算百分之一 tick，避免换算回 tick 时因舍入落在前一个 tick
+        self.current_time = self.state.ticks_to_seconds(tick as f64 + 0.01) as f32;
+        self.last_tick = tick;
+        self.emit_transport_event();
+        // 事件报告目标位置；之后从前一个 tick 起扫描，恰好位于目标处的音符也会触发
+        self.last_tick = tick.saturating_sub(1);
+    }
+
+    /// 紧急停止所有声音：发送 `all_notes_off`，并清空拖动试听、侧边键盘、电脑键盘、选区试听与音序器记录的发声状态；
+    /// 不改变播放状态
+    pub fn panic(&mut self) {
+        self.release_computer_keyboard();
+        self.stop_playback_backend();
+        self.drag_preview_key = None;
+        self.preview_release_at = None;
+        self.key_preview = KeyPreviewState::Idle;
+        self.audition_voices.clear();
+    }
+
+    /// 窗口失焦，或指针在窗口外松开而收不到松开事件时，松开仍在试听的音
+    fn release_stuck_previews(&mut self, ctx: &Context) {
+        let (focused, down) = ctx.input(|i| (i.focused, i.pointer.any_down()));
+        if focused && down {
+            return;
+        }
+        if let KeyPreviewState::Held { key, .. } = self.key_preview {
+            if let Some(playback) = &self.playback {
+                playback.note_off(key);
+            }
+            self.key_preview = KeyPreviewState::Idle;
+        }
+        // 单次试听（`preview_release_at`）按时松开；失焦时也立即松开
+        if self.drag_preview_key.is_some() && (!focused || self.preview_release_at.is_none()) {
+            self.preview_note_off();
+        }
+        if !focused {
+            self.stop_audition();
+        }
+    }
+
+    /// 键盘走带操作的目标位置
+    fn transport_target(&self, action: ShortcutAction) -> Option<u64> {
+        let tick = self.current_tick_position();
+        let measure = self.state.ticks_per_measure().max(1);
+        let beat = (self.state.ticks_per_beat as u64 * 4 / self.state.time_signature.1.max(1) as u64).max(1);
+        let target = match action {
+            ShortcutAction::SeekToStart => 0,
+            ShortcutAction::SeekToEnd => self.state.notes.iter().map(|n| n.start + n.duration).max().unwrap_or(0),
+            ShortcutAction::PreviousBar => tick.saturating_sub(1) / measure * measure,
+            ShortcutAction::NextBar => (tick / measure + 1) * measure,
+            ShortcutAction::BeatEarlier => tick.saturating_sub(beat),
+            ShortcutAction::BeatLater => tick + beat,
+            ShortcutAction::PreviousMarker => self.state.markers.iter().rev().find(|m| m.tick < tick)?.tick,
+            ShortcutAction::NextMarker => self.state.markers.iter().find(|m| m.tick > tick)?.tick,
+            _ => return None,
+        };
+        Some(target)
+    }
+
+    fn stop_playback_backend(&mut self) {
+        // 弯音不会随音符结束复位，停止时回到中心，避免之后的预听跑调
+        let bent = self.sent_pitch_bend.take().is_some_and(|value| value != 0);
+        if let Some(playback) = &self.playback {
+            playback.all_notes_off();
+            if bent {
+                playback.pitch_bend(0);
+            }
+        }
+        self.sounding_notes.clear();
+        self.sustained_keys.clear();
+        self.sent_cc_values.clear();
+    }
+
+    /// 让正在发声的音符与当前状态保持一致：被删除、移调或移出播放头的音符立即 note_off，
+    /// 若启用 `trigger_notes_under_playhead`，新覆盖播放头的音符立即 note_on。
+    fn sync_sounding_notes(&mut self) {
+        self.release_stale_sounding_notes();
+        if !self.is_playing || !self.trigger_notes_under_playhead {
+            return;
+        }
+        let Some(playback) = &self.playback else {
+            return;
+        };
+        let tick = self.last_tick;
+        for note in &self.state.notes {
+            if !self.is_note_audible(note) {
+                continue;
+            }
+            let spans_playhead = note.start <= tick && tick < note.start + note.duration;
+            if spans_playhead && !self.sounding_notes.contains_key(&note.id) {
+                let velocity = self.state.apply_velocity_curve_to_note(note);
+                playback.note_on(note.key, velocity);
+                self.sounding_notes.insert(note.id, note.key);
+            }
+        }
+    }
+
+    fn release_stale_sounding_notes(&mut self) {
+        if self.sounding_notes.is_empty() {
+            return;
+        }
+        let tick = self.last_tick;
+        // 每帧都会调用：按 ID 经索引查找，不逐个扫描全部音符
+        let mut sounding = std::mem::take(&mut self.sounding_notes);
+        sounding.retain(|id, key| {
+            let still_sounding = self.note_index_by_id(*id).is_some_and(|idx| {
+                let note = &self.state.notes[idx];
+                note.key == *key
+                    && !self.hidden_tracks.contains(&note.track)
+                    && note.start <= tick
+                    && tick < note.start + note.duration
+            });
+            if !still_sounding {
+                if let Some(playback) = &self.playback {
+                    playback.note_off(*key);
+                }
+            }
+            still_sounding
+        });
+        self.sounding_notes = sounding;
+    }
+
+    fn prune_note_groups(&mut self) {
+        for id in self.state.prune_groups() {
+            self.emit_event(EditorEvent::GroupDissolved(id));
+        }
+    }
+
+    fn emit_event(&mut self, event: EditorEvent) {
+        if matches!(
+            event,
+            EditorEvent::StateReplaced(_)
+                | EditorEvent::StateEdited
+                | EditorEvent::NotesChanged(_)
+                | EditorEvent::TicksPerBeatChanged { .. }
+                | EditorEvent::NoteAdded(_)
+                | EditorEvent::NoteDeleted(_)
+                | EditorEvent::NoteUpdated { .. }
+        ) {
+            self.notes_revision += 1;
+        }
+        if let Some(listener) = &mut self.event_listener {
+            listener(&event);
+        }
+        self.pending_events.push(event);
+        if self.max_pending_events > 0 && self.pending_events.len() > self.max_pending_events {
+            if !self.events_overflowed {
+                log::warn!(
+                    "MidiEditor: more than {} events pending, dropping the oldest (is take_events being called?)",
+                    self.max_pending_events
+                );
+                self.events_overflowed = true;
+            }
+            Self::trim_front(&mut self.pending_events, self.max_pending_events);
+        }
+    }
+
+    /// 只保留末尾的 `max` 个元素
+    fn trim_front<T>(items: &mut Vec<T>, max: usize) {
+        if items.len() > max {
+            items.drain(..items.len() - max);
+        }
+    }
+
+    /// 只用于整体替换（`replace_state`、撤销、重做）：每次都会克隆整个状态
+    fn emit_state_replaced(&mut self) {
+        self.emit_event(EditorEvent::StateReplaced(self.state.clone()));
+    }
+
+    /// 发出一次编辑的细粒度事件，宿主打开 `state_replaced_on_edit` 时再附带完整状态
+    fn emit_edit_event(&mut self, event: EditorEvent) {
+        self.emit_event(event);
+        self.emit_state_replaced_on_edit();
+    }
+
+    fn emit_state_replaced_on_edit(&mut self) {
+        if self.state_replaced_on_edit {
+            self.emit_state_replaced();
+        }
+    }
+
+    fn emit_transport_event(&mut self) {
+        let loop_progress = if self.loop_enabled && self.loop_end_tick > self.loop_start_tick {
+            let loop_duration = self.loop_end_tick - self.loop_start_tick;
+            if loop_duration > 0 {
+                let position_in_loop = self.last_tick.saturating_sub(self.loop_start_tick);
+                (position_in_loop as f32 / loop_duration as f32).clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        } else {
+            0.0
+        };
+        
+        self.emit_event(EditorEvent::TransportChanged {
+            current_time: self.current_time,
+            current_tick: self.last_tick,
+            loop_enabled: self.loop_enabled,
+            loop_start_tick: self.loop_start_tick,
+            loop_end_tick: self.loop_end_tick,
+            loop_progress,
+            playback_rate: self.playback_rate,
+            musical_time: self.state.musical_time(self.last_tick),
+        });
+    }
+
+    pub fn apply_command(&mut self, command: EditorCommand) {
+        match command {
+            EditorCommand::ReplaceState(state) => self.replace_state(state),
+            EditorCommand::SetNotes(notes) => {
+                self.edit_state(|state| {
+                    state.notes = notes;
+                    state.notes.sort_by_key(|n| n.start);
+                });
+            }
+            EditorCommand::AppendNotes(mut notes) => {
+                if notes.is_empty() {
+                    return;
+                }
+                self.edit_state(|state| {
+                    state.notes.append(&mut notes);
+                    state.notes.sort_by_key(|n| n.start);
+                });
+            }
+            EditorCommand::ClearNotes => self.clear(),
+            EditorCommand::SeekSeconds(seconds) => {
+                self.seek_to_seconds(seconds);
+            }
+            EditorCommand::SeekToTick(tick) => self.seek_to_tick(tick),
+            EditorCommand::Panic => self.panic(),
+            EditorCommand::SetPlayback(is_playing) => {
+                if self.is_playing != is_playing {
+                    self.is_playing = is_playing;
+                    if !self.is_playing {
+                        self.stop_playback_backend();
+                        self.notify_playback_stopped();
+                    } else {
+                        self.notify_playback_started();
+                    }
+                    self.emit_event(EditorEvent::PlaybackStateChanged {
+                        is_playing: self.is_playing,
+                    });
+                }
+            }
+            EditorCommand::CenterOnKey(key) => self.center_on_key(key),
+            EditorCommand::SetBpm(bpm) => self.set_bpm(bpm),
+            EditorCommand::SetTempoAt { tick, bpm } => self.set_tempo_at(tick, bpm),
+            EditorCommand::SetTimeSignature(numer, denom) => self.set_time_signature(numer, denom),
+            EditorCommand::SetKeySignature(key_signature) => self.set_key_signature(key_signature),
+            EditorCommand::AddMarker { tick, label, color } => {
+                self.add_marker(tick, label, color.unwrap_or(Marker::DEFAULT_COLOR));
+            }
+            EditorCommand::MoveMarker { marker_id, tick } => self.move_marker(marker_id, tick),
+            EditorCommand::RemoveMarker { marker_id } => {
+                self.remove_marker(marker_id);
+            }
+            EditorCommand::RescaleTicksPerBeat(tpb) => self.rescale_ticks_per_beat(tpb),
+            EditorCommand::SetVolume(volume) => self.set_volume(volume),
+            EditorCommand::SetLoop {
+                enabled,
+                start_tick,
+                end_tick,
+            } => {
+                self.loop_enabled = enabled;
+                self.loop_start_tick = start_tick;
+                self.loop_end_tick = end_tick.max(start_tick + 1);
+            }
+            EditorCommand::ShiftLoop { forward } => self.shift_loop(forward),
+            EditorCommand::SetLoopToSelection => self.set_loop_to_selection(),
+            EditorCommand::ScaleLoop { double } => self.scale_loop(double),
+            EditorCommand::SetSnap { interval, mode } => {
+                self.snap_resolution = None;
+                self.snap_interval = interval.max(1);
+                self.snap_mode = mode;
+            }
+            EditorCommand::SetSnapResolution(resolution) => self.snap_resolution = resolution,
+            EditorCommand::OverrideTransport(state) => {
+                self.set_transport_state(state);
+            }
+            EditorCommand::AddCurvePoint { lane_id, tick, value } => {
+                self.push_undo_snapshot();
+                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                    let point = lane.insert_point(tick, value);
+                    self.emit_event(EditorEvent::CurvePointAdded {
+                        lane_id,
+                        point_id: point.id,
+                    });
+                }
+            }
+            EditorCommand::UpdateCurvePoint {
+                lane_id,
+                point_id,
+                tick,
+                value,
+            } => {
+                self.push_undo_snapshot();
+                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                    if lane.update_point(point_id, tick, value).is_some() {
+                        self.emit_event(EditorEvent::CurvePointUpdated {
+                            lane_id,
+                            point_id,
+                        });
+                    }
+                }
+            }
+            EditorCommand::RemoveCurvePoint { lane_id, point_id } => {
+                self.push_undo_snapshot();
+                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                    if lane.remove_point(point_id).is_some() {
+                        self.emit_event(EditorEvent::CurvePointRemoved {
+                            lane_id,
+                            point_id,
+                        });
+                    }
+                }
+            }
+            EditorCommand::SetCurvePointInterpolation {
+                lane_id,
+                point_id,
+                interpolation,
+            } => self.set_curve_point_interpolation(lane_id, point_id, interpolation),
+            EditorCommand::AddCurveLane { cc_number } => {
+                self.add_cc_lane(cc_number);
+            }
+            EditorCommand::AddPitchBendLane => {
+                self.add_pitch_bend_lane();
+            }
+            EditorCommand::ToggleCurveLaneEnabled { lane_id } => {
+                self.push_undo_snapshot();
+                if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                    lane.enabled = !lane.enabled;
+                    self.emit_edit_event(EditorEvent::CurveChanged(lane_id));
+                }
+            }
+            EditorCommand::Quantize {
+                targets,
+                strength,
+                interval,
+            } => self.quantize_notes(targets, strength, interval),
+            EditorCommand::HumanizeNotes {
+                time_range,
+                velocity_range,
+                delay_only,
+                seed,
+            } => {
+                if !self.selected_notes.is_empty() {
+                    self.push_undo_snapshot();
+                    let note_ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+                    if self.state.humanize_notes(&note_ids, time_range, velocity_range, delay_only, seed) == 0 {
+                        self.discard_undo_snapshot();
+                    } else {
+                        self.sync_sounding_notes();
+                        self.emit_edit_event(EditorEvent::NotesChanged(note_ids));
+                    }
+                }
+            }
+            EditorCommand::BatchTransform {
+                transform_type,
+                value,
+            } => {
+                if !self.selected_notes.is_empty() {
+                    self.push_undo_snapshot();
+                    let note_ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+                    let originals = self.selected_notes_snapshot();
+                    self.state.batch_transform_notes(&note_ids, transform_type, value);
+                    if transform_type == BatchTransformType::PitchOffset {
+                        // 受限音高：吸附到最近的允许音高，附近没有时保持原音高
+                        for original in originals {
+                            if let Some(note) = self.note_by_id(original.id) {
+                                let key = self.constrain_key(note.key).unwrap_or(original.key);
+                                if key != note.key {
+                                    self.edit_note_by_id(original.id, |n| n.key = key);
+                                }
+                            }
+                        }
+                    }
+                    self.sync_sounding_notes();
+                    self.emit_edit_event(EditorEvent::NotesChanged(note_ids));
+                }
+            }
+            EditorCommand::ResolveOverlaps { policy } => self.resolve_overlaps(policy),
+            EditorCommand::ValidateState { max_tick } => {
+                self.validate_state(max_tick);
+            }
+            EditorCommand::GlueNotes { max_gap } => self.glue_selected_notes(max_gap.unwrap_or(self.snap_ticks())),
+            EditorCommand::Legato { overlap_ticks } => self.legato_selection(overlap_ticks),
+            EditorCommand::Arpeggiate {
+                pattern,
+                rate_ticks,
+                octaves,
+                gate,
+            } => self.arpeggiate_selection(pattern, rate_ticks, octaves, gate),
+            EditorCommand::VelocityRamp {
+                start_velocity,
+                end_velocity,
+                curve,
+            } => {
+                let ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+                let mut state = self.state.clone();
+                let changes = state.velocity_ramp(&ids, start_velocity, end_velocity, curve);
+                if !changes.is_empty() {
+                    self.push_undo_snapshot();
+                    self.state = state;
+                    for (before, after) in changes {
+                        self.emit_note_updated(before, after);
+                    }
+                }
+            }
+            EditorCommand::TimeStretchSelection {
+                ratio,
+                anchor,
+                include_curves,
+            } => self.time_stretch_selection(ratio, anchor, include_curves),
+            EditorCommand::InvertPitch { axis_key } => self.invert_selected_pitches(axis_key),
+            EditorCommand::DuplicateSelection => self.duplicate_selection(),
+            EditorCommand::SelectNotes(query) => self.select_notes(query),
+            EditorCommand::SetNoteLabel(label) => self.set_selected_note_labels(label),
+            EditorCommand::SetEditBounds(bounds) => self.set_edit_bounds(bounds),
+            EditorCommand::SetScaleHighlight(scale) => self.set_scale_highlight(scale),
+            EditorCommand::SetMetronome(enabled) => self.metronome_enabled = enabled,
+            EditorCommand::SetPlaybackRate(rate) => self.set_playback_rate(rate),
+            EditorCommand::SetRecording(enabled) => self.set_recording(enabled),
+            EditorCommand::SetStepInput(enabled) => self.set_step_input(enabled),
+            EditorCommand::StepInput { key, velocity } => self.step_input_note(key, velocity),
+            EditorCommand::StepRest => self.step_move(true),
+            EditorCommand::ZoomToFit => self.zoom_to_fit(),
+            EditorCommand::ZoomToSelection => self.zoom_to_selection(),
+            EditorCommand::PasteAt { tick, key_anchor, mode } => self.paste_clipboard_with(tick, key_anchor, mode),
+            EditorCommand::SetView { zoom_x, zoom_y, scroll_x, scroll_y } => {
+                self.set_view(zoom_x, zoom_y, scroll_x, scroll_y)
+            }
+            EditorCommand::ScrollToTick(tick) => self.scroll_to_tick(tick),
+            EditorCommand::ScrollToKey(key) => self.scroll_to_key(key),
+            EditorCommand::PlaySelection => self.play_selection(),
+        }
+        self.emit_history_if_changed();
+    }
+
+    fn preview_note_on(&mut self, key: u8, velocity: u8) {
+        self.preview_release_at = None;
+        if let Some(playback) = &self.playback {
+            if let Some(prev) = self.drag_preview_key.take() {
+                playback.note_off(prev);
+            }
+            playback.note_on(key, velocity);
+            self.drag_preview_key = Some(key);
+        }
+    }
+
+    /// 推进侧边键盘试听的状态机
+    ///
+    /// `keys_top` 为第 127 号音高所在行的顶边（已包含纵向滚动）。力度由指针在按键内的水平位置决定；
+    /// 滑奏到新按键时若指针已离开键盘列，则沿用上一个音的力度。
+    fn update_key_preview(&mut self, pointer: Option<Pos2>, pressed: bool, down: bool, keys_rect: Rect, keys_top: f32) {
+        let layout = self.row_layout();
+        let key_at = |pos: Pos2| -> u8 { layout.key_at((pos.y - keys_top) / self.zoom_y.max(1.0)) };
+        let velocity_at = |pos: Pos2| self.key_velocity_curve.velocity_at((pos.x - keys_rect.min.x) / keys_rect.width().max(1.0));
+        let next = match (self.key_preview, pointer) {
+            (KeyPreviewState::Idle, Some(pos)) if pressed => KeyPreviewState::Held {
+                key: key_at(pos),
+                velocity: velocity_at(pos),
+            },
+            (KeyPreviewState::Held { key, velocity }, Some(pos)) if down => {
+                let new_key = key_at(pos);
+                if new_key == key {
+                    return;
+                }
+                let in_column = pos.x >= keys_rect.min.x && pos.x <= keys_rect.max.x;
+                KeyPreviewState::Held {
+                    key: new_key,
+                    velocity: if in_column { velocity_at(pos) } else { velocity },
+                }
+            }
+            (KeyPreviewState::Held { .. }, _) if !down => KeyPreviewState::Idle,
+            _ => return,
+        };
+        // 步进输入只响应按下，滑动试听不插入
+        if let (KeyPreviewState::Idle, KeyPreviewState::Held { key, velocity }) = (self.key_preview, next) {
+            self.step_input_note(key, velocity);
+        }
+        if let Some(playback) = &self.playback {
+            if let KeyPreviewState::Held { key, .. } = self.key_preview {
+                playback.note_off(key);
+            }
+            if let KeyPreviewState::Held { key, velocity } = next {
+                playback.note_on(key, velocity);
+            }
+        }
+        self.key_preview = next;
+    }
+
+    /// 试听一个音并在短时间后自动松开（由 `release_due_preview` 在之后的帧中处理）
+    fn preview_note_once(&mut self, key: u8, velocity: u8) {
+        self.preview_note_on(key, velocity);
+        if self.drag_preview_key.is_some() {
+            self.preview_release_at = Some(self.last_update + PREVIEW_ONCE_SECONDS);
+        }
+    }
+
+    /// 到期时松开 `preview_note_once` 试听的音，未到期时预约重绘
+    fn release_due_preview(&mut self, ctx: &Context) {
+        let Some(at) = self.preview_release_at else {
+            return;
+        };
+        let remaining = at - ctx.input(|i| i.time);
+        if remaining > 0.0 {
+            self.request_repaint(ctx, Some(std::time::Duration::from_secs_f64(remaining)));
+        } else {
+            self.preview_release_at = None;
+            self.preview_note_off();
+        }
+    }
+
+    /// 选区变化时以和弦形式试听新选中的音符（起点最早的 `AUDITION_MAX_VOICES` 个）；
+    /// 框选进行中先不处理，拖动音符时由拖动试听负责
+    fn audition_selection_if_changed(&mut self) {
+        if self.selection_box_start.is_some() || self.auditioned_selection == self.selected_notes {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.auditioned_selection, self.selected_notes.clone());
+        if !self.audition_on_select || self.is_dragging_note {
+            return;
+        }
+        let mut added: Vec<Note> = self
+            .state
+            .notes
+            .iter()
+            .filter(|n| self.selected_notes.contains(&n.id) && !previous.contains(&n.id))
+            .copied()
+            .collect();
+        if added.is_empty() {
+            return;
+        }
+        added.sort_by_key(|n| (n.start, n.key));
+        let now = self.last_update;
+        let off_at = now + f64::from(self.audition_duration);
+        let voices = added
+            .iter()
+            .take(AUDITION_MAX_VOICES)
+            .map(|n| AuditionVoice { key: n.key, velocity: n.velocity, on_at: now, off_at, sounding: false })
+            .collect();
+        self.start_audition(voices);
+    }
+
+    /// 按选中音符的相对位置（按速度图换算为秒）试听选区；不改变走带、播放头与播放状态
+    fn play_selection(&mut self) {
+        let mut notes = self.selected_notes_snapshot();
+        notes.sort_by_key(|n| (n.start, n.key));
+        let Some(first) = notes.first().map(|n| n.start) else {
+            return;
+        };
+        let origin = self.state.ticks_to_seconds(first as f64);
+        let now = self.last_update;
+        let voices = notes
+            .iter()
+            .take(AUDITION_MAX_NOTES)
+            .map(|n| AuditionVoice {
+                key: n.key,
+                velocity: n.velocity,
+                on_at: now + self.state.ticks_to_seconds(n.start as f64) - origin,
+                off_at: now + self.state.ticks_to_seconds((n.start + n.duration) as f64) - origin,
+                sounding: false,
+            })
+            .collect();
+        self.start_audition(voices);
+    }
+
+    /// 取代正在进行的试听
+    fn start_audition(&mut self, voices: Vec<AuditionVoice>) {
+        self.stop_audition();
+        if self.playback.is_some() {
+            self.audition_voices = voices;
+            self.update_audition(self.last_update);
+        }
+    }
+
+    fn stop_audition(&mut self) {
+        let voices = std::mem::take(&mut self.audition_voices);
+        self.release_audition_keys(voices.iter().filter(|v| v.sounding).map(|v| v.key));
+    }
+
+    /// 推进试听排程：到时的音发出 note on / note off；返回下一个需要处理的时间
+    fn update_audition(&mut self, now: f64) -> Option<f64> {
+        let playback = self.playback.clone()?;
+        let mut released = Vec::new();
+        for voice in &mut self.audition_voices {
+            if !voice.sounding && now >= voice.on_at {
+                playback.note_on(voice.key, voice.velocity);
+                voice.sounding = true;
+            }
+            if voice.sounding && now >= voice.off_at {
+                released.push(voice.key);
+            }
+        }
+        self.audition_voices.retain(|v| now < v.off_at);
+        self.release_audition_keys(released.into_iter());
+        self.audition_voices
+            .iter()
+            .map(|v| if v.sounding { v.off_at } else { v.on_at })
+            .min_by(f64::total_cmp)
+    }
+
+    /// 松开试听的音；仍被其它试听音或走带占用的音高不发 note off
+    fn release_audition_keys(&self, keys: impl Iterator<Item = u8>) {
+        let Some(playback) = &self.playback else {
+            return;
+        };
+        for key in keys {
+            let held = self.audition_voices.iter().any(|v| v.sounding && v.key == key)
+                || self.sounding_notes.values().any(|sounding| *sounding == key);
+            if !held {
+                playback.note_off(key);
+            }
+        }
+    }
+
+    /// 每帧推进试听排程，并为下一个音预约重绘
+    fn release_due_audition(&mut self, ctx: &Context) {
+        if self.audition_voices.is_empty() {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+        if let Some(at) = self.update_audition(now) {
+            self.request_repaint(ctx, Some(std::time::Duration::from_secs_f64((at - now).max(0.0))));
+        }
+    }
+
+    fn preview_note_off(&mut self) {
+        self.preview_release_at = None;
+        if let Some(prev) = self.drag_preview_key.take() {
+            if let Some(playback) = &self.playback {
+                playback.note_off(prev);
+            }
+        }
+    }
+
+    fn emit_note_added(&mut self, note: Note) {
+        self.emit_event(EditorEvent::NoteAdded(note));
+    }
+
+    fn emit_note_deleted(&mut self, note: Note) {
+        self.emit_event(EditorEvent::NoteDeleted(note));
+    }
+
+    /// 为所有选中音符设置同一标签（一次撤销）
+    fn set_selected_note_labels(&mut self, label: Option<SmallString>) {
+        let changed: Vec<usize> = self
+            .state
+            .notes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| self.selected_notes.contains(&n.id) && n.label != label)
+            .map(|(idx, _)| idx)
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        for idx in changed {
+            let before = self.state.notes[idx];
+            self.state.notes[idx].label = label;
+            self.emit_note_updated(before, self.state.notes[idx]);
+        }
+    }
+
+    fn emit_note_updated(&mut self, before: Note, after: Note) {
+        if before != after {
+            self.emit_event(EditorEvent::NoteUpdated { before, after });
+        }
+    }
+
+    fn finalize_note_drag_if_needed(&mut self) {
+        if self.drag_changed_note {
+            let originals = self.drag_original_notes.clone();
+            for (id, before) in originals {
+                if let Some(idx) = self.note_index_by_id(id) {
+                    let after = self.state.notes[idx];
+                    if self.drag_duplicate == DuplicateDrag::Copied {
+                        self.emit_note_added(after);
+                    } else {
+                        self.emit_note_updated(before, after);
+                    }
+                }
+            }
+            if self.auto_trim_overlaps {
+                let moved: Vec<NoteId> = self.drag_original_notes.iter().map(|(id, _)| *id).collect();
+                self.trim_overlaps_under(&moved);
+            }
+        } else if self.drag_action != DragAction::Create {
+            // 只是点击了音符：撤回按下时压入的快照
+            self.discard_undo_snapshot();
+        }
+        let sized = self.drag_action == DragAction::Create
+            || (self.drag_changed_note && matches!(self.drag_action, DragAction::ResizeStart | DragAction::ResizeEnd));
+        if let Some(note) = self.drag_primary_anchor.and_then(|id| self.note_by_id(id)).filter(|_| sized) {
+            self.last_note_length = Some(note.duration);
+        }
+        self.drag_original_notes.clear();
+        self.drag_primary_anchor = None;
+        self.drag_changed_note = false;
+    }
+
+    /// 结束力度柱等通道拖动：发出 `NoteUpdated`；没有任何改动时撤回开始时压入的撤销快照
+    fn finalize_lane_edit(&mut self) {
+        if let Some(state) = self.lane_edit_state.take() {
+            if self.lane_edit_changed {
+                for (id, before) in state.originals {
+                    if let Some(idx) = self.note_index_by_id(id) {
+                        let after = self.state.notes[idx];
+                        if before != after {
+                            self.emit_note_updated(before, after);
+                        }
+                    }
+                }
+            } else {
+                self.discard_undo_snapshot();
+            }
+        }
+        self.lane_edit_changed = false;
+    }
+
+    fn notify_selection_changed(&mut self, previous: BTreeSet<NoteId>) {
+        if previous != self.selected_notes {
+            self.emit_event(EditorEvent::SelectionChanged(
+                self.selected_notes.iter().copied().collect(),
+            ));
+        }
+    }
+
+    /// 按查询替换选区，只考虑可编辑轨道上的音符
+    fn select_notes(&mut self, query: SelectionQuery) {
+        let matches = |note: &Note| match query {
+            SelectionQuery::SamePitch(key) => note.key == key,
+            SelectionQuery::InLoopRegion => (self.loop_start_tick..self.loop_end_tick).contains(&note.start),
+            SelectionQuery::Invert => !self.selected_notes.contains(&note.id),
+        };
+        let selection: BTreeSet<NoteId> = self
+            .state
+            .notes
+            .iter()
+            .filter(|note| self.is_note_editable(note) && matches(note))
+            .map(|note| note.id)
+            .collect();
+        let previous = std::mem::replace(&mut self.selected_notes, selection);
+        self.notify_selection_changed(previous);
+    }
+
+    /// "Select by" 对话框当前条件对应的 `NoteFilter`
+    fn select_by_filter(&self) -> NoteFilter {
+        let range = |(enabled, lo, hi): (bool, u8, u8)| enabled.then(|| lo.min(hi)..=lo.max(hi));
+        let (enabled, lo, hi) = self.select_by_duration;
+        NoteFilter {
+            velocity: range(self.select_by_velocity),
+            key: range(self.select_by_key),
+            duration: enabled.then(|| lo.min(hi)..=lo.max(hi)),
+        }
+    }
+
+    /// 满足 "Select by" 条件的可编辑音符；有选区时只在选区内筛选
+    fn select_by_matches(&self) -> Vec<NoteId> {
+        self.state
+            .query_notes(&self.select_by_filter())
+            .into_iter()
+            .filter(|id| self.selected_notes.is_empty() || self.selected_notes.contains(id))
+            .filter(|id| self.note_by_id(*id).is_some_and(|note| self.is_note_editable(&note)))
+            .collect()
+    }
+
+    fn set_single_selection(&mut self, note_id: NoteId) {
+        let prev = self.selected_notes.clone();
+        self.selected_notes.clear();
+        self.selected_notes.insert(note_id);
+        self.notify_selection_changed(prev);
+    }
+
+    fn toggle_selection(&mut self, note_id: NoteId) {
+        let prev = self.selected_notes.clone();
+        if !self.selected_notes.insert(note_id) {
+            self.selected_notes.remove(&note_id);
+        }
+        self.notify_selection_changed(prev);
+    }
+
+    fn extend_selection(&mut self, note_id: NoteId) {
+        if self.selected_notes.contains(&note_id) {
+            return;
+        }
+        let prev = self.selected_notes.clone();
+        self.selected_notes.insert(note_id);
+        self.notify_selection_changed(prev);
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        self.palette = self.theme.unwrap_or_else(|| MidiEditorTheme::from_visuals(ui.visuals()));
+        self.update_step_modifier(ui.input(|i| i.modifiers.shift));
+        let total_height = ui.available_height();
+        ui.set_min_height(total_height);
+
+        ui.horizontal(|ui| {
+            ui.set_min_height(total_height);
+            ui.vertical(|ui| {
+                ui.set_min_height(total_height);
+                self.ui_toolbar(ui);
+                ui.separator();
+                if self.show_minimap {
+                    self.ui_minimap(ui);
+                }
+                
+                // Allocate space for piano roll and curve lanes with draggable splitter
+                // Account for bottom status bar (typically 25-30 pixels)
+                let status_bar_height = 25.0;
+                let available_height_raw = ui.available_height();
+                let event_list_height = if self.show_event_list { EVENT_LIST_HEIGHT } else { 0.0 };
+                let remaining_height = (available_height_raw - status_bar_height - event_list_height).max(0.0);
+                let min_piano_height = 200.0;
+                let min_curve_height = 80.0;
+                
+                // Calculate heights based on splitter ratio
+                let piano_roll_height = if self.curve_lane_visible {
+                    (remaining_height * self.splitter_ratio).max(min_piano_height).min(remaining_height)
+                } else {
+                    remaining_height
+                };
+                let curve_height = if self.curve_lane_visible {
+                    (remaining_height - piano_roll_height).max(0.0)
+                } else {
+                    0.0
+                };
+                
+                // Piano roll area
+                let piano_rect = ui.allocate_ui_with_layout(
+                    Vec2::new(ui.available_width(), piano_roll_height),
+                    Layout::top_down(Align::LEFT),
+                    |ui| {
+                        self.ui_piano_roll(ui);
+                    }
+                ).response.rect;
+                
+                // Draggable splitter (only if curve editor is visible)
+                if self.curve_lane_visible {
+                    let splitter_height = 4.0;
+                    let splitter_rect = Rect::from_min_size(
+                        Pos2::new(piano_rect.min.x, piano_rect.max.y),
+                        Vec2::new(piano_rect.width(), splitter_height)
+                    );
+                    
+                    let splitter_response = ui.allocate_rect(splitter_rect, Sense::click_and_drag());
+                    let painter = ui.painter_at(splitter_rect);
+                    
+                    // Draw splitter
+                    painter.rect_filled(splitter_rect, 0.0, self.palette.splitter);
+                    painter.rect_stroke(splitter_rect, 0.0, Stroke::new(1.0, self.palette.separator));
+                    
+                    // Handle dragging
+                    if splitter_response.drag_started() {
+                        self.dragging_splitter = true;
+                    }
+                    if self.dragging_splitter {
+                        if let Some(pointer) = ui.input(|i| i.pointer.hover_pos()) {
+                            let new_ratio = ((pointer.y - piano_rect.min.y) / remaining_height)
+                                .clamp(min_piano_height / remaining_height, 1.0 - min_curve_height / remaining_height);
+                            self.splitter_ratio = new_ratio;
+                        }
+                        if ui.input(|i| i.pointer.any_released()) {
+                            self.dragging_splitter = false;
+                        }
+                        ui.ctx().set_cursor_icon(CursorIcon::ResizeVertical);
+                    } else if splitter_response.hovered() {
+                        ui.ctx().set_cursor_icon(CursorIcon::ResizeVertical);
+                    }
+                    
+                    // Curve lanes area
+                    ui.allocate_ui_with_layout(
+                        Vec2::new(ui.available_width(), curve_height),
+                        Layout::top_down(Align::LEFT),
+                        |ui| {
+                            self.ui_curve_lanes(ui);
+                        }
+                    );
+                }
+                if self.show_event_list {
+                    ui.separator();
+                    self.ui_event_list(ui);
+                }
+            });
+            ui.separator();
+            self.ui_inspector(ui, total_height);
+        });
+
+        if self.sync.is_some() {
+            self.follow_sync();
+            self.last_update = ui.input(|i| i.time);
+            if self.is_playing {
+                self.request_repaint(ui.ctx(), None);
+            } else {
+                // 等待同步源开始播放
+                self.request_repaint(ui.ctx(), Some(std::time::Duration::from_millis(50)));
+            }
+        } else if self.is_playing {
+            self.request_repaint(ui.ctx(), None);
+            let now = ui.input(|i| i.time);
+            let dt = now - self.last_update;
+            self.last_update = now;
+
+            if dt > 0.0 && dt < 1.0 {
+                // Avoid large jumps
+                self.advance_playback(dt as f32);
+            }
+        } else {
+            self.last_update = ui.input(|i| i.time);
+            // Update last_tick to match current_time so when we start playing we don't skip or retrigger weirdly
+            // But if we scrub, we might want to silence notes.
+        }
+        if self.is_playing {
+            self.update_recording();
+        } else {
+            self.finish_recording_take();
+        }
+
+        self.ui_note_properties(ui.ctx());
+        self.handle_shortcuts(ui.ctx());
+        self.release_stuck_previews(ui.ctx());
+        self.release_due_preview(ui.ctx());
+        self.release_due_audition(ui.ctx());
+        
+        // Context menu for piano roll
+        if let Some(menu_pos) = self.context_menu_pos {
+            let menu_response = egui::Area::new(egui::Id::new("piano_roll_context_menu"))
+                .order(egui::Order::Foreground)
+                .fixed_pos(menu_pos)
+                .show(ui.ctx(), |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        let has_selection = !self.selected_notes.is_empty();
+                        
+                        // Set minimum width for all buttons to ensure consistent width
+                        ui.set_min_width(200.0);
+                        
+                        // Quantize to snap grid
+                        if ui.add_enabled(has_selection && self.snap_ticks() > 0, egui::Button::new("Quantize to snap grid")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.quantize_selected_notes();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        if ui.add_enabled(has_selection, egui::Button::new("Quantize...")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.open_quantize_dialog();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        
+                        ui.separator();
+                        
+                        // Snap Mode and Snap Interval buttons side by side
+                        ui.horizontal(|ui| {
+                            // Snap Mode submenu (adaptive width)
+                            ui.menu_button("Snap Mode", |ui| {
+                                for (mode, label) in [(SnapMode::Absolute, "Absolute"), (SnapMode::Relative, "Relative")] {
+                                    if ui.selectable_label(self.snap_mode == mode, label).clicked() {
+                                        self.snap_mode = mode;
+                                        self.context_menu_pos = None;
+                                        self.context_menu_open_pos = None;
+                                    }
+                                }
+                            });
+                            
+                            // Snap Interval submenu (adaptive width)
+                            ui.menu_button("Snap Interval", |ui| {
+                                if self.ui_snap_choices(ui) {
+                                    self.swing_original_notes.clear();
+                                    self.swing_menu_ratio = 0.0;
+                                    self.context_menu_pos = None;
+                                    self.context_menu_open_pos = None;
+                                }
+                            });
+                        });
+                        
+                        ui.separator();
+                        
+                        // Humanize
+                        if ui.add_enabled(has_selection, egui::Button::new("Humanize...")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.show_humanize_dialog = true;
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        
+                        // Clean up overlaps（没有选区时作用于全部音符）
+                        ui.menu_button("Clean up overlaps", |ui| {
+                            for policy in OverlapPolicy::ALL {
+                                if ui.button(policy.label()).clicked() {
+                                    self.swing_original_notes.clear();
+                                    self.swing_menu_ratio = 0.0;
+                                    self.apply_command(EditorCommand::ResolveOverlaps { policy });
+                                    self.context_menu_pos = None;
+                                    self.context_menu_open_pos = None;
+                                }
+                            }
+                        });
+
+                        // Legato
+                        if ui.add_enabled(has_selection, egui::Button::new("Legato")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.apply_command(EditorCommand::Legato { overlap_ticks: 0 });
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+
+                        // Glue: 间隔小于吸附间隔的同音高音符合并
+                        if ui.add_enabled(has_selection, egui::Button::new("Glue")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.apply_command(EditorCommand::GlueNotes { max_gap: None });
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+
+                        // Arpeggiate: 每步为一个吸附间隔（自由吸附时为十六分音符）
+                        ui.add_enabled_ui(has_selection, |ui| {
+                            ui.menu_button("Arpeggiate", |ui| {
+                                ui.add(DragValue::new(&mut self.arp_octaves).range(1..=4).prefix("Octaves: "));
+                                ui.add(Slider::new(&mut self.arp_gate, 0.1..=1.0).text("Gate"));
+                                ui.separator();
+                                for pattern in ArpPattern::ALL {
+                                    if ui.button(pattern.label()).clicked() {
+                                        self.swing_original_notes.clear();
+                                        self.swing_menu_ratio = 0.0;
+                                        let rate_ticks = match self.snap_ticks() {
+                                            0 => (self.state.ticks_per_beat as u64 / 4).max(1),
+                                            snap => snap,
+                                        };
+                                        self.apply_command(EditorCommand::Arpeggiate {
+                                            pattern,
+                                            rate_ticks,
+                                            octaves: self.arp_octaves,
+                                            gate: self.arp_gate,
+                                        });
+                                        self.context_menu_pos = None;
+                                        self.context_menu_open_pos = None;
+                                    }
+                                }
+                            });
+                        });
+
+                        // Batch Transform
+                        if ui.add_enabled(has_selection, egui::Button::new("Batch Transform...")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.show_batch_transform_dialog = true;
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+
+                        // Invert pitch around an axis note
+                        if ui.add_enabled(has_selection, egui::Button::new("Invert pitch...")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.swing_original_notes.clear();
+                            self.swing_menu_ratio = 0.0;
+                            self.open_invert_dialog();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+
+                        // Note labels (articulations)
+                        if ui.add_enabled(has_selection, egui::Button::new("Set label…")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
                             self.swing_original_notes.clear();
                             self.swing_menu_ratio = 0.0;
-                            let time_range = (self.snap_interval / 12).max(1).min(20);
-                            let velocity_range = 5;
+                            self.label_dialog_text = self
+                                .first_selected_note()
+                                .and_then(|n| n.label)
+                                .map(|label| label.to_string())
+                                .unwrap_or_default();
+                            self.show_label_dialog = true;
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        
+                        ui.separator();
+
+                        // Selection helpers
+                        let pitch_source = self
+                            .context_menu_note
+                            .and_then(|id| self.note_by_id(id))
+                            .or_else(|| self.first_selected_note());
+                        if ui.add_enabled(pitch_source.is_some(), egui::Button::new("Select all with this pitch")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::SelectSamePitch))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            if let Some(note) = pitch_source {
+                                self.select_notes(SelectionQuery::SamePitch(note.key));
+                            }
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        if ui.add(egui::Button::new("Select in loop region")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::SelectInLoop))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.select_notes(SelectionQuery::InLoopRegion);
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        if ui.add_enabled(has_selection, egui::Button::new("Set loop to selection")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::LoopToSelection))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.set_loop_to_selection();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        if ui.add(egui::Button::new("Invert selection")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::InvertSelection))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.select_notes(SelectionQuery::Invert);
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        if ui.add(egui::Button::new("Select by…")
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.show_select_by_dialog = true;
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+
+                        ui.separator();
+
+                        // Duplicate right after the selection
+                        if ui.add_enabled(has_selection, egui::Button::new("Duplicate")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::Duplicate))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.duplicate_selection();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+
+                        // Paste at the right-clicked position, lowest note on the right-clicked key
+                        ui.horizontal(|ui| {
+                            let anchor = self.context_menu_anchor;
+                            if ui.add_enabled(self.has_clipboard() && anchor.is_some(), egui::Button::new("Paste here")).clicked() {
+                                if let Some((tick, key)) = anchor {
+                                    self.apply_command(EditorCommand::PasteAt {
+                                        tick,
+                                        key_anchor: Some(key),
+                                        mode: self.paste_mode,
+                                    });
+                                }
+                                self.context_menu_pos = None;
+                                self.context_menu_open_pos = None;
+                            }
+                            ui.menu_button("Paste mode", |ui| {
+                                for mode in PasteMode::ALL {
+                                    if ui.selectable_label(self.paste_mode == mode, mode.label()).clicked() {
+                                        self.paste_mode = mode;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                        
+                        // Note groups
+                        if ui.add_enabled(has_selection, egui::Button::new("Group selection")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::Group))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.group_selected_notes();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        let has_group = self
+                            .selected_notes
+                            .iter()
+                            .any(|id| self.state.group_of(*id).is_some());
+                        if ui.add_enabled(has_group, egui::Button::new("Ungroup")
+                            .shortcut_text(self.shortcut_text(ui.ctx(), ShortcutAction::Ungroup))
+                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
+                            self.ungroup_selected_notes();
+                            self.context_menu_pos = None;
+                            self.context_menu_open_pos = None;
+                        }
+                        
+                        ui.separator();
+                        
+                        // Swing - directly in menu
+                        if has_selection {
+                            ui.label("Swing:");
+                            // Check if selection changed - if so, reinitialize
+                            let current_selection: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+                            let selection_changed = self.swing_original_notes.is_empty() 
+                                || self.swing_original_notes.len() != current_selection.len()
+                                || !self.swing_original_notes.iter().all(|(id, _)| current_selection.contains(id));
+                            
+                            if selection_changed {
+                                // Restore original positions if we had previous swing applied
+                                if !self.swing_original_notes.is_empty() && self.swing_menu_ratio > 0.0 {
+                                    let original_notes = self.swing_original_notes.clone();
+                                    for (id, original_start) in &original_notes {
+                                        if let Some(note) = self.note_mut_by_id(*id) {
+                                            note.start = *original_start;
+                                        }
+                                    }
+                                }
+                                
+                                // Initialize with current selection
+                                self.swing_original_notes = self.selected_notes
+                                    .iter()
+                                    .filter_map(|&id| {
+                                        self.note_by_id(id).map(|note| (id, note.start))
+                                    })
+                                    .collect();
+                                self.swing_menu_ratio = 0.0;
+                                // Create undo snapshot when starting swing adjustment
+                                self.push_undo_snapshot();
+                            }
+                            
+                            ui.horizontal(|ui| {
+                                let mut swing = self.swing_menu_ratio;
+                                
+                                // Slider for 0-100% range
+                                let slider_response = ui.add(
+                                    Slider::new(&mut swing, 0.0..=1.0)
+                                        .text("%")
+                                        .custom_formatter(|n, _| format!("{:.0}%", n * 100.0)),
+                                );
+                                
+                                // Custom input for values beyond 100% (up to 200%)
+                                let drag_response = ui.add(
+                                    DragValue::new(&mut swing)
+                                        .range(0.0..=2.0)
+                                        .speed(0.01)
+                                        .suffix("%")
+                                        .custom_formatter(|n, _| format!("{:.1}", n * 100.0)),
+                                );
+                                
+                                if slider_response.changed() || drag_response.changed() {
+                                    self.swing_menu_ratio = swing.clamp(0.0, 2.0);
+                                    // Apply swing in real-time
+                                    self.apply_swing_to_selected_notes_realtime(self.swing_menu_ratio);
+                                }
+                            });
+                        }
+                    });
+                });
+            
+            // Close menu if user clicks elsewhere
+            // Logic: if click is NOT in the menu rect, close the menu
+            // Valid buttons (Quantize, Humanize, Batch Transform) already set context_menu_pos = None
+            // Submenu buttons (Snap Mode, Snap Interval) don't close the menu (egui handles this)
+            let ctx = ui.ctx();
+            if ctx.input(|i| i.pointer.primary_clicked() || i.pointer.secondary_clicked()) {
+                if let Some(click_pos) = ctx.input(|i| i.pointer.interact_pos()) {
+                    // Get the actual menu rect from the Area response
+                    let menu_rect = menu_response.response.rect;
+                    
+                    // Ignore the click that opened the menu (same position within a small threshold)
+                    let ignore_click = if let Some(open_pos) = self.context_menu_open_pos {
+                        let threshold = 5.0; // pixels
+                        click_pos.distance(open_pos) < threshold
+                    } else {
+                        false
+                    };
+                    
+                    // Simple logic: if click is NOT in menu rect, close menu
+                    // Submenus (menu_button popups) are handled by egui automatically
+                    // If user clicks outside the main menu, close it
+                    if !ignore_click && !menu_rect.contains(click_pos) {
+                        // Clear swing adjustment state when closing menu
+                        self.swing_original_notes.clear();
+                        self.swing_menu_ratio = 0.0;
+                        self.context_menu_pos = None;
+                        self.context_menu_open_pos = None;
+                    }
+                    // If click is inside menu_rect, don't close (menu item click will handle it)
+                } else {
+                    // Can't determine click position, close menu to be safe
+                    // Clear swing adjustment state when closing menu
+                    self.swing_original_notes.clear();
+                    self.swing_menu_ratio = 0.0;
+                    self.context_menu_pos = None;
+                    self.context_menu_open_pos = None;
+                }
+            }
+        }
+        
+        // Playback settings dialog
+        if self.show_playback_settings {
+            egui::Window::new("Playback Settings")
+                .collapsible(false)
+                .resizable(true)
+                .show(ui.ctx(), |ui| {
+                    ui.set_min_width(300.0);
+                    
+                    ui.label("Volume:");
+                    let mut volume = self.volume;
+                    if ui
+                        .add(
+                            Slider::new(&mut volume, 0.0..=1.0)
+                                .text("%")
+                                .custom_formatter(|n, _| format!("{:.0}%", n * 200.0)),
+                        )
+                        .changed()
+                    {
+                        self.set_volume(volume);
+                    } else {
+                        self.volume = volume;
+                    }
+
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.metronome_enabled, "Metronome");
+                        let mut click_volume = self.metronome_volume;
+                        if ui
+                            .add_enabled(
+                                self.metronome_enabled,
+                                Slider::new(&mut click_volume, 0.0..=1.0)
+                                    .custom_formatter(|n, _| format!("{:.0}%", n * 100.0)),
+                            )
+                            .changed()
+                        {
+                            self.set_metronome_volume(click_volume);
+                        }
+                    });
+
+                    ui.separator();
+                    ui.label("Playback rate:");
+                    let mut rate = self.playback_rate;
+                    if ui
+                        .add(
+                            Slider::new(&mut rate, PLAYBACK_RATE_RANGE.0..=PLAYBACK_RATE_RANGE.1)
+                                .logarithmic(true)
+                                .custom_formatter(|n, _| format!("{n:.2}x")),
+                        )
+                        .on_hover_text("Audition slower or faster without changing the tempo")
+                        .changed()
+                    {
+                        self.set_playback_rate(rate);
+                    }
+
+                    ui.separator();
+                    ui.label("Pitch:");
+                    let mut pitch = self.preview_pitch_shift;
+                    if ui
+                        .add(Slider::new(&mut pitch, -12.0..=12.0).text("± semitone"))
+                        .changed()
+                    {
+                        self.preview_pitch_shift = pitch;
+                        if let Some(playback) = &self.playback {
+                            playback.set_pitch_shift(pitch);
+                        }
+                    }
+
+                    ui.separator();
+                    ui.checkbox(
+                        &mut self.trigger_notes_under_playhead,
+                        "Sound notes placed under the playhead",
+                    );
+                    ui.checkbox(&mut self.computer_keyboard_input, "Play notes with the computer keyboard")
+                        .on_hover_text("Z..M and Q..P rows play two octaves; - / = shift the octave");
+                    ui.checkbox(&mut self.record_quantize, "Quantize recorded notes to the snap grid");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.audition_on_select, "Audition selected notes");
+                        ui.add_enabled(
+                            self.audition_on_select,
+                            DragValue::new(&mut self.audition_duration).range(0.05..=4.0).speed(0.01).suffix(" s"),
+                        );
+                    });
+
+                    ui.separator();
+                    ui.checkbox(&mut self.loop_enabled, "Loop");
+                    if self.loop_enabled {
+                        ui.horizontal(|ui| {
+                            ui.label("Start:");
+                            let mut loop_start = self.loop_start_tick as i64;
+                            if ui
+                                .add(DragValue::new(&mut loop_start).speed(1.0))
+                                .changed()
+                            {
+                                self.loop_start_tick = loop_start.max(0) as u64;
+                                if self.loop_start_tick >= self.loop_end_tick {
+                                    self.loop_end_tick = self.loop_start_tick + 1;
+                                }
+                            }
+                            ui.label("End:");
+                            let mut loop_end = self.loop_end_tick as i64;
+                            if ui
+                                .add(DragValue::new(&mut loop_end).speed(1.0))
+                                .changed()
+                            {
+                                self.loop_end_tick = loop_end.max((self.loop_start_tick + 1) as i64) as u64;
+                            }
+                        });
+                    }
+
+                    ui.separator();
+                    ui.label("Snap Interval:");
+                    ComboBox::from_id_salt("snap_combo_dialog")
+                        .selected_text(self.snap_label())
+                        .show_ui(ui, |ui| {
+                            self.ui_snap_choices(ui);
+                        });
+
+                    ui.separator();
+                    ui.label("Double-click on a note:");
+                    ComboBox::from_id_salt("note_double_click_dialog")
+                        .selected_text(self.note_double_click.label())
+                        .show_ui(ui, |ui| {
+                            for action in NoteDoubleClickAction::ALL {
+                                ui.selectable_value(&mut self.note_double_click, action, action.label());
+                            }
+                        });
+
+                    ui.separator();
+                    ui.label("Snap Mode:");
+                    ComboBox::from_id_salt("snap_mode_dialog")
+                        .selected_text(match self.snap_mode {
+                            SnapMode::Absolute => "Absolute",
+                            SnapMode::Relative => "Relative",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.snap_mode, SnapMode::Absolute, "Absolute");
+                            ui.selectable_value(&mut self.snap_mode, SnapMode::Relative, "Relative");
+                        });
+
+                    ui.separator();
+                    if ui.button("Close").clicked() {
+                        self.show_playback_settings = false;
+                    }
+                });
+        }
+        
+        // Batch transform dialog
+        if self.show_label_dialog {
+            let mut open = true;
+            egui::Window::new("Set Label")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    ui.label(format!("Label for {} selected note(s):", self.selected_notes.len()));
+                    let response = ui.add(
+                        TextEdit::singleline(&mut self.label_dialog_text)
+                            .char_limit(SmallString::CAPACITY)
+                            .hint_text("e.g. stacc., pizz."),
+                    );
+                    let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                    ui.horizontal(|ui| {
+                        if ui.button("Apply").clicked() || submitted {
+                            let label = SmallString::non_empty(&self.label_dialog_text);
+                            self.apply_command(EditorCommand::SetNoteLabel(label));
+                            self.show_label_dialog = false;
+                        }
+                        if ui.button("Clear").clicked() {
+                            self.apply_command(EditorCommand::SetNoteLabel(None));
+                            self.show_label_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_label_dialog = false;
+                        }
+                    });
+                });
+            if !open {
+                self.show_label_dialog = false;
+            }
+        }
+
+        if self.show_select_by_dialog {
+            let scope = if self.selected_notes.is_empty() { "all notes" } else { "the selection" };
+            egui::Window::new("Select by")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    ui.label(format!("Filter {scope} by:"));
+                    let naming = self.pitch_naming();
+                    egui::Grid::new("select_by_grid").num_columns(3).show(ui, |ui| {
+                        let (enabled, lo, hi) = &mut self.select_by_velocity;
+                        ui.checkbox(enabled, "Velocity");
+                        ui.add_enabled(*enabled, DragValue::new(lo).range(1..=127));
+                        ui.add_enabled(*enabled, DragValue::new(hi).range(1..=127));
+                        ui.end_row();
+                        let (enabled, lo, hi) = &mut self.select_by_key;
+                        ui.checkbox(enabled, "Pitch");
+                        ui.add_enabled(*enabled, DragValue::new(lo).range(0..=127).custom_formatter(|n, _| tooltip::format_pitch(n as u8, naming)));
+                        ui.add_enabled(*enabled, DragValue::new(hi).range(0..=127).custom_formatter(|n, _| tooltip::format_pitch(n as u8, naming)));
+                        ui.end_row();
+                        let (enabled, lo, hi) = &mut self.select_by_duration;
+                        ui.checkbox(enabled, "Duration");
+                        ui.add_enabled(*enabled, DragValue::new(lo).range(1..=u32::MAX as u64).suffix(" ticks"));
+                        ui.add_enabled(*enabled, DragValue::new(hi).range(1..=u32::MAX as u64).suffix(" ticks"));
+                        ui.end_row();
+                    });
+                    let matching = self.select_by_matches();
+                    ui.label(format!("{} notes match", matching.len()));
+                    ui.horizontal(|ui| {
+                        if ui.button("OK").clicked() {
+                            let previous = std::mem::replace(&mut self.selected_notes, matching.into_iter().collect());
+                            self.notify_selection_changed(previous);
+                            self.show_select_by_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_select_by_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        if self.show_tpb_dialog {
+            egui::Window::new("Ticks per Beat")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    let current = self.state.ticks_per_beat;
+                    ui.label(format!("Current: {current} ticks per beat"));
+                    ui.add(DragValue::new(&mut self.tpb_dialog_value).range(1..=32767).prefix("New: "));
+                    let target = self.tpb_dialog_value.max(1);
+                    if target < current {
+                        // 缩小 PPQ 时统计换算后无法精确表示的音符
+                        let inexact = |tick: u64| !(tick * target as u64).is_multiple_of(current.max(1) as u64);
+                        let rounded = self
+                            .state
+                            .notes
+                            .iter()
+                            .filter(|n| inexact(n.start) || inexact(n.start + n.duration))
+                            .count();
+                        let warning = if rounded > 0 {
+                            format!("⚠ Lowering the resolution rounds {rounded} notes to the nearest tick")
+                        } else {
+                            "⚠ Lowering the resolution may round future edits".to_owned()
+                        };
+                        ui.colored_label(ui.visuals().warn_fg_color, warning);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(target != current, Button::new("Apply")).clicked() {
+                            self.apply_command(EditorCommand::RescaleTicksPerBeat(target));
+                            self.show_tpb_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_tpb_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        if self.show_invert_dialog {
+            let naming = self.pitch_naming();
+            egui::Window::new("Invert Pitch")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Axis note:");
+                        ui.add(
+                            DragValue::new(&mut self.invert_axis_key)
+                                .range(0..=127)
+                                .custom_formatter(|n, _| tooltip::format_pitch(n as u8, naming)),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Apply").clicked() {
+                            self.apply_command(EditorCommand::InvertPitch { axis_key: self.invert_axis_key });
+                            self.show_invert_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_invert_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        if self.show_quantize_dialog {
+            egui::Window::new("Quantize")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    ui.horizontal(|ui| {
+                        for targets in QuantizeTargets::ALL {
+                            ui.selectable_value(&mut self.quantize_targets, targets, targets.label());
+                        }
+                    });
+                    ui.add(
+                        Slider::new(&mut self.quantize_strength, 0.0..=1.0)
+                            .text("Strength")
+                            .custom_formatter(|n, _| format!("{:.0}%", n * 100.0)),
+                    );
+                    ui.add(DragValue::new(&mut self.quantize_interval).range(1..=7680).prefix("Grid: ").suffix(" ticks"));
+                    ui.horizontal(|ui| {
+                        if ui.button("Apply").clicked() {
+                            self.apply_command(EditorCommand::Quantize {
+                                targets: self.quantize_targets,
+                                strength: self.quantize_strength,
+                                interval: self.quantize_interval,
+                            });
+                            self.show_quantize_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_quantize_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        if self.show_humanize_dialog {
+            let note_ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+            egui::Window::new("Humanize")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Timing:");
+                        ui.selectable_value(&mut self.humanize_time_relative, false, "Ticks");
+                        ui.selectable_value(&mut self.humanize_time_relative, true, "Fraction of grid");
+                    });
+                    if self.humanize_time_relative {
+                        let ticks = self.humanize_time_range();
+                        ui.add(
+                            Slider::new(&mut self.humanize_time_fraction, 0.0..=0.5)
+                                .text("Range")
+                                .custom_formatter(move |n, _| format!("±{:.0}% ({ticks} ticks)", n * 100.0)),
+                        );
+                    } else {
+                        ui.add(Slider::new(&mut self.humanize_time_ticks, 0..=480).text("Range").prefix("±").suffix(" ticks"));
+                    }
+                    ui.add(Slider::new(&mut self.humanize_velocity_range, 0..=64).text("Velocity").prefix("±"));
+                    ui.checkbox(&mut self.humanize_delay_only, "Only delay (never rush)");
+                    ui.horizontal(|ui| {
+                        ui.add(DragValue::new(&mut self.humanize_seed).prefix("Seed: "));
+                        if ui.button("Reroll").clicked() {
+                            self.humanize_seed = fastrand::u64(..);
+                        }
+                    });
+                    let affected = self.state.humanize_preview_count(
+                        &note_ids,
+                        self.humanize_time_range(),
+                        self.humanize_velocity_range,
+                        self.humanize_delay_only,
+                        self.humanize_seed,
+                    );
+                    ui.label(format!("{affected} of {} selected notes will change", note_ids.len()));
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(affected > 0, egui::Button::new("Apply")).clicked() {
                             self.apply_command(EditorCommand::HumanizeNotes {
-                                time_range,
-                                velocity_range,
+                                time_range: self.humanize_time_range(),
+                                velocity_range: self.humanize_velocity_range,
+                                delay_only: self.humanize_delay_only,
+                                seed: Some(self.humanize_seed),
+                            });
+                            self.show_humanize_dialog = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.show_humanize_dialog = false;
+                        }
+                    });
+                });
+        }
+
+        if self.show_batch_transform_dialog {
+            egui::Window::new("Batch Transform")
+                .collapsible(false)
+                .resizable(true)
+                .show(ui.ctx(), |ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Transform Type:");
+                        ui.horizontal_wrapped(|ui| {
+                            for transform_type in BatchTransformType::ALL {
+                                let selected = !self.batch_ramp_selected
+                                    && !self.batch_stretch_selected
+                                    && std::mem::discriminant(&self.batch_transform_type)
+                                        == std::mem::discriminant(&transform_type);
+                                if ui.selectable_label(selected, transform_type.label()).clicked() {
+                                    if !selected {
+                                        self.batch_transform_type = transform_type;
+                                        self.batch_transform_value = transform_type.default_value();
+                                    }
+                                    self.batch_ramp_selected = false;
+                                    self.batch_stretch_selected = false;
+                                }
+                            }
+                            if ui.selectable_label(self.batch_ramp_selected, "Velocity Ramp").clicked() {
+                                self.batch_ramp_selected = true;
+                                self.batch_stretch_selected = false;
+                            }
+                            if ui.selectable_label(self.batch_stretch_selected, "Time Stretch").clicked() {
+                                self.batch_stretch_selected = true;
+                                self.batch_ramp_selected = false;
+                            }
+                        });
+                        
+                        ui.add_space(10.0);
+                        
+                        if self.batch_ramp_selected {
+                            ui.label("Velocity from the earliest to the latest selected note:");
+                            ui.add(egui::Slider::new(&mut self.batch_ramp_start, 1..=127).text("Start"));
+                            ui.add(egui::Slider::new(&mut self.batch_ramp_end, 1..=127).text("End"));
+                            ui.horizontal(|ui| {
+                                for curve in RampCurve::ALL {
+                                    ui.selectable_value(&mut self.batch_ramp_curve, curve, curve.label());
+                                }
+                            });
+                        } else if self.batch_stretch_selected {
+                            ui.label("Stretch ratio (2.0 = twice as slow):");
+                            ui.add(egui::Slider::new(&mut self.batch_stretch_ratio, 0.1..=4.0).logarithmic(true));
+                            ui.horizontal(|ui| {
+                                ui.label("Anchor:");
+                                for anchor in StretchAnchor::ALL {
+                                    ui.selectable_value(&mut self.batch_stretch_anchor, anchor, anchor.label());
+                                }
+                            });
+                            ui.checkbox(&mut self.batch_stretch_curves, "Stretch curve points in the selection range");
+                        } else {
+                            match self.batch_transform_type {
+                                BatchTransformType::VelocityOffset => {
+                                    ui.label("Velocity offset (-127 to +127):");
+                                    ui.add(egui::Slider::new(&mut self.batch_transform_value, -127.0..=127.0));
+                                }
+                                BatchTransformType::DurationScale => {
+                                    ui.label("Duration scale factor (0.1 to 10.0):");
+                                    ui.add(egui::Slider::new(&mut self.batch_transform_value, 0.1..=10.0));
+                                }
+                                BatchTransformType::PitchOffset => {
+                                    ui.label("Pitch offset (semitones, -127 to +127):");
+                                    ui.add(egui::Slider::new(&mut self.batch_transform_value, -127.0..=127.0));
+                                }
+                                BatchTransformType::SetVelocity => {
+                                    ui.label("Velocity (1 to 127):");
+                                    ui.add(egui::Slider::new(&mut self.batch_transform_value, 1.0..=127.0).step_by(1.0));
+                                }
+                                BatchTransformType::CompressVelocity { ref mut center } => {
+                                    ui.label("Scale each velocity's distance from the center (below 1 compresses):");
+                                    ui.add(egui::Slider::new(center, 1..=127).text("Center"));
+                                    ui.add(egui::Slider::new(&mut self.batch_transform_value, 0.0..=2.0).text("Factor"));
+                                }
+                                BatchTransformType::StartOffset => {
+                                    let range = self.state.ticks_per_beat as f64 * 4.0;
+                                    ui.label("Start offset (ticks, earlier notes stop at 0):");
+                                    ui.add(egui::Slider::new(&mut self.batch_transform_value, -range..=range).step_by(1.0));
+                                }
+                            }
+                        }
+                        
+                        ui.add_space(10.0);
+                        
+                        ui.horizontal(|ui| {
+                            if ui.button("Apply").clicked() {
+                                if self.batch_ramp_selected {
+                                    self.apply_command(EditorCommand::VelocityRamp {
+                                        start_velocity: self.batch_ramp_start,
+                                        end_velocity: self.batch_ramp_end,
+                                        curve: self.batch_ramp_curve,
+                                    });
+                                } else if self.batch_stretch_selected {
+                                    self.apply_command(EditorCommand::TimeStretchSelection {
+                                        ratio: self.batch_stretch_ratio,
+                                        anchor: self.batch_stretch_anchor,
+                                        include_curves: self.batch_stretch_curves,
+                                    });
+                                } else if !self.selected_notes.is_empty() {
+                                    self.apply_command(EditorCommand::BatchTransform {
+                                        transform_type: self.batch_transform_type,
+                                        value: self.batch_transform_value,
+                                    });
+                                }
+                                self.show_batch_transform_dialog = false;
+                            }
+                            if ui.button("Cancel").clicked() {
+                                self.show_batch_transform_dialog = false;
+                            }
+                        });
+                    });
+                });
+        }
+
+        if std::mem::take(&mut self.clipboard_export_pending) && self.has_clipboard() {
+            ui.ctx().copy_text(self.clipboard_payload().to_clipboard_text());
+        }
+        self.audition_selection_if_changed();
+        self.release_due_audition(ui.ctx());
+        self.emit_history_if_changed();
+        self.emit_view_if_changed();
+    }
+
+    /// 推进播放时钟并运行音序器；`ui()` 每帧以帧间隔调用，宿主也可以自行驱动时钟。
+    /// `dt` 为真实经过的秒数，按播放速率换算为曲子中的时间。
+    pub fn advance_playback(&mut self, dt: f32) {
+        if !self.is_playing || dt <= 0.0 {
+            return;
+        }
+        self.current_time += dt * self.playback_rate;
+        self.update_sequencer();
+    }
+
+    fn update_sequencer(&mut self) {
+        if self.state.ticks_per_beat == 0 || self.state.bpm <= 0.0 {
+            return;
+        }
+
+        let current_tick = self.current_tick_position();
+
+        // Edits made since the last frame may have moved sounding notes away from the playhead
+        self.release_stale_sounding_notes();
+
+        if let Some(playback) = &self.playback {
+            for note in &self.state.notes {
+                if !self.is_note_audible(note) {
+                    continue;
+                }
+                // Check for Note On: start lies between last_tick (exclusive) and current_tick (inclusive)
+                // Note: We use > last_tick to ensure we don't retrigger if we paused exactly on start.
+                // But for FIRST note starting at 0, last_tick might be 0.
+                // So special case: if last_tick is 0, include 0.
+
+                let should_trigger_start = if self.last_tick == 0 {
+                    note.start >= self.last_tick && note.start <= current_tick
+                } else {
+                    note.start > self.last_tick && note.start <= current_tick
+                };
+
+                if should_trigger_start {
+                    let velocity = self.state.apply_velocity_curve_to_note(note);
+                    playback.note_on(note.key, velocity);
+                    self.sounding_notes.insert(note.id, note.key);
+                    // 重新触发的键不再由踏板保持，否则抬起踏板时会切断新音符
+                    self.sustained_keys.remove(&note.key);
+                }
+
+                // Check for Note Off: end lies between last_tick and current_tick
+                // 延音踏板踩下时推迟 note_off，直到踏板抬起
+                let end = note.start + note.duration;
+                if end > self.last_tick && end <= current_tick {
+                    if self.state.sustain_at(end) {
+                        self.sustained_keys.insert(note.key);
+                    } else {
+                        playback.note_off(note.key);
+                    }
+                    self.sounding_notes.remove(&note.id);
+                }
+            }
+
+            // 踏板抬起：释放被保持的键（仍有音符在发声的键除外）
+            if !self.sustained_keys.is_empty() && !self.state.sustain_at(current_tick) {
+                for key in std::mem::take(&mut self.sustained_keys) {
+                    if !self.sounding_notes.values().any(|sounding| *sounding == key) {
+                        playback.note_off(key);
+                    }
+                }
+            }
+
+            // 节拍器：拍点网格从 0 开始按当前拍号计算；循环时终点上的拍点留给跳回后的起点，每帧最多响一声
+            if self.metronome_enabled {
+                let beat_ticks = (self.state.ticks_per_measure() / self.state.time_signature.0.max(1) as u64).max(1);
+                let first = if self.last_tick == 0 { 0 } else { self.last_tick / beat_ticks + 1 };
+                let mut last = current_tick / beat_ticks;
+                if self.loop_enabled && self.sync.is_none() && last * beat_ticks >= self.loop_end_tick {
+                    last = self.loop_end_tick.saturating_sub(1) / beat_ticks;
+                }
+                if last >= first && self.last_tick <= current_tick {
+                    playback.click(last.is_multiple_of(self.state.time_signature.0.max(1) as u64));
+                }
+            }
+
+            // CC 曲线：取整后的值变化时发送，越过最后一个音符后仍跟随曲线
+            for lane in self.state.curves.iter().filter(|lane| lane.enabled) {
+                let (Some(cc), Some(value)) = (lane.lane_type.cc_number(), lane.value_at(current_tick)) else {
+                    continue;
+                };
+                let value = value.round().clamp(0.0, 127.0) as u8;
+                if self.sent_cc_values.insert(cc, value) != Some(value) {
+                    playback.control_change(cc, value);
+                }
+            }
+
+            // 弯音曲线：取整后的值变化时发送
+            let bend = self
+                .state
+                .curves
+                .iter()
+                .filter(|lane| lane.enabled && lane.lane_type == CurveLaneType::PitchBend)
+                .find_map(|lane| lane.value_at(current_tick));
+            if let Some(value) = bend {
+                let value = value.round().clamp(-8192.0, 8191.0) as i16;
+                if self.sent_pitch_bend.replace(value) != Some(value) {
+                    playback.pitch_bend(value);
+                }
+            }
+        }
+
+        // Handle loop playback (an external sync source owns the position)
+        if self.loop_enabled && self.is_playing && self.sync.is_none() {
+            let loop_duration_ticks = self.loop_end_tick.saturating_sub(self.loop_start_tick);
+            if loop_duration_ticks > 0 && current_tick >= self.loop_end_tick {
+                // Jump back to loop start
+                self.current_time = self.state.ticks_to_seconds(self.loop_start_tick as f64) as f32;
+                // Set last_tick to one less than loop_start to ensure notes at loop_start are triggered
+                // If loop_start is 0, we use 0 (which is handled specially in the trigger logic)
+                self.last_tick = self.loop_start_tick.saturating_sub(1);
+                // Stop all notes when looping
+                self.stop_playback_backend();
+                // Don't update last_tick to current_tick after loop jump, use the value we set above
+                self.emit_transport_event();
+                return;
+            } else if current_tick < self.loop_start_tick {
+                // If somehow before loop start, jump to loop start
+                self.current_time = self.state.ticks_to_seconds(self.loop_start_tick as f64) as f32;
+                // Same logic as above for ensuring notes at loop_start are triggered
+                self.last_tick = self.loop_start_tick.saturating_sub(1);
+                // Don't update last_tick to current_tick after loop jump, use the value we set above
+                self.emit_transport_event();
+                return;
+            }
+        }
+
+        self.last_tick = current_tick;
+        self.emit_transport_event();
+    }
+
+    fn ui_inspector(&mut self, ui: &mut Ui, min_height: f32) {
+        ui.set_min_width(240.0);
+        ui.set_min_height(min_height);
+        ui.vertical(|ui| {
+            ui.heading("Inspector");
+            ui.separator();
+            let selection_len = self.selected_notes.len();
+            ui.label(format!("Selected notes: {selection_len}"));
+            ui.add_space(4.0);
+            if selection_len == 0 {
+                if ui
+                    .add_enabled(self.has_clipboard(), Button::new("Paste at playhead"))
+                    .clicked()
+                {
+                    let tick = self.current_tick_position();
+                    self.paste_clipboard_at(tick);
+                }
+                ui.label("Tip: Hold Shift to box-select inside the piano roll.");
+                self.draw_text_event_inspector(ui);
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Copy").clicked() {
+                    self.copy_selection();
+                }
+                if ui.button("Cut").clicked() {
+                    self.cut_selection();
+                }
+                if ui
+                    .add_enabled(self.has_clipboard(), Button::new("Paste"))
+                    .clicked()
+                {
+                    let tick = self.current_tick_position();
+                    self.paste_clipboard_at(tick);
+                }
+                if ui.button("Delete").clicked() {
+                    self.delete_selected_notes();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(self.snap_ticks() > 0, Button::new("Quantize to snap grid"))
+                    .clicked()
+                {
+                    self.quantize_selected_notes();
+                }
+                if ui.button("Quantize...").clicked() {
+                    self.open_quantize_dialog();
+                }
+            });
+
+            ui.separator();
+            ui.label("Advanced Tools");
+            ui.horizontal(|ui| {
+                if ui.button("Humanize...").clicked() {
+                    self.show_humanize_dialog = true;
+                }
+                if ui.button("Batch Transform...").clicked() {
+                    self.show_batch_transform_dialog = true;
+                }
+            });
+
+            if selection_len == 1 {
+                if let Some(note) = self.first_selected_note() {
+                    self.draw_single_note_inspector(ui, note);
+                }
+            } else {
+                self.draw_multi_note_summary(ui);
+            }
+            self.draw_text_event_inspector(ui);
+        });
+    }
+
+    /// 播放头处的歌词与文本事件：逐个修改或删除，并可在播放头添加新事件
+    fn draw_text_event_inspector(&mut self, ui: &mut Ui) {
+        ui.separator();
+        let tick = self.current_tick_position();
+        ui.label(format!("Lyrics & text at {}", self.state.musical_time(tick)));
+        let mut edit = None;
+        let mut remove = None;
+        for (index, event) in self.state.text_events.iter().enumerate().filter(|(_, e)| e.tick == tick) {
+            ui.horizontal(|ui| {
+                let mut edited = event.clone();
+                ComboBox::from_id_salt(("text_event_kind", index))
+                    .width(64.0)
+                    .selected_text(edited.kind.label())
+                    .show_ui(ui, |ui| {
+                        for kind in TextEventKind::ALL {
+                            ui.selectable_value(&mut edited.kind, kind, kind.label());
+                        }
+                    });
+                // 文本在失去焦点时提交，避免每次按键都产生撤销步骤
+                let buffer_id = ui.id().with(("text_event", index));
+                let mut text = ui
+                    .data(|d| d.get_temp::<String>(buffer_id))
+                    .unwrap_or_else(|| event.text().into_owned());
+                let response = ui.add(TextEdit::singleline(&mut text).desired_width(110.0));
+                if response.lost_focus() {
+                    ui.data_mut(|d| d.remove::<String>(buffer_id));
+                    if text != event.text() {
+                        edited.text = text.into_bytes();
+                    }
+                } else if response.has_focus() {
+                    ui.data_mut(|d| d.insert_temp(buffer_id, text));
+                }
+                if edited != *event {
+                    edit = Some((index, edited));
+                }
+                if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            let (kind, text) = &mut self.text_event_draft;
+            ComboBox::from_id_salt("new_text_event_kind")
+                .width(64.0)
+                .selected_text(kind.label())
+                .show_ui(ui, |ui| {
+                    for option in TextEventKind::ALL {
+                        ui.selectable_value(kind, option, option.label());
+                    }
+                });
+            let response = ui.add(TextEdit::singleline(text).desired_width(110.0).hint_text("Add at playhead"));
+            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+            if (ui.button("Add").clicked() || submitted) && !text.trim().is_empty() {
+                let event = TextEvent { track: self.active_track, ..TextEvent::new(tick, *kind, text.trim()) };
+                text.clear();
+                self.add_text_event(event);
+            }
+        });
+        if let Some((index, event)) = edit {
+            self.set_text_event(index, event);
+        }
+        if let Some(index) = remove {
+            self.remove_text_event(index);
+        }
+    }
+
+    fn draw_single_note_inspector(&mut self, ui: &mut Ui, note: Note) {
+        ui.separator();
+        ui.label("Single note properties");
+        let note_id = note.id;
+
+        let mut start = note.start;
+        ui.horizontal(|ui| {
+            ui.label("Start");
+            let speed = self.snap_ticks().max(1) as f64;
+            if ui
+                .add(musical_time_drag_value(&mut start, &self.state, speed))
+                .on_hover_text("Bar.beat.tick, e.g. 5.2.0; a plain number is ticks")
+                .changed()
+            {
+                self.edit_note_by_id(note_id, |n| n.start = start);
+            }
+        });
+
+        let mut duration = note.duration as i64;
+        ui.horizontal(|ui| {
+            ui.label("Duration");
+            if ui
+                .add(DragValue::new(&mut duration).speed(self.snap_ticks().max(1) as f64))
+                .changed()
+            {
+                let duration = duration.max(1) as u64;
+                self.edit_note_by_id(note_id, |n| n.duration = duration);
+            }
+        });
+
+        let mut key = note.key as i32;
+        if ui
+            .add(Slider::new(&mut key, 0..=127).text("Pitch"))
+            .changed()
+        {
+            // 吸附到音阶时沿拖动方向取下一个音阶音，逐半音拖动滑块也能离开当前音
+            let disable_snap = ui.input(|i| i.modifiers.alt);
+            let key = match self.scale_snap(disable_snap) {
+                Some((root, kind)) => {
+                    let keys = kind.keys(root);
+                    let in_scale = |k: &i32| keys.contains(*k as u8);
+                    let found = if key > note.key as i32 {
+                        (key..=127).find(in_scale)
+                    } else {
+                        (0..=key).rev().find(in_scale)
+                    };
+                    found.unwrap_or(note.key as i32)
+                }
+                None => key,
+            };
+            if let Some(key) = self.constrain_key(key as u8) {
+                self.edit_note_by_id(note_id, |n| n.key = key);
+            }
+        }
+
+        let mut velocity = note.velocity as i32;
+        if ui
+            .add(Slider::new(&mut velocity, 1..=127).text("Velocity"))
+            .changed()
+        {
+            let velocity = velocity as u8;
+            self.edit_note_by_id(note_id, |n| n.velocity = velocity);
+        }
+
+        // 标签在失去焦点时提交，避免每次按键都产生撤销步骤
+        let buffer_id = ui.id().with(("note_label", note_id.0));
+        let mut text = ui
+            .data(|d| d.get_temp::<String>(buffer_id))
+            .unwrap_or_else(|| note.label.map(|l| l.to_string()).unwrap_or_default());
+        ui.horizontal(|ui| {
+            ui.label("Label");
+            let response = ui.add(
+                TextEdit::singleline(&mut text)
+                    .char_limit(SmallString::CAPACITY)
+                    .hint_text("e.g. stacc."),
+            );
+            if response.lost_focus() {
+                ui.data_mut(|d| d.remove::<String>(buffer_id));
+                let label = SmallString::non_empty(&text);
+                if label != note.label {
+                    self.edit_note_by_id(note_id, |n| n.label = label);
+                }
+            } else if response.has_focus() {
+                ui.data_mut(|d| d.insert_temp(buffer_id, text));
+            }
+        });
+    }
+
+    fn draw_multi_note_summary(&self, ui: &mut Ui) {
+        let snapshot = self.selected_notes_snapshot();
+        if snapshot.is_empty() {
+            return;
+        }
+        let min_start = snapshot.iter().map(|n| n.start).min().unwrap_or(0);
+        let max_start = snapshot.iter().map(|n| n.start).max().unwrap_or(0);
+        let min_duration = snapshot.iter().map(|n| n.duration).min().unwrap_or(0);
+        let max_duration = snapshot.iter().map(|n| n.duration).max().unwrap_or(0);
+        let avg_velocity =
+            snapshot.iter().map(|n| n.velocity as u32).sum::<u32>() / snapshot.len() as u32;
+
+        ui.separator();
+        ui.label("Multi-note summary");
+        ui.label(format!("Start range: {min_start} - {max_start}"));
+        ui.label(format!("Duration range: {min_duration} - {max_duration}"));
+        ui.label(format!("Average velocity: {avg_velocity}"));
+    }
+
+    fn ui_toolbar(&mut self, ui: &mut Ui) {
+        ui.horizontal_wrapped(|ui| {
+            // Time display
+            let total_seconds = self.current_time;
+            let minutes = (total_seconds / 60.0) as u32;
+            let seconds = (total_seconds % 60.0) as u32;
+            let milliseconds = ((total_seconds % 1.0) * 1000.0) as u32;
+            let time_display = format!("{:02}:{:02}.{:03}", minutes, seconds, milliseconds);
+            // 显示的是曲子中的位置；非原速播放时附上速率
+            if self.playback_rate == 1.0 {
+                ui.label(format!("Time: {}", time_display));
+            } else {
+                ui.label(format!("Time: {} ({:.2}x)", time_display, self.playback_rate));
+            }
+            ui.separator();
+            
+            if ui
+                .button(if self.is_playing {
+                    "⏸ Pause"
+                } else {
+                    "▶ Play"
+                })
+                .clicked()
+            {
+                self.is_playing = !self.is_playing;
+                if self.is_playing {
+                    self.last_update = ui.input(|i| i.time);
+                    // Reset last_tick to avoid mass triggering if we jumped
+                    // If we are at 0, set last_tick to 0 to include start notes.
+                    // If we are later, set to just before current to avoid retriggering current tick notes?
+                    // Let's just set it exact.
+                    self.last_tick = self.current_tick_position();
+                    // If we just started from 0, last_tick is 0. Our logic in update_sequencer handles 0 specially?
+                    // No, logic says if last_tick == 0, include 0.
+                    // So if we seek to 0 and play, it works.
+                    // If we pause at 0 and play, it re-triggers. That's probably fine.
+                } else {
+                    self.stop_playback_backend();
+                }
+                self.emit_event(EditorEvent::PlaybackStateChanged {
+                    is_playing: self.is_playing,
+                });
+                if self.is_playing {
+                    self.notify_playback_started();
+                } else {
+                    self.notify_playback_stopped();
+                }
+            }
+            let rec_text = RichText::new("● Rec").color(if self.recording { Color32::RED } else { ui.visuals().text_color() });
+            if ui
+                .selectable_label(self.recording, rec_text)
+                .on_hover_text("Record live input (feed_live_note_on/off or the computer keyboard) while playing")
+                .clicked()
+            {
+                self.set_recording(!self.recording);
+            }
+            if ui.button("⏹ Stop").clicked() {
+                self.is_playing = false;
+                self.current_time = 0.0;
+                self.last_tick = 0;
+                self.stop_playback_backend();
+                self.notify_playback_stopped();
+                self.emit_event(EditorEvent::PlaybackStateChanged { is_playing: false });
+                self.emit_transport_event();
+            }
+            if ui.small_button("Panic").on_hover_text("Silence all notes (all notes off)").clicked() {
+                self.panic();
+            }
+
+            ui.separator();
+
+            let history_tip = |verb: &str, entry: Option<&UndoEntry>| match entry.and_then(|e| e.label.as_deref()) {
+                Some(label) => format!("{verb} {label}"),
+                None => verb.to_owned(),
+            };
+            let undo_tip = history_tip("Undo", self.undo_stack.last());
+            let redo_tip = history_tip("Redo", self.redo_stack.last());
+            if ui
+                .add_enabled(!self.undo_stack.is_empty(), Button::new("↺"))
+                .on_hover_text(undo_tip)
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(!self.redo_stack.is_empty(), Button::new("↻"))
+                .on_hover_text(redo_tip)
+                .clicked()
+            {
+                self.redo();
+            }
+
+            ui.separator();
+
+            ui.label("Sig:");
+            ui.horizontal(|ui| {
+                let mut numer = self.state.time_signature.0;
+                let mut denom = self.state.time_signature.1;
+                let numer_changed = ui
+                    .add(DragValue::new(&mut numer).speed(0.1).range(1..=32))
+                    .changed();
+                ui.label("/");
+                let denom_changed = ui
+                    .add(DragValue::new(&mut denom).speed(0.1).range(1..=32))
+                    .changed();
+                if numer_changed || denom_changed {
+                    self.set_time_signature(numer, denom);
+                }
+            });
+
+            // 调号决定音高名称的拼写（升号或降号）
+            let mut key_signature = self.state.key_signature;
+            let tonic = tooltip::pitch_class_name(key_signature.tonic(), key_signature);
+            ComboBox::from_id_salt("key_signature_combo")
+                .width(36.0)
+                .selected_text(if key_signature.minor { format!("{tonic}m") } else { tonic.to_owned() })
+                .show_ui(ui, |ui| {
+                    for key in KeySignature::all() {
+                        ui.selectable_value(&mut key_signature, key, key.label());
+                    }
+                })
+                .response
+                .on_hover_text("Key signature: spells note names with sharps or flats");
+            if key_signature != self.state.key_signature {
+                self.set_key_signature(key_signature);
+            }
+
+            ui.separator();
+
+            // 编辑播放头所在速度段的速度
+            ui.label("BPM:");
+            let playhead_tick = self.current_tick_position();
+            let mut bpm = self.state.tempo_at(playhead_tick);
+            if ui
+                .add(DragValue::new(&mut bpm).speed(1.0).range(20.0..=400.0))
+                .changed()
+            {
+                self.set_tempo_at(playhead_tick, bpm);
+            }
+            let now = ui.input(|i| i.time);
+            let tap = ui.button("Tap");
+            if tap.clicked() {
+                self.tap_tempo(now);
+            }
+            // 单击会隐藏普通的悬停提示，敲击期间直接显示算出的速度，超时后恢复
+            let since_tap = self.tap_times.last().map(|&last| now - last).filter(|&since| since <= TAP_TEMPO_TIMEOUT);
+            if let Some(since_tap) = since_tap {
+                tap.show_tooltip_text(match self.tap_bpm {
+                    Some(bpm) => format!("Tapped tempo: {}", format_bpm(bpm)),
+                    None => format!("Keep tapping ({} more)", TAP_TEMPO_MIN_TAPS - self.tap_times.len()),
+                });
+                self.request_repaint(ui.ctx(), Some(std::time::Duration::from_secs_f64(TAP_TEMPO_TIMEOUT - since_tap)));
+            } else {
+                tap.on_hover_text("Click in time with the music to set the tempo");
+            }
+
+            ui.separator();
+
+            // Shift+点击新建音符的时值与力度
+            ui.label("Length:");
+            ComboBox::from_id_salt("note_length_combo")
+                .selected_text(self.note_length.label())
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.note_length, NoteLength::Snap, "Snap");
+                    ui.selectable_value(&mut self.note_length, NoteLength::LastUsed, "Last used");
+                    ui.separator();
+                    let (resolution, modifier) = match self.note_length {
+                        NoteLength::Fixed(resolution, modifier) => (resolution, modifier),
+                        _ => (SnapResolution::Quarter, SnapModifier::Straight),
+                    };
+                    for r in SnapResolution::ALL {
+                        ui.selectable_value(&mut self.note_length, NoteLength::Fixed(r, modifier), r.label());
+                    }
+                    ui.separator();
+                    for m in SnapModifier::ALL {
+                        let selected = matches!(self.note_length, NoteLength::Fixed(_, current) if current == m);
+                        if ui.selectable_label(selected, m.label()).clicked() {
+                            self.note_length = NoteLength::Fixed(resolution, m);
+                        }
+                    }
+                });
+            ui.add(DragValue::new(&mut self.default_velocity).range(1..=127).prefix("Vel: "))
+                .on_hover_text("Velocity of new notes");
+
+            ui.separator();
+
+            ui.label("Scale:");
+            let mut scale = self.scale_highlight;
+            ComboBox::from_id_salt("scale_kind_combo")
+                .selected_text(scale.map_or("Off", |(_, kind)| kind.label()))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut scale, None, "Off");
+                    // 从关闭状态打开时以调号的主音为根音
+                    let root = scale.map_or(self.state.key_signature.tonic(), |(root, _)| root);
+                    for kind in ScaleKind::ALL {
+                        ui.selectable_value(&mut scale, Some((root, kind)), kind.label());
+                    }
+                });
+            if let Some((root, kind)) = scale {
+                let mut root = root;
+                ComboBox::from_id_salt("scale_root_combo")
+                    .width(48.0)
+                    .selected_text(tooltip::pitch_class_name(root, self.state.key_signature))
+                    .show_ui(ui, |ui| {
+                        for pitch_class in 0..12 {
+                            ui.selectable_value(&mut root, pitch_class, tooltip::pitch_class_name(pitch_class, self.state.key_signature));
+                        }
+                    });
+                scale = Some((root, kind));
+                ui.checkbox(&mut self.snap_to_scale, "Snap")
+                    .on_hover_text("Move dragged notes by scale degrees (hold Alt to bypass)");
+            }
+            if scale != self.scale_highlight {
+                self.set_scale_highlight(scale);
+            }
+
+            ui.separator();
+
+            // 多轨：选择可编辑的轨道，勾选框控制其他轨道是否显示为幽灵音符
+            if self.state.is_multi_track() {
+                let names: Vec<String> = self
+                    .state
+                    .tracks
+                    .iter()
+                    .enumerate()
+                    .map(|(index, track)| track.display_name(index))
+                    .collect();
+                ui.label("Track:");
+                ComboBox::from_id_salt("track_combo")
+                    .selected_text(names.get(self.active_track as usize).cloned().unwrap_or_default())
+                    .show_ui(ui, |ui| {
+                        for (index, name) in names.iter().enumerate() {
+                            let track = index as u16;
+                            ui.horizontal(|ui| {
+                                let mut visible = self.is_track_visible(track);
+                                if ui
+                                    .add_enabled(track != self.active_track, Checkbox::without_text(&mut visible))
+                                    .on_hover_text("Show as ghost notes")
+                                    .changed()
+                                {
+                                    self.set_track_visible(track, visible);
+                                }
+                                if ui.selectable_label(track == self.active_track, name).clicked() {
+                                    self.set_active_track(track);
+                                }
                             });
-                            self.context_menu_pos = None;
-                            self.context_menu_open_pos = None;
                         }
-                        
-                        // Batch Transform
-                        if ui.add_enabled(has_selection, egui::Button::new("Batch Transform...")
-                            .min_size(egui::Vec2::new(200.0, 0.0))).clicked() {
-                            self.swing_original_notes.clear();
-                            self.swing_menu_ratio = 0.0;
-                            self.show_batch_transform_dialog = true;
-                            self.context_menu_pos = None;
-                            self.context_menu_open_pos = None;
+                    });
+                ui.separator();
+            }
+
+            // Display loop status and playback position
+            if self.loop_enabled {
+                ui.horizontal(|ui| {
+                    ui.label("🔁 Loop:");
+                    ui.label(format!(
+                        "{} - {}",
+                        self.state.musical_time(self.loop_start_tick),
+                        self.state.musical_time(self.loop_end_tick)
+                    ));
+                });
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Position:");
+                let tick = self.state.seconds_to_ticks(self.current_time as f64).max(0.0) as u64;
+                ui.label(self.state.musical_time(tick).to_string())
+                    .on_hover_text("Bar.beat.tick");
+            });
+
+            ui.separator();
+
+            for tool in NoteTool::ALL {
+                ui.selectable_value(&mut self.note_tool, tool, tool.label());
+            }
+            ui.separator();
+
+            if ui
+                .selectable_label(self.step_input, "⏺ Step")
+                .on_hover_text("Step input: sidebar keys insert notes at the step cursor (→ rest, ← back, hold Shift for chords)")
+                .clicked()
+            {
+                self.set_step_input(!self.step_input);
+            }
+            ui.toggle_value(&mut self.auto_trim_overlaps, "✂ Trim")
+                .on_hover_text("Auto-trim overlaps: dragging a note over others of the same pitch shortens or removes them");
+            if self.computer_keyboard_input {
+                ui.label(format!("⌨ {}", tooltip::format_pitch(self.computer_keyboard_base, self.pitch_naming())))
+                    .on_hover_text("Computer keyboard piano: Z..M / Q..P play, - / = shift the octave");
+            }
+
+            if ui
+                .button("⤢ Fit")
+                .on_hover_text("Zoom to fit the editable region and all notes (F)")
+                .clicked()
+            {
+                self.zoom_to_fit();
+            }
+            if ui
+                .add_enabled(!self.selected_notes.is_empty(), Button::new("⤢ Selection"))
+                .on_hover_text("Zoom to the selected notes (Shift+F)")
+                .clicked()
+            {
+                self.zoom_to_selection();
+            }
+            ui.toggle_value(&mut self.show_event_list, "☰ List")
+                .on_hover_text("Show the notes as an editable, sortable list");
+
+            ui.menu_button("⚙", |ui| {
+                if ui.button("Playback Settings…").clicked() {
+                    self.show_playback_settings = true;
+                    ui.close_menu();
+                }
+                if ui.button("Ticks per Beat…").clicked() {
+                    self.tpb_dialog_value = self.state.ticks_per_beat;
+                    self.show_tpb_dialog = true;
+                    ui.close_menu();
+                }
+                ui.add_enabled(
+                    self.reference_waveform.is_some(),
+                    Checkbox::new(&mut self.show_reference_waveform, "Reference Waveform"),
+                );
+                ui.checkbox(&mut self.show_minimap, "Overview");
+                let mut drum_mode = self.drum_map.is_some();
+                if ui.checkbox(&mut drum_mode, "Drum Map").changed() {
+                    // 打开时使用内置的 GM 映射
+                    self.drum_map = drum_mode.then(DrumMap::general_midi);
+                }
+                if let Some(drum_map) = &mut self.drum_map {
+                    ui.checkbox(&mut drum_map.hide_unmapped, "Hide Unmapped Rows");
+                }
+                ui.checkbox(&mut self.show_note_names, "Note Names");
+                ui.checkbox(&mut self.show_note_velocities, "Note Velocities");
+                ui.checkbox(&mut self.color_by_velocity, "Color by Velocity");
+                ui.add_enabled(
+                    self.color_by_velocity,
+                    Checkbox::new(&mut self.color_by_effective_velocity, "Use Velocity Curve"),
+                )
+                .on_hover_text("Color by the velocity that will actually play");
+            });
+        });
+    }
+
+    /// 事件列表中按当前排序列排列的可编辑音符下标
+    ///
+    /// 每帧重新排序：只排下标，五万个音符也只需几毫秒；排序稳定，相同值保持起点顺序（降序时整体反转）。
+    fn event_list_order(&self) -> Vec<usize> {
+        let (column, descending) = self.event_list_sort;
+        let notes = &self.state.notes;
+        let mut order: Vec<usize> = (0..notes.len()).filter(|&i| self.is_note_editable(&notes[i])).collect();
+        let value = |i: usize| match column {
+            EventListColumn::Start => notes[i].start,
+            EventListColumn::Duration => notes[i].duration,
+            EventListColumn::Pitch => notes[i].key as u64,
+            EventListColumn::Velocity => notes[i].velocity as u64,
+        };
+        // 音符本身按起点、ID 排序，按起点升序时无需再排
+        if column != EventListColumn::Start {
+            order.sort_by_key(|&i| value(i));
+        }
+        if descending {
+            order.reverse();
+        }
+        order
+    }
+
+    /// 选中事件列表中单击的音符，并滚动钢琴卷帘使其可见
+    fn select_from_event_list(&mut self, note: Note) {
+        let previous = std::mem::replace(&mut self.selected_notes, BTreeSet::from([note.id]));
+        self.notify_selection_changed(previous);
+        let visible = self
+            .visible_range()
+            .is_some_and(|(ticks, _)| ticks.contains(&note.start));
+        if !visible {
+            let tpb = self.state.ticks_per_beat as u64;
+            self.scroll_to_tick(note.start.saturating_sub(tpb));
+        }
+        self.scroll_to_key(note.key);
+    }
+
+    fn ui_event_list(&mut self, ui: &mut Ui) {
+        let naming = self.pitch_naming();
+        let width = EVENT_LIST_COLUMN_WIDTH;
+        ui.horizontal(|ui| {
+            ui.add_sized([width * 0.5, 0.0], Label::new("#"));
+            for column in EventListColumn::ALL {
+                let (sorted, descending) = self.event_list_sort;
+                let text = match (sorted == column, descending) {
+                    (true, false) => format!("{} ▲", column.label()),
+                    (true, true) => format!("{} ▼", column.label()),
+                    (false, _) => column.label().to_string(),
+                };
+                if ui.add_sized([width, 0.0], Button::new(text).frame(false)).clicked() {
+                    // 再次单击同一列切换升降序
+                    self.event_list_sort = (column, sorted == column && !descending);
+                }
+            }
+        });
+        let order = self.event_list_order();
+        let row_height = ui.spacing().interact_size.y;
+        let mut clicked = None;
+        let mut edit: Option<(NoteId, Note)> = None;
+        ScrollArea::vertical()
+            .id_salt("event_list")
+            .auto_shrink([false, false])
+            .max_height(ui.available_height().min(EVENT_LIST_HEIGHT - row_height))
+            .show_rows(ui, row_height, order.len(), |ui, rows| {
+                for row in rows {
+                    let note = self.state.notes[order[row]];
+                    let mut edited = note;
+                    ui.horizontal(|ui| {
+                        let selected = self.selected_notes.contains(&note.id);
+                        if ui
+                            .add_sized([width * 0.5, row_height], SelectableLabel::new(selected, format!("{}", row + 1)))
+                            .clicked()
+                        {
+                            clicked = Some(note);
+                        }
+                        ui.add_sized(
+                            [width, row_height],
+                            musical_time_drag_value(&mut edited.start, &self.state, self.snap_ticks().max(1) as f64),
+                        );
+                        ui.add_sized(
+                            [width, row_height],
+                            DragValue::new(&mut edited.duration)
+                                .speed(self.snap_ticks().max(1) as f64)
+                                .range(1..=u64::MAX),
+                        );
+                        ui.add_sized(
+                            [width, row_height],
+                            DragValue::new(&mut edited.key)
+                                .range(0..=127)
+                                .custom_formatter(|v, _| tooltip::format_pitch(v as u8, naming)),
+                        );
+                        ui.add_sized([width, row_height], DragValue::new(&mut edited.velocity).range(1..=127));
+                    });
+                    if edited != note {
+                        edit = Some((note.id, edited));
+                    }
+                }
+            });
+        if let Some(note) = clicked {
+            self.select_from_event_list(note);
+        }
+        if let Some((id, edited)) = edit {
+            let Some(key) = self.constrain_key(edited.key) else {
+                return;
+            };
+            self.edit_note_by_id(id, |n| {
+                n.start = edited.start;
+                n.duration = edited.duration;
+                n.key = key;
+                n.velocity = edited.velocity;
+            });
+        }
+    }
+
+    /// 概览条：整首曲子的音符标记与表示可见区域的视口矩形
+    fn ui_minimap(&mut self, ui: &mut Ui) {
+        let (rect, response) =
+            ui.allocate_exact_size(Vec2::new(ui.available_width(), MINIMAP_HEIGHT), Sense::click_and_drag());
+        self.minimap_rect = Some(rect);
+        let stale = self
+            .minimap
+            .as_ref()
+            .is_none_or(|cache| cache.revision != self.notes_revision || cache.note_count != self.state.notes.len());
+        if stale {
+            self.minimap = Some(MinimapCache::build(&self.state.notes, self.notes_revision, self.state.ticks_per_measure()));
+        }
+        let Some(cache) = &self.minimap else {
+            return;
+        };
+        let strip = Self::minimap_strip(rect);
+        let total = cache.total_ticks as f32;
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(strip, 2.0, self.palette.minimap_background);
+
+        let (low, high) = cache
+            .columns
+            .iter()
+            .flatten()
+            .fold((127u8, 0u8), |(lo, hi), (l, h)| (lo.min(*l), hi.max(*h)));
+        let marks = strip.shrink(3.0);
+        let key_y = |key: u8| {
+            let t = if high > low { (key - low) as f32 / (high - low) as f32 } else { 0.5 };
+            marks.max.y - t * marks.height()
+        };
+        let column_width = strip.width() / MINIMAP_COLUMNS as f32;
+        for (index, (l, h)) in cache.columns.iter().enumerate().filter_map(|(i, c)| c.map(|c| (i, c))) {
+            let x = strip.min.x + index as f32 * column_width;
+            painter.rect_filled(
+                Rect::from_min_max(Pos2::new(x, key_y(h) - 1.0), Pos2::new(x + column_width.max(1.0), key_y(l) + 1.0)),
+                0.0,
+                self.palette.minimap_note,
+            );
+        }
+
+        // 视口矩形：钢琴卷帘当前可见的 tick 范围
+        let tpb = self.state.ticks_per_beat.max(1) as f32;
+        let view_width = self.piano_roll_rect.map_or(strip.width(), |r| r.width() - PIANO_KEY_WIDTH).max(1.0);
+        let view_start = -self.manual_scroll_x / self.zoom_x * tpb;
+        let view_end = view_start + view_width / self.zoom_x * tpb;
+        let tick_x = |tick: f32| strip.min.x + tick / total * strip.width();
+        let viewport = Rect::from_x_y_ranges(tick_x(view_start)..=tick_x(view_end), strip.y_range()).intersect(strip);
+        painter.rect_filled(viewport, 2.0, self.palette.minimap_viewport);
+        painter.rect_stroke(viewport, 2.0, Stroke::new(1.0, self.palette.minimap_viewport_outline));
+
+        let edge_at = |x: f32| {
+            if (x - viewport.min.x).abs() <= MINIMAP_EDGE_GRAB {
+                MinimapDrag::LeftEdge
+            } else if (x - viewport.max.x).abs() <= MINIMAP_EDGE_GRAB {
+                MinimapDrag::RightEdge
+            } else {
+                MinimapDrag::Pan
+            }
+        };
+        if let Some(hover) = response.hover_pos() {
+            if self.minimap_drag.is_none() && edge_at(hover.x) != MinimapDrag::Pan {
+                ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
+            }
+        }
+        if response.drag_started() {
+            let origin = ui.input(|i| i.pointer.press_origin()).or(response.interact_pointer_pos());
+            self.minimap_drag = origin.map(|pos| edge_at(pos.x));
+        }
+        let mode = match (response.clicked(), self.minimap_drag) {
+            (true, _) => Some(MinimapDrag::Pan),
+            (false, mode) if response.dragged() => mode,
+            _ => None,
+        };
+        if let (Some(mode), Some(pointer)) = (mode, response.interact_pointer_pos()) {
+            let tick = ((pointer.x - strip.min.x) / strip.width()).clamp(0.0, 1.0) * total;
+            let min_span = tpb / 4.0;
+            match mode {
+                MinimapDrag::Pan => {
+                    self.manual_scroll_x = (view_width / 2.0 - tick / tpb * self.zoom_x).min(0.0);
+                }
+                MinimapDrag::LeftEdge => {
+                    let start = tick.min(view_end - min_span).max(0.0);
+                    self.zoom_x = (view_width / ((view_end - start) / tpb)).clamp(10.0, 500.0);
+                    self.manual_scroll_x = (view_width - view_end / tpb * self.zoom_x).min(0.0);
+                }
+                MinimapDrag::RightEdge => {
+                    let end = tick.max(view_start + min_span);
+                    self.zoom_x = (view_width / ((end - view_start) / tpb)).clamp(10.0, 500.0);
+                    self.manual_scroll_x = (-view_start / tpb * self.zoom_x).min(0.0);
+                }
+            }
+        }
+        if response.drag_stopped() {
+            self.minimap_drag = None;
+        }
+    }
+
+    /// 概览条中与钢琴卷帘网格对齐的部分（左侧让出键盘宽度）
+    fn minimap_strip(rect: Rect) -> Rect {
+        Rect::from_min_max(Pos2::new(rect.min.x + PIANO_KEY_WIDTH, rect.min.y + 2.0), Pos2::new(rect.max.x, rect.max.y - 2.0))
+    }
+
+    /// 按视图大小（不含键盘与时间轴）执行缩放请求
+    fn apply_zoom_request(&mut self, target: ZoomTarget, view: Vec2) {
+        let notes: Vec<Note> = match target {
+            ZoomTarget::All => self.state.notes.clone(),
+            ZoomTarget::Selection => self.selected_notes_snapshot(),
+        };
+        let tpb = self.state.ticks_per_beat.max(1) as f32;
+        let note_range = || {
+            let start = notes.iter().map(|n| n.start).min()?;
+            Some((start, start + Self::block_span(&notes)))
+        };
+        let (start, end) = match target {
+            ZoomTarget::All => self
+                .edit_bounds
+                .or_else(note_range)
+                .unwrap_or((0, self.state.ticks_per_measure())),
+            ZoomTarget::Selection => match note_range() {
+                Some(range) => range,
+                None => return,
+            },
+        };
+        let beats = (end - start).max(1) as f32 / tpb;
+        self.zoom_x = (view.x.max(1.0) / beats).clamp(10.0, 500.0);
+        self.manual_scroll_x = -(start as f32 / tpb) * self.zoom_x;
+
+        // 音高范围上下各留一行，超出最小缩放时以范围中心居中
+        let layout = self.row_layout();
+        let note_rows = || notes.iter().filter_map(|n| layout.row_of(n.key));
+        let (Some(top), Some(bottom)) = (note_rows().min(), note_rows().max()) else {
+            return;
+        };
+        let rows = (bottom - top) as f32 + 3.0;
+        self.zoom_y = (view.y.max(1.0) / rows).clamp(5.0, 50.0);
+        let center_row = (top as f32 + bottom as f32) / 2.0 + 0.5;
+        self.manual_scroll_y = (view.y / 2.0 - center_row * self.zoom_y).min(0.0);
+    }
+
+    fn ui_piano_roll(&mut self, ui: &mut Ui) {
+        let key_width = PIANO_KEY_WIDTH;
+        let timeline_height = TIMELINE_HEIGHT;
+
+        // Piano Roll ScrollArea
+        // We disable built-in scrolling since we handle it manually via middle mouse
+        ScrollArea::both()
+            .auto_shrink([false, false])
+            .enable_scrolling(false) // Disable wheel scroll
+            .show(ui, |ui| {
+                let available_size = ui.available_size();
+                let (rect, response) =
+                    ui.allocate_exact_size(available_size, Sense::click_and_drag());
+                self.piano_roll_rect = Some(rect);
+
+                if let Some(target) = self.zoom_request.take() {
+                    self.apply_zoom_request(target, rect.size() - Vec2::new(key_width, timeline_height));
+                }
+
+                // Handle Zoom (Ctrl/Alt + Scroll)
+                let scroll_delta = ui.input(|i| i.raw_scroll_delta);
+                if scroll_delta != Vec2::ZERO {
+                    if ui.input(|i| i.modifiers.ctrl) {
+                        // Zoom X (Horizontal) around mouse pointer
+                        if scroll_delta.y != 0.0 {
+                            let old_zoom = self.zoom_x;
+                            let new_zoom = (self.zoom_x
+                                * if scroll_delta.y > 0.0 { 1.1 } else { 0.9 })
+                            .clamp(10.0, 500.0);
+
+                            // Calculate mouse position relative to timeline start (in beats)
+                            // mouse_x = start_x + beats * zoom + scroll
+                            // beats = (mouse_x - start_x - scroll) / zoom
+                            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
+                                let rel_x = mouse_pos.x - (rect.min.x + key_width);
+                                let beats_at_mouse = (rel_x - self.manual_scroll_x) / old_zoom;
+
+                                // We want beats_at_mouse to stay at rel_x after zoom
+                                // rel_x = beats_at_mouse * new_zoom + new_scroll
+                                // new_scroll = rel_x - beats_at_mouse * new_zoom
+                                self.manual_scroll_x = rel_x - beats_at_mouse * new_zoom;
+                            }
+
+                            self.zoom_x = new_zoom;
+                        }
+                    } else if ui.input(|i| i.modifiers.alt) {
+                        // Zoom Y (Vertical) around mouse pointer
+                        if scroll_delta.y != 0.0 {
+                            let old_zoom = self.zoom_y;
+                            let new_zoom = (self.zoom_y
+                                * if scroll_delta.y > 0.0 { 1.1 } else { 0.9 })
+                            .clamp(5.0, 50.0);
+
+                            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
+                                let rel_y = mouse_pos.y - (rect.min.y + timeline_height);
+                                // keys_from_top = (rel_y - scroll) / zoom
+                                // Note: Our y calc is more complex: note_to_y = (127 - note) * zoom
+                                // But linear mapping is: y = value * zoom + scroll.
+                                // So logic is same.
+                                let value_at_mouse = (rel_y - self.manual_scroll_y) / old_zoom;
+                                self.manual_scroll_y = rel_y - value_at_mouse * new_zoom;
+                            }
+
+                            self.zoom_y = new_zoom;
+                        }
+                    }
+                }
+
+                // Handle Middle Mouse Pan
+                // Note: interact_pos() returns None if not interacting with specific widget?
+                // For global pointer, use pointer.interact_pos() or pointer.pos().
+                if ui.input(|i| i.pointer.middle_down()) {
+                    if self.is_panning {
+                        if let Some(start) = self.pan_start {
+                            // use pointer.pos() for raw screen coordinates, consistent for dragging
+                            if let Some(curr) = ui.input(|i| i.pointer.hover_pos()) {
+                                let delta = curr - start;
+                                // Apply delta to scroll.
+                                // ui.scroll_with_delta(delta); // SCROLLAREA LOGIC REMOVED
+                                // Manually update scroll offset
+                                self.manual_scroll_x += delta.x;
+                                self.manual_scroll_y += delta.y;
+
+                                // Update start for continuous delta
+                                self.pan_start = Some(curr);
+                            }
+                        }
+                    } else {
+                        self.is_panning = true;
+                        self.pan_start = ui.input(|i| i.pointer.hover_pos());
+                    }
+                } else {
+                    self.is_panning = false;
+                    self.pan_start = None;
+                }
+
+                // Apply Scroll Constraints
+                // Limit horizontal scroll: can't scroll past 0 (can't see negative time)
+                // Note: manual_scroll_x is an offset. Content moves right when scroll_x is positive?
+                // Wait, usually scroll is negative offset to move content left (to see right side).
+                // Let's assume manual_scroll_x <= 0 means we have scrolled right.
+                // If manual_scroll_x > 0, we see negative time gap.
+                // So max x is 0.
+
+                if self.manual_scroll_x > 0.0 {
+                    self.manual_scroll_x = 0.0;
+                }
+
+                // Limit vertical scroll
+                let layout = self.row_layout();
+                let total_content_height = layout.len() as f32 * self.zoom_y;
+                let view_height = rect.height() - timeline_height;
+                // If content < view, pin to top.
+                // If content > view, allow scrolling down to (view - content).
+
+                let min_scroll_y = if total_content_height > view_height {
+                    view_height - total_content_height
+                } else {
+                    0.0
+                };
+
+                // Clamp scroll y between min_scroll_y and 0.
+                self.manual_scroll_y = self.manual_scroll_y.clamp(min_scroll_y, 0.0);
+
+                let mut pointer_consumed = false;
+                let note_offset_x = rect.min.x + key_width + self.manual_scroll_x;
+
+                // Coordinate transforms (defined early for use in interactions)
+                let tick_to_x = |tick: u64, zoom_x: f32, ticks_per_beat: u16| -> f32 {
+                    (tick as f32 / ticks_per_beat as f32) * zoom_x
+                };
+
+                // Handle timeline interactions (playhead seek and loop editing)
+                if let Some(pointer) = response.interact_pointer_pos() {
+                    let in_timeline = pointer.y < rect.min.y + timeline_height
+                        && pointer.x >= rect.min.x + key_width;
+                    
+                    if in_timeline {
+                        let modifiers = ui.input(|i| i.modifiers);
+                        let is_shift = modifiers.shift;
+                        let disable_snap = modifiers.alt;
+                        
+                        // Convert pointer position to tick
+                        let mut x = pointer.x - (rect.min.x + key_width);
+                        x = (x - self.manual_scroll_x).max(0.0);
+                        let beats = x / self.zoom_x;
+                        let tick = (beats * self.state.ticks_per_beat as f32) as i64;
+                        let snapped_tick = self.snap_tick(tick, None, disable_snap);
+                        
+                        let hovered_marker = self.marker_near_x(pointer.x, note_offset_x);
+
+                        // 右键单击标记：删除该标记
+                        if response.clicked_by(PointerButton::Secondary) && !is_shift {
+                            if let Some(marker_id) = hovered_marker {
+                                self.remove_marker(marker_id);
+                                pointer_consumed = true;
+                            }
+                        }
+
+                        // 双击空白处：在吸附位置添加标记
+                        if response.double_clicked() && hovered_marker.is_none() && !is_shift {
+                            let label = format!("Marker {}", self.state.markers.len() + 1);
+                            self.add_marker(snapped_tick, label, Marker::DEFAULT_COLOR);
+                            pointer_consumed = true;
+                        }
+
+                        // Handle right-click: Shift+右键删除循环区域
+                        if response.clicked_by(PointerButton::Secondary) && is_shift {
+                            // 检查循环区域是否启用
+                            if self.loop_enabled {
+                                // 将点击位置转换为 tick（使用已计算的 snapped_tick）
+                                let click_tick = snapped_tick;
+                                
+                                // 判断点击位置是否在循环区域内
+                                if click_tick >= self.loop_start_tick && click_tick <= self.loop_end_tick {
+                                    // 删除循环区域
+                                    self.loop_enabled = false;
+                                    pointer_consumed = true;
+                                }
+                            }
+                        }
+                        
+                        // Handle drag start
+                        if ui.input(|i| i.pointer.primary_pressed()) && !self.is_dragging_note {
+                            if is_shift {
+                                // Shift + 左键：开始循环边界编辑
+                                if self.loop_enabled {
+                                    // Determine edit mode based on pointer position relative to loop region
+                                    let loop_start_x = note_offset_x
+                                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
+                                    let loop_end_x = note_offset_x
+                                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
+                                    
+                                    let loop_width = loop_end_x - loop_start_x;
+                                    let relative_x = pointer.x - loop_start_x;
+                                    
+                                    let edit_mode = if loop_width > 0.0 {
+                                        if relative_x < loop_width / 3.0 {
+                                            LoopEditMode::Start
+                                        } else if relative_x > loop_width * 2.0 / 3.0 {
+                                            LoopEditMode::End
+                                        } else {
+                                            LoopEditMode::Move
+                                        }
+                                    } else {
+                                        LoopEditMode::Move
+                                    };
+                                    
+                                    self.drag_action = DragAction::LoopEdit;
+                                    self.loop_edit_mode = Some(edit_mode);
+                                    self.drag_original_loop_start = Some(self.loop_start_tick);
+                                    self.drag_original_loop_end = Some(self.loop_end_tick);
+                                    self.drag_start_pos = Some(pointer);
+                                    pointer_consumed = true;
+                                } else {
+                                    // Loop not enabled: enable it and set initial region
+                                    self.loop_enabled = true;
+                                    self.drag_action = DragAction::LoopEdit;
+                                    self.loop_edit_mode = Some(LoopEditMode::Move);
+                                    self.loop_start_tick = snapped_tick;
+                                    self.loop_end_tick = snapped_tick + self.state.ticks_per_measure();
+                                    self.drag_original_loop_start = Some(self.loop_start_tick);
+                                    self.drag_original_loop_end = Some(self.loop_end_tick);
+                                    self.drag_start_pos = Some(pointer);
+                                    pointer_consumed = true;
+                                }
+                            } else if let Some(marker_id) = hovered_marker {
+                                // 左键按住标记：拖动标记
+                                if let Some(marker) = self.state.markers.iter().find(|m| m.id == marker_id) {
+                                    self.dragging_marker = Some((marker_id, marker.tick));
+                                    self.push_undo_snapshot();
+                                    self.drag_action = DragAction::MarkerMove;
+                                    pointer_consumed = true;
+                                }
+                            } else {
+                                // 单独左键：开始播放位置调整
+                                self.drag_action = DragAction::PlayheadSeek;
+                                self.current_time = self.state.ticks_to_seconds(snapped_tick as f64) as f32;
+                                self.last_tick = snapped_tick;
+                                if self.step_input {
+                                    self.set_step_cursor(snapped_tick);
+                                }
+                                self.is_dragging_note = false;
+                                self.emit_transport_event();
+                                pointer_consumed = true;
+                            }
+                        }
+                        
+                        // Handle drag update
+                        if ui.input(|i| i.pointer.primary_down()) {
+                            match self.drag_action {
+                                DragAction::LoopEdit => {
+                                    if let Some(edit_mode) = self.loop_edit_mode {
+                                        match edit_mode {
+                                            LoopEditMode::Start => {
+                                                self.loop_start_tick = snapped_tick.min(self.loop_end_tick.saturating_sub(1));
+                                            }
+                                            LoopEditMode::End => {
+                                                self.loop_end_tick = snapped_tick.max(self.loop_start_tick + 1);
+                                            }
+                                            LoopEditMode::Move => {
+                                                if let (Some(original_start), Some(original_end), Some(start_pos)) = 
+                                                    (self.drag_original_loop_start, self.drag_original_loop_end, self.drag_start_pos) {
+                                                    let delta_x = pointer.x - start_pos.x;
+                                                    let delta_beats = delta_x / self.zoom_x;
+                                                    let delta_ticks = (delta_beats * self.state.ticks_per_beat as f32) as i64;
+                                                    
+                                                    let loop_duration = original_end - original_start;
+                                                    let new_start_raw = (original_start as i64 + delta_ticks).max(0);
+                                                    let new_start = self.snap_tick(new_start_raw, None, disable_snap);
+                                                    let new_end = new_start + loop_duration;
+                                                    
+                                                    self.loop_start_tick = new_start;
+                                                    self.loop_end_tick = new_end;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    pointer_consumed = true;
+                                }
+                                DragAction::PlayheadSeek => {
+                                    self.current_time = self.state.ticks_to_seconds(snapped_tick as f64) as f32;
+                                    self.last_tick = snapped_tick;
+                                    if self.step_input {
+                                        self.set_step_cursor(snapped_tick);
+                                    }
+                                    self.emit_transport_event();
+                                    pointer_consumed = true;
+                                }
+                                DragAction::MarkerMove => {
+                                    if let Some((marker_id, _)) = self.dragging_marker {
+                                        self.place_marker(marker_id, snapped_tick);
+                                    }
+                                    pointer_consumed = true;
+                                }
+                                _ => {}
+                            }
+                        }
+                        
+                        // Handle drag end
+                        if ui.input(|i| i.pointer.primary_released())
+                            && matches!(self.drag_action, DragAction::LoopEdit | DragAction::PlayheadSeek)
+                        {
+                            self.drag_action = DragAction::None;
+                            self.loop_edit_mode = None;
+                            self.drag_original_loop_start = None;
+                            self.drag_original_loop_end = None;
+                            self.drag_start_pos = None;
+                        }
+                        
+                        // Update cursor based on hover state
+                        if !self.is_dragging_note {
+                            if is_shift {
+                                if self.loop_enabled {
+                                    let loop_start_x = note_offset_x
+                                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
+                                    let loop_end_x = note_offset_x
+                                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
+                                    
+                                    let loop_width = loop_end_x - loop_start_x;
+                                    let relative_x = pointer.x - loop_start_x;
+                                    
+                                    if loop_width > 0.0 {
+                                        if relative_x < loop_width / 3.0
+                                            || relative_x > loop_width * 2.0 / 3.0
+                                        {
+                                            ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
+                                        } else {
+                                            ui.ctx().set_cursor_icon(CursorIcon::Grab);
+                                        }
+                                    } else {
+                                        ui.ctx().set_cursor_icon(CursorIcon::Grab);
+                                    }
+                                } else {
+                                    ui.ctx().set_cursor_icon(CursorIcon::Grab);
+                                }
+                            } else if hovered_marker.is_some() || self.dragging_marker.is_some() {
+                                ui.ctx().set_cursor_icon(CursorIcon::Grab);
+                            } else {
+                                ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+                            }
+                        }
+                    }
+                }
+                // 标记拖动在松开时结束，指针离开时间轴也一样
+                if self.dragging_marker.is_some() && !ui.input(|i| i.pointer.primary_down()) {
+                    self.finish_marker_drag();
+                }
+
+                // Coordinate transforms
+                let time_to_x = |time: f32, zoom_x: f32| -> f32 {
+                    // time in beats
+                    time * zoom_x
+                };
+
+                let tick_to_x = |tick: u64, zoom_x: f32, ticks_per_beat: u16| -> f32 {
+                    (tick as f32 / ticks_per_beat as f32) * zoom_x
+                };
+
+                // High notes at top, low notes at bottom; rows hidden by the drum map have no y
+                let note_to_y = |note: u8, zoom_y: f32| -> Option<f32> {
+                    layout.row_of(note).map(|row| row as f32 * zoom_y)
+                };
+                let drum_mode = self.drum_map.is_some();
+
+                let painter = ui.painter_at(rect);
+                let theme = self.palette;
+                let grid_top = rect.min.y + timeline_height;
+                let grid_bottom = rect.max.y;
+                painter.rect_filled(rect, 0.0, theme.background);
+
+                // Draw Vertical Grid (Beats / Measures / Subdivisions)
+                let tpb = self.state.ticks_per_beat.max(1) as u64;
+                let ticks_per_measure = self.state.ticks_per_measure();
+
+                let visible_beats_start = (-self.manual_scroll_x / self.zoom_x).floor();
+                let visible_beats_end = visible_beats_start + (rect.width() / self.zoom_x) + 2.0;
+                let mut start_tick = (visible_beats_start * tpb as f32).floor() as i64;
+                if start_tick < 0 {
+                    start_tick = 0;
+                }
+                let end_tick = (visible_beats_end * tpb as f32).ceil() as i64;
+
+                let mut grid_shapes = Vec::new();
+                render::vertical_grid(
+                    &mut grid_shapes,
+                    &theme,
+                    self.triplet_grid(),
+                    note_offset_x,
+                    self.zoom_x,
+                    tpb,
+                    ticks_per_measure,
+                    start_tick,
+                    end_tick,
+                    Rangef::new(rect.min.x, rect.max.x),
+                    Rangef::new(grid_top, grid_bottom),
+                );
+
+                // 音阶高亮画在水平网格线之下
+                if let Some((root, kind)) = self.scale_highlight {
+                    render::scale_rows(
+                        &mut grid_shapes,
+                        &theme,
+                        kind.keys(root),
+                        root,
+                        &layout.keys,
+                        rect.min.y + timeline_height + self.manual_scroll_y,
+                        self.zoom_y,
+                        Rangef::new(rect.min.x + key_width, rect.max.x),
+                        Rangef::new(grid_top, rect.max.y),
+                    );
+                }
+
+                // Draw Grid (Horizontal/Notes)
+                render::horizontal_grid(
+                    &mut grid_shapes,
+                    &theme,
+                    rect.min.y + timeline_height + self.manual_scroll_y,
+                    self.zoom_y,
+                    layout.len(),
+                    Rangef::new(rect.min.x + key_width, rect.max.x),
+                    Rangef::new(grid_top, rect.max.y),
+                );
+                if let Some(allowed) = self.allowed_keys {
+                    render::disallowed_rows(
+                        &mut grid_shapes,
+                        &theme,
+                        |key| allowed.contains(key),
+                        &layout.keys,
+                        rect.min.y + timeline_height + self.manual_scroll_y,
+                        self.zoom_y,
+                        Rangef::new(rect.min.x + key_width, rect.max.x),
+                        Rangef::new(grid_top, rect.max.y),
+                    );
+                }
+                if let Some(waveform) = self.reference_waveform.as_ref().filter(|_| self.show_reference_waveform) {
+                    let keys_top = rect.min.y + timeline_height + self.manual_scroll_y;
+                    let band = match waveform.key_band {
+                        Some((low, high)) => Rangef::new(
+                            keys_top + (127 - high.min(127).max(low)) as f32 * self.zoom_y,
+                            keys_top + (128 - low.min(127)) as f32 * self.zoom_y,
+                        ),
+                        None => Rangef::new(keys_top, keys_top + 128.0 * self.zoom_y),
+                    };
+                    // 波形按起始速度线性换算，速度变化之后只作近似对照
+                    let beats_per_second = self.state.bpm.max(1.0) / 60.0;
+                    render::reference_waveform(
+                        &mut grid_shapes,
+                        &theme,
+                        waveform,
+                        note_offset_x,
+                        self.zoom_x * beats_per_second,
+                        Rangef::new(rect.min.x + key_width, rect.max.x),
+                        band.intersection(Rangef::new(grid_top, rect.max.y)),
+                        band,
+                    );
+                }
+                painter.extend(grid_shapes);
+
+                let base_x = rect.min.x + key_width;
+                let base_y = rect.min.y + timeline_height;
+                let manual_scroll_x = self.manual_scroll_x;
+                let manual_scroll_y = self.manual_scroll_y;
+                let zoom_x = self.zoom_x;
+                let zoom_y = self.zoom_y;
+                let ticks_per_beat = self.state.ticks_per_beat as f32;
+
+                let pointer_to_tick = move |pos: Pos2| -> i64 {
+                    let rel_x = pos.x - base_x - manual_scroll_x;
+                    let beats = rel_x / zoom_x;
+                    (beats * ticks_per_beat).round() as i64
+                };
+                let pointer_to_key = |pos: Pos2| -> u8 {
+                    let keyboard_top = base_y + manual_scroll_y;
+                    let rel_y = pos.y - keyboard_top;
+                    layout.key_at(rel_y / zoom_y)
+                };
+
+                // Draw Notes with viewport culling for performance
+                let note_offset_y = rect.min.y + timeline_height + self.manual_scroll_y;
+                
+                // Calculate visible time range for culling (notes are sorted by start time)
+                let visible_start_tick = if self.manual_scroll_x < 0.0 {
+                    ((-self.manual_scroll_x / self.zoom_x) * self.state.ticks_per_beat as f32) as u64
+                } else {
+                    0
+                };
+                let visible_end_tick = visible_start_tick.saturating_add(
+                    ((rect.width() / self.zoom_x) * self.state.ticks_per_beat as f32) as u64 + 1
+                );
+                
+                // Use binary search to find notes in visible time range
+                let notes_snapshot = &self.state.notes;
+                let start_idx = notes_snapshot.partition_point(|n| n.start + n.duration < visible_start_tick);
+                let end_idx = notes_snapshot.partition_point(|n| n.start <= visible_end_tick);
+                
+                // 鼓映射模式下音符是以起点为中心、宽度固定为一行高的菱形，与时值无关
+                let editor_tpb = self.state.ticks_per_beat;
+                let note_span = |start: u64, duration: u64, ticks_per_beat: u16| -> (f32, f32) {
+                    let x = note_offset_x + tick_to_x(start, zoom_x, ticks_per_beat);
+                    if drum_mode {
+                        (x - zoom_y * 0.5, zoom_y)
+                    } else {
+                        (x, tick_to_x(duration, zoom_x, ticks_per_beat).max(5.0))
+                    }
+                };
+                let note_rect_of = |note: &Note| {
+                    let Some(y) = note_to_y(note.key, zoom_y) else {
+                        return Rect::NOTHING;
+                    };
+                    let (x, w) = note_span(note.start, note.duration, editor_tpb);
+                    Rect::from_min_size(Pos2::new(x, note_offset_y + y), Vec2::new(w, zoom_y))
+                };
+
+                // 宿主设置的幽灵内容与多轨状态下其他可见轨道的音符只作参考，不参与交互
+                let mut ghost_shapes = Vec::new();
+                if let Some(ghost) = &self.ghost {
+                    let editor_tpb = self.state.ticks_per_beat.max(1) as u128;
+                    let to_ghost_ticks = |tick: u64| (tick as u128 * ghost.ticks_per_beat as u128 / editor_tpb) as u64;
+                    let (ghost_start, ghost_end) = (to_ghost_ticks(visible_start_tick), to_ghost_ticks(visible_end_tick) + 1);
+                    let start_idx = ghost.notes.partition_point(|n| n.start + n.duration < ghost_start);
+                    let end_idx = ghost.notes.partition_point(|n| n.start <= ghost_end);
+                    for note in &ghost.notes[start_idx..end_idx.max(start_idx)] {
+                        let Some(y) = note_to_y(note.key, self.zoom_y) else {
+                            continue;
+                        };
+                        let (x, w) = note_span(note.start, note.duration, ghost.ticks_per_beat);
+                        let ghost_rect = Rect::from_min_size(Pos2::new(x, note_offset_y + y), Vec2::new(w, self.zoom_y));
+                        if ghost_rect.intersects(rect) {
+                            render::ghost_note(&mut ghost_shapes, &theme, ghost_rect);
+                        }
+                    }
+                }
+                for note in &notes_snapshot[start_idx..end_idx.min(notes_snapshot.len())] {
+                    if !self.is_note_editable(note) && !self.hidden_tracks.contains(&note.track) {
+                        let ghost_rect = note_rect_of(note);
+                        if ghost_rect.intersects(rect) {
+                            render::ghost_note(&mut ghost_shapes, &theme, ghost_rect);
+                        }
+                    }
+                }
+                painter.extend(ghost_shapes);
+
+                // Collect note IDs and rects first to avoid borrow conflicts
+                let visible_slice = &notes_snapshot[start_idx..end_idx.min(notes_snapshot.len())];
+                let visible_notes: Vec<(NoteId, Rect)> = visible_slice
+                    .iter()
+                    .filter(|note| self.is_note_editable(note))
+                    .map(|note| (note.id, note_rect_of(note)))
+                    .filter(|(_, note_rect)| note_rect.intersects(rect))
+                    .collect();
+
+                // 音符主体合并为一个网格，视图与音符都没变时沿用上一帧的网格；
+                // 选中的音符再逐个叠加选中色与加粗描边（鼓映射模式的菱形音符仍逐个绘制）
+                let mut note_shapes = Vec::new();
+                if drum_mode {
+                    for note in visible_slice.iter().filter(|note| self.is_note_editable(note)) {
+                        let note_rect = note_rect_of(note);
+                        if note_rect.intersects(rect) {
+                            let selected = self.selected_notes.contains(&note.id);
+                            render::drum_hit(&mut note_shapes, &theme, note_rect, self.note_fill(&theme, note, selected), selected);
+                        }
+                    }
+                } else {
+                    let key = (!(self.color_by_velocity && self.color_by_effective_velocity)).then(|| NoteMeshKey {
+                        zoom: Vec2::new(zoom_x, zoom_y),
+                        ticks_per_beat: editor_tpb,
+                        rows: layout.rows,
+                        active_track: self.state.is_multi_track().then_some(self.active_track),
+                        color_by_velocity: self.color_by_velocity,
+                        theme,
+                    });
+                    let notes_in = |(start, end): (u64, u64)| {
+                        let from = notes_snapshot.partition_point(|n| n.start + n.duration < start);
+                        let to = notes_snapshot.partition_point(|n| n.start <= end);
+                        &notes_snapshot[from..to.max(from)]
+                    };
+                    let cached = self.note_mesh.as_ref().filter(|cache| {
+                        key.as_ref() == Some(&cache.key)
+                            && cache.ticks.0 <= visible_start_tick
+                            && visible_end_tick <= cache.ticks.1
+                            && cache.notes_hash == note_mesh_hash(notes_in(cache.ticks))
+                    });
+                    // egui 0.30 的 `Shape::Mesh` 按值持有网格，无法共享 `Arc<Mesh>`；
+                    // 命中缓存时只复制顶点与索引缓冲并整体平移，不再逐个音符生成四边形
+                    let origin = Vec2::new(note_offset_x, note_offset_y);
+                    let mut mesh = match cached {
+                        Some(cache) => cache.mesh.clone(),
+                        None => {
+                            let pad = visible_end_tick - visible_start_tick;
+                            let ticks = (visible_start_tick.saturating_sub(pad), visible_end_tick.saturating_add(pad));
+                            let notes = notes_in(ticks);
+                            let mut mesh = Mesh::default();
+                            mesh.reserve_vertices(notes.len() * 8);
+                            mesh.reserve_triangles(notes.len() * 4);
+                            for note in notes.iter().filter(|note| self.is_note_editable(note)) {
+                                let note_rect = note_rect_of(note);
+                                if note_rect.is_positive() {
+                                    let fill = self.note_fill(&theme, note, false);
+                                    render::note_quads(&mut mesh, &theme, note_rect.translate(-origin), fill);
+                                }
+                            }
+                            self.note_mesh_rebuilds += 1;
+                            let notes_hash = note_mesh_hash(notes);
+                            self.note_mesh = key.map(|key| NoteMeshCache { key, ticks, notes_hash, mesh: mesh.clone() });
+                            mesh
+                        }
+                    };
+                    mesh.translate(origin);
+                    note_shapes.push(Shape::mesh(mesh));
+                    if !self.selected_notes.is_empty() {
+                        for note in visible_slice.iter().filter(|note| self.selected_notes.contains(&note.id)) {
+                            let note_rect = note_rect_of(note);
+                            if self.is_note_editable(note) && note_rect.intersects(rect) {
+                                render::note(&mut note_shapes, &theme, note_rect, self.note_fill(&theme, note, true), true);
+                            }
+                        }
+                    }
+                }
+                painter.extend(note_shapes);
+
+                // Pitch names / velocities inside notes that are large enough
+                if (self.show_note_names || self.show_note_velocities) && self.zoom_y >= NOTE_LABEL_MIN_ZOOM_Y {
+                    let font = FontId::proportional((self.zoom_y - 3.0).min(11.0));
+                    for note in &notes_snapshot[start_idx..end_idx.min(notes_snapshot.len())] {
+                        if !self.is_note_editable(note) {
+                            continue;
+                        }
+                        let note_rect = note_rect_of(note);
+                        if note_rect.width() < NOTE_TEXT_MIN_WIDTH || !note_rect.intersects(rect) {
+                            continue;
+                        }
+                        let text = match (self.show_note_names, self.show_note_velocities) {
+                            (true, true) => format!("{} {}", tooltip::format_pitch(note.key, self.pitch_naming()), note.velocity),
+                            (true, false) => tooltip::format_pitch(note.key, self.pitch_naming()),
+                            _ => note.velocity.to_string(),
+                        };
+                        let fill = self.note_fill(&theme, note, self.selected_notes.contains(&note.id));
+                        painter.with_clip_rect(note_rect.shrink(2.0).intersect(painter.clip_rect())).text(
+                            Pos2::new(note_rect.min.x + 4.0, note_rect.center().y),
+                            Align2::LEFT_CENTER,
+                            text,
+                            font.clone(),
+                            render::note_text_color(fill),
+                        );
+                    }
+                }
+
+                // Note labels above their notes when zoomed in enough
+                if self.zoom_y >= NOTE_LABEL_MIN_ZOOM_Y {
+                    let labels: HashMap<NoteId, SmallString> = notes_snapshot[start_idx..end_idx.min(notes_snapshot.len())]
+                        .iter()
+                        .filter_map(|note| note.label.map(|label| (note.id, label)))
+                        .collect();
+                    for (note_id, note_rect) in &visible_notes {
+                        if let Some(label) = labels.get(note_id) {
+                            painter.text(
+                                Pos2::new(note_rect.min.x + 1.0, note_rect.min.y),
+                                Align2::LEFT_BOTTOM,
+                                label.as_str(),
+                                FontId::proportional(9.0),
+                                theme.note_label,
+                            );
+                        }
+                    }
+                }
+
+                // Group members share a colored underline
+                if !self.state.groups.is_empty() {
+                    let group_colors: HashMap<NoteId, Color32> = self
+                        .state
+                        .groups
+                        .iter()
+                        .flat_map(|group| {
+                            let [r, g, b] = group.color;
+                            group.note_ids.iter().map(move |id| (*id, Color32::from_rgb(r, g, b)))
+                        })
+                        .collect();
+                    for (note_id, note_rect) in &visible_notes {
+                        if let Some(color) = group_colors.get(note_id) {
+                            let y = note_rect.max.y - 1.5;
+                            painter.line_segment(
+                                [Pos2::new(note_rect.min.x + 1.0, y), Pos2::new(note_rect.max.x - 1.0, y)],
+                                Stroke::new(3.0, *color),
+                            );
+                        }
+                    }
+                }
+
+                // 歌词与文本事件显示在网格顶部对应的 tick 处；同一 tick 的多个事件上下排列
+                let text_margin = self.state.ticks_per_beat as u64 * 4; // 起点在左侧外的文本仍可能伸进视野
+                let text_events = &self.state.text_events;
+                let text_start = text_events.partition_point(|e| e.tick + text_margin < visible_start_tick);
+                let text_end = text_events.partition_point(|e| e.tick <= visible_end_tick);
+                let mut stacked = (u64::MAX, 0);
+                for event in &text_events[text_start..text_end] {
+                    let x = note_offset_x + tick_to_x(event.tick, self.zoom_x, self.state.ticks_per_beat);
+                    if x < rect.min.x + key_width {
+                        continue;
+                    }
+                    stacked = if stacked.0 == event.tick { (event.tick, stacked.1 + 1) } else { (event.tick, 0) };
+                    let y = grid_top + 2.0 + stacked.1 as f32 * 12.0;
+                    let text = match event.kind {
+                        TextEventKind::Lyric => event.text().into_owned(),
+                        TextEventKind::Text => format!("✎ {}", event.text()),
+                    };
+                    painter.line_segment([Pos2::new(x, grid_top), Pos2::new(x, y + 10.0)], Stroke::new(1.0, theme.timeline_text));
+                    painter.text(Pos2::new(x + 2.0, y), Align2::LEFT_TOP, text, FontId::proportional(10.0), theme.timeline_text);
+                }
+
+                if let Some(preview) = self.drop_preview.take() {
+                    for note in &preview.notes {
+                        let Some(key) = self.constrain_key((note.key as i16 + preview.key_offset).clamp(0, 127) as u8) else {
+                            continue;
+                        };
+                        let Some(y) = note_to_y(key, self.zoom_y) else {
+                            continue;
+                        };
+                        let (x, w) = note_span(note.start + preview.at_tick, note.duration, self.state.ticks_per_beat);
+                        let ghost_rect = Rect::from_min_size(Pos2::new(x, note_offset_y + y), Vec2::new(w, self.zoom_y));
+                        if ghost_rect.intersects(rect) {
+                            painter.rect_filled(ghost_rect.shrink(1.0), 2.0, theme.drop_preview);
+                            painter.rect_stroke(
+                                ghost_rect.shrink(1.0),
+                                2.0,
+                                Stroke::new(1.0, theme.note_outline.gamma_multiply(0.6)),
+                            );
+                        }
+                    }
+                }
+
+                // Darken everything outside the editable region
+                if let Some((lo, hi)) = self.edit_bounds {
+                    let shade = theme.out_of_bounds;
+                    let roll_left = rect.min.x + key_width;
+                    let lo_x = (note_offset_x + tick_to_x(lo, self.zoom_x, self.state.ticks_per_beat)).max(roll_left);
+                    let hi_x = (note_offset_x + tick_to_x(hi, self.zoom_x, self.state.ticks_per_beat)).max(roll_left);
+                    if lo_x > roll_left {
+                        painter.rect_filled(
+                            Rect::from_min_max(Pos2::new(roll_left, grid_top), Pos2::new(lo_x, grid_bottom)),
+                            0.0,
+                            shade,
+                        );
+                    }
+                    if hi_x < rect.max.x {
+                        painter.rect_filled(
+                            Rect::from_min_max(Pos2::new(hi_x, grid_top), Pos2::new(rect.max.x, grid_bottom)),
+                            0.0,
+                            shade,
+                        );
+                    }
+                }
+
+                let mut toast_remaining = None;
+                if let Some(toast) = &mut self.toast {
+                    let now = ui.input(|i| i.time);
+                    let expires_at = *toast.expires_at.get_or_insert(now + 2.0);
+                    if now < expires_at {
+                        let galley = painter.layout_no_wrap(
+                            toast.message.clone(),
+                            FontId::proportional(13.0),
+                            theme.toast_text,
+                        );
+                        let toast_rect = Rect::from_min_size(
+                            Pos2::new(rect.max.x - galley.size().x - 24.0, grid_top + 8.0),
+                            galley.size() + Vec2::new(16.0, 8.0),
+                        );
+                        painter.rect_filled(toast_rect, 4.0, theme.toast_background);
+                        painter.galley(toast_rect.min + Vec2::new(8.0, 4.0), galley, theme.toast_text);
+                        toast_remaining = Some(expires_at - now);
+                    } else {
+                        self.toast = None;
+                    }
+                }
+                // 提示是静态的，只需在到期时再重绘一次将其移除
+                if let Some(remaining) = toast_remaining {
+                    self.request_repaint(ui.ctx(), Some(std::time::Duration::from_secs_f64(remaining)));
+                }
+                
+                // 悬停提示（拖拽、按下或菜单打开时不显示）
+                let hovered_note = response
+                    .hover_pos()
+                    .filter(|_| {
+                        !self.is_dragging_note
+                            && self.context_menu_pos.is_none()
+                            && !ui.input(|i| i.pointer.any_down())
+                    })
+                    .and_then(|pointer| {
+                        visible_notes.iter().rev().find(|(_, note_rect)| note_rect.contains(pointer))
+                    })
+                    .map(|(note_id, _)| *note_id);
+                self.show_note_tooltip(ui, hovered_note);
+
+                // Handle interactions (need to find note by ID)
+                let select_tool = self.note_tool == NoteTool::Select;
+                let mut double_clicked = None;
+                for (note_id, note_rect) in &visible_notes {
+                    if select_tool && response.double_clicked() {
+                        if let Some(pointer) = response.interact_pointer_pos().filter(|p| note_rect.contains(*p)) {
+                            double_clicked.get_or_insert((*note_id, pointer));
+                        }
+                    }
+
+                    if select_tool && response.clicked_by(PointerButton::Primary) {
+                        if let Some(pointer) = response.interact_pointer_pos() {
+                            if note_rect.contains(pointer) {
+                                let modifiers = ui.input(|i| i.modifiers);
+                                self.handle_note_click(*note_id, modifiers);
+                                pointer_consumed = true;
+                            }
+                        }
+                    }
+
+                    if select_tool && !self.is_dragging_note && ui.input(|i| i.pointer.primary_pressed()) {
+                        if let Some(pointer) = response.interact_pointer_pos() {
+                            if note_rect.contains(pointer) {
+                                let modifiers = ui.input(|i| i.modifiers);
+                                let action = self.resolve_drag_action(pointer, *note_rect);
+                                let duplicate = action == DragAction::Move
+                                    && self.duplicate_drag_modifiers != Modifiers::NONE
+                                    && modifiers.contains(self.duplicate_drag_modifiers);
+                                // 复制拖动时修饰键不参与选择（否则 Ctrl 会把按下的音符移出选区）
+                                let selection_modifiers = if duplicate { Modifiers::NONE } else { modifiers };
+                                self.prepare_selection_for_drag(*note_id, selection_modifiers);
+                                let pointer_tick = pointer_to_tick(pointer);
+                                self.begin_note_drag(*note_id, pointer, pointer_tick, action);
+                                if duplicate {
+                                    self.drag_duplicate = DuplicateDrag::Armed;
+                                }
+                                pointer_consumed = true;
+                            }
+                        }
+                    }
+
+                    if let Some(pointer) = response.hover_pos().filter(|_| select_tool) {
+                        let action = self.resolve_drag_action(pointer, *note_rect);
+                        if matches!(action, DragAction::ResizeStart | DragAction::ResizeEnd)
+                            && note_rect.contains(pointer)
+                        {
+                            ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
+                        }
+                    }
+
+                    if response.clicked_by(PointerButton::Secondary) {
+                        if let Some(pointer) = response.interact_pointer_pos() {
+                            if note_rect.contains(pointer) {
+                                let modifiers = ui.input(|i| i.modifiers);
+                                if modifiers.shift {
+                                    // Shift+右键：删除音符
+                                    self.push_undo_snapshot();
+                                    self.delete_note_by_id(*note_id);
+                                    pointer_consumed = true;
+                                } else {
+                                    // 普通右键：显示上下文菜单
+                                    self.context_menu_pos = Some(pointer);
+                                    self.context_menu_open_pos = Some(pointer);
+                                    self.context_menu_note = Some(*note_id);
+                                    self.context_menu_anchor = Some((
+                                        self.snap_tick(pointer_to_tick(pointer).max(0), None, false),
+                                        pointer_to_key(pointer),
+                                    ));
+                                    pointer_consumed = true;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if let Some((note_id, pointer)) = double_clicked {
+                    self.handle_note_double_click(note_id, pointer);
+                    pointer_consumed = true;
+                }
+
+                if self.is_dragging_note && ui.input(|i| i.pointer.primary_down()) {
+                    if let Some(pointer) = response.interact_pointer_pos() {
+                        let modifiers = ui.input(|i| i.modifiers);
+                        if self.drag_action == DragAction::Move
+                            && modifiers.command
+                            && modifiers.shift
+                            && !rect.contains(pointer)
+                        {
+                            // Ctrl+Shift 拖出编辑器：转为跨编辑器拖放
+                            self.begin_external_note_drag(ui.ctx());
+                        } else {
+                            self.update_note_drag(
+                                pointer,
+                                pointer_to_tick,
+                                pointer_to_key,
+                                modifiers,
+                            );
+                        }
+                    }
+                }
+
+                // 点击（没有拖动）不会触发 drag_stopped，松开时同样结束；绘制或擦除拖动另行结束
+                if self.note_stroke.is_none()
+                    && (response.drag_stopped() || (self.is_dragging_note && !ui.input(|i| i.pointer.primary_down())))
+                {
+                    self.preview_note_off();
+                    self.finalize_note_drag_if_needed();
+                    self.reset_note_drag_state();
+                }
+
+                // Notes dragged in from another editor
+                if let Some(payload) = DragAndDrop::payload::<NoteDragPayload>(ui.ctx()) {
+                    let hover = ui.input(|i| i.pointer.hover_pos());
+                    if let Some(pointer) = hover.filter(|p| {
+                        response.contains_pointer()
+                            && p.x > rect.min.x + key_width
+                            && p.y > rect.min.y + timeline_height
+                    }) {
+                        let tpb = self.state.ticks_per_beat;
+                        let notes = payload.notes_for(tpb);
+                        let raw_tick = pointer_to_tick(pointer) - payload.anchor_tick_for(tpb) as i64;
+                        let disable_snap = ui.input(|i| i.modifiers.alt);
+                        let at_tick = self.snap_tick(raw_tick, None, disable_snap);
+                        let key_offset = pointer_to_key(pointer) as i16 - payload.anchor_key as i16;
+                        if ui.input(|i| i.pointer.primary_released()) {
+                            DragAndDrop::clear_payload(ui.ctx());
+                            self.accept_external_drop(&notes, at_tick, key_offset);
+                        } else {
+                            self.show_drop_preview(&notes, at_tick, key_offset);
+                        }
+                    }
+                }
+
+                if !pointer_consumed && ui.input(|i| i.pointer.primary_pressed()) {
+                    if let Some(pointer) = response.interact_pointer_pos() {
+                        let in_roll = pointer.x > rect.min.x + key_width
+                            && pointer.y > rect.min.y + timeline_height;
+                        if in_roll && !select_tool {
+                            let modifiers = ui.input(|i| i.modifiers);
+                            self.begin_note_stroke(pointer_to_tick(pointer), pointer_to_key(pointer), modifiers);
+                        } else if in_roll {
+                            let modifiers = ui.input(|i| i.modifiers);
+                            if modifiers.shift {
+                                // Shift+左键：创建新音符
+                                if !self.is_dragging_note {
+                                    self.create_note_at_pointer(
+                                        pointer,
+                                        pointer_to_tick,
+                                        pointer_to_key,
+                                    );
+                                }
+                            } else {
+                                // 直接左键：开始框选（如果不在音符上）
+                                if !self.is_dragging_note {
+                                    self.selection_box_start = Some(pointer);
+                                    self.selection_box_end = Some(pointer);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if let Some(stroke) = &self.note_stroke {
+                    if ui.input(|i| i.pointer.primary_down()) {
+                        if let Some(pointer) = response.interact_pointer_pos() {
+                            if self.note_tool == NoteTool::Slice {
+                                let x = note_offset_x + tick_to_x(stroke.cut, self.zoom_x, self.state.ticks_per_beat);
+                                let from_y = note_to_y(stroke.key, self.zoom_y)
+                                    .map_or(pointer.y, |y| note_offset_y + y + self.zoom_y * 0.5);
+                                painter.line_segment(
+                                    [Pos2::new(x, from_y), Pos2::new(x, pointer.y)],
+                                    Stroke::new(1.5, theme.selection_box),
+                                );
+                            }
+                            self.continue_note_stroke(pointer_to_tick(pointer), pointer_to_key(pointer));
+                        }
+                    } else {
+                        self.finish_note_stroke();
+                    }
+                }
+
+                if let Some(start) = self.selection_box_start {
+                    if ui.input(|i| i.pointer.primary_down()) {
+                        if let Some(pointer) = response.hover_pos() {
+                            self.selection_box_end = Some(pointer);
+                        }
+                        if let Some(end) = self.selection_box_end {
+                            let selection_rect = Rect::from_two_pos(start, end);
+                            painter.rect_stroke(
+                                selection_rect,
+                                0.0,
+                                Stroke::new(1.0, theme.selection_box),
+                            );
+                        }
+                    }
+
+                    if ui.input(|i| i.pointer.primary_released()) {
+                        if let Some(end) = self.selection_box_end {
+                            let selection_rect = Rect::from_two_pos(start, end);
+                            let previous = if ui.input(|i| i.modifiers.shift) {
+                                self.selected_notes.clone()
+                            } else {
+                                std::mem::take(&mut self.selected_notes)
+                            };
+                            // Optimize box selection with viewport culling
+                            let selection_start_tick = if self.manual_scroll_x < 0.0 {
+                                (((selection_rect.min.x - note_offset_x) / self.zoom_x) * self.state.ticks_per_beat as f32) as u64
+                            } else {
+                                0
+                            };
+                            let selection_end_tick = selection_start_tick.saturating_add(
+                                ((selection_rect.width() / self.zoom_x) * self.state.ticks_per_beat as f32) as u64 + 1
+                            );
+                            
+                            let notes_snapshot = &self.state.notes;
+                            let sel_start_idx = notes_snapshot.partition_point(|n| n.start + n.duration < selection_start_tick);
+                            let sel_end_idx = notes_snapshot.partition_point(|n| n.start <= selection_end_tick);
+                            
+                            for note in &notes_snapshot[sel_start_idx..sel_end_idx.min(notes_snapshot.len())] {
+                                if self.state.is_multi_track() && note.track != self.active_track {
+                                    continue;
+                                }
+                                if note_rect_of(note).intersects(selection_rect) {
+                                    self.selected_notes.insert(note.id);
+                                }
+                            }
+                            self.notify_selection_changed(previous);
+                        }
+                        self.selection_box_start = None;
+                        self.selection_box_end = None;
+                    }
+                }
+
+                // Handle right-click on piano roll area (not on notes)
+                if !pointer_consumed && response.clicked_by(PointerButton::Secondary) {
+                    if let Some(pointer) = response.interact_pointer_pos() {
+                        let in_roll = pointer.x > rect.min.x + key_width
+                            && pointer.y > rect.min.y + timeline_height;
+                        if in_roll {
+                            let modifiers = ui.input(|i| i.modifiers);
+                            if modifiers.shift {
+                                // Shift+右键：删除选中音符
+                                if !self.selected_notes.is_empty() {
+                                    self.delete_selected_notes();
+                                }
+                            } else {
+                                // 普通右键：显示上下文菜单
+                                self.context_menu_pos = Some(pointer);
+                                self.context_menu_open_pos = Some(pointer);
+                                self.context_menu_note = None;
+                                self.context_menu_anchor = Some((
+                                    self.snap_tick(pointer_to_tick(pointer).max(0), None, false),
+                                    pointer_to_key(pointer),
+                                ));
+                            }
+                        }
+                    }
+                }
+
+                // Draw Timeline (Top Bar) - Drawn AFTER Notes
+                // Fill timeline background
+                let timeline_rect =
+                    Rect::from_min_size(rect.min, Vec2::new(rect.width(), timeline_height));
+                painter.rect_filled(timeline_rect, 0.0, theme.background);
+                painter.line_segment(
+                    [timeline_rect.left_bottom(), timeline_rect.right_bottom()],
+                    Stroke::new(1.0, theme.separator), // Separator line
+                );
+
+                // Draw Timeline Labels (per measure)
+                let mut measure_tick = (start_tick as u64 / ticks_per_measure) * ticks_per_measure;
+                while measure_tick as i64 <= end_tick {
+                    let x = note_offset_x + (measure_tick as f32 / tpb as f32) * self.zoom_x;
+                    if x >= rect.min.x + key_width - 5.0 && x <= rect.max.x {
+                        painter.line_segment(
+                            [
+                                Pos2::new(x, rect.min.y),
+                                Pos2::new(x, rect.min.y + timeline_height),
+                            ],
+                            Stroke::new(1.0, theme.measure_line),
+                        );
+                        let measure_index = (measure_tick / ticks_per_measure) + 1;
+                        painter.text(
+                            Pos2::new(x + 4.0, rect.min.y + 15.0),
+                            Align2::LEFT_CENTER,
+                            format!("{}", measure_index),
+                            FontId::proportional(11.0),
+                            theme.timeline_text,
+                        );
+                    }
+                    measure_tick += ticks_per_measure;
+                }
+
+                // 速度变化标记（时间轴下半部分的小三角与速度值）
+                let tempo_color = theme.tempo_marker;
+                for change in &self.state.tempo_changes {
+                    let x = note_offset_x + tick_to_x(change.tick, self.zoom_x, self.state.ticks_per_beat);
+                    if x < rect.min.x + key_width || x > rect.max.x {
+                        continue;
+                    }
+                    let bottom = rect.min.y + timeline_height;
+                    painter.add(Shape::convex_polygon(
+                        vec![
+                            Pos2::new(x - 4.0, bottom - 7.0),
+                            Pos2::new(x + 4.0, bottom - 7.0),
+                            Pos2::new(x, bottom),
+                        ],
+                        tempo_color,
+                        Stroke::NONE,
+                    ));
+                    painter.text(
+                        Pos2::new(x + 6.0, bottom - 1.0),
+                        Align2::LEFT_BOTTOM,
+                        format_bpm(change.bpm),
+                        FontId::proportional(10.0),
+                        tempo_color,
+                    );
+                }
+
+                // 标记：时间轴顶部的彩色旗子与名称
+                for marker in &self.state.markers {
+                    let x = note_offset_x + tick_to_x(marker.tick, self.zoom_x, self.state.ticks_per_beat);
+                    if x < rect.min.x + key_width || x > rect.max.x {
+                        continue;
+                    }
+                    let [r, g, b] = marker.color;
+                    let color = Color32::from_rgb(r, g, b);
+                    painter.line_segment(
+                        [Pos2::new(x, rect.min.y), Pos2::new(x, rect.min.y + timeline_height)],
+                        Stroke::new(2.0, color),
+                    );
+                    let galley = painter.layout_no_wrap(marker.label.clone(), FontId::proportional(9.0), Color32::BLACK);
+                    let flag = Rect::from_min_size(Pos2::new(x, rect.min.y), galley.size() + Vec2::new(6.0, 2.0));
+                    painter.rect_filled(flag, 2.0, color);
+                    painter.galley(flag.min + Vec2::new(3.0, 1.0), galley, Color32::BLACK);
+                }
+
+                // Draw Loop Markers on Timeline (if enabled)
+                if self.loop_enabled {
+                    let loop_start_x = note_offset_x
+                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
+                    let loop_end_x = note_offset_x
+                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
+                    
+                    // Draw loop start marker
+                    if loop_start_x >= rect.min.x + key_width && loop_start_x <= rect.max.x {
+                        painter.add(Shape::convex_polygon(
+                            vec![
+                                Pos2::new(loop_start_x, rect.min.y),
+                                Pos2::new(loop_start_x - 4.0, rect.min.y + 8.0),
+                                Pos2::new(loop_start_x + 4.0, rect.min.y + 8.0),
+                            ],
+                            theme.loop_marker,
+                            Stroke::NONE,
+                        ));
+                        painter.text(
+                            Pos2::new(loop_start_x, rect.min.y + 20.0),
+                            Align2::CENTER_TOP,
+                            "L",
+                            FontId::proportional(9.0),
+                            theme.loop_marker,
+                        );
+                    }
+                    
+                    // Draw loop end marker
+                    if loop_end_x >= rect.min.x + key_width && loop_end_x <= rect.max.x {
+                        painter.add(Shape::convex_polygon(
+                            vec![
+                                Pos2::new(loop_end_x, rect.min.y),
+                                Pos2::new(loop_end_x - 4.0, rect.min.y + 8.0),
+                                Pos2::new(loop_end_x + 4.0, rect.min.y + 8.0),
+                            ],
+                            theme.loop_marker,
+                            Stroke::NONE,
+                        ));
+                        painter.text(
+                            Pos2::new(loop_end_x, rect.min.y + 20.0),
+                            Align2::CENTER_TOP,
+                            "R",
+                            FontId::proportional(9.0),
+                            theme.loop_marker,
+                        );
+                    }
+                }
+
+                // Draw Loop Region (if enabled) - before playhead
+                if self.loop_enabled {
+                    let loop_start_x = note_offset_x
+                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
+                    let loop_end_x = note_offset_x
+                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
+                    
+                    if loop_end_x > rect.min.x + key_width && loop_start_x < rect.max.x {
+                        let loop_rect = Rect::from_min_max(
+                            Pos2::new(loop_start_x.max(rect.min.x + key_width), rect.min.y),
+                            Pos2::new(loop_end_x.min(rect.max.x), rect.max.y),
+                        );
+                        // Semi-transparent overlay
+                        painter.rect_filled(
+                            loop_rect,
+                            0.0,
+                            theme.loop_region,
+                        );
+                        // Loop boundaries
+                        if loop_start_x >= rect.min.x + key_width {
+                            painter.line_segment(
+                                [Pos2::new(loop_start_x, rect.min.y), Pos2::new(loop_start_x, rect.max.y)],
+                                Stroke::new(2.0, theme.loop_marker),
+                            );
+                        }
+                        if loop_end_x <= rect.max.x {
+                            painter.line_segment(
+                                [Pos2::new(loop_end_x, rect.min.y), Pos2::new(loop_end_x, rect.max.y)],
+                                Stroke::new(2.0, theme.loop_marker),
+                            );
                         }
-                        
-                        ui.separator();
-                        
-                        // Swing - directly in menu
-                        if has_selection {
-                            ui.label("Swing:");
-                            // Check if selection changed - if so, reinitialize
-                            let current_selection: Vec<NoteId> = self.selected_notes.iter().copied().collect();
-                            let selection_changed = self.swing_original_notes.is_empty() 
-                                || self.swing_original_notes.len() != current_selection.len()
-                                || !self.swing_original_notes.iter().all(|(id, _)| current_selection.contains(id));
-                            
-                            if selection_changed {
-                                // Restore original positions if we had previous swing applied
-                                if !self.swing_original_notes.is_empty() && self.swing_menu_ratio > 0.0 {
-                                    let original_notes = self.swing_original_notes.clone();
-                                    for (id, original_start) in &original_notes {
-                                        if let Some(note) = self.note_mut_by_id(*id) {
-                                            note.start = *original_start;
-                                        }
-                                    }
-                                }
-                                
-                                // Initialize with current selection
-                                self.swing_original_notes = self.selected_notes
-                                    .iter()
-                                    .filter_map(|&id| {
-                                        self.note_by_id(id).map(|note| (id, note.start))
-                                    })
-                                    .collect();
-                                self.swing_menu_ratio = 0.0;
-                                // Create undo snapshot when starting swing adjustment
-                                self.push_undo_snapshot();
-                            }
-                            
-                            ui.horizontal(|ui| {
-                                let mut swing = self.swing_menu_ratio;
-                                
-                                // Slider for 0-100% range
-                                let slider_response = ui.add(
-                                    Slider::new(&mut swing, 0.0..=1.0)
-                                        .text("%")
-                                        .custom_formatter(|n, _| format!("{:.0}%", n * 100.0)),
-                                );
-                                
-                                // Custom input for values beyond 100% (up to 200%)
-                                let drag_response = ui.add(
-                                    DragValue::new(&mut swing)
-                                        .range(0.0..=2.0)
-                                        .speed(0.01)
-                                        .suffix("%")
-                                        .custom_formatter(|n, _| format!("{:.1}", n * 100.0)),
+                    }
+                }
+
+                // Draw Playhead (Timeline portion + Line)
+                // Drawn AFTER notes (so it's on top of notes)
+                let playhead_beats = self.state.seconds_to_ticks(self.current_time as f64)
+                    / self.state.ticks_per_beat.max(1) as f64;
+                let playhead_x = note_offset_x + time_to_x(playhead_beats as f32, self.zoom_x);
+                if playhead_x > rect.min.x + key_width {
+                    painter.line_segment(
+                        [
+                            Pos2::new(playhead_x, rect.min.y),
+                            Pos2::new(playhead_x, rect.max.y),
+                        ],
+                        Stroke::new(2.0, theme.playhead),
+                    );
+                }
+
+                // 步进光标：贯穿时间轴与网格的实线，时间轴中带一个向下的三角
+                if self.step_input {
+                    let step_x = note_offset_x + tick_to_x(self.step_cursor, self.zoom_x, self.state.ticks_per_beat);
+                    if step_x > rect.min.x + key_width && step_x < rect.max.x {
+                        painter.line_segment(
+                            [Pos2::new(step_x, rect.min.y), Pos2::new(step_x, rect.max.y)],
+                            Stroke::new(2.0, theme.step_cursor),
+                        );
+                        let tip = Pos2::new(step_x, rect.min.y + timeline_height);
+                        painter.add(Shape::convex_polygon(
+                            vec![tip + Vec2::new(-6.0, -10.0), tip + Vec2::new(6.0, -10.0), tip],
+                            theme.step_cursor,
+                            Stroke::NONE,
+                        ));
+                    }
+                }
+
+                // Draw Piano Keys (Sidebar) - Drawn LAST so they cover playhead and notes
+                // Fill background for sidebar to occlude content
+                let sidebar_rect = Rect::from_min_size(
+                    Pos2::new(rect.min.x, rect.min.y + timeline_height),
+                    Vec2::new(key_width, rect.height() - timeline_height),
+                );
+                painter.rect_filled(sidebar_rect, 0.0, theme.background);
+
+                for (row, &note_idx) in layout.keys.iter().enumerate() {
+                    let y = rect.min.y
+                        + timeline_height
+                        + row as f32 * self.zoom_y
+                        + self.manual_scroll_y;
+
+                    // Only draw if visible
+                    if y > rect.min.y + timeline_height && y < rect.max.y {
+                        let key_rect = Rect::from_min_size(
+                            Pos2::new(rect.min.x, y),
+                            Vec2::new(key_width, self.zoom_y),
+                        );
+
+                        // 鼓映射：有名称的行标出 "音高 名称"，其余行压暗
+                        if let Some(drum_map) = &self.drum_map {
+                            let name = drum_map.name(note_idx);
+                            let fill = if name.is_some() { theme.drum_row } else { theme.unmapped_drum_row };
+                            painter.rect_filled(key_rect, 0.0, fill);
+                            painter.rect_stroke(key_rect, 0.0, Stroke::new(1.0, theme.key_outline));
+                            if let Some(name) = name {
+                                painter.with_clip_rect(key_rect.intersect(painter.clip_rect())).text(
+                                    key_rect.left_center() + Vec2::new(2.0, 0.0),
+                                    Align2::LEFT_CENTER,
+                                    format!("{note_idx} {name}"),
+                                    FontId::proportional(10.0),
+                                    theme.drum_label,
                                 );
-                                
-                                if slider_response.changed() || drag_response.changed() {
-                                    self.swing_menu_ratio = swing.clamp(0.0, 2.0);
-                                    // Apply swing in real-time
-                                    self.apply_swing_to_selected_notes_realtime(self.swing_menu_ratio);
-                                }
-                            });
+                            }
+                            continue;
+                        }
+
+                        let is_black = [1, 3, 6, 8, 10].contains(&(note_idx % 12));
+                        let (key_color, text_color) = if is_black {
+                            (theme.black_key, theme.white_key)
+                        } else {
+                            (theme.white_key, theme.black_key)
+                        };
+
+                        painter.rect_filled(key_rect, 0.0, key_color);
+                        painter.rect_stroke(key_rect, 0.0, Stroke::new(1.0, theme.key_outline));
+
+                        // 音阶主音：右侧色条，并与 C 一样标注音名
+                        let is_root = self.scale_highlight.is_some_and(|(root, _)| note_idx % 12 == root);
+                        if is_root {
+                            painter.rect_filled(
+                                Rect::from_min_max(
+                                    Pos2::new(key_rect.max.x - 4.0, key_rect.min.y),
+                                    key_rect.max,
+                                ),
+                                0.0,
+                                theme.scale_root,
+                            );
+                        }
+
+                        // C notes label
+                        if note_idx % 12 == 0 || is_root {
+                            painter.text(
+                                key_rect.left_center() + Vec2::new(2.0, 0.0),
+                                Align2::LEFT_CENTER,
+                                tooltip::format_pitch(note_idx, self.pitch_naming()),
+                                FontId::proportional(10.0),
+                                text_color,
+                            );
+                        }
+
+                    }
+                }
+
+                // Interaction: press a key to preview, slide to glide, release anywhere
+                let keys_rect = Rect::from_min_max(
+                    Pos2::new(rect.min.x, rect.min.y + timeline_height),
+                    Pos2::new(rect.min.x + key_width, rect.max.y),
+                );
+                let keys_top = rect.min.y + timeline_height + self.manual_scroll_y;
+                let (pointer, pressed, down) = ui.input(|i| {
+                    (i.pointer.interact_pos(), i.pointer.primary_pressed(), i.pointer.primary_down())
+                });
+                let pressed_in_keys = pressed && ui.rect_contains_pointer(keys_rect);
+                self.update_key_preview(pointer, pressed_in_keys, down, keys_rect, keys_top);
+                if let KeyPreviewState::Held { velocity, .. } = self.key_preview {
+                    show_tooltip_at_pointer(ui.ctx(), ui.layer_id(), ui.id().with("key_preview_velocity"), |ui| {
+                        ui.label(format!("Velocity {velocity}"));
+                    });
+                }
+            });
+    }
+
+    /// 悬停超过 `hover_tooltip_delay` 后显示音符提示；等待期间只预约一次到期重绘
+    fn show_note_tooltip(&mut self, ui: &Ui, hovered: Option<NoteId>) {
+        let (Some(delay), Some(note_id)) = (self.hover_tooltip_delay, hovered) else {
+            self.note_tooltip_hover = None;
+            return;
+        };
+        let now = ui.input(|i| i.time);
+        let since = match self.note_tooltip_hover {
+            Some((id, since)) if id == note_id => since,
+            _ => {
+                self.note_tooltip_hover = Some((note_id, now));
+                now
+            }
+        };
+        let remaining = delay as f64 - (now - since);
+        if remaining > 0.0 {
+            self.request_repaint(ui.ctx(), Some(std::time::Duration::from_secs_f64(remaining)));
+            return;
+        }
+        if let Some(note) = self.state.notes.iter().find(|n| n.id == note_id) {
+            let text = tooltip::note_tooltip(note, &self.state, self.pitch_naming());
+            show_tooltip_at_pointer(ui.ctx(), ui.layer_id(), ui.id().with("note_tooltip"), |ui| {
+                ui.label(text);
+            });
+        }
+    }
+
+    /// 按 ID 查找音符下标，平均 O(1)
+    ///
+    /// 命中的下标与 `state.notes` 核对后直接返回；结构性修改（增删、排序、整体替换）
+    /// 之后索引过期，未命中时退回线性查找，找到说明索引过期，顺便重建。
+    fn note_index_by_id(&self, id: NoteId) -> Option<usize> {
+        let notes = &self.state.notes;
+        if let Some(&idx) = self.note_index.borrow().get(&id) {
+            if notes.get(idx).is_some_and(|n| n.id == id) {
+                return Some(idx);
+            }
+        }
+        let idx = notes.iter().position(|n| n.id == id)?;
+        self.rebuild_note_index();
+        Some(idx)
+    }
+
+    fn rebuild_note_index(&self) {
+        let mut index = self.note_index.borrow_mut();
+        index.clear();
+        for (idx, note) in self.state.notes.iter().enumerate() {
+            // ID 重复时与线性查找一样取第一个
+            index.entry(note.id).or_insert(idx);
+        }
+    }
+
+    fn sort_notes(&mut self) {
+        self.notes_revision += 1;
+        self.state
+            .notes
+            .sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.id.0.cmp(&b.id.0)));
+        self.rebuild_note_index();
+    }
+
+    /// 在修改状态之前调用，开始一个新的撤销步骤
+    ///
+    /// 不会克隆状态：步骤的差异在下一次压栈、撤销或重做时由 `close_undo_step` 计算。
+    fn push_undo_snapshot(&mut self) {
+        self.redo_stack.clear();
+        let label = match &mut self.undo_group {
+            Some(group) if group.pushed => return,
+            Some(group) => {
+                group.pushed = true;
+                Some(group.label.clone())
+            }
+            None => None,
+        };
+        self.close_undo_step();
+        let revision = self.next_revision;
+        self.next_revision += 1;
+        self.undo_stack.push(UndoEntry { label, change: None, revision });
+        self.trim_undo_stack();
+    }
+
+    /// 撤销栈超出上限时丢弃最早的步骤，并记下被丢弃步骤之后的修订号
+    fn trim_undo_stack(&mut self) {
+        let excess = self.undo_stack.len().saturating_sub(self.max_undo_entries);
+        if excess > 0 {
+            self.history_floor = self.undo_stack[excess - 1].revision;
+            self.undo_stack.drain(..excess);
+        }
+    }
+
+    /// 结束进行中的撤销步骤：与基准比较得出差异，并把基准推进到当前状态
+    fn close_undo_step(&mut self) {
+        if let Some(entry) = self.undo_stack.last_mut().filter(|entry| entry.change.is_none()) {
+            let change = StateDelta::between(&self.history_base, &self.state);
+            change.redo(&mut self.history_base);
+            entry.change = Some(change);
+        }
+    }
+
+    /// 撤回刚压入但没有用上的快照；撤销组内的快照属于整个组，不撤回
+    fn discard_undo_snapshot(&mut self) -> bool {
+        if self.undo_group.is_some() || self.undo_stack.last().is_none_or(|entry| entry.change.is_some()) {
+            return false;
+        }
+        self.undo_stack.pop();
+        true
+    }
+
+    /// 撤回刚压入的快照，并把状态恢复到压入时的样子
+    fn revert_undo_snapshot(&mut self) {
+        if self.discard_undo_snapshot() {
+            StateDelta::between(&self.history_base, &self.state).undo(&mut self.state);
+        }
+    }
+
+    /// 撤销/重做跨过了撤销组已压入的快照，组内之后的编辑需要重新压入
+    fn restart_undo_group(&mut self) {
+        if let Some(group) = &mut self.undo_group {
+            group.pushed = false;
+        }
+    }
+
+    /// 开始一个撤销组：到对应的 `end_undo_group` 为止的所有编辑合并为一个名为 `label` 的撤销步骤
+    ///
+    /// 可以嵌套，只有最外层的名称生效；组内没有任何编辑时不产生撤销步骤。
+    pub fn begin_undo_group(&mut self, label: &str) {
+        match &mut self.undo_group {
+            Some(group) => group.depth += 1,
+            None => {
+                self.undo_group = Some(UndoGroup { label: label.to_owned(), depth: 1, pushed: false });
+            }
+        }
+    }
+
+    pub fn end_undo_group(&mut self) {
+        if let Some(group) = &mut self.undo_group {
+            group.depth -= 1;
+            if group.depth == 0 {
+                self.undo_group = None;
+                self.emit_history_if_changed();
+            }
+        }
+    }
+
+    pub fn undo_depth(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn redo_depth(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    /// 各撤销步骤的名称，下一次撤销的在前；没有名称的普通编辑为 `None`
+    pub fn undo_labels(&self) -> Vec<Option<&str>> {
+        self.undo_stack.iter().rev().map(|entry| entry.label.as_deref()).collect()
+    }
+
+    /// 各重做步骤的名称，下一次重做的在前
+    pub fn redo_labels(&self) -> Vec<Option<&str>> {
+        self.redo_stack.iter().rev().map(|entry| entry.label.as_deref()).collect()
+    }
+
+    /// 当前状态的修订号
+    ///
+    /// 每个新的编辑步骤都会得到比之前所有步骤更大的修订号；撤销、重做回到某一步时修订号也回到那一步，
+    /// 因此可以与 `mark_saved` 记下的修订号比较。按下音符但还没有拖动时不算改动。
+    pub fn revision(&self) -> u64 {
+        let mut entries = self.undo_stack.iter().rev();
+        if self.pending_step_is_unchanged() {
+            entries.next();
+        }
+        entries.next().map_or(self.history_floor, |entry| entry.revision)
+    }
+
+    /// 记下当前修订号为已保存，之后 `is_dirty` 与之比较
+    pub fn mark_saved(&mut self) {
+        self.saved_revision = self.revision();
+        self.emit_history_if_changed();
+    }
+
+    /// 当前状态与上次 `mark_saved` 时是否不同（撤销回保存时的那一步后重新为 `false`）
+    pub fn is_dirty(&self) -> bool {
+        self.revision() != self.saved_revision
+    }
+
+    /// 音符拖动或通道拖动开始时压入的步骤还没有改动任何东西
+    fn pending_step_is_unchanged(&self) -> bool {
+        let pending = self.undo_stack.last().is_some_and(|entry| entry.change.is_none());
+        let idle_drag =
+            self.is_dragging_note && !self.drag_changed_note && self.drag_action != DragAction::Create;
+        let idle_lane_edit = self.lane_edit_state.is_some() && !self.lane_edit_changed;
+        pending && self.undo_group.is_none() && (idle_drag || idle_lane_edit)
+    }
+
+    fn emit_history_if_changed(&mut self) {
+        if self.undo_group.is_some() {
+            return;
+        }
+        let depths = (self.undo_stack.len(), self.redo_stack.len());
+        if depths != self.reported_history {
+            self.reported_history = depths;
+            self.emit_event(EditorEvent::HistoryChanged { undo_depth: depths.0, redo_depth: depths.1 });
+        }
+        let dirty = self.is_dirty();
+        if dirty != self.reported_dirty {
+            self.reported_dirty = dirty;
+            self.emit_event(EditorEvent::DirtyChanged(dirty));
+        }
+    }
+
+    fn note_mut_by_id(&mut self, id: NoteId) -> Option<&mut Note> {
+        let idx = self.note_index_by_id(id)?;
+        self.state.notes.get_mut(idx)
+    }
+
+    fn note_by_id(&self, id: NoteId) -> Option<Note> {
+        self.note_index_by_id(id).map(|idx| self.state.notes[idx])
+    }
+
+    fn first_selected_note(&self) -> Option<Note> {
+        self.selected_notes
+            .iter()
+            .next()
+            .and_then(|id| self.note_by_id(*id))
+    }
+
+    fn selected_notes_snapshot(&self) -> Vec<Note> {
+        self.selected_notes
+            .iter()
+            .filter_map(|id| self.note_by_id(*id))
+            .collect()
+    }
+
+    fn edit_note_by_id<F>(&mut self, id: NoteId, mut edit: F)
+    where
+        F: FnMut(&mut Note),
+    {
+        if let Some(idx) = self.note_index_by_id(id) {
+            self.push_undo_snapshot();
+            let before = self.state.notes[idx];
+            edit(&mut self.state.notes[idx]);
+            self.state.notes[idx].duration = self.state.notes[idx].duration.max(1);
+            let after = self.state.notes[idx];
+            self.sort_notes();
+            self.sync_sounding_notes();
+            self.emit_note_updated(before, after);
+        }
+    }
+
+    fn copy_selection(&mut self) {
+        self.clipboard = self.selected_notes_snapshot();
+        self.clipboard.sort_by_key(|n| n.start);
+        self.truncate_clipboard();
+        self.clipboard_groups = self
+            .state
+            .groups
+            .iter()
+            .filter_map(|group| {
+                let members: Vec<NoteId> = group
+                    .note_ids
+                    .iter()
+                    .copied()
+                    .filter(|id| self.clipboard.iter().any(|n| n.id == *id))
+                    .collect();
+                (!members.is_empty()).then(|| NoteGroup {
+                    note_ids: members,
+                    ..group.clone()
+                })
+            })
+            .collect();
+        // 选中了曲线点时只复制这些点（保留与音符的相对位置）；否则随音符复制各曲线在音符范围内的点
+        self.clipboard_curves.clear();
+        match self.clipboard_range() {
+            Some((start, end)) if self.selected_curve_points.is_empty() => {
+                self.curve_clipboard = None;
+                self.clipboard_curves = self.state.copy_curve_range(start, end);
+            }
+            range => self.copy_curve_selection(range.map(|(start, _)| start)),
+        }
+        self.clipboard_export_pending = self.system_clipboard;
+    }
+
+    /// 剪贴板音符从最早起点到最晚终点的范围
+    fn clipboard_range(&self) -> Option<(u64, u64)> {
+        let start = self.clipboard.iter().map(|n| n.start).min()?;
+        Some((start, start + Self::block_span(&self.clipboard)))
+    }
+
+    /// 设置曲线点的插值方式（作为一个撤销步骤），没有变化时不记录
+    fn set_curve_point_interpolation(
+        &mut self,
+        lane_id: CurveLaneId,
+        point_id: CurvePointId,
+        interpolation: Option<CurveInterpolation>,
+    ) {
+        let unchanged = self
+            .state
+            .curves
+            .iter()
+            .find(|c| c.id == lane_id)
+            .and_then(|lane| lane.points.iter().find(|p| p.id == point_id))
+            .is_none_or(|point| point.interpolation == interpolation);
+        if unchanged {
+            return;
+        }
+        self.push_undo_snapshot();
+        if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+            lane.set_point_interpolation(point_id, interpolation);
+        }
+        self.emit_event(EditorEvent::CurvePointUpdated { lane_id, point_id });
+    }
+
+    /// 开始铅笔或直线工具的一次拖动，整个拖动作为一个撤销步骤
+    fn begin_curve_stroke(&mut self, lane_id: CurveLaneId, start: (u64, f32)) {
+        self.finish_curve_stroke();
+        self.push_undo_snapshot();
+        self.curve_stroke = Some(CurveStroke {
+            lane_id,
+            start,
+            last: start,
+            last_point: None,
+            touched: start.0..=start.0,
+            removed: Vec::new(),
+            added: Vec::new(),
+        });
+        if self.curve_tool == CurveTool::Pencil {
+            self.continue_curve_stroke(start);
+        }
+    }
+
+    /// 铅笔写入 `to` 处的点并覆盖与上一步之间的点；直线工具只更新终点
+    fn continue_curve_stroke(&mut self, to: (u64, f32)) {
+        let Some(stroke) = self.curve_stroke.as_mut() else {
+            return;
+        };
+        if self.curve_tool == CurveTool::Pencil {
+            let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == stroke.lane_id) else {
+                return;
+            };
+            let from = if stroke.last_point.is_some() { stroke.last.0 } else { to.0 };
+            let (removed, added) = lane.draw_point(from, to.0, to.1, stroke.last_point);
+            stroke.removed.extend(removed);
+            if let Some(point_id) = added {
+                stroke.added.push(point_id);
+                stroke.last_point = Some(point_id);
+            }
+        }
+        stroke.last = to;
+        stroke.touched = (*stroke.touched.start()).min(to.0)..=(*stroke.touched.end()).max(to.0);
+    }
+
+    /// 结束拖动：直线工具写入斜坡，清理经过范围内的多余点，再成批发出增删事件；曲线没有变化时不记录撤销
+    fn finish_curve_stroke(&mut self) {
+        let Some(mut stroke) = self.curve_stroke.take() else {
+            return;
+        };
+        let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == stroke.lane_id) else {
+            return;
+        };
+        if self.curve_tool == CurveTool::Line {
+            let (removed, added) = lane.replace_with_ramp(stroke.start, stroke.last);
+            stroke.removed.extend(removed);
+            stroke.added.extend(added);
+        }
+        let thinned = lane.thin_points(*stroke.touched.start(), *stroke.touched.end());
+        let removed: Vec<CurvePointId> = stroke
+            .removed
+            .iter()
+            .chain(&thinned)
+            .copied()
+            .filter(|id| !stroke.added.contains(id))
+            .collect();
+        let added: Vec<CurvePointId> = stroke
+            .added
+            .iter()
+            .copied()
+            .filter(|id| !stroke.removed.contains(id) && !thinned.contains(id))
+            .collect();
+        if removed.is_empty() && added.is_empty() {
+            self.discard_undo_snapshot();
+            return;
+        }
+        let lane_id = stroke.lane_id;
+        self.selected_curve_points.retain(|id| !removed.contains(id));
+        for point_id in removed {
+            self.emit_event(EditorEvent::CurvePointRemoved { lane_id, point_id });
+        }
+        for point_id in added {
+            self.emit_event(EditorEvent::CurvePointAdded { lane_id, point_id });
+        }
+    }
+
+    /// 复制当前曲线中选中的点；没有选中点时复制循环区域内的点
+    ///
+    /// `anchor` 为一起复制的音符起点，选中的点相对它保存，粘贴时与音符保持原来的间隔。
+    fn copy_curve_selection(&mut self, anchor: Option<u64>) {
+        let Some(lane) = self
+            .displayed_curve_lane()
+            .and_then(|id| self.state.curves.iter().find(|c| c.id == id))
+        else {
+            return;
+        };
+        self.curve_clipboard = if !self.selected_curve_points.is_empty() {
+            match anchor {
+                Some(anchor) => lane.copy_points_anchored(&self.selected_curve_points, anchor),
+                None => lane.copy_points(&self.selected_curve_points),
+            }
+        } else if self.loop_enabled {
+            lane.copy_range(self.loop_start_tick, self.loop_end_tick)
+        } else {
+            None
+        };
+    }
+
+    /// 添加 CC 曲线并在曲线编辑区显示它；同一控制器已有曲线时只切换显示
+    pub fn add_cc_lane(&mut self, cc: u8) -> CurveLaneId {
+        self.add_curve_lane(CurveLaneType::ControlChange(cc.min(127)))
+    }
+
+    /// 添加弯音曲线并在曲线编辑区显示它；已有弯音曲线时只切换显示
+    pub fn add_pitch_bend_lane(&mut self) -> CurveLaneId {
+        self.add_curve_lane(CurveLaneType::PitchBend)
+    }
+
+    fn add_curve_lane(&mut self, lane_type: CurveLaneType) -> CurveLaneId {
+        let existing = self.state.curves.iter().find(|lane| lane.lane_type == lane_type).map(|lane| lane.id);
+        let lane_id = match existing {
+            Some(lane_id) => lane_id,
+            None => {
+                self.push_undo_snapshot();
+                let lane_id = self.state.add_curve_lane(lane_type);
+                self.emit_event(EditorEvent::CurveLaneAdded(lane_id));
+                lane_id
+            }
+        };
+        self.show_curve_lane(lane_id);
+        lane_id
+    }
+
+    /// 在曲线编辑区显示指定曲线（清除点选择）
+    fn show_curve_lane(&mut self, lane_id: CurveLaneId) {
+        if self.displayed_curve_lane() != Some(lane_id) {
+            self.selected_curve_points.clear();
+            self.dragging_curve_point = None;
+        }
+        self.selected_curve_lane = Some(lane_id);
+    }
+
+    /// 曲线编辑区上方的曲线选择栏：切换显示的曲线、添加 CC 或弯音曲线
+    fn ui_curve_lane_selector(&mut self, ui: &mut Ui) {
+        let displayed = self.displayed_curve_lane();
+        let lanes: Vec<(CurveLaneId, String)> =
+            self.state.curves.iter().map(|lane| (lane.id, lane.name.clone())).collect();
+        let mut show_lane = None;
+        let mut add_cc = None;
+        let mut add_pitch_bend = false;
+        ui.horizontal(|ui| {
+            self.ui_lower_lane_view_switch(ui);
+            ui.label("Lane:");
+            let selected_name = lanes
+                .iter()
+                .find(|(id, _)| Some(*id) == displayed)
+                .map(|(_, name)| name.clone())
+                .unwrap_or_default();
+            ComboBox::from_id_salt("curve_lane_combo")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    for (id, name) in &lanes {
+                        if ui.selectable_label(Some(*id) == displayed, name).clicked() {
+                            show_lane = Some(*id);
                         }
-                    });
+                    }
                 });
-            
-            // Close menu if user clicks elsewhere
-            // Logic: if click is NOT in the menu rect, close the menu
-            // Valid buttons (Quantize, Humanize, Batch Transform) already set context_menu_pos = None
-            // Submenu buttons (Snap Mode, Snap Interval) don't close the menu (egui handles this)
-            let ctx = ui.ctx();
-            if ctx.input(|i| i.pointer.primary_clicked() || i.pointer.secondary_clicked()) {
-                if let Some(click_pos) = ctx.input(|i| i.pointer.interact_pos()) {
-                    // Get the actual menu rect from the Area response
-                    let menu_rect = menu_response.response.rect;
-                    
-                    // Ignore the click that opened the menu (same position within a small threshold)
-                    let ignore_click = if let Some(open_pos) = self.context_menu_open_pos {
-                        let threshold = 5.0; // pixels
-                        click_pos.distance(open_pos) < threshold
-                    } else {
-                        false
-                    };
-                    
-                    // Simple logic: if click is NOT in menu rect, close menu
-                    // Submenus (menu_button popups) are handled by egui automatically
-                    // If user clicks outside the main menu, close it
-                    if !ignore_click && !menu_rect.contains(click_pos) {
-                        // Clear swing adjustment state when closing menu
-                        self.swing_original_notes.clear();
-                        self.swing_menu_ratio = 0.0;
-                        self.context_menu_pos = None;
-                        self.context_menu_open_pos = None;
+            ui.menu_button("+ CC", |ui| {
+                for cc in [1u8, 7, 10, 11, 64] {
+                    if ui.button(CurveLaneType::ControlChange(cc).default_name()).clicked() {
+                        add_cc = Some(cc);
+                        ui.close_menu();
                     }
-                    // If click is inside menu_rect, don't close (menu item click will handle it)
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.custom_cc_number).range(0..=127).prefix("CC"));
+                    if ui.button("Add").clicked() {
+                        add_cc = Some(self.custom_cc_number);
+                        ui.close_menu();
+                    }
+                });
+            });
+            add_pitch_bend = ui.button("+ Pitch Bend").clicked();
+            ui.separator();
+            for tool in CurveTool::ALL {
+                ui.selectable_value(&mut self.curve_tool, tool, tool.label());
+            }
+        });
+        if let Some(lane_id) = show_lane {
+            self.show_curve_lane(lane_id);
+        }
+        if let Some(cc) = add_cc {
+            self.add_cc_lane(cc);
+        }
+        if add_pitch_bend {
+            self.add_pitch_bend_lane();
+        }
+    }
+
+    /// 在曲线与音符力度柱之间切换分隔条下方的显示内容
+    fn ui_lower_lane_view_switch(&mut self, ui: &mut Ui) {
+        for view in LowerLaneView::ALL {
+            ui.selectable_value(&mut self.lower_lane_view, view, view.label());
+        }
+        ui.separator();
+    }
+
+    /// 经典力度编辑区：每个可编辑音符在起点处一根力度柱，选中音符的柱子高亮
+    ///
+    /// 按下并拖动柱子设置该音符的力度；拖动选中的音符时整个选区按相同差值变化（相对模式），
+    /// 每次拖动只记录一个撤销快照。
+    fn ui_note_velocity_lane(&mut self, ui: &mut Ui) {
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), Sense::click_and_drag());
+        self.velocity_lane_rect = Some(rect);
+        let bars_rect = rect.shrink2(Vec2::new(0.0, VELOCITY_LANE_PADDING));
+        let tpb = self.state.ticks_per_beat.max(1) as u64;
+        let note_offset_x = rect.min.x + PIANO_KEY_WIDTH + self.manual_scroll_x;
+        let start_tick = ((-self.manual_scroll_x / self.zoom_x).max(0.0) * tpb as f32) as u64;
+        let end_tick = start_tick.saturating_add(((rect.width() / self.zoom_x) * tpb as f32) as u64 + 1);
+
+        let first = self.state.notes.partition_point(|n| n.start < start_tick);
+        let last = self.state.notes.partition_point(|n| n.start <= end_tick);
+        let bars: Vec<(NoteId, f32, u8)> = self.state.notes[first..last.max(first)]
+            .iter()
+            .filter(|note| self.is_note_editable(note))
+            .map(|note| (note.id, self.tick_to_screen_x(rect, note.start), note.velocity))
+            .filter(|(_, x, _)| rect.x_range().contains(*x))
+            .collect();
+
+        let theme = self.palette;
+        let mut shapes = vec![Shape::rect_filled(rect, 0.0, theme.lane_background)];
+        render::vertical_grid(
+            &mut shapes,
+            &theme,
+            self.triplet_grid(),
+            note_offset_x,
+            self.zoom_x,
+            tpb,
+            self.state.ticks_per_measure(),
+            start_tick as i64,
+            end_tick as i64,
+            rect.x_range(),
+            rect.y_range(),
+        );
+        for (id, x, velocity) in &bars {
+            let color = if self.selected_notes.contains(id) { theme.note_outline } else { theme.note };
+            render::velocity_stem(&mut shapes, *x, *velocity, bars_rect, color);
+        }
+        ui.painter_at(rect).extend(shapes);
+
+        let velocity_at = |y: f32| (((bars_rect.max.y - y) / bars_rect.height().max(1.0)) * 127.0).round().clamp(1.0, 127.0) as u8;
+        let (pressed, down, pointer) = ui.input(|i| (i.pointer.primary_pressed(), i.pointer.primary_down(), i.pointer.interact_pos()));
+        if pressed && response.hovered() && self.lane_edit_state.is_none() {
+            let hit = pointer.and_then(|pos| {
+                bars.iter()
+                    .map(|(id, x, _)| (*id, (x - pos.x).abs()))
+                    .filter(|(_, dx)| *dx <= VELOCITY_BAR_HIT_RADIUS)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(id, _)| id)
+            });
+            if let Some(anchor) = hit {
+                let targets = if self.selected_notes.contains(&anchor) {
+                    self.selected_notes.iter().copied().collect()
+                } else {
+                    self.set_single_selection(anchor);
+                    vec![anchor]
+                };
+                self.begin_lane_edit(targets, anchor);
+            }
+        }
+        if self.lane_edit_state.is_some() {
+            if let Some(pos) = pointer {
+                self.apply_lane_velocity(velocity_at(pos.y), true);
+            }
+            if !down {
+                self.finalize_lane_edit();
+            }
+        }
+    }
+
+    /// 曲线编辑区当前显示的曲线
+    fn displayed_curve_lane(&self) -> Option<CurveLaneId> {
+        self.selected_curve_lane
+            .filter(|id| self.state.curves.iter().any(|c| c.id == *id))
+            .or_else(|| {
+                self.state
+                    .curves
+                    .iter()
+                    .find(|c| c.lane_type == CurveLaneType::Velocity)
+                    .map(|c| c.id)
+            })
+    }
+
+    fn has_clipboard(&self) -> bool {
+        !self.clipboard.is_empty() || self.curve_clipboard.is_some() || !self.clipboard_curves.is_empty()
+    }
+
+    /// 导出剪贴板内容，供宿主在编辑器之间传递
+    pub fn clipboard_payload(&self) -> ClipboardPayload {
+        ClipboardPayload {
+            ticks_per_beat: self.state.ticks_per_beat,
+            notes: self.clipboard.clone(),
+            groups: self.clipboard_groups.clone(),
+            curve: self.curve_clipboard.clone(),
+            curves: self.clipboard_curves.clone(),
+            bpm: Some(self.state.bpm),
+        }
+    }
+
+    /// 用外部载荷替换剪贴板（按本编辑器的 tick 精度换算），之后照常粘贴
+    pub fn set_clipboard_payload(&mut self, payload: &ClipboardPayload) {
+        let payload = payload.rescaled(self.state.ticks_per_beat);
+        self.clipboard = payload.notes;
+        self.clipboard_groups = payload.groups;
+        self.curve_clipboard = payload.curve;
+        self.clipboard_curves = payload.curves;
+        if self.truncate_clipboard() {
+            let kept: BTreeSet<NoteId> = self.clipboard.iter().map(|n| n.id).collect();
+            for group in &mut self.clipboard_groups {
+                group.note_ids.retain(|id| kept.contains(id));
+            }
+            self.clipboard_groups.retain(|g| !g.note_ids.is_empty());
+        }
+    }
+
+    /// 按 `max_clipboard_notes` 截断剪贴板（保留最早的音符），发生截断时返回 true
+    fn truncate_clipboard(&mut self) -> bool {
+        if self.max_clipboard_notes == 0 || self.clipboard.len() <= self.max_clipboard_notes {
+            return false;
+        }
+        log::warn!(
+            "MidiEditor: clipboard limited to {} of {} notes",
+            self.max_clipboard_notes,
+            self.clipboard.len()
+        );
+        self.clipboard.sort_by_key(|n| n.start);
+        self.clipboard.truncate(self.max_clipboard_notes);
+        true
+    }
+
+    fn cut_selection(&mut self) {
+        if self.selected_notes.is_empty() {
+            return;
+        }
+        self.copy_selection();
+        let ids: Vec<_> = self.selected_notes.iter().copied().collect();
+        self.remove_notes(ids);
+        // 一起复制的曲线点也一并剪切（与删除音符同一撤销步骤）
+        let removed = match self.clipboard_range() {
+            Some((start, end)) if !self.clipboard_curves.is_empty() => self.state.remove_curve_range(start, end),
+            _ => match (self.curve_clipboard.is_some(), self.displayed_curve_lane()) {
+                (true, Some(lane_id)) => {
+                    let selected = std::mem::take(&mut self.selected_curve_points);
+                    self.state
+                        .curves
+                        .iter_mut()
+                        .find(|c| c.id == lane_id)
+                        .map(|lane| selected.iter().filter_map(|id| lane.remove_point(*id)).map(|p| (lane_id, p.id)).collect())
+                        .unwrap_or_default()
+                }
+                _ => Vec::new(),
+            },
+        };
+        for (lane_id, point_id) in removed {
+            self.emit_event(EditorEvent::CurvePointRemoved { lane_id, point_id });
+        }
+    }
+
+    /// 按当前的粘贴方式在 `target_tick` 粘贴，音高不变
+    fn paste_clipboard_at(&mut self, target_tick: u64) {
+        self.paste_clipboard_with(target_tick, None, self.paste_mode);
+    }
+
+    /// 在 `target_tick` 粘贴音符与曲线片段，作为一个撤销步骤
+    fn paste_clipboard_with(&mut self, target_tick: u64, key_anchor: Option<u8>, mode: PasteMode) {
+        if !self.has_clipboard() {
+            return;
+        }
+        self.push_undo_snapshot();
+        let target_tick = if self.clipboard.is_empty() {
+            target_tick
+        } else {
+            self.paste_notes_at(target_tick, key_anchor, mode)
+        };
+        self.paste_curve_at(target_tick);
+        self.paste_curve_lanes_at(target_tick);
+    }
+
+    /// 把随音符复制的曲线片段粘贴到同类曲线（不存在时创建），每条曲线一个 `CurvePointsPasted`
+    fn paste_curve_lanes_at(&mut self, target_tick: u64) {
+        let overwrite = self.curve_paste_overwrite;
+        for clip in self.clipboard_curves.clone() {
+            let existing = self.state.curves.iter().find(|c| c.lane_type == clip.lane_type).map(|c| c.id);
+            let lane_id = existing.unwrap_or_else(|| self.state.add_curve_lane(clip.lane_type));
+            if existing.is_none() {
+                self.emit_event(EditorEvent::CurveLaneAdded(lane_id));
+            }
+            if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                let (removed, added) = lane.paste_clip(&clip, target_tick, overwrite);
+                self.emit_event(EditorEvent::CurvePointsPasted { lane_id, removed, added });
+            }
+        }
+    }
+
+    fn paste_curve_at(&mut self, target_tick: u64) {
+        let (Some(clip), Some(lane_id)) = (self.curve_clipboard.clone(), self.displayed_curve_lane()) else {
+            return;
+        };
+        let target_tick = self.clamp_start_to_bounds(target_tick.saturating_sub(clip.lead), clip.span);
+        let overwrite = self.curve_paste_overwrite;
+        if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+            let (removed, added) = lane.paste_clip(&clip, target_tick, overwrite);
+            self.selected_curve_points = added.clone();
+            self.emit_event(EditorEvent::CurvePointsPasted {
+                lane_id,
+                removed,
+                added,
+            });
+        }
+    }
+
+    /// 粘贴剪贴板中的音符（不记录撤销），返回实际落点
+    ///
+    /// `key_anchor` 为 `Some` 时整体移调使最低音落在该音高上（整块限制在 0..=127 内）。
+    fn paste_notes_at(&mut self, target_tick: u64, key_anchor: Option<u8>, mode: PasteMode) -> u64 {
+        let min_start = self
+            .clipboard
+            .iter()
+            .map(|n| n.start)
+            .min()
+            .unwrap_or(target_tick);
+        let span = Self::block_span(&self.clipboard);
+        let target_tick = self.clamp_start_to_bounds(target_tick, span);
+        let offset = target_tick.saturating_sub(min_start);
+        let templates = self.clipboard.clone();
+        let transpose = key_anchor.map_or(0, |anchor| {
+            let low = templates.iter().map(|n| n.key).min().unwrap_or(anchor) as i32;
+            let high = templates.iter().map(|n| n.key).max().unwrap_or(anchor) as i32;
+            (anchor as i32 - low).clamp(-low, 127 - high)
+        });
+        let previous = self.selected_notes.clone();
+        self.selected_notes.clear();
+        let track = self.active_track;
+        let range = target_tick..target_tick + span.max(1);
+        if mode == PasteMode::Replace {
+            let (removed, kept) = std::mem::take(&mut self.state.notes)
+                .into_iter()
+                .partition(|n: &Note| n.track == track && range.contains(&n.start));
+            self.state.notes = kept;
+            for note in removed {
+                self.emit_note_deleted(note);
+            }
+            self.prune_note_groups();
+        }
+        let existing: HashSet<(u64, u8)> = match mode {
+            PasteMode::MergeSkipDuplicates => self
+                .state
+                .notes
+                .iter()
+                .filter(|n| n.track == track && range.contains(&n.start))
+                .map(|n| (n.start, n.key))
+                .collect(),
+            _ => HashSet::new(),
+        };
+        let mut pasted_ids = HashMap::new();
+        for template in templates {
+            let Some(key) = self.constrain_key((template.key as i32 + transpose) as u8) else {
+                continue;
+            };
+            if existing.contains(&(template.start + offset, key)) {
+                continue;
+            }
+            let new_note = Note {
+                label: template.label,
+                track: self.active_track,
+                ..Note::new(template.start + offset, template.duration, key, template.velocity)
+            };
+            self.state.notes.push(new_note);
+            self.emit_note_added(new_note);
+            self.selected_notes.insert(new_note.id);
+            pasted_ids.insert(template.id, new_note.id);
+        }
+        self.sort_notes();
+        self.sync_sounding_notes();
+        // Pasted copies of grouped notes form new groups
+        for group in self.clipboard_groups.clone() {
+            let members: Vec<NoteId> = group
+                .note_ids
+                .iter()
+                .filter_map(|id| pasted_ids.get(id).copied())
+                .collect();
+            if !members.is_empty() {
+                let (id, _) = self.state.create_group(group.name, &members);
+                self.emit_event(EditorEvent::GroupCreated(id));
+            }
+        }
+        self.notify_selection_changed(previous);
+        target_tick
+    }
+
+    /// 从最早起点到最晚终点的跨度
+    fn block_span(notes: &[Note]) -> u64 {
+        let start = notes.iter().map(|n| n.start).min().unwrap_or(0);
+        let end = notes.iter().map(|n| n.start + n.duration).max().unwrap_or(0);
+        end.saturating_sub(start)
+    }
+
+    /// 把选中音符复制到选区终点之后（一次撤销）并选中副本，重复调用可把乐句平铺下去
+    ///
+    /// 偏移为选区跨度向上取整到吸附间隔；不经过剪贴板，超出可编辑区域的副本被丢弃。
+    fn duplicate_selection(&mut self) {
+        let originals = self.selected_notes_snapshot();
+        if originals.is_empty() {
+            return;
+        }
+        let span = Self::block_span(&originals).max(1);
+        let snap = self.snap_ticks();
+        let offset = if snap > 0 {
+            span.div_ceil(snap).saturating_mul(snap)
+        } else {
+            span
+        };
+        let copies: Vec<Note> = originals
+            .iter()
+            .map(|original| Note {
+                label: original.label,
+                track: original.track,
+                ..Note::new(
+                    original.start.saturating_add(offset).min(u64::MAX - original.duration),
+                    original.duration,
+                    original.key,
+                    original.velocity,
+                )
+            })
+            .filter(|copy| self.edit_bounds.is_none_or(|(lo, hi)| copy.start >= lo && copy.start < hi))
+            .collect();
+        if copies.is_empty() {
+            self.show_toast("Outside the editable region");
+            return;
+        }
+        self.push_undo_snapshot();
+        let previous = std::mem::take(&mut self.selected_notes);
+        for copy in copies {
+            self.state.notes.push(copy);
+            self.emit_note_added(copy);
+            self.selected_notes.insert(copy.id);
+        }
+        self.sort_notes();
+        self.sync_sounding_notes();
+        self.notify_selection_changed(previous);
+    }
+
+    fn delete_selected_notes(&mut self) {
+        if self.selected_notes.is_empty() {
+            return;
+        }
+        let ids: Vec<_> = self.selected_notes.iter().copied().collect();
+        self.remove_notes(ids);
+    }
+
+    /// 自动修剪重叠：与 `moved` 中的音符同轨道、同音高并重叠的其他音符被截短（从前方重叠时截掉尾部，
+    /// 从后方重叠时推迟起点），完全被覆盖的删除
+    ///
+    /// 在拖动的撤销步骤结束前调用，改动并入同一步撤销；发出 `NoteUpdated` / `NoteDeleted`。
+    fn trim_overlaps_under(&mut self, moved: &[NoteId]) {
+        let moved_ids: HashSet<NoteId> = moved.iter().copied().collect();
+        let movers: Vec<Note> = moved.iter().filter_map(|id| self.note_by_id(*id)).collect();
+        let mut updated = Vec::new();
+        let mut removed = Vec::new();
+        for note in self.state.notes.iter_mut().filter(|n| !moved_ids.contains(&n.id)) {
+            let before = *note;
+            let mut covered = false;
+            for mover in movers.iter().filter(|m| m.key == note.key && m.track == note.track) {
+                let (start, end) = (note.start, note.start + note.duration);
+                let (mover_start, mover_end) = (mover.start, mover.start + mover.duration);
+                if start >= mover_end || mover_start >= end {
+                    continue;
+                }
+                if start < mover_start {
+                    note.duration = mover_start - start;
+                } else if end <= mover_end {
+                    covered = true;
+                    break;
                 } else {
-                    // Can't determine click position, close menu to be safe
-                    // Clear swing adjustment state when closing menu
-                    self.swing_original_notes.clear();
-                    self.swing_menu_ratio = 0.0;
-                    self.context_menu_pos = None;
-                    self.context_menu_open_pos = None;
+                    note.start = mover_end;
+                    note.duration = end - mover_end;
                 }
             }
+            if covered {
+                *note = before;
+                removed.push(before);
+            } else if *note != before {
+                updated.push((before, *note));
+            }
         }
-        
-        // Playback settings dialog
-        if self.show_playback_settings {
-            egui::Window::new("Playback Settings")
-                .collapsible(false)
-                .resizable(true)
-                .show(ui.ctx(), |ui| {
-                    ui.set_min_width(300.0);
-                    
-                    ui.label("Volume:");
-                    let mut volume = self.volume;
-                    if ui
-                        .add(
-                            Slider::new(&mut volume, 0.0..=1.0)
-                                .text("%")
-                                .custom_formatter(|n, _| format!("{:.0}%", n * 200.0)),
-                        )
-                        .changed()
-                    {
-                        self.set_volume(volume);
-                    } else {
-                        self.volume = volume;
-                    }
+        if updated.is_empty() && removed.is_empty() {
+            return;
+        }
+        let removed_ids: HashSet<NoteId> = removed.iter().map(|n| n.id).collect();
+        self.state.notes.retain(|n| !removed_ids.contains(&n.id));
+        self.sort_notes();
+        for (before, after) in updated {
+            self.emit_note_updated(before, after);
+        }
+        let previous = self.selected_notes.clone();
+        for note in removed {
+            self.selected_notes.remove(&note.id);
+            self.emit_note_deleted(note);
+        }
+        self.prune_note_groups();
+        self.sync_sounding_notes();
+        self.notify_selection_changed(previous);
+    }
 
-                    ui.separator();
-                    ui.label("Pitch:");
-                    let mut pitch = self.preview_pitch_shift;
-                    if ui
-                        .add(Slider::new(&mut pitch, -12.0..=12.0).text("± semitone"))
-                        .changed()
-                    {
-                        self.preview_pitch_shift = pitch;
-                        if let Some(playback) = &self.playback {
-                            playback.set_pitch_shift(pitch);
-                        }
-                    }
+    /// 消除同音高重叠（一次撤销），并通过 `OverlapsResolved` 报告受影响的音符数
+    fn resolve_overlaps(&mut self, policy: OverlapPolicy) {
+        let mut state = self.state.clone();
+        let scope = (!self.selected_notes.is_empty()).then_some(&self.selected_notes);
+        let (updated, removed) = state.resolve_overlaps(scope, policy);
+        let count = updated.len() + removed.len();
+        if count > 0 {
+            self.push_undo_snapshot();
+            self.state = state;
+            for (before, after) in updated {
+                self.emit_note_updated(before, after);
+            }
+            let previous = self.selected_notes.clone();
+            for note in removed {
+                self.selected_notes.remove(&note.id);
+                self.emit_note_deleted(note);
+            }
+            self.prune_note_groups();
+            self.sync_sounding_notes();
+            self.notify_selection_changed(previous);
+            self.show_toast(format!("Cleaned up {count} overlapping notes"));
+        } else {
+            self.show_toast("No overlapping notes");
+        }
+        self.emit_event(EditorEvent::OverlapsResolved { count });
+    }
 
-                    ui.separator();
-                    ui.checkbox(&mut self.loop_enabled, "Loop");
-                    if self.loop_enabled {
-                        ui.horizontal(|ui| {
-                            ui.label("Start:");
-                            let mut loop_start = self.loop_start_tick as i64;
-                            if ui
-                                .add(DragValue::new(&mut loop_start).speed(1.0))
-                                .changed()
-                            {
-                                self.loop_start_tick = loop_start.max(0) as u64;
-                                if self.loop_start_tick >= self.loop_end_tick {
-                                    self.loop_end_tick = self.loop_start_tick + 1;
-                                }
-                            }
-                            ui.label("End:");
-                            let mut loop_end = self.loop_end_tick as i64;
-                            if ui
-                                .add(DragValue::new(&mut loop_end).speed(1.0))
-                                .changed()
-                            {
-                                self.loop_end_tick = loop_end.max((self.loop_start_tick + 1) as i64) as u64;
-                            }
-                        });
-                    }
+    /// 检查状态（启用循环时一并检查循环区），有问题时选中涉及的可编辑音符，并通过 `ValidationFinished` 报告
+    pub fn validate_state(&mut self, max_tick: Option<u64>) -> Vec<ValidationIssue> {
+        let options = ValidationOptions {
+            max_tick,
+            loop_range: self.loop_enabled.then_some((self.loop_start_tick, self.loop_end_tick)),
+        };
+        let issues = self.state.validate_with(&options);
+        if issues.is_empty() {
+            self.show_toast("No problems found");
+        } else {
+            let offending: BTreeSet<NoteId> = issues.iter().flat_map(ValidationIssue::note_ids).collect();
+            let selection: BTreeSet<NoteId> = self
+                .state
+                .notes
+                .iter()
+                .filter(|note| offending.contains(&note.id) && self.is_note_editable(note))
+                .map(|note| note.id)
+                .collect();
+            let previous = std::mem::replace(&mut self.selected_notes, selection);
+            self.notify_selection_changed(previous);
+            let noun = if issues.len() == 1 { "problem" } else { "problems" };
+            self.show_toast(format!("Found {} {noun}", issues.len()));
+        }
+        self.emit_event(EditorEvent::ValidationFinished { issues: issues.clone() });
+        issues
+    }
+
+    /// 粘合选中的同音高音符（一次撤销）；被合并掉的音符发出 `NoteDeleted` 并离开选区
+    fn glue_selected_notes(&mut self, max_gap: u64) {
+        if self.selected_notes.is_empty() {
+            return;
+        }
+        let ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+        let mut state = self.state.clone();
+        let (updated, removed) = state.glue_notes(&ids, max_gap);
+        if removed.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        self.state = state;
+        let previous = self.selected_notes.clone();
+        for note in removed {
+            self.selected_notes.remove(&note.id);
+            self.emit_note_deleted(note);
+        }
+        for (before, after) in updated {
+            self.emit_note_updated(before, after);
+        }
+        self.prune_note_groups();
+        self.sync_sounding_notes();
+        self.notify_selection_changed(previous);
+    }
+
+    /// 连奏：每个选中音符终止于下一个起点更晚的选中音符处（加上 `overlap_ticks`），已经重叠过多的音符也会被截短
+    fn legato_selection(&mut self, overlap_ticks: i64) {
+        let selection = self.selected_notes_snapshot();
+        let mut starts: Vec<u64> = selection.iter().map(|note| note.start).collect();
+        starts.sort_unstable();
+        starts.dedup();
+        let bounds_end = self.edit_bounds.map(|(_, hi)| hi);
+        let changes: Vec<(Note, u64)> = selection
+            .into_iter()
+            .filter_map(|note| {
+                let next = starts[starts.partition_point(|start| *start <= note.start)..].first()?;
+                let mut end = (*next as i64 + overlap_ticks).max(note.start as i64 + 1) as u64;
+                if let Some(hi) = bounds_end {
+                    end = end.min(hi.max(note.start + 1));
+                }
+                (end - note.start != note.duration).then_some((note, end - note.start))
+            })
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        for (before, duration) in changes {
+            if let Some(note) = self.note_mut_by_id(before.id) {
+                note.duration = duration;
+                let after = *note;
+                self.emit_note_updated(before, after);
+            }
+        }
+        self.sync_sounding_notes();
+    }
+
+    /// 打开音高镜像对话框，轴默认取第一个选中音符的音高
+    fn open_invert_dialog(&mut self) {
+        if let Some(note) = self.first_selected_note() {
+            self.invert_axis_key = note.key;
+        }
+        self.show_invert_dialog = true;
+    }
+
+    /// 以 `axis_key` 为轴镜像选中音符的音高（一次撤销），并试听第一个选中音符的新音高
+    fn invert_selected_pitches(&mut self, axis_key: u8) {
+        let ids: Vec<NoteId> = self.selected_notes.iter().copied().collect();
+        let mut state = self.state.clone();
+        let changes = state.invert_pitch(&ids, axis_key);
+        if changes.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        self.state = state;
+        for (before, after) in changes {
+            self.emit_note_updated(before, after);
+        }
+        self.sync_sounding_notes();
+        if let Some(note) = self.first_selected_note() {
+            self.preview_note_once(note.key, note.velocity);
+        }
+    }
+
+    /// 以基准点伸缩选中音符（以及可选的选区范围内曲线点），一次撤销
+    fn time_stretch_selection(&mut self, ratio: f64, anchor: StretchAnchor, include_curves: bool) {
+        let selection = self.selected_notes_snapshot();
+        let (Some(first), Some(last)) = (
+            selection.iter().map(|note| note.start).min(),
+            selection.iter().map(|note| note.start + note.duration).max(),
+        ) else {
+            return;
+        };
+        let anchor_tick = match anchor {
+            StretchAnchor::SelectionStart => first,
+            StretchAnchor::Playhead => self.current_tick_position(),
+            StretchAnchor::BarStart => {
+                let measure = self.state.ticks_per_measure();
+                first / measure * measure
+            }
+        };
+        let ids: Vec<NoteId> = selection.iter().map(|note| note.id).collect();
+        let mut state = self.state.clone();
+        let changes = state.time_stretch(&ids, anchor_tick, ratio);
+        let moved_points = if include_curves {
+            state.time_stretch_curves((first, last), anchor_tick, ratio)
+        } else {
+            Vec::new()
+        };
+        if changes.is_empty() && moved_points.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        self.state = state;
+        for (before, after) in changes {
+            self.emit_note_updated(before, after);
+        }
+        for (lane_id, point_id) in moved_points {
+            self.emit_event(EditorEvent::CurvePointUpdated { lane_id, point_id });
+        }
+        self.sync_sounding_notes();
+    }
+
+    /// 用琶音替换选中的和弦（一次撤销），新音符成为选区；受限音高时吸附，附近没有允许音高的步被跳过
+    fn arpeggiate_selection(&mut self, pattern: ArpPattern, rate_ticks: u64, octaves: u8, gate: f32) {
+        if self.selected_notes.is_empty() || rate_ticks == 0 {
+            return;
+        }
+        let originals = self.selected_notes_snapshot();
+        let generated: Vec<Note> = arpeggiate(&originals, pattern, rate_ticks, octaves, gate, &mut fastrand::Rng::new())
+            .into_iter()
+            .filter_map(|note| self.constrain_key(note.key).map(|key| Note { key, ..note }))
+            .collect();
+        self.push_undo_snapshot();
+        let previous = std::mem::take(&mut self.selected_notes);
+        self.state.notes.retain(|note| !previous.contains(&note.id));
+        for note in originals {
+            self.emit_note_deleted(note);
+        }
+        self.state.notes.extend_from_slice(&generated);
+        self.sort_notes();
+        for note in &generated {
+            self.emit_note_added(*note);
+        }
+        self.selected_notes = generated.iter().map(|note| note.id).collect();
+        self.prune_note_groups();
+        self.sync_sounding_notes();
+        self.notify_selection_changed(previous);
+    }
+
+    /// 选中音符整体平移 `ticks`（负数向前）并移调 `semitones`，作为一个撤销步骤
+    ///
+    /// 偏移量会被夹住，使所有音符保持在 0 tick（或可编辑区间）之后、音高 0–127 之内，彼此的相对位置不变；
+    /// 设置了允许音高时移调跳到该方向上下一个允许的音高。
+    pub fn nudge_selected_notes(&mut self, ticks: i64, semitones: i32) {
+        self.nudge_selection(ticks, semitones, false);
+    }
+
+    /// `coalesce` 为 true 时（按住方向键的自动重复）不再压入撤销快照，同一次按键只占一个撤销步骤
+    fn nudge_selection(&mut self, ticks: i64, semitones: i32, coalesce: bool) {
+        let originals: Vec<Note> = self
+            .state
+            .notes
+            .iter()
+            .filter(|n| self.selected_notes.contains(&n.id))
+            .copied()
+            .collect();
+        if originals.is_empty() {
+            return;
+        }
+        let (lo, hi) = self.edit_bounds.unwrap_or((0, u64::MAX));
+        let min_start = originals.iter().map(|n| n.start).min().unwrap_or(0) as i64;
+        let max_end = originals.iter().map(|n| n.start + n.duration).max().unwrap_or(0);
+        let ticks = if ticks < 0 {
+            ticks.max(lo as i64 - min_start).min(0)
+        } else {
+            ticks.min(hi.saturating_sub(max_end).min(i64::MAX as u64) as i64)
+        };
+        let min_key = originals.iter().map(|n| n.key).min().unwrap_or(0) as i32;
+        let max_key = originals.iter().map(|n| n.key).max().unwrap_or(127) as i32;
+        let semitones = semitones.clamp(-min_key, 127 - max_key);
+        let allowed = self.allowed_keys;
+        let transpose = |key: u8| -> u8 {
+            let mut target = key as i32 + semitones;
+            if let Some(allowed) = allowed.filter(|_| semitones != 0) {
+                while (0..=127).contains(&target) && !allowed.contains(target as u8) {
+                    target += semitones.signum();
+                }
+            }
+            if (0..=127).contains(&target) { target as u8 } else { key }
+        };
+        let moved: Vec<(Note, Note)> = originals
+            .iter()
+            .map(|before| {
+                let after = Note {
+                    start: (before.start as i64 + ticks).max(0) as u64,
+                    key: transpose(before.key),
+                    ..*before
+                };
+                (*before, after)
+            })
+            .filter(|(before, after)| before != after)
+            .collect();
+        if moved.is_empty() {
+            return;
+        }
+        if !coalesce || self.undo_stack.is_empty() {
+            self.push_undo_snapshot();
+        }
+        for (before, after) in &moved {
+            if let Some(note) = self.note_mut_by_id(before.id) {
+                *note = *after;
+            }
+        }
+        self.sort_notes();
+        self.sync_sounding_notes();
+        for (before, after) in moved.iter().copied() {
+            self.emit_note_updated(before, after);
+        }
+        if semitones != 0 {
+            if let Some((_, after)) = moved.iter().find(|(before, after)| before.key != after.key) {
+                self.preview_note_once(after.key, 100);
+            }
+        }
+    }
+
+    fn quantize_selected_notes(&mut self) {
+        self.quantize_notes(QuantizeTargets::Starts, 1.0, self.snap_ticks());
+    }
 
-                    ui.separator();
-                    ui.label("Snap Interval:");
-                    let mut snap = self.snap_interval;
-                    let snap_label = if snap == 0 {
-                        "Free".to_owned()
-                    } else {
-                        format!("1/{}", (480 * 4 / snap).max(1))
-                    };
-                    ComboBox::from_id_salt("snap_combo_dialog")
-                        .selected_text(snap_label)
-                        .show_ui(ui, |ui| {
-                            ui.selectable_value(&mut snap, 480 * 4, "1/1");
-                            ui.selectable_value(&mut snap, 480 * 2, "1/2");
-                            ui.selectable_value(&mut snap, 480, "1/4");
-                            ui.selectable_value(&mut snap, 240, "1/8");
-                            ui.selectable_value(&mut snap, 120, "1/16");
-                            ui.selectable_value(&mut snap, 0, "Free");
-                        });
-                    if snap != self.snap_interval {
-                        self.set_snap_interval(snap);
-                    }
+    /// 人性化对话框当前设置的时间范围（tick）
+    fn humanize_time_range(&self) -> u64 {
+        if self.humanize_time_relative {
+            (self.snap_ticks() as f32 * self.humanize_time_fraction).round() as u64
+        } else {
+            self.humanize_time_ticks
+        }
+    }
 
-                    ui.separator();
-                    ui.label("Snap Mode:");
-                    ComboBox::from_id_salt("snap_mode_dialog")
-                        .selected_text(match self.snap_mode {
-                            SnapMode::Absolute => "Absolute",
-                            SnapMode::Relative => "Relative",
-                        })
-                        .show_ui(ui, |ui| {
-                            ui.selectable_value(&mut self.snap_mode, SnapMode::Absolute, "Absolute");
-                            ui.selectable_value(&mut self.snap_mode, SnapMode::Relative, "Relative");
-                        });
+    /// 打开量化对话框，网格默认取当前吸附间隔
+    fn open_quantize_dialog(&mut self) {
+        let snap = self.snap_ticks();
+        if snap > 0 {
+            self.quantize_interval = snap;
+        }
+        self.show_quantize_dialog = true;
+    }
 
-                    ui.separator();
-                    if ui.button("Close").clicked() {
-                        self.show_playback_settings = false;
+    /// 量化选中音符（一次撤销）：每个目标端点移向最近网格点的 `strength` 比例
+    fn quantize_notes(&mut self, targets: QuantizeTargets, strength: f32, interval: u64) {
+        if self.selected_notes.is_empty() || interval == 0 {
+            return;
+        }
+        let strength = strength.clamp(0.0, 1.0) as f64;
+        let toward_grid = |tick: u64| -> u64 {
+            let grid = (tick as f64 / interval as f64).round() * interval as f64;
+            (tick as f64 + (grid - tick as f64) * strength).round().max(0.0) as u64
+        };
+        let changes: Vec<(Note, Note)> = self
+            .selected_notes_snapshot()
+            .into_iter()
+            .filter_map(|before| {
+                let end = before.start + before.duration;
+                let (start, end) = match targets {
+                    QuantizeTargets::Starts => {
+                        let start = toward_grid(before.start);
+                        (start, start + before.duration)
                     }
-                });
+                    QuantizeTargets::Ends => (before.start, toward_grid(end)),
+                    QuantizeTargets::Both => (toward_grid(before.start), toward_grid(end)),
+                };
+                let after = Note { start, duration: end.saturating_sub(start).max(1), ..before };
+                (after != before).then_some((before, after))
+            })
+            .collect();
+        if changes.is_empty() {
+            return;
         }
-        
-        // Batch transform dialog
-        if self.show_batch_transform_dialog {
-            egui::Window::new("Batch Transform")
-                .collapsible(false)
-                .resizable(true)
-                .show(ui.ctx(), |ui| {
-                    ui.vertical(|ui| {
-                        ui.label("Transform Type:");
-                        ui.horizontal(|ui| {
-                            if ui.selectable_label(
-                                self.batch_transform_type == BatchTransformType::VelocityOffset,
-                                "Velocity Offset",
-                            ).clicked() {
-                                self.batch_transform_type = BatchTransformType::VelocityOffset;
-                            }
-                            if ui.selectable_label(
-                                self.batch_transform_type == BatchTransformType::DurationScale,
-                                "Duration Scale",
-                            ).clicked() {
-                                self.batch_transform_type = BatchTransformType::DurationScale;
-                            }
-                            if ui.selectable_label(
-                                self.batch_transform_type == BatchTransformType::PitchOffset,
-                                "Pitch Offset",
-                            ).clicked() {
-                                self.batch_transform_type = BatchTransformType::PitchOffset;
-                            }
-                        });
-                        
-                        ui.add_space(10.0);
-                        
-                        match self.batch_transform_type {
-                            BatchTransformType::VelocityOffset => {
-                                ui.label("Velocity offset (-127 to +127):");
-                                ui.add(egui::Slider::new(&mut self.batch_transform_value, -127.0..=127.0));
-                            }
-                            BatchTransformType::DurationScale => {
-                                ui.label("Duration scale factor (0.1 to 10.0):");
-                                ui.add(egui::Slider::new(&mut self.batch_transform_value, 0.1..=10.0));
-                            }
-                            BatchTransformType::PitchOffset => {
-                                ui.label("Pitch offset (semitones, -127 to +127):");
-                                ui.add(egui::Slider::new(&mut self.batch_transform_value, -127.0..=127.0));
-                            }
-                        }
-                        
-                        ui.add_space(10.0);
-                        
-                        ui.horizontal(|ui| {
-                            if ui.button("Apply").clicked() {
-                                if !self.selected_notes.is_empty() {
-                                    self.apply_command(EditorCommand::BatchTransform {
-                                        transform_type: self.batch_transform_type,
-                                        value: self.batch_transform_value,
-                                    });
-                                }
-                                self.show_batch_transform_dialog = false;
-                            }
-                            if ui.button("Cancel").clicked() {
-                                self.show_batch_transform_dialog = false;
-                            }
-                        });
-                    });
-                });
+        self.push_undo_snapshot();
+        for (before, after) in changes {
+            if let Some(note) = self.note_mut_by_id(before.id) {
+                *note = after;
+            }
+            self.emit_note_updated(before, after);
         }
-        
+        self.sort_notes();
+        self.sync_sounding_notes();
     }
 
-    fn update_sequencer(&mut self) {
-        if self.state.ticks_per_beat == 0 || self.state.bpm <= 0.0 {
+    #[allow(dead_code)]
+    fn apply_swing_to_selected_notes(&mut self, swing_ratio: f32) {
+        if self.selected_notes.is_empty() || swing_ratio <= 0.0 {
             return;
         }
+        
+        self.push_undo_snapshot();
+        // Save original positions
+        self.swing_original_notes = self.selected_notes
+            .iter()
+            .filter_map(|&id| {
+                self.note_by_id(id).map(|note| (id, note.start))
+            })
+            .collect();
+        
+        self.apply_swing_to_selected_notes_realtime(swing_ratio);
+    }
 
-        let seconds_per_beat = 60.0 / self.state.bpm;
-        let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-
-        let current_tick = (self.current_time / seconds_per_tick) as u64;
-
-        if let Some(playback) = &self.playback {
-            for note in &self.state.notes {
-                // Check for Note On: start lies between last_tick (exclusive) and current_tick (inclusive)
-                // Note: We use > last_tick to ensure we don't retrigger if we paused exactly on start.
-                // But for FIRST note starting at 0, last_tick might be 0.
-                // So special case: if last_tick is 0, include 0.
-
-                let should_trigger_start = if self.last_tick == 0 {
-                    note.start >= self.last_tick && note.start <= current_tick
+    fn apply_swing_to_selected_notes_realtime(&mut self, swing_ratio: f32) {
+        if self.selected_notes.is_empty() || self.swing_original_notes.is_empty() {
+            return;
+        }
+        
+        let tpb = self.state.ticks_per_beat as u64;
+        let delay_ticks = (tpb as f32 * 0.5 * swing_ratio) as u64;
+        
+        // Clone original notes to avoid borrow checker issues
+        let original_notes = self.swing_original_notes.clone();
+        
+        // Restore original positions first, then apply swing
+        for (id, original_start) in &original_notes {
+            if let Some(note) = self.note_mut_by_id(*id) {
+                note.start = *original_start;
+            }
+        }
+        
+        // Now apply swing based on original positions
+        for (id, original_start) in &original_notes {
+            if let Some((before, after)) = self.note_mut_by_id(*id).map(|note| {
+                let before = *note;
+                let beat_position = original_start / tpb;
+                let is_even_beat = (beat_position % 2) == 1;
+                
+                if is_even_beat {
+                    note.start = original_start.saturating_add(delay_ticks);
                 } else {
-                    note.start > self.last_tick && note.start <= current_tick
-                };
-
-                if should_trigger_start {
-                    let velocity = self.state.apply_velocity_curve_to_note(note);
-                    playback.note_on(note.key, velocity);
+                    note.start = *original_start;
+                }
+                let after = *note;
+                (before, after)
+            }) {
+                if before.start != after.start {
+                    self.emit_note_updated(before, after);
                 }
+            }
+        }
+        
+        self.sort_notes();
+        self.emit_state_replaced_on_edit();
+    }
 
-                // Check for Note Off: end lies between last_tick and current_tick
-                let end = note.start + note.duration;
-                if end > self.last_tick && end <= current_tick {
-                    playback.note_off(note.key);
+    #[allow(dead_code)]
+    fn note_near_tick(&self, tick: u64) -> Option<Note> {
+        self.state.notes.iter().copied().min_by_key(|note| {
+            if tick >= note.start && tick <= note.start + note.duration {
+                0
+            } else if tick < note.start {
+                note.start - tick
+            } else {
+                tick - (note.start + note.duration)
+            }
+        })
+    }
+
+    fn begin_lane_edit(&mut self, targets: Vec<NoteId>, anchor: NoteId) {
+        let mut uniques = BTreeSet::new();
+        let mut originals = Vec::new();
+        for id in targets {
+            if uniques.insert(id) {
+                if let Some(idx) = self.note_index_by_id(id) {
+                    originals.push((id, self.state.notes[idx]));
                 }
             }
         }
+        if originals.is_empty() {
+            return;
+        }
+        self.push_undo_snapshot();
+        self.lane_edit_state = Some(LaneEditState {
+            anchor,
+            originals,
+        });
+        self.lane_edit_changed = false;
+    }
 
-        // Handle loop playback
-        if self.loop_enabled && self.is_playing {
-            let loop_duration_ticks = self.loop_end_tick.saturating_sub(self.loop_start_tick);
-            if loop_duration_ticks > 0 && current_tick >= self.loop_end_tick {
-                // Jump back to loop start
-                let _loop_duration_seconds = loop_duration_ticks as f32 * seconds_per_tick;
-                self.current_time = self.loop_start_tick as f32 * seconds_per_tick;
-                // Set last_tick to one less than loop_start to ensure notes at loop_start are triggered
-                // If loop_start is 0, we use 0 (which is handled specially in the trigger logic)
-                self.last_tick = self.loop_start_tick.saturating_sub(1);
-                // Stop all notes when looping
-                if let Some(playback) = &self.playback {
-                    playback.all_notes_off();
+    fn apply_lane_velocity(&mut self, value: u8, relative: bool) {
+        let originals = self.lane_edit_state.as_ref().map(|s| s.originals.clone());
+        if let Some(state) = &self.lane_edit_state {
+            let anchor_original = state
+                .originals
+                .iter()
+                .find(|(id, _)| *id == state.anchor)
+                .map(|(_, note)| *note);
+            if let Some(anchor) = anchor_original {
+                let delta = value as i32 - anchor.velocity as i32;
+                if let Some(originals) = originals {
+                    for (id, original) in &originals {
+                        if let Some(note) = self.note_mut_by_id(*id) {
+                            let mut new_velocity = if relative {
+                                (original.velocity as i32 + delta).clamp(1, 127) as u8
+                            } else {
+                                value
+                            };
+                            new_velocity = new_velocity.clamp(1, 127);
+                            if note.velocity != new_velocity {
+                                note.velocity = new_velocity;
+                                self.lane_edit_changed = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    fn apply_lane_pitch(&mut self, value: u8, relative: bool) {
+        let originals = self.lane_edit_state.as_ref().map(|s| s.originals.clone());
+        if let Some(state) = &self.lane_edit_state {
+            let anchor_original = state
+                .originals
+                .iter()
+                .find(|(id, _)| *id == state.anchor)
+                .map(|(_, note)| *note);
+            if let Some(anchor) = anchor_original {
+                let delta = value as i32 - anchor.key as i32;
+                if let Some(originals) = originals {
+                    for (id, original) in &originals {
+                        if let Some(note) = self.note_mut_by_id(*id) {
+                            let mut new_key = if relative {
+                                (original.key as i32 + delta).clamp(0, 127) as u8
+                            } else {
+                                value
+                            };
+                            new_key = new_key.clamp(0, 127);
+                            if note.key != new_key {
+                                note.key = new_key;
+                                self.lane_edit_changed = true;
+                            }
+                        }
+                    }
                 }
-                // Don't update last_tick to current_tick after loop jump, use the value we set above
-                self.emit_transport_event();
-                return;
-            } else if current_tick < self.loop_start_tick {
-                // If somehow before loop start, jump to loop start
-                self.current_time = self.loop_start_tick as f32 * seconds_per_tick;
-                // Same logic as above for ensuring notes at loop_start are triggered
-                self.last_tick = self.loop_start_tick.saturating_sub(1);
-                // Don't update last_tick to current_tick after loop jump, use the value we set above
-                self.emit_transport_event();
-                return;
             }
         }
+    }
 
-        self.last_tick = current_tick;
-        self.emit_transport_event();
+    fn current_tick_position(&self) -> u64 {
+        if self.state.ticks_per_beat == 0 {
+            return 0;
+        }
+        self.state.seconds_to_ticks(self.current_time as f64).max(0.0) as u64
     }
 
-    fn ui_inspector(&mut self, ui: &mut Ui, min_height: f32) {
-        ui.set_min_width(240.0);
-        ui.set_min_height(min_height);
-        ui.vertical(|ui| {
-            ui.heading("Inspector");
-            ui.separator();
-            let selection_len = self.selected_notes.len();
-            ui.label(format!("Selected notes: {selection_len}"));
-            ui.add_space(4.0);
-            if selection_len == 0 {
-                if ui
-                    .add_enabled(!self.clipboard.is_empty(), Button::new("Paste at playhead"))
-                    .clicked()
-                {
-                    let tick = self.current_tick_position();
-                    self.paste_clipboard_at(tick);
-                }
-                ui.label("Tip: Hold Shift to box-select inside the piano roll.");
-                return;
-            }
+    fn notify_playback_started(&self) {
+        if let Some(observer) = &self.playback_observer {
+            observer.on_playback_started();
+        }
+    }
 
-            ui.horizontal(|ui| {
-                if ui.button("Copy").clicked() {
-                    self.copy_selection();
-                }
-                if ui.button("Cut").clicked() {
-                    self.cut_selection();
-                }
-                if ui
-                    .add_enabled(!self.clipboard.is_empty(), Button::new("Paste"))
-                    .clicked()
-                {
-                    let tick = self.current_tick_position();
-                    self.paste_clipboard_at(tick);
-                }
-                if ui.button("Delete").clicked() {
-                    self.delete_selected_notes();
-                }
-            });
+    fn notify_playback_stopped(&self) {
+        if let Some(observer) = &self.playback_observer {
+            observer.on_playback_stopped();
+        }
+    }
 
-            if ui
-                .add_enabled(self.snap_interval > 0, Button::new("Quantize to snap grid"))
-                .clicked()
-            {
-                self.quantize_selected_notes();
-            }
+    /// 电脑键盘弹奏的低八度行起始音高（Z 键）
+    pub fn computer_keyboard_base(&self) -> u8 {
+        self.computer_keyboard_base
+    }
 
-            ui.separator();
-            ui.label("Advanced Tools");
-            ui.horizontal(|ui| {
-                if ui.button("Humanize").clicked() {
-                    // Default: ±10 ticks time, ±5 velocity
-                    let time_range = (self.snap_interval / 12).max(1).min(20);
-                    let velocity_range = 5;
-                    self.apply_command(EditorCommand::HumanizeNotes {
-                        time_range,
-                        velocity_range,
-                    });
-                }
-                if ui.button("Batch Transform...").clicked() {
-                    self.show_batch_transform_dialog = true;
+    /// 电脑键盘弹奏：跟踪同时按住的多个键，忽略自动重复；步进输入开启时按下的音也写入步进光标处
+    fn handle_computer_keyboard(&mut self, ctx: &Context) {
+        if !self.computer_keyboard_input || ctx.wants_keyboard_input() || !ctx.input(|i| i.focused) {
+            self.release_computer_keyboard();
+            return;
+        }
+        let events = ctx.input(|i| i.events.clone());
+        for event in events {
+            let Event::Key { key, pressed, repeat, modifiers, .. } = event else {
+                continue;
+            };
+            if !pressed {
+                if let Some(index) = self.computer_keyboard_held.iter().position(|(held, _)| *held == key) {
+                    let (_, pitch) = self.computer_keyboard_held.remove(index);
+                    self.computer_keyboard_note(pitch, false);
                 }
-            });
-
-            if selection_len == 1 {
-                if let Some(note) = self.first_selected_note() {
-                    self.draw_single_note_inspector(ui, note);
+                continue;
+            }
+            if repeat || modifiers.command || modifiers.ctrl || modifiers.alt {
+                continue;
+            }
+            match key {
+                Key::Minus => self.computer_keyboard_base = self.computer_keyboard_base.saturating_sub(12),
+                Key::Equals => self.computer_keyboard_base = (self.computer_keyboard_base + 12).min(96),
+                _ => {
+                    let Some(offset) = computer_keyboard_offset(key) else {
+                        continue;
+                    };
+                    if self.computer_keyboard_held.iter().any(|(held, _)| *held == key) {
+                        continue;
+                    }
+                    let pitch = (self.computer_keyboard_base + offset).min(127);
+                    self.computer_keyboard_held.push((key, pitch));
+                    self.computer_keyboard_note(pitch, true);
+                    self.step_input_note(pitch, COMPUTER_KEYBOARD_VELOCITY);
                 }
-            } else {
-                self.draw_multi_note_summary(ui);
             }
-        });
+        }
     }
 
-    fn draw_single_note_inspector(&mut self, ui: &mut Ui, note: Note) {
-        ui.separator();
-        ui.label("Single note properties");
-        let note_id = note.id;
-
-        let mut start = note.start as i64;
-        ui.horizontal(|ui| {
-            ui.label("Start");
-            if ui
-                .add(DragValue::new(&mut start).speed(self.snap_interval.max(1) as f64))
-                .changed()
-            {
-                let start = start.max(0) as u64;
-                self.edit_note_by_id(note_id, |n| n.start = start);
-            }
-        });
-
-        let mut duration = note.duration as i64;
-        ui.horizontal(|ui| {
-            ui.label("Duration");
-            if ui
-                .add(DragValue::new(&mut duration).speed(self.snap_interval.max(1) as f64))
-                .changed()
-            {
-                let duration = duration.max(1) as u64;
-                self.edit_note_by_id(note_id, |n| n.duration = duration);
+    fn computer_keyboard_note(&mut self, key: u8, pressed: bool) {
+        if let Some(playback) = &self.playback {
+            if pressed {
+                playback.note_on(key, COMPUTER_KEYBOARD_VELOCITY);
+            } else {
+                playback.note_off(key);
             }
-        });
-
-        let mut key = note.key as i32;
-        if ui
-            .add(Slider::new(&mut key, 0..=127).text("Pitch"))
-            .changed()
-        {
-            let key = key as u8;
-            self.edit_note_by_id(note_id, |n| n.key = key);
         }
-
-        let mut velocity = note.velocity as i32;
-        if ui
-            .add(Slider::new(&mut velocity, 1..=127).text("Velocity"))
-            .changed()
-        {
-            let velocity = velocity as u8;
-            self.edit_note_by_id(note_id, |n| n.velocity = velocity);
+        if pressed {
+            self.feed_live_note_on(key, COMPUTER_KEYBOARD_VELOCITY);
+        } else {
+            self.feed_live_note_off(key);
         }
+        self.emit_event(EditorEvent::LiveNoteInput {
+            key,
+            velocity: COMPUTER_KEYBOARD_VELOCITY,
+            pressed,
+            tick: self.current_tick_position(),
+        });
     }
 
-    fn draw_multi_note_summary(&self, ui: &mut Ui) {
-        let snapshot = self.selected_notes_snapshot();
-        if snapshot.is_empty() {
-            return;
+    /// 松开所有按住的电脑键盘音符（关闭功能、文本框获得焦点或窗口失焦时）
+    fn release_computer_keyboard(&mut self) {
+        for (_, pitch) in std::mem::take(&mut self.computer_keyboard_held) {
+            self.computer_keyboard_note(pitch, false);
         }
-        let min_start = snapshot.iter().map(|n| n.start).min().unwrap_or(0);
-        let max_start = snapshot.iter().map(|n| n.start).max().unwrap_or(0);
-        let min_duration = snapshot.iter().map(|n| n.duration).min().unwrap_or(0);
-        let max_duration = snapshot.iter().map(|n| n.duration).max().unwrap_or(0);
-        let avg_velocity =
-            snapshot.iter().map(|n| n.velocity as u32).sum::<u32>() / snapshot.len() as u32;
-
-        ui.separator();
-        ui.label("Multi-note summary");
-        ui.label(format!("Start range: {min_start} - {max_start}"));
-        ui.label(format!("Duration range: {min_duration} - {max_duration}"));
-        ui.label(format!("Average velocity: {avg_velocity}"));
     }
 
-    fn ui_toolbar(&mut self, ui: &mut Ui) {
-        ui.horizontal(|ui| {
-            // Time display
-            let seconds_per_beat = 60.0 / self.state.bpm.max(1.0);
-            let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat.max(1) as f32;
-            let _current_tick = (self.current_time / seconds_per_tick) as u64;
-            let total_seconds = self.current_time;
-            let minutes = (total_seconds / 60.0) as u32;
-            let seconds = (total_seconds % 60.0) as u32;
-            let milliseconds = ((total_seconds % 1.0) * 1000.0) as u32;
-            let time_display = format!("{:02}:{:02}.{:03}", minutes, seconds, milliseconds);
-            ui.label(format!("Time: {}", time_display));
-            ui.separator();
-            
-            if ui
-                .button(if self.is_playing {
-                    "⏸ Pause"
-                } else {
-                    "▶ Play"
+    fn handle_shortcuts(&mut self, ctx: &Context) {
+        self.handle_computer_keyboard(ctx);
+        let typing = ctx.wants_keyboard_input();
+        // egui-winit 把 Ctrl/Cmd+C/X/V 转成 Copy/Cut/Paste 事件而不发送按键，这里按对应的按键查找快捷键；
+        // 同一帧里同时收到两者时只执行一次
+        let clipboard_key = |event: &Event| match event {
+            Event::Copy => Some(Key::C),
+            Event::Cut => Some(Key::X),
+            Event::Paste(_) => Some(Key::V),
+            _ => None,
+        };
+        let clipboard_keys: Vec<Key> = ctx.input(|i| i.events.iter().filter_map(clipboard_key).collect());
+        let playing_keys = self.computer_keyboard_input && !typing;
+        if self.system_clipboard && !typing {
+            let pasted = ctx.input(|i| {
+                i.events.iter().find_map(|event| match event {
+                    Event::Paste(text) => ClipboardPayload::from_clipboard_text(text),
+                    _ => None,
                 })
-                .clicked()
-            {
+            });
+            if let Some(payload) = pasted {
+                self.set_clipboard_payload(&payload);
+            }
+        }
+        let actions: Vec<(ShortcutAction, bool)> = ctx.input(|i| {
+            i.events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Key { key, pressed: true, repeat, modifiers, .. } => {
+                        if modifiers.command && clipboard_keys.contains(key) {
+                            return None;
+                        }
+                        // 电脑键盘弹奏时，用作琴键的按键（如 `,`）不再触发快捷键
+                        if playing_keys && !(modifiers.command || modifiers.alt) && is_computer_keyboard_key(*key) {
+                            return None;
+                        }
+                        let action = self.shortcuts.action(*modifiers, *key)?;
+                        // 文本框输入时只响应带 Ctrl/Cmd 的快捷键
+                        (!typing || modifiers.command).then_some((action, *repeat))
+                    }
+                    // 文本框自己处理剪贴板事件
+                    _ if !typing => {
+                        let action = self.shortcuts.action(Modifiers::COMMAND, clipboard_key(event)?)?;
+                        Some((action, false))
+                    }
+                    _ => None,
+                })
+                .collect()
+        });
+        for (action, repeat) in actions {
+            self.run_shortcut(ctx, action, repeat);
+        }
+    }
+
+    /// 执行快捷键对应的操作；`repeat` 表示按住按键产生的自动重复
+    fn run_shortcut(&mut self, ctx: &Context, action: ShortcutAction, repeat: bool) {
+        let nudge = self.snap_ticks().max(1) as i64;
+        match action {
+            ShortcutAction::Copy => self.copy_selection(),
+            ShortcutAction::Cut => self.cut_selection(),
+            ShortcutAction::Paste => {
+                let tick = self.current_tick_position();
+                self.paste_clipboard_at(tick);
+            }
+            ShortcutAction::Duplicate => self.duplicate_selection(),
+            ShortcutAction::Delete => self.delete_selected_notes(),
+            ShortcutAction::Undo => {
+                self.undo();
+            }
+            ShortcutAction::Redo => {
+                self.redo();
+            }
+            ShortcutAction::SelectSamePitch => {
+                if let Some(note) = self.first_selected_note() {
+                    self.select_notes(SelectionQuery::SamePitch(note.key));
+                }
+            }
+            ShortcutAction::SelectInLoop => self.select_notes(SelectionQuery::InLoopRegion),
+            ShortcutAction::LoopToSelection => self.set_loop_to_selection(),
+            ShortcutAction::InvertSelection => self.select_notes(SelectionQuery::Invert),
+            ShortcutAction::Group => {
+                self.group_selected_notes();
+            }
+            ShortcutAction::Ungroup => self.ungroup_selected_notes(),
+            ShortcutAction::Quantize => self.quantize_selected_notes(),
+            ShortcutAction::ZoomToFit => self.zoom_to_fit(),
+            ShortcutAction::ZoomToSelection => self.zoom_to_selection(),
+            ShortcutAction::PlaySelection => self.play_selection(),
+            ShortcutAction::SeekToStart
+            | ShortcutAction::SeekToEnd
+            | ShortcutAction::PreviousBar
+            | ShortcutAction::NextBar
+            | ShortcutAction::BeatEarlier
+            | ShortcutAction::BeatLater
+            | ShortcutAction::PreviousMarker
+            | ShortcutAction::NextMarker => {
+                if let Some(tick) = self.transport_target(action) {
+                    self.seek_to_tick(tick);
+                }
+            }
+            ShortcutAction::TogglePlayback => {
+                if !self.enable_space_playback {
+                    return;
+                }
                 self.is_playing = !self.is_playing;
                 if self.is_playing {
-                    self.last_update = ui.input(|i| i.time);
-                    // Reset last_tick to avoid mass triggering if we jumped
-                    let seconds_per_beat = 60.0 / self.state.bpm;
-                    let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-                    // If we are at 0, set last_tick to 0 to include start notes.
-                    // If we are later, set to just before current to avoid retriggering current tick notes?
-                    // Let's just set it exact.
-                    self.last_tick = (self.current_time / seconds_per_tick) as u64;
-                    // If we just started from 0, last_tick is 0. Our logic in update_sequencer handles 0 specially?
-                    // No, logic says if last_tick == 0, include 0.
-                    // So if we seek to 0 and play, it works.
-                    // If we pause at 0 and play, it re-triggers. That's probably fine.
+                    self.last_update = ctx.input(|i| i.time);
+                    self.last_tick = self.current_tick_position();
+                    self.notify_playback_started();
                 } else {
                     self.stop_playback_backend();
+                    self.notify_playback_stopped();
                 }
                 self.emit_event(EditorEvent::PlaybackStateChanged {
                     is_playing: self.is_playing,
                 });
-                if self.is_playing {
-                    self.notify_playback_started();
-                } else {
-                    self.notify_playback_stopped();
-                }
-            }
-            if ui.button("⏹ Stop").clicked() {
-                self.is_playing = false;
-                self.current_time = 0.0;
-                self.last_tick = 0;
-                self.stop_playback_backend();
-                self.notify_playback_stopped();
-                self.emit_event(EditorEvent::PlaybackStateChanged { is_playing: false });
-                self.emit_transport_event();
             }
-
-            ui.separator();
-
-            if ui
-                .add_enabled(!self.undo_stack.is_empty(), Button::new("↺"))
-                .clicked()
-            {
-                self.undo();
+            ShortcutAction::NudgeEarlier | ShortcutAction::NudgeLater if self.step_input => {
+                self.step_move(action == ShortcutAction::NudgeLater);
             }
-            if ui
-                .add_enabled(!self.redo_stack.is_empty(), Button::new("↻"))
-                .clicked()
-            {
-                self.redo();
-            }
-
-            ui.separator();
-
-            ui.label("Sig:");
-            ui.horizontal(|ui| {
-                let mut numer = self.state.time_signature.0;
-                let mut denom = self.state.time_signature.1;
-                let numer_changed = ui
-                    .add(DragValue::new(&mut numer).speed(0.1).range(1..=32))
-                    .changed();
-                ui.label("/");
-                let denom_changed = ui
-                    .add(DragValue::new(&mut denom).speed(0.1).range(1..=32))
-                    .changed();
-                if numer_changed || denom_changed {
-                    self.set_time_signature(numer, denom);
-                }
-            });
-
-            ui.separator();
+            ShortcutAction::NudgeEarlier => self.nudge_selection(-nudge, 0, repeat),
+            ShortcutAction::NudgeLater => self.nudge_selection(nudge, 0, repeat),
+            ShortcutAction::NudgeEarlierFine => self.nudge_selection(-1, 0, repeat),
+            ShortcutAction::NudgeLaterFine => self.nudge_selection(1, 0, repeat),
+            ShortcutAction::TransposeUp => self.nudge_selection(0, 1, repeat),
+            ShortcutAction::TransposeDown => self.nudge_selection(0, -1, repeat),
+            ShortcutAction::OctaveUp => self.nudge_selection(0, 12, repeat),
+            ShortcutAction::OctaveDown => self.nudge_selection(0, -12, repeat),
+            ShortcutAction::LoopEarlier => self.shift_loop(false),
+            ShortcutAction::LoopLater => self.shift_loop(true),
+            ShortcutAction::LoopLonger => self.scale_loop(true),
+            ShortcutAction::LoopShorter => self.scale_loop(false),
+        }
+    }
 
-            ui.label("BPM:");
-            let mut bpm = self.state.bpm;
-            if ui
-                .add(DragValue::new(&mut bpm).speed(1.0).range(20.0..=400.0))
-                .changed()
-            {
-                self.set_bpm(bpm);
-            }
+    /// 菜单项旁显示的快捷键文字，操作未绑定时为空
+    fn shortcut_text(&self, ctx: &Context, action: ShortcutAction) -> String {
+        self.shortcuts
+            .shortcuts(action)
+            .next()
+            .map(|shortcut| ctx.format_shortcut(&shortcut))
+            .unwrap_or_default()
+    }
 
-            ui.separator();
+    fn delete_note_by_id(&mut self, id: NoteId) {
+        if let Some(idx) = self.note_index_by_id(id) {
+            self.push_undo_snapshot();
+            let removed = self.state.notes.remove(idx);
+            self.sync_sounding_notes();
+            self.emit_note_deleted(removed);
+            self.selected_notes.remove(&removed.id);
+            self.prune_note_groups();
+        }
+    }
 
-            // Display loop status and playback position
-            if self.loop_enabled {
-                ui.horizontal(|ui| {
-                    ui.label("🔁 Loop:");
-                    let seconds_per_beat = 60.0 / self.state.bpm;
-                    let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-                    let loop_start_seconds = self.loop_start_tick as f32 * seconds_per_tick;
-                    let loop_end_seconds = self.loop_end_tick as f32 * seconds_per_tick;
-                    ui.label(format!(
-                        "{:.2}s - {:.2}s",
-                        loop_start_seconds,
-                        loop_end_seconds
-                    ));
+    /// 双击音符：发出 `NoteDoubleClicked`，再按 `note_double_click` 打开属性小窗或删除音符
+    fn handle_note_double_click(&mut self, note_id: NoteId, pointer: Pos2) {
+        // 第二次按下开始的拖动没有移动过，先结束它（撤回按下时的快照），之后的删除才是独立的一步撤销
+        if self.is_dragging_note {
+            self.preview_note_off();
+            self.finalize_note_drag_if_needed();
+            self.reset_note_drag_state();
+        }
+        self.emit_event(EditorEvent::NoteDoubleClicked(note_id));
+        match self.note_double_click {
+            NoteDoubleClickAction::EventOnly => {}
+            NoteDoubleClickAction::Properties => {
+                self.note_properties = self.note_by_id(note_id).map(|draft| NotePropertiesPopup {
+                    note_id,
+                    open_pos: pointer,
+                    draft,
+                    focus_pending: true,
                 });
             }
+            NoteDoubleClickAction::Delete => self.delete_note_by_id(note_id),
+        }
+    }
 
-            ui.horizontal(|ui| {
-                ui.label("Position:");
-                let current_beat = self.current_time * self.state.bpm / 60.0;
-                let current_measure = (current_beat / self.state.time_signature.0 as f32).floor() + 1.0;
-                let beat_in_measure = (current_beat % self.state.time_signature.0 as f32) + 1.0;
-                ui.label(format!(
-                    "{:.2}s ({:.1}:{:.1})",
-                    self.current_time,
-                    current_measure,
-                    beat_in_measure
-                ));
+    /// 音符属性小窗：Enter 或 OK 通过 `edit_note_by_id` 提交草稿（一步撤销），Escape、Cancel 或点击窗外放弃；
+    /// 音符不复存在时直接关闭
+    fn ui_note_properties(&mut self, ctx: &Context) {
+        let Some(mut popup) = self.note_properties.take() else {
+            return;
+        };
+        if self.note_by_id(popup.note_id).is_none() {
+            return;
+        }
+        let naming = self.pitch_naming();
+        let speed = self.snap_ticks().max(1) as f64;
+        let (mut commit, mut cancel) = (false, false);
+        let area = egui::Area::new(egui::Id::new("note_properties_popup"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(popup.open_pos + Vec2::new(8.0, 8.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    let draft = &mut popup.draft;
+                    egui::Grid::new("note_properties_grid").num_columns(2).show(ui, |ui| {
+                        ui.label("Start");
+                        let start = ui.add(musical_time_drag_value(&mut draft.start, &self.state, speed));
+                        if std::mem::take(&mut popup.focus_pending) {
+                            start.request_focus();
+                        }
+                        ui.end_row();
+                        ui.label("Duration");
+                        ui.add(DragValue::new(&mut draft.duration).speed(speed).range(1..=u64::MAX));
+                        ui.end_row();
+                        ui.label("Pitch");
+                        ui.add(
+                            DragValue::new(&mut draft.key)
+                                .range(0..=127)
+                                .custom_formatter(|v, _| tooltip::format_pitch(v as u8, naming)),
+                        );
+                        ui.end_row();
+                        ui.label("Velocity");
+                        ui.add(DragValue::new(&mut draft.velocity).range(1..=127));
+                        ui.end_row();
+                    });
+                    ui.horizontal(|ui| {
+                        commit |= ui.button("OK").clicked();
+                        cancel |= ui.button("Cancel").clicked();
+                    });
+                });
             });
-
-            ui.separator();
-
-            if ui.button("⚙ Playback Settings").clicked() {
-                self.show_playback_settings = true;
-            }
+        // 字段先处理 Enter/Escape（写入正在输入的数值），再由小窗消耗，不再触发快捷键
+        commit |= ctx.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Enter));
+        cancel |= ctx.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Escape));
+        let clicked_outside = ctx.input(|i| {
+            (i.pointer.primary_clicked() || i.pointer.secondary_clicked())
+                && i.pointer.interact_pos().is_some_and(|pos| {
+                    !area.response.rect.contains(pos) && pos.distance(popup.open_pos) >= 5.0
+                })
         });
+        if commit {
+            let draft = popup.draft;
+            let key = self.constrain_key(draft.key);
+            if self.note_by_id(popup.note_id).is_some_and(|note| note != draft) {
+                self.edit_note_by_id(popup.note_id, |n| {
+                    n.start = draft.start;
+                    n.duration = draft.duration;
+                    n.key = key.unwrap_or(n.key);
+                    n.velocity = draft.velocity;
+                });
+            }
+        } else if !cancel && !clicked_outside {
+            self.note_properties = Some(popup);
+        }
     }
 
-    fn ui_piano_roll(&mut self, ui: &mut Ui) {
-        let key_width = 60.0;
-        let timeline_height = 30.0;
-
-        // Piano Roll ScrollArea
-        // We disable built-in scrolling since we handle it manually via middle mouse
-        ScrollArea::both()
-            .auto_shrink([false, false])
-            .enable_scrolling(false) // Disable wheel scroll
-            .show(ui, |ui| {
-                let available_size = ui.available_size();
-                let (rect, response) =
-                    ui.allocate_exact_size(available_size, Sense::click_and_drag());
-
-                // Handle Zoom (Ctrl/Alt + Scroll)
-                let scroll_delta = ui.input(|i| i.raw_scroll_delta);
-                if scroll_delta != Vec2::ZERO {
-                    if ui.input(|i| i.modifiers.ctrl) {
-                        // Zoom X (Horizontal) around mouse pointer
-                        if scroll_delta.y != 0.0 {
-                            let old_zoom = self.zoom_x;
-                            let new_zoom = (self.zoom_x
-                                * if scroll_delta.y > 0.0 { 1.1 } else { 0.9 })
-                            .clamp(10.0, 500.0);
-
-                            // Calculate mouse position relative to timeline start (in beats)
-                            // mouse_x = start_x + beats * zoom + scroll
-                            // beats = (mouse_x - start_x - scroll) / zoom
-                            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
-                                let rel_x = mouse_pos.x - (rect.min.x + key_width);
-                                let beats_at_mouse = (rel_x - self.manual_scroll_x) / old_zoom;
-
-                                // We want beats_at_mouse to stay at rel_x after zoom
-                                // rel_x = beats_at_mouse * new_zoom + new_scroll
-                                // new_scroll = rel_x - beats_at_mouse * new_zoom
-                                self.manual_scroll_x = rel_x - beats_at_mouse * new_zoom;
-                            }
-
-                            self.zoom_x = new_zoom;
-                        }
-                    } else if ui.input(|i| i.modifiers.alt) {
-                        // Zoom Y (Vertical) around mouse pointer
-                        if scroll_delta.y != 0.0 {
-                            let old_zoom = self.zoom_y;
-                            let new_zoom = (self.zoom_y
-                                * if scroll_delta.y > 0.0 { 1.1 } else { 0.9 })
-                            .clamp(5.0, 50.0);
+    /// 点击分组成员时作用于整个分组，按住 Alt 时只作用于该音符
+    fn click_targets(&self, note_id: NoteId, modifiers: Modifiers) -> Vec<NoteId> {
+        match self.state.group_of(note_id) {
+            Some(group) if !modifiers.alt => group.note_ids.clone(),
+            _ => vec![note_id],
+        }
+    }
 
-                            if let Some(mouse_pos) = ui.input(|i| i.pointer.hover_pos()) {
-                                let rel_y = mouse_pos.y - (rect.min.y + timeline_height);
-                                // keys_from_top = (rel_y - scroll) / zoom
-                                // Note: Our y calc is more complex: note_to_y = (127 - note) * zoom
-                                // But linear mapping is: y = value * zoom + scroll.
-                                // So logic is same.
-                                let value_at_mouse = (rel_y - self.manual_scroll_y) / old_zoom;
-                                self.manual_scroll_y = rel_y - value_at_mouse * new_zoom;
-                            }
+    fn handle_note_click(&mut self, note_id: NoteId, modifiers: Modifiers) {
+        let targets = self.click_targets(note_id, modifiers);
+        if targets.len() == 1 {
+            if modifiers.command {
+                self.toggle_selection(note_id);
+            } else if modifiers.shift {
+                self.extend_selection(note_id);
+            } else {
+                self.set_single_selection(note_id);
+            }
+            return;
+        }
+        let prev = self.selected_notes.clone();
+        if modifiers.command && self.selected_notes.contains(&note_id) {
+            for id in &targets {
+                self.selected_notes.remove(id);
+            }
+        } else {
+            if !modifiers.command && !modifiers.shift {
+                self.selected_notes.clear();
+            }
+            self.selected_notes.extend(targets);
+        }
+        self.notify_selection_changed(prev);
+    }
 
-                            self.zoom_y = new_zoom;
-                        }
-                    }
+    fn prepare_selection_for_drag(&mut self, note_id: NoteId, modifiers: Modifiers) {
+        let targets = self.click_targets(note_id, modifiers);
+        let prev = self.selected_notes.clone();
+        if modifiers.command {
+            if self.selected_notes.contains(&note_id) {
+                for id in &targets {
+                    self.selected_notes.remove(id);
                 }
+            } else {
+                self.selected_notes.extend(targets);
+            }
+        } else if modifiers.shift {
+            self.selected_notes.extend(targets);
+        } else if !self.selected_notes.contains(&note_id) {
+            self.selected_notes.clear();
+            self.selected_notes.extend(targets);
+        }
+        self.notify_selection_changed(prev);
+    }
 
-                // Handle Middle Mouse Pan
-                // Note: interact_pos() returns None if not interacting with specific widget?
-                // For global pointer, use pointer.interact_pos() or pointer.pos().
-                if ui.input(|i| i.pointer.middle_down()) {
-                    if self.is_panning {
-                        if let Some(start) = self.pan_start {
-                            // use pointer.pos() for raw screen coordinates, consistent for dragging
-                            if let Some(curr) = ui.input(|i| i.pointer.hover_pos()) {
-                                let delta = curr - start;
-                                // Apply delta to scroll.
-                                // ui.scroll_with_delta(delta); // SCROLLAREA LOGIC REMOVED
-                                // Manually update scroll offset
-                                self.manual_scroll_x += delta.x;
-                                self.manual_scroll_y += delta.y;
+    fn resolve_drag_action(&self, pointer: Pos2, rect: Rect) -> DragAction {
+        const HANDLE_WIDTH: f32 = 6.0;
+        // 鼓映射模式下音符宽度与时值无关，没有调整时值的手柄
+        if self.drum_map.is_some() {
+            return DragAction::Move;
+        }
+        let left_handle =
+            Rect::from_min_max(rect.min, Pos2::new(rect.min.x + HANDLE_WIDTH, rect.max.y));
+        let right_handle =
+            Rect::from_min_max(Pos2::new(rect.max.x - HANDLE_WIDTH, rect.min.y), rect.max);
+        if left_handle.contains(pointer) {
+            DragAction::ResizeStart
+        } else if right_handle.contains(pointer) {
+            DragAction::ResizeEnd
+        } else {
+            DragAction::Move
+        }
+    }
 
-                                // Update start for continuous delta
-                                self.pan_start = Some(curr);
-                            }
-                        }
-                    } else {
-                        self.is_panning = true;
-                        self.pan_start = ui.input(|i| i.pointer.hover_pos());
-                    }
-                } else {
-                    self.is_panning = false;
-                    self.pan_start = None;
-                }
+    fn begin_note_drag(
+        &mut self,
+        anchor: NoteId,
+        pointer: Pos2,
+        pointer_tick: i64,
+        action: DragAction,
+    ) {
+        if self.selected_notes.is_empty() {
+            self.set_single_selection(anchor);
+        }
+        self.push_undo_snapshot();
+        self.is_dragging_note = true;
+        self.is_resizing_note = matches!(action, DragAction::ResizeStart | DragAction::ResizeEnd);
+        self.drag_action = action;
+        self.drag_start_pos = Some(pointer);
+        self.drag_primary_anchor = Some(anchor);
+        self.drag_original_notes = self
+            .selected_notes
+            .iter()
+            .filter_map(|id| {
+                self.note_index_by_id(*id)
+                    .map(|idx| (*id, self.state.notes[idx]))
+            })
+            .collect();
+        if let Some(note) = self.note_by_id(anchor) {
+            self.drag_original_start = Some(note.start);
+            self.drag_original_duration = Some(note.duration);
+            self.drag_original_key = Some(note.key);
+        }
+        self.drag_pointer_offset_ticks =
+            Some(pointer_tick - self.drag_original_start.unwrap_or(0) as i64);
+        self.drag_changed_note = false;
+        if matches!(action, DragAction::Move | DragAction::None) {
+            if let Some(note) = self.note_by_id(anchor) {
+                self.preview_note_on(note.key, 100);
+            }
+        }
+    }
 
-                // Apply Scroll Constraints
-                // Limit horizontal scroll: can't scroll past 0 (can't see negative time)
-                // Note: manual_scroll_x is an offset. Content moves right when scroll_x is positive?
-                // Wait, usually scroll is negative offset to move content left (to see right side).
-                // Let's assume manual_scroll_x <= 0 means we have scrolled right.
-                // If manual_scroll_x > 0, we see negative time gap.
-                // So max x is 0.
+    fn reset_note_drag_state(&mut self) {
+        self.is_dragging_note = false;
+        self.is_resizing_note = false;
+        self.drag_action = DragAction::None;
+        self.drag_start_pos = None;
+        self.drag_original_start = None;
+        self.drag_original_duration = None;
+        self.drag_original_key = None;
+        self.drag_pointer_offset_ticks = None;
+        self.drag_original_notes.clear();
+        self.drag_primary_anchor = None;
+        self.drag_duplicate = DuplicateDrag::Off;
+    }
 
-                if self.manual_scroll_x > 0.0 {
-                    self.manual_scroll_x = 0.0;
-                }
+    /// 复制拖动第一次移动时调用：在原位插入所选音符的副本，之后拖动的是副本，原音符保持不动
+    fn copy_dragged_notes(&mut self) {
+        let previous = std::mem::take(&mut self.selected_notes);
+        for (id, original) in self.drag_original_notes.iter_mut() {
+            let copy = Note { id: NoteId::next(), ..*original };
+            self.state.notes.push(copy);
+            self.selected_notes.insert(copy.id);
+            if self.drag_primary_anchor == Some(*id) {
+                self.drag_primary_anchor = Some(copy.id);
+            }
+            *id = copy.id;
+            *original = copy;
+        }
+        self.sort_notes();
+        self.drag_duplicate = DuplicateDrag::Copied;
+        self.notify_selection_changed(previous);
+    }
 
-                // Limit vertical scroll
-                let total_content_height = 128.0 * self.zoom_y;
-                let view_height = rect.height() - timeline_height;
-                // If content < view, pin to top.
-                // If content > view, allow scrolling down to (view - content).
+    /// 放弃当前的移动拖拽，把原始音符作为 `NoteDragPayload` 交给 egui 的 DragAndDrop
+    fn begin_external_note_drag(&mut self, ctx: &Context) {
+        let originals: Vec<Note> = self.drag_original_notes.iter().map(|(_, n)| *n).collect();
+        let anchor_start = self.drag_original_start.unwrap_or(0);
+        let anchor_tick = (anchor_start as i64 + self.drag_pointer_offset_ticks.unwrap_or(0)).max(0) as u64;
+        let anchor_key = self.drag_original_key.unwrap_or(60);
+        // Revert the in-editor move along with the snapshot pushed in begin_note_drag
+        self.revert_undo_snapshot();
+        self.sync_sounding_notes();
+        self.preview_note_off();
+        self.drag_changed_note = false;
+        self.reset_note_drag_state();
+        if !originals.is_empty() {
+            DragAndDrop::set_payload(
+                ctx,
+                NoteDragPayload::from_notes(&originals, self.state.ticks_per_beat, anchor_tick, anchor_key),
+            );
+        }
+    }
 
-                let min_scroll_y = if total_content_height > view_height {
-                    view_height - total_content_height
-                } else {
-                    0.0
-                };
+    fn create_note_at_pointer<F, G>(&mut self, pointer: Pos2, to_tick: F, to_key: G)
+    where
+        F: Fn(Pos2) -> i64,
+        G: Fn(Pos2) -> u8,
+    {
+        let start_tick = to_tick(pointer).max(0);
+        let snapped_start = self.snap_tick(start_tick, None, false);
+        if let Some((lo, hi)) = self.edit_bounds {
+            if snapped_start < lo || snapped_start >= hi {
+                self.show_toast("Outside the editable region");
+                return;
+            }
+        }
+        let mut default_duration = self.new_note_length();
+        if let Some((_, hi)) = self.edit_bounds {
+            default_duration = default_duration.min(hi - snapped_start);
+        }
+        let Some(key) = self.constrain_key(to_key(pointer)) else {
+            self.show_toast("No allowed pitch near this row");
+            return;
+        };
+        let note = Note {
+            track: self.active_track,
+            ..Note::new(snapped_start, default_duration, key, self.default_velocity.clamp(1, 127))
+        };
+        self.push_undo_snapshot();
+        self.state.notes.push(note);
+        self.sort_notes();
+        self.sync_sounding_notes();
+        self.emit_note_added(note);
+        self.set_single_selection(note.id);
+        self.drag_primary_anchor = Some(note.id);
+        self.drag_original_notes = vec![(note.id, note)];
+        self.drag_original_start = Some(note.start);
+        self.drag_original_duration = Some(note.duration);
+        self.drag_original_key = Some(note.key);
+        self.drag_pointer_offset_ticks = Some(0);
+        self.is_dragging_note = true;
+        self.drag_action = DragAction::Create;
+        self.drag_start_pos = Some(pointer);
+        self.preview_note_on(note.key, note.velocity);
+    }
 
-                // Clamp scroll y between min_scroll_y and 0.
-                self.manual_scroll_y = self.manual_scroll_y.clamp(min_scroll_y, 0.0);
+    /// 按 `note_length` 得出新建音符的时值（至少 1 tick）
+    fn new_note_length(&self) -> u64 {
+        let snap = match self.snap_ticks() {
+            0 => self.state.ticks_per_beat as u64,
+            snap => snap,
+        };
+        match self.note_length {
+            NoteLength::Snap => snap,
+            NoteLength::Fixed(resolution, modifier) => resolution.ticks(self.state.ticks_per_beat, modifier),
+            NoteLength::LastUsed => self.last_note_length.unwrap_or(snap),
+        }
+        .max(1)
+    }
 
-                let mut pointer_consumed = false;
-                let note_offset_x = rect.min.x + key_width + self.manual_scroll_x;
+    /// 绘制、擦除或切片工具按下：记录一次撤销快照并处理按下处的格子；切点按吸附取整，按住 Alt 不吸附
+    fn begin_note_stroke(&mut self, tick: i64, key: u8, modifiers: Modifiers) {
+        let key = match self.note_tool {
+            NoteTool::Draw => match self.constrain_key(key) {
+                Some(key) => key,
+                None => {
+                    self.show_toast("No allowed pitch near this row");
+                    return;
+                }
+            },
+            _ => key,
+        };
+        self.push_undo_snapshot();
+        self.note_stroke = Some(NoteStroke {
+            key,
+            last_tick: tick,
+            cut: self.snap_tick(tick, None, modifiers.alt),
+            added: Vec::new(),
+            removed: Vec::new(),
+            sliced: Vec::new(),
+        });
+        if self.note_tool == NoteTool::Draw {
+            self.preview_note_on(key, self.default_velocity.clamp(1, 127));
+        }
+        self.continue_note_stroke(tick, key);
+    }
 
-                // Coordinate transforms (defined early for use in interactions)
-                let tick_to_x = |tick: u64, zoom_x: f32, ticks_per_beat: u16| -> f32 {
-                    (tick as f32 / ticks_per_beat as f32) * zoom_x
+    /// 处理上一步与 `tick` 之间经过的格子：绘制工具在空格写入音符，擦除工具删除 `key` 上经过的音符，
+    /// 切片工具在切点切开起点音高与 `key` 之间的音符（切点须在音符内部，两半都至少 1 tick）
+    fn continue_note_stroke(&mut self, tick: i64, key: u8) {
+        let Some(mut stroke) = self.note_stroke.take() else {
+            return;
+        };
+        let from = stroke.last_tick.min(tick).max(0) as u64;
+        let to = stroke.last_tick.max(tick).max(0) as u64;
+        stroke.last_tick = tick;
+        let multi_track = self.state.is_multi_track();
+        let track = self.active_track;
+        match self.note_tool {
+            NoteTool::Draw => {
+                let cell = match self.snap_ticks() {
+                    0 => self.state.ticks_per_beat as u64,
+                    snap => snap,
                 };
-
-                // Handle timeline interactions (playhead seek and loop editing)
-                if let Some(pointer) = response.interact_pointer_pos() {
-                    let in_timeline = pointer.y < rect.min.y + timeline_height
-                        && pointer.x >= rect.min.x + key_width;
-                    
-                    if in_timeline {
-                        let modifiers = ui.input(|i| i.modifiers);
-                        let is_shift = modifiers.shift;
-                        let disable_snap = modifiers.alt;
-                        
-                        // Convert pointer position to tick
-                        let mut x = pointer.x - (rect.min.x + key_width);
-                        x = (x - self.manual_scroll_x).max(0.0);
-                        let beats = x / self.zoom_x;
-                        let seconds_per_beat = 60.0 / self.state.bpm;
-                        let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-                        let tick = (beats * seconds_per_beat / seconds_per_tick) as i64;
-                        let snapped_tick = self.snap_tick(tick, None, disable_snap);
-                        
-                        // Handle right-click: Shift+右键删除循环区域
-                        if response.clicked_by(PointerButton::Secondary) && is_shift {
-                            // 检查循环区域是否启用
-                            if self.loop_enabled {
-                                // 将点击位置转换为 tick（使用已计算的 snapped_tick）
-                                let click_tick = snapped_tick as u64;
-                                
-                                // 判断点击位置是否在循环区域内
-                                if click_tick >= self.loop_start_tick && click_tick <= self.loop_end_tick {
-                                    // 删除循环区域
-                                    self.loop_enabled = false;
-                                    pointer_consumed = true;
-                                }
-                            }
-                        }
-                        
-                        // Handle drag start
-                        if ui.input(|i| i.pointer.primary_pressed()) && !self.is_dragging_note {
-                            if is_shift {
-                                // Shift + 左键：开始循环边界编辑
-                                if self.loop_enabled {
-                                    // Determine edit mode based on pointer position relative to loop region
-                                    let loop_start_x = note_offset_x
-                                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
-                                    let loop_end_x = note_offset_x
-                                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
-                                    
-                                    let loop_width = loop_end_x - loop_start_x;
-                                    let relative_x = pointer.x - loop_start_x;
-                                    
-                                    let edit_mode = if loop_width > 0.0 {
-                                        if relative_x < loop_width / 3.0 {
-                                            LoopEditMode::Start
-                                        } else if relative_x > loop_width * 2.0 / 3.0 {
-                                            LoopEditMode::End
-                                        } else {
-                                            LoopEditMode::Move
-                                        }
-                                    } else {
-                                        LoopEditMode::Move
-                                    };
-                                    
-                                    self.drag_action = DragAction::LoopEdit;
-                                    self.loop_edit_mode = Some(edit_mode);
-                                    self.drag_original_loop_start = Some(self.loop_start_tick);
-                                    self.drag_original_loop_end = Some(self.loop_end_tick);
-                                    self.drag_start_pos = Some(pointer);
-                                    pointer_consumed = true;
-                                } else {
-                                    // Loop not enabled: enable it and set initial region
-                                    self.loop_enabled = true;
-                                    self.drag_action = DragAction::LoopEdit;
-                                    self.loop_edit_mode = Some(LoopEditMode::Move);
-                                    self.loop_start_tick = snapped_tick;
-                                    self.loop_end_tick = (snapped_tick + 1920).max(snapped_tick + 1);
-                                    self.drag_original_loop_start = Some(self.loop_start_tick);
-                                    self.drag_original_loop_end = Some(self.loop_end_tick);
-                                    self.drag_start_pos = Some(pointer);
-                                    pointer_consumed = true;
-                                }
-                            } else {
-                                // 单独左键：开始播放位置调整
-                                self.drag_action = DragAction::PlayheadSeek;
-                                self.current_time = snapped_tick as f32 * seconds_per_tick;
-                                self.last_tick = snapped_tick;
-                                self.is_dragging_note = false;
-                                self.emit_transport_event();
-                                pointer_consumed = true;
-                            }
-                        }
-                        
-                        // Handle drag update
-                        if ui.input(|i| i.pointer.primary_down()) {
-                            match self.drag_action {
-                                DragAction::LoopEdit => {
-                                    if let Some(edit_mode) = self.loop_edit_mode {
-                                        match edit_mode {
-                                            LoopEditMode::Start => {
-                                                self.loop_start_tick = snapped_tick.min(self.loop_end_tick.saturating_sub(1));
-                                            }
-                                            LoopEditMode::End => {
-                                                self.loop_end_tick = snapped_tick.max(self.loop_start_tick + 1);
-                                            }
-                                            LoopEditMode::Move => {
-                                                if let (Some(original_start), Some(original_end), Some(start_pos)) = 
-                                                    (self.drag_original_loop_start, self.drag_original_loop_end, self.drag_start_pos) {
-                                                    let delta_x = pointer.x - start_pos.x;
-                                                    let delta_beats = delta_x / self.zoom_x;
-                                                    let delta_ticks = (delta_beats * seconds_per_beat / seconds_per_tick) as i64;
-                                                    
-                                                    let loop_duration = original_end - original_start;
-                                                    let new_start_raw = (original_start as i64 + delta_ticks).max(0) as i64;
-                                                    let new_start = self.snap_tick(new_start_raw, None, disable_snap);
-                                                    let new_end = new_start + loop_duration;
-                                                    
-                                                    self.loop_start_tick = new_start;
-                                                    self.loop_end_tick = new_end;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    pointer_consumed = true;
-                                }
-                                DragAction::PlayheadSeek => {
-                                    self.current_time = snapped_tick as f32 * seconds_per_tick;
-                                    self.last_tick = snapped_tick;
-                                    self.emit_transport_event();
-                                    pointer_consumed = true;
-                                }
-                                _ => {}
-                            }
-                        }
-                        
-                        // Handle drag end
-                        if ui.input(|i| i.pointer.primary_released()) {
-                            if matches!(self.drag_action, DragAction::LoopEdit | DragAction::PlayheadSeek) {
-                                self.drag_action = DragAction::None;
-                                self.loop_edit_mode = None;
-                                self.drag_original_loop_start = None;
-                                self.drag_original_loop_end = None;
-                                self.drag_start_pos = None;
-                            }
-                        }
-                        
-                        // Update cursor based on hover state
-                        if !self.is_dragging_note {
-                            if is_shift {
-                                if self.loop_enabled {
-                                    let loop_start_x = note_offset_x
-                                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
-                                    let loop_end_x = note_offset_x
-                                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
-                                    
-                                    let loop_width = loop_end_x - loop_start_x;
-                                    let relative_x = pointer.x - loop_start_x;
-                                    
-                                    if loop_width > 0.0 {
-                                        if relative_x < loop_width / 3.0 {
-                                            ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
-                                        } else if relative_x > loop_width * 2.0 / 3.0 {
-                                            ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
-                                        } else {
-                                            ui.ctx().set_cursor_icon(CursorIcon::Grab);
-                                        }
-                                    } else {
-                                        ui.ctx().set_cursor_icon(CursorIcon::Grab);
-                                    }
-                                } else {
-                                    ui.ctx().set_cursor_icon(CursorIcon::Grab);
-                                }
-                            } else {
-                                ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-                            }
-                        }
+                let (lo, hi) = self.edit_bounds.unwrap_or((0, u64::MAX));
+                let velocity = self.default_velocity.clamp(1, 127);
+                let mut start = from / cell * cell;
+                while start <= to {
+                    let end = start.saturating_add(cell).min(hi);
+                    let occupied = self.state.notes.iter().any(|n| {
+                        n.key == stroke.key
+                            && (!multi_track || n.track == track)
+                            && n.start < end
+                            && n.start + n.duration > start
+                    });
+                    if start >= lo && start < end && !occupied {
+                        let note = Note { track, ..Note::new(start, end - start, stroke.key, velocity) };
+                        self.state.notes.push(note);
+                        stroke.added.push(note);
+                    }
+                    start += cell;
+                }
+                self.sort_notes();
+            }
+            NoteTool::Erase => {
+                let before = self.state.notes.len();
+                self.state.notes.retain(|n| {
+                    let hit = n.key == key
+                        && (!multi_track || n.track == track)
+                        && n.start <= to
+                        && n.start + n.duration > from;
+                    if hit {
+                        stroke.removed.push(*n);
+                    }
+                    !hit
+                });
+                if self.state.notes.len() != before {
+                    self.sync_sounding_notes();
+                }
+            }
+            NoteTool::Slice => {
+                let cut = stroke.cut;
+                let keys = stroke.key.min(key)..=stroke.key.max(key);
+                let in_bounds = self.edit_bounds.is_none_or(|(lo, hi)| cut >= lo && cut < hi);
+                let mut halves = Vec::new();
+                for note in self.state.notes.iter_mut() {
+                    if in_bounds
+                        && keys.contains(&note.key)
+                        && (!multi_track || note.track == track)
+                        && note.start < cut
+                        && cut < note.start + note.duration
+                    {
+                        let half = Note { id: NoteId::next(), start: cut, duration: note.start + note.duration - cut, ..*note };
+                        stroke.sliced.push((*note, half.id));
+                        note.duration = cut - note.start;
+                        halves.push(half);
                     }
                 }
+                if !halves.is_empty() {
+                    stroke.added.extend_from_slice(&halves);
+                    self.state.notes.extend(halves);
+                    self.sort_notes();
+                    self.sync_sounding_notes();
+                }
+            }
+            NoteTool::Select => {}
+        }
+        self.note_stroke = Some(stroke);
+    }
 
-                // Coordinate transforms
-                let time_to_x = |time: f32, zoom_x: f32| -> f32 {
-                    // time in beats
-                    time * zoom_x
-                };
+    /// 结束绘制或擦除拖动：成批发出增删事件，没有变化时不记录撤销
+    fn finish_note_stroke(&mut self) {
+        let Some(stroke) = self.note_stroke.take() else {
+            return;
+        };
+        self.preview_note_off();
+        if stroke.added.is_empty() && stroke.removed.is_empty() && stroke.sliced.is_empty() {
+            self.discard_undo_snapshot();
+            return;
+        }
+        self.sync_sounding_notes();
+        let previous = self.selected_notes.clone();
+        for note in &stroke.removed {
+            self.selected_notes.remove(&note.id);
+        }
+        // 切开选中的音符时两半都保持选中
+        for (before, half) in &stroke.sliced {
+            if previous.contains(&before.id) {
+                self.selected_notes.insert(*half);
+            }
+        }
+        self.notify_selection_changed(previous);
+        if !stroke.removed.is_empty() {
+            self.prune_note_groups();
+        }
+        for note in stroke.removed {
+            self.emit_note_deleted(note);
+        }
+        for (before, _) in stroke.sliced {
+            if let Some(after) = self.note_by_id(before.id) {
+                self.emit_note_updated(before, after);
+            }
+        }
+        for note in stroke.added {
+            self.emit_note_added(note);
+        }
+    }
 
-                let tick_to_x = |tick: u64, zoom_x: f32, ticks_per_beat: u16| -> f32 {
-                    (tick as f32 / ticks_per_beat as f32) * zoom_x
+    fn update_note_drag<F, G>(&mut self, pointer: Pos2, to_tick: F, to_key: G, modifiers: Modifiers)
+    where
+        F: Fn(Pos2) -> i64,
+        G: Fn(Pos2) -> u8,
+    {
+        let originals_snapshot = self.drag_original_notes.clone();
+        let pointer_tick = to_tick(pointer);
+        // Alt 属于复制拖动的修饰键时不再表示关闭吸附
+        let disable_snap =
+            modifiers.alt && !(self.drag_duplicate != DuplicateDrag::Off && self.duplicate_drag_modifiers.alt);
+        match self.drag_action {
+            DragAction::Move | DragAction::None => {
+                let key = to_key(pointer);
+                let anchor_id = match self.drag_primary_anchor {
+                    Some(id) => id,
+                    None => return,
                 };
-
-                let note_to_y = |note: u8, zoom_y: f32| -> f32 {
-                    // High notes at top, low notes at bottom.
-                    (127 - note) as f32 * zoom_y
+                let anchor_original = match originals_snapshot
+                    .iter()
+                    .find(|(id, _)| *id == anchor_id)
+                    .map(|(_, note)| *note)
+                {
+                    Some(note) => note,
+                    None => return,
                 };
-
-                let painter = ui.painter_at(rect);
-                let grid_top = rect.min.y + timeline_height;
-                let grid_bottom = rect.max.y;
-                let measure_line_color = Color32::from_rgb(210, 210, 210);
-                let beat_line_color = Color32::from_rgb(140, 140, 140);
-                let subdivision_color = Color32::from_rgb(90, 90, 90);
-                let horizontal_line_color = Color32::from_rgb(90, 90, 90);
-                let separator_color = Color32::from_rgb(130, 130, 130);
-
-                // Draw Vertical Grid (Beats / Measures / Subdivisions)
-                let tpb = self.state.ticks_per_beat.max(1) as u64;
-                let denom = self.state.time_signature.1.max(1) as u64;
-                let numer = self.state.time_signature.0.max(1) as u64;
-                let ticks_per_measure = (tpb * numer * 4).saturating_div(denom).max(tpb);
-
-                let visible_beats_start = (-self.manual_scroll_x / self.zoom_x).floor();
-                let visible_beats_end = visible_beats_start + (rect.width() / self.zoom_x) + 2.0;
-                let mut start_tick = (visible_beats_start * tpb as f32).floor() as i64;
-                if start_tick < 0 {
-                    start_tick = 0;
+                let offset = self.drag_pointer_offset_ticks.unwrap_or(0);
+                let snapped = self.snap_tick(
+                    pointer_tick - offset,
+                    Some(anchor_original.start),
+                    disable_snap,
+                );
+                let mut delta = snapped as i64 - anchor_original.start as i64;
+                if let Some((lo, hi)) = self.edit_bounds {
+                    let min_start = originals_snapshot.iter().map(|(_, n)| n.start).min().unwrap_or(0) as i64;
+                    let max_end = originals_snapshot
+                        .iter()
+                        .map(|(_, n)| n.start + n.duration)
+                        .max()
+                        .unwrap_or(0) as i64;
+                    let min_delta = lo as i64 - min_start;
+                    delta = delta.min(hi as i64 - max_end).max(min_delta);
+                }
+                if self.drag_duplicate == DuplicateDrag::Armed {
+                    let anchor_key = self.constrain_key(self.dragged_key(
+                        anchor_original.key,
+                        anchor_original.key,
+                        key,
+                        disable_snap,
+                    ));
+                    if delta == 0 && anchor_key.is_none_or(|k| k == anchor_original.key) {
+                        return;
+                    }
+                    self.copy_dragged_notes();
+                    self.update_note_drag(pointer, to_tick, to_key, modifiers);
+                    return;
                 }
-                let end_tick = (visible_beats_end * tpb as f32).ceil() as i64;
-
-                let subdivision = if self.zoom_x >= 220.0 {
-                    8
-                } else if self.zoom_x >= 90.0 {
-                    4
-                } else if self.zoom_x >= 45.0 {
-                    2
-                } else {
-                    1
-                };
-                let tick_step = (tpb / subdivision).max(1);
-
-                let mut tick = (start_tick / tick_step as i64) * tick_step as i64;
-                if tick < 0 {
-                    tick = 0;
+                for (id, original) in &originals_snapshot {
+                    let mut preview = None;
+                    let allowed_key =
+                        self.constrain_key(self.dragged_key(original.key, anchor_original.key, key, disable_snap));
+                    if let Some(note) = self.note_mut_by_id(*id) {
+                        let new_start = (original.start as i64 + delta).max(0) as u64;
+                        let new_key = allowed_key.unwrap_or(note.key);
+                        let should_preview = note.key != new_key && *id == anchor_id;
+                        if note.start != new_start || note.key != new_key {
+                            note.start = new_start;
+                            note.key = new_key;
+                            self.drag_changed_note = true;
+                        }
+                        if should_preview {
+                            preview = Some(new_key);
+                        }
+                    }
+                    if let Some(key) = preview {
+                        self.preview_note_on(key, 100);
+                    }
                 }
-
-                while tick <= end_tick {
-                    let x = note_offset_x + (tick as f32 / tpb as f32) * self.zoom_x;
-                    if x >= rect.min.x && x <= rect.max.x {
-                        if tick as u64 % ticks_per_measure == 0 {
-                            painter.line_segment(
-                                [Pos2::new(x, grid_top), Pos2::new(x, grid_bottom)],
-                                Stroke::new(1.0, measure_line_color),
-                            );
-                        } else if tick as u64 % tpb == 0 {
-                            painter.line_segment(
-                                [Pos2::new(x, grid_top), Pos2::new(x, grid_bottom)],
-                                Stroke::new(1.0, beat_line_color),
-                            );
-                        } else {
-                            Self::draw_dashed_vertical_line(
-                                &painter,
-                                x,
-                                grid_top,
-                                grid_bottom,
-                                Stroke::new(1.0, subdivision_color),
-                            );
+                self.sort_notes();
+            }
+            DragAction::ResizeStart => {
+                if let Some(anchor_id) = self.drag_primary_anchor {
+                    if let Some(original) = originals_snapshot
+                        .iter()
+                        .find(|(id, _)| *id == anchor_id)
+                        .map(|(_, note)| *note)
+                    {
+                        let snapped =
+                            self.snap_tick(pointer_tick, Some(original.start), disable_snap);
+                        let end = original.start + original.duration;
+                        let lower = self.edit_bounds.map_or(0, |(lo, _)| lo);
+                        let new_start = snapped.max(lower).min(end.saturating_sub(1));
+                        if let Some(note) = self.note_mut_by_id(anchor_id) {
+                            if new_start != note.start
+                                || note.duration != end.saturating_sub(new_start)
+                            {
+                                note.start = new_start;
+                                note.duration = end.saturating_sub(new_start).max(1);
+                                self.drag_changed_note = true;
+                            }
                         }
                     }
-                    tick += tick_step as i64;
                 }
-
-                // Draw Grid (Horizontal/Notes)
-                for i in 0..=127 {
-                    let y = rect.min.y
-                        + timeline_height
-                        + note_to_y((127 - i) as u8, self.zoom_y)
-                        + self.manual_scroll_y;
-
-                    // Only draw if visible (and maybe clip)
-                    if y > rect.min.y + timeline_height && y < rect.max.y {
-                        painter.line_segment(
-                            [
-                                Pos2::new(rect.min.x + key_width, y),
-                                Pos2::new(rect.max.x, y),
-                            ],
-                            Stroke::new(1.0, horizontal_line_color),
+            }
+            DragAction::ResizeEnd => {
+                if let Some(anchor_id) = self.drag_primary_anchor {
+                    if let Some(original) = originals_snapshot
+                        .iter()
+                        .find(|(id, _)| *id == anchor_id)
+                        .map(|(_, note)| *note)
+                    {
+                        let snapped = self.snap_tick(
+                            pointer_tick,
+                            Some(original.start + original.duration),
+                            disable_snap,
                         );
+                        let upper = self.edit_bounds.map_or(u64::MAX, |(_, hi)| hi);
+                        if let Some(note) = self.note_mut_by_id(anchor_id) {
+                            let new_end = snapped.min(upper).max(note.start + 1);
+                            if new_end != note.start + note.duration {
+                                note.duration = new_end.saturating_sub(note.start).max(1);
+                                self.drag_changed_note = true;
+                            }
+                        }
                     }
                 }
-
-                // Handle Note Off if we released mouse anywhere
-                if ui.input(|i| i.pointer.primary_released()) {
-                    if let Some(note) = self.active_key_note {
-                        if let Some(playback) = &self.playback {
-                            playback.note_off(note);
+            }
+            DragAction::Create => {
+                if let Some(anchor_id) = self.drag_primary_anchor {
+                    if let Some(original) = originals_snapshot
+                        .iter()
+                        .find(|(id, _)| *id == anchor_id)
+                        .map(|(_, note)| *note)
+                    {
+                        let snapped = self.snap_tick(
+                            pointer_tick,
+                            Some(original.start + original.duration),
+                            disable_snap,
+                        );
+                        let upper = self.edit_bounds.map_or(u64::MAX, |(_, hi)| hi);
+                        let new_end = snapped.min(upper).max(original.start + 1);
+                        let new_key = self.constrain_key(to_key(pointer)).unwrap_or(original.key);
+                        let mut preview = None;
+                        if let Some(note) = self.note_mut_by_id(anchor_id) {
+                            if new_end != note.start + note.duration {
+                                note.duration = new_end - note.start;
+                            }
+                            if new_key != note.key {
+                                note.key = new_key;
+                                preview = Some(new_key);
+                            }
+                            self.drag_changed_note = true;
+                        }
+                        if let Some(key) = preview {
+                            self.preview_note_on(key, 100);
                         }
-                        self.active_key_note = None;
                     }
                 }
+            }
+            DragAction::LoopEdit | DragAction::PlayheadSeek | DragAction::MarkerMove => {
+                // Loop editing and playhead seeking - handled in ui_piano_roll interaction code
+            }
+        }
+        if self.drag_changed_note {
+            self.sync_sounding_notes();
+        }
+    }
 
-                let base_x = rect.min.x + key_width;
-                let base_y = rect.min.y + timeline_height;
-                let manual_scroll_x = self.manual_scroll_x;
-                let manual_scroll_y = self.manual_scroll_y;
-                let zoom_x = self.zoom_x;
-                let zoom_y = self.zoom_y;
-                let ticks_per_beat = self.state.ticks_per_beat as f32;
+    /// 吸附时值的选项（右键菜单与播放设置对话框共用）：七种时值、普通/三连音/附点、Free，
+    /// 使用自定义 tick 间隔时另外列出；返回是否点选了某一项
+    fn ui_snap_choices(&mut self, ui: &mut Ui) -> bool {
+        let current = self.snap_resolution;
+        let modifier = current.map_or(SnapModifier::Straight, |(_, modifier)| modifier);
+        let mut choice = None;
+        for resolution in SnapResolution::ALL {
+            let selected = current.is_some_and(|(r, _)| r == resolution);
+            if ui.selectable_label(selected, resolution.label()).clicked() {
+                choice = Some(Some((resolution, modifier)));
+            }
+        }
+        ui.separator();
+        for m in SnapModifier::ALL {
+            if ui.selectable_label(current.is_some() && modifier == m, m.label()).clicked() {
+                let resolution = current.map_or(SnapResolution::Sixteenth, |(resolution, _)| resolution);
+                choice = Some(Some((resolution, m)));
+            }
+        }
+        ui.separator();
+        if ui.selectable_label(current.is_none() && self.snap_interval <= 1, "Free").clicked() {
+            self.apply_command(EditorCommand::SetSnap { interval: 0, mode: self.snap_mode });
+            return true;
+        }
+        if current.is_none() && self.snap_interval > 1 {
+            let _ = ui.selectable_label(true, format!("Custom ({} ticks)", self.snap_interval));
+        }
+        match choice {
+            Some(resolution) => {
+                self.apply_command(EditorCommand::SetSnapResolution(resolution));
+                true
+            }
+            None => false,
+        }
+    }
 
-                let pointer_to_tick = move |pos: Pos2| -> i64 {
-                    let rel_x = pos.x - base_x - manual_scroll_x;
-                    let beats = rel_x / zoom_x;
-                    (beats * ticks_per_beat).round() as i64
-                };
-                let pointer_to_key = move |pos: Pos2| -> u8 {
-                    let keyboard_top = base_y + manual_scroll_y;
-                    let rel_y = pos.y - keyboard_top;
-                    let key_val = 127.0 - rel_y / zoom_y;
-                    key_val.clamp(0.0, 127.0).round() as u8
-                };
+    fn snap_value(&self, value: i64) -> i64 {
+        let interval = self.snap_ticks() as i64;
+        if interval == 0 {
+            return value;
+        }
+        let remainder = value.rem_euclid(interval);
+        if remainder >= interval / 2 {
+            value + (interval - remainder)
+        } else {
+            value - remainder
+        }
+    }
 
-                // Draw Notes with viewport culling for performance
-                let note_offset_y = rect.min.y + timeline_height + self.manual_scroll_y;
-                
-                // Calculate visible time range for culling (notes are sorted by start time)
-                let visible_start_tick = if self.manual_scroll_x < 0.0 {
-                    ((-self.manual_scroll_x / self.zoom_x) * self.state.ticks_per_beat as f32) as u64
-                } else {
-                    0
-                };
-                let visible_end_tick = visible_start_tick.saturating_add(
-                    ((rect.width() / self.zoom_x) * self.state.ticks_per_beat as f32) as u64 + 1
-                );
-                
-                // Use binary search to find notes in visible time range
-                let notes_snapshot = &self.state.notes;
-                let start_idx = notes_snapshot.partition_point(|n| n.start + n.duration < visible_start_tick);
-                let end_idx = notes_snapshot.partition_point(|n| n.start <= visible_end_tick);
+    fn snap_tick(&self, raw_tick: i64, reference: Option<u64>, disable: bool) -> u64 {
+        if self.snap_ticks() == 0 || disable {
+            return raw_tick.max(0) as u64;
+        }
+        match (self.snap_mode, reference) {
+            (SnapMode::Relative, Some(original)) => {
+                let delta = raw_tick - original as i64;
+                let snapped_delta = self.snap_value(delta);
+                (original as i64 + snapped_delta).max(0) as u64
+            }
+            _ => self.snap_value(raw_tick).max(0) as u64,
+        }
+    }
+
+    fn ui_curve_lanes(&mut self, ui: &mut Ui) {
+        if self.lower_lane_view == LowerLaneView::NoteVelocity {
+            ui.horizontal(|ui| self.ui_lower_lane_view_switch(ui));
+            self.ui_note_velocity_lane(ui);
+            return;
+        }
+        self.ui_curve_lane_selector(ui);
+        // Find the displayed curve lane ID and clone data
+            if let Some(lane_id) = self.displayed_curve_lane() {
+                let key_width = 60.0; // Same as piano roll (for grid alignment calculation)
+                let tpb = self.state.ticks_per_beat.max(1) as u64;
+                let manual_scroll_x = self.manual_scroll_x;
+                let zoom_x = self.zoom_x;
+                let theme = self.palette;
+                let triplet_grid = self.triplet_grid();
+                let available_height = ui.available_height();
                 
-                // Collect note IDs and rects first to avoid borrow conflicts
-                let visible_notes: Vec<(NoteId, Rect)> = notes_snapshot[start_idx..end_idx.min(notes_snapshot.len())]
-                    .iter()
-                    .map(|note| {
-                        let x = note_offset_x
-                            + tick_to_x(note.start, self.zoom_x, self.state.ticks_per_beat);
-                        let y = note_offset_y + note_to_y(note.key, self.zoom_y);
-                        let w =
-                            tick_to_x(note.duration, self.zoom_x, self.state.ticks_per_beat).max(5.0);
-                        let h = self.zoom_y;
-                        let note_rect = Rect::from_min_size(Pos2::new(x, y), Vec2::new(w, h));
-                        (note.id, note_rect)
-                    })
-                    .filter(|(_, note_rect)| note_rect.intersects(rect))
-                    .collect();
+                // Clone points and lane info for rendering
+                let (lane_type, interpolation, points_clone) = self.state.curves.iter()
+                    .find(|c| c.id == lane_id)
+                    .map(|c| (c.lane_type, c.interpolation, c.points.clone()))
+                    .unwrap_or((CurveLaneType::Velocity, CurveInterpolation::Linear, Vec::new()));
+                let (min_val, max_val) = lane_type.value_range();
+                let value_range = max_val - min_val;
+                let dragging = self.dragging_curve_point;
                 
-                // Now draw and handle interactions
-                for (note_id, note_rect) in &visible_notes {
-                    let is_selected = self.selected_notes.contains(note_id);
-                    let color = if is_selected {
-                        Color32::from_rgb(150, 250, 150)
-                    } else {
-                        Color32::from_rgb(100, 200, 100)
-                    };
-                    painter.rect_filled(note_rect.shrink(1.0), 2.0, color);
-                    // Draw stroke: 4x thicker white stroke for selected notes, normal for others
-                    let stroke_width = if is_selected { 4.0 } else { 1.0 };
-                    painter.rect_stroke(
-                        note_rect.shrink(1.0),
-                        2.0,
-                        Stroke::new(stroke_width, Color32::WHITE),
-                    );
-                }
+                let mut point_to_delete: Option<CurvePointId> = None;
+                let mut context_point: Option<CurvePointId> = None;
+                let mut point_interpolation: Option<(CurvePointId, Option<CurveInterpolation>)> = None;
+                let mut point_to_start_drag: Option<CurvePointId> = None;
+                let mut new_point: Option<(u64, f32)> = None;
+                let mut copy_points = false;
+                let mut paste_points_at: Option<u64> = None;
+                let toggle_selection = ui.input(|i| i.modifiers.command);
                 
-                // Handle interactions (need to find note by ID)
-                for (note_id, note_rect) in &visible_notes {
-                    if response.clicked_by(PointerButton::Primary) {
-                        if let Some(pointer) = response.interact_pointer_pos() {
-                            if note_rect.contains(pointer) {
-                                let modifiers = ui.input(|i| i.modifiers);
-                                self.handle_note_click(*note_id, modifiers);
-                                pointer_consumed = true;
-                            }
+                // Curve editing area - full width, extends to window edges
+                ui.push_id("curve_editor_scroll", |ui| {
+                    ScrollArea::horizontal()
+                        .auto_shrink([false, false])
+                        .show(ui, |ui| {
+                        let available_width = ui.available_width();
+                        let (rect, response) = ui.allocate_exact_size(
+                            Vec2::new(available_width.max(400.0), available_height),
+                            Sense::click_and_drag()
+                        );
+                        
+                        let painter = ui.painter_at(rect);
+                        self.curve_lane_rect = Some(rect);
+                        
+                        // Draw background
+                        painter.rect_filled(rect, 0.0, theme.curve_background);
+                        
+                        // Draw grid lines using EXACT same logic as piano roll
+                        // To align grids, we need to calculate note_offset_x the same way as piano roll
+                        // Piano roll: note_offset_x = rect.min.x + key_width + manual_scroll_x
+                        // But piano roll's rect.min.x includes the key area, while our rect.min.x is at window edge
+                        // So we need to offset by key_width to align: rect.min.x + key_width + manual_scroll_x
+                        // This ensures grid lines align perfectly even though curve editor extends to edges
+                        let note_offset_x = rect.min.x + key_width + manual_scroll_x;
+                        
+                        let ticks_per_measure = self.state.ticks_per_measure();
+                        
+                        // Calculate visible range - allow showing tick 0 even if it's slightly to the left
+                        let visible_beats_start = (-manual_scroll_x / zoom_x).floor();
+                        let visible_beats_end = visible_beats_start + (rect.width() / zoom_x) + 2.0;
+                        // Allow rendering from tick 0 even if it's slightly outside visible range
+                        let render_start_beats = visible_beats_start.min(0.0);
+                        let mut start_tick = (render_start_beats * tpb as f32).floor() as i64;
+                        if start_tick < 0 {
+                            start_tick = 0;
+                        }
+                        let end_tick = (visible_beats_end * tpb as f32).ceil() as i64;
+                        
+                        // Allow rendering slightly to the left to show tick 0 grid line
+                        let mut grid_shapes = Vec::new();
+                        render::vertical_grid(
+                            &mut grid_shapes,
+                            &theme,
+                            triplet_grid,
+                            note_offset_x,
+                            zoom_x,
+                            tpb,
+                            ticks_per_measure,
+                            start_tick,
+                            end_tick,
+                            Rangef::new(rect.min.x - 10.0, rect.max.x),
+                            Rangef::new(rect.min.y, rect.max.y),
+                        );
+                        painter.extend(grid_shapes);
+                        
+                        // Draw horizontal grid (value lines)
+                        for i in 0..=4 {
+                            let y = rect.min.y + (rect.height() / 4.0) * i as f32;
+                            painter.line_segment(
+                                [Pos2::new(rect.min.x, y), Pos2::new(rect.max.x, y)],
+                                Stroke::new(1.0, theme.curve_grid_line),
+                            );
                         }
-                    }
 
-                    if !self.is_dragging_note && ui.input(|i| i.pointer.primary_pressed()) {
-                        if let Some(pointer) = response.interact_pointer_pos() {
-                            if note_rect.contains(pointer) {
-                                let modifiers = ui.input(|i| i.modifiers);
-                                self.prepare_selection_for_drag(*note_id, modifiers);
-                                let action = self.resolve_drag_action(pointer, *note_rect);
-                                let pointer_tick = pointer_to_tick(pointer);
-                                self.begin_note_drag(*note_id, pointer, pointer_tick, action);
-                                pointer_consumed = true;
+                        // 双极性曲线（弯音、音高）在 0 处画中心线
+                        if min_val < 0.0 && max_val > 0.0 {
+                            let y = rect.max.y - (-min_val / value_range) * rect.height();
+                            painter.line_segment(
+                                [Pos2::new(rect.min.x, y), Pos2::new(rect.max.x, y)],
+                                Stroke::new(1.0, theme.curve_zero_line),
+                            );
+                        }
+                        
+                        // 逐段绘制：阶梯段（如延音踏板）画成色块，每个点的值保持到下一个点；
+                        // 线性段画直线，平滑段按像素取样画折线。第一个点之前与最后一个点之后保持端点的值
+                        let curve_stroke = Stroke::new(2.0, theme.curve_line);
+                        let to_screen = |tick: f32, value: f32| {
+                            Pos2::new(
+                                note_offset_x + (tick / tpb as f32) * zoom_x,
+                                rect.max.y - (value - min_val) / value_range * rect.height(),
+                            )
+                        };
+                        if let Some(first) = points_clone.first() {
+                            let start = to_screen(first.tick as f32, first.value);
+                            if start.x > rect.min.x {
+                                painter.line_segment([Pos2::new(rect.min.x, start.y), start], Stroke::new(1.0, curve_stroke.color.gamma_multiply(0.5)));
                             }
                         }
-                    }
-
-                    if let Some(pointer) = response.hover_pos() {
-                        let action = self.resolve_drag_action(pointer, *note_rect);
-                        if matches!(action, DragAction::ResizeStart | DragAction::ResizeEnd)
-                            && note_rect.contains(pointer)
-                        {
-                            ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
+                        for (index, point) in points_clone.iter().enumerate() {
+                            let start = to_screen(point.tick as f32, point.value);
+                            let next = points_clone.get(index + 1);
+                            let x1 = next.map(|next| to_screen(next.tick as f32, next.value).x).unwrap_or(rect.max.x);
+                            if x1 < rect.min.x - 10.0 || start.x > rect.max.x + 10.0 || x1 <= start.x {
+                                continue;
+                            }
+                            let segment = match next {
+                                Some(_) => point.interpolation.unwrap_or(interpolation),
+                                None => CurveInterpolation::Step,
+                            };
+                            match (segment, next) {
+                                (CurveInterpolation::Step, _) | (_, None) => {
+                                    let block = Rect::from_min_max(start, Pos2::new(x1, rect.max.y));
+                                    if interpolation == CurveInterpolation::Step {
+                                        painter.rect_filled(block, 0.0, theme.curve_line.gamma_multiply(0.27));
+                                    }
+                                    painter.line_segment([start, Pos2::new(x1, start.y)], curve_stroke);
+                                    if let Some(next) = next {
+                                        painter.line_segment([Pos2::new(x1, start.y), to_screen(next.tick as f32, next.value)], curve_stroke);
+                                    }
+                                }
+                                (CurveInterpolation::Linear, Some(next)) => {
+                                    painter.line_segment([start, to_screen(next.tick as f32, next.value)], curve_stroke);
+                                }
+                                (CurveInterpolation::Smooth, Some(next)) => {
+                                    let steps = ((x1 - start.x) / 4.0).ceil().clamp(1.0, 256.0) as usize;
+                                    let span = (next.tick - point.tick) as f32;
+                                    let polyline: Vec<Pos2> = (0..=steps)
+                                        .map(|i| {
+                                            let alpha = i as f32 / steps as f32;
+                                            let value = segment.interpolate(point.value, next.value, alpha);
+                                            to_screen(point.tick as f32 + span * alpha, value)
+                                        })
+                                        .collect();
+                                    painter.add(egui::Shape::line(polyline, curve_stroke));
+                                }
+                            }
                         }
-                    }
-
-                    if response.clicked_by(PointerButton::Secondary) {
-                        if let Some(pointer) = response.interact_pointer_pos() {
-                            if note_rect.contains(pointer) {
-                                let modifiers = ui.input(|i| i.modifiers);
-                                if modifiers.shift {
-                                    // Shift+右键：删除音符
-                                    self.push_undo_snapshot();
-                                    self.delete_note_by_id(*note_id);
-                                    pointer_consumed = true;
+                        
+                        // Draw curve points and handle interactions
+                        for point in &points_clone {
+                            let x = note_offset_x + (point.tick as f32 / tpb as f32) * zoom_x;
+                            let normalized_value = (point.value - min_val) / value_range;
+                            let y = rect.max.y - normalized_value * rect.height();
+                            
+                            if x >= rect.min.x - 5.0 && x <= rect.max.x + 5.0 {
+                                let point_pos = Pos2::new(x, y);
+                                let point_rect = Rect::from_center_size(point_pos, Vec2::new(8.0, 8.0));
+                                if self.selected_curve_points.contains(&point.id) {
+                                    painter.circle_filled(point_pos, 5.0, theme.selected_curve_point);
+                                    painter.circle_stroke(point_pos, 5.0, Stroke::new(2.0, theme.note_outline));
                                 } else {
-                                    // 普通右键：显示上下文菜单
-                                    self.context_menu_pos = Some(pointer);
-                                    self.context_menu_open_pos = Some(pointer);
-                                    pointer_consumed = true;
+                                    painter.circle_filled(point_pos, 4.0, theme.curve_point);
+                                    painter.circle_stroke(point_pos, 4.0, Stroke::new(1.0, theme.note_outline));
+                                }
+                                
+                                // Handle point interactions
+                                if response.clicked_by(PointerButton::Primary) {
+                                    if let Some(pointer) = response.interact_pointer_pos() {
+                                        if point_rect.contains(pointer) {
+                                            point_to_start_drag = Some(point.id);
+                                        }
+                                    }
+                                }
+                                
+                                if response.clicked_by(PointerButton::Secondary) {
+                                    if let Some(pointer) = response.interact_pointer_pos() {
+                                        if point_rect.contains(pointer) {
+                                            context_point = Some(point.id);
+                                        }
+                                    }
                                 }
                             }
                         }
-                    }
-                }
-
-                if self.is_dragging_note && ui.input(|i| i.pointer.primary_down()) {
-                    if let Some(pointer) = response.interact_pointer_pos() {
-                        let modifiers = ui.input(|i| i.modifiers);
-                        self.update_note_drag(
-                            pointer,
-                            &pointer_to_tick,
-                            &pointer_to_key,
-                            modifiers,
-                        );
-                    }
-                }
-
-                if response.drag_stopped() {
-                    self.preview_note_off();
-                    self.finalize_note_drag_if_needed();
-                    self.is_dragging_note = false;
-                    self.is_resizing_note = false;
-                    self.drag_action = DragAction::None;
-                    self.drag_start_pos = None;
-                    self.drag_original_start = None;
-                    self.drag_original_duration = None;
-                    self.drag_original_key = None;
-                    self.drag_pointer_offset_ticks = None;
-                    self.drag_original_notes.clear();
-                    self.drag_primary_anchor = None;
-                }
-
-                if !pointer_consumed && ui.input(|i| i.pointer.primary_pressed()) {
-                    if let Some(pointer) = response.interact_pointer_pos() {
-                        let in_roll = pointer.x > rect.min.x + key_width
-                            && pointer.y > rect.min.y + timeline_height;
-                        if in_roll {
-                            let modifiers = ui.input(|i| i.modifiers);
-                            if modifiers.shift {
-                                // Shift+左键：创建新音符
-                                if !self.is_dragging_note {
-                                    self.create_note_at_pointer(
-                                        pointer,
-                                        &pointer_to_tick,
-                                        &pointer_to_key,
-                                    );
+                        
+                        // Handle dragging
+                        if let Some((drag_lane_id, drag_point_id)) = dragging {
+                            if drag_lane_id == lane_id && ui.input(|i| i.pointer.primary_down()) {
+                                if let Some(pointer) = response.interact_pointer_pos() {
+                                    // Convert pointer position to tick
+                                    // note_offset_x = rect.min.x + key_width + manual_scroll_x
+                                    // So: pointer.x - note_offset_x = pointer.x - rect.min.x - key_width - manual_scroll_x
+                                    // This gives us the position relative to tick 0
+                                    let rel_x = pointer.x - note_offset_x;
+                                    let beats = rel_x / zoom_x;
+                                    let tick = (beats * tpb as f32).round() as i64;
+                                    // Only allow dragging to tick >= 0
+                                    if tick >= 0 {
+                                        let disable_snap = ui.input(|i| i.modifiers.alt);
+                                        let snapped_tick = if disable_snap {
+                                            tick as u64
+                                        } else {
+                                            self.snap_value(tick).max(0) as u64
+                                        };
+                                        
+                                        let rel_y = pointer.y - rect.min.y;
+                                        let normalized_value = 1.0 - (rel_y / rect.height());
+                                        let value = min_val + normalized_value * value_range;
+                                        
+                                        if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == drag_lane_id) {
+                                            lane.update_point(drag_point_id, snapped_tick, value);
+                                            self.emit_event(EditorEvent::CurvePointUpdated {
+                                                lane_id: drag_lane_id,
+                                                point_id: drag_point_id,
+                                            });
+                                        }
+                                    }
                                 }
                             } else {
-                                // 直接左键：开始框选（如果不在音符上）
-                                if !self.is_dragging_note {
-                                    self.selection_box_start = Some(pointer);
-                                    self.selection_box_end = Some(pointer);
+                                self.dragging_curve_point = None;
+                            }
+                        }
+                        
+                        // 铅笔/直线工具：拖动时修改曲线，松开时清理多余的点并成批发出事件（Alt 不吸附）
+                        if self.curve_tool != CurveTool::Point && dragging.is_none() {
+                            let disable_snap = ui.input(|i| i.modifiers.alt);
+                            let to_curve_point = |editor: &Self, pointer: Pos2| {
+                                let tick = ((pointer.x - note_offset_x) / zoom_x * tpb as f32).round().max(0.0) as i64;
+                                let tick = if disable_snap { tick as u64 } else { editor.snap_value(tick).max(0) as u64 };
+                                let value = min_val + (1.0 - (pointer.y - rect.min.y) / rect.height()) * value_range;
+                                (tick, value.clamp(min_val, max_val))
+                            };
+                            if response.drag_started_by(PointerButton::Primary) {
+                                let origin = ui.input(|i| i.pointer.press_origin()).or(response.interact_pointer_pos());
+                                if let Some(origin) = origin {
+                                    let start = to_curve_point(self, origin);
+                                    self.begin_curve_stroke(lane_id, start);
+                                }
+                            }
+                            if response.dragged_by(PointerButton::Primary) {
+                                if let Some(pointer) = response.interact_pointer_pos() {
+                                    let point = to_curve_point(self, pointer);
+                                    self.continue_curve_stroke(point);
                                 }
                             }
+                            if response.drag_stopped() {
+                                self.finish_curve_stroke();
+                            }
+                            if let (CurveTool::Line, Some(stroke)) = (self.curve_tool, &self.curve_stroke) {
+                                painter.line_segment(
+                                    [to_screen(stroke.start.0 as f32, stroke.start.1), to_screen(stroke.last.0 as f32, stroke.last.1)],
+                                    Stroke::new(1.5, theme.note_outline),
+                                );
+                            }
                         }
-                    }
-                }
 
-                if let Some(start) = self.selection_box_start {
-                    if ui.input(|i| i.pointer.primary_down()) {
-                        if let Some(pointer) = response.hover_pos() {
-                            self.selection_box_end = Some(pointer);
-                        }
-                        if let Some(end) = self.selection_box_end {
-                            let selection_rect = Rect::from_two_pos(start, end);
-                            painter.rect_stroke(
-                                selection_rect,
-                                0.0,
-                                Stroke::new(1.0, Color32::from_rgb(120, 200, 255)),
-                            );
+                        // Right-click on a point opens its interpolation/delete menu,
+                        // on empty space the copy/paste menu at that tick
+                        if response.clicked_by(PointerButton::Secondary) {
+                            if let Some(pointer) = response.interact_pointer_pos() {
+                                let tick = ((pointer.x - note_offset_x) / zoom_x * tpb as f32).round().max(0.0) as u64;
+                                self.curve_context_tick = Some(self.snap_value(tick as i64).max(0) as u64);
+                                self.curve_context_point = context_point;
+                            }
                         }
-                    }
-
-                    if ui.input(|i| i.pointer.primary_released()) {
-                        if let Some(end) = self.selection_box_end {
-                            let selection_rect = Rect::from_two_pos(start, end);
-                            let previous = if ui.input(|i| i.modifiers.shift) {
-                                self.selected_notes.clone()
-                            } else {
-                                std::mem::take(&mut self.selected_notes)
-                            };
-                            // Optimize box selection with viewport culling
-                            let selection_start_tick = if self.manual_scroll_x < 0.0 {
-                                (((selection_rect.min.x - note_offset_x) / self.zoom_x) * self.state.ticks_per_beat as f32) as u64
-                            } else {
-                                0
-                            };
-                            let selection_end_tick = selection_start_tick.saturating_add(
-                                ((selection_rect.width() / self.zoom_x) * self.state.ticks_per_beat as f32) as u64 + 1
-                            );
-                            
-                            let notes_snapshot = &self.state.notes;
-                            let sel_start_idx = notes_snapshot.partition_point(|n| n.start + n.duration < selection_start_tick);
-                            let sel_end_idx = notes_snapshot.partition_point(|n| n.start <= selection_end_tick);
-                            
-                            for note in &notes_snapshot[sel_start_idx..sel_end_idx.min(notes_snapshot.len())] {
-                                let x = note_offset_x
-                                    + tick_to_x(note.start, self.zoom_x, self.state.ticks_per_beat);
-                                let y = note_offset_y + note_to_y(note.key, self.zoom_y);
-                                let w = tick_to_x(
-                                    note.duration,
-                                    self.zoom_x,
-                                    self.state.ticks_per_beat,
-                                )
-                                .max(5.0);
-                                let h = self.zoom_y;
-                                let note_rect =
-                                    Rect::from_min_size(Pos2::new(x, y), Vec2::new(w, h));
-                                if note_rect.intersects(selection_rect) {
-                                    self.selected_notes.insert(note.id);
+                        if let Some(context_tick) = self.curve_context_tick {
+                            let has_points = !self.selected_curve_points.is_empty() || self.loop_enabled;
+                            let can_paste = self.curve_clipboard.is_some();
+                            let context_point = self
+                                .curve_context_point
+                                .and_then(|id| points_clone.iter().find(|p| p.id == id))
+                                .map(|p| (p.id, p.interpolation));
+                            let menu = response.context_menu(|ui| {
+                                if let Some((point_id, current)) = context_point {
+                                    let default_label = format!("Lane default ({})", interpolation.label());
+                                    if ui.radio(current.is_none(), default_label).clicked() {
+                                        point_interpolation = Some((point_id, None));
+                                        ui.close_menu();
+                                    }
+                                    for mode in CurveInterpolation::ALL {
+                                        if ui.radio(current == Some(mode), mode.label()).clicked() {
+                                            point_interpolation = Some((point_id, Some(mode)));
+                                            ui.close_menu();
+                                        }
+                                    }
+                                    ui.separator();
+                                    if ui.add_enabled(points_clone.len() > 1, Button::new("Delete point")).clicked() {
+                                        point_to_delete = Some(point_id);
+                                        ui.close_menu();
+                                    }
+                                    return;
                                 }
+                                if ui.add_enabled(has_points, Button::new("Copy points")).clicked() {
+                                    copy_points = true;
+                                    ui.close_menu();
+                                }
+                                if ui.add_enabled(can_paste, Button::new("Paste points here")).clicked() {
+                                    paste_points_at = Some(context_tick);
+                                    ui.close_menu();
+                                }
+                                ui.checkbox(&mut self.curve_paste_overwrite, "Overwrite existing points");
+                            });
+                            if menu.is_none() && !response.clicked_by(PointerButton::Secondary) {
+                                self.curve_context_tick = None;
+                                self.curve_context_point = None;
                             }
-                            self.notify_selection_changed(previous);
                         }
-                        self.selection_box_start = None;
-                        self.selection_box_end = None;
-                    }
-                }
 
-                // Handle right-click on piano roll area (not on notes)
-                if !pointer_consumed && response.clicked_by(PointerButton::Secondary) {
-                    if let Some(pointer) = response.interact_pointer_pos() {
-                        let in_roll = pointer.x > rect.min.x + key_width
-                            && pointer.y > rect.min.y + timeline_height;
-                        if in_roll {
-                            let modifiers = ui.input(|i| i.modifiers);
-                            if modifiers.shift {
-                                // Shift+右键：删除选中音符
-                                if !self.selected_notes.is_empty() {
-                                    self.delete_selected_notes();
+                        // Handle adding new point
+                        if response.clicked_by(PointerButton::Primary) && dragging.is_none() && point_to_start_drag.is_none() {
+                            if let Some(pointer) = response.interact_pointer_pos() {
+                                if rect.contains(pointer) {
+                                    // Convert pointer position to tick
+                                    // note_offset_x = rect.min.x + key_width + manual_scroll_x
+                                    let rel_x = pointer.x - note_offset_x;
+                                    let beats = rel_x / zoom_x;
+                                    let tick = (beats * tpb as f32).round() as i64;
+                                    // Only allow adding points at tick >= 0
+                                    if tick >= 0 {
+                                        let disable_snap = ui.input(|i| i.modifiers.alt);
+                                        let snapped_tick = if disable_snap {
+                                            tick as u64
+                                        } else {
+                                            self.snap_value(tick).max(0) as u64
+                                        };
+                                        
+                                        let rel_y = pointer.y - rect.min.y;
+                                        let normalized_value = 1.0 - (rel_y / rect.height());
+                                        let value = min_val + normalized_value * value_range;
+                                        
+                                        // Check if clicking near existing point
+                                        let near_existing = points_clone.iter().any(|p| {
+                                            let px = note_offset_x + (p.tick as f32 / tpb as f32) * zoom_x;
+                                            let py = rect.max.y - ((p.value - min_val) / value_range) * rect.height();
+                                            (pointer.x - px).abs() < 10.0 && (pointer.y - py).abs() < 10.0
+                                        });
+                                        
+                                        if !near_existing {
+                                            new_point = Some((snapped_tick, value));
+                                        }
+                                    }
                                 }
-                            } else {
-                                // 普通右键：显示上下文菜单
-                                self.context_menu_pos = Some(pointer);
-                                self.context_menu_open_pos = Some(pointer);
                             }
                         }
+                    });
+                }); // Close push_id
+                
+                // Handle deletions and additions outside the closure
+                if let Some(point_id) = point_to_delete {
+                    self.push_undo_snapshot();
+                    self.selected_curve_points.retain(|id| *id != point_id);
+                    if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                        lane.remove_point(point_id);
+                        self.emit_event(EditorEvent::CurvePointRemoved {
+                            lane_id,
+                            point_id,
+                        });
                     }
                 }
-
-                // Draw Timeline (Top Bar) - Drawn AFTER Notes
-                // Fill timeline background
-                let timeline_rect =
-                    Rect::from_min_size(rect.min, Vec2::new(rect.width(), timeline_height));
-                painter.rect_filled(timeline_rect, 0.0, ui.visuals().window_fill());
-                painter.line_segment(
-                    [timeline_rect.left_bottom(), timeline_rect.right_bottom()],
-                    Stroke::new(1.0, separator_color), // Separator line
-                );
-
-                // Draw Timeline Labels (per measure)
-                let mut measure_tick = (start_tick as u64 / ticks_per_measure) * ticks_per_measure;
-                while measure_tick as i64 <= end_tick {
-                    let x = note_offset_x + (measure_tick as f32 / tpb as f32) * self.zoom_x;
-                    if x >= rect.min.x + key_width - 5.0 && x <= rect.max.x {
-                        painter.line_segment(
-                            [
-                                Pos2::new(x, rect.min.y),
-                                Pos2::new(x, rect.min.y + timeline_height),
-                            ],
-                            Stroke::new(1.0, measure_line_color),
-                        );
-                        let measure_index = (measure_tick / ticks_per_measure) + 1;
-                        painter.text(
-                            Pos2::new(x + 4.0, rect.min.y + 15.0),
-                            Align2::LEFT_CENTER,
-                            format!("{}", measure_index),
-                            FontId::proportional(11.0),
-                            Color32::GRAY,
-                        );
+                
+                if let Some((point_id, mode)) = point_interpolation {
+                    self.set_curve_point_interpolation(lane_id, point_id, mode);
+                }
+                if copy_points {
+                    self.copy_curve_selection(None);
+                }
+                if let Some(tick) = paste_points_at {
+                    if self.curve_clipboard.is_some() {
+                        self.push_undo_snapshot();
+                        self.paste_curve_at(tick);
                     }
-                    measure_tick += ticks_per_measure;
                 }
 
-                // Draw Loop Markers on Timeline (if enabled)
-                if self.loop_enabled {
-                    let loop_start_x = note_offset_x
-                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
-                    let loop_end_x = note_offset_x
-                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
-                    
-                    // Draw loop start marker
-                    if loop_start_x >= rect.min.x + key_width && loop_start_x <= rect.max.x {
-                        painter.add(Shape::convex_polygon(
-                            vec![
-                                Pos2::new(loop_start_x, rect.min.y),
-                                Pos2::new(loop_start_x - 4.0, rect.min.y + 8.0),
-                                Pos2::new(loop_start_x + 4.0, rect.min.y + 8.0),
-                            ],
-                            Color32::from_rgb(100, 150, 255),
-                            Stroke::NONE,
-                        ));
-                        painter.text(
-                            Pos2::new(loop_start_x, rect.min.y + 20.0),
-                            Align2::CENTER_TOP,
-                            "L",
-                            FontId::proportional(9.0),
-                            Color32::from_rgb(100, 150, 255),
-                        );
-                    }
-                    
-                    // Draw loop end marker
-                    if loop_end_x >= rect.min.x + key_width && loop_end_x <= rect.max.x {
-                        painter.add(Shape::convex_polygon(
-                            vec![
-                                Pos2::new(loop_end_x, rect.min.y),
-                                Pos2::new(loop_end_x - 4.0, rect.min.y + 8.0),
-                                Pos2::new(loop_end_x + 4.0, rect.min.y + 8.0),
-                            ],
-                            Color32::from_rgb(100, 150, 255),
-                            Stroke::NONE,
-                        ));
-                        painter.text(
-                            Pos2::new(loop_end_x, rect.min.y + 20.0),
-                            Align2::CENTER_TOP,
-                            "R",
-                            FontId::proportional(9.0),
-                            Color32::from_rgb(100, 150, 255),
-                        );
+                if let Some((tick, value)) = new_point {
+                    self.selected_curve_points.clear();
+                    self.push_undo_snapshot();
+                    if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
+                        let point = lane.insert_point(tick, value);
+                        self.emit_event(EditorEvent::CurvePointAdded {
+                            lane_id,
+                            point_id: point.id,
+                        });
                     }
                 }
-
-                // Draw Loop Region (if enabled) - before playhead
-                if self.loop_enabled {
-                    let loop_start_x = note_offset_x
-                        + tick_to_x(self.loop_start_tick, self.zoom_x, self.state.ticks_per_beat);
-                    let loop_end_x = note_offset_x
-                        + tick_to_x(self.loop_end_tick, self.zoom_x, self.state.ticks_per_beat);
-                    
-                    if loop_end_x > rect.min.x + key_width && loop_start_x < rect.max.x {
-                        let loop_rect = Rect::from_min_max(
-                            Pos2::new(loop_start_x.max(rect.min.x + key_width), rect.min.y),
-                            Pos2::new(loop_end_x.min(rect.max.x), rect.max.y),
-                        );
-                        // Semi-transparent overlay
-                        painter.rect_filled(
-                            loop_rect,
-                            0.0,
-                            Color32::from_rgba_unmultiplied(100, 150, 255, 60),
-                        );
-                        // Loop boundaries
-                        if loop_start_x >= rect.min.x + key_width {
-                            painter.line_segment(
-                                [Pos2::new(loop_start_x, rect.min.y), Pos2::new(loop_start_x, rect.max.y)],
-                                Stroke::new(2.0, Color32::from_rgb(100, 150, 255)),
-                            );
-                        }
-                        if loop_end_x <= rect.max.x {
-                            painter.line_segment(
-                                [Pos2::new(loop_end_x, rect.min.y), Pos2::new(loop_end_x, rect.max.y)],
-                                Stroke::new(2.0, Color32::from_rgb(100, 150, 255)),
-                            );
+                
+                if let Some(point_id) = point_to_start_drag {
+                    if toggle_selection {
+                        if let Some(index) = self.selected_curve_points.iter().position(|id| *id == point_id) {
+                            self.selected_curve_points.remove(index);
+                        } else {
+                            self.selected_curve_points.push(point_id);
                         }
+                    } else if !self.selected_curve_points.contains(&point_id) {
+                        self.selected_curve_points = vec![point_id];
                     }
+                    self.push_undo_snapshot();
+                    self.dragging_curve_point = Some((lane_id, point_id));
                 }
+            } else {
+                ui.label("No curve lane found");
+            }
+    }
+}
 
-                // Draw Playhead (Timeline portion + Line)
-                // Drawn AFTER notes (so it's on top of notes)
-                let playhead_x = note_offset_x
-                    + time_to_x(
-                        (self.current_time * self.state.bpm / 60.0) as f32,
-                        self.zoom_x,
-                    );
-                if playhead_x > rect.min.x + key_width {
-                    painter.line_segment(
-                        [
-                            Pos2::new(playhead_x, rect.min.y),
-                            Pos2::new(playhead_x, rect.max.y),
-                        ],
-                        Stroke::new(2.0, Color32::from_rgba_premultiplied(100, 200, 255, 128)),
-                    );
-                }
+/// 单个 `MidiState` 的大致字节数（只计音符、曲线点与分组等主要部分）
+fn state_size_estimate(state: &MidiState) -> usize {
+    std::mem::size_of::<MidiState>()
+        + state.notes.len() * std::mem::size_of::<Note>()
+        + state
+            .curves
+            .iter()
+            .map(|lane| std::mem::size_of::<CurveLane>() + lane.points.len() * std::mem::size_of::<CurvePoint>())
+            .sum::<usize>()
+        + state
+            .groups
+            .iter()
+            .map(|g| std::mem::size_of::<NoteGroup>() + g.note_ids.len() * std::mem::size_of::<NoteId>())
+            .sum::<usize>()
+}
 
-                // Draw Piano Keys (Sidebar) - Drawn LAST so they cover playhead and notes
-                // Fill background for sidebar to occlude content
-                let sidebar_rect = Rect::from_min_size(
-                    Pos2::new(rect.min.x, rect.min.y + timeline_height),
-                    Vec2::new(key_width, rect.height() - timeline_height),
-                );
-                painter.rect_filled(sidebar_rect, 0.0, ui.visuals().window_fill());
+/// 速度标记文本："128 BPM"，带小数时保留一位
+/// 电脑键盘按键相对低八度起始音高的半音数：Z..M 行（黑键在 S D G H J）与 Q..P 行（黑键在 2 3 5 6 7 9 0）
+fn computer_keyboard_offset(key: Key) -> Option<u8> {
+    let offset = match key {
+        Key::Z => 0,
+        Key::S => 1,
+        Key::X => 2,
+        Key::D => 3,
+        Key::C => 4,
+        Key::V => 5,
+        Key::G => 6,
+        Key::B => 7,
+        Key::H => 8,
+        Key::N => 9,
+        Key::J => 10,
+        Key::M => 11,
+        Key::Comma | Key::Q => 12,
+        Key::Num2 => 13,
+        Key::W => 14,
+        Key::Num3 => 15,
+        Key::E => 16,
+        Key::R => 17,
+        Key::Num5 => 18,
+        Key::T => 19,
+        Key::Num6 => 20,
+        Key::Y => 21,
+        Key::Num7 => 22,
+        Key::U => 23,
+        Key::I => 24,
+        Key::Num9 => 25,
+        Key::O => 26,
+        Key::Num0 => 27,
+        Key::P => 28,
+        _ => return None,
+    };
+    Some(offset)
+}
 
-                for i in 0..=127 {
-                    let y = rect.min.y
-                        + timeline_height
-                        + note_to_y((127 - i) as u8, self.zoom_y)
-                        + self.manual_scroll_y;
+/// 电脑键盘弹奏时占用的按键（琴键与升降八度）
+fn is_computer_keyboard_key(key: Key) -> bool {
+    matches!(key, Key::Minus | Key::Equals) || computer_keyboard_offset(key).is_some()
+}
 
-                    // Only draw if visible
-                    if y > rect.min.y + timeline_height && y < rect.max.y {
-                        let note_idx = 127 - i;
-                        let is_black = [1, 3, 6, 8, 10].contains(&(note_idx % 12));
-                        let key_color = if is_black {
-                            Color32::BLACK
-                        } else {
-                            Color32::WHITE
-                        };
-                        let text_color = if is_black {
-                            Color32::WHITE
-                        } else {
-                            Color32::BLACK
-                        };
+fn format_bpm(bpm: f32) -> String {
+    if bpm.fract().abs() < 0.05 {
+        format!("{bpm:.0} BPM")
+    } else {
+        format!("{bpm:.1} BPM")
+    }
+}
 
-                        let key_rect = Rect::from_min_size(
-                            Pos2::new(rect.min.x, y),
-                            Vec2::new(key_width, self.zoom_y),
-                        );
+/// 以 "小节.拍.tick" 显示并接受输入的位置 DragValue；不含分隔符的输入按原始 tick 解析
+fn musical_time_drag_value<'a>(tick: &'a mut u64, state: &'a MidiState, speed: f64) -> DragValue<'a> {
+    DragValue::new(tick)
+        .speed(speed)
+        .custom_formatter(move |v, _| state.musical_time(v.max(0.0) as u64).to_string())
+        .custom_parser(move |text| {
+            state
+                .parse_musical_time(text)
+                .map(|tick| tick as f64)
+                .or_else(|| text.trim().parse().ok())
+        })
+}
 
-                        painter.rect_filled(key_rect, 0.0, key_color);
-                        painter.rect_stroke(key_rect, 0.0, Stroke::new(1.0, Color32::GRAY));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Sent {
+        On(u8),
+        Off(u8),
+        AllOff,
+        Cc(u8, u8),
+        Bend(i16),
+        Click(bool),
+    }
 
-                        // C notes label
-                        if note_idx >= 12 && (note_idx - 12) % 12 == 0 {
-                            painter.text(
-                                key_rect.left_center() + Vec2::new(2.0, 0.0),
-                                Align2::LEFT_CENTER,
-                                format!("C{}", (note_idx / 12) as i32 - 1),
-                                FontId::proportional(10.0),
-                                text_color,
-                            );
-                        } else if note_idx < 12 && note_idx % 12 == 0 {
-                            painter.text(
-                                key_rect.left_center() + Vec2::new(2.0, 0.0),
-                                Align2::LEFT_CENTER,
-                                format!("C{}", (note_idx as i32 / 12) - 1),
-                                FontId::proportional(10.0),
-                                text_color,
-                            );
-                        }
+    #[derive(Default)]
+    struct RecordingPlayback {
+        sent: Mutex<Vec<Sent>>,
+    }
 
-                        // Interaction: Click Key to preview
-                        if ui.rect_contains_pointer(key_rect) {
-                            if ui.input(|i| i.pointer.primary_pressed()) {
-                                self.active_key_note = Some(note_idx);
-                                if let Some(playback) = &self.playback {
-                                    playback.note_on(note_idx, 100);
-                                }
-                            }
-                        }
+    impl RecordingPlayback {
+        /// Keys that received a note_on without a matching note_off.
+        fn ringing_keys(&self) -> BTreeSet<u8> {
+            let mut ringing = BTreeSet::new();
+            for msg in self.sent.lock().unwrap().iter() {
+                match *msg {
+                    Sent::On(key) => {
+                        ringing.insert(key);
+                    }
+                    Sent::Off(key) => {
+                        ringing.remove(&key);
                     }
+                    Sent::AllOff => ringing.clear(),
+                    Sent::Cc(..) | Sent::Bend(_) | Sent::Click(_) => {}
                 }
+            }
+            ringing
+        }
+
+        fn count(&self, msg: Sent) -> usize {
+            self.sent.lock().unwrap().iter().filter(|m| **m == msg).count()
+        }
+    }
+
+    impl PlaybackBackend for RecordingPlayback {
+        fn note_on(&self, key: u8, _velocity: u8) {
+            self.sent.lock().unwrap().push(Sent::On(key));
+        }
+        fn note_off(&self, key: u8) {
+            self.sent.lock().unwrap().push(Sent::Off(key));
+        }
+        fn all_notes_off(&self) {
+            self.sent.lock().unwrap().push(Sent::AllOff);
+        }
+        fn set_volume(&self, _volume: f32) {}
+        fn set_pitch_shift(&self, _semitones: f32) {}
+        fn control_change(&self, cc: u8, value: u8) {
+            self.sent.lock().unwrap().push(Sent::Cc(cc, value));
+        }
+        fn pitch_bend(&self, value: i16) {
+            self.sent.lock().unwrap().push(Sent::Bend(value));
+        }
+        fn click(&self, accented: bool) {
+            self.sent.lock().unwrap().push(Sent::Click(accented));
+        }
+    }
+
+    // 120 bpm at 480 tpb: one beat is 0.5s.
+    const BEAT: f32 = 0.5;
+
+    fn playing_editor(notes: Vec<Note>) -> (MidiEditor, Arc<RecordingPlayback>) {
+        let backend = Arc::new(RecordingPlayback::default());
+        let state = MidiState {
+            notes,
+            ..MidiState::default()
+        };
+        let mut editor = MidiEditor::with_state(state, Some(backend.clone() as PlaybackHandle));
+        editor.apply_command(EditorCommand::SetPlayback(true));
+        (editor, backend)
+    }
+
+    #[test]
+    fn metronome_accents_bar_starts_and_follows_loops_and_meter_changes() {
+        let (mut editor, backend) = playing_editor(Vec::new());
+        let clicks = |backend: &RecordingPlayback| -> Vec<bool> {
+            backend
+                .sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|m| match m {
+                    Sent::Click(accented) => Some(*accented),
+                    _ => None,
+                })
+                .collect()
+        };
+        editor.advance_playback(BEAT);
+        assert!(clicks(&backend).is_empty(), "metronome is off by default");
+
+        let (mut editor, backend) = playing_editor(Vec::new());
+        editor.apply_command(EditorCommand::SetMetronome(true));
+        editor.apply_command(EditorCommand::SetTimeSignature(3, 4));
+        editor.apply_command(EditorCommand::SetLoop {
+            enabled: true,
+            start_tick: 0,
+            end_tick: 1920,
+        });
+        // 每步 120 tick：0、480、960、1440 各响一声，到 1920 跳回起点
+        for _ in 0..16 {
+            editor.advance_playback(BEAT / 4.0);
+        }
+        assert_eq!(clicks(&backend), vec![true, false, false, true]);
+
+        // 跳回后改为 2/4：起点重音，480 为弱拍，960 为新小节的重音
+        editor.apply_command(EditorCommand::SetTimeSignature(2, 4));
+        for _ in 0..8 {
+            editor.advance_playback(BEAT / 4.0);
+        }
+        assert_eq!(clicks(&backend), vec![true, false, false, true, true, false, true]);
+    }
+
+    #[test]
+    fn play_selection_and_select_audition_leave_the_transport_alone() {
+        let backend = Arc::new(RecordingPlayback::default());
+        let phrase = [Note::new(0, 480, 60, 100), Note::new(480, 480, 64, 100), Note::new(960, 240, 67, 100)];
+        let chord: Vec<Note> = (0..10).map(|i| Note::new(1920, 480, 48 + i, 90)).collect();
+        let state = MidiState { notes: phrase.iter().chain(&chord).copied().collect(), ..MidiState::default() };
+        let mut editor = MidiEditor::with_state(state, Some(backend.clone() as PlaybackHandle));
+        editor.last_update = 10.0;
+
+        // 按相对位置依次发声（120 bpm：480 tick 为 0.5 秒）
+        editor.selected_notes = phrase.iter().map(|n| n.id).collect();
+        editor.apply_command(EditorCommand::PlaySelection);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+        editor.update_audition(10.3);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+        editor.update_audition(10.5);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([64]));
+        editor.update_audition(11.0);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([67]));
+        assert_eq!(editor.update_audition(11.1), Some(11.25));
+        assert_eq!(editor.update_audition(11.25), None);
+        assert!(backend.ringing_keys().is_empty());
+        assert!(!editor.is_playing);
+        assert_eq!(editor.current_time, 0.0);
+
+        // 选中试听：默认关闭；开启后新选中的音以和弦发声，最多 8 个音
+        editor.selected_notes = chord.iter().map(|n| n.id).collect();
+        editor.audition_selection_if_changed();
+        assert!(backend.ringing_keys().is_empty());
+        editor.audition_on_select = true;
+        editor.selected_notes.clear();
+        editor.audition_selection_if_changed();
+        editor.selected_notes = chord.iter().map(|n| n.id).collect();
+        editor.audition_selection_if_changed();
+        assert_eq!(backend.ringing_keys(), (48..56).collect::<BTreeSet<u8>>());
+        editor.update_audition(editor.last_update + 0.5);
+        assert!(backend.ringing_keys().is_empty());
+        assert!(!editor.is_playing);
+    }
+
+    #[test]
+    fn playback_rate_scales_the_transport_but_not_the_tempo() {
+        let note = Note::new(480, 240, 60, 100);
+        let (mut editor, backend) = playing_editor(vec![note]);
+        editor.take_events();
+        editor.apply_command(EditorCommand::SetPlaybackRate(0.5));
+        assert!(editor.take_events().iter().any(|e| matches!(
+            e,
+            EditorEvent::TransportChanged { playback_rate, .. } if *playback_rate == 0.5
+        )));
+
+        // 半速：真实的一拍只走半拍
+        editor.advance_playback(BEAT);
+        assert_eq!(editor.current_tick_position(), 240);
+        assert!(backend.ringing_keys().is_empty());
+        editor.advance_playback(BEAT);
+        assert_eq!(editor.current_tick_position(), 480);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+        assert_eq!(editor.state.bpm, 120.0);
+
+        // 循环边界按 tick 处理，与速率无关
+        editor.apply_command(EditorCommand::SetLoop { enabled: true, start_tick: 0, end_tick: 960 });
+        editor.set_playback_rate(8.0);
+        assert_eq!(editor.playback_rate(), 2.0);
+        editor.advance_playback(BEAT * 0.25);
+        assert_eq!(editor.current_tick_position(), 720);
+        editor.advance_playback(BEAT * 0.25);
+        assert_eq!(editor.current_tick_position(), 0);
+    }
+
+    #[test]
+    fn panic_and_backend_swaps_silence_everything_that_is_held() {
+        let note = Note::new(0, 960, 60, 100);
+        let (mut editor, backend) = playing_editor(vec![note]);
+        editor.advance_playback(BEAT * 0.5);
+        editor.preview_note_on(72, 100);
+        editor.key_preview = KeyPreviewState::Held { key: 48, velocity: 90 };
+        editor.apply_command(EditorCommand::Panic);
+        assert_eq!(backend.sent.lock().unwrap().last(), Some(&Sent::AllOff));
+        assert!(backend.ringing_keys().is_empty());
+        assert_eq!((editor.drag_preview_key, editor.key_preview), (None, KeyPreviewState::Idle));
+        assert!(editor.is_playing, "panic does not stop the transport");
+
+        // 换后端前旧后端先收到 all_notes_off，新后端不会收到旧后端的 note off
+        editor.preview_note_on(72, 100);
+        let replacement = Arc::new(RecordingPlayback::default());
+        editor.set_playback_backend(Some(replacement.clone() as PlaybackHandle));
+        assert_eq!(backend.sent.lock().unwrap().last(), Some(&Sent::AllOff));
+        assert!(replacement.sent.lock().unwrap().is_empty());
+        assert_eq!(editor.drag_preview_key, None);
+    }
+
+    #[test]
+    fn deleting_sounding_note_releases_it() {
+        let note = Note::new(0, 960, 60, 100);
+        let (mut editor, backend) = playing_editor(vec![note]);
+        editor.advance_playback(BEAT * 0.5);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+
+        editor.remove_notes([note.id]);
+        assert!(backend.ringing_keys().is_empty());
+
+        editor.advance_playback(BEAT * 2.0);
+        assert!(backend.ringing_keys().is_empty());
+    }
+
+    #[test]
+    fn moving_sounding_note_away_releases_it() {
+        let note = Note::new(0, 960, 60, 100);
+        let (mut editor, backend) = playing_editor(vec![note]);
+        editor.advance_playback(BEAT * 0.5);
+
+        // Transposed while sounding: the old key must not keep ringing.
+        editor.edit_note_by_id(note.id, |n| n.key = 64);
+        assert!(backend.ringing_keys().is_empty());
+
+        // Shifted later than the playhead: released now, retriggered when reached.
+        editor.edit_note_by_id(note.id, |n| n.start = 1440);
+        editor.advance_playback(BEAT * 3.0);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([64]));
+        editor.advance_playback(BEAT * 2.0);
+        assert!(backend.ringing_keys().is_empty());
+    }
+
+    #[test]
+    fn inserting_under_playhead_respects_setting() {
+        let (mut editor, backend) = playing_editor(Vec::new());
+        editor.advance_playback(BEAT);
+        editor.insert_note(Note::new(0, 720, 60, 100));
+        assert_eq!(backend.count(Sent::On(60)), 0);
+        editor.advance_playback(BEAT);
+
+        editor.trigger_notes_under_playhead = true;
+        let id = editor.insert_note(Note::new(720, 960, 62, 100));
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([62]));
+
+        // Moving it while it still spans the playhead must not retrigger it.
+        editor.edit_note_by_id(id, |n| n.start = 840);
+        assert_eq!(backend.count(Sent::On(62)), 1);
+
+        editor.advance_playback(BEAT * 2.0);
+        assert!(backend.ringing_keys().is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo_mid_playback_leave_no_stuck_notes() {
+        let (mut editor, backend) = playing_editor(Vec::new());
+        editor.trigger_notes_under_playhead = true;
+        editor.advance_playback(BEAT * 0.5);
+        editor.insert_note(Note::new(0, 960, 60, 100));
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+
+        assert!(editor.undo());
+        assert!(backend.ringing_keys().is_empty());
+
+        assert!(editor.redo());
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+
+        editor.advance_playback(BEAT * 2.0);
+        assert!(backend.ringing_keys().is_empty());
+    }
+
+    #[test]
+    fn external_drop_rescales_and_inserts_as_one_undo_step() {
+        let source = [Note::new(960, 480, 60, 90), Note::new(1440, 240, 64, 80)];
+        let payload = NoteDragPayload::from_notes(&source, 960, 1000, 60);
+        assert_eq!(payload.notes[0].start, 0);
+        assert_eq!(payload.notes[1].start, 240);
+        assert_eq!(payload.anchor_tick, 20);
+
+        let (mut editor, _backend) = playing_editor(vec![Note::new(0, 480, 48, 100)]);
+        let notes = payload.notes_for(editor.state.ticks_per_beat);
+        let inserted = editor.accept_external_drop(&notes, 1920, 2);
+        assert_eq!(inserted.len(), 2);
+        assert_eq!(editor.state.notes.len(), 3);
+        let dropped: Vec<(u64, u64, u8)> = editor.state.notes[1..]
+            .iter()
+            .map(|n| (n.start, n.duration, n.key))
+            .collect();
+        assert_eq!(dropped, vec![(1920, 240, 62), (2160, 120, 66)]);
+        assert_eq!(editor.selected_notes.len(), 2);
+
+        assert!(editor.undo());
+        assert_eq!(editor.state.notes.len(), 1);
+    }
+
+    #[test]
+    fn humanize_with_a_seed_is_reproducible_and_matches_the_preview() {
+        let notes: Vec<Note> = (0..16).map(|i| Note::new(480 + i * 240, 120, 60 + i as u8, 100)).collect();
+        let state = MidiState { notes: notes.clone(), ..MidiState::default() };
+        let ids: Vec<NoteId> = notes.iter().map(|n| n.id).collect();
+        let humanize = |delay_only| {
+            let mut editor = MidiEditor::with_state(state.clone(), None);
+            editor.selected_notes = ids.iter().copied().collect();
+            let preview = editor.state.humanize_preview_count(&ids, 30, 10, delay_only, 7);
+            let depth = editor.undo_depth();
+            editor.apply_command(EditorCommand::HumanizeNotes {
+                time_range: 30,
+                velocity_range: 10,
+                delay_only,
+                seed: Some(7),
             });
+            assert_eq!(editor.undo_depth(), depth + 1);
+            let changed = editor.state.notes.iter().filter(|n| !notes.contains(n)).count();
+            assert_eq!(changed, preview);
+            editor
+        };
+
+        let first = humanize(false);
+        assert_eq!(first.state.notes, humanize(false).state.notes);
+        assert!(first.state.notes.iter().all(|n| (1..=127).contains(&n.velocity)));
+
+        // 只延后：每个音符都不早于原来的起点
+        let delayed = humanize(true);
+        for original in &notes {
+            let note = delayed.state.notes.iter().find(|n| n.id == original.id).unwrap();
+            assert!(note.start >= original.start && note.start <= original.start + 30);
+        }
+
+        // 范围为 0 时什么都不改变，也不留下撤销步骤
+        let mut editor = MidiEditor::with_state(state.clone(), None);
+        editor.selected_notes = ids.iter().copied().collect();
+        let depth = editor.undo_depth();
+        editor.apply_command(EditorCommand::HumanizeNotes { time_range: 0, velocity_range: 0, delay_only: false, seed: None });
+        assert_eq!(editor.undo_depth(), depth);
+        assert_eq!(editor.state.notes, notes);
+    }
+
+    #[test]
+    fn paste_at_transposes_to_the_anchor_and_honours_the_paste_mode() {
+        let phrase = [Note::new(0, 240, 64, 100), Note::new(240, 240, 67, 100)];
+        let existing = [Note::new(960, 240, 48, 90), Note::new(1200, 240, 51, 90), Note::new(1920, 240, 48, 90)];
+        let state = MidiState {
+            notes: phrase.iter().chain(&existing).copied().collect(),
+            ..MidiState::default()
+        };
+        let pitches = |editor: &MidiEditor| editor.state.notes.iter().map(|n| (n.start, n.key)).collect::<Vec<_>>();
+        let paste = |mode| {
+            let mut editor = MidiEditor::with_state(state.clone(), None);
+            editor.selected_notes = phrase.iter().map(|n| n.id).collect();
+            editor.copy_selection();
+            editor.take_events();
+            let depth = editor.undo_depth();
+            editor.apply_command(EditorCommand::PasteAt { tick: 960, key_anchor: Some(48), mode });
+            assert_eq!(editor.undo_depth(), depth + 1, "{mode:?} is one undo step");
+            editor
+        };
+
+        // 最低音落在 48 上，原有的 (960, 48) 保留
+        let editor = paste(PasteMode::Insert);
+        assert_eq!(
+            pitches(&editor),
+            vec![(0, 64), (240, 67), (960, 48), (960, 48), (1200, 51), (1200, 51), (1920, 48)]
+        );
+
+        // 起点在 960..1440 内的音符先被删除，范围外的保留
+        let mut editor = paste(PasteMode::Replace);
+        assert_eq!(pitches(&editor), vec![(0, 64), (240, 67), (960, 48), (1200, 51), (1920, 48)]);
+        assert!(!editor.state.notes.iter().any(|n| n.id == existing[0].id));
+        let events = editor.take_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteDeleted(_))).count(), 2);
+        editor.undo();
+        assert_eq!(editor.state.notes.len(), 5);
+
+        // 与已有音符重复的不再加入，整块限制在音高范围内
+        let editor = paste(PasteMode::MergeSkipDuplicates);
+        assert_eq!(pitches(&editor), vec![(0, 64), (240, 67), (960, 48), (1200, 51), (1920, 48)]);
+        assert!(editor.selected_notes.is_empty());
+        let mut editor = MidiEditor::with_state(state.clone(), None);
+        editor.selected_notes = phrase.iter().map(|n| n.id).collect();
+        editor.copy_selection();
+        editor.apply_command(EditorCommand::PasteAt { tick: 3840, key_anchor: Some(127), mode: PasteMode::Insert });
+        assert_eq!(pitches(&editor)[5..], [(3840, 124), (4080, 127)]);
+    }
+
+    #[test]
+    fn groups_select_together_survive_paste_and_dissolve_on_delete() {
+        let a = Note::new(0, 240, 60, 100);
+        let b = Note::new(240, 240, 62, 100);
+        let c = Note::new(480, 240, 64, 100);
+        let mut editor = MidiEditor::with_state(
+            MidiState {
+                notes: vec![a, b, c],
+                ..MidiState::default()
+            },
+            None,
+        );
+        editor.selected_notes = BTreeSet::from([a.id, b.id]);
+        let group = editor.group_selected_notes().unwrap();
+
+        editor.handle_note_click(b.id, Modifiers::NONE);
+        assert_eq!(editor.selected_notes, BTreeSet::from([a.id, b.id]));
+        editor.handle_note_click(b.id, Modifiers::ALT);
+        assert_eq!(editor.selected_notes, BTreeSet::from([b.id]));
+
+        editor.selected_notes = BTreeSet::from([a.id, b.id]);
+        editor.copy_selection();
+        editor.paste_clipboard_at(960);
+        assert_eq!(editor.state.groups.len(), 2);
+        assert!(editor.state.groups[1].note_ids.iter().all(|id| editor.selected_notes.contains(id)));
+
+        editor.take_events();
+        editor.remove_notes([a.id, b.id]);
+        assert_eq!(editor.state.groups.len(), 1);
+        assert!(editor
+            .take_events()
+            .iter()
+            .any(|e| matches!(e, EditorEvent::GroupDissolved(id) if *id == group)));
+    }
+
+    #[test]
+    fn stopping_clears_sounding_notes() {
+        let note = Note::new(0, 960, 60, 100);
+        let (mut editor, backend) = playing_editor(vec![note]);
+        editor.advance_playback(BEAT * 0.5);
+        editor.apply_command(EditorCommand::SetPlayback(false));
+        assert!(backend.ringing_keys().is_empty());
+        assert!(editor.sounding_notes.is_empty());
+
+        // Deleting after stop must not send a second note_off.
+        let offs = backend.count(Sent::Off(60));
+        editor.remove_notes([note.id]);
+        assert_eq!(backend.count(Sent::Off(60)), offs);
+    }
+
+    #[test]
+    fn render_to_image_is_deterministic_and_ignores_view_state() {
+        let mut state = MidiState::default();
+        state.notes.push(Note::new(480, 480, 71, 100));
+        state.notes.push(Note::new(960, 240, 40, 100));
+        let mut editor = MidiEditor::with_state(state, None);
+
+        let image = editor.render_to_image(192, 120, 0..1920, 60..=71, false);
+        assert_eq!(image.size, [192, 120]);
+        // 4 beats over 192px and 12 keys over 120px: the note spans x 48..96, y 0..10.
+        let dark = MidiEditorTheme::dark();
+        assert_eq!(image.pixels[5 * 192 + 72], dark.note);
+        assert_eq!(image.pixels[55 * 192 + 20], dark.background);
+
+        editor.zoom_x *= 3.0;
+        editor.manual_scroll_y -= 200.0;
+        editor.selected_notes.extend(editor.state.notes.iter().map(|n| n.id));
+        assert_eq!(editor.render_to_image(192, 120, 0..1920, 60..=71, false), image);
+
+        let with_lane = editor.render_to_image(192, 120, 0..1920, 60..=71, true);
+        assert_ne!(with_lane, image);
+        assert_eq!(with_lane, editor.render_to_image(192, 120, 0..1920, 60..=71, true));
+    }
+
+    #[test]
+    fn loop_follows_the_selection_by_bar_and_exports_only_the_loop() {
+        let notes = [Note::new(0, 480, 60, 100), Note::new(2000, 400, 62, 100), Note::new(3700, 400, 64, 100)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.to_vec(), ..MidiState::default() }, None);
+        editor.loop_enabled = false;
+        editor.apply_command(EditorCommand::SetLoopToSelection);
+        assert_eq!((editor.loop_start_tick, editor.loop_end_tick), (0, 1920), "no selection leaves the loop alone");
+
+        editor.selected_notes = BTreeSet::from([notes[1].id]);
+        editor.apply_command(EditorCommand::SetLoopToSelection);
+        assert!(editor.loop_enabled);
+        assert_eq!((editor.loop_start_tick, editor.loop_end_tick), (1920, 3840));
+
+        // 第三个音符跨过循环终点，被裁到终点上
+        let exported = MidiState::from_smf(&editor.export_loop_smf().unwrap());
+        let spans: Vec<(u64, u64, u8)> = exported.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+        assert_eq!(spans, vec![(80, 400, 62), (1780, 140, 64)]);
+
+        editor.set_loop_range(5000, 6000);
+        assert_eq!(editor.export_loop_smf().unwrap_err(), LoopExportError::NoNotes);
+        editor.set_loop_range(6000, 6000);
+        assert_eq!(editor.export_loop_smf().unwrap_err(), LoopExportError::EmptyLoop);
+    }
+
+    #[test]
+    fn new_notes_use_the_chosen_length_mode_and_default_velocity() {
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        editor.apply_options(&MidiEditorOptions {
+            note_length: NoteLength::Fixed(SnapResolution::Quarter, SnapModifier::Dotted),
+            default_velocity: 72,
+            ..MidiEditorOptions::default()
+        });
+        let create = |editor: &mut MidiEditor, tick: i64| {
+            editor.create_note_at_pointer(Pos2::ZERO, |_| tick, |_| 60);
+            let note = editor.note_by_id(editor.drag_primary_anchor.unwrap()).unwrap();
+            editor.finalize_note_drag_if_needed();
+            editor.is_dragging_note = false;
+            editor.drag_action = DragAction::None;
+            (note.duration, note.velocity)
+        };
+        assert_eq!(create(&mut editor, 0), (720, 72));
+
+        // 吸附模式：十六分音符网格
+        editor.note_length = NoteLength::Snap;
+        assert_eq!(create(&mut editor, 960).0, editor.snap_ticks());
+
+        // 最近一次调整过时值的音符
+        editor.note_length = NoteLength::LastUsed;
+        let resized = editor.state.notes[0].id;
+        editor.drag_primary_anchor = Some(resized);
+        editor.drag_action = DragAction::ResizeEnd;
+        editor.edit_note_by_id(resized, |n| n.duration = 300);
+        editor.drag_changed_note = true;
+        editor.finalize_note_drag_if_needed();
+        assert_eq!(create(&mut editor, 1920), (300, 72));
     }
 
-    fn draw_dashed_vertical_line(painter: &Painter, x: f32, top: f32, bottom: f32, stroke: Stroke) {
-        let dash_len = 2.0;
-        let gap_len = 2.0;
-        let mut y = top;
-        while y < bottom {
-            let next = (y + dash_len).min(bottom);
-            painter.line_segment([Pos2::new(x, y), Pos2::new(x, next)], stroke.clone());
-            y += dash_len + gap_len;
-        }
+    #[test]
+    fn edit_bounds_clamp_paste_and_drop_and_reject_creation_outside() {
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        editor.set_edit_bounds(Some((0, 1920)));
+        editor.snap_resolution = None;
+        editor.snap_interval = 0;
+
+        editor.create_note_at_pointer(Pos2::ZERO, |_| 4000, |_| 60);
+        assert!(editor.state.notes.is_empty());
+        assert!(editor.toast.is_some());
+
+        editor.create_note_at_pointer(Pos2::ZERO, |_| 1900, |_| 60);
+        let created = editor.state.notes[0];
+        assert_eq!(created.start + created.duration, 1920);
+
+        editor.clipboard = vec![Note::new(0, 480, 62, 100), Note::new(240, 480, 64, 100)];
+        editor.paste_clipboard_at(3000);
+        let pasted: Vec<Note> = editor.state.notes.iter().filter(|n| n.key != 60).copied().collect();
+        assert_eq!(pasted.iter().map(|n| n.start).min(), Some(1920 - 720));
+        assert!(pasted.iter().all(|n| n.start + n.duration <= 1920));
+
+        let dropped = editor.accept_external_drop(&[Note::new(0, 480, 70, 100)], 5000, 0);
+        assert_eq!(editor.note_by_id(dropped[0]).map(|n| n.start), Some(1440));
+
+        editor.set_edit_bounds(None);
+        editor.paste_clipboard_at(3000);
+        assert!(editor.state.notes.iter().any(|n| n.start == 3000));
     }
 
-    fn note_index_by_id(&self, id: NoteId) -> Option<usize> {
-        self.state.notes.iter().position(|n| n.id == id)
+    #[test]
+    fn cc_lanes_play_back_past_the_last_note() {
+        let (mut editor, backend) = playing_editor(vec![Note::new(0, 240, 60, 100)]);
+        editor.apply_command(EditorCommand::AddCurveLane { cc_number: 64 });
+        let lane_id = editor.displayed_curve_lane().unwrap();
+        let lane = editor.state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        assert_eq!(lane.name, "Sustain (CC64)");
+        // 同一 tick 的两个点：960 之前保持 0，从 960 起为 127
+        lane.insert_point(0, 0.0);
+        lane.insert_point(960, 0.0);
+        lane.insert_point(960, 127.0);
+
+        editor.advance_playback(BEAT * 0.5);
+        editor.advance_playback(BEAT * 0.5);
+        assert_eq!(backend.count(Sent::Cc(64, 0)), 1);
+        editor.advance_playback(BEAT * 1.5);
+        assert_eq!(backend.count(Sent::Cc(64, 127)), 1);
+
+        // 再次添加同一控制器只切换显示，不产生第二条曲线
+        editor.apply_command(EditorCommand::AddCurveLane { cc_number: 64 });
+        let cc_lanes = editor.state.curves.iter().filter(|lane| lane.lane_type.cc_number().is_some()).count();
+        assert_eq!(cc_lanes, 1);
     }
 
-    fn sort_notes(&mut self) {
-        self.state
-            .notes
-            .sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.id.0.cmp(&b.id.0)));
+    #[test]
+    fn sustain_pedal_defers_note_off_until_release() {
+        let (mut editor, backend) =
+            playing_editor(vec![Note::new(0, 240, 60, 100), Note::new(480, 240, 64, 100), Note::new(720, 240, 60, 100)]);
+        editor.apply_command(EditorCommand::AddCurveLane { cc_number: 64 });
+        let lane_id = editor.displayed_curve_lane().unwrap();
+        let lane = editor.state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        assert_eq!(lane.interpolation, CurveInterpolation::Step);
+        // 半踩的值归为踩下；踏板在 tick 1440 抬起
+        lane.insert_point(0, 90.0);
+        lane.insert_point(1440, 10.0);
+        assert_eq!(lane.points.iter().map(|p| p.value).collect::<Vec<_>>(), vec![127.0, 0.0]);
+
+        editor.advance_playback(BEAT * 0.5);
+        editor.advance_playback(BEAT * 0.75);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60, 64]));
+        assert_eq!(backend.count(Sent::Off(60)), 0);
+
+        // 60 在 tick 720 重新触发；之后的结束仍在踏板下，直到踏板抬起才一起释放
+        editor.advance_playback(BEAT * 0.5);
+        editor.advance_playback(BEAT * 1.0);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60, 64]));
+        editor.advance_playback(BEAT * 0.5);
+        assert!(backend.ringing_keys().is_empty());
+        assert_eq!(backend.count(Sent::Off(60)), 1);
+
+        // 停止播放时被保持的键由 all_notes_off 一并释放
+        editor.apply_command(EditorCommand::SeekSeconds(0.0));
+        editor.advance_playback(BEAT * 1.0);
+        editor.apply_command(EditorCommand::SetPlayback(false));
+        assert!(backend.ringing_keys().is_empty());
+        assert!(editor.sustained_keys.is_empty());
     }
 
-    fn push_undo_snapshot(&mut self) {
-        const MAX_HISTORY: usize = 64;
-        self.undo_stack.push(self.state.clone());
-        if self.undo_stack.len() > MAX_HISTORY {
-            self.undo_stack.remove(0);
+    #[test]
+    fn pitch_bend_lane_glides_and_recenters_on_stop() {
+        let (mut editor, backend) = playing_editor(vec![Note::new(0, 1920, 60, 100)]);
+        editor.apply_command(EditorCommand::AddPitchBendLane);
+        let lane_id = editor.displayed_curve_lane().unwrap();
+        let lane = editor.state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        assert_eq!(lane.lane_type, CurveLaneType::PitchBend);
+        // 两拍内从不弯音滑到最高
+        lane.insert_point(0, 0.0);
+        lane.insert_point(960, 8191.0);
+
+        for _ in 0..8 {
+            editor.advance_playback(BEAT * 0.25);
         }
-        self.redo_stack.clear();
+        let bends: Vec<i16> = backend
+            .sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|msg| match msg {
+                Sent::Bend(value) => Some(*value),
+                _ => None,
+            })
+            .collect();
+        assert!(bends.len() >= 4);
+        assert!(bends.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(bends.last(), Some(&8191));
+
+        editor.apply_command(EditorCommand::SetPlayback(false));
+        assert_eq!(backend.sent.lock().unwrap().last(), Some(&Sent::Bend(0)));
     }
 
-    fn note_mut_by_id(&mut self, id: NoteId) -> Option<&mut Note> {
-        let idx = self.note_index_by_id(id)?;
-        self.state.notes.get_mut(idx)
+    #[test]
+    fn playback_follows_the_tempo_map() {
+        // 前两拍 120 BPM（1 秒），之后 240 BPM：tick 1440 的音符在 1.25 秒处发声
+        let (mut editor, backend) = playing_editor(vec![Note::new(1440, 240, 64, 100)]);
+        editor.state.insert_tempo_change(960, 240.0);
+        editor.advance_playback(1.2);
+        assert!(backend.ringing_keys().is_empty());
+        editor.advance_playback(0.1);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([64]));
+        assert!(editor.current_tick_position().abs_diff(1536) <= 1);
+
+        // 工具栏的 BPM 编辑播放头所在的速度段
+        editor.apply_command(EditorCommand::SetTempoAt { tick: editor.current_tick_position(), bpm: 200.0 });
+        assert_eq!(editor.state.bpm, 120.0);
+        assert_eq!(editor.state.tempo_changes, vec![crate::structure::TempoChange { tick: 960, bpm: 200.0 }]);
     }
 
-    fn note_by_id(&self, id: NoteId) -> Option<Note> {
-        self.state.notes.iter().copied().find(|n| n.id == id)
+    #[test]
+    fn midi_clock_sync_drives_transport_and_note_scheduling() {
+        use crate::sync::{AtomicTransport, TransportSync};
+
+        let backend = Arc::new(RecordingPlayback::default());
+        let state = MidiState {
+            notes: vec![Note::new(480, 480, 60, 100), Note::new(1440, 480, 62, 100)],
+            ..MidiState::default()
+        };
+        let mut editor = MidiEditor::with_state(state, Some(backend.clone() as PlaybackHandle));
+        let transport = Arc::new(AtomicTransport::new(120.0));
+        editor.set_sync(Some(Box::new(TransportSync(transport.clone()))));
+
+        transport.handle_midi_message(0, &[0xFA]);
+        editor.follow_sync();
+        assert!(editor.is_playing);
+
+        // 36 clocks at 120 bpm = 1.5 beats
+        for i in 1..=36u64 {
+            transport.handle_midi_message(i * 20_833, &[0xF8]);
+        }
+        editor.follow_sync();
+        assert!((editor.current_time - 1.5 * BEAT).abs() < 1e-3);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([60]));
+
+        transport.handle_midi_message(36 * 20_833, &[0xFC]);
+        editor.follow_sync();
+        assert!(!editor.is_playing);
+        assert!(backend.ringing_keys().is_empty());
+
+        // Song Position Pointer: 12 sixteenths = beat 3
+        transport.handle_midi_message(0, &[0xF2, 12, 0]);
+        editor.follow_sync();
+        assert!((editor.current_time - 3.0 * BEAT).abs() < 1e-3);
     }
 
-    fn first_selected_note(&self) -> Option<Note> {
-        self.selected_notes
+    #[test]
+    fn scale_highlight_shades_only_visible_rows() {
+        let mut editor = MidiEditor::with_state_and_options(
+            MidiState::default(),
+            None,
+            MidiEditorOptions { scale_highlight: Some((14, ScaleKind::Dorian)), ..MidiEditorOptions::default() },
+        );
+        assert_eq!(editor.scale_highlight(), Some((2, ScaleKind::Dorian)));
+        editor.apply_command(EditorCommand::SetScaleHighlight(Some((9, ScaleKind::MinorPentatonic))));
+        let keys = ScaleKind::MinorPentatonic.keys(9);
+        assert!([57, 60, 62, 64, 67, 69].iter().all(|key| keys.contains(*key)));
+        assert!(!keys.contains(59) && !keys.contains(61));
+
+        // 向下滚动 10 行、行高 20：可见的 100 像素只覆盖音高 117..=113
+        let mut shapes = Vec::new();
+        render::scale_rows(&mut shapes, &MidiEditorTheme::dark(), keys, 9, &RowLayout::new(None).keys, -200.0, 20.0, Rangef::new(0.0, 300.0), Rangef::new(0.0, 100.0));
+        let colors: Vec<Color32> = shapes
             .iter()
-            .next()
-            .and_then(|id| self.note_by_id(*id))
+            .map(|shape| match shape {
+                Shape::Rect(rect) => rect.fill,
+                _ => unreachable!(),
+            })
+            .collect();
+        let out_of_scale = Color32::from_black_alpha(70);
+        // 117 = A（主音）、116、115 = G、114、113
+        assert_eq!(colors.len(), 5);
+        assert_eq!(colors[0], Color32::from_white_alpha(16));
+        assert_eq!(colors[1], out_of_scale);
+        assert_eq!(colors[2], Color32::from_white_alpha(7));
+        assert_eq!(colors[3], out_of_scale);
+
+        editor.apply_command(EditorCommand::SetScaleHighlight(None));
+        assert_eq!(editor.scale_highlight(), None);
     }
 
-    fn selected_notes_snapshot(&self) -> Vec<Note> {
-        self.selected_notes
-            .iter()
-            .filter_map(|id| self.note_by_id(*id))
-            .collect()
+    #[test]
+    fn step_input_inserts_at_the_cursor_rests_and_holds_chords() {
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        editor.set_snap_interval(240);
+        editor.apply_command(EditorCommand::StepInput { key: 60, velocity: 100 });
+        assert!(editor.state.notes.is_empty(), "disabled step input ignores keys");
+
+        editor.apply_command(EditorCommand::SetStepInput(true));
+        editor.apply_command(EditorCommand::StepInput { key: 60, velocity: 100 });
+        editor.apply_command(EditorCommand::StepRest);
+        // 按住 Shift 输入和弦：光标停在原处，松开后前进一步
+        editor.update_step_modifier(true);
+        editor.apply_command(EditorCommand::StepInput { key: 64, velocity: 90 });
+        editor.apply_command(EditorCommand::StepInput { key: 67, velocity: 90 });
+        assert_eq!(editor.step_cursor(), 480);
+        editor.update_step_modifier(false);
+        assert_eq!(editor.step_cursor(), 720);
+
+        let notes: Vec<_> = editor.state.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+        assert_eq!(notes, vec![(0, 240, 60), (480, 240, 64), (480, 240, 67)]);
+
+        // 编辑范围截断最后一步的时值，范围外只提示
+        editor.set_edit_bounds(Some((0, 800)));
+        editor.apply_command(EditorCommand::StepInput { key: 72, velocity: 100 });
+        assert_eq!(editor.state.notes.last().map(|n| (n.start, n.duration)), Some((720, 80)));
+        editor.apply_command(EditorCommand::StepInput { key: 72, velocity: 100 });
+        assert_eq!(editor.state.notes.len(), 4);
+        assert!(editor.toast.is_some());
     }
 
-    fn edit_note_by_id<F>(&mut self, id: NoteId, mut edit: F)
-    where
-        F: FnMut(&mut Note),
-    {
-        if let Some(idx) = self.note_index_by_id(id) {
-            self.push_undo_snapshot();
-            let before = self.state.notes[idx];
-            edit(&mut self.state.notes[idx]);
-            self.state.notes[idx].duration = self.state.notes[idx].duration.max(1);
-            let after = self.state.notes[idx];
-            self.sort_notes();
-            self.emit_note_updated(before, after);
-        }
+    #[test]
+    fn recording_writes_a_take_as_one_undo_step() {
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        let seek = |editor: &mut MidiEditor, tick: u64| {
+            editor.current_time = editor.state.ticks_to_seconds(tick as f64) as f32;
+            editor.update_recording();
+        };
+        editor.apply_command(EditorCommand::SetPlayback(true));
+        editor.feed_live_note_on(60, 100);
+        assert!(editor.state.notes.is_empty(), "not recording");
+
+        editor.apply_command(EditorCommand::SetRecording(true));
+        seek(&mut editor, 480);
+        editor.feed_live_note_on(60, 100);
+        seek(&mut editor, 720);
+        assert_eq!(editor.state.notes[0].duration, 240, "held notes grow with the playhead");
+        editor.feed_live_note_on(64, 90);
+        seek(&mut editor, 840);
+        editor.feed_live_note_off(60);
+        seek(&mut editor, 960);
+        // 停止时仍按住的音符在停止处结束
+        editor.apply_command(EditorCommand::SetPlayback(false));
+        editor.finish_recording_take();
+
+        let notes: Vec<_> = editor.state.notes.iter().map(|n| (n.start, n.duration, n.key, n.velocity)).collect();
+        assert_eq!(notes, vec![(480, 360, 60, 100), (720, 240, 64, 90)]);
+        editor.undo();
+        assert!(editor.state.notes.is_empty());
+
+        editor.record_quantize = true;
+        editor.set_snap_interval(480);
+        editor.apply_command(EditorCommand::SetPlayback(true));
+        seek(&mut editor, 500);
+        editor.feed_live_note_on(67, 100);
+        seek(&mut editor, 700);
+        editor.feed_live_note_off(67);
+        assert_eq!(editor.state.notes.iter().map(|n| (n.start, n.duration)).collect::<Vec<_>>(), vec![(480, 480)]);
     }
 
-    fn copy_selection(&mut self) {
-        self.clipboard = self.selected_notes_snapshot();
-        self.clipboard.sort_by_key(|n| n.start);
+    #[test]
+    fn resolving_overlaps_is_one_undo_step_and_reports_the_count() {
+        let notes = vec![Note::new(0, 480, 60, 100), Note::new(240, 480, 60, 100), Note::new(0, 240, 64, 100), Note::new(120, 240, 64, 100)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.clone(), ..MidiState::default() }, None);
+        let resolved = |editor: &mut MidiEditor| {
+            editor.take_events().into_iter().find_map(|e| match e {
+                EditorEvent::OverlapsResolved { count } => Some(count),
+                _ => None,
+            })
+        };
+
+        // 有选区时只处理选中的音符
+        editor.selected_notes = BTreeSet::from([notes[0].id, notes[1].id]);
+        editor.apply_command(EditorCommand::ResolveOverlaps { policy: OverlapPolicy::DeleteShorter });
+        assert_eq!(resolved(&mut editor), Some(1));
+        assert_eq!(editor.state.find_overlaps(), vec![(notes[2].id, notes[3].id)]);
+        assert_eq!(editor.selected_notes, BTreeSet::from([notes[0].id]));
+
+        editor.selected_notes.clear();
+        editor.apply_command(EditorCommand::ResolveOverlaps { policy: OverlapPolicy::MergeIntoOne });
+        assert_eq!(resolved(&mut editor), Some(2));
+        assert!(editor.state.find_overlaps().is_empty());
+        editor.apply_command(EditorCommand::ResolveOverlaps { policy: OverlapPolicy::MergeIntoOne });
+        assert_eq!(resolved(&mut editor), Some(0));
+
+        editor.undo();
+        assert_eq!(editor.state.notes.len(), 3);
     }
 
-    fn cut_selection(&mut self) {
-        if self.selected_notes.is_empty() {
-            return;
-        }
-        self.copy_selection();
-        let ids: Vec<_> = self.selected_notes.iter().copied().collect();
-        self.remove_notes(ids);
+    #[test]
+    fn validate_command_selects_offending_notes_and_reports_issues() {
+        let ok = Note::new(0, 480, 60, 100);
+        let silent = Note { velocity: 0, ..Note::new(480, 480, 62, 100) };
+        let late = Note::new(1800, 480, 64, 100);
+        let mut editor = MidiEditor::with_state(MidiState { notes: vec![ok, silent, late], ..MidiState::default() }, None);
+        editor.selected_notes.insert(ok.id);
+        (editor.loop_enabled, editor.loop_start_tick, editor.loop_end_tick) = (true, 960, 480);
+        let reported = |editor: &mut MidiEditor| {
+            editor.take_events().into_iter().find_map(|e| match e {
+                EditorEvent::ValidationFinished { issues } => Some(issues),
+                _ => None,
+            })
+        };
+
+        editor.apply_command(EditorCommand::ValidateState { max_tick: Some(1920) });
+        let issues = reported(&mut editor).unwrap();
+        assert_eq!(issues.len(), 3);
+        assert!(issues.iter().any(|issue| matches!(issue, ValidationIssue::InvalidLoop { .. })));
+        assert_eq!(editor.selected_notes, BTreeSet::from([silent.id, late.id]));
+
+        editor.apply_command(EditorCommand::ValidateState { max_tick: None });
+        assert_eq!(reported(&mut editor).unwrap().len(), 2);
     }
 
-    fn paste_clipboard_at(&mut self, target_tick: u64) {
-        if self.clipboard.is_empty() {
-            return;
-        }
-        let min_start = self
-            .clipboard
-            .iter()
-            .map(|n| n.start)
-            .min()
-            .unwrap_or(target_tick);
-        let offset = target_tick.saturating_sub(min_start);
-        let templates = self.clipboard.clone();
-        self.push_undo_snapshot();
-        let previous = self.selected_notes.clone();
-        self.selected_notes.clear();
-        for template in templates {
-            let new_note = Note::new(
-                template.start + offset,
-                template.duration,
-                template.key,
-                template.velocity,
-            );
-            self.state.notes.push(new_note);
-            self.emit_note_added(new_note);
-            self.selected_notes.insert(new_note.id);
-        }
-        self.sort_notes();
-        self.notify_selection_changed(previous);
+    #[test]
+    fn quantize_moves_starts_and_ends_partway_in_one_undo_step() {
+        let a = Note::new(100, 250, 60, 100);
+        let b = Note::new(500, 30, 62, 100);
+        let mut editor = MidiEditor::with_state(MidiState { notes: vec![a, b], ..MidiState::default() }, None);
+        let select_all = |editor: &mut MidiEditor| editor.selected_notes = BTreeSet::from([a.id, b.id]);
+        let spans = |editor: &MidiEditor| [a.id, b.id].map(|id| editor.note_by_id(id).map(|n| (n.start, n.duration)).unwrap());
+
+        select_all(&mut editor);
+        editor.apply_command(EditorCommand::Quantize { targets: QuantizeTargets::Starts, strength: 0.5, interval: 240 });
+        assert_eq!(spans(&editor), [(50, 250), (490, 30)]);
+        editor.undo();
+
+        select_all(&mut editor);
+        editor.apply_command(EditorCommand::Quantize { targets: QuantizeTargets::Ends, strength: 1.0, interval: 240 });
+        assert_eq!(spans(&editor), [(100, 140), (500, 1)], "durations never drop below one tick");
+        editor.undo();
+
+        select_all(&mut editor);
+        editor.take_events();
+        editor.apply_command(EditorCommand::Quantize { targets: QuantizeTargets::Both, strength: 1.0, interval: 240 });
+        assert_eq!(spans(&editor), [(0, 240), (480, 1)]);
+        assert_eq!(editor.take_events().iter().filter(|e| matches!(e, EditorEvent::NoteUpdated { .. })).count(), 2);
+        editor.undo();
+        assert_eq!(spans(&editor), [(100, 250), (500, 30)]);
     }
 
-    fn delete_selected_notes(&mut self) {
-        if self.selected_notes.is_empty() {
-            return;
+    #[test]
+    fn nudge_clamps_the_whole_selection_and_keeps_notes_sorted() {
+        let a = Note::new(100, 240, 125, 100);
+        let b = Note::new(300, 240, 60, 100);
+        let c = Note::new(200, 240, 40, 100);
+        let mut editor = MidiEditor::with_state(MidiState { notes: vec![a, c, b], ..MidiState::default() }, None);
+        editor.selected_notes = BTreeSet::from([a.id, b.id]);
+        let spans = |editor: &MidiEditor| [a.id, b.id].map(|id| editor.note_by_id(id).map(|n| (n.start, n.key)).unwrap());
+
+        // 只能前移 100 tick、上移 2 个半音，两个音符的间距保持不变
+        editor.nudge_selected_notes(-480, 12);
+        assert_eq!(spans(&editor), [(0, 127), (200, 62)]);
+        assert!(editor.state.notes.windows(2).all(|w| w[0].start <= w[1].start));
+        editor.nudge_selected_notes(-1, 1);
+        assert_eq!(editor.undo_stack.len(), 1, "a move that is fully clamped is not an undo step");
+
+        // 自动重复沿用第一次按键的快照
+        editor.nudge_selection(120, -12, false);
+        editor.nudge_selection(120, -12, true);
+        assert_eq!(spans(&editor), [(240, 103), (440, 38)]);
+        editor.undo();
+        assert_eq!(spans(&editor), [(0, 127), (200, 62)]);
+
+        // 有允许音高时移调跳到下一个允许的音高
+        let mut allowed = BitSet128::default();
+        for key in [62, 64, 127] {
+            allowed.insert(key);
         }
-        let ids: Vec<_> = self.selected_notes.iter().copied().collect();
-        self.remove_notes(ids);
+        editor.allowed_keys = Some(allowed);
+        editor.selected_notes = BTreeSet::from([b.id]);
+        editor.nudge_selected_notes(0, 1);
+        assert_eq!(editor.note_by_id(b.id).map(|n| n.key), Some(64));
     }
 
-    fn quantize_selected_notes(&mut self) {
-        if self.selected_notes.is_empty() || self.snap_interval == 0 {
-            return;
-        }
-        self.push_undo_snapshot();
-        let ids: Vec<_> = self.selected_notes.iter().copied().collect();
-        for id in ids {
-            let start_tick = self.note_by_id(id).map(|n| n.start).unwrap_or(0);
-            let snapped = self.snap_tick(start_tick as i64, None, false);
-            if let Some((before, after)) = self.note_mut_by_id(id).map(|note| {
-                let before = *note;
-                note.start = snapped;
-                let after = *note;
-                (before, after)
-            }) {
-                self.emit_note_updated(before, after);
-            }
-        }
-        self.sort_notes();
+    #[test]
+    fn undo_groups_collapse_commands_into_one_labelled_step() {
+        let mut editor = MidiEditor::new(None);
+        let history = |editor: &mut MidiEditor| {
+            editor
+                .take_events()
+                .into_iter()
+                .filter_map(|e| match e {
+                    EditorEvent::HistoryChanged { undo_depth, redo_depth } => Some((undo_depth, redo_depth)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        editor.apply_command(EditorCommand::AppendNotes(vec![Note::new(0, 480, 60, 100)]));
+        assert_eq!(history(&mut editor), vec![(1, 0)]);
+
+        editor.begin_undo_group("Build chord");
+        editor.begin_undo_group("ignored inner name");
+        editor.apply_command(EditorCommand::AppendNotes(vec![Note::new(0, 480, 64, 100)]));
+        editor.end_undo_group();
+        editor.apply_command(EditorCommand::AppendNotes(vec![Note::new(0, 480, 67, 100)]));
+        assert!(history(&mut editor).is_empty(), "nothing is reported while the group is open");
+        editor.end_undo_group();
+        assert_eq!(history(&mut editor), vec![(2, 0)]);
+        assert_eq!(editor.undo_labels(), vec![Some("Build chord"), None]);
+
+        // 空的组不产生撤销步骤
+        editor.begin_undo_group("Nothing");
+        editor.end_undo_group();
+        assert_eq!(editor.undo_depth(), 2);
+
+        assert!(editor.undo());
+        assert_eq!(editor.state.notes.len(), 1);
+        assert_eq!((editor.undo_depth(), editor.redo_depth()), (1, 1));
+        assert_eq!(editor.redo_labels(), vec![Some("Build chord")]);
+        assert!(editor.redo());
+        assert_eq!(editor.state.notes.len(), 3);
+        assert_eq!(editor.undo_labels()[0], Some("Build chord"));
     }
 
-    #[allow(dead_code)]
-    fn apply_swing_to_selected_notes(&mut self, swing_ratio: f32) {
-        if self.selected_notes.is_empty() || swing_ratio <= 0.0 {
-            return;
-        }
-        
-        self.push_undo_snapshot();
-        // Save original positions
-        self.swing_original_notes = self.selected_notes
-            .iter()
-            .filter_map(|&id| {
-                self.note_by_id(id).map(|note| (id, note.start))
-            })
-            .collect();
-        
-        self.apply_swing_to_selected_notes_realtime(swing_ratio);
+    #[test]
+    fn undoing_back_to_the_saved_revision_is_clean_again() {
+        let mut editor = MidiEditor::new(None);
+        editor.apply_options(&MidiEditorOptions {
+            max_undo_entries: 2,
+            ..MidiEditorOptions::default()
+        });
+        let dirty_events = |editor: &mut MidiEditor| {
+            editor
+                .take_events()
+                .into_iter()
+                .filter_map(|e| match e {
+                    EditorEvent::DirtyChanged(dirty) => Some(dirty),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(!editor.is_dirty());
+
+        editor.apply_command(EditorCommand::AppendNotes(vec![Note::new(0, 480, 60, 100)]));
+        let saved = editor.revision();
+        editor.mark_saved();
+        assert!(!editor.is_dirty());
+        editor.take_events();
+
+        editor.apply_command(EditorCommand::SetBpm(140.0));
+        editor.apply_command(EditorCommand::SetTimeSignature(3, 4));
+        assert!(editor.revision() > saved);
+        assert!(editor.is_dirty());
+        assert_eq!(dirty_events(&mut editor), vec![true]);
+
+        assert!(editor.undo());
+        assert!(editor.undo());
+        assert_eq!(editor.revision(), saved);
+        assert!(!editor.is_dirty());
+
+        // 撤销后的新编辑得到新的修订号，不会与重做栈中的步骤混淆
+        assert!(editor.redo());
+        let redone = editor.revision();
+        editor.undo();
+        editor.set_bpm(90.0);
+        assert!(editor.revision() > redone);
+
+        // 保存时的步骤被挤出撤销栈后，撤销到底仍然回到保存时的修订号
+        editor.mark_saved();
+        let saved = editor.revision();
+        editor.set_bpm(100.0);
+        editor.set_bpm(110.0);
+        assert_eq!(editor.undo_depth(), 2);
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.state.bpm, 90.0);
+        assert_eq!(editor.revision(), saved);
+        assert!(!editor.is_dirty());
+
+        editor.replace_state(MidiState::default());
+        assert!(editor.is_dirty());
+        editor.mark_saved();
+        assert!(!editor.is_dirty());
     }
 
-    fn apply_swing_to_selected_notes_realtime(&mut self, swing_ratio: f32) {
-        if self.selected_notes.is_empty() || self.swing_original_notes.is_empty() {
-            return;
+    #[test]
+    fn delta_history_round_trips_every_kind_of_edit() {
+        // 同一 tick 上 id 较大的音符排在前面：不是编辑器自己的排序，撤销后也要原样恢复
+        let a = Note::new(0, 480, 60, 100);
+        let b = Note::new(0, 480, 64, 100);
+        let c = Note::new(960, 480, 67, 100);
+        let state = MidiState { notes: vec![b, a, c], ..MidiState::default() };
+        let mut editor = MidiEditor::with_state(state, None);
+        let parts = |editor: &MidiEditor| (editor.state.notes.clone(), editor.state.curves.clone(), editor.state.bpm);
+        let mut seen = vec![parts(&editor)];
+
+        editor.insert_note(Note::new(480, 240, 62, 90));
+        seen.push(parts(&editor));
+        editor.selected_notes = BTreeSet::from([a.id]);
+        editor.delete_selected_notes();
+        seen.push(parts(&editor));
+        editor.selected_notes = BTreeSet::from([c.id]);
+        editor.nudge_selected_notes(-960, 0);
+        seen.push(parts(&editor));
+        editor.apply_command(EditorCommand::AddCurveLane { cc_number: 7 });
+        seen.push(parts(&editor));
+        let lane_id = editor.state.curves.last().unwrap().id;
+        editor.apply_command(EditorCommand::AddCurvePoint { lane_id, tick: 240, value: 0.5 });
+        seen.push(parts(&editor));
+        editor.set_bpm(90.0);
+        seen.push(parts(&editor));
+
+        for expected in seen.iter().rev().skip(1) {
+            assert!(editor.undo());
+            assert_eq!(&parts(&editor), expected);
         }
-        
-        let tpb = self.state.ticks_per_beat as u64;
-        let delay_ticks = (tpb as f32 * 0.5 * swing_ratio) as u64;
-        
-        // Clone original notes to avoid borrow checker issues
-        let original_notes = self.swing_original_notes.clone();
-        
-        // Restore original positions first, then apply swing
-        for (id, original_start) in &original_notes {
-            if let Some(note) = self.note_mut_by_id(*id) {
-                note.start = *original_start;
-            }
+        assert!(!editor.undo());
+        for expected in seen.iter().skip(1) {
+            assert!(editor.redo());
+            assert_eq!(&parts(&editor), expected);
         }
-        
-        // Now apply swing based on original positions
-        for (id, original_start) in &original_notes {
-            if let Some((before, after)) = self.note_mut_by_id(*id).map(|note| {
-                let before = *note;
-                let beat_position = original_start / tpb;
-                let is_even_beat = (beat_position % 2) == 1;
-                
-                if is_even_beat {
-                    note.start = original_start.saturating_add(delay_ticks);
-                } else {
-                    note.start = *original_start;
-                }
-                let after = *note;
-                (before, after)
-            }) {
-                if before.start != after.start {
-                    self.emit_note_updated(before, after);
-                }
-            }
+    }
+
+    #[test]
+    fn editing_fifty_thousand_notes_keeps_history_small() {
+        let notes: Vec<Note> = (0..50_000u64).map(|i| Note::new(i * 120, 100, 36 + (i % 48) as u8, 100)).collect();
+        let original = notes.clone();
+        let mut editor = MidiEditor::with_state(MidiState { notes, ..MidiState::default() }, None);
+        let state_bytes = state_size_estimate(&editor.state);
+        let before = editor.memory_usage_estimate();
+
+        for i in 0..64 {
+            editor.selected_notes = BTreeSet::from([original[i * 700].id]);
+            editor.nudge_selected_notes(0, 1);
         }
-        
-        self.sort_notes();
-        self.emit_state_replaced();
+        editor.take_events();
+        // 64 个撤销步骤加起来远小于一份完整状态（之前每一步都是一份）
+        let history = editor.memory_usage_estimate() - before;
+        assert!(history < state_bytes / 10, "history uses {history} bytes for a {state_bytes}-byte state");
+
+        while editor.undo() {}
+        assert_eq!(editor.state.notes, original);
     }
 
-    #[allow(dead_code)]
-    fn note_near_tick(&self, tick: u64) -> Option<Note> {
-        self.state.notes.iter().copied().min_by_key(|note| {
-            if tick >= note.start && tick <= note.start + note.duration {
-                0
-            } else if tick < note.start {
-                (note.start - tick) as u64
-            } else {
-                (tick - (note.start + note.duration)) as u64
-            }
-        })
+    #[test]
+    fn velocity_ramp_interpolates_by_start_tick() {
+        let notes = vec![
+            Note::new(0, 120, 60, 100),
+            Note::new(0, 120, 64, 100),
+            Note::new(480, 120, 60, 100),
+            Note::new(960, 120, 60, 100),
+        ];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.clone(), ..MidiState::default() }, None);
+        let velocities = |editor: &MidiEditor| notes.iter().map(|n| editor.note_by_id(n.id).unwrap().velocity).collect::<Vec<_>>();
+
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        editor.apply_command(EditorCommand::VelocityRamp { start_velocity: 20, end_velocity: 120, curve: RampCurve::Linear });
+        assert_eq!(velocities(&editor), vec![20, 20, 70, 120]);
+        editor.apply_command(EditorCommand::VelocityRamp { start_velocity: 20, end_velocity: 120, curve: RampCurve::Exponential });
+        let exponential = velocities(&editor);
+        assert_eq!((exponential[0], exponential[3]), (20, 120));
+        assert!(exponential[2] < 70);
+
+        // 两个音符恰好得到起止力度；一次撤销回到上一步
+        editor.selected_notes = BTreeSet::from([notes[2].id, notes[3].id]);
+        editor.apply_command(EditorCommand::VelocityRamp { start_velocity: 127, end_velocity: 1, curve: RampCurve::Exponential });
+        assert_eq!(velocities(&editor)[2..], [127, 1]);
+        editor.undo();
+        assert_eq!(velocities(&editor), exponential);
     }
 
-    #[allow(dead_code)]
-    fn begin_lane_edit(&mut self, lane: LaneType, targets: Vec<NoteId>, anchor: NoteId) {
-        let mut uniques = BTreeSet::new();
-        let mut originals = Vec::new();
-        for id in targets {
-            if uniques.insert(id) {
-                if let Some(idx) = self.note_index_by_id(id) {
-                    originals.push((id, self.state.notes[idx]));
-                }
-            }
-        }
-        if originals.is_empty() {
-            return;
+    #[test]
+    fn time_stretch_scales_offsets_and_durations_from_the_anchor() {
+        let notes = vec![Note::new(480, 240, 60, 100), Note::new(960, 240, 62, 100)];
+        let mut state = MidiState { notes: notes.clone(), ..MidiState::default() };
+        let lane_id = state.add_cc_lane(1);
+        let lane = state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+        for tick in [480, 960, 3000] {
+            lane.insert_point(tick, 64.0);
         }
-        self.push_undo_snapshot();
-        self.lane_edit_state = Some(LaneEditState {
-            lane,
-            anchor,
-            originals,
-        });
-        self.lane_edit_changed = false;
+        let mut editor = MidiEditor::with_state(state, None);
+        let spans = |editor: &MidiEditor| notes.iter().map(|n| editor.note_by_id(n.id).map(|n| (n.start, n.duration)).unwrap()).collect::<Vec<_>>();
+        let point_ticks = |editor: &MidiEditor| {
+            let lane = editor.state.curves.iter().find(|lane| lane.id == lane_id).unwrap();
+            lane.points.iter().map(|p| p.tick).collect::<Vec<_>>()
+        };
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        editor.take_events();
+
+        editor.apply_command(EditorCommand::TimeStretchSelection { ratio: 2.0, anchor: StretchAnchor::SelectionStart, include_curves: true });
+        assert_eq!(spans(&editor), vec![(480, 480), (1440, 480)]);
+        // 选区范围（480..=1200）外的曲线点不动
+        assert_eq!(point_ticks(&editor), vec![480, 1440, 3000]);
+        let events = editor.take_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteUpdated { .. })).count(), 2);
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::CurvePointUpdated { .. })).count(), 1);
+        editor.undo();
+        assert_eq!(spans(&editor), vec![(480, 240), (960, 240)]);
+        assert_eq!(point_ticks(&editor), vec![480, 960, 3000]);
+
+        // 撤销会清空选区
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        editor.apply_command(EditorCommand::TimeStretchSelection { ratio: 0.5, anchor: StretchAnchor::BarStart, include_curves: false });
+        assert_eq!(spans(&editor), vec![(240, 120), (480, 120)]);
+        assert_eq!(point_ticks(&editor), vec![480, 960, 3000]);
+        editor.undo();
+
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        let playhead = editor.state.ticks_to_seconds(960.0) as f32;
+        editor.apply_command(EditorCommand::SeekSeconds(playhead));
+        editor.apply_command(EditorCommand::TimeStretchSelection { ratio: 0.5, anchor: StretchAnchor::Playhead, include_curves: false });
+        assert_eq!(spans(&editor), vec![(720, 120), (960, 120)]);
     }
 
-    #[allow(dead_code)]
-    fn apply_lane_velocity(&mut self, value: u8, relative: bool) {
-        let originals = self.lane_edit_state.as_ref().map(|s| s.originals.clone());
-        if let Some(state) = &self.lane_edit_state {
-            let anchor_original = state
-                .originals
-                .iter()
-                .find(|(id, _)| *id == state.anchor)
-                .map(|(_, note)| *note);
-            if let Some(anchor) = anchor_original {
-                let delta = value as i32 - anchor.velocity as i32;
-                if let Some(originals) = originals {
-                    for (id, original) in &originals {
-                        if let Some(note) = self.note_mut_by_id(*id) {
-                            let mut new_velocity = if relative {
-                                (original.velocity as i32 + delta).clamp(1, 127) as u8
-                            } else {
-                                value
-                            };
-                            new_velocity = new_velocity.clamp(1, 127);
-                            if note.velocity != new_velocity {
-                                note.velocity = new_velocity;
-                                self.lane_edit_changed = true;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    #[test]
+    fn invert_pitch_mirrors_around_the_axis_in_one_undo_step() {
+        let notes = [Note::new(0, 240, 60, 100), Note::new(0, 240, 64, 100), Note::new(240, 240, 100, 100)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.to_vec(), ..MidiState::default() }, None);
+        let keys = |editor: &MidiEditor| notes.map(|n| editor.note_by_id(n.id).unwrap().key);
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        editor.take_events();
+
+        editor.apply_command(EditorCommand::InvertPitch { axis_key: 62 });
+        assert_eq!(keys(&editor), [64, 60, 24]);
+        assert_eq!(editor.take_events().iter().filter(|e| matches!(e, EditorEvent::NoteUpdated { .. })).count(), 3);
+        editor.undo();
+        assert_eq!(keys(&editor), [60, 64, 100]);
+
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        editor.apply_command(EditorCommand::InvertPitch { axis_key: 120 });
+        assert_eq!(keys(&editor), [127, 127, 127], "mirrored keys are clamped to 0..=127");
     }
 
-    #[allow(dead_code)]
-    fn apply_lane_pitch(&mut self, value: u8, relative: bool) {
-        let originals = self.lane_edit_state.as_ref().map(|s| s.originals.clone());
-        if let Some(state) = &self.lane_edit_state {
-            let anchor_original = state
-                .originals
-                .iter()
-                .find(|(id, _)| *id == state.anchor)
-                .map(|(_, note)| *note);
-            if let Some(anchor) = anchor_original {
-                let delta = value as i32 - anchor.key as i32;
-                if let Some(originals) = originals {
-                    for (id, original) in &originals {
-                        if let Some(note) = self.note_mut_by_id(*id) {
-                            let mut new_key = if relative {
-                                (original.key as i32 + delta).clamp(0, 127) as u8
-                            } else {
-                                value
-                            };
-                            new_key = new_key.clamp(0, 127);
-                            if note.key != new_key {
-                                note.key = new_key;
-                                self.lane_edit_changed = true;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    #[test]
+    fn duplicate_tiles_the_selection_without_touching_the_clipboard() {
+        let riff = [Note::new(0, 240, 60, 100), Note::new(480, 300, 64, 90)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: riff.to_vec(), ..MidiState::default() }, None);
+        editor.set_snap_interval(480);
+        editor.clipboard = vec![Note::new(0, 10, 10, 10)];
+        editor.selected_notes = riff.iter().map(|n| n.id).collect();
+        editor.take_events();
+
+        // 跨度 780 向上取整到 960
+        editor.apply_command(EditorCommand::DuplicateSelection);
+        editor.apply_command(EditorCommand::DuplicateSelection);
+        let spans: Vec<_> = editor.state.notes.iter().map(|n| (n.start, n.key)).collect();
+        assert_eq!(spans, vec![(0, 60), (480, 64), (960, 60), (1440, 64), (1920, 60), (2400, 64)]);
+        let selected: Vec<_> = editor.selected_notes_snapshot().iter().map(|n| n.start).collect();
+        assert_eq!(selected, vec![1920, 2400]);
+        let events = editor.take_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteAdded(_))).count(), 4);
+        assert_eq!(editor.clipboard.len(), 1);
+        editor.undo();
+        assert_eq!(editor.state.notes.len(), 4);
+
+        let late = Note::new(u64::MAX - 100, 50, 60, 100);
+        editor.state.notes = vec![late];
+        editor.selected_notes = BTreeSet::from([late.id]);
+        editor.apply_command(EditorCommand::DuplicateSelection);
+        assert_eq!(editor.state.notes.last().map(|n| (n.start, n.duration)), Some((u64::MAX - 50, 50)));
+    }
+
+    #[test]
+    fn selection_queries_replace_the_selection_and_notify() {
+        let notes = [Note::new(0, 240, 60, 100), Note::new(480, 240, 62, 100), Note::new(960, 240, 60, 100), Note::new(1920, 240, 60, 100)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.to_vec(), ..MidiState::default() }, None);
+        let selected = |editor: &MidiEditor| editor.selected_notes_snapshot().iter().map(|n| n.start).collect::<Vec<_>>();
+        editor.loop_start_tick = 480;
+        editor.loop_end_tick = 1920;
+
+        editor.apply_command(EditorCommand::SelectNotes(SelectionQuery::SamePitch(60)));
+        assert_eq!(selected(&editor), vec![0, 960, 1920]);
+        editor.apply_command(EditorCommand::SelectNotes(SelectionQuery::Invert));
+        assert_eq!(selected(&editor), vec![480]);
+        editor.apply_command(EditorCommand::SelectNotes(SelectionQuery::InLoopRegion));
+        assert_eq!(selected(&editor), vec![480, 960]);
+        let changes = editor.take_events().iter().filter(|e| matches!(e, EditorEvent::SelectionChanged(_))).count();
+        assert_eq!(changes, 3);
+
+        editor.apply_command(EditorCommand::SelectNotes(SelectionQuery::InLoopRegion));
+        assert!(editor.take_events().is_empty(), "an unchanged selection emits nothing");
     }
 
-    fn current_tick_position(&self) -> u64 {
-        if self.state.ticks_per_beat == 0 {
-            return 0;
-        }
-        let seconds_per_beat = 60.0 / self.state.bpm.max(1.0);
-        let seconds_per_tick = seconds_per_beat / self.state.ticks_per_beat as f32;
-        (self.current_time / seconds_per_tick).max(0.0) as u64
+    #[test]
+    fn select_by_filters_the_current_selection_or_all_notes() {
+        let notes = [Note::new(0, 120, 38, 30), Note::new(120, 120, 38, 120), Note::new(240, 120, 42, 25)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.to_vec(), ..MidiState::default() }, None);
+        editor.select_by_velocity = (true, 40, 1);
+        assert_eq!(editor.select_by_matches(), vec![notes[0].id, notes[2].id], "reversed bounds are accepted");
+
+        editor.selected_notes = BTreeSet::from([notes[1].id, notes[2].id]);
+        assert_eq!(editor.select_by_matches(), vec![notes[2].id]);
+        editor.select_by_key = (true, 36, 40);
+        assert!(editor.select_by_matches().is_empty());
     }
 
-    fn notify_playback_started(&self) {
-        if let Some(observer) = &self.playback_observer {
-            observer.on_playback_started();
+    #[test]
+    fn light_theme_keeps_grid_and_notes_visible() {
+        let luma = |c: Color32| 0.299 * c.r() as f32 + 0.587 * c.g() as f32 + 0.114 * c.b() as f32;
+        for visuals in [Visuals::dark(), Visuals::light()] {
+            let theme = MidiEditorTheme::from_visuals(&visuals);
+            assert_eq!(theme.background, visuals.panel_fill);
+            let background = luma(theme.background);
+            for (name, color) in [
+                ("measure", theme.measure_line),
+                ("beat", theme.beat_line),
+                ("row", theme.row_line),
+                ("note", theme.note),
+                ("outline", theme.note_outline),
+            ] {
+                assert!((luma(color) - background).abs() > 20.0, "{name} blends into the background (dark mode: {})", visuals.dark_mode);
+            }
         }
+        assert_eq!(MidiEditorTheme::from_visuals(&Visuals::dark()), MidiEditorTheme::dark());
     }
 
-    fn notify_playback_stopped(&self) {
-        if let Some(observer) = &self.playback_observer {
-            observer.on_playback_stopped();
-        }
+    #[test]
+    fn default_shortcuts_match_exact_modifiers() {
+        let map = ShortcutMap::default();
+        let command_shift = Modifiers::COMMAND | Modifiers::SHIFT;
+        assert_eq!(map.action(Modifiers::COMMAND, Key::Z), Some(ShortcutAction::Undo));
+        assert_eq!(map.action(command_shift, Key::Z), Some(ShortcutAction::Redo));
+        assert_eq!(map.action(Modifiers::COMMAND, Key::Y), Some(ShortcutAction::Redo));
+        assert_eq!(map.action(Modifiers::SHIFT, Key::F), Some(ShortcutAction::ZoomToSelection));
+        assert_eq!(map.action(command_shift, Key::ArrowUp), Some(ShortcutAction::LoopLonger));
+        assert_eq!(map.action(Modifiers::ALT, Key::F), None);
+        assert_eq!(map.shortcuts(ShortcutAction::Delete).count(), 2);
+        assert_eq!(map.shortcuts(ShortcutAction::Quantize).count(), 0);
+
+        let mut map = map;
+        // 重新绑定一个按键会替换它原来的操作
+        map.bind(ShortcutAction::Quantize, KeyboardShortcut::new(Modifiers::COMMAND, Key::D));
+        assert_eq!(map.action(Modifiers::COMMAND, Key::D), Some(ShortcutAction::Quantize));
+        assert_eq!(map.shortcuts(ShortcutAction::Duplicate).count(), 0);
+        map.unbind(ShortcutAction::Redo);
+        assert_eq!(map.action(command_shift, Key::Z), None);
+        assert_eq!(map.action(Modifiers::COMMAND, Key::Z), Some(ShortcutAction::Undo));
     }
 
-    fn handle_shortcuts(&mut self, ctx: &Context) {
-        let command = ctx.input(|i| i.modifiers.command);
-        let shift = ctx.input(|i| i.modifiers.shift);
-        if command && ctx.input(|i| i.key_pressed(Key::C)) {
-            self.copy_selection();
-        }
-        if command && ctx.input(|i| i.key_pressed(Key::X)) {
-            self.cut_selection();
-        }
-        if command && ctx.input(|i| i.key_pressed(Key::V)) {
-            let tick = self.current_tick_position();
-            self.paste_clipboard_at(tick);
-        }
-        if ctx.input(|i| i.key_pressed(Key::Delete) || i.key_pressed(Key::Backspace)) {
-            self.delete_selected_notes();
-        }
-        if command && ctx.input(|i| i.key_pressed(Key::Z)) {
-            if shift {
-                self.redo();
-            } else {
-                self.undo();
-            }
-        } else if command && ctx.input(|i| i.key_pressed(Key::Y)) {
-            self.redo();
-        }
+    #[test]
+    fn snap_resolution_follows_ticks_per_beat() {
+        use SnapModifier::*;
+        assert_eq!(SnapResolution::Sixteenth.ticks(480, Straight), 120);
+        assert_eq!(SnapResolution::Sixteenth.ticks(96, Straight), 24);
+        assert_eq!(SnapResolution::Whole.ticks(960, Straight), 3840);
+        assert_eq!(SnapResolution::Eighth.ticks(96, Triplet), 32);
+        assert_eq!(SnapResolution::Quarter.ticks(480, Dotted), 720);
+        assert_eq!(SnapResolution::SixtyFourth.ticks(24, Triplet), 1);
+
+        let mut editor = MidiEditor::with_state(MidiState { ticks_per_beat: 96, ..MidiState::default() }, None);
+        assert_eq!((editor.snap_ticks(), editor.snap_label().as_str()), (24, "1/16"));
+        editor.apply_command(EditorCommand::SetSnapResolution(Some((SnapResolution::Eighth, Triplet))));
+        assert_eq!((editor.snap_ticks(), editor.snap_label().as_str()), (32, "1/8T"));
+        assert_eq!(editor.snap_value(40), 32);
+        // 自定义 tick 间隔仍然可用，并取代音符时值
+        editor.apply_command(EditorCommand::SetSnap { interval: 30, mode: SnapMode::Absolute });
+        assert_eq!((editor.snap_resolution, editor.snap_ticks()), (None, 30));
+        assert_eq!(editor.snap_label(), "30 ticks");
     }
 
-    fn delete_note_by_id(&mut self, id: NoteId) {
-        if let Some(idx) = self.note_index_by_id(id) {
-            self.push_undo_snapshot();
-            let removed = self.state.notes.remove(idx);
-            self.emit_note_deleted(removed);
-            self.selected_notes.remove(&removed.id);
-        }
+    #[test]
+    fn triplet_grid_splits_beats_into_thirds() {
+        let lines = |triplet: bool, tpb: u64| {
+            let mut shapes = Vec::new();
+            let theme = MidiEditorTheme::dark();
+            render::vertical_grid(&mut shapes, &theme, triplet, 0.0, 100.0, tpb, tpb * 4, 0, tpb as i64, Rangef::new(-1.0, 1000.0), Rangef::new(0.0, 8.0));
+            // 虚线由多段组成，只取每条线的 x
+            let mut xs: Vec<i32> = shapes
+                .iter()
+                .filter_map(|shape| match shape {
+                    Shape::LineSegment { points, .. } => Some(points[0].x.round() as i32),
+                    _ => None,
+                })
+                .collect();
+            xs.dedup();
+            xs
+        };
+        assert_eq!(lines(false, 480), vec![0, 25, 50, 75, 100]);
+        assert_eq!(lines(true, 480), vec![0, 17, 33, 50, 67, 83, 100]);
+        // 100 不能被 6 整除：位置按拍内序号计算，下一拍仍落在整拍上
+        assert_eq!(lines(true, 100), vec![0, 16, 33, 50, 66, 83, 100]);
     }
 
-    fn handle_note_click(&mut self, note_id: NoteId, modifiers: Modifiers) {
-        if modifiers.command {
-            self.toggle_selection(note_id);
-        } else if modifiers.shift {
-            self.extend_selection(note_id);
-        } else {
-            self.set_single_selection(note_id);
+    #[test]
+    fn note_text_stays_readable_on_every_fill() {
+        for selected in [false, true] {
+            assert_eq!(render::note_text_color(render::note_fill(&MidiEditorTheme::dark(), selected)), Color32::from_gray(20));
         }
+        assert_eq!(render::note_text_color(Color32::from_rgb(30, 40, 120)), Color32::from_gray(235));
     }
 
-    fn prepare_selection_for_drag(&mut self, note_id: NoteId, modifiers: Modifiers) {
-        if modifiers.command {
-            if !self.selected_notes.remove(&note_id) {
-                self.selected_notes.insert(note_id);
-            }
-        } else if modifiers.shift {
-            self.extend_selection(note_id);
-        } else if !self.selected_notes.contains(&note_id) {
-            self.set_single_selection(note_id);
-        }
+    #[test]
+    fn velocity_colors_follow_the_gradient_and_optionally_the_curve() {
+        let theme = MidiEditorTheme::dark();
+        assert_eq!(render::velocity_fill(&theme, 1), theme.velocity_low);
+        assert_eq!(render::velocity_fill(&theme, 64), theme.note);
+        assert_eq!(render::velocity_fill(&theme, 127), theme.velocity_high);
+
+        let note = Note::new(0, 480, 60, 127);
+        let mut state = MidiState { notes: vec![note], ..MidiState::default() };
+        let lane_id = state.add_curve_lane(CurveLaneType::Velocity);
+        state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap().insert_point(0, 1.0);
+        let mut editor = MidiEditor::with_state(state, None);
+        assert_eq!(editor.note_fill(&theme, &note, true), theme.selected_note);
+        editor.color_by_velocity = true;
+        assert_eq!(editor.note_fill(&theme, &note, true), theme.velocity_high, "selection does not change the fill");
+        editor.color_by_effective_velocity = true;
+        assert_eq!(editor.note_fill(&theme, &note, false), theme.velocity_low);
     }
 
-    fn resolve_drag_action(&self, pointer: Pos2, rect: Rect) -> DragAction {
-        const HANDLE_WIDTH: f32 = 6.0;
-        let left_handle =
-            Rect::from_min_max(rect.min, Pos2::new(rect.min.x + HANDLE_WIDTH, rect.max.y));
-        let right_handle =
-            Rect::from_min_max(Pos2::new(rect.max.x - HANDLE_WIDTH, rect.min.y), rect.max);
-        if left_handle.contains(pointer) {
-            DragAction::ResizeStart
-        } else if right_handle.contains(pointer) {
-            DragAction::ResizeEnd
-        } else {
-            DragAction::Move
-        }
+    #[test]
+    fn glue_merges_chains_of_close_same_pitch_notes() {
+        let chain = [Note::new(0, 100, 60, 70), Note::new(110, 90, 60, 100), Note::new(200, 100, 60, 100)];
+        let far = Note::new(600, 100, 60, 100);
+        let other = Note::new(100, 100, 62, 100);
+        let notes = vec![chain[0], chain[1], chain[2], far, other];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.clone(), ..MidiState::default() }, None);
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+
+        editor.apply_command(EditorCommand::GlueNotes { max_gap: Some(5) });
+        assert_eq!(editor.state.notes.len(), 4, "only the touching pair is glued");
+        editor.undo();
+
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+        editor.take_events();
+        editor.apply_command(EditorCommand::GlueNotes { max_gap: None });
+        let glued = editor.note_by_id(chain[0].id).unwrap();
+        assert_eq!((glued.start, glued.duration, glued.velocity), (0, 300, 70));
+        assert_eq!(editor.state.notes.len(), 3);
+        assert_eq!(editor.note_by_id(far.id), Some(far));
+        let events = editor.take_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteDeleted(_))).count(), 2);
+        assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteUpdated { before, after } if *before == chain[0] && *after == glued)));
+
+        editor.undo();
+        assert_eq!(editor.state.notes.len(), 5);
     }
 
-    fn begin_note_drag(
-        &mut self,
-        anchor: NoteId,
-        pointer: Pos2,
-        pointer_tick: i64,
-        action: DragAction,
-    ) {
-        if self.selected_notes.is_empty() {
-            self.set_single_selection(anchor);
-        }
-        self.push_undo_snapshot();
-        self.is_dragging_note = true;
-        self.is_resizing_note = matches!(action, DragAction::ResizeStart | DragAction::ResizeEnd);
-        self.drag_action = action;
-        self.drag_start_pos = Some(pointer);
-        self.drag_primary_anchor = Some(anchor);
-        self.drag_original_notes = self
-            .selected_notes
-            .iter()
-            .filter_map(|id| {
-                self.note_index_by_id(*id)
-                    .map(|idx| (*id, self.state.notes[idx]))
+    #[test]
+    fn legato_joins_selected_notes_and_shortens_overlaps() {
+        let notes = vec![
+            Note::new(0, 100, 60, 100),
+            Note::new(0, 2000, 64, 100), // 和弦里重叠过多的音同样截到下一个起点
+            Note::new(480, 100, 67, 100),
+            Note::new(960, 100, 72, 100),
+        ];
+        let unselected = Note::new(240, 100, 50, 100);
+        let mut state = MidiState { notes: notes.clone(), ..MidiState::default() };
+        state.notes.push(unselected);
+        let mut editor = MidiEditor::with_state(state, None);
+        editor.selected_notes = notes.iter().map(|n| n.id).collect();
+
+        editor.apply_command(EditorCommand::Legato { overlap_ticks: -20 });
+        let durations: Vec<_> = notes.iter().map(|n| editor.note_by_id(n.id).unwrap().duration).collect();
+        assert_eq!(durations, vec![460, 460, 460, 100]);
+        assert_eq!(editor.note_by_id(unselected.id), Some(unselected));
+        let updates: Vec<_> = editor
+            .take_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                EditorEvent::NoteUpdated { before, after } => Some((before.duration, after.duration)),
+                _ => None,
             })
             .collect();
-        if let Some(note) = self.state.notes.iter().find(|n| n.id == anchor) {
-            self.drag_original_start = Some(note.start);
-            self.drag_original_duration = Some(note.duration);
-            self.drag_original_key = Some(note.key);
-        }
-        self.drag_pointer_offset_ticks =
-            Some(pointer_tick - self.drag_original_start.unwrap_or(0) as i64);
-        self.drag_changed_note = false;
-        if matches!(action, DragAction::Move | DragAction::None) {
-            if let Some(note) = self.state.notes.iter().find(|n| n.id == anchor) {
-                self.preview_note_on(note.key, 100);
-            }
-        }
+        assert_eq!(updates.len(), 3);
+        assert!(updates.contains(&(2000, 460)));
+
+        editor.undo();
+        assert_eq!(editor.note_by_id(notes[1].id).map(|n| n.duration), Some(2000));
     }
 
-    fn create_note_at_pointer<F, G>(&mut self, pointer: Pos2, to_tick: F, to_key: G)
-    where
-        F: Fn(Pos2) -> i64,
-        G: Fn(Pos2) -> u8,
-    {
-        let start_tick = to_tick(pointer).max(0);
-        let snapped_start = self.snap_tick(start_tick, None, false);
-        let default_duration = if self.snap_interval > 0 {
-            self.snap_interval
-        } else {
-            self.state.ticks_per_beat as u64
-        }
-        .max(1);
-        let key = to_key(pointer);
-        let note = Note::new(snapped_start, default_duration, key, 100);
-        self.push_undo_snapshot();
-        self.state.notes.push(note);
-        self.sort_notes();
-        self.emit_note_added(note);
-        self.set_single_selection(note.id);
-        self.drag_primary_anchor = Some(note.id);
-        self.drag_original_notes = vec![(note.id, note)];
-        self.drag_original_start = Some(note.start);
-        self.drag_original_duration = Some(note.duration);
-        self.drag_original_key = Some(note.key);
-        self.drag_pointer_offset_ticks = Some(0);
-        self.is_dragging_note = true;
-        self.drag_action = DragAction::Create;
-        self.drag_start_pos = Some(pointer);
-        self.preview_note_on(note.key, 100);
+    #[test]
+    fn arpeggiate_replaces_the_selection_as_one_undo_step() {
+        let chord = [Note::new(0, 480, 60, 100), Note::new(0, 480, 64, 100)];
+        let kept = Note::new(960, 480, 50, 100);
+        let mut editor = MidiEditor::with_state(MidiState { notes: vec![chord[0], chord[1], kept], ..MidiState::default() }, None);
+        editor.selected_notes = chord.iter().map(|n| n.id).collect();
+        editor.apply_command(EditorCommand::Arpeggiate {
+            pattern: ArpPattern::Up,
+            rate_ticks: 120,
+            octaves: 1,
+            gate: 1.0,
+        });
+
+        let notes: Vec<_> = editor.state.notes.iter().map(|n| (n.start, n.key)).collect();
+        assert_eq!(notes, vec![(0, 60), (120, 64), (240, 60), (360, 64), (960, 50)]);
+        assert_eq!(editor.selected_notes.len(), 4);
+        let events = editor.take_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteDeleted(_))).count(), 2);
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteAdded(_))).count(), 4);
+
+        editor.undo();
+        assert_eq!(editor.state.notes.len(), 3);
+        assert!(editor.state.notes.contains(&chord[1]));
     }
 
-    fn update_note_drag<F, G>(&mut self, pointer: Pos2, to_tick: F, to_key: G, modifiers: Modifiers)
-    where
-        F: Fn(Pos2) -> i64,
-        G: Fn(Pos2) -> u8,
-    {
-        let originals_snapshot = self.drag_original_notes.clone();
-        let pointer_tick = to_tick(pointer);
-        let disable_snap = modifiers.alt;
-        match self.drag_action {
-            DragAction::Move | DragAction::None => {
-                let key = to_key(pointer);
-                let anchor_id = match self.drag_primary_anchor {
-                    Some(id) => id,
-                    None => return,
-                };
-                let anchor_original = match originals_snapshot
-                    .iter()
-                    .find(|(id, _)| *id == anchor_id)
-                    .map(|(_, note)| *note)
-                {
-                    Some(note) => note,
-                    None => return,
-                };
-                let offset = self.drag_pointer_offset_ticks.unwrap_or(0);
-                let snapped = self.snap_tick(
-                    pointer_tick - offset,
-                    Some(anchor_original.start),
-                    disable_snap,
-                );
-                let delta = snapped as i64 - anchor_original.start as i64;
-                let key_delta = key as i16 - anchor_original.key as i16;
-                for (id, original) in &originals_snapshot {
-                    let mut preview = None;
-                    if let Some(note) = self.note_mut_by_id(*id) {
-                        let new_start = (original.start as i64 + delta).max(0) as u64;
-                        let new_key = (original.key as i16 + key_delta).clamp(0, 127) as u8;
-                        let should_preview = note.key != new_key && *id == anchor_id;
-                        if note.start != new_start || note.key != new_key {
-                            note.start = new_start;
-                            note.key = new_key;
-                            self.drag_changed_note = true;
-                        }
-                        if should_preview {
-                            preview = Some(new_key);
-                        }
-                    }
-                    if let Some(key) = preview {
-                        self.preview_note_on(key, 100);
-                    }
-                }
-                self.sort_notes();
-            }
-            DragAction::ResizeStart => {
-                if let Some(anchor_id) = self.drag_primary_anchor {
-                    if let Some(original) = originals_snapshot
-                        .iter()
-                        .find(|(id, _)| *id == anchor_id)
-                        .map(|(_, note)| *note)
-                    {
-                        let snapped =
-                            self.snap_tick(pointer_tick, Some(original.start), disable_snap);
-                        let end = original.start + original.duration;
-                        let new_start = snapped.min(end.saturating_sub(1));
-                        if let Some(note) = self.note_mut_by_id(anchor_id) {
-                            if new_start != note.start
-                                || note.duration != end.saturating_sub(new_start)
-                            {
-                                note.start = new_start;
-                                note.duration = end.saturating_sub(new_start).max(1);
-                                self.drag_changed_note = true;
-                            }
-                        }
-                    }
-                }
-            }
-            DragAction::ResizeEnd => {
-                if let Some(anchor_id) = self.drag_primary_anchor {
-                    if let Some(original) = originals_snapshot
-                        .iter()
-                        .find(|(id, _)| *id == anchor_id)
-                        .map(|(_, note)| *note)
-                    {
-                        let snapped = self.snap_tick(
-                            pointer_tick,
-                            Some(original.start + original.duration),
-                            disable_snap,
-                        );
-                        if let Some(note) = self.note_mut_by_id(anchor_id) {
-                            let new_end = snapped.max(note.start + 1);
-                            if new_end != note.start + note.duration {
-                                note.duration = new_end.saturating_sub(note.start).max(1);
-                                self.drag_changed_note = true;
-                            }
-                        }
-                    }
-                }
-            }
-            DragAction::Create => {
-                if let Some(anchor_id) = self.drag_primary_anchor {
-                    if let Some(original) = originals_snapshot
-                        .iter()
-                        .find(|(id, _)| *id == anchor_id)
-                        .map(|(_, note)| *note)
-                    {
-                        let snapped = self.snap_tick(
-                            pointer_tick,
-                            Some(original.start + original.duration),
-                            disable_snap,
-                        );
-                        let new_end = snapped.max(original.start + 1);
-                        let new_key = to_key(pointer);
-                        let mut preview = None;
-                        if let Some(note) = self.note_mut_by_id(anchor_id) {
-                            if new_end != note.start + note.duration {
-                                note.duration = new_end - note.start;
-                            }
-                            if new_key != note.key {
-                                note.key = new_key;
-                                preview = Some(new_key);
-                            }
-                            self.drag_changed_note = true;
-                        }
-                        if let Some(key) = preview {
-                            self.preview_note_on(key, 100);
-                        }
-                    }
-                }
-            }
-            DragAction::LoopEdit | DragAction::PlayheadSeek => {
-                // Loop editing and playhead seeking - handled in ui_piano_roll interaction code
-                return;
-            }
-        }
+    #[test]
+    fn allowed_keys_snap_creation_paste_and_transpose() {
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        editor.snap_resolution = None;
+        editor.snap_interval = 0;
+        editor.set_allowed_keys(Some([36u8, 38, 42].into_iter().collect()));
+
+        editor.create_note_at_pointer(Pos2::ZERO, |_| 0, |_| 37);
+        assert_eq!(editor.state.notes[0].key, 36);
+        editor.create_note_at_pointer(Pos2::ZERO, |_| 480, |_| 100);
+        assert_eq!(editor.state.notes.len(), 1);
+        assert!(editor.toast.is_some());
+
+        editor.clipboard = vec![Note::new(0, 480, 41, 100), Note::new(0, 480, 90, 100)];
+        editor.paste_clipboard_at(960);
+        let pasted: Vec<u8> = editor.state.notes.iter().filter(|n| n.start == 960).map(|n| n.key).collect();
+        assert_eq!(pasted, vec![42]);
+
+        let transposed = editor.state.notes[0].id;
+        editor.selected_notes = BTreeSet::from([transposed]);
+        editor.apply_command(EditorCommand::BatchTransform {
+            transform_type: BatchTransformType::PitchOffset,
+            value: 3.0,
+        });
+        assert_eq!(editor.note_by_id(transposed).map(|n| n.key), Some(38));
+        assert!(editor.midi_state().notes.iter().all(|n| [36, 38, 42].contains(&n.key)));
+    }
+
+    #[test]
+    fn copying_notes_with_curve_points_pastes_both_as_one_undo_step() {
+        let mut state = MidiState::default();
+        state.notes.push(Note::new(0, 480, 60, 100));
+        let lane_id = state.curves[0].id;
+        let kept = state.curves[0].insert_point(0, 10.0).id;
+        let copied = state.curves[0].insert_point(240, 90.0).id;
+        let mut editor = MidiEditor::with_state(state, None);
+
+        editor.selected_notes = editor.state.notes.iter().map(|n| n.id).collect();
+        editor.selected_curve_points = vec![copied];
+        editor.copy_selection();
+        editor.take_events();
+
+        let undo_depth = editor.undo_stack.len();
+        editor.paste_clipboard_at(1920);
+        assert_eq!(editor.undo_stack.len(), undo_depth + 1);
+        assert!(editor.state.notes.iter().any(|n| n.start == 1920));
+        let lane = editor.state.curves.iter().find(|c| c.id == lane_id).unwrap();
+        assert!(lane.points.iter().any(|p| p.id == kept));
+        assert!(lane.points.iter().any(|p| p.tick == 2160 && p.value == 90.0));
+        assert_eq!(
+            editor
+                .take_events()
+                .iter()
+                .filter(|e| matches!(e, EditorEvent::CurvePointsPasted { .. }))
+                .count(),
+            1
+        );
+
+        let mut other = MidiEditor::with_state(MidiState { ticks_per_beat: 960, ..MidiState::default() }, None);
+        other.set_clipboard_payload(&editor.clipboard_payload());
+        other.paste_clipboard_at(0);
+        assert_eq!(other.state.notes[0].duration, 960);
+        assert_eq!(other.state.curves[0].points.len(), 1);
+    }
+
+    #[test]
+    fn copying_notes_carries_curve_points_in_their_range_to_matching_lanes() {
+        let mut state = MidiState::default();
+        state.notes.push(Note::new(480, 960, 60, 100));
+        let lane_id = state.add_cc_lane(11);
+        let lane = state.curves.iter_mut().find(|c| c.id == lane_id).unwrap();
+        let outside = lane.insert_point(0, 10.0).id;
+        lane.insert_point(480, 40.0);
+        lane.insert_point(1200, 100.0);
+        lane.insert_point(1440, 5.0);
+        let mut editor = MidiEditor::with_state(state, None);
+        editor.selected_notes = editor.state.notes.iter().map(|n| n.id).collect();
+        editor.copy_selection();
+        assert!(editor.curve_clipboard.is_none());
+        assert_eq!(editor.clipboard_curves.len(), 1);
+        assert_eq!(editor.clipboard_curves[0].points, vec![(0, 40.0), (720, 100.0)]);
+
+        // 目标没有该 CC 曲线时创建它
+        let mut other = MidiEditor::with_state(MidiState::default(), None);
+        other.set_clipboard_payload(&editor.clipboard_payload());
+        other.paste_clipboard_at(1920);
+        let pasted = other.state.curves.iter().find(|c| c.lane_type == CurveLaneType::ControlChange(11)).unwrap().clone();
+        assert_eq!(pasted.points.iter().map(|p| (p.tick, p.value)).collect::<Vec<_>>(), vec![(1920, 40.0), (2640, 100.0)]);
+        assert!(other.take_events().iter().any(|e| matches!(e, EditorEvent::CurveLaneAdded(id) if *id == pasted.id)));
+        other.undo();
+        assert!(other.state.curves.iter().all(|c| c.lane_type != CurveLaneType::ControlChange(11)));
+
+        // 剪切连同范围内的点一起删除，一次撤销全部恢复
+        editor.take_events();
+        editor.cut_selection();
+        let lane = editor.state.curves.iter().find(|c| c.id == lane_id).unwrap();
+        assert_eq!(lane.points.iter().map(|p| p.tick).collect::<Vec<_>>(), vec![0, 1440]);
+        assert!(lane.points.iter().any(|p| p.id == outside));
+        let removed = editor.take_events().iter().filter(|e| matches!(e, EditorEvent::CurvePointRemoved { .. })).count();
+        assert_eq!(removed, 2);
+        editor.undo();
+        assert_eq!(editor.state.notes.len(), 1);
+        assert_eq!(editor.state.curves.iter().find(|c| c.id == lane_id).unwrap().points.len(), 4);
+    }
+
+    #[test]
+    fn drum_map_rows_skip_unnamed_keys_only_when_hidden() {
+        let gm = DrumMap::general_midi();
+        assert_eq!(gm.name(36), Some("Kick"));
+        assert_eq!(gm.name(38), Some("Snare"));
+        assert_eq!(gm.name(20), None);
+
+        // 不隐藏时与普通卷帘相同
+        let full = RowLayout::new(Some(&gm));
+        assert_eq!((full.len(), full.row_of(127), full.row_of(0)), (128, Some(0), Some(127)));
+
+        let compressed = RowLayout::new(Some(&DrumMap { hide_unmapped: true, ..gm }));
+        assert_eq!(compressed.len(), crate::editor::GM_DRUM_MAP.len());
+        assert_eq!(compressed.row_of(81), Some(0));
+        assert_eq!(compressed.row_of(35), Some(compressed.len() - 1));
+        assert_eq!(compressed.row_of(34), None);
+        assert_eq!(compressed.key_at(1.5), 80);
+        assert_eq!(compressed.key_at(500.0), 35);
+
+        // 空映射没有可显示的行，退回到完整的 128 行
+        assert_eq!(RowLayout::new(Some(&DrumMap { hide_unmapped: true, ..DrumMap::default() })).len(), 128);
+    }
+
+    #[test]
+    fn minimap_marks_follow_the_notes_revision() {
+        let notes = vec![Note::new(0, 480, 60, 100), Note::new(7680, 960, 72, 100)];
+        let mut editor = MidiEditor::with_state(MidiState { notes, ..MidiState::default() }, None);
+        let cache = MinimapCache::build(&editor.state.notes, editor.notes_revision, editor.state.ticks_per_measure());
+        assert_eq!(cache.total_ticks, 8640 + 1920);
+        assert_eq!(cache.columns[0], Some((60, 60)));
+        let first = (7680 * MINIMAP_COLUMNS as u64 / cache.total_ticks) as usize;
+        assert_eq!(cache.columns[first], Some((72, 72)));
+        assert_eq!(cache.columns.iter().flatten().count(), 24 + 47);
+
+        // 选区、播放头等变化不使缓存失效；音符增删改会
+        let revision = editor.notes_revision;
+        editor.selected_notes.insert(editor.state.notes[0].id);
+        editor.apply_command(EditorCommand::SeekSeconds(0.5));
+        assert_eq!(editor.notes_revision, revision);
+        editor.apply_command(EditorCommand::AppendNotes(vec![Note::new(480, 480, 40, 100)]));
+        assert!(editor.notes_revision > revision);
+        let revision = editor.notes_revision;
+        editor.undo();
+        assert!(editor.notes_revision > revision);
+    }
+
+    #[test]
+    fn reference_waveform_peaks_are_culled_to_visible_columns() {
+        let samples: Vec<f32> = (0..1000).map(|i| if i < 500 { 0.5 } else { -0.25 }).collect();
+        let waveform = ReferenceWaveform::from_samples(&samples, 1000, 10);
+        assert_eq!(waveform.peaks.len(), 10);
+        assert_eq!(waveform.duration_seconds, 1.0);
+        assert_eq!(waveform.peak_range(0.0, 0.1), Some((0.0, 0.5)));
+        assert_eq!(waveform.peak_range(0.45, 0.55), Some((-0.25, 0.5)));
+        assert_eq!(waveform.peak_range(1.0, 2.0), None);
+
+        // 0 秒位于 x = -50，每秒 100 像素：可见的 0..100 中只有 0..50 有波形
+        let mut shapes = Vec::new();
+        let band = Rangef::new(0.0, 100.0);
+        render::reference_waveform(&mut shapes, &MidiEditorTheme::dark(), &waveform, -50.0, 100.0, Rangef::new(0.0, 100.0), band, band);
+        assert_eq!(shapes.len(), 50);
+        let Shape::Rect(first) = &shapes[0] else {
+            panic!("waveform columns are filled rects");
+        };
+        assert_eq!(first.rect.min, Pos2::new(0.0, 50.0));
+        assert_eq!(first.rect.max.y, 62.5);
+    }
+
+    #[test]
+    fn import_merges_rescaled_transposed_notes_as_one_undo_step() {
+        let existing = Note::new(0, 480, 60, 100);
+        let mut editor = MidiEditor::with_state(
+            MidiState { notes: vec![existing], ..MidiState::default() },
+            None,
+        );
+        editor.seek_to_seconds(1.0);
+        let playhead = editor.current_tick_position();
+
+        let mut lane = CurveLane::new(CurveLaneType::Pitch);
+        lane.insert_point(960, 0.5);
+        let source = MidiState {
+            ticks_per_beat: 960,
+            notes: vec![Note::new(960, 960, 64, 90), Note::new(0, 480, 126, 90)],
+            curves: vec![lane],
+            ..MidiState::default()
+        };
+        let options = ImportMergeOptions { transpose: 3, include_curves: true, ..ImportMergeOptions::default() };
+        assert_eq!(editor.import_notes(source, options), 1);
+
+        // 126 + 3 超出范围被丢弃；TPB 960 -> 480 后 tick 960 变为 480，再加上播放头偏移
+        let imported = editor.state.notes.iter().find(|n| n.id != existing.id).copied().unwrap();
+        assert_eq!((imported.start, imported.duration, imported.key), (playhead + 480, 480, 67));
+        assert_eq!(editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![imported.id]);
+        let pitch = editor.state.curves.iter().find(|c| c.lane_type == CurveLaneType::Pitch).unwrap();
+        assert_eq!(pitch.points[0].tick, playhead + 480);
+        let events = editor.take_events();
+        assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteAdded(_))).count(), 1);
+        assert!(events.iter().any(|e| matches!(e, EditorEvent::CurveLaneAdded(_))));
+
+        assert!(editor.undo());
+        assert_eq!(editor.state.notes.len(), 1);
+        assert!(editor.state.curves.iter().all(|c| c.lane_type != CurveLaneType::Pitch));
     }
 
-    fn snap_value(&self, value: i64) -> i64 {
-        if self.snap_interval == 0 {
-            return value;
-        }
-        let interval = self.snap_interval as i64;
-        let remainder = value.rem_euclid(interval);
-        if remainder >= interval / 2 {
-            value + (interval - remainder)
-        } else {
-            value - remainder
-        }
+    #[test]
+    fn import_honors_edit_bounds_and_allowed_keys() {
+        let mut editor = MidiEditor::new(None);
+        editor.set_edit_bounds(Some((1920, 3840)));
+        editor.set_allowed_keys(Some([60, 64, 67].into_iter().collect()));
+        let source = MidiState {
+            notes: vec![Note::new(0, 480, 61, 100), Note::new(480, 480, 30, 100)],
+            ..MidiState::default()
+        };
+        let options = ImportMergeOptions { placement: ImportPlacement::Start, ..ImportMergeOptions::default() };
+        assert_eq!(editor.import_notes(source, options), 1);
+        let note = editor.state.notes[0];
+        assert_eq!((note.start, note.key), (1920, 60));
     }
 
-    fn snap_tick(&self, raw_tick: i64, reference: Option<u64>, disable: bool) -> u64 {
-        if self.snap_interval == 0 || disable {
-            return raw_tick.max(0) as u64;
+    #[test]
+    fn history_clipboard_and_event_queue_respect_limits() {
+        let mut editor = MidiEditor::new(None);
+        editor.apply_options(&MidiEditorOptions {
+            max_pending_events: 3,
+            max_clipboard_notes: 2,
+            max_undo_entries: 2,
+            ..MidiEditorOptions::default()
+        });
+        let heard = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = heard.clone();
+        editor.set_event_listener(move |_| counter.set(counter.get() + 1));
+        let before = editor.memory_usage_estimate();
+
+        let ids: Vec<NoteId> = (0..5).map(|i| editor.insert_note(Note::new(i * 480, 480, 60, 100))).collect();
+        assert_eq!(editor.undo_stack.len(), 2);
+        assert!(editor.undo() && editor.undo());
+        assert!(!editor.undo());
+        assert_eq!(editor.state.notes.len(), 3);
+
+        // 监听器收到全部事件，队列只保留最新的 3 条
+        assert_eq!(heard.get(), 7);
+        let events = editor.take_events();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[2], EditorEvent::StateReplaced(_)));
+        editor.insert_note(Note::new(9600, 480, 60, 100));
+        assert_eq!(editor.take_events().len(), 1);
+
+        editor.selected_notes = ids[..3].iter().copied().collect();
+        editor.copy_selection();
+        assert_eq!(editor.clipboard.iter().map(|n| n.start).collect::<Vec<_>>(), vec![0, 480]);
+        assert!(editor.memory_usage_estimate() > before);
+    }
+
+    #[test]
+    fn active_track_limits_editing_and_hidden_tracks_stay_silent() {
+        let lead = Note::new(0, 960, 72, 100);
+        let bass = Note { track: 1, ..Note::new(0, 960, 40, 100) };
+        let backend = Arc::new(RecordingPlayback::default());
+        let state = MidiState {
+            notes: vec![lead, bass],
+            tracks: vec![crate::structure::MidiTrack::default(); 2],
+            ..MidiState::default()
+        };
+        let mut editor = MidiEditor::with_state(state, Some(backend.clone() as PlaybackHandle));
+        editor.selected_notes = BTreeSet::from([lead.id, bass.id]);
+        editor.set_active_track(1);
+        assert_eq!(editor.selected_notes, BTreeSet::from([bass.id]));
+        editor.set_active_track(5);
+        assert_eq!(editor.active_track(), 1);
+
+        editor.clipboard = vec![Note::new(0, 480, 43, 100)];
+        editor.paste_clipboard_at(960);
+        assert!(editor.state.notes.iter().any(|n| n.key == 43 && n.track == 1));
+
+        // 可编辑的轨道不能隐藏；隐藏的轨道不发声
+        editor.set_track_visible(1, false);
+        assert!(editor.is_track_visible(1));
+        editor.set_track_visible(0, false);
+        editor.apply_command(EditorCommand::SetPlayback(true));
+        editor.advance_playback(BEAT * 0.5);
+        assert_eq!(backend.ringing_keys(), BTreeSet::from([40]));
+
+        assert!(editor.undo());
+        assert_eq!(editor.active_track(), 1);
+        assert!(!editor.is_track_visible(0));
+    }
+
+    #[test]
+    fn rescaling_ticks_per_beat_carries_loop_and_snap_and_undoes_cleanly() {
+        let note = Note::new(480, 240, 60, 100);
+        let mut editor = MidiEditor::with_state(MidiState { notes: vec![note], ..MidiState::default() }, None);
+        editor.apply_command(EditorCommand::SetLoop { enabled: true, start_tick: 1920, end_tick: 3840 });
+        editor.apply_command(EditorCommand::SetSnap { interval: 120, mode: SnapMode::Absolute });
+        editor.take_events();
+
+        editor.apply_command(EditorCommand::RescaleTicksPerBeat(960));
+        assert_eq!(editor.state.ticks_per_beat, 960);
+        assert_eq!((editor.state.notes[0].start, editor.state.notes[0].duration), (960, 480));
+        assert_eq!((editor.loop_start_tick, editor.loop_end_tick), (3840, 7680));
+        assert_eq!(editor.snap_ticks(), 240);
+        assert!(editor
+            .take_events()
+            .iter()
+            .any(|e| matches!(e, EditorEvent::TicksPerBeatChanged { from: 480, to: 960 })));
+
+        // 撤销恢复原来的 tick，编辑器里的循环区与吸附间隔随之换算回去
+        assert!(editor.undo());
+        assert_eq!(editor.state.ticks_per_beat, 480);
+        assert_eq!(editor.state.notes[0], note);
+        assert_eq!((editor.loop_start_tick, editor.loop_end_tick), (1920, 3840));
+        assert_eq!(editor.snap_ticks(), 120);
+        assert!(editor.redo());
+        assert_eq!(editor.loop_start_tick, 3840);
+    }
+
+    #[test]
+    fn tap_tempo_averages_recent_taps_and_ignores_outliers_and_pauses() {
+        let mut editor = MidiEditor::new(None);
+        let undo_depth = editor.undo_depth();
+        // 前三次只记录，第四次起设置速度；0.6 秒一拍为 100 BPM，一次迟到的敲击被当作离群值丢弃
+        for now in [10.0, 10.6, 11.2] {
+            assert_eq!(editor.tap_tempo(now), None);
         }
-        match (self.snap_mode, reference) {
-            (SnapMode::Relative, Some(original)) => {
-                let delta = raw_tick - original as i64;
-                let snapped_delta = self.snap_value(delta);
-                (original as i64 + snapped_delta).max(0) as u64
-            }
-            _ => self.snap_value(raw_tick).max(0) as u64,
+        let bpm = editor.tap_tempo(12.2).unwrap();
+        assert!((bpm - 100.0).abs() < 0.01, "{bpm}");
+        let bpm = editor.tap_tempo(12.7).unwrap();
+        assert!(bpm > 100.0 && bpm < 110.0, "{bpm}");
+        assert_eq!(editor.state.bpm, bpm);
+        // 同一轮敲击只产生一个撤销步骤
+        assert_eq!(editor.undo_depth(), undo_depth + 1);
+
+        // 停顿超过 2 秒后重新开始一轮
+        for now in [15.0, 15.5, 16.0] {
+            assert_eq!(editor.tap_tempo(now), None);
         }
+        assert_eq!(editor.tap_tempo(16.5), Some(120.0));
+        assert_eq!(editor.undo_depth(), undo_depth + 2);
+        assert!(editor.undo());
+        assert_eq!(editor.state.bpm, bpm);
     }
 
-    fn ui_curve_lanes(&mut self, ui: &mut Ui) {
-        // Find velocity curve lane ID and clone data
-        let velocity_lane_id = self.state.curves.iter()
-            .find(|c| c.lane_type == CurveLaneType::Velocity)
-            .map(|c| c.id);
-        
-            if let Some(lane_id) = velocity_lane_id {
-                let key_width = 60.0; // Same as piano roll (for grid alignment calculation)
-                let tpb = self.state.ticks_per_beat.max(1) as u64;
-                let manual_scroll_x = self.manual_scroll_x;
-                let zoom_x = self.zoom_x;
-                let available_height = ui.available_height();
-                
-                // Clone points and lane info for rendering
-                let points_clone: Vec<_> = self.state.curves.iter()
-                    .find(|c| c.id == lane_id)
-                    .map(|c| c.points.clone())
-                    .unwrap_or_default();
-                let (min_val, max_val) = CurveLaneType::Velocity.value_range();
-                let value_range = max_val - min_val;
-                let dragging = self.dragging_curve_point;
-                
-                let mut point_to_delete: Option<CurvePointId> = None;
-                let mut point_to_start_drag: Option<CurvePointId> = None;
-                let mut new_point: Option<(u64, f32)> = None;
-                
-                // Curve editing area - full width, extends to window edges
-                ui.push_id("curve_editor_scroll", |ui| {
-                    ScrollArea::horizontal()
-                        .auto_shrink([false, false])
-                        .show(ui, |ui| {
-                        let available_width = ui.available_width();
-                        let (rect, response) = ui.allocate_exact_size(
-                            Vec2::new(available_width.max(400.0), available_height),
-                            Sense::click_and_drag()
-                        );
-                        
-                        let painter = ui.painter_at(rect);
-                        
-                        // Draw background
-                        painter.rect_filled(rect, 0.0, Color32::from_rgb(40, 40, 40));
-                        
-                        // Draw grid lines using EXACT same logic as piano roll
-                        // To align grids, we need to calculate note_offset_x the same way as piano roll
-                        // Piano roll: note_offset_x = rect.min.x + key_width + manual_scroll_x
-                        // But piano roll's rect.min.x includes the key area, while our rect.min.x is at window edge
-                        // So we need to offset by key_width to align: rect.min.x + key_width + manual_scroll_x
-                        // This ensures grid lines align perfectly even though curve editor extends to edges
-                        let note_offset_x = rect.min.x + key_width + manual_scroll_x;
-                        
-                        let denom = self.state.time_signature.1.max(1) as u64;
-                        let numer = self.state.time_signature.0.max(1) as u64;
-                        let ticks_per_measure = (tpb * numer * 4).saturating_div(denom).max(tpb);
-                        
-                        // Calculate visible range - allow showing tick 0 even if it's slightly to the left
-                        let visible_beats_start = (-manual_scroll_x / zoom_x).floor();
-                        let visible_beats_end = visible_beats_start + (rect.width() / zoom_x) + 2.0;
-                        // Allow rendering from tick 0 even if it's slightly outside visible range
-                        let render_start_beats = visible_beats_start.min(0.0);
-                        let mut start_tick = (render_start_beats * tpb as f32).floor() as i64;
-                        if start_tick < 0 {
-                            start_tick = 0;
-                        }
-                        let end_tick = (visible_beats_end * tpb as f32).ceil() as i64;
-                        
-                        let subdivision = if zoom_x >= 220.0 {
-                            8
-                        } else if zoom_x >= 90.0 {
-                            4
-                        } else if zoom_x >= 45.0 {
-                            2
-                        } else {
-                            1
-                        };
-                        let tick_step = (tpb / subdivision).max(1);
-                        
-                        let mut tick = (start_tick / tick_step as i64) * tick_step as i64;
-                        if tick < 0 {
-                            tick = 0;
-                        }
-                        
-                        let grid_top = rect.min.y;
-                        let grid_bottom = rect.max.y;
-                        
-                        let measure_line_color = Color32::from_rgb(210, 210, 210);
-                        let beat_line_color = Color32::from_rgb(140, 140, 140);
-                        let subdivision_color = Color32::from_rgb(90, 90, 90);
-                        
-                        // Allow rendering slightly to the left to show tick 0 grid line
-                        let render_left_bound = rect.min.x - 10.0;
-                        while tick <= end_tick {
-                            let x = note_offset_x + (tick as f32 / tpb as f32) * zoom_x;
-                            if x >= render_left_bound && x <= rect.max.x {
-                                if tick as u64 % ticks_per_measure == 0 {
-                                    painter.line_segment(
-                                        [Pos2::new(x, grid_top), Pos2::new(x, grid_bottom)],
-                                        Stroke::new(1.0, measure_line_color),
-                                    );
-                                } else if tick as u64 % tpb == 0 {
-                                    painter.line_segment(
-                                        [Pos2::new(x, grid_top), Pos2::new(x, grid_bottom)],
-                                        Stroke::new(1.0, beat_line_color),
-                                    );
-                                } else {
-                                    Self::draw_dashed_vertical_line(
-                                        &painter,
-                                        x,
-                                        grid_top,
-                                        grid_bottom,
-                                        Stroke::new(1.0, subdivision_color),
-                                    );
-                                }
-                            }
-                            tick += tick_step as i64;
-                        }
-                        
-                        // Draw horizontal grid (value lines)
-                        for i in 0..=4 {
-                            let y = rect.min.y + (rect.height() / 4.0) * i as f32;
-                            painter.line_segment(
-                                [Pos2::new(rect.min.x, y), Pos2::new(rect.max.x, y)],
-                                Stroke::new(1.0, Color32::from_rgb(50, 50, 50)),
-                            );
-                        }
-                        
-                        // Draw curve line
-                        if points_clone.len() >= 2 {
-                            let mut points_vec = Vec::new();
-                            for point in &points_clone {
-                                let x = note_offset_x + (point.tick as f32 / tpb as f32) * zoom_x;
-                                let normalized_value = (point.value - min_val) / value_range;
-                                let y = rect.max.y - normalized_value * rect.height();
-                                if x >= rect.min.x - 10.0 && x <= rect.max.x + 10.0 {
-                                    points_vec.push(Pos2::new(x, y));
-                                }
-                            }
-                            if points_vec.len() >= 2 {
-                                for i in 0..points_vec.len() - 1 {
-                                    painter.line_segment(
-                                        [points_vec[i], points_vec[i + 1]],
-                                        Stroke::new(2.0, Color32::from_rgb(100, 200, 100)),
-                                    );
-                                }
-                            }
-                        }
-                        
-                        // Draw curve points and handle interactions
-                        for point in &points_clone {
-                            let x = note_offset_x + (point.tick as f32 / tpb as f32) * zoom_x;
-                            let normalized_value = (point.value - min_val) / value_range;
-                            let y = rect.max.y - normalized_value * rect.height();
-                            
-                            if x >= rect.min.x - 5.0 && x <= rect.max.x + 5.0 {
-                                let point_pos = Pos2::new(x, y);
-                                let point_rect = Rect::from_center_size(point_pos, Vec2::new(8.0, 8.0));
-                                painter.circle_filled(point_pos, 4.0, Color32::from_rgb(150, 250, 150));
-                                painter.circle_stroke(point_pos, 4.0, Stroke::new(1.0, Color32::WHITE));
-                                
-                                // Handle point interactions
-                                if response.clicked_by(PointerButton::Primary) {
-                                    if let Some(pointer) = response.interact_pointer_pos() {
-                                        if point_rect.contains(pointer) {
-                                            point_to_start_drag = Some(point.id);
-                                        }
-                                    }
-                                }
-                                
-                                if response.clicked_by(PointerButton::Secondary) {
-                                    if let Some(pointer) = response.interact_pointer_pos() {
-                                        if point_rect.contains(pointer) && points_clone.len() > 1 {
-                                            point_to_delete = Some(point.id);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        
-                        // Handle dragging
-                        if let Some((drag_lane_id, drag_point_id)) = dragging {
-                            if drag_lane_id == lane_id && ui.input(|i| i.pointer.primary_down()) {
-                                if let Some(pointer) = response.interact_pointer_pos() {
-                                    // Convert pointer position to tick
-                                    // note_offset_x = rect.min.x + key_width + manual_scroll_x
-                                    // So: pointer.x - note_offset_x = pointer.x - rect.min.x - key_width - manual_scroll_x
-                                    // This gives us the position relative to tick 0
-                                    let rel_x = pointer.x - note_offset_x;
-                                    let beats = rel_x / zoom_x;
-                                    let tick = (beats * tpb as f32).round() as i64;
-                                    // Only allow dragging to tick >= 0
-                                    if tick >= 0 {
-                                        let disable_snap = ui.input(|i| i.modifiers.alt);
-                                        let snapped_tick = if disable_snap {
-                                            tick as u64
-                                        } else {
-                                            self.snap_value(tick).max(0) as u64
-                                        };
-                                        
-                                        let rel_y = pointer.y - rect.min.y;
-                                        let normalized_value = 1.0 - (rel_y / rect.height());
-                                        let value = min_val + normalized_value * value_range;
-                                        
-                                        if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == drag_lane_id) {
-                                            lane.update_point(drag_point_id, snapped_tick, value);
-                                            self.emit_event(EditorEvent::CurvePointUpdated {
-                                                lane_id: drag_lane_id,
-                                                point_id: drag_point_id,
-                                            });
-                                        }
-                                    }
-                                }
-                            } else {
-                                self.dragging_curve_point = None;
-                            }
-                        }
-                        
-                        // Handle adding new point
-                        if response.clicked_by(PointerButton::Primary) && dragging.is_none() && point_to_start_drag.is_none() {
-                            if let Some(pointer) = response.interact_pointer_pos() {
-                                if rect.contains(pointer) {
-                                    // Convert pointer position to tick
-                                    // note_offset_x = rect.min.x + key_width + manual_scroll_x
-                                    let rel_x = pointer.x - note_offset_x;
-                                    let beats = rel_x / zoom_x;
-                                    let tick = (beats * tpb as f32).round() as i64;
-                                    // Only allow adding points at tick >= 0
-                                    if tick >= 0 {
-                                        let disable_snap = ui.input(|i| i.modifiers.alt);
-                                        let snapped_tick = if disable_snap {
-                                            tick as u64
-                                        } else {
-                                            self.snap_value(tick).max(0) as u64
-                                        };
-                                        
-                                        let rel_y = pointer.y - rect.min.y;
-                                        let normalized_value = 1.0 - (rel_y / rect.height());
-                                        let value = min_val + normalized_value * value_range;
-                                        
-                                        // Check if clicking near existing point
-                                        let near_existing = points_clone.iter().any(|p| {
-                                            let px = note_offset_x + (p.tick as f32 / tpb as f32) * zoom_x;
-                                            let py = rect.max.y - ((p.value - min_val) / value_range) * rect.height();
-                                            (pointer.x - px).abs() < 10.0 && (pointer.y - py).abs() < 10.0
-                                        });
-                                        
-                                        if !near_existing {
-                                            new_point = Some((snapped_tick, value));
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    });
-                }); // Close push_id
-                
-                // Handle deletions and additions outside the closure
-                if let Some(point_id) = point_to_delete {
-                    self.push_undo_snapshot();
-                    if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
-                        lane.remove_point(point_id);
-                        self.emit_event(EditorEvent::CurvePointRemoved {
-                            lane_id,
-                            point_id,
-                        });
-                    }
-                }
-                
-                if let Some((tick, value)) = new_point {
-                    self.push_undo_snapshot();
-                    if let Some(lane) = self.state.curves.iter_mut().find(|c| c.id == lane_id) {
-                        let point = lane.insert_point(tick, value);
-                        self.emit_event(EditorEvent::CurvePointAdded {
-                            lane_id,
-                            point_id: point.id,
-                        });
-                    }
-                }
-                
-                if let Some(point_id) = point_to_start_drag {
-                    self.push_undo_snapshot();
-                    self.dragging_curve_point = Some((lane_id, point_id));
-                }
-            } else {
-                ui.label("No velocity curve lane found");
-            }
+    #[test]
+    fn key_signature_command_is_undoable_and_respells_names() {
+        let mut editor = MidiEditor::new(None);
+        editor.apply_command(EditorCommand::SetKeySignature(KeySignature::new(-2, false)));
+        assert_eq!(editor.midi_state().key_signature.label(), "Bb major");
+        assert_eq!(tooltip::format_pitch(70, editor.pitch_naming()), "Bb4");
+        assert!(editor.take_events().iter().any(|e| matches!(e, EditorEvent::KeySignatureChanged(_))));
+        assert!(editor.undo());
+        assert_eq!(editor.midi_state().key_signature, KeySignature::default());
+        assert_eq!(tooltip::format_pitch(70, editor.pitch_naming()), "A#4");
+
+        // 宿主可以把中央 C 叫作 C3
+        editor.apply_options(&MidiEditorOptions { middle_c_octave: 3, ..MidiEditorOptions::default() });
+        assert_eq!(tooltip::format_pitch(60, editor.pitch_naming()), "C3");
+    }
+
+    #[test]
+    fn text_events_are_added_edited_and_removed_with_undo() {
+        let mut editor = MidiEditor::new(None);
+        editor.add_text_event(TextEvent::new(960, TextEventKind::Lyric, "la"));
+        let first = editor.add_text_event(TextEvent::new(0, TextEventKind::Text, "Intro"));
+        assert_eq!(first, 0);
+        // 同一 tick 的新事件排在已有事件之后
+        assert_eq!(editor.add_text_event(TextEvent::new(960, TextEventKind::Lyric, "di")), 2);
+
+        editor.set_text_event(0, TextEvent::new(1920, TextEventKind::Text, "Outro"));
+        let texts: Vec<(u64, String)> =
+            editor.midi_state().text_events.iter().map(|e| (e.tick, e.text().into_owned())).collect();
+        assert_eq!(texts, vec![(960, "la".into()), (960, "di".into()), (1920, "Outro".into())]);
+        assert_eq!(editor.remove_text_event(1).map(|e| e.text), Some(b"di".to_vec()));
+        assert_eq!(editor.remove_text_event(5), None);
+
+        assert!(editor.undo());
+        assert_eq!(editor.midi_state().text_events.len(), 3);
+        assert!(editor.undo());
+        assert_eq!(editor.midi_state().text_events[0].text(), "Intro");
+    }
+
+    #[test]
+    fn edits_emit_fine_grained_events_and_state_replaced_only_on_request() {
+        let notes = vec![Note::new(0, 480, 60, 100), Note::new(480, 480, 62, 100)];
+        let mut editor = MidiEditor::with_state(MidiState { notes: notes.clone(), ..MidiState::default() }, None);
+        let replaced = |events: &[EditorEvent]| events.iter().any(|e| matches!(e, EditorEvent::StateReplaced(_)));
+        editor.take_events();
+
+        editor.set_bpm(140.0);
+        editor.set_time_signature(3, 4);
+        editor.edit_state(|state| state.notes[0].velocity = 90);
+        editor.selected_notes.extend(notes.iter().map(|n| n.id));
+        editor.apply_command(EditorCommand::BatchTransform {
+            transform_type: BatchTransformType::VelocityOffset,
+            value: -10.0,
+        });
+        let events = editor.take_events();
+        assert!(!replaced(&events));
+        assert!(events.iter().any(|e| matches!(e, EditorEvent::TempoChanged)));
+        assert!(events.iter().any(|e| matches!(e, EditorEvent::TimeSignatureChanged { numer: 3, denom: 4 })));
+        assert!(events.iter().any(|e| matches!(e, EditorEvent::StateEdited)));
+        assert!(events.iter().any(|e| matches!(e, EditorEvent::NotesChanged(ids) if ids.len() == 2)));
+
+        // 撤销仍然整体替换状态
+        assert!(editor.undo());
+        assert!(replaced(&editor.take_events()));
+
+        editor.apply_options(&MidiEditorOptions { state_replaced_on_edit: true, ..MidiEditorOptions::default() });
+        editor.set_bpm(90.0);
+        let events = editor.take_events();
+        assert!(matches!(events.as_slice(), [EditorEvent::TempoChanged, EditorEvent::StateReplaced(state)] if state.bpm == 90.0));
+    }
+
+    #[test]
+    fn every_tick_rescale_rounds_to_the_same_tick() {
+        // 479 / 5 = 95.8：截断会得到 95，四舍五入得到 96
+        let note = Note::new(479, 481, 60, 100);
+        let mut rescaled = MidiState { notes: vec![note], ..MidiState::default() };
+        rescaled.rescale_ticks_per_beat(96);
+        let expected = (rescaled.notes[0].start, rescaled.notes[0].duration);
+        assert_eq!(expected, (96, 96));
+
+        let payload = ClipboardPayload { ticks_per_beat: 480, notes: vec![note], ..ClipboardPayload::default() };
+        let pasted = payload.rescaled(96).notes[0];
+        assert_eq!((pasted.start, pasted.duration), expected);
+
+        let dragged = NoteDragPayload::from_notes(&[Note::new(0, 1, 60, 100), note], 480, 0, 60).notes_for(96)[1];
+        assert_eq!((dragged.start, dragged.duration), expected);
+
+        let mut editor = MidiEditor::with_state(MidiState { ticks_per_beat: 96, ..MidiState::default() }, None);
+        let source = MidiState { notes: vec![Note::new(0, 1, 40, 100), note], ..MidiState::default() };
+        let options = ImportMergeOptions { placement: ImportPlacement::Start, ..ImportMergeOptions::default() };
+        assert_eq!(editor.import_notes(source, options), 2);
+        let imported = editor.state.notes.iter().find(|n| n.key == 60).unwrap();
+        assert_eq!((imported.start, imported.duration), expected);
+    }
+
+    #[test]
+    fn merge_import_trims_covered_notes_when_auto_trim_is_on() {
+        let existing = Note::new(0, 960, 60, 100);
+        let mut editor = MidiEditor::with_state(MidiState { notes: vec![existing], ..MidiState::default() }, None);
+        editor.apply_options(&MidiEditorOptions { auto_trim_overlaps: true, ..MidiEditorOptions::default() });
+        let source = MidiState { notes: vec![Note::new(480, 960, 60, 90)], ..MidiState::default() };
+        let options = ImportMergeOptions { placement: ImportPlacement::Start, ..ImportMergeOptions::default() };
+        assert_eq!(editor.import_notes(source, options), 1);
+
+        let trimmed = editor.state.notes.iter().find(|n| n.id == existing.id).unwrap();
+        assert_eq!((trimmed.start, trimmed.duration), (0, 480));
+        assert!(editor.state.find_overlaps().is_empty());
+        // 导入与修剪是同一步撤销
+        assert!(editor.undo());
+        assert_eq!(editor.state.notes, vec![existing]);
+    }
+
+    #[test]
+    fn curve_points_copied_with_notes_keep_their_offset_from_the_notes() {
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        editor.state.notes.push(Note::new(0, 480, 60, 100));
+        editor.state.notes.push(Note::new(960, 480, 64, 100));
+        let lane_id = editor.add_cc_lane(7);
+        let lane = editor.state.curves.iter_mut().find(|c| c.id == lane_id).unwrap();
+        let ramp = vec![lane.insert_point(480, 20.0).id, lane.insert_point(720, 100.0).id];
+        editor.selected_notes = editor.state.notes.iter().map(|n| n.id).collect();
+        editor.selected_curve_points = ramp;
+        editor.copy_selection();
+
+        editor.paste_clipboard_at(1920);
+        let ticks = |editor: &MidiEditor, value: f32| {
+            let lane = editor.state.curves.iter().find(|c| c.lane_type == CurveLaneType::ControlChange(7)).unwrap();
+            lane.points.iter().filter(|p| p.value == value).map(|p| p.tick).collect::<Vec<_>>()
+        };
+        assert_eq!(ticks(&editor, 20.0), vec![480, 2400]);
+        assert_eq!(ticks(&editor, 100.0), vec![720, 2640]);
+
+        // 早于音符的点落在音符落点之前
+        let mut editor = MidiEditor::with_state(MidiState::default(), None);
+        editor.state.notes.push(Note::new(960, 480, 60, 100));
+        let lane_id = editor.add_cc_lane(7);
+        let lane = editor.state.curves.iter_mut().find(|c| c.id == lane_id).unwrap();
+        let early = lane.insert_point(480, 20.0).id;
+        editor.selected_notes = editor.state.notes.iter().map(|n| n.id).collect();
+        editor.selected_curve_points = vec![early];
+        editor.copy_selection();
+        editor.paste_clipboard_at(3840);
+        assert!(editor.state.notes.iter().any(|n| n.start == 3840));
+        assert_eq!(ticks(&editor, 20.0), vec![480, 3360]);
     }
 }
@@ -0,0 +1,391 @@
+//! Piano roll painting shared by `MidiEditor::ui` and offscreen rendering.
+//!
+//! 网格与音符的绘制都生成 `Shape` 列表：界面中直接交给 `Painter`，
+//! `render_to_image` 则交给下面的简单光栅化器，两者输出保持一致。
+
+use crate::editor::{BitSet128, MidiEditorTheme, ReferenceWaveform};
+use egui::epaint::ColorMode;
+use egui::*;
+
+/// Number of grid subdivisions per beat for the given horizontal zoom.
+///
+/// With `triplet` the beat is split into 3, 6 or 12 instead of 2, 4 or 8.
+pub(crate) fn grid_subdivision(zoom_x: f32, triplet: bool) -> u64 {
+    let straight = if zoom_x >= 220.0 {
+        8
+    } else if zoom_x >= 90.0 {
+        4
+    } else if zoom_x >= 45.0 {
+        2
+    } else {
+        1
+    };
+    if triplet && straight > 1 {
+        straight / 2 * 3
+    } else {
+        straight
+    }
+}
+
+pub(crate) fn dashed_vertical_line(shapes: &mut Vec<Shape>, x: f32, top: f32, bottom: f32, stroke: Stroke) {
+    let dash_len = 2.0;
+    let gap_len = 2.0;
+    let mut y = top;
+    while y < bottom {
+        let next = (y + dash_len).min(bottom);
+        shapes.push(Shape::line_segment([Pos2::new(x, y), Pos2::new(x, next)], stroke));
+        y += dash_len + gap_len;
+    }
+}
+
+/// 垂直网格（小节线、拍线、细分线）
+///
+/// `note_offset_x` 是 tick 0 的 x 坐标，只绘制落在 `x_range` 内的线。
+/// `triplet` 时细分线按三连音划分；细分线位置按拍内序号计算，`tpb` 不能整除时也不会累积误差。
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn vertical_grid(
+    shapes: &mut Vec<Shape>,
+    theme: &MidiEditorTheme,
+    triplet: bool,
+    note_offset_x: f32,
+    zoom_x: f32,
+    tpb: u64,
+    ticks_per_measure: u64,
+    start_tick: i64,
+    end_tick: i64,
+    x_range: Rangef,
+    y_range: Rangef,
+) {
+    let tpb = tpb.max(1);
+    let subdivision = grid_subdivision(zoom_x, triplet).min(tpb);
+    let mut index = start_tick.max(0) as u64 * subdivision / tpb;
+    loop {
+        let tick = (index / subdivision * tpb + index % subdivision * tpb / subdivision) as i64;
+        if tick > end_tick {
+            break;
+        }
+        index += 1;
+        let x = note_offset_x + (tick as f32 / tpb as f32) * zoom_x;
+        if x_range.contains(x) {
+            let top = Pos2::new(x, y_range.min);
+            let bottom = Pos2::new(x, y_range.max);
+            if (tick as u64).is_multiple_of(ticks_per_measure) {
+                shapes.push(Shape::line_segment([top, bottom], Stroke::new(1.0, theme.measure_line)));
+            } else if (tick as u64).is_multiple_of(tpb) {
+                shapes.push(Shape::line_segment([top, bottom], Stroke::new(1.0, theme.beat_line)));
+            } else {
+                dashed_vertical_line(shapes, x, y_range.min, y_range.max, Stroke::new(1.0, theme.subdivision_line));
+            }
+        }
+    }
+}
+
+/// 水平网格（共 `rows` 行，普通模式下每个音高一行），`keys_top` 是第一行顶边的 y 坐标
+pub(crate) fn horizontal_grid(
+    shapes: &mut Vec<Shape>,
+    theme: &MidiEditorTheme,
+    keys_top: f32,
+    zoom_y: f32,
+    rows: usize,
+    x_range: Rangef,
+    y_range: Rangef,
+) {
+    for i in 0..=rows {
+        let y = keys_top + i as f32 * zoom_y;
+        if y > y_range.min && y < y_range.max {
+            shapes.push(Shape::line_segment(
+                [Pos2::new(x_range.min, y), Pos2::new(x_range.max, y)],
+                Stroke::new(1.0, theme.row_line),
+            ));
+        }
+    }
+}
+
+/// 音阶高亮：主音行、音阶内与音阶外的行各叠加主题中对应的覆盖色；只遍历可见的行
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn scale_rows(
+    shapes: &mut Vec<Shape>,
+    theme: &MidiEditorTheme,
+    in_scale: BitSet128,
+    root: u8,
+    row_keys: &[u8],
+    keys_top: f32,
+    zoom_y: f32,
+    x_range: Rangef,
+    y_range: Rangef,
+) {
+    if zoom_y <= 0.0 {
+        return;
+    }
+    // 行号从顶部开始计，`row_keys[row]` 为该行的音高
+    let rows = row_keys.len() as f32;
+    let first_row = ((y_range.min - keys_top) / zoom_y).floor().clamp(0.0, rows) as usize;
+    let last_row = ((y_range.max - keys_top) / zoom_y).ceil().clamp(0.0, rows) as usize;
+    for (row, &key) in row_keys.iter().enumerate().take(last_row).skip(first_row) {
+        let top = keys_top + row as f32 * zoom_y;
+        let band = Rangef::new(top.max(y_range.min), (top + zoom_y).min(y_range.max));
+        if band.min >= band.max {
+            continue;
+        }
+        let color = if !in_scale.contains(key) {
+            theme.out_of_scale_row
+        } else if key % 12 == root % 12 {
+            theme.scale_root_row
+        } else {
+            theme.scale_row
+        };
+        shapes.push(Shape::rect_filled(Rect::from_x_y_ranges(x_range, band), 0.0, color));
+    }
+}
+
+/// 不允许放置音符的音高行：斜线阴影
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn disallowed_rows(
+    shapes: &mut Vec<Shape>,
+    theme: &MidiEditorTheme,
+    allowed: impl Fn(u8) -> bool,
+    row_keys: &[u8],
+    keys_top: f32,
+    zoom_y: f32,
+    x_range: Rangef,
+    y_range: Rangef,
+) {
+    let spacing = 8.0;
+    let stroke = Stroke::new(1.0, theme.disallowed_hatch);
+    for (row, &key) in row_keys.iter().enumerate() {
+        if allowed(key) {
+            continue;
+        }
+        let top = keys_top + row as f32 * zoom_y;
+        let row = Rangef::new(top.max(y_range.min), (top + zoom_y).min(y_range.max));
+        if row.min >= row.max {
+            continue;
+        }
+        shapes.push(Shape::rect_filled(
+            Rect::from_x_y_ranges(x_range, row),
+            0.0,
+            theme.disallowed_row,
+        ));
+        // 45° 斜线，x 坐标按 spacing 对齐到世界坐标，滚动时图案保持稳定
+        let height = row.max - row.min;
+        let mut x = ((x_range.min - height) / spacing).floor() * spacing;
+        while x < x_range.max {
+            let (mut x0, mut y0) = (x, row.max);
+            let (mut x1, mut y1) = (x + height, row.min);
+            if x0 < x_range.min {
+                y0 -= x_range.min - x0;
+                x0 = x_range.min;
+            }
+            if x1 > x_range.max {
+                y1 += x1 - x_range.max;
+                x1 = x_range.max;
+            }
+            if x1 > x0 {
+                shapes.push(Shape::line_segment([Pos2::new(x0, y0), Pos2::new(x1, y1)], stroke));
+            }
+            x += spacing;
+        }
+    }
+}
+
+/// 参考音频波形：每个像素列一条竖线，取该列时间范围内峰值的包络
+///
+/// `origin_x` 是 0 秒的 x 坐标；波形以 `band` 的中线为零点，只绘制与 `x_range`、`y_range` 相交的部分。
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn reference_waveform(
+    shapes: &mut Vec<Shape>,
+    theme: &MidiEditorTheme,
+    waveform: &ReferenceWaveform,
+    origin_x: f32,
+    pixels_per_second: f32,
+    x_range: Rangef,
+    y_range: Rangef,
+    band: Rangef,
+) {
+    if pixels_per_second <= 0.0 || y_range.min >= y_range.max {
+        return;
+    }
+    let start_x = x_range.min.max(origin_x).floor();
+    let end_x = x_range.max.min(origin_x + waveform.duration_seconds * pixels_per_second);
+    let center = band.center();
+    let half_height = band.span() * 0.5;
+    let mut x = start_x;
+    while x < end_x {
+        let t0 = (x - origin_x) / pixels_per_second;
+        let t1 = (x + 1.0 - origin_x) / pixels_per_second;
+        if let Some((min, max)) = waveform.peak_range(t0, t1) {
+            let top = (center - max * half_height).max(y_range.min);
+            let bottom = (center - min * half_height).min(y_range.max);
+            if bottom >= top {
+                shapes.push(Shape::rect_filled(
+                    Rect::from_min_max(Pos2::new(x, top), Pos2::new(x + 1.0, bottom.max(top + 1.0))),
+                    0.0,
+                    theme.waveform,
+                ));
+            }
+        }
+        x += 1.0;
+    }
+}
+
+/// 音符主体：填充 + 描边（选中时描边加粗）
+pub(crate) fn note(shapes: &mut Vec<Shape>, theme: &MidiEditorTheme, note_rect: Rect, fill: Color32, selected: bool) {
+    let stroke_width = if selected { 4.0 } else { 1.0 };
+    shapes.push(Shape::rect_filled(note_rect.shrink(1.0), 2.0, fill));
+    shapes.push(Shape::rect_stroke(note_rect.shrink(1.0), 2.0, Stroke::new(stroke_width, theme.note_outline)));
+}
+
+/// 音符主体的网格版本：描边色的外框内叠一层填充，两个直角四边形
+///
+/// 钢琴卷帘把所有可见音符合并进同一个 `Mesh` 一次提交；选中音符的加粗描边另用 [`note`] 叠加。
+pub(crate) fn note_quads(mesh: &mut Mesh, theme: &MidiEditorTheme, note_rect: Rect, fill: Color32) {
+    let outer = note_rect.shrink(1.0);
+    mesh.add_colored_rect(outer, theme.note_outline);
+    let inner = outer.shrink(1.0);
+    if inner.is_positive() {
+        mesh.add_colored_rect(inner, fill);
+    }
+}
+
+/// 鼓映射模式下的音符：内接于 `note_rect` 的菱形，描边与普通音符一致
+pub(crate) fn drum_hit(shapes: &mut Vec<Shape>, theme: &MidiEditorTheme, note_rect: Rect, fill: Color32, selected: bool) {
+    let stroke_width = if selected { 3.0 } else { 1.0 };
+    let rect = note_rect.shrink(1.0);
+    let points = vec![
+        Pos2::new(rect.center().x, rect.min.y),
+        Pos2::new(rect.max.x, rect.center().y),
+        Pos2::new(rect.center().x, rect.max.y),
+        Pos2::new(rect.min.x, rect.center().y),
+    ];
+    shapes.push(Shape::convex_polygon(points, fill, Stroke::new(stroke_width, theme.note_outline)));
+}
+
+/// 音符的填充色
+pub(crate) fn note_fill(theme: &MidiEditorTheme, selected: bool) -> Color32 {
+    if selected {
+        theme.selected_note
+    } else {
+        theme.note
+    }
+}
+
+/// 按力度着色时的填充色：1..=127 在蓝、绿、红三个色标之间线性插值
+pub(crate) fn velocity_fill(theme: &MidiEditorTheme, velocity: u8) -> Color32 {
+    let stops = [theme.velocity_low, theme.note, theme.velocity_high];
+    let t = (velocity.clamp(1, 127) - 1) as f32 / 126.0 * 2.0;
+    let index = (t as usize).min(1);
+    stops[index].lerp_to_gamma(stops[index + 1], t - index as f32)
+}
+
+/// 画在音符内的文字颜色：按填充色的亮度选深色或浅色，保证可读
+pub(crate) fn note_text_color(fill: Color32) -> Color32 {
+    let luma = 0.299 * fill.r() as f32 + 0.587 * fill.g() as f32 + 0.114 * fill.b() as f32;
+    if luma > 140.0 {
+        Color32::from_gray(20)
+    } else {
+        Color32::from_gray(235)
+    }
+}
+
+/// 多轨状态下非当前轨道的音符：半透明灰色，无描边
+pub(crate) fn ghost_note(shapes: &mut Vec<Shape>, theme: &MidiEditorTheme, note_rect: Rect) {
+    shapes.push(Shape::rect_filled(note_rect.shrink(1.0), 2.0, theme.ghost_note));
+}
+
+/// 力度柱：在 `lane_rect` 中从底部向上画出与力度成比例的竖线
+pub(crate) fn velocity_stem(shapes: &mut Vec<Shape>, x: f32, velocity: u8, lane_rect: Rect, color: Color32) {
+    let height = lane_rect.height() * (velocity.min(127) as f32 / 127.0);
+    shapes.push(Shape::line_segment(
+        [Pos2::new(x, lane_rect.max.y), Pos2::new(x, lane_rect.max.y - height)],
+        Stroke::new(2.0, color),
+    ));
+    shapes.push(Shape::rect_filled(
+        Rect::from_center_size(Pos2::new(x, lane_rect.max.y - height), Vec2::splat(4.0)),
+        0.0,
+        color,
+    ));
+}
+
+/// Rasterizes the shapes produced above into an image.
+///
+/// Only solid rects and line segments are supported (rounding and text are ignored),
+/// which is all the piano roll painting emits. Output is fully deterministic.
+pub(crate) fn rasterize(shapes: &[Shape], size: [usize; 2], background: Color32) -> ColorImage {
+    let mut image = ColorImage::new(size, background);
+    for shape in shapes {
+        paint_shape(&mut image, shape);
+    }
+    image
+}
+
+fn paint_shape(image: &mut ColorImage, shape: &Shape) {
+    match shape {
+        Shape::Vec(shapes) => {
+            for shape in shapes {
+                paint_shape(image, shape);
+            }
+        }
+        Shape::Rect(rect_shape) => {
+            fill_rect(image, rect_shape.rect, rect_shape.fill);
+            let stroke = rect_shape.stroke;
+            if stroke.width > 0.0 {
+                let r = rect_shape.rect;
+                let half = stroke.width / 2.0;
+                fill_rect(image, Rect::from_x_y_ranges(r.min.x - half..=r.max.x + half, r.min.y - half..=r.min.y + half), stroke.color);
+                fill_rect(image, Rect::from_x_y_ranges(r.min.x - half..=r.max.x + half, r.max.y - half..=r.max.y + half), stroke.color);
+                fill_rect(image, Rect::from_x_y_ranges(r.min.x - half..=r.min.x + half, r.min.y + half..=r.max.y - half), stroke.color);
+                fill_rect(image, Rect::from_x_y_ranges(r.max.x - half..=r.max.x + half, r.min.y + half..=r.max.y - half), stroke.color);
+            }
+        }
+        Shape::LineSegment { points, stroke } => {
+            if let ColorMode::Solid(color) = stroke.color {
+                line(image, *points, stroke.width, color);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn line(image: &mut ColorImage, [a, b]: [Pos2; 2], width: f32, color: Color32) {
+    let half = (width / 2.0).max(0.5);
+    if (a.x - b.x).abs() < f32::EPSILON || (a.y - b.y).abs() < f32::EPSILON {
+        let rect = Rect::from_two_pos(a, b).expand(half);
+        fill_rect(image, rect, color);
+        return;
+    }
+    // Diagonal lines are stamped pixel by pixel along their length.
+    let steps = a.distance(b).ceil() as usize;
+    for i in 0..=steps {
+        let p = a.lerp(b, i as f32 / steps as f32);
+        fill_rect(image, Rect::from_center_size(p, Vec2::splat(half * 2.0)), color);
+    }
+}
+
+fn fill_rect(image: &mut ColorImage, rect: Rect, color: Color32) {
+    if color.a() == 0 || !rect.is_positive() {
+        return;
+    }
+    let [width, height] = image.size;
+    let x0 = rect.min.x.round().max(0.0) as usize;
+    let y0 = rect.min.y.round().max(0.0) as usize;
+    let x1 = (rect.max.x.round().max(0.0) as usize).min(width);
+    let y1 = (rect.max.y.round().max(0.0) as usize).min(height);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let pixel = &mut image.pixels[y * width + x];
+            *pixel = blend(*pixel, color);
+        }
+    }
+}
+
+/// Source-over blending of premultiplied colors.
+fn blend(dst: Color32, src: Color32) -> Color32 {
+    let inv = 255 - src.a() as u32;
+    let channel = |s: u8, d: u8| (s as u32 + (d as u32 * inv + 127) / 255).min(255) as u8;
+    Color32::from_rgba_premultiplied(
+        channel(src.r(), dst.r()),
+        channel(src.g(), dst.g()),
+        channel(src.b(), dst.b()),
+        channel(src.a(), dst.a()),
+    )
+}
@@ -0,0 +1,147 @@
+//! 示例与测试用的演示编曲
+//!
+//! 剪辑内容由 `egui_midi::demo` 生成并嵌入剪辑；同一种子总是生成相同的编排（ID 除外）。
+
+use crate::preview_loader::preview_notes_from_state;
+use crate::project::ProjectFile;
+use crate::structure::{Clip, ClipType, MidiClipData, TimelineMarker, TimelineState, Track};
+use egui::Color32;
+use egui_midi::demo::{generate_with_seed, DemoKind, DEFAULT_SEED};
+
+const TRACK_COLORS: [Color32; 8] = [
+    Color32::from_rgb(220, 90, 90),
+    Color32::from_rgb(230, 160, 70),
+    Color32::from_rgb(210, 200, 80),
+    Color32::from_rgb(110, 200, 100),
+    Color32::from_rgb(80, 190, 190),
+    Color32::from_rgb(90, 140, 230),
+    Color32::from_rgb(150, 110, 220),
+    Color32::from_rgb(210, 110, 190),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DemoProject {
+    /// 8 条轨道、每条 16 个一小节剪辑的密集编排
+    Grid,
+    /// 稀疏的歌曲结构（前奏、主歌、副歌……），每段有时间轴标记
+    SongForm,
+}
+
+impl DemoProject {
+    pub const ALL: [DemoProject; 2] = [DemoProject::Grid, DemoProject::SongForm];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DemoProject::Grid => "Grid (8 tracks x 16 clips)",
+            DemoProject::SongForm => "Song Form",
+        }
+    }
+}
+
+/// 用默认种子生成演示编曲
+pub fn generate_project(kind: DemoProject) -> ProjectFile {
+    generate_project_with_seed(kind, DEFAULT_SEED)
+}
+
+/// 用指定种子生成演示编曲
+pub fn generate_project_with_seed(kind: DemoProject, seed: u64) -> ProjectFile {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    match kind {
+        DemoProject::Grid => grid(&mut rng),
+        DemoProject::SongForm => song_form(&mut rng),
+    }
+}
+
+fn grid(rng: &mut fastrand::Rng) -> ProjectFile {
+    let timeline = TimelineState::default();
+    let bar = timeline.tick_to_time(timeline.ticks_per_measure());
+    let names = ["Drums", "Bass", "Keys", "Pads", "Lead", "Strings", "Perc", "FX"];
+    let tracks = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let mut track = new_track(name, index);
+            let content = track_content(index);
+            for clip_index in 0..16 {
+                let start = clip_index as f64 * bar;
+                let clip = demo_clip(&track, &timeline, content, rng.u64(..), start, 1);
+                track.clips.push(clip);
+            }
+            track
+        })
+        .collect();
+    ProjectFile::new(timeline, tracks)
+}
+
+fn song_form(rng: &mut fastrand::Rng) -> ProjectFile {
+    let mut timeline = TimelineState { bpm: 110.0, ..TimelineState::default() };
+    let bar = timeline.tick_to_time(timeline.ticks_per_measure());
+    // (段落名, 小节数, 各轨道是否演奏：Drums, Bass, Keys, Pads)
+    let sections: [(&str, u64, [bool; 4]); 8] = [
+        ("Intro", 4, [false, false, true, true]),
+        ("Verse 1", 8, [true, true, true, false]),
+        ("Chorus 1", 8, [true, true, true, true]),
+        ("Verse 2", 8, [true, true, true, false]),
+        ("Chorus 2", 8, [true, true, true, true]),
+        ("Bridge", 4, [false, true, false, true]),
+        ("Chorus 3", 8, [true, true, true, true]),
+        ("Outro", 4, [false, false, true, true]),
+    ];
+    let mut tracks: Vec<Track> = ["Drums", "Bass", "Keys", "Pads"]
+        .iter()
+        .enumerate()
+        .map(|(index, name)| new_track(name, index))
+        .collect();
+    let mut bar_index = 0;
+    for (section_index, (name, bars, plays)) in sections.iter().enumerate() {
+        let start = bar_index as f64 * bar;
+        let color = TRACK_COLORS[section_index % TRACK_COLORS.len()];
+        timeline.insert_marker(TimelineMarker::new(start, name.to_string(), Some(color)));
+        for (index, track) in tracks.iter_mut().enumerate() {
+            // 键盘在主歌里随机留白，让编排更稀疏
+            let keys_rest = index == 2 && name.starts_with("Verse") && rng.bool();
+            if plays[index] && !keys_rest {
+                let clip = demo_clip(track, &timeline, track_content(index), rng.u64(..), start, *bars);
+                track.clips.push(clip);
+            }
+        }
+        bar_index += bars;
+    }
+    ProjectFile::new(timeline, tracks)
+}
+
+fn new_track(name: &str, index: usize) -> Track {
+    let mut track = Track::new(name.to_string());
+    track.color = Some(TRACK_COLORS[index % TRACK_COLORS.len()]);
+    track
+}
+
+/// 轨道使用的演示内容（压力内容太大，不用于剪辑）
+fn track_content(track_index: usize) -> DemoKind {
+    [DemoKind::DrumGroove, DemoKind::CMajorScale, DemoKind::SustainedPads][track_index % 3]
+}
+
+/// 生成 `bars` 小节长的 MIDI 剪辑，内容按剪辑长度截断，速度与编曲一致
+fn demo_clip(track: &Track, timeline: &TimelineState, content: DemoKind, seed: u64, start: f64, bars: u64) -> Clip {
+    let mut state = generate_with_seed(content, seed);
+    state.bpm = timeline.bpm;
+    state.time_signature = timeline.time_signature;
+    let end = state.ticks_per_measure() * bars;
+    state.notes.retain(|note| note.start < end);
+    for note in &mut state.notes {
+        note.duration = note.duration.min(end - note.start);
+    }
+
+    let duration = timeline.tick_to_time(timeline.ticks_per_measure() * bars);
+    let mut clip = Clip::new_midi(track.id, start, duration, content.label().to_string());
+    clip.color = track.color.unwrap_or(clip.color);
+    clip.clip_type = ClipType::Midi {
+        midi_data: Some(MidiClipData {
+            midi_file_path: None,
+            preview_notes: preview_notes_from_state(&state),
+            midi_state: Some(state),
+            source_file_path: None,
+        }),
+    };
+    clip
+}
@@ -2,8 +2,11 @@
 //!
 //! 定义了音轨编辑器的命令系统和事件系统，用于与宿主应用交互。
 
-use crate::structure::{ClipId, TrackId, ClipType};
+use crate::structure::{ClipId, TrackId, ClipType, MarkerId};
+use egui::{Color32, Pos2};
 
+// CreateClip 可能携带嵌入的 MidiState；命令只是临时值，不值得为此装箱
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug)]
 pub enum TrackEditorCommand {
     CreateClip {
@@ -35,16 +38,31 @@ pub enum TrackEditorCommand {
         clip_id: ClipId,
         new_name: String,
     },
+    SetClipMute {
+        clip_id: ClipId,
+        muted: bool,
+    },
+    SetClipColor {
+        clip_ids: Vec<ClipId>,
+        color: Color32,
+    },
     CreateTrack {
         name: String,
     },
     DeleteTrack {
         track_id: TrackId,
     },
+    /// 删除所有轨道及其剪辑
+    ClearAll,
     RenameTrack {
         track_id: TrackId,
         new_name: String,
     },
+    /// 设置轨道颜色，并同步修改轨道上所有剪辑的颜色
+    SetTrackColor {
+        track_id: TrackId,
+        color: Color32,
+    },
     SetPlayhead {
         position: f64,
     },
@@ -113,6 +131,61 @@ pub enum TrackEditorCommand {
         clip_id: ClipId,
         new_file_path: String,
     },
+    /// 在后台重新读取剪辑文件并更新预览（完成后发出 `ClipPreviewLoaded`）
+    ReloadClipPreview {
+        clip_id: ClipId,
+    },
+    /// 读取文件剪辑引用的文件并把内容嵌入剪辑（`midi_state`），之后不再依赖该文件；
+    /// 原路径按 `TrackEditorOptions::keep_source_path_on_embed` 清除或保留为 `source_file_path`
+    EmbedClipContent {
+        clip_id: ClipId,
+    },
+    /// 把剪辑嵌入的内容写入 `path`，并让剪辑改为引用该文件
+    ExtractClipContent {
+        clip_id: ClipId,
+        path: String,
+    },
+    AddMarker {
+        position: f64,
+        name: String,
+        color: Option<Color32>,
+    },
+    RemoveMarker {
+        marker_id: MarkerId,
+    },
+}
+
+/// 可能需要用户确认的破坏性操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DestructiveAction {
+    /// 删除轨道，`clip_count` 为轨道上的剪辑数
+    DeleteTrack { track_id: TrackId, clip_count: usize },
+    /// 删除 `count` 个剪辑
+    DeleteClips { count: usize },
+    /// 清空工程
+    ClearAll { track_count: usize, clip_count: usize },
+}
+
+impl DestructiveAction {
+    /// 受影响的剪辑数
+    pub fn clip_count(&self) -> usize {
+        match *self {
+            DestructiveAction::DeleteTrack { clip_count, .. } => clip_count,
+            DestructiveAction::DeleteClips { count } => count,
+            DestructiveAction::ClearAll { clip_count, .. } => clip_count,
+        }
+    }
+}
+
+/// 确认钩子对破坏性操作的处理方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmStrategy {
+    /// 直接执行（例如宿主有自己的撤销系统）
+    Proceed,
+    /// 弹出编辑器内置的确认对话框，确认后才执行
+    Ask,
+    /// 放弃执行，不发出任何事件
+    Cancel,
 }
 
 #[derive(Clone, Debug)]
@@ -137,6 +210,31 @@ pub enum TrackEditorEvent {
         clip_id: ClipId,
         new_name: String,
     },
+    ClipMuteChanged {
+        clip_id: ClipId,
+        muted: bool,
+    },
+    ClipColorChanged {
+        clip_id: ClipId,
+        color: Color32,
+    },
+    /// 后台加载的预览音符已应用到剪辑
+    ClipPreviewLoaded {
+        clip_id: ClipId,
+    },
+    /// 剪辑内容在文件引用与嵌入之间转换完成（预览已刷新）
+    ClipContentChanged {
+        clip_id: ClipId,
+    },
+    /// 用户在剪辑菜单中选择了 "Save content as…"；宿主选择路径后发送 `ExtractClipContent`
+    ClipContentSaveRequested {
+        clip_id: ClipId,
+    },
+    /// `EmbedClipContent` / `ExtractClipContent` 失败，剪辑保持原样
+    ClipContentFailed {
+        clip_id: ClipId,
+        error: String,
+    },
     ClipDeleted {
         clip_id: ClipId,
     },
@@ -149,6 +247,14 @@ pub enum TrackEditorEvent {
     TrackDeleted {
         track_id: TrackId,
     },
+    TrackRenamed {
+        track_id: TrackId,
+        new_name: String,
+    },
+    TrackColorChanged {
+        track_id: TrackId,
+        color: Color32,
+    },
     TimeSignatureChanged {
         numer: u8,
         denom: u8,
@@ -192,4 +298,29 @@ pub enum TrackEditorEvent {
         track_id: TrackId,
         monitor: bool,
     },
+    MarkerAdded {
+        marker_id: MarkerId,
+    },
+    MarkerRemoved {
+        marker_id: MarkerId,
+    },
+    /// 右键点击没有被内置菜单处理（或 `builtin_context_menus` 关闭），宿主可在 `screen_pos` 显示自己的菜单；
+    /// `time` 为点击处未吸附的时间（秒），点击轨道头时为播放头位置
+    ContextRequested {
+        area: ContextArea,
+        time: f64,
+        screen_pos: Pos2,
+    },
+}
+
+/// `ContextRequested` 的点击区域
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContextArea {
+    /// 顶部时间轴
+    Timeline,
+    /// 轨道上的空白网格
+    TrackLane(TrackId),
+    Clip(ClipId),
+    /// 左侧轨道头
+    Header(TrackId),
 }
@@ -0,0 +1,151 @@
+//! 分轨导出模块
+//!
+//! 把一条轨道上的 MIDI 剪辑合并为单轨 SMF（stem），可只导出某个时间范围。
+
+use crate::preview_loader::load_clip_state;
+use crate::structure::{Clip, ClipId, ClipType, MidiState, TimelineState, Track, TrackId};
+use egui_midi::structure::{Note, TrackMeta};
+use midly::Smf;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportError {
+    TrackNotFound(TrackId),
+    EmptyRange { start: f64, end: f64 },
+    ClipContent { clip_id: ClipId, error: String },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::TrackNotFound(track_id) => write!(f, "轨道 {} 不存在", track_id.0),
+            ExportError::EmptyRange { start, end } => {
+                write!(f, "导出范围 {start:.3}s - {end:.3}s 为空")
+            }
+            ExportError::ClipContent { clip_id, error } => {
+                write!(f, "无法读取剪辑 {} 的内容：{error}", clip_id.0)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// 将轨道在 `[start, end)` 秒范围内的 MIDI 内容合并为一个 `MidiState`
+///
+/// 结果使用工程的 BPM、TPB 和拍号，范围起点对应 tick 0。静音剪辑被跳过；
+/// 跨越剪辑边界或范围边界的音符被截断到边界内；力度曲线折算进音符力度。
+pub fn track_range_state(
+    track: &Track,
+    timeline: &TimelineState,
+    start: f64,
+    end: f64,
+) -> Result<MidiState, ExportError> {
+    let mut notes = Vec::new();
+    let mut meta: Option<TrackMeta> = None;
+    for clip in track.clips.iter().filter(|clip| !clip.muted) {
+        let window_start = clip.start_time.max(start);
+        let window_end = clip.end_time().min(end);
+        if window_end <= window_start {
+            continue;
+        }
+        let Some(content) = clip_content(clip)? else {
+            continue;
+        };
+        for note in &content.notes {
+            let note_on = (clip.start_time + content.ticks_to_seconds(note.start as f64)).max(window_start);
+            let note_off =
+                (clip.start_time + content.ticks_to_seconds((note.start + note.duration) as f64)).min(window_end);
+            if note_off <= note_on {
+                continue;
+            }
+            let tick_on = timeline.time_to_tick(note_on - start);
+            let tick_off = timeline.time_to_tick(note_off - start);
+            if tick_off <= tick_on {
+                continue;
+            }
+            let mut exported = Note::new(
+                tick_on,
+                tick_off - tick_on,
+                note.key,
+                content.apply_velocity_curve_to_note(note),
+            );
+            exported.label = note.label;
+            notes.push(exported);
+        }
+        meta.get_or_insert_with(|| content.track.clone());
+    }
+    notes.sort_by_key(|note| (note.start, note.key));
+
+    let mut track_meta = meta.unwrap_or_default();
+    track_meta.track_name = Some(track.name.clone());
+    Ok(MidiState {
+        notes,
+        ticks_per_beat: timeline.ticks_per_beat,
+        bpm: timeline.bpm,
+        time_signature: timeline.time_signature,
+        track: track_meta,
+        ..MidiState::default()
+    })
+}
+
+/// 导出整条轨道（从 0 秒到最后一个剪辑的终点）
+pub fn track_smf(track: &Track, timeline: &TimelineState) -> Result<Smf<'static>, ExportError> {
+    let end = track.clips.iter().map(Clip::end_time).fold(0.0, f64::max);
+    Ok(track_range_state(track, timeline, 0.0, end)?.to_smf())
+}
+
+/// 导出多条轨道在 `[start, end)` 秒范围内的内容，每条轨道一个 SMF，顺序与 `track_ids` 一致
+pub fn range_smfs(
+    tracks: &[Track],
+    timeline: &TimelineState,
+    start: f64,
+    end: f64,
+    track_ids: &[TrackId],
+) -> Result<Vec<(TrackId, Smf<'static>)>, ExportError> {
+    let start = start.max(0.0);
+    if end.is_nan() || end <= start {
+        return Err(ExportError::EmptyRange { start, end });
+    }
+    track_ids
+        .iter()
+        .map(|&track_id| {
+            let track = tracks
+                .iter()
+                .find(|track| track.id == track_id)
+                .ok_or(ExportError::TrackNotFound(track_id))?;
+            Ok((track_id, track_range_state(track, timeline, start, end)?.to_smf()))
+        })
+        .collect()
+}
+
+/// 由轨道名称生成可用作文件名的字符串（不含扩展名）
+///
+/// 非法字符和控制字符替换为 `_`，去掉首尾的空格和点；结果为空时使用 `fallback`。
+pub fn stem_file_name(track_name: &str, fallback: &str) -> String {
+    const ILLEGAL: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+    let sanitized: String = track_name
+        .chars()
+        .map(|c| if ILLEGAL.contains(&c) || c.is_control() { '_' } else { c })
+        .collect();
+    let trimmed = sanitized.trim_matches(|c: char| c == ' ' || c == '.');
+    if trimmed.is_empty() {
+        fallback.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// 剪辑的 MIDI 内容：引用文件时读取文件，否则使用内嵌内容；没有内容的剪辑返回 `None`
+fn clip_content(clip: &Clip) -> Result<Option<MidiState>, ExportError> {
+    let ClipType::Midi { midi_data: Some(midi_data) } = &clip.clip_type else {
+        return Ok(None);
+    };
+    if let Some(path) = &midi_data.midi_file_path {
+        return load_clip_state(Path::new(path))
+            .map(Some)
+            .map_err(|error| ExportError::ClipContent { clip_id: clip.id, error });
+    }
+    Ok(midi_data.midi_state.clone())
+}
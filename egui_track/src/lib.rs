@@ -11,7 +11,7 @@
 //!
 //! ## 基本使用
 //!
-//! ```rust
+//! ```rust,ignore
 //! use egui_track::{TrackEditor, TrackEditorOptions};
 //!
 //! let mut editor = TrackEditor::new(TrackEditorOptions::default());
@@ -22,7 +22,7 @@
 //!
 //! ## 集成到宿主应用
 //!
-//! ```rust
+//! ```rust,ignore
 //! use egui_track::{TrackEditor, TrackEditorEvent, TrackEditorCommand};
 //!
 //! // 创建编辑器
@@ -50,10 +50,16 @@ pub mod structure;
 pub mod editor;
 pub mod ui;
 pub mod project;
+pub mod preview_loader;
+pub mod export;
 pub mod utils;
+pub mod demo;
 
-pub use structure::{Track, Clip, TrackId, ClipId, TimelineState, ClipType, MidiClipData, AudioClipData, PreviewNote};
-pub use editor::{TrackEditorCommand, TrackEditorEvent};
-pub use ui::{TrackEditor, TrackEditorOptions};
+pub use structure::{Track, Clip, TrackId, ClipId, TimelineState, ClipType, MidiClipData, AudioClipData, PreviewNote, MarkerId, TimelineMarker};
+pub use editor::{ConfirmStrategy, ContextArea, DestructiveAction, TrackEditorCommand, TrackEditorEvent};
+pub use ui::{TrackEditor, TrackEditorOptions, ClipDoubleClickAction};
 pub use project::ProjectFile;
-pub use utils::format_time;
+pub use export::{stem_file_name, ExportError};
+pub use utils::{clip_tooltip, format_time};
+pub use egui_midi::sync::{AtomicTransport, SyncSource, TransportSync};
+pub use egui_midi::palette_picker::{palette_button, palette_picker, RecentColors};
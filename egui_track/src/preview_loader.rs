@@ -0,0 +1,140 @@
+//! 预览音符后台加载模块
+//!
+//! 带文件路径但没有预览数据的 MIDI 剪辑会被排入队列，由后台线程读取并解析文件，
+//! 编辑器每帧取回已完成的结果并逐个应用，避免打开项目时同步解析所有剪辑文件。
+//! 剪辑文件的读写（[`load_clip_state`] / [`save_clip_state`]）也在这里，嵌入和提取剪辑内容时共用。
+
+use crate::structure::{ClipId, MidiState, PreviewNote};
+use egui_midi::structure::SmfImportOptions;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// 单个剪辑最多保留的预览音符数量，超出时均匀采样
+const MAX_PREVIEW_NOTES: usize = 1000;
+
+/// 一次加载的结果
+pub type PreviewLoadResult = Result<Vec<PreviewNote>, String>;
+
+/// 从 MIDI 文件生成预览音符（时间以秒为单位，相对于剪辑开始）
+pub fn load_preview_notes(path: &Path) -> PreviewLoadResult {
+    load_clip_state(path).map(|state| preview_notes_from_state(&state))
+}
+
+/// 由 MIDI 状态生成预览音符（时间以秒为单位，相对于剪辑开始）
+pub fn preview_notes_from_state(state: &MidiState) -> Vec<PreviewNote> {
+    let step = state.notes.len().div_ceil(MAX_PREVIEW_NOTES).max(1);
+    state
+        .notes
+        .iter()
+        .step_by(step)
+        .map(|note| {
+            let start = state.ticks_to_seconds(note.start as f64);
+            PreviewNote {
+                start,
+                duration: state.ticks_to_seconds((note.start + note.duration) as f64) - start,
+                key: note.key,
+                velocity: note.velocity,
+            }
+        })
+        .collect()
+}
+
+/// 读取剪辑文件（标准 MIDI 格式），文本事件作为音符标签恢复
+pub fn load_clip_state(path: &Path) -> Result<MidiState, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    egui_midi::structure::load_single_track_with_options(&bytes, SmfImportOptions { text_events_as_labels: true })
+        .map_err(|e| format!("Invalid MIDI file {:?}: {}", path, e))
+}
+
+/// 将 MIDI 状态写入剪辑文件（标准 MIDI 格式，保留音符分组和标签），必要时创建父目录
+pub fn save_clip_state(path: &Path, state: &MidiState) -> Result<(), String> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+    }
+    std::fs::write(path, egui_midi::structure::export_single_track(state))
+        .map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+/// 后台预览加载器
+///
+/// 同时进行的文件读取数量受 `max_concurrent` 限制，其余请求在队列中等待。
+pub struct PreviewLoader {
+    max_concurrent: usize,
+    queue: VecDeque<(ClipId, PathBuf)>,
+    in_flight: HashSet<ClipId>,
+    cancelled: HashSet<ClipId>,
+    requested: HashSet<ClipId>,
+    sender: Sender<(ClipId, PreviewLoadResult)>,
+    receiver: Receiver<(ClipId, PreviewLoadResult)>,
+}
+
+impl PreviewLoader {
+    pub fn new(max_concurrent: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            max_concurrent: max_concurrent.max(1),
+            queue: VecDeque::new(),
+            in_flight: HashSet::new(),
+            cancelled: HashSet::new(),
+            requested: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// 剪辑是否已经请求过加载（无论结果如何，同一剪辑只自动加载一次）
+    pub fn is_requested(&self, clip_id: ClipId) -> bool {
+        self.requested.contains(&clip_id)
+    }
+
+    /// 将剪辑排入加载队列
+    pub fn enqueue(&mut self, clip_id: ClipId, path: PathBuf) {
+        self.requested.insert(clip_id);
+        self.queue.retain(|(id, _)| *id != clip_id);
+        self.queue.push_back((clip_id, path));
+    }
+
+    /// 取消剪辑的加载；已在读取中的结果到达后会被丢弃
+    pub fn cancel(&mut self, clip_id: ClipId) {
+        self.queue.retain(|(id, _)| *id != clip_id);
+        self.requested.remove(&clip_id);
+        if self.in_flight.contains(&clip_id) {
+            self.cancelled.insert(clip_id);
+        }
+    }
+
+    /// 是否还有排队或正在读取的剪辑
+    pub fn is_busy(&self) -> bool {
+        !self.queue.is_empty() || !self.in_flight.is_empty()
+    }
+
+    /// 取回已完成的结果并启动排队中的读取
+    pub fn poll(&mut self) -> Vec<(ClipId, PreviewLoadResult)> {
+        let mut finished = Vec::new();
+        while let Ok((clip_id, result)) = self.receiver.try_recv() {
+            self.in_flight.remove(&clip_id);
+            if !self.cancelled.remove(&clip_id) {
+                finished.push((clip_id, result));
+            }
+        }
+
+        while self.in_flight.len() < self.max_concurrent {
+            // 同一剪辑的旧读取尚未返回时，等它返回后再开始新的读取
+            let Some(index) = self.queue.iter().position(|(id, _)| !self.in_flight.contains(id)) else {
+                break;
+            };
+            let Some((clip_id, path)) = self.queue.remove(index) else {
+                break;
+            };
+            self.in_flight.insert(clip_id);
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                let _ = sender.send((clip_id, load_preview_notes(&path)));
+            });
+        }
+
+        finished
+    }
+}
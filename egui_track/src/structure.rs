@@ -50,8 +50,24 @@ where
     Ok(Color32::from(helper))
 }
 
+fn serialize_opt_color32<S>(color: &Option<Color32>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    color.map(Color32Helper::from).serialize(serializer)
+}
+
+fn deserialize_opt_color32<'de, D>(deserializer: D) -> Result<Option<Color32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let helper = Option::<Color32Helper>::deserialize(deserializer)?;
+    Ok(helper.map(Color32::from))
+}
+
 static TRACK_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 static CLIP_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+static MARKER_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct TrackId(pub u64);
@@ -71,6 +87,38 @@ impl ClipId {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct MarkerId(pub u64);
+
+impl MarkerId {
+    pub fn next() -> Self {
+        MarkerId(MARKER_ID_COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// 时间轴标记（段落标记）
+///
+/// 带颜色的标记会在编辑区背景中为其与下一个标记之间的区段着色。
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimelineMarker {
+    pub id: MarkerId,
+    pub position: f64,         // 标记位置（秒）
+    pub name: String,
+    #[serde(default, serialize_with = "serialize_opt_color32", deserialize_with = "deserialize_opt_color32")]
+    pub color: Option<Color32>,
+}
+
+impl TimelineMarker {
+    pub fn new(position: f64, name: String, color: Option<Color32>) -> Self {
+        Self {
+            id: MarkerId::next(),
+            position: position.max(0.0),
+            name,
+            color,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PreviewNote {
     pub start: f64,      // 相对于剪辑开始的时间（秒）
@@ -85,6 +133,16 @@ pub struct MidiClipData {
     pub preview_notes: Vec<PreviewNote>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub midi_state: Option<MidiState>,
+    /// 内容嵌入后保留的原文件路径，仅作来源记录，不再从该文件读取
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file_path: Option<String>,
+}
+
+impl MidiClipData {
+    /// 内容是否嵌入在剪辑中（而不是引用文件）
+    pub fn is_embedded(&self) -> bool {
+        self.midi_file_path.is_none() && self.midi_state.is_some()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -93,6 +151,8 @@ pub struct AudioClipData {
     pub waveform_data: Option<Vec<f32>>,  // 归一化的波形数据，用于预览
 }
 
+// MIDI 剪辑内嵌 MidiState；剪辑数量不多，不值得为此装箱而改动公开结构
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ClipType {
     Midi { midi_data: Option<MidiClipData> },
@@ -109,6 +169,8 @@ pub struct Clip {
     pub name: String,
     #[serde(serialize_with = "serialize_color32", deserialize_with = "deserialize_color32")]
     pub color: Color32,
+    #[serde(default)]
+    pub muted: bool,           // 剪辑静音（不影响所在轨道）
 }
 
 impl Clip {
@@ -123,10 +185,12 @@ impl Clip {
                     midi_file_path: None,
                     preview_notes: Vec::new(),
                     midi_state: None,
+                    source_file_path: None,
                 })
             },
             name,
             color: Color32::from_rgb(100, 200, 100),
+            muted: false,
         }
     }
 
@@ -139,6 +203,7 @@ impl Clip {
             clip_type: ClipType::Audio { audio_data: None },
             name,
             color: Color32::from_rgb(150, 150, 250),
+            muted: false,
         }
     }
 
@@ -161,6 +226,8 @@ pub struct Track {
     pub monitor: bool,         // 监听开关
     pub inserts: Vec<String>,  // 插入效果器列表（显示名称）
     pub sends: Vec<(String, f32)>, // 发送列表（总线名称 + 发送量 0.0-1.0）
+    #[serde(default, serialize_with = "serialize_opt_color32", deserialize_with = "deserialize_opt_color32")]
+    pub color: Option<Color32>,    // 轨道颜色（新建剪辑沿用该颜色）
     pub clips: Vec<Clip>,
 }
 
@@ -179,9 +246,22 @@ impl Track {
             monitor: false,
             inserts: Vec::new(),
             sends: Vec::new(),
+            color: None,
             clips: Vec::new(),
         }
     }
+
+    /// 轨道上所有剪辑的起点与终点（秒），升序且去重
+    pub fn clip_boundaries(&self) -> Vec<f64> {
+        let mut boundaries: Vec<f64> = self
+            .clips
+            .iter()
+            .flat_map(|clip| [clip.start_time, clip.end_time()])
+            .collect();
+        boundaries.sort_by(f64::total_cmp);
+        boundaries.dedup_by(|a, b| (*a - *b).abs() < 1e-9);
+        boundaries
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -198,6 +278,8 @@ pub struct TimelineState {
     pub time_signature: (u8, u8),
     pub bpm: f32,
     pub ticks_per_beat: u16,   // 每拍的 tick 数（与 MIDI 编辑器一致，默认 480）
+    #[serde(default)]
+    pub markers: Vec<TimelineMarker>, // 时间轴标记（按位置排序）
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -221,23 +303,28 @@ impl Default for TimelineState {
             time_signature: (4, 4),
             bpm: 120.0,
             ticks_per_beat: 480,  // 默认 480 ticks/beat
+            markers: Vec::new(),
         }
     }
 }
 
 impl TimelineState {
     /// 将时间（秒）转换为 tick
+    ///
+    /// 剪辑以秒存储，但编辑按 tick 进行：一次乘法一次除法后只取整一次，
+    /// 因此 `time_to_tick(tick_to_time(t)) == t` 对任何实际可用的 tick 都成立，反复换算不会漂移。
     pub fn time_to_tick(&self, time: f64) -> u64 {
-        let seconds_per_beat = 60.0 / self.bpm.max(1.0) as f64;
-        let seconds_per_tick = seconds_per_beat / self.ticks_per_beat.max(1) as f64;
-        (time / seconds_per_tick).round() as u64
+        (time * self.ticks_per_second()).round().max(0.0) as u64
     }
 
     /// 将 tick 转换为时间（秒）
     pub fn tick_to_time(&self, tick: u64) -> f64 {
-        let seconds_per_beat = 60.0 / self.bpm.max(1.0) as f64;
-        let seconds_per_tick = seconds_per_beat / self.ticks_per_beat.max(1) as f64;
-        tick as f64 * seconds_per_tick
+        tick as f64 / self.ticks_per_second()
+    }
+
+    /// 每秒的 tick 数
+    fn ticks_per_second(&self) -> f64 {
+        self.bpm.max(1.0) as f64 * self.ticks_per_beat.max(1) as f64 / 60.0
     }
 
     /// 将节拍转换为 tick
@@ -253,34 +340,99 @@ impl TimelineState {
     /// 将 tick 转换为 x 坐标（像素）
     /// 这是与 MIDI 编辑器一致的坐标转换函数
     pub fn tick_to_x(&self, tick: u64, header_width: f32) -> f32 {
-        let beat = self.tick_to_beat(tick);
-        let scroll_beat = self.scroll_x;
-        let rel_beat = beat - scroll_beat;
-        header_width + (rel_beat as f32 * self.zoom_x) + self.manual_scroll_x
+        let rel_beat = self.tick_to_beat(tick) - self.scroll_x;
+        (header_width as f64 + rel_beat * self.zoom_x as f64 + self.manual_scroll_x as f64) as f32
     }
 
     /// 将 x 坐标（像素）转换为 tick
     /// 这是与 MIDI 编辑器一致的坐标转换函数
     pub fn x_to_tick(&self, x: f32, header_width: f32) -> u64 {
-        let rel_x = x - header_width - self.manual_scroll_x;
-        let beat = (rel_x / self.zoom_x) as f64 + self.scroll_x;
+        let rel_x = x as f64 - header_width as f64 - self.manual_scroll_x as f64;
+        let beat = rel_x / self.zoom_x as f64 + self.scroll_x;
         self.beat_to_tick(beat.max(0.0))
     }
 
+    /// tick 在编辑器画面中的 x 坐标，`grid_left` 为网格区域左边缘（键盘列右侧）
+    ///
+    /// 与界面绘制一致，只计入 `manual_scroll_x`；全程以 f64 计算，只在最后转换为像素，
+    /// 很长的编排中网格线、剪辑与播放头也不会相互错位。
+    pub fn tick_to_screen_x(&self, tick: u64, grid_left: f32) -> f32 {
+        let offset = self.tick_to_beat(tick) * self.zoom_x as f64;
+        (grid_left as f64 + self.manual_scroll_x as f64 + offset) as f32
+    }
+
+    /// 时间（秒）在编辑器画面中的 x 坐标；不经过 tick 取整，播放头可以平滑移动
+    pub fn time_to_screen_x(&self, time: f64, grid_left: f32) -> f32 {
+        let offset = time * self.ticks_per_second() / self.ticks_per_beat.max(1) as f64 * self.zoom_x as f64;
+        (grid_left as f64 + self.manual_scroll_x as f64 + offset) as f32
+    }
+
+    /// `tick_to_screen_x` 的逆运算：不取整，网格左侧的位置为负值
+    pub fn screen_x_to_ticks(&self, x: f32, grid_left: f32) -> f64 {
+        let rel_x = x as f64 - grid_left as f64 - self.manual_scroll_x as f64;
+        rel_x / self.zoom_x.max(f32::EPSILON) as f64 * self.ticks_per_beat.max(1) as f64
+    }
+
     /// 对齐 tick 到网格
+    ///
+    /// 纯整数运算：已对齐的 tick 再次对齐保持不变。
     pub fn snap_tick(&self, tick: u64, disable_snap: bool) -> u64 {
         if !self.snap_enabled || disable_snap || self.snap_interval == 0 {
             return tick;
         }
-        (match self.snap_mode {
-            SnapMode::Absolute => {
-                (tick as f64 / self.snap_interval as f64).round() * self.snap_interval as f64
-            }
+        let interval = self.snap_interval;
+        match self.snap_mode {
+            SnapMode::Absolute => tick.saturating_add(interval / 2) / interval * interval,
             SnapMode::Relative => {
                 // 相对对齐模式：保持相对偏移（这里简化处理，与绝对对齐相同）
-                (tick as f64 / self.snap_interval as f64).round() * self.snap_interval as f64
+                tick.saturating_add(interval / 2) / interval * interval
             }
-        }) as u64
+        }
+    }
+
+    /// 每小节的 tick 数（由拍号决定）
+    pub fn ticks_per_measure(&self) -> u64 {
+        let tpb = self.ticks_per_beat.max(1) as u64;
+        let numer = self.time_signature.0.max(1) as u64;
+        let denom = self.time_signature.1.max(1) as u64;
+        (tpb * numer * 4).saturating_div(denom).max(tpb)
+    }
+
+    /// 返回与 [start_tick, end_tick] 相交的小节区间 `(序号, 起始 tick, 结束 tick)`
+    ///
+    /// 网格、标尺和背景着色共用此函数，保证小节宽度始终一致。
+    pub fn measures_in_range(&self, start_tick: u64, end_tick: u64) -> Vec<(u64, u64, u64)> {
+        let ticks_per_measure = self.ticks_per_measure();
+        let mut index = start_tick / ticks_per_measure;
+        let mut measures = Vec::new();
+        loop {
+            let measure_start = index * ticks_per_measure;
+            if measure_start > end_tick {
+                break;
+            }
+            measures.push((index, measure_start, measure_start + ticks_per_measure));
+            index += 1;
+        }
+        measures
+    }
+
+    /// 按位置插入标记，保持列表有序
+    pub fn insert_marker(&mut self, marker: TimelineMarker) {
+        let index = self.markers.partition_point(|m| m.position <= marker.position);
+        self.markers.insert(index, marker);
+    }
+
+    /// 返回带颜色的标记区段 `(起始秒, 结束秒, 颜色)`，最后一段的结束为 `None`（延伸到末尾）
+    pub fn marker_sections(&self) -> Vec<(f64, Option<f64>, Color32)> {
+        self.markers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, marker)| {
+                let color = marker.color?;
+                let end = self.markers.get(i + 1).map(|next| next.position);
+                Some((marker.position, end, color))
+            })
+            .collect()
     }
 
     /// 旧的时间到 x 坐标转换（保持向后兼容）
@@ -1,6 +1,10 @@
 //! Clip interaction module
 //!
-//! Defines hit regions for clip interaction (click, resize, etc.)
+//! Defines hit regions for clip interaction (click, resize, etc.) and the per-frame
+//! hit test of the track roll that both cursor icons and pointer handling rely on.
+
+use crate::structure::ClipId;
+use egui::{CursorIcon, Pos2, Rect};
 
 /// Hit region of a clip for interaction detection
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -12,3 +16,175 @@ pub enum ClipHitRegion {
     /// Right edge of the clip (for resizing from end)
     RightEdge,
 }
+
+/// What the pointer is over in the track roll
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollHit {
+    /// Track header column (including the corner above it)
+    Header,
+    /// Timeline ruler above the lanes
+    Timeline,
+    /// Resize zone at the start of a clip
+    ClipEdgeLeft(ClipId),
+    /// Resize zone at the end of a clip
+    ClipEdgeRight(ClipId),
+    /// Title bar of a clip (rename, locate playhead)
+    ClipTitle(ClipId),
+    /// Body of a clip (select, move)
+    ClipBody(ClipId),
+    /// Empty part of a track lane
+    EmptyLane(usize),
+    /// Below the last track
+    BelowTracks,
+}
+
+impl RollHit {
+    /// The clip under the pointer, whatever part of it was hit
+    pub fn clip(&self) -> Option<ClipId> {
+        match *self {
+            RollHit::ClipEdgeLeft(id) | RollHit::ClipEdgeRight(id) | RollHit::ClipTitle(id) | RollHit::ClipBody(id) => {
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Clip region used for selection and drag dispatch (`None` for the title bar and non-clip hits)
+    pub fn clip_region(&self) -> Option<(ClipId, ClipHitRegion)> {
+        match *self {
+            RollHit::ClipEdgeLeft(id) => Some((id, ClipHitRegion::LeftEdge)),
+            RollHit::ClipEdgeRight(id) => Some((id, ClipHitRegion::RightEdge)),
+            RollHit::ClipBody(id) => Some((id, ClipHitRegion::Body)),
+            _ => None,
+        }
+    }
+
+    /// Cursor shown while hovering this region (`None` keeps the default cursor)
+    pub fn cursor(&self) -> Option<CursorIcon> {
+        match self {
+            RollHit::Timeline => Some(CursorIcon::PointingHand),
+            RollHit::ClipEdgeLeft(_) | RollHit::ClipEdgeRight(_) => Some(CursorIcon::ResizeHorizontal),
+            RollHit::ClipBody(_) => Some(CursorIcon::Grab),
+            _ => None,
+        }
+    }
+}
+
+/// Geometry of the track roll for one frame
+#[derive(Clone, Copy, Debug)]
+pub struct RollLayout {
+    /// Whole roll area including header column and timeline
+    pub rect: Rect,
+    pub header_width: f32,
+    pub timeline_height: f32,
+    /// Screen y of the top of the first lane (scroll applied)
+    pub lanes_top: f32,
+    pub lane_height: f32,
+    pub track_count: usize,
+    /// Width of the resize zone at each clip edge
+    pub edge_threshold: f32,
+    /// Height of the clip title bar
+    pub title_bar_height: f32,
+    /// Clips must be taller than this for the title bar to exist
+    pub min_title_clip_height: f32,
+}
+
+impl RollLayout {
+    /// Classifies `pointer`; `clips` is in draw order, so later clips are on top.
+    ///
+    /// Only the topmost clip under the pointer is considered: its edge zones win over its
+    /// title bar, and a neighbouring clip never receives a hit through an edge zone. Edge
+    /// zones shrink on narrow clips so the body stays grabbable.
+    pub fn classify(&self, pointer: Pos2, clips: &[(ClipId, Rect, usize)]) -> Option<RollHit> {
+        if !self.rect.contains(pointer) {
+            return None;
+        }
+        if pointer.x < self.rect.min.x + self.header_width {
+            return Some(RollHit::Header);
+        }
+        if pointer.y < self.rect.min.y + self.timeline_height {
+            return Some(RollHit::Timeline);
+        }
+        if let Some((clip_id, clip_rect, _)) = clips.iter().rev().find(|(_, clip_rect, _)| clip_rect.contains(pointer)) {
+            let edge = self.edge_threshold.min(clip_rect.width() / 3.0);
+            if pointer.x - clip_rect.min.x < edge {
+                return Some(RollHit::ClipEdgeLeft(*clip_id));
+            }
+            if clip_rect.max.x - pointer.x < edge {
+                return Some(RollHit::ClipEdgeRight(*clip_id));
+            }
+            let has_title = clip_rect.height() > self.min_title_clip_height;
+            if has_title && pointer.y <= clip_rect.min.y + self.title_bar_height {
+                return Some(RollHit::ClipTitle(*clip_id));
+            }
+            return Some(RollHit::ClipBody(*clip_id));
+        }
+        let lane = ((pointer.y - self.lanes_top) / self.lane_height.max(f32::EPSILON)).floor();
+        if lane >= 0.0 && (lane as usize) < self.track_count {
+            Some(RollHit::EmptyLane(lane as usize))
+        } else {
+            Some(RollHit::BelowTracks)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{pos2, vec2};
+
+    fn layout() -> RollLayout {
+        RollLayout {
+            rect: Rect::from_min_size(Pos2::ZERO, vec2(800.0, 400.0)),
+            header_width: 100.0,
+            timeline_height: 30.0,
+            lanes_top: 30.0,
+            lane_height: 80.0,
+            track_count: 2,
+            edge_threshold: 5.0,
+            title_bar_height: 18.0,
+            min_title_clip_height: 22.0,
+        }
+    }
+
+    fn clip(id: u64, x: f32, width: f32, track: usize) -> (ClipId, Rect, usize) {
+        let rect = Rect::from_min_size(pos2(x, 30.0 + track as f32 * 80.0), vec2(width, 80.0));
+        (ClipId(id), rect, track)
+    }
+
+    #[test]
+    fn areas_outside_the_lanes_are_classified_first() {
+        let layout = layout();
+        let clips = [clip(1, 80.0, 200.0, 0)];
+        assert_eq!(layout.classify(pos2(50.0, 10.0), &clips), Some(RollHit::Header));
+        // 剪辑延伸到轨道头下方时，轨道头仍然优先
+        assert_eq!(layout.classify(pos2(90.0, 60.0), &clips), Some(RollHit::Header));
+        assert_eq!(layout.classify(pos2(300.0, 10.0), &clips), Some(RollHit::Timeline));
+        assert_eq!(layout.classify(pos2(500.0, 150.0), &clips), Some(RollHit::EmptyLane(1)));
+        assert_eq!(layout.classify(pos2(500.0, 300.0), &clips), Some(RollHit::BelowTracks));
+        assert_eq!(layout.classify(pos2(900.0, 60.0), &clips), None);
+    }
+
+    #[test]
+    fn clip_edges_win_over_the_title_bar() {
+        let layout = layout();
+        let clips = [clip(1, 200.0, 200.0, 0)];
+        assert_eq!(layout.classify(pos2(202.0, 35.0), &clips), Some(RollHit::ClipEdgeLeft(ClipId(1))));
+        assert_eq!(layout.classify(pos2(202.0, 90.0), &clips), Some(RollHit::ClipEdgeLeft(ClipId(1))));
+        assert_eq!(layout.classify(pos2(398.0, 35.0), &clips), Some(RollHit::ClipEdgeRight(ClipId(1))));
+        assert_eq!(layout.classify(pos2(300.0, 35.0), &clips), Some(RollHit::ClipTitle(ClipId(1))));
+        assert_eq!(layout.classify(pos2(300.0, 90.0), &clips), Some(RollHit::ClipBody(ClipId(1))));
+    }
+
+    #[test]
+    fn overlapping_clips_resolve_to_the_topmost_one() {
+        let layout = layout();
+        // 第二个剪辑绘制在上层，其左边缘落在第一个剪辑的右边缘区内
+        let clips = [clip(1, 200.0, 200.0, 0), clip(2, 397.0, 100.0, 0)];
+        assert_eq!(layout.classify(pos2(398.0, 90.0), &clips), Some(RollHit::ClipEdgeLeft(ClipId(2))));
+        assert_eq!(layout.classify(pos2(396.0, 90.0), &clips), Some(RollHit::ClipEdgeRight(ClipId(1))));
+        // 很窄的剪辑中间仍可抓取主体
+        let narrow = [clip(3, 200.0, 9.0, 0)];
+        assert_eq!(layout.classify(pos2(204.5, 90.0), &narrow), Some(RollHit::ClipBody(ClipId(3))));
+    }
+}
@@ -5,13 +5,22 @@
 mod clip;
 mod toolbar;
 
-use crate::editor::{TrackEditorCommand, TrackEditorEvent};
-use crate::structure::{Track, Clip, TrackId, ClipId, TimelineState, ClipType};
+use crate::editor::{ConfirmStrategy, ContextArea, DestructiveAction, TrackEditorCommand, TrackEditorEvent};
+use crate::export::{self, ExportError};
+use crate::preview_loader::{load_clip_state, preview_notes_from_state, save_clip_state, PreviewLoader};
+use crate::project::ProjectFile;
+use egui_midi::palette_picker::{palette_button, palette_picker};
+use egui_midi::sync::SyncSource;
+use crate::structure::{Track, Clip, TrackId, ClipId, TimelineState, ClipType, MidiClipData, TimelineMarker};
 use egui::*;
 use std::collections::BTreeSet;
 use std::rc::Rc;
 use std::cell::RefCell;
 
+type EventListener = Box<dyn FnMut(&TrackEditorEvent)>;
+type NameValidator = Box<dyn Fn(&str) -> Result<(), String>>;
+type DestructiveConfirm = Box<dyn Fn(DestructiveAction) -> ConfirmStrategy>;
+
 // UI 常量
 const CLIP_TITLE_BAR_HEIGHT: f32 = 18.0;
 const CLIP_TITLE_BAR_MIN_HEIGHT: f32 = 4.0;
@@ -33,13 +42,23 @@ const TIMELINE_MEASURE_LINE_OFFSET: f32 = 5.0;
 /// # 示例
 ///
 /// ```rust
-/// use egui_track::TrackEditorOptions;
+/// use egui_track::{ClipDoubleClickAction, TrackEditorOptions};
 ///
 /// let options = TrackEditorOptions {
 ///     default_track_height: 100.0,
 ///     min_clip_width: 30.0,
 ///     track_header_width: 250.0,
 ///     timeline_height: 40.0,
+///     shade_alternate_measures: true,
+///     tint_marker_sections: true,
+///     clip_double_click: ClipDoubleClickAction::EmitEvent,
+///     max_concurrent_preview_reads: 4,
+///     keep_source_path_on_embed: false,
+///     debug_repaint_counter: false,
+///     confirm_delete_threshold: 10,
+///     hover_tooltip_delay: None,
+///     builtin_context_menus: true,
+///     max_pending_events: 4096,
 /// };
 /// ```
 #[derive(Clone)]
@@ -52,6 +71,41 @@ pub struct TrackEditorOptions {
     pub track_header_width: f32,
     /// 时间轴的高度（像素）
     pub timeline_height: f32,
+    /// 是否以明暗交替的底色区分相邻小节
+    pub shade_alternate_measures: bool,
+    /// 是否用带颜色标记的颜色淡淡地铺满其所在区段
+    pub tint_marker_sections: bool,
+    /// 双击剪辑主体时的行为（双击标题栏始终是重命名）
+    pub clip_double_click: ClipDoubleClickAction,
+    /// 后台加载剪辑预览时同时读取的文件数上限
+    pub max_concurrent_preview_reads: usize,
+    /// 嵌入剪辑内容（`EmbedClipContent`）时是否把原文件路径保留在 `source_file_path` 中
+    pub keep_source_path_on_embed: bool,
+    /// 统计编辑器发出的重绘请求次数（调试用），通过 `TrackEditor::repaint_request_count` 读取；
+    /// 空闲时（未播放、无后台加载、无拖拽）计数应保持不变
+    pub debug_repaint_counter: bool,
+    /// 未设置确认钩子时，用户删除操作影响的剪辑数超过此值才弹出确认对话框（0 表示从不询问）
+    pub confirm_delete_threshold: usize,
+    /// 悬停在剪辑上多久（秒）后显示名称、位置、来源等提示；`None` 关闭（默认）
+    pub hover_tooltip_delay: Option<f32>,
+    /// 是否显示内置的剪辑、轨道与网格右键菜单；关闭后这些右键点击都改为发出 `ContextRequested`
+    pub builtin_context_menus: bool,
+    /// 未被 `take_events` 取走的事件最多保留多少条（0 不限制）；超出时丢弃最旧的并记录警告，监听器不受影响
+    pub max_pending_events: usize,
+}
+
+/// 双击剪辑主体时执行的动作
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClipDoubleClickAction {
+    /// 仅发出 `ClipDoubleClicked` 事件，由宿主决定如何处理
+    #[default]
+    EmitEvent,
+    /// 内联编辑剪辑名称
+    RenameInline,
+    /// 切换剪辑静音，发出 `ClipMuteChanged`
+    ToggleMute,
+    /// 单独选中该剪辑后发出 `ClipDoubleClicked`（宿主据此打开编辑器）
+    OpenAndEmit,
 }
 
 impl Default for TrackEditorOptions {
@@ -61,6 +115,16 @@ impl Default for TrackEditorOptions {
             min_clip_width: 20.0,
             track_header_width: 240.0,  // 200.0 * 1.2
             timeline_height: 30.0,      // 60.0 / 2
+            shade_alternate_measures: true,
+            tint_marker_sections: true,
+            clip_double_click: ClipDoubleClickAction::default(),
+            max_concurrent_preview_reads: 4,
+            keep_source_path_on_embed: false,
+            debug_repaint_counter: false,
+            confirm_delete_threshold: 10,
+            hover_tooltip_delay: None,
+            builtin_context_menus: true,
+            max_pending_events: 4096,
         }
     }
 }
@@ -77,14 +141,26 @@ pub struct TrackEditor {
     drag_start_time: Option<f64>,
     drag_clip_id: Option<ClipId>,
     drag_pointer_offset: Option<Vec2>,  // 拖拽时指针相对于剪辑的偏移量
+    last_clip_click: Option<(ClipId, f64)>,  // 上一次单击的剪辑及时间（用于识别双击的第二次按下）
     editing_clip_name: Option<ClipId>,  // 正在编辑名称的剪辑
     editing_clip_name_value: Option<String>,  // 正在编辑的名称值（用于持久化编辑状态）
+    clip_name_error: Option<String>,  // 剪辑名称校验失败的提示
+    editing_track_name: Option<(TrackId, String)>,  // 正在内联编辑名称的轨道及编辑值
+    track_name_error: Option<String>,  // 轨道名称校验失败的提示
+    track_name_focus_pending: bool,  // 下一帧让轨道名称输入框获取焦点
+    name_validator: Option<NameValidator>,
+    destructive_confirm: Option<DestructiveConfirm>,
+    pending_confirmation: Option<(DestructiveAction, TrackEditorCommand)>,  // 等待确认对话框答复的删除命令
     track_context_menu_pos: Option<Pos2>,  // 轨道右键菜单位置
     track_context_menu_open_pos: Option<Pos2>,  // 轨道右键菜单打开时的位置
     track_context_menu_track_id: Option<TrackId>,  // 显示右键菜单的轨道ID
     clip_context_menu_pos: Option<Pos2>,  // 剪辑右键菜单位置
     clip_context_menu_open_pos: Option<Pos2>,  // 剪辑右键菜单打开时的位置
     clip_context_menu_clip_id: Option<ClipId>,  // 显示右键菜单的剪辑ID
+    grid_context_menu_pos: Option<Pos2>,  // 空白网格右键菜单位置
+    grid_context_menu_open_pos: Option<Pos2>,  // 空白网格右键菜单打开时的位置
+    grid_context_menu_time: Option<f64>,  // 空白网格右键处对齐后的时间（秒）
+    clip_tooltip_hover: Option<(ClipId, f64)>,  // 悬停的剪辑及开始悬停的时间
     clipboard: Vec<Clip>,  // 剪贴板：存储复制的剪辑
     selection_box_start: Option<Pos2>,
     selection_box_end: Option<Pos2>,
@@ -97,10 +173,21 @@ pub struct TrackEditor {
     // Playback state
     is_playing: bool,
     last_update: f64,
+    sync: Option<Box<dyn SyncSource>>,
     
     // Events
     pending_events: Vec<TrackEditorEvent>,
-    event_listener: Option<Box<dyn FnMut(&TrackEditorEvent)>>,
+    events_overflowed: bool,  // 自上次 take_events 以来是否已丢弃过事件（只警告一次）
+    event_listener: Option<EventListener>,
+
+    // 预览音符后台加载
+    preview_loader: PreviewLoader,
+
+    // 上一帧轨道编辑区域的屏幕矩形
+    roll_rect: Option<Rect>,
+
+    // 调试：累计的重绘请求次数（仅在 debug_repaint_counter 启用时统计）
+    repaint_requests: u64,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -112,6 +199,14 @@ enum DragAction {
     PlayheadSeek,
 }
 
+/// 轨道名称内联编辑在本帧产生的动作
+enum TrackNameAction {
+    Start(TrackId, String),
+    Edit(String),
+    Commit(TrackId, String),
+    Cancel,
+}
+
 impl TrackEditor {
     /// 将轨道索引转换为 y 坐标（参考 MIDI 编辑器的 note_to_y）
     fn track_to_y(&self, track_index: usize, timeline_height: f32) -> f32 {
@@ -126,13 +221,14 @@ impl TrackEditor {
         let mut y = top;
         while y < bottom {
             let next = (y + dash_len).min(bottom);
-            painter.line_segment([Pos2::new(x, y), Pos2::new(x, next)], stroke.clone());
+            painter.line_segment([Pos2::new(x, y), Pos2::new(x, next)], stroke);
             y += dash_len + gap_len;
         }
     }
 
 
     pub fn new(options: TrackEditorOptions) -> Self {
+        let preview_loader = PreviewLoader::new(options.max_concurrent_preview_reads);
         Self {
             tracks: Vec::new(),
             timeline: TimelineState::default(),
@@ -143,14 +239,26 @@ impl TrackEditor {
             drag_start_time: None,
             drag_clip_id: None,
             drag_pointer_offset: None,
+            last_clip_click: None,
             editing_clip_name: None,
             editing_clip_name_value: None,
+            clip_name_error: None,
+            editing_track_name: None,
+            track_name_error: None,
+            track_name_focus_pending: false,
+            name_validator: None,
+            destructive_confirm: None,
+            pending_confirmation: None,
             track_context_menu_pos: None,
             track_context_menu_open_pos: None,
             track_context_menu_track_id: None,
             clip_context_menu_pos: None,
             clip_context_menu_open_pos: None,
             clip_context_menu_clip_id: None,
+            grid_context_menu_pos: None,
+            grid_context_menu_open_pos: None,
+            grid_context_menu_time: None,
+            clip_tooltip_hover: None,
             clipboard: Vec::new(),
             selection_box_start: None,
             selection_box_end: None,
@@ -158,9 +266,14 @@ impl TrackEditor {
             pan_start_pos: None,
             metronome_enabled: false,
             is_playing: false,
+            sync: None,
             last_update: 0.0,
             pending_events: Vec::new(),
+            events_overflowed: false,
             event_listener: None,
+            preview_loader,
+            roll_rect: None,
+            repaint_requests: 0,
         }
     }
 
@@ -191,6 +304,77 @@ impl TrackEditor {
         self.event_listener = Some(listener);
     }
 
+    /// 设置外部同步源（`None` 恢复用帧间隔推进播放头）
+    ///
+    /// 同步期间播放状态与播放头每帧从同步源读取（按拍位置换算到时间轴 BPM），
+    /// 位置变化时发出 `PlayheadChanged`。
+    pub fn set_sync(&mut self, sync: Option<Box<dyn SyncSource>>) {
+        self.sync = sync;
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.sync.is_some()
+    }
+
+    /// 设置名称校验器
+    ///
+    /// 内联重命名轨道或剪辑时，提交前会调用此校验器；返回 `Err` 时在输入框旁显示错误并保持编辑状态。
+    /// 空名称总是被拒绝。
+    ///
+    /// # 示例
+    ///
+    /// ```rust
+    /// use egui_track::{TrackEditor, TrackEditorOptions};
+    ///
+    /// let mut editor = TrackEditor::new(TrackEditorOptions::default());
+    /// editor.set_name_validator(|name| {
+    ///     if name.contains('/') {
+    ///         Err("Name cannot contain '/'".to_string())
+    ///     } else {
+    ///         Ok(())
+    ///     }
+    /// });
+    /// ```
+    pub fn set_name_validator(&mut self, validator: impl Fn(&str) -> Result<(), String> + 'static) {
+        self.name_validator = Some(Box::new(validator));
+    }
+
+    /// 设置破坏性操作的确认钩子
+    ///
+    /// 用户在界面中删除轨道、删除剪辑或清空工程时调用此钩子：返回 `Proceed` 直接执行，
+    /// `Ask` 弹出内置确认对话框，`Cancel` 放弃且不发出事件。未设置时按
+    /// `TrackEditorOptions::confirm_delete_threshold` 决定是否询问。
+    /// 通过 `execute_command` 发送的命令不经过确认。
+    ///
+    /// # 示例
+    ///
+    /// ```rust
+    /// use egui_track::{ConfirmStrategy, TrackEditor, TrackEditorOptions};
+    ///
+    /// let mut editor = TrackEditor::new(TrackEditorOptions::default());
+    /// // 宿主有自己的撤销系统，删除无需确认
+    /// editor.set_destructive_action_confirm(|_| ConfirmStrategy::Proceed);
+    /// ```
+    pub fn set_destructive_action_confirm(&mut self, confirm: impl Fn(DestructiveAction) -> ConfirmStrategy + 'static) {
+        self.destructive_confirm = Some(Box::new(confirm));
+    }
+
+    /// 正在等待确认对话框答复的破坏性操作
+    pub fn pending_confirmation(&self) -> Option<DestructiveAction> {
+        self.pending_confirmation.as_ref().map(|(action, _)| *action)
+    }
+
+    /// 按名称校验器检查名称
+    fn validate_name(&self, name: &str) -> Result<(), String> {
+        if name.trim().is_empty() {
+            return Err("Name cannot be empty".to_string());
+        }
+        match &self.name_validator {
+            Some(validator) => validator(name),
+            None => Ok(()),
+        }
+    }
+
     /// 获取并清空待处理的事件列表
     ///
     /// # 返回
@@ -210,6 +394,7 @@ impl TrackEditor {
     /// }
     /// ```
     pub fn take_events(&mut self) -> Vec<TrackEditorEvent> {
+        self.events_overflowed = false;
         std::mem::take(&mut self.pending_events)
     }
 
@@ -261,15 +446,33 @@ impl TrackEditor {
             TrackEditorCommand::RenameClip { clip_id, new_name } => {
                 self.rename_clip(clip_id, new_name);
             }
+            TrackEditorCommand::SetClipMute { clip_id, muted } => {
+                self.set_clip_mute(clip_id, muted);
+            }
+            TrackEditorCommand::SetClipColor { clip_ids, color } => {
+                for clip_id in clip_ids {
+                    self.set_clip_color(clip_id, color);
+                }
+            }
             TrackEditorCommand::CreateTrack { name } => {
                 self.create_track(name);
             }
             TrackEditorCommand::DeleteTrack { track_id } => {
                 self.delete_track(track_id);
             }
+            TrackEditorCommand::ClearAll => {
+                let track_ids: Vec<TrackId> = self.tracks.iter().map(|t| t.id).collect();
+                for track_id in track_ids {
+                    self.delete_track(track_id);
+                }
+                self.selected_clips.clear();
+            }
             TrackEditorCommand::RenameTrack { track_id, new_name } => {
                 self.rename_track(track_id, new_name);
             }
+            TrackEditorCommand::SetTrackColor { track_id, color } => {
+                self.set_track_color(track_id, color);
+            }
             TrackEditorCommand::SetPlayhead { position } => {
                 self.timeline.playhead_position = position;
                 self.emit_event(TrackEditorEvent::PlayheadChanged { position });
@@ -362,16 +565,178 @@ impl TrackEditor {
             TrackEditorCommand::UpdateClipMidiFilePath { clip_id, new_file_path } => {
                 self.update_clip_midi_file_path(clip_id, new_file_path);
             }
+            TrackEditorCommand::ReloadClipPreview { clip_id } => {
+                if let Some(path) = self.clip_midi_file_path(clip_id) {
+                    self.preview_loader.cancel(clip_id);
+                    self.preview_loader.enqueue(clip_id, path);
+                }
+            }
+            TrackEditorCommand::EmbedClipContent { clip_id } => {
+                let result = self.embed_clip_content(clip_id);
+                self.emit_clip_content_result(clip_id, result);
+            }
+            TrackEditorCommand::ExtractClipContent { clip_id, path } => {
+                let result = self.extract_clip_content(clip_id, &path);
+                self.emit_clip_content_result(clip_id, result);
+            }
             TrackEditorCommand::DeleteClips { clip_ids } => {
                 self.delete_clips(clip_ids);
             }
+            TrackEditorCommand::AddMarker { position, name, color } => {
+                let marker = TimelineMarker::new(position, name, color);
+                let marker_id = marker.id;
+                self.timeline.insert_marker(marker);
+                self.emit_event(TrackEditorEvent::MarkerAdded { marker_id });
+            }
+            TrackEditorCommand::RemoveMarker { marker_id } => {
+                let before = self.timeline.markers.len();
+                self.timeline.markers.retain(|m| m.id != marker_id);
+                if self.timeline.markers.len() != before {
+                    self.emit_event(TrackEditorEvent::MarkerRemoved { marker_id });
+                }
+            }
+        }
+    }
+
+    /// 执行用户发起的命令：破坏性命令先经过确认钩子（见 `set_destructive_action_confirm`）
+    ///
+    /// 需要询问时命令被暂存，直到确认对话框得到答复；已有待确认的命令时新的破坏性命令被忽略。
+    pub fn request_command(&mut self, command: TrackEditorCommand) {
+        let Some(action) = self.destructive_action(&command) else {
+            self.execute_command(command);
+            return;
+        };
+        let strategy = match &self.destructive_confirm {
+            Some(confirm) => confirm(action),
+            None => {
+                let threshold = self.options.confirm_delete_threshold;
+                if threshold > 0 && action.clip_count() > threshold {
+                    ConfirmStrategy::Ask
+                } else {
+                    ConfirmStrategy::Proceed
+                }
+            }
+        };
+        match strategy {
+            ConfirmStrategy::Proceed => self.execute_command(command),
+            ConfirmStrategy::Ask => {
+                if self.pending_confirmation.is_none() {
+                    self.pending_confirmation = Some((action, command));
+                }
+            }
+            ConfirmStrategy::Cancel => {}
+        }
+    }
+
+    /// 命令对应的破坏性操作；不删除任何内容的命令返回 `None`
+    fn destructive_action(&self, command: &TrackEditorCommand) -> Option<DestructiveAction> {
+        match command {
+            TrackEditorCommand::DeleteTrack { track_id } => {
+                let track = self.tracks.iter().find(|t| t.id == *track_id)?;
+                Some(DestructiveAction::DeleteTrack { track_id: *track_id, clip_count: track.clips.len() })
+            }
+            TrackEditorCommand::DeleteClip { clip_id } => self
+                .tracks
+                .iter()
+                .any(|t| t.clips.iter().any(|clip| clip.id == *clip_id))
+                .then_some(DestructiveAction::DeleteClips { count: 1 }),
+            TrackEditorCommand::DeleteClips { clip_ids } => {
+                let count = self
+                    .tracks
+                    .iter()
+                    .flat_map(|t| &t.clips)
+                    .filter(|clip| clip_ids.contains(&clip.id))
+                    .count();
+                (count > 0).then_some(DestructiveAction::DeleteClips { count })
+            }
+            TrackEditorCommand::ClearAll => {
+                if self.tracks.is_empty() {
+                    return None;
+                }
+                Some(DestructiveAction::ClearAll {
+                    track_count: self.tracks.len(),
+                    clip_count: self.tracks.iter().map(|t| t.clips.len()).sum(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// 显示破坏性操作的确认对话框；确认后执行暂存的命令，取消时丢弃且不发出事件
+    fn show_delete_confirmation(&mut self, ctx: &Context) {
+        let Some((action, _)) = &self.pending_confirmation else {
+            return;
+        };
+        let message = match *action {
+            DestructiveAction::DeleteTrack { track_id, clip_count } => {
+                let name = self
+                    .tracks
+                    .iter()
+                    .find(|t| t.id == track_id)
+                    .map(|t| t.name.clone())
+                    .unwrap_or_default();
+                format!("Delete track \"{name}\" and its {clip_count} clips?")
+            }
+            DestructiveAction::DeleteClips { count } => format!("Delete {count} clips?"),
+            DestructiveAction::ClearAll { track_count, clip_count } => {
+                format!("Delete all {track_count} tracks and {clip_count} clips?")
+            }
+        };
+
+        let mut confirmed = None;
+        egui::Window::new("Confirm delete")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .order(Order::Foreground)
+            .show(ctx, |ui| {
+                ui.label(message);
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Delete").clicked() {
+                        confirmed = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        confirmed = Some(false);
+                    }
+                });
+            });
+        if ctx.input(|i| i.key_pressed(Key::Escape)) {
+            confirmed = Some(false);
+        }
+
+        if let Some(confirmed) = confirmed {
+            if let Some((_, command)) = self.pending_confirmation.take() {
+                if confirmed {
+                    self.execute_command(command);
+                }
+            }
         }
     }
 
     pub fn ui(&mut self, ui: &mut Ui) {
+        self.update_preview_loading(ui.ctx());
+
         // 播放时的自动时间更新（参考 MIDI 编辑器的实现）
-        if self.is_playing {
-            ui.ctx().request_repaint();
+        if let Some(sync) = &self.sync {
+            let playing = sync.is_playing();
+            let position = sync.current_beats() * 60.0 / self.timeline.bpm.max(1.0) as f64;
+            self.last_update = ui.input(|i| i.time);
+            if playing != self.is_playing {
+                self.is_playing = playing;
+                self.emit_event(TrackEditorEvent::PlaybackStateChanged { is_playing: playing });
+            }
+            if position != self.timeline.playhead_position {
+                self.timeline.playhead_position = position;
+                self.emit_event(TrackEditorEvent::PlayheadChanged { position });
+            }
+            if playing {
+                self.request_repaint(ui.ctx(), None);
+            } else {
+                self.request_repaint(ui.ctx(), Some(std::time::Duration::from_millis(50)));
+            }
+        } else if self.is_playing {
+            self.request_repaint(ui.ctx(), None);
             let now = ui.input(|i| i.time);
             let dt = now - self.last_update;
             self.last_update = now;
@@ -404,6 +769,8 @@ impl TrackEditor {
             // 主编辑区域（基于 MIDI 编辑器的 ui_piano_roll）
             self.ui_track_roll(ui);
         });
+
+        self.show_delete_confirmation(ui.ctx());
     }
 
     /// 主编辑区域（基于 MIDI 编辑器的 ui_piano_roll 函数）
@@ -419,6 +786,7 @@ impl TrackEditor {
                 let available_size = ui.available_size();
                 let (rect, response) =
                     ui.allocate_exact_size(available_size, Sense::click_and_drag());
+                self.roll_rect = Some(rect);
 
                 // 处理缩放（Ctrl/Alt + 滚轮）
                 self.handle_zoom(ui, &rect, key_width, timeline_height);
@@ -426,74 +794,15 @@ impl TrackEditor {
                 // 处理中键拖拽平移（参考 MIDI 编辑器的实现）
                 self.handle_panning(ui);
 
+                // 处理键盘导航（, / . 跳到上一个/下一个剪辑边界）
+                self.handle_keyboard(ui);
+
                 // 限制垂直滚动
                 self.clamp_vertical_scroll(&rect, timeline_height);
 
                 let mut pointer_consumed = false;
-                let note_offset_x = rect.min.x + key_width + self.timeline.manual_scroll_x;
-
-                // 坐标转换函数
-                let tick_to_x = |tick: u64, zoom_x: f32, ticks_per_beat: u16| -> f32 {
-                    (tick as f32 / ticks_per_beat as f32) * zoom_x
-                };
-
-                // 处理时间轴交互（播放头定位）
-                if let Some(pointer) = response.interact_pointer_pos() {
-                    let in_timeline = pointer.y < rect.min.y + timeline_height
-                        && pointer.x >= rect.min.x + key_width;
-                    
-                    if in_timeline {
-                        let modifiers = ui.input(|i| i.modifiers);
-                        let disable_snap = modifiers.alt;
-                        
-                        // 将指针位置转换为 tick
-                        let mut x = pointer.x - (rect.min.x + key_width);
-                        x = (x - self.timeline.manual_scroll_x).max(0.0);
-                        let beats = x / self.timeline.zoom_x;
-                        let seconds_per_beat = 60.0 / self.timeline.bpm;
-                        let seconds_per_tick = seconds_per_beat / self.timeline.ticks_per_beat as f32;
-                        let tick = (beats * seconds_per_beat / seconds_per_tick) as i64;
-                        let snapped_tick = self.timeline.snap_tick(tick as u64, disable_snap) as i64;
-                        
-                        // 处理播放头定位
-                        if ui.input(|i| i.pointer.primary_pressed()) && !matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd) {
-                            self.drag_action = DragAction::PlayheadSeek;
-                            self.timeline.playhead_position = snapped_tick as f64 * seconds_per_tick as f64;
-                            self.emit_event(TrackEditorEvent::PlayheadChanged {
-                                position: self.timeline.playhead_position,
-                            });
-                            pointer_consumed = true;
-                        }
-                        
-                        // 处理拖拽更新
-                        if ui.input(|i| i.pointer.primary_down()) {
-                            if self.drag_action == DragAction::PlayheadSeek {
-                                self.timeline.playhead_position = snapped_tick as f64 * seconds_per_tick as f64;
-                                self.emit_event(TrackEditorEvent::PlayheadChanged {
-                                    position: self.timeline.playhead_position,
-                                });
-                                pointer_consumed = true;
-                            }
-                        }
-                        
-                        // 处理拖拽结束
-                        if ui.input(|i| i.pointer.primary_released()) {
-                            if self.drag_action == DragAction::PlayheadSeek {
-                                self.drag_action = DragAction::None;
-                            }
-                        }
-                        
-                        // 更新光标
-                        if self.drag_action != DragAction::PlayheadSeek {
-                            ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
-                        }
-                    }
-                }
-
-                // 坐标转换函数
-                let time_to_x = |time: f32, zoom_x: f32| -> f32 {
-                    time * zoom_x
-                };
+                // 网格区域左边缘；所有 tick 与屏幕坐标的换算都经由 TimelineState 以 f64 完成
+                let grid_left = rect.min.x + key_width;
 
                 let painter = ui.painter_at(rect);
                 let grid_top = rect.min.y + timeline_height;
@@ -506,17 +815,12 @@ impl TrackEditor {
 
                 // 绘制垂直网格（小节线、拍线、细分线）
                 let tpb = self.timeline.ticks_per_beat.max(1) as u64;
-                let denom = self.timeline.time_signature.1.max(1) as u64;
-                let numer = self.timeline.time_signature.0.max(1) as u64;
-                let ticks_per_measure = (tpb * numer * 4).saturating_div(denom).max(tpb);
-
-                let visible_beats_start = (-self.timeline.manual_scroll_x / self.timeline.zoom_x).floor();
-                let visible_beats_end = visible_beats_start + (rect.width() / self.timeline.zoom_x) + 2.0;
-                let mut start_tick = (visible_beats_start * tpb as f32).floor() as i64;
-                if start_tick < 0 {
-                    start_tick = 0;
-                }
-                let end_tick = (visible_beats_end * tpb as f32).ceil() as i64;
+                let ticks_per_measure = self.timeline.ticks_per_measure();
+
+                let visible_beats_start = (self.timeline.screen_x_to_ticks(grid_left, grid_left) / tpb as f64).floor();
+                let visible_beats_end = (self.timeline.screen_x_to_ticks(rect.max.x, grid_left) / tpb as f64).ceil() + 2.0;
+                let start_tick = (visible_beats_start * tpb as f64).max(0.0) as i64;
+                let end_tick = (visible_beats_end * tpb as f64) as i64;
 
                 let subdivision = if self.timeline.zoom_x >= 220.0 {
                     8
@@ -529,20 +833,61 @@ impl TrackEditor {
                 };
                 let tick_step = (tpb / subdivision).max(1);
 
+                // 绘制背景着色（小节明暗交替、标记区段）
+                let tick_x = |tick: u64| self.timeline.tick_to_screen_x(tick, grid_left);
+                if self.options.shade_alternate_measures {
+                    let shade = if ui.visuals().dark_mode {
+                        Color32::from_white_alpha(6)
+                    } else {
+                        Color32::from_black_alpha(8)
+                    };
+                    for (index, measure_start, measure_end) in
+                        self.timeline.measures_in_range(start_tick as u64, end_tick.max(0) as u64)
+                    {
+                        if index % 2 == 1 {
+                            let left = tick_x(measure_start).max(grid_left);
+                            let right = tick_x(measure_end).min(rect.max.x);
+                            if right > left {
+                                painter.rect_filled(
+                                    Rect::from_min_max(Pos2::new(left, grid_top), Pos2::new(right, grid_bottom)),
+                                    0.0,
+                                    shade,
+                                );
+                            }
+                        }
+                    }
+                }
+                if self.options.tint_marker_sections {
+                    for (section_start, section_end, color) in self.timeline.marker_sections() {
+                        let left = tick_x(self.timeline.time_to_tick(section_start)).max(grid_left);
+                        let right = section_end
+                            .map(|end| tick_x(self.timeline.time_to_tick(end)))
+                            .unwrap_or(rect.max.x)
+                            .min(rect.max.x);
+                        if right > left {
+                            painter.rect_filled(
+                                Rect::from_min_max(Pos2::new(left, grid_top), Pos2::new(right, grid_bottom)),
+                                0.0,
+                                color.gamma_multiply(0.08),
+                            );
+                        }
+                    }
+                }
+
                 let mut tick = (start_tick / tick_step as i64) * tick_step as i64;
                 if tick < 0 {
                     tick = 0;
                 }
 
                 while tick <= end_tick {
-                    let x = note_offset_x + (tick as f32 / tpb as f32) * self.timeline.zoom_x;
+                    let x = tick_x(tick as u64);
                     if x >= rect.min.x && x <= rect.max.x {
-                        if tick as u64 % ticks_per_measure == 0 {
+                        if (tick as u64).is_multiple_of(ticks_per_measure) {
                             painter.line_segment(
                                 [Pos2::new(x, grid_top), Pos2::new(x, grid_bottom)],
                                 Stroke::new(1.0, measure_line_color),
                             );
-                        } else if tick as u64 % tpb == 0 {
+                        } else if (tick as u64).is_multiple_of(tpb) {
                             painter.line_segment(
                                 [Pos2::new(x, grid_top), Pos2::new(x, grid_bottom)],
                                 Stroke::new(1.0, beat_line_color),
@@ -593,14 +938,8 @@ impl TrackEditor {
                 let clip_offset_y = rect.min.y + timeline_height + self.timeline.manual_scroll_y;
                 
                 // 计算可见时间范围（用于视口剔除）
-                let visible_start_tick = if self.timeline.manual_scroll_x < 0.0 {
-                    ((-self.timeline.manual_scroll_x / self.timeline.zoom_x) * self.timeline.ticks_per_beat as f32) as u64
-                } else {
-                    0
-                };
-                let visible_end_tick = visible_start_tick.saturating_add(
-                    ((rect.width() / self.timeline.zoom_x) * self.timeline.ticks_per_beat as f32) as u64 + 1
-                );
+                let visible_start_tick = self.timeline.screen_x_to_ticks(grid_left, grid_left).max(0.0) as u64;
+                let visible_end_tick = self.timeline.screen_x_to_ticks(rect.max.x, grid_left).max(0.0).ceil() as u64 + 1;
                 
                 // 收集可见剪辑的矩形
                 let mut visible_clips: Vec<(ClipId, Rect, usize)> = Vec::new();
@@ -611,10 +950,9 @@ impl TrackEditor {
                         
                         // 视口剔除：只处理可见时间范围内的剪辑
                         if clip_end_tick >= visible_start_tick && clip_start_tick <= visible_end_tick {
-                            let x = note_offset_x
-                                + tick_to_x(clip_start_tick, self.timeline.zoom_x, self.timeline.ticks_per_beat);
+                            let x = self.timeline.tick_to_screen_x(clip_start_tick, grid_left);
                             let y = clip_offset_y + (track_index as f32 * self.timeline.zoom_y);
-                            let w = tick_to_x(clip_end_tick - clip_start_tick, self.timeline.zoom_x, self.timeline.ticks_per_beat).max(self.options.min_clip_width);
+                            let w = (self.timeline.tick_to_screen_x(clip_end_tick, grid_left) - x).max(self.options.min_clip_width);
                             let h = self.timeline.zoom_y;
                             let clip_rect = Rect::from_min_size(Pos2::new(x, y), Vec2::new(w, h));
                             
@@ -625,6 +963,32 @@ impl TrackEditor {
                     }
                 }
                 
+                // 本帧的命中测试布局：光标、悬停提示与指针交互都按同一分类处理
+                let roll_layout = clip::RollLayout {
+                    rect,
+                    header_width: key_width,
+                    timeline_height,
+                    lanes_top: clip_offset_y,
+                    lane_height: self.timeline.zoom_y,
+                    track_count: self.tracks.len(),
+                    edge_threshold: CLIP_EDGE_THRESHOLD,
+                    title_bar_height: CLIP_TITLE_BAR_HEIGHT,
+                    min_title_clip_height: CLIP_TITLE_BAR_HEIGHT + CLIP_TITLE_BAR_MIN_HEIGHT,
+                };
+
+                // 悬停提示（拖拽、按下或菜单打开时不显示）
+                let hovered_clip = ui
+                    .input(|i| i.pointer.hover_pos())
+                    .filter(|pointer| {
+                        rect.contains(*pointer)
+                            && self.drag_action == DragAction::None
+                            && self.clip_context_menu_pos.is_none()
+                            && !ui.input(|i| i.pointer.any_down())
+                    })
+                    .and_then(|pointer| roll_layout.classify(pointer, &visible_clips))
+                    .and_then(|hit| hit.clip());
+                self.show_clip_tooltip(ui, hovered_clip);
+
                 // 绘制剪辑
                 for (clip_id, clip_rect, track_index) in &visible_clips {
                     // 查找剪辑数据
@@ -645,6 +1009,12 @@ impl TrackEditor {
                     } else {
                         Color32::from_rgb(100, 200, 100)
                     };
+                    // 静音剪辑整体变暗
+                    let color = if clip.muted {
+                        color.gamma_multiply(0.4)
+                    } else {
+                        color
+                    };
                     
                     // 计算标题栏高度
                     let title_bar_height = if clip_rect.height() > CLIP_TITLE_BAR_HEIGHT + CLIP_TITLE_BAR_MIN_HEIGHT {
@@ -690,7 +1060,7 @@ impl TrackEditor {
                                 painter.text(
                                     text_pos,
                                     Align2::LEFT_CENTER,
-                                    &clip.name,
+                                    if clip.muted { format!("{} (muted)", clip.name) } else { clip.name.clone() },
                                     FontId::proportional(11.0),
                                     Color32::WHITE,
                         );
@@ -721,11 +1091,11 @@ impl TrackEditor {
                             );
                             
                             // 计算时间到像素的转换
-                            let clip_duration = clip.duration as f32;
+                            let clip_duration = clip.duration;
                             let content_width = content_rect.width();
                             let time_to_x = |time: f64| -> f32 {
                                 if clip_duration > 0.0 {
-                                    (time as f32 / clip_duration) * content_width
+                                    (time / clip_duration) as f32 * content_width
                                 } else {
                                     0.0
                                 }
@@ -782,16 +1152,13 @@ impl TrackEditor {
                 // 处理剪辑交互
                 let base_x = rect.min.x + key_width;
                 let base_y = rect.min.y + timeline_height;
-                let manual_scroll_x = self.timeline.manual_scroll_x;
                 let manual_scroll_y = self.timeline.manual_scroll_y;
-                let zoom_x = self.timeline.zoom_x;
                 let zoom_y = self.timeline.zoom_y;
-                let ticks_per_beat = self.timeline.ticks_per_beat as f32;
+                let scroll_origin_x = base_x as f64 + self.timeline.manual_scroll_x as f64;
+                let ticks_per_px = self.timeline.ticks_per_beat.max(1) as f64 / self.timeline.zoom_x.max(f32::EPSILON) as f64;
 
                 let pointer_to_tick = move |pos: Pos2| -> i64 {
-                    let rel_x = pos.x - base_x - manual_scroll_x;
-                    let beats = rel_x / zoom_x;
-                    (beats * ticks_per_beat).round() as i64
+                    ((pos.x as f64 - scroll_origin_x) * ticks_per_px).round() as i64
                 };
                 let pointer_to_track = move |pos: Pos2| -> Option<usize> {
                     let keyboard_top = base_y + manual_scroll_y;
@@ -800,139 +1167,139 @@ impl TrackEditor {
                     Some(track_index)
                 };
 
-                // 处理剪辑点击和拖拽
-                for (clip_id, clip_rect, track_index) in &visible_clips {
-                    // 查找剪辑数据
-                    let clip_data = if let Some(track) = self.tracks.get(*track_index) {
-                        track.clips.iter().find(|c| c.id == *clip_id)
-                    } else {
-                        None
-                    };
-                    
-                    if clip_data.is_none() {
-                        continue;
+                // 按命中区域分派指针交互；重叠的剪辑只有最上层的那个会收到事件
+                let hit = response
+                    .interact_pointer_pos()
+                    .and_then(|pointer| roll_layout.classify(pointer, &visible_clips));
+                let modifiers = ui.input(|i| i.modifiers);
+                let clip_dragging = matches!(
+                    self.drag_action,
+                    DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd
+                );
+
+                // 播放头定位：在时间轴上按下开始，拖拽期间跟随指针，松开结束
+                if let Some(pointer) = response.interact_pointer_pos() {
+                    let seek_tick = self.timeline.snap_tick(
+                        self.timeline.screen_x_to_ticks(pointer.x, grid_left).max(0.0) as u64,
+                        modifiers.alt,
+                    );
+                    let start_seek = hit == Some(clip::RollHit::Timeline)
+                        && !clip_dragging
+                        && ui.input(|i| i.pointer.primary_pressed());
+                    let continue_seek =
+                        self.drag_action == DragAction::PlayheadSeek && ui.input(|i| i.pointer.primary_down());
+                    if start_seek || continue_seek {
+                        self.drag_action = DragAction::PlayheadSeek;
+                        self.timeline.playhead_position = self.timeline.tick_to_time(seek_tick);
+                        self.emit_event(TrackEditorEvent::PlayheadChanged {
+                            position: self.timeline.playhead_position,
+                        });
+                        pointer_consumed = true;
                     }
-                    let clip = clip_data.unwrap();
-                    
-                    // 计算标题栏区域
-                    let title_bar_rect = if clip_rect.height() > CLIP_TITLE_BAR_HEIGHT + CLIP_TITLE_BAR_MIN_HEIGHT {
-                        Some(Rect::from_min_max(
-                            Pos2::new(clip_rect.min.x, clip_rect.min.y),
-                            Pos2::new(clip_rect.max.x, clip_rect.min.y + CLIP_TITLE_BAR_HEIGHT),
-                        ))
-                    } else {
-                        None
-                    };
-                    
-                        if response.clicked_by(PointerButton::Primary) {
-                        if let Some(pointer) = response.interact_pointer_pos() {
-                            // 检查是否点击了标题栏
-                            if let Some(title_rect) = title_bar_rect {
-                                if title_rect.contains(pointer) {
-                                    // 双击标题栏开始编辑名称
-                                    if response.double_clicked() {
-                                                self.editing_clip_name = Some(*clip_id);
-                                                self.editing_clip_name_value = Some(clip.name.clone());
-                                    }
-                                    pointer_consumed = true;
-                                } else if clip_rect.contains(pointer) {
-                            let modifiers = ui.input(|i| i.modifiers);
-                                    self.handle_clip_click(*clip_id, modifiers, clip::ClipHitRegion::Body);
-                                    pointer_consumed = true;
-                                }
-                            } else if clip_rect.contains(pointer) {
-                                let modifiers = ui.input(|i| i.modifiers);
-                                self.handle_clip_click(*clip_id, modifiers, clip::ClipHitRegion::Body);
-                                pointer_consumed = true;
+                }
+                if self.drag_action == DragAction::PlayheadSeek && ui.input(|i| i.pointer.primary_released()) {
+                    self.drag_action = DragAction::None;
+                }
+
+                // 剪辑的点击、双击、右键与拖拽开始
+                let hit_clip = hit.and_then(|hit| hit.clip()).and_then(|clip_id| {
+                    let (_, clip_rect, track_index) = visible_clips.iter().find(|(id, ..)| *id == clip_id)?;
+                    let clip = self.tracks.get(*track_index)?.clips.iter().find(|c| c.id == clip_id)?;
+                    Some((clip_id, *clip_rect, clip.name.clone(), clip.muted, clip.start_time))
+                });
+                if let (Some(hit), Some((clip_id, clip_rect, clip_name, clip_muted, clip_start))) = (hit, hit_clip) {
+                    let in_title_bar = matches!(hit, clip::RollHit::ClipTitle(_));
+
+                    if response.clicked_by(PointerButton::Primary) {
+                        if in_title_bar {
+                            // Alt + 单击标题栏将播放头定位到剪辑起点；双击标题栏开始编辑名称
+                            if modifiers.alt {
+                                self.execute_command(TrackEditorCommand::SetPlayhead { position: clip_start });
+                            } else if response.double_clicked() {
+                                self.editing_clip_name = Some(clip_id);
+                                self.editing_clip_name_value = Some(clip_name.clone());
                             }
+                        } else {
+                            self.handle_clip_click(clip_id, modifiers, clip::ClipHitRegion::Body);
                         }
+                        self.last_clip_click = Some((clip_id, ui.input(|i| i.time)));
+                        pointer_consumed = true;
                     }
-                    
-                    // 处理双击剪辑（打开编辑器）
-                    if response.double_clicked() {
-                        if let Some(pointer) = response.interact_pointer_pos() {
-                            if clip_rect.contains(pointer) {
-                                // 检查是否双击在标题栏（如果是，不触发打开编辑器）
-                                let in_title_bar = if let Some(title_rect) = title_bar_rect {
-                                    title_rect.contains(pointer)
-                                } else {
-                                    false
-                                };
-                                
-                                if !in_title_bar {
-                                    // 触发双击剪辑事件
-                                    self.emit_event(TrackEditorEvent::ClipDoubleClicked {
-                                        clip_id: *clip_id,
-                                    });
-                                    pointer_consumed = true;
-                                }
+
+                    // 双击剪辑主体按 clip_double_click 选项分派（标题栏的双击用于重命名）
+                    if response.double_clicked() && !in_title_bar {
+                        match self.options.clip_double_click {
+                            ClipDoubleClickAction::EmitEvent => {
+                                self.emit_event(TrackEditorEvent::ClipDoubleClicked { clip_id });
+                            }
+                            ClipDoubleClickAction::RenameInline => {
+                                self.editing_clip_name = Some(clip_id);
+                                self.editing_clip_name_value = Some(clip_name);
+                            }
+                            ClipDoubleClickAction::ToggleMute => {
+                                self.execute_command(TrackEditorCommand::SetClipMute {
+                                    clip_id,
+                                    muted: !clip_muted,
+                                });
+                            }
+                            ClipDoubleClickAction::OpenAndEmit => {
+                                self.selected_clips.clear();
+                                self.selected_clips.insert(clip_id);
+                                self.emit_event(TrackEditorEvent::ClipDoubleClicked { clip_id });
                             }
                         }
+                        pointer_consumed = true;
                     }
-                    
-                    // 处理右键点击剪辑（显示上下文菜单）
+
+                    // 右键剪辑：显示上下文菜单或交给宿主
                     if response.clicked_by(PointerButton::Secondary) {
                         if let Some(pointer) = response.interact_pointer_pos() {
-                            if clip_rect.contains(pointer) {
+                            if self.options.builtin_context_menus {
                                 self.clip_context_menu_pos = Some(pointer);
                                 self.clip_context_menu_open_pos = Some(pointer);
-                                self.clip_context_menu_clip_id = Some(*clip_id);
-                                pointer_consumed = true;
+                                self.clip_context_menu_clip_id = Some(clip_id);
+                            } else {
+                                let time = self.timeline.tick_to_time(pointer_to_tick(pointer).max(0) as u64);
+                                self.emit_event(TrackEditorEvent::ContextRequested {
+                                    area: ContextArea::Clip(clip_id),
+                                    time,
+                                    screen_pos: pointer,
+                                });
                             }
+                            pointer_consumed = true;
                         }
                     }
 
-                    if !matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd) 
-                        && ui.input(|i| i.pointer.primary_pressed()) {
-                        if let Some(pointer) = response.interact_pointer_pos() {
-                            // 排除标题栏区域
-                            let in_title_bar = if clip_rect.height() > CLIP_TITLE_BAR_HEIGHT + CLIP_TITLE_BAR_MIN_HEIGHT {
-                                pointer.y >= clip_rect.min.y && pointer.y <= clip_rect.min.y + CLIP_TITLE_BAR_HEIGHT
-                            } else {
-                                false
-                            };
-                            
-                            if clip_rect.contains(pointer) && !in_title_bar {
-                                // 检查是否在边缘（用于调整大小）
-                                let hit_region = if (pointer.x - clip_rect.min.x) < CLIP_EDGE_THRESHOLD {
-                                    clip::ClipHitRegion::LeftEdge
-                                } else if (clip_rect.max.x - pointer.x) < CLIP_EDGE_THRESHOLD {
-                                    clip::ClipHitRegion::RightEdge
-                                } else {
-                                    clip::ClipHitRegion::Body
-                                };
-                                
-                            let drag_action = match hit_region {
+                    if !clip_dragging && ui.input(|i| i.pointer.primary_pressed()) {
+                        // 双击的第二次按下不开始拖拽
+                        let double_click_delay = ui.ctx().options(|o| o.input_options.max_double_click_delay);
+                        let now = ui.input(|i| i.time);
+                        let is_second_press = self
+                            .last_clip_click
+                            .is_some_and(|(last_id, last_time)| last_id == clip_id && now - last_time < double_click_delay);
+
+                        if is_second_press {
+                            pointer_consumed = true;
+                        } else if let (Some((_, region)), Some(pointer)) = (hit.clip_region(), response.interact_pointer_pos()) {
+                            self.drag_action = match region {
                                 clip::ClipHitRegion::LeftEdge => DragAction::ResizeClipStart,
                                 clip::ClipHitRegion::RightEdge => DragAction::ResizeClipEnd,
                                 clip::ClipHitRegion::Body => DragAction::MoveClip,
                             };
-                                
-                                self.drag_action = drag_action;
-                                self.drag_clip_id = Some(*clip_id);
-                                self.drag_start_pos = Some(pointer);
-                                
-                                // 计算指针相对于剪辑的偏移量（用于平滑拖拽）
-                                self.drag_pointer_offset = Some(pointer - clip_rect.min);
-                                
-                                // 找到剪辑的开始时间
-                                if let Some(track) = self.tracks.get(*track_index) {
-                                    if let Some(clip) = track.clips.iter().find(|c| c.id == *clip_id) {
-                                        self.drag_start_time = Some(clip.start_time);
-                                    }
-                                }
-                                
-                                pointer_consumed = true;
-                            }
+                            self.drag_clip_id = Some(clip_id);
+                            self.drag_start_pos = Some(pointer);
+                            // 指针相对于剪辑的偏移量（用于平滑拖拽）
+                            self.drag_pointer_offset = Some(pointer - clip_rect.min);
+                            self.drag_start_time = Some(clip_start);
+                            pointer_consumed = true;
                         }
                     }
-
-                    // 移除鼠标图标设置，使用默认鼠标图标
                 }
 
                 // 处理剪辑拖拽更新
-                if matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd) 
-                    && ui.input(|i| i.pointer.primary_down()) {
+                if matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd)
+                    && ui.input(|i| i.pointer.primary_down())
+                {
                     // 使用 hover_pos 作为 interact_pointer_pos 的备用
                     let pointer = response.interact_pointer_pos()
                         .or_else(|| response.hover_pos());
@@ -1061,29 +1428,81 @@ impl TrackEditor {
                 }
 
                 // 检测拖拽结束：同时检查 drag_stopped 和 primary_released
-                let drag_ended = response.drag_stopped() 
+                let drag_ended = response.drag_stopped()
                     || ui.input(|i| i.pointer.primary_released());
-                
-                if drag_ended {
-                    if matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd) {
-                self.drag_action = DragAction::None;
-                self.drag_clip_id = None;
-                self.drag_start_pos = None;
-                self.drag_start_time = None;
-                        self.drag_pointer_offset = None;
+
+                if drag_ended
+                    && matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd)
+                {
+                    self.drag_action = DragAction::None;
+                    self.drag_clip_id = None;
+                    self.drag_start_pos = None;
+                    self.drag_start_time = None;
+                    self.drag_pointer_offset = None;
+                }
+
+                // 光标：拖拽中由拖拽动作决定，否则由悬停的命中区域决定
+                let drag_cursor = match self.drag_action {
+                    DragAction::MoveClip => Some(CursorIcon::Grabbing),
+                    DragAction::ResizeClipStart | DragAction::ResizeClipEnd => Some(CursorIcon::ResizeHorizontal),
+                    DragAction::PlayheadSeek => Some(CursorIcon::PointingHand),
+                    _ => None,
+                };
+                let cursor = if self.drag_action == DragAction::None {
+                    response
+                        .hover_pos()
+                        .and_then(|pointer| roll_layout.classify(pointer, &visible_clips))
+                        .and_then(|hit| hit.cursor())
+                } else {
+                    drag_cursor
+                };
+                if let (Some(cursor), false) = (cursor, self.is_panning) {
+                    ui.ctx().set_cursor_icon(cursor);
+                }
+
+                // 右键空白网格：打开网格菜单，记录对齐后的时间；右键时间轴交给宿主
+                if !pointer_consumed && response.clicked_by(PointerButton::Secondary) {
+                    if let (Some(pointer), Some(hit)) = (response.interact_pointer_pos(), hit) {
+                        let tick = pointer_to_tick(pointer).max(0) as u64;
+                        match hit {
+                            clip::RollHit::EmptyLane(_) | clip::RollHit::BelowTracks
+                                if self.options.builtin_context_menus =>
+                            {
+                                let tick = self.timeline.snap_tick(tick, modifiers.alt);
+                                self.grid_context_menu_pos = Some(pointer);
+                                self.grid_context_menu_open_pos = Some(pointer);
+                                self.grid_context_menu_time = Some(self.timeline.tick_to_time(tick));
+                            }
+                            clip::RollHit::EmptyLane(track_index) => {
+                                if let Some(track_id) = self.tracks.get(track_index).map(|track| track.id) {
+                                    self.emit_event(TrackEditorEvent::ContextRequested {
+                                        area: ContextArea::TrackLane(track_id),
+                                        time: self.timeline.tick_to_time(tick),
+                                        screen_pos: pointer,
+                                    });
+                                }
+                            }
+                            clip::RollHit::Timeline => {
+                                self.emit_event(TrackEditorEvent::ContextRequested {
+                                    area: ContextArea::Timeline,
+                                    time: self.timeline.tick_to_time(tick),
+                                    screen_pos: pointer,
+                                });
+                            }
+                            _ => {}
+                        }
                     }
                 }
 
-                // 处理选择框
-                if !pointer_consumed && ui.input(|i| i.pointer.primary_pressed()) {
+                // 处理选择框（在标题栏按下也会开始，松开时选中该剪辑）
+                if !pointer_consumed && !clip_dragging && ui.input(|i| i.pointer.primary_pressed()) {
                     if let Some(pointer) = response.interact_pointer_pos() {
-                        let in_roll = pointer.x > rect.min.x + key_width
-                            && pointer.y > rect.min.y + timeline_height;
-                        if in_roll {
-                            if !matches!(self.drag_action, DragAction::MoveClip | DragAction::ResizeClipStart | DragAction::ResizeClipEnd) {
-                                self.selection_box_start = Some(pointer);
-                                self.selection_box_end = Some(pointer);
-                            }
+                        if matches!(
+                            hit,
+                            Some(clip::RollHit::EmptyLane(_) | clip::RollHit::BelowTracks | clip::RollHit::ClipTitle(_))
+                        ) {
+                            self.selection_box_start = Some(pointer);
+                            self.selection_box_end = Some(pointer);
                         }
                     }
                 }
@@ -1133,9 +1552,10 @@ impl TrackEditor {
                 );
 
                 // 绘制时间轴标签（小节标记）
-                let mut measure_tick = (start_tick as u64 / ticks_per_measure) * ticks_per_measure;
-                while measure_tick as i64 <= end_tick {
-                    let x = note_offset_x + (measure_tick as f32 / tpb as f32) * self.timeline.zoom_x;
+                for (measure_index, measure_tick, _) in
+                    self.timeline.measures_in_range(start_tick as u64, end_tick.max(0) as u64)
+                {
+                    let x = self.timeline.tick_to_screen_x(measure_tick, grid_left);
                     if x >= rect.min.x + key_width - TIMELINE_MEASURE_LINE_OFFSET && x <= rect.max.x {
                         painter.line_segment(
                             [
@@ -1144,24 +1564,38 @@ impl TrackEditor {
                             ],
                             Stroke::new(1.0, measure_line_color),
                         );
-                        let measure_index = (measure_tick / ticks_per_measure) + 1;
                         painter.text(
                             Pos2::new(x + TIMELINE_MEASURE_LABEL_OFFSET_X, rect.min.y + TIMELINE_MEASURE_LABEL_OFFSET_Y),
                             Align2::LEFT_CENTER,
-                            format!("{}:1", measure_index),
+                            format!("{}:1", measure_index + 1),
                             FontId::proportional(11.0),
                             Color32::GRAY,
                         );
                     }
-                    measure_tick += ticks_per_measure;
                 }
 
-                // 绘制播放头
-                let playhead_x = note_offset_x
-                    + time_to_x(
-                        (self.timeline.playhead_position * self.timeline.bpm as f64 / 60.0) as f32,
-                        self.timeline.zoom_x,
+                // 绘制时间轴标记
+                for marker in &self.timeline.markers {
+                    let x = self.timeline.tick_to_screen_x(self.timeline.time_to_tick(marker.position), grid_left);
+                    if x < rect.min.x + key_width || x > rect.max.x {
+                        continue;
+                    }
+                    let marker_color = marker.color.unwrap_or(Color32::from_rgb(230, 180, 80));
+                    painter.line_segment(
+                        [Pos2::new(x, rect.min.y + timeline_height * 0.5), Pos2::new(x, rect.min.y + timeline_height)],
+                        Stroke::new(2.0, marker_color),
+                    );
+                    painter.text(
+                        Pos2::new(x + TIMELINE_MEASURE_LABEL_OFFSET_X, rect.min.y + timeline_height - 4.0),
+                        Align2::LEFT_BOTTOM,
+                        &marker.name,
+                        FontId::proportional(10.0),
+                        marker_color,
                     );
+                }
+
+                // 绘制播放头
+                let playhead_x = self.timeline.time_to_screen_x(self.timeline.playhead_position, grid_left);
                 if playhead_x > rect.min.x + key_width {
                     painter.line_segment(
                         [
@@ -1178,6 +1612,7 @@ impl TrackEditor {
                     if response.clicked_elsewhere() {
                         self.editing_clip_name = None;
                         self.editing_clip_name_value = None;
+                        self.clip_name_error = None;
                     } else {
                         // 找到正在编辑的剪辑
                         for (clip_id, clip_rect, track_index) in &visible_clips {
@@ -1229,8 +1664,10 @@ impl TrackEditor {
                                             // 更新编辑值
                                             *name_ref.borrow_mut() = current_edit_value.clone();
                                             
-                                            // 如果失去焦点或按 Enter，完成编辑
-                                            if name_response.lost_focus() || ui.input(|i| i.key_pressed(Key::Enter)) {
+                                            // 如果按 Escape，取消编辑；失去焦点或按 Enter，完成编辑
+                                            if ui.input(|i| i.key_pressed(Key::Escape)) {
+                                                *cancel_flag.borrow_mut() = true;
+                                            } else if name_response.lost_focus() || ui.input(|i| i.key_pressed(Key::Enter)) {
                                                 if let Some(ref orig) = original_name {
                                                     if current_edit_value != *orig && !current_edit_value.is_empty() {
                                                         commands.borrow_mut().push(TrackEditorCommand::RenameClip {
@@ -1241,27 +1678,43 @@ impl TrackEditor {
                                                 }
                                                 *finish_flag.borrow_mut() = true;
                                             }
-                                            
-                                            // 如果按 Escape，取消编辑
-                                            if ui.input(|i| i.key_pressed(Key::Escape)) {
-                                                *cancel_flag.borrow_mut() = true;
-                                            }
                                         });
                                         
-                                        // 在闭包外执行收集的命令
+                                        // 在闭包外执行收集的命令（重命名需先通过名称校验）
                                         for command in pending_commands.borrow_mut().drain(..) {
+                                            if let TrackEditorCommand::RenameClip { new_name, .. } = &command {
+                                                if let Err(error) = self.validate_name(new_name) {
+                                                    self.clip_name_error = Some(error);
+                                                    *should_finish_editing.borrow_mut() = false;
+                                                    continue;
+                                                }
+                                            }
+                                            self.clip_name_error = None;
                                             self.execute_command(command);
                                         }
                                         
+                                        // 校验失败时在标题栏下方显示错误
+                                        if let Some(error) = &self.clip_name_error {
+                                            painter.text(
+                                                Pos2::new(title_bar_rect.min.x + 4.0, title_bar_rect.max.y + 2.0),
+                                                Align2::LEFT_TOP,
+                                                error,
+                                                FontId::proportional(11.0),
+                                                Color32::from_rgb(230, 110, 110),
+                                            );
+                                        }
+                                        
                                         // 在闭包外更新编辑状态
                                         if *should_finish_editing.borrow() {
                                             self.editing_clip_name = None;
                                             self.editing_clip_name_value = None;
+                                            self.clip_name_error = None;
                                         }
                                         
                                         if *should_cancel_editing.borrow() {
                                             self.editing_clip_name = None;
                                             self.editing_clip_name_value = None;
+                                            self.clip_name_error = None;
                                         } else {
                                             // 更新编辑值
                                             self.editing_clip_name_value = Some(new_name_value.borrow().clone());
@@ -1283,6 +1736,9 @@ impl TrackEditor {
 
                 // 收集需要执行的命令（避免借用冲突）
                 let pending_commands: Rc<RefCell<Vec<TrackEditorCommand>>> = Rc::new(RefCell::new(Vec::new()));
+                let track_name_action: Rc<RefCell<Option<TrackNameAction>>> = Rc::new(RefCell::new(None));
+                let focus_track_name = std::mem::take(&mut self.track_name_focus_pending);
+                let mut header_context: Option<(TrackId, Pos2)> = None;
 
                 // 为每个轨道面板创建交互式 UI
                 for (track_index, track) in self.tracks.iter().enumerate() {
@@ -1302,18 +1758,30 @@ impl TrackEditor {
                         let track_volume = track.volume;
                         let track_pan = track.pan;
                         let track_name = track.name.clone();
+                        let track_color = track.color;
                         let track_inserts = track.inserts.clone();
                         let track_sends = track.sends.clone();
                         let commands = pending_commands.clone();
+                        let name_action = track_name_action.clone();
+                        let editing_name = self
+                            .editing_track_name
+                            .as_ref()
+                            .filter(|(id, _)| *id == track_id)
+                            .map(|(_, value)| value.clone());
+                        let name_error = editing_name.as_ref().and(self.track_name_error.clone());
                         let zoom_y = self.timeline.zoom_y;
                         
                         // 检测右键点击，显示上下文菜单
                         let track_response = ui.allocate_rect(track_panel_rect, egui::Sense::click());
                         if track_response.secondary_clicked() {
                             if let Some(pointer) = ui.input(|i| i.pointer.interact_pos()) {
-                                self.track_context_menu_pos = Some(pointer);
-                                self.track_context_menu_open_pos = Some(pointer);
-                                self.track_context_menu_track_id = Some(track_id);
+                                if self.options.builtin_context_menus {
+                                    self.track_context_menu_pos = Some(pointer);
+                                    self.track_context_menu_open_pos = Some(pointer);
+                                    self.track_context_menu_track_id = Some(track_id);
+                                } else {
+                                    header_context = Some((track_id, pointer));
+                                }
                             }
                         }
                         
@@ -1341,17 +1809,43 @@ impl TrackEditor {
                                 ui.vertical(|ui| {
                                     ui.set_width(key_width);
                                     
-                                    // 轨道名称（顶部，可编辑）
+                                    // 轨道颜色与名称（顶部，双击名称内联编辑）
                                     ui.horizontal(|ui| {
-                                        let mut name_value = track_name.clone();
-                                        let name_response = ui.text_edit_singleline(&mut name_value);
-                                        if name_response.changed() && name_value != track_name {
-                                            commands.borrow_mut().push(TrackEditorCommand::RenameTrack {
+                                        let mut color = track_color.unwrap_or(Color32::from_gray(120));
+                                        if palette_button(ui, "track_color", &mut color)
+                                            .on_hover_text("Track color")
+                                            .changed()
+                                        {
+                                            commands.borrow_mut().push(TrackEditorCommand::SetTrackColor {
                                                 track_id,
-                                                new_name: name_value,
+                                                color,
                                             });
                                         }
+                                        if let Some(mut name_value) = editing_name {
+                                            let name_response = ui.text_edit_singleline(&mut name_value);
+                                            if focus_track_name {
+                                                name_response.request_focus();
+                                            }
+                                            let action = if ui.input(|i| i.key_pressed(Key::Escape)) {
+                                                TrackNameAction::Cancel
+                                            } else if name_response.lost_focus() {
+                                                TrackNameAction::Commit(track_id, name_value)
+                                            } else {
+                                                TrackNameAction::Edit(name_value)
+                                            };
+                                            *name_action.borrow_mut() = Some(action);
+                                        } else {
+                                            let name_label = ui
+                                                .add(Label::new(RichText::new(&track_name).strong()).truncate().sense(Sense::click()))
+                                                .on_hover_text("Double-click to rename");
+                                            if name_label.double_clicked() {
+                                                *name_action.borrow_mut() = Some(TrackNameAction::Start(track_id, track_name.clone()));
+                                            }
+                                        }
                                     });
+                                    if let Some(error) = &name_error {
+                                        ui.colored_label(Color32::from_rgb(230, 110, 110), error);
+                                    }
                                     
                                     // 顶部按钮行
                                     ui.horizontal(|ui| {
@@ -1444,7 +1938,7 @@ impl TrackEditor {
                                     let mut volume_value = track_volume;
                                     // 计算 dB 值（0.0-1.0 映射到 -∞ 到 0 dB，简化处理）
                                     let db_value = if volume_value > 0.0 {
-                                        (volume_value.ln() * 8.685889638065035).max(-60.0) // 20 * log10(volume)
+                                        (volume_value.ln() * 8.685_889).max(-60.0) // 20 * log10(volume)
                                     } else {
                                         -60.0
                                     };
@@ -1513,6 +2007,13 @@ impl TrackEditor {
                         }
                     }
                 }
+                if let Some((track_id, pointer)) = header_context {
+                    self.emit_event(TrackEditorEvent::ContextRequested {
+                        area: ContextArea::Header(track_id),
+                        time: self.timeline.playhead_position,
+                        screen_pos: pointer,
+                    });
+                }
                 
                 // 显示轨道右键菜单（参考 MIDI 编辑器的实现）
                 if let Some(menu_pos) = self.track_context_menu_pos {
@@ -1532,6 +2033,13 @@ impl TrackEditor {
                                         self.track_context_menu_open_pos = None;
                                         self.track_context_menu_track_id = None;
                                     }
+
+                                    if ui.button("Delete All Tracks").clicked() {
+                                        pending_commands.borrow_mut().push(TrackEditorCommand::ClearAll);
+                                        self.track_context_menu_pos = None;
+                                        self.track_context_menu_open_pos = None;
+                                        self.track_context_menu_track_id = None;
+                                    }
                                 });
                             });
                         
@@ -1641,6 +2149,59 @@ impl TrackEditor {
                                         self.clip_context_menu_clip_id = None;
                                     }
                                     
+                                    ui.separator();
+
+                                    // 颜色（右键的剪辑在选区内时作用于整个选区）
+                                    ui.label("Color");
+                                    let color_targets: Vec<ClipId> = if self.selected_clips.contains(&menu_clip_id) {
+                                        self.selected_clips.iter().copied().collect()
+                                    } else {
+                                        vec![menu_clip_id]
+                                    };
+                                    let mut color = self.tracks.iter()
+                                        .flat_map(|t| t.clips.iter())
+                                        .find(|c| c.id == menu_clip_id)
+                                        .map(|c| c.color)
+                                        .unwrap_or(Color32::GRAY);
+                                    if palette_picker(ui, "clip_color", &mut color) {
+                                        pending_commands.borrow_mut().push(TrackEditorCommand::SetClipColor {
+                                            clip_ids: color_targets,
+                                            color,
+                                        });
+                                    }
+
+                                    ui.separator();
+
+                                    // 文件引用与嵌入内容之间的转换（保存路径由宿主通过 ClipContentSaveRequested 选择）
+                                    let (file_backed, embedded) = self.tracks.iter()
+                                        .flat_map(|t| t.clips.iter())
+                                        .find(|c| c.id == menu_clip_id)
+                                        .and_then(|c| match &c.clip_type {
+                                            ClipType::Midi { midi_data: Some(midi_data) } => {
+                                                Some((midi_data.midi_file_path.is_some(), midi_data.is_embedded()))
+                                            }
+                                            _ => None,
+                                        })
+                                        .unwrap_or((false, false));
+                                    if ui.add_enabled(file_backed, egui::Button::new("Make self-contained")
+                                        .min_size(egui::Vec2::new(150.0, 0.0))).clicked() {
+                                        pending_commands.borrow_mut().push(TrackEditorCommand::EmbedClipContent {
+                                            clip_id: menu_clip_id,
+                                        });
+                                        self.clip_context_menu_pos = None;
+                                        self.clip_context_menu_open_pos = None;
+                                        self.clip_context_menu_clip_id = None;
+                                    }
+                                    if ui.add_enabled(embedded, egui::Button::new("Save content as…")
+                                        .min_size(egui::Vec2::new(150.0, 0.0))).clicked() {
+                                        self.emit_event(TrackEditorEvent::ClipContentSaveRequested {
+                                            clip_id: menu_clip_id,
+                                        });
+                                        self.clip_context_menu_pos = None;
+                                        self.clip_context_menu_open_pos = None;
+                                        self.clip_context_menu_clip_id = None;
+                                    }
+
                                     ui.separator();
                                     
                                     // 删除
@@ -1682,6 +2243,52 @@ impl TrackEditor {
                     }
                 }
 
+                // 显示空白网格右键菜单
+                if let Some(menu_pos) = self.grid_context_menu_pos {
+                    if let Some(menu_time) = self.grid_context_menu_time {
+                        let menu_response = egui::Area::new(egui::Id::new("grid_context_menu"))
+                            .order(egui::Order::Foreground)
+                            .fixed_pos(menu_pos)
+                            .show(ui.ctx(), |ui| {
+                                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                    ui.set_min_width(150.0);
+
+                                    if ui.button("Locate playhead here").clicked() {
+                                        pending_commands.borrow_mut().push(TrackEditorCommand::SetPlayhead {
+                                            position: menu_time,
+                                        });
+                                        self.grid_context_menu_pos = None;
+                                        self.grid_context_menu_open_pos = None;
+                                        self.grid_context_menu_time = None;
+                                    }
+                                });
+                            });
+
+                        // 关闭菜单逻辑
+                        let ctx = ui.ctx();
+                        if ctx.input(|i| i.pointer.primary_clicked() || i.pointer.secondary_clicked()) {
+                            if let Some(click_pos) = ctx.input(|i| i.pointer.interact_pos()) {
+                                let menu_rect = menu_response.response.rect;
+                                let ignore_click = if let Some(open_pos) = self.grid_context_menu_open_pos {
+                                    click_pos.distance(open_pos) < TRACK_CONTEXT_MENU_THRESHOLD
+                                } else {
+                                    false
+                                };
+
+                                if !ignore_click && !menu_rect.contains(click_pos) {
+                                    self.grid_context_menu_pos = None;
+                                    self.grid_context_menu_open_pos = None;
+                                    self.grid_context_menu_time = None;
+                                }
+                            } else {
+                                self.grid_context_menu_pos = None;
+                                self.grid_context_menu_open_pos = None;
+                                self.grid_context_menu_time = None;
+                            }
+                        }
+                    }
+                }
+
                 // Add "New Track" button (below the last track)
                 let add_track_button_height = 30.0;
                 let add_track_button_y = if !self.tracks.is_empty() {
@@ -1717,14 +2324,79 @@ impl TrackEditor {
                     });
                 }
 
-                // 执行收集的命令（包括按钮添加的命令）
+                // 执行收集的命令（包括按钮添加的命令），删除类命令按需确认
                 for command in pending_commands.borrow_mut().drain(..) {
-                    self.execute_command(command);
+                    self.request_command(command);
+                }
+
+                let name_action = track_name_action.borrow_mut().take();
+                if let Some(action) = name_action {
+                    self.handle_track_name_action(action);
                 }
             });
     }
 
 
+    /// 悬停超过 `hover_tooltip_delay` 后显示剪辑提示；等待期间只预约一次到期重绘
+    fn show_clip_tooltip(&mut self, ui: &Ui, hovered: Option<ClipId>) {
+        let (Some(delay), Some(clip_id)) = (self.options.hover_tooltip_delay, hovered) else {
+            self.clip_tooltip_hover = None;
+            return;
+        };
+        let now = ui.input(|i| i.time);
+        let since = match self.clip_tooltip_hover {
+            Some((id, since)) if id == clip_id => since,
+            _ => {
+                self.clip_tooltip_hover = Some((clip_id, now));
+                now
+            }
+        };
+        let remaining = delay as f64 - (now - since);
+        if remaining > 0.0 {
+            self.request_repaint(ui.ctx(), Some(std::time::Duration::from_secs_f64(remaining)));
+            return;
+        }
+        let clip = self.tracks.iter().flat_map(|t| &t.clips).find(|c| c.id == clip_id);
+        if let Some(clip) = clip {
+            let text = crate::utils::clip_tooltip(clip, &self.timeline);
+            show_tooltip_at_pointer(ui.ctx(), ui.layer_id(), ui.id().with("clip_tooltip"), |ui| {
+                ui.label(text);
+            });
+        }
+    }
+
+    /// 处理轨道名称内联编辑：校验失败时保留编辑框并记录错误
+    fn handle_track_name_action(&mut self, action: TrackNameAction) {
+        match action {
+            TrackNameAction::Start(track_id, name) => {
+                self.editing_track_name = Some((track_id, name));
+                self.track_name_error = None;
+                self.track_name_focus_pending = true;
+            }
+            TrackNameAction::Edit(value) => {
+                if let Some((_, current)) = &mut self.editing_track_name {
+                    *current = value;
+                }
+            }
+            TrackNameAction::Commit(track_id, value) => match self.validate_name(&value) {
+                Ok(()) => {
+                    self.editing_track_name = None;
+                    self.track_name_error = None;
+                    self.execute_command(TrackEditorCommand::RenameTrack { track_id, new_name: value });
+                }
+                Err(error) => {
+                    self.editing_track_name = Some((track_id, value));
+                    self.track_name_error = Some(error);
+                    self.track_name_focus_pending = true;
+                }
+            },
+            TrackNameAction::Cancel => {
+                self.editing_track_name = None;
+                self.track_name_error = None;
+            }
+        }
+    }
+
     /// 处理缩放操作（Ctrl/Alt + 滚轮）
     fn handle_zoom(&mut self, ui: &Ui, rect: &Rect, key_width: f32, timeline_height: f32) {
         let scroll_delta = ui.input(|i| i.raw_scroll_delta);
@@ -1768,6 +2440,44 @@ impl TrackEditor {
         }
     }
 
+    /// 处理键盘导航：`,` / `.` 将播放头移到上一个/下一个剪辑边界
+    ///
+    /// 有选中剪辑时只考虑这些剪辑所在的轨道，否则考虑所有轨道。文本框获得焦点时不处理。
+    fn handle_keyboard(&mut self, ui: &Ui) {
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+        let (previous, next) = ui.input(|i| (i.key_pressed(Key::Comma), i.key_pressed(Key::Period)));
+        if previous != next {
+            self.locate_clip_boundary(next);
+        }
+    }
+
+    /// 将播放头移到相邻的剪辑边界（起点或终点），没有更多边界时不动
+    fn locate_clip_boundary(&mut self, forward: bool) {
+        const EPSILON: f64 = 1e-6;
+        let selected_tracks = self.selected_tracks();
+        let mut boundaries: Vec<f64> = self
+            .tracks
+            .iter()
+            .filter(|track| selected_tracks.is_empty() || selected_tracks.contains(&track.id))
+            .flat_map(|track| track.clip_boundaries())
+            .collect();
+        boundaries.sort_by(f64::total_cmp);
+
+        let playhead = self.timeline.playhead_position;
+        let target = if forward {
+            let index = boundaries.partition_point(|b| *b <= playhead + EPSILON);
+            boundaries.get(index).copied()
+        } else {
+            let index = boundaries.partition_point(|b| *b < playhead - EPSILON);
+            index.checked_sub(1).map(|i| boundaries[i])
+        };
+        if let Some(position) = target {
+            self.execute_command(TrackEditorCommand::SetPlayhead { position });
+        }
+    }
+
     /// 处理中键拖拽平移
     fn handle_panning(&mut self, ui: &Ui) {
         if ui.input(|i| i.pointer.middle_down()) {
@@ -1867,7 +2577,7 @@ impl TrackEditor {
                     } else {
                         "MIDI Clip".to_string()
                     };
-                    (name, Color32::from_rgb(100, 200, 100))
+                    (name, track.color.unwrap_or(Color32::from_rgb(100, 200, 100)))
                 }
                 ClipType::Audio { audio_data } => {
                     let name = if let Some(audio_data) = audio_data {
@@ -1883,7 +2593,7 @@ impl TrackEditor {
                     } else {
                         "Audio Clip".to_string()
                     };
-                    (name, Color32::from_rgb(150, 150, 250))
+                    (name, track.color.unwrap_or(Color32::from_rgb(150, 150, 250)))
                 }
             };
             
@@ -1896,6 +2606,7 @@ impl TrackEditor {
                 clip_type,
                 name,
                 color,
+                muted: false,
             };
             track.clips.push(clip);
         }
@@ -1906,6 +2617,7 @@ impl TrackEditor {
             if let Some(pos) = track.clips.iter().position(|c| c.id == clip_id) {
                 track.clips.remove(pos);
                 self.selected_clips.remove(&clip_id);
+                self.preview_loader.cancel(clip_id);
                 return;
             }
         }
@@ -1925,6 +2637,8 @@ impl TrackEditor {
             // Update clip position
             // 限制：不允许将剪辑移动到小于 0 的位置
             let clamped_start = new_start.max(0.0);
+            let old_track_id = clip.track_id;
+            let old_start = clip.start_time;
             clip.track_id = new_track_id;
             // 根据 disable_snap 决定是否吸附
             clip.start_time = if disable_snap {
@@ -1932,36 +2646,57 @@ impl TrackEditor {
             } else {
                 self.timeline.snap_time(clamped_start)
             };
+            let new_start = clip.start_time;
 
             // Add to new track
             if let Some(track) = self.tracks.iter_mut().find(|t| t.id == new_track_id) {
                 track.clips.push(clip);
             }
+            // 拖拽时每帧都会调用，只在位置真正改变时通知
+            if old_track_id != new_track_id || old_start != new_start {
+                self.emit_event(TrackEditorEvent::ClipMoved {
+                    clip_id,
+                    old_track_id,
+                    new_track_id,
+                    new_start,
+                });
+            }
         }
     }
 
     fn resize_clip(&mut self, clip_id: ClipId, new_duration: f64, resize_from_start: bool, disable_snap: bool) {
         for track in &mut self.tracks {
             if let Some(clip) = track.clips.iter_mut().find(|c| c.id == clip_id) {
-                // 根据 disable_snap 决定是否吸附
                 let snapped_duration = if disable_snap {
+                    let new_duration = new_duration.max(0.1);
+                    if resize_from_start {
+                        // 限制：不允许将剪辑调整到小于 0 的位置
+                        clip.start_time = (clip.end_time() - new_duration).max(0.0);
+                    }
                     new_duration
                 } else {
-                    self.timeline.snap_time(new_duration)
-                }.max(0.1);
-                
-                if resize_from_start {
-                    let old_start = clip.start_time;
-                    let calculated_start = old_start + clip.duration - snapped_duration;
-                    // 根据 disable_snap 决定是否吸附开始位置
-                    let new_start = if disable_snap {
-                        calculated_start
+                    // 吸附时以 tick 为准：起止点都落在网格上，秒数只由 tick 派生，
+                    // 反复调整也不会让剪辑在长时间线上逐渐偏离网格
+                    let start_tick = self.timeline.time_to_tick(clip.start_time);
+                    let end_tick = self.timeline.time_to_tick(clip.end_time());
+                    let duration_ticks = self.timeline.snap_tick(self.timeline.time_to_tick(new_duration), false);
+                    let (start_tick, end_tick) = if resize_from_start {
+                        let start = self.timeline.snap_tick(end_tick.saturating_sub(duration_ticks), false);
+                        (start, end_tick.max(start))
                     } else {
-                        self.timeline.snap_time(calculated_start)
+                        (start_tick, start_tick + duration_ticks)
                     };
-                    // 限制：不允许将剪辑调整到小于 0 的位置
-                    clip.start_time = new_start.max(0.0);
-                }
+                    clip.start_time = self.timeline.tick_to_time(start_tick);
+                    let snapped_duration = self.timeline.tick_to_time(end_tick) - clip.start_time;
+                    if snapped_duration >= 0.1 {
+                        snapped_duration
+                    } else if resize_from_start {
+                        clip.start_time = (self.timeline.tick_to_time(end_tick) - 0.1).max(0.0);
+                        self.timeline.tick_to_time(end_tick) - clip.start_time
+                    } else {
+                        0.1
+                    }
+                };
                 clip.duration = snapped_duration;
                 self.emit_event(TrackEditorEvent::ClipResized {
                     clip_id,
@@ -1992,6 +2727,45 @@ impl TrackEditor {
         }
     }
 
+    fn set_clip_mute(&mut self, clip_id: ClipId, muted: bool) {
+        for track in &mut self.tracks {
+            if let Some(clip) = track.clips.iter_mut().find(|c| c.id == clip_id) {
+                if clip.muted != muted {
+                    clip.muted = muted;
+                    self.emit_event(TrackEditorEvent::ClipMuteChanged { clip_id, muted });
+                }
+                return;
+            }
+        }
+    }
+
+    fn set_clip_color(&mut self, clip_id: ClipId, color: Color32) {
+        for track in &mut self.tracks {
+            if let Some(clip) = track.clips.iter_mut().find(|c| c.id == clip_id) {
+                if clip.color != color {
+                    clip.color = color;
+                    self.emit_event(TrackEditorEvent::ClipColorChanged { clip_id, color });
+                }
+                return;
+            }
+        }
+    }
+
+    fn set_track_color(&mut self, track_id: TrackId, color: Color32) {
+        let Some(track) = self.tracks.iter_mut().find(|t| t.id == track_id) else {
+            return;
+        };
+        if track.color == Some(color) {
+            return;
+        }
+        track.color = Some(color);
+        let clip_ids: Vec<ClipId> = track.clips.iter().map(|c| c.id).collect();
+        self.emit_event(TrackEditorEvent::TrackColorChanged { track_id, color });
+        for clip_id in clip_ids {
+            self.set_clip_color(clip_id, color);
+        }
+    }
+
     fn rename_clip(&mut self, clip_id: ClipId, new_name: String) {
         for track in &mut self.tracks {
             if let Some(clip) = track.clips.iter_mut().find(|c| c.id == clip_id) {
@@ -2014,14 +2788,20 @@ impl TrackEditor {
 
     fn delete_track(&mut self, track_id: TrackId) {
         if let Some(pos) = self.tracks.iter().position(|t| t.id == track_id) {
-            self.tracks.remove(pos);
+            let track = self.tracks.remove(pos);
+            for clip in &track.clips {
+                self.preview_loader.cancel(clip.id);
+            }
             self.emit_event(TrackEditorEvent::TrackDeleted { track_id });
         }
     }
 
     fn rename_track(&mut self, track_id: TrackId, new_name: String) {
         if let Some(track) = self.tracks.iter_mut().find(|t| t.id == track_id) {
-            track.name = new_name;
+            if track.name != new_name {
+                track.name = new_name.clone();
+                self.emit_event(TrackEditorEvent::TrackRenamed { track_id, new_name });
+            }
         }
     }
     
@@ -2100,14 +2880,120 @@ impl TrackEditor {
                 break;
             }
         }
+        // 路径变化后旧文件的读取结果不再有效
+        self.preview_loader.cancel(clip_id);
+    }
+
+    /// 读取文件剪辑引用的文件，把内容嵌入剪辑并切换为嵌入模式
+    fn embed_clip_content(&mut self, clip_id: ClipId) -> Result<(), String> {
+        let path = self
+            .clip_midi_file_path(clip_id)
+            .ok_or_else(|| "Clip does not reference a MIDI file".to_string())?;
+        let state = load_clip_state(&path)?;
+        let keep_source = self.options.keep_source_path_on_embed;
+        let midi_data = self.clip_midi_data_mut(clip_id).ok_or_else(|| "Clip not found".to_string())?;
+        midi_data.preview_notes = preview_notes_from_state(&state);
+        midi_data.midi_state = Some(state);
+        let file_path = midi_data.midi_file_path.take();
+        midi_data.source_file_path = if keep_source { file_path } else { None };
+        self.preview_loader.cancel(clip_id);
+        Ok(())
+    }
+
+    /// 把嵌入的内容写入 `path`，并让剪辑改为引用该文件
+    fn extract_clip_content(&mut self, clip_id: ClipId, path: &str) -> Result<(), String> {
+        let midi_data = self.clip_midi_data_mut(clip_id).ok_or_else(|| "Clip not found".to_string())?;
+        let state = midi_data
+            .midi_state
+            .take()
+            .ok_or_else(|| "Clip has no embedded MIDI content".to_string())?;
+        if let Err(error) = save_clip_state(std::path::Path::new(path), &state) {
+            midi_data.midi_state = Some(state);
+            return Err(error);
+        }
+        midi_data.preview_notes = preview_notes_from_state(&state);
+        midi_data.midi_file_path = Some(path.to_string());
+        midi_data.source_file_path = None;
+        self.preview_loader.cancel(clip_id);
+        Ok(())
+    }
+
+    fn emit_clip_content_result(&mut self, clip_id: ClipId, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.emit_event(TrackEditorEvent::ClipContentChanged { clip_id }),
+            Err(error) => self.emit_event(TrackEditorEvent::ClipContentFailed { clip_id, error }),
+        }
+    }
+
+    fn clip_midi_data_mut(&mut self, clip_id: ClipId) -> Option<&mut MidiClipData> {
+        self.tracks
+            .iter_mut()
+            .flat_map(|track| track.clips.iter_mut())
+            .find(|clip| clip.id == clip_id)
+            .and_then(|clip| match &mut clip.clip_type {
+                ClipType::Midi { midi_data: Some(midi_data) } => Some(midi_data),
+                _ => None,
+            })
+    }
+
+    /// 剪辑关联的 MIDI 文件路径
+    fn clip_midi_file_path(&self, clip_id: ClipId) -> Option<std::path::PathBuf> {
+        self.tracks
+            .iter()
+            .flat_map(|track| track.clips.iter())
+            .find(|clip| clip.id == clip_id)
+            .and_then(|clip| match &clip.clip_type {
+                ClipType::Midi { midi_data: Some(midi_data) } => midi_data.midi_file_path.as_ref().map(std::path::PathBuf::from),
+                _ => None,
+            })
+    }
+
+    /// 将缺少预览的文件剪辑排入后台加载队列，并应用已完成的结果
+    fn update_preview_loading(&mut self, ctx: &Context) {
+        for track in &self.tracks {
+            for clip in &track.clips {
+                if let ClipType::Midi { midi_data: Some(midi_data) } = &clip.clip_type {
+                    if let Some(path) = &midi_data.midi_file_path {
+                        if midi_data.preview_notes.is_empty() && !self.preview_loader.is_requested(clip.id) {
+                            self.preview_loader.enqueue(clip.id, std::path::PathBuf::from(path));
+                        }
+                    }
+                }
+            }
+        }
+
+        // 读取失败的剪辑保持无预览，不自动重试（可用 ReloadClipPreview 手动重试）
+        for (clip_id, result) in self.preview_loader.poll() {
+            if let Ok(preview_notes) = result {
+                self.update_clip_preview(clip_id, preview_notes);
+                self.emit_event(TrackEditorEvent::ClipPreviewLoaded { clip_id });
+            }
+        }
+
+        if self.preview_loader.is_busy() {
+            self.request_repaint(ctx, Some(std::time::Duration::from_millis(50)));
+        }
+    }
+
+    /// 请求重绘：`after` 为 `None` 时下一帧立即重绘，否则在给定时长后重绘
+    fn request_repaint(&mut self, ctx: &Context, after: Option<std::time::Duration>) {
+        match after {
+            Some(delay) => ctx.request_repaint_after(delay),
+            None => ctx.request_repaint(),
+        }
+        if self.options.debug_repaint_counter {
+            self.repaint_requests += 1;
+        }
     }
     
     /// 删除多个剪辑
-    
     fn delete_clips(&mut self, clip_ids: Vec<ClipId>) {
         for track in &mut self.tracks {
             track.clips.retain(|clip| !clip_ids.contains(&clip.id));
         }
+        for clip_id in &clip_ids {
+            self.preview_loader.cancel(*clip_id);
+        }
         
         // 从选中集合中移除已删除的剪辑
         for clip_id in &clip_ids {
@@ -2127,6 +3013,18 @@ impl TrackEditor {
             listener(&event);
         }
         self.pending_events.push(event);
+        let max = self.options.max_pending_events;
+        if max > 0 && self.pending_events.len() > max {
+            if !self.events_overflowed {
+                log::warn!(
+                    "TrackEditor: more than {} events pending, dropping the oldest (is take_events being called?)",
+                    max
+                );
+                self.events_overflowed = true;
+            }
+            let excess = self.pending_events.len() - max;
+            self.pending_events.drain(..excess);
+        }
     }
 
     // Public getters
@@ -2140,6 +3038,13 @@ impl TrackEditor {
         &self.tracks
     }
 
+    /// 用项目内容替换全部轨道与时间轴（不经过删除确认）
+    pub fn load_project(&mut self, project: ProjectFile) {
+        self.execute_command(TrackEditorCommand::ClearAll);
+        self.tracks = project.tracks;
+        self.timeline = project.timeline;
+    }
+
     /// 获取时间轴状态的只读引用
     ///
     /// # 返回
@@ -2157,6 +3062,66 @@ impl TrackEditor {
     pub fn selected_clips(&self) -> &BTreeSet<ClipId> {
         &self.selected_clips
     }
+
+    /// 启用 `debug_repaint_counter` 后编辑器累计发出的重绘请求次数
+    pub fn repaint_request_count(&self) -> u64 {
+        self.repaint_requests
+    }
+
+    /// 包含选中剪辑的轨道，按轨道顺序排列
+    pub fn selected_tracks(&self) -> Vec<TrackId> {
+        self.tracks
+            .iter()
+            .filter(|track| track.clips.iter().any(|c| self.selected_clips.contains(&c.id)))
+            .map(|track| track.id)
+            .collect()
+    }
+
+    /// 将整条轨道导出为单轨 SMF（stem），详见 [`export::track_range_state`]
+    pub fn export_track_smf(&self, track_id: TrackId) -> Result<midly::Smf<'static>, ExportError> {
+        let track = self
+            .tracks
+            .iter()
+            .find(|track| track.id == track_id)
+            .ok_or(ExportError::TrackNotFound(track_id))?;
+        export::track_smf(track, &self.timeline)
+    }
+
+    /// 将多条轨道在 `[start, end)` 秒范围内的内容分别导出为 SMF，范围起点对应 tick 0
+    pub fn export_range_smf(
+        &self,
+        start: f64,
+        end: f64,
+        tracks: &[TrackId],
+    ) -> Result<Vec<(TrackId, midly::Smf<'static>)>, ExportError> {
+        export::range_smfs(&self.tracks, &self.timeline, start, end, tracks)
+    }
+
+    /// 获取某一时间点在指定轨道中线上的屏幕坐标
+    ///
+    /// 基于上一帧的布局和当前缩放/滚动计算，在第一次调用 `ui()` 之前返回 `None`。
+    /// 宿主可用它在剪辑上方绘制叠加层或生成合成输入。
+    pub fn time_to_screen(&self, time: f64, track_index: usize) -> Option<Pos2> {
+        let rect = self.roll_rect?;
+        let y = rect.min.y
+            + self.track_to_y(track_index, self.options.timeline_height)
+            + self.timeline.zoom_y * 0.5;
+        Some(Pos2::new(self.time_to_screen_x(rect, time), y))
+    }
+
+    /// 获取某一时间点在顶部时间轴（垂直居中）上的屏幕坐标
+    pub fn timeline_time_to_screen(&self, time: f64) -> Option<Pos2> {
+        let rect = self.roll_rect?;
+        Some(Pos2::new(
+            self.time_to_screen_x(rect, time),
+            rect.min.y + self.options.timeline_height * 0.5,
+        ))
+    }
+
+    fn time_to_screen_x(&self, rect: Rect, time: f64) -> f32 {
+        self.timeline
+            .tick_to_screen_x(self.timeline.time_to_tick(time), rect.min.x + self.options.track_header_width)
+    }
     
     /// 构建剪辑的工具提示文本
     #[allow(dead_code)]
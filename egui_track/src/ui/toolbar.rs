@@ -153,13 +153,11 @@ impl Toolbar {
                 ui.label("Interval:");
                 // 计算常见的吸附精度选项（以 tick 为单位）
                 let ticks_per_beat = self.timeline.ticks_per_beat as u64;
-                let common_intervals = vec![
-                    (ticks_per_beat / 4, "1/16"),
+                let common_intervals = [(ticks_per_beat / 4, "1/16"),
                     (ticks_per_beat / 2, "1/8"),
                     (ticks_per_beat, "1/4"),
                     (ticks_per_beat * 2, "1/2"),
-                    (ticks_per_beat * 4, "1"),
-                ];
+                    (ticks_per_beat * 4, "1")];
                 
                 let current_interval = self.timeline.snap_interval;
                 let mut selected_index = common_intervals.iter()
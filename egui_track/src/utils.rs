@@ -1,6 +1,9 @@
 //! 工具函数模块
 //!
-//! 包含通用的工具函数，如时间格式化等。
+//! 包含通用的工具函数，如时间格式化、悬停提示文本等。
+
+use crate::structure::{Clip, ClipType, TimelineState};
+use egui_midi::tooltip::format_bar_beat_tick;
 
 /// 将时间（秒）格式化为 "MM:SS.mmm" 格式
 ///
@@ -27,3 +30,47 @@ pub fn format_time(time_seconds: f64) -> String {
     format!("{:02}:{:02}.{:03}", minutes, seconds, milliseconds)
 }
 
+
+/// 剪辑的悬停提示：名称、起止位置（"小节:拍:tick"）、小节数、内容来源与音符数
+///
+/// 只使用剪辑中已有的数据，不读取文件：引用文件的 MIDI 剪辑的音符数来自已加载的预览。
+pub fn clip_tooltip(clip: &Clip, timeline: &TimelineState) -> String {
+    let position = |time: f64| {
+        format_bar_beat_tick(timeline.time_to_tick(time), timeline.ticks_per_beat, timeline.time_signature)
+    };
+    let measures = timeline.time_to_tick(clip.duration) as f64 / timeline.ticks_per_measure().max(1) as f64;
+    let measures = if (measures - measures.round()).abs() < 1e-6 {
+        format!("{}", measures.round())
+    } else {
+        format!("{measures:.2}")
+    };
+    let mut lines = vec![
+        clip.name.clone(),
+        format!("Start {}", position(clip.start_time)),
+        format!("End {}", position(clip.end_time())),
+        format!("Length {measures} measures"),
+    ];
+    match &clip.clip_type {
+        ClipType::Midi { midi_data: Some(data) } => {
+            let (source, notes) = match (&data.midi_file_path, &data.midi_state) {
+                (Some(path), _) => (path.clone(), data.preview_notes.len()),
+                (None, Some(state)) => ("embedded".to_string(), state.notes.len()),
+                (None, None) => ("none".to_string(), data.preview_notes.len()),
+            };
+            lines.push(format!("Source {source}"));
+            lines.push(format!("Notes {notes}"));
+        }
+        ClipType::Midi { midi_data: None } => {
+            lines.push("Source none".to_string());
+            lines.push("Notes 0".to_string());
+        }
+        ClipType::Audio { audio_data } => {
+            let source = audio_data
+                .as_ref()
+                .and_then(|data| data.audio_file_path.clone())
+                .unwrap_or_else(|| "none".to_string());
+            lines.push(format!("Source {source}"));
+        }
+    }
+    lines.join("\n")
+}
@@ -1,5 +1,6 @@
 use eframe::egui;
-use egui_track::{TrackEditor, TrackEditorOptions, ClipType, ProjectFile, format_time};
+use egui_track::{TrackEditor, TrackEditorOptions, ProjectFile, format_time};
+use egui_track::demo::{self, DemoProject};
 use std::path::PathBuf;
 use rfd::FileDialog;
 
@@ -146,6 +147,16 @@ impl eframe::App for TrackEditorApp {
                         self.export_project();
                         ui.close_menu();
                     }
+                    ui.separator();
+                    ui.menu_button("Load demo content", |ui| {
+                        for kind in DemoProject::ALL {
+                            if ui.button(kind.label()).clicked() {
+                                self.editor.load_project(demo::generate_project(kind));
+                                self.current_project_path = None;
+                                ui.close_menu();
+                            }
+                        }
+                    });
                 });
             });
         });
@@ -1,6 +1,8 @@
 use eframe::egui::{self, TopBottomPanel};
 use egui_midi::audio::{AudioEngine, PlaybackBackend};
-use egui_midi::structure::MidiState;
+use egui_midi::demo::{self, DemoKind};
+use egui_midi::editor::EditorEvent;
+use egui_midi::structure::{ImportWarning, MidiState};
 use egui_midi::ui::MidiEditor;
 use midly::Smf;
 use rfd::FileDialog;
@@ -23,12 +25,54 @@ struct MyApp {
     editor: MidiEditor,
     current_path: Option<PathBuf>,
     status_line: String,
+    image_export: Option<ImageExportSettings>,
+    /// What the last lenient MIDI import skipped or repaired, shown until dismissed.
+    import_warnings: Vec<ImportWarning>,
+    /// Title last sent to the window, so it is only updated when it changes.
+    window_title: String,
+    /// The window was asked to close with unsaved changes; shows the confirmation dialog.
+    confirm_close: bool,
+    /// The user chose to close anyway (or saved first), so the next close request goes through.
+    allow_close: bool,
+}
+
+/// Settings edited in the "Export Image..." dialog.
+struct ImageExportSettings {
+    width: usize,
+    height: usize,
+    first_measure: u64,
+    last_measure: u64,
+    low_key: u8,
+    high_key: u8,
+    velocity_lane: bool,
+}
+
+impl ImageExportSettings {
+    fn for_state(state: &MidiState) -> Self {
+        let ticks_per_measure = state.ticks_per_measure();
+        let end = state.notes.iter().map(|n| n.start + n.duration).max().unwrap_or(0);
+        let (low_key, high_key) = state
+            .notes
+            .iter()
+            .fold((127u8, 0u8), |(low, high), n| (low.min(n.key), high.max(n.key)));
+        let (low_key, high_key) = if low_key > high_key { (48, 84) } else { (low_key, high_key) };
+        Self {
+            width: 1200,
+            height: 600,
+            first_measure: 1,
+            last_measure: end.div_ceil(ticks_per_measure).max(1),
+            low_key,
+            high_key,
+            velocity_lane: true,
+        }
+    }
 }
 
 impl MyApp {
     fn new() -> Self {
         // Initialize Audio
         // Note: In a real app you might want to handle errors gracefully
+        #[allow(clippy::arc_with_non_send_sync)]
         let audio: Arc<dyn PlaybackBackend> = Arc::new(AudioEngine::new());
 
         // Create editor with default empty state
@@ -39,6 +83,11 @@ impl MyApp {
             editor,
             current_path: None,
             status_line: "Ready - Use File menu to open or import a MIDI file".to_owned(),
+            image_export: None,
+            import_warnings: Vec::new(),
+            window_title: String::new(),
+            confirm_close: false,
+            allow_close: false,
         }
     }
 
@@ -70,6 +119,23 @@ impl MyApp {
                     self.export_midi_dialog();
                     ui.close_menu();
                 }
+                if ui.button("Export loop as MIDI...").clicked() {
+                    self.export_loop_dialog();
+                    ui.close_menu();
+                }
+                if ui.button("Export Image...").clicked() {
+                    self.image_export = Some(ImageExportSettings::for_state(self.editor.midi_state()));
+                    ui.close_menu();
+                }
+                ui.separator();
+                ui.menu_button("Load demo content", |ui| {
+                    for kind in DemoKind::ALL {
+                        if ui.button(kind.label()).clicked() {
+                            self.load_demo(kind);
+                            ui.close_menu();
+                        }
+                    }
+                });
             });
 
             if let Some(path) = &self.current_path {
@@ -82,10 +148,17 @@ impl MyApp {
 
     fn new_project(&mut self) {
         self.editor.replace_state(MidiState::default());
+        self.editor.mark_saved();
         self.current_path = None;
         self.set_status("Created new project");
     }
 
+    fn load_demo(&mut self, kind: DemoKind) {
+        self.editor.replace_state(demo::generate(kind));
+        self.current_path = None;
+        self.set_status(format!("Loaded demo content: {}", kind.label()));
+    }
+
     fn open_project_dialog(&mut self) {
         if let Some(path) = FileDialog::new()
             .add_filter("AquaMIDI Project", &["aquamidi"])
@@ -94,6 +167,8 @@ impl MyApp {
             match read_aquamidi_file(&path) {
                 Ok(state) => {
                     self.editor.replace_state(state);
+                    self.editor.mark_saved();
+                    self.editor.zoom_to_fit();
                     self.current_path = Some(path.clone());
                     self.set_status(format!("Opened {}", path.display()));
                 }
@@ -105,7 +180,10 @@ impl MyApp {
     fn save_project(&mut self) {
         if let Some(path) = self.current_path.clone() {
             match write_aquamidi_file(&path, &self.editor.snapshot_state()) {
-                Ok(_) => self.set_status(format!("Saved {}", path.display())),
+                Ok(_) => {
+                    self.editor.mark_saved();
+                    self.set_status(format!("Saved {}", path.display()));
+                }
                 Err(err) => self.set_error(err),
             }
         } else {
@@ -121,6 +199,7 @@ impl MyApp {
         {
             match write_aquamidi_file(&path, &self.editor.snapshot_state()) {
                 Ok(_) => {
+                    self.editor.mark_saved();
                     self.current_path = Some(path.clone());
                     self.set_status(format!("Saved {}", path.display()));
                 }
@@ -135,10 +214,16 @@ impl MyApp {
             .pick_file()
         {
             match import_midi_file(&path) {
-                Ok(state) => {
+                Ok((state, warnings)) => {
                     self.editor.replace_state(state);
+                    self.editor.zoom_to_fit();
                     self.current_path = None; // Imported MIDI is not a project file
-                    self.set_status(format!("Imported {}", path.display()));
+                    if warnings.is_empty() {
+                        self.set_status(format!("Imported {}", path.display()));
+                    } else {
+                        self.set_status(format!("Imported {} with {} warning(s)", path.display(), warnings.len()));
+                    }
+                    self.import_warnings = warnings;
                 }
                 Err(err) => self.set_error(err),
             }
@@ -158,6 +243,196 @@ impl MyApp {
         }
     }
 
+    /// 只导出循环区内的音符与曲线，循环起点成为文件开头
+    fn export_loop_dialog(&mut self) {
+        let smf = match self.editor.export_loop_smf() {
+            Ok(smf) => smf,
+            Err(err) => {
+                self.set_error(format!("Export error: {err}"));
+                return;
+            }
+        };
+        if let Some(path) = FileDialog::new()
+            .add_filter("Standard MIDI", &["mid", "midi"])
+            .set_file_name(self.default_file_name("loop.mid"))
+            .save_file()
+        {
+            let mut buffer = Vec::new();
+            let result = smf
+                .write_std(&mut buffer)
+                .map_err(|err| format!("Failed to encode SMF: {err}"))
+                .and_then(|_| fs::write(&path, buffer).map_err(|err| format!("Failed to write {}: {err}", path.display())));
+            match result {
+                Ok(()) => self.set_status(format!("Exported loop to {}", path.display())),
+                Err(err) => self.set_error(err),
+            }
+        }
+    }
+
+    fn image_export_window(&mut self, ctx: &egui::Context) {
+        let Some(settings) = self.image_export.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut export = false;
+        egui::Window::new("Export Image")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("image_export_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Size (px):");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut settings.width).range(64..=8192));
+                        ui.label("×");
+                        ui.add(egui::DragValue::new(&mut settings.height).range(64..=8192));
+                    });
+                    ui.end_row();
+                    ui.label("Measures:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut settings.first_measure).range(1..=9999));
+                        ui.label("to");
+                        ui.add(egui::DragValue::new(&mut settings.last_measure).range(settings.first_measure..=9999));
+                    });
+                    ui.end_row();
+                    ui.label("Keys:");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut settings.low_key).range(0..=127));
+                        ui.label("to");
+                        ui.add(egui::DragValue::new(&mut settings.high_key).range(settings.low_key..=127));
+                    });
+                    ui.end_row();
+                });
+                ui.checkbox(&mut settings.velocity_lane, "Include velocity lane");
+                ui.separator();
+                export = ui.button("Export PNG...").clicked();
+            });
+
+        if export {
+            self.export_image_dialog();
+        } else if !open {
+            self.image_export = None;
+        }
+    }
+
+    fn export_image_dialog(&mut self) {
+        let Some(settings) = self.image_export.take() else {
+            return;
+        };
+        if let Some(path) = FileDialog::new()
+            .add_filter("PNG Image", &["png"])
+            .set_file_name(self.default_file_name("png"))
+            .save_file()
+        {
+            let ticks_per_measure = self.editor.midi_state().ticks_per_measure();
+            let tick_range = (settings.first_measure - 1) * ticks_per_measure..settings.last_measure * ticks_per_measure;
+            let result = self
+                .editor
+                .render_to_png(
+                    settings.width,
+                    settings.height,
+                    tick_range,
+                    settings.low_key..=settings.high_key,
+                    settings.velocity_lane,
+                )
+                .map_err(|err| format!("Failed to encode image: {err}"))
+                .and_then(|bytes| {
+                    fs::write(&path, bytes).map_err(|err| format!("Failed to write {}: {err}", path.display()))
+                });
+            match result {
+                Ok(_) => self.set_status(format!("Exported {}", path.display())),
+                Err(err) => self.set_error(err),
+            }
+        } else {
+            self.image_export = Some(settings);
+        }
+    }
+
+    fn import_warnings_window(&mut self, ctx: &egui::Context) {
+        if self.import_warnings.is_empty() {
+            return;
+        }
+        let mut open = true;
+        let mut dismissed = false;
+        egui::Window::new("MIDI Import Warnings")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("The file was imported, but some content was skipped or repaired:");
+                egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                    for warning in &self.import_warnings {
+                        ui.label(format!("• {warning}"));
+                    }
+                });
+                dismissed = ui.button("OK").clicked();
+            });
+        if !open || dismissed {
+            self.import_warnings.clear();
+        }
+    }
+
+    /// Shows "*" in front of the project name while there are unsaved changes.
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let name = self
+            .current_path
+            .as_ref()
+            .and_then(|p| p.file_name().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "Untitled".to_owned());
+        let marker = if self.editor.is_dirty() { "*" } else { "" };
+        let title = format!("{marker}{name} - egui MIDI Editor Example");
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    /// Holds back a close request while there are unsaved changes and asks what to do.
+    fn confirm_close_window(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| i.viewport().close_requested()) && self.editor.is_dirty() && !self.allow_close {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.confirm_close = true;
+        }
+        if !self.confirm_close {
+            return;
+        }
+        let mut choice = None;
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("Save changes to the project before closing?");
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        choice = Some(CloseChoice::Save);
+                    }
+                    if ui.button("Don't Save").clicked() {
+                        choice = Some(CloseChoice::Discard);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        choice = Some(CloseChoice::Cancel);
+                    }
+                });
+            });
+        match choice {
+            Some(CloseChoice::Save) => {
+                self.save_project();
+                self.confirm_close = false;
+                if !self.editor.is_dirty() {
+                    self.allow_close = true;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
+            Some(CloseChoice::Discard) => {
+                self.confirm_close = false;
+                self.allow_close = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            Some(CloseChoice::Cancel) => self.confirm_close = false,
+            None => {}
+        }
+    }
+
     fn default_file_name(&self, extension: &str) -> String {
         self.current_path
             .as_ref()
@@ -178,6 +453,12 @@ impl MyApp {
     }
 }
 
+enum CloseChoice {
+    Save,
+    Discard,
+    Cancel,
+}
+
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         TopBottomPanel::top("menu_bar").show(ctx, |ui| {
@@ -188,13 +469,23 @@ impl eframe::App for MyApp {
             self.editor.ui(ui);
         });
 
+        self.image_export_window(ctx);
+        self.import_warnings_window(ctx);
+        self.confirm_close_window(ctx);
+
         TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
             ui.label(self.status_line.clone());
         });
 
         for event in self.editor.take_events() {
-            log::info!("[EditorEvent] {:?}", event);
+            match event {
+                EditorEvent::StateReplaced(state) => {
+                    log::info!("[EditorEvent] StateReplaced ({} notes)", state.notes.len())
+                }
+                event => log::info!("[EditorEvent] {:?}", event),
+            }
         }
+        self.update_window_title(ctx);
     }
 }
 
@@ -205,9 +496,14 @@ fn write_aquamidi_file(path: &Path, state: &MidiState) -> Result<(), String> {
     let mut buffer = Vec::new();
     buffer.extend_from_slice(AQUAMIDI_MAGIC);
     buffer.extend_from_slice(&AQUAMIDI_VERSION.to_le_bytes());
-    let smf = state
-        .to_single_track_smf()
-        .map_err(|err| format!("Export error: {err}"))?;
+    let arena = midly::Arena::new();
+    let smf = if state.is_multi_track() {
+        state.to_smf_in(&arena)
+    } else {
+        state
+            .to_single_track_smf_in(&arena)
+            .map_err(|err| format!("Export error: {err}"))?
+    };
     smf.write_std(&mut buffer)
         .map_err(|err| format!("Failed to encode project: {err}"))?;
     fs::write(path, buffer).map_err(|err| format!("Failed to write {}: {err}", path.display()))
@@ -229,19 +525,36 @@ fn read_aquamidi_file(path: &Path) -> Result<MidiState, String> {
     }
     let smf_bytes = &data[AQUAMIDI_MAGIC.len() + 4..];
     let smf = Smf::parse(smf_bytes).map_err(|err| format!("Failed to parse SMF: {err}"))?;
-    MidiState::from_smf_strict(&smf).map_err(|err| format!("Invalid MIDI data: {err}"))
+    let state = MidiState::from_smf(&smf);
+    if !state.is_multi_track() {
+        state
+            .validate_single_track()
+            .map_err(|err| format!("Invalid MIDI data: {err}"))?;
+    }
+    Ok(state)
 }
 
-fn import_midi_file(path: &Path) -> Result<MidiState, String> {
+/// 宽松导入：能恢复的音符都保留，跳过的内容作为警告返回；多轨文件按轨道分开载入，不算警告
+fn import_midi_file(path: &Path) -> Result<(MidiState, Vec<ImportWarning>), String> {
     let data = fs::read(path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
     let smf = Smf::parse(&data).map_err(|err| format!("Failed to parse MIDI file: {err}"))?;
-    MidiState::from_smf_strict(&smf).map_err(|err| format!("Invalid MIDI data: {err}"))
+    let (state, mut warnings) = MidiState::from_smf_lenient(&smf);
+    if state.is_multi_track() {
+        warnings.retain(|warning| !matches!(warning, ImportWarning::NotSingleTrack(_)));
+    }
+    Ok((state, warnings))
 }
 
 fn export_midi_file(path: &Path, state: &MidiState) -> Result<(), String> {
-    let smf = state
-        .to_single_track_smf()
-        .map_err(|err| format!("Export error: {err}"))?;
+    let arena = midly::Arena::new();
+    let smf = if state.is_multi_track() {
+        // format 1，每条轨道单独写出
+        state.to_smf_in(&arena)
+    } else {
+        state
+            .to_single_track_smf()
+            .map_err(|err| format!("Export error: {err}"))?
+    };
     let mut buffer = Vec::new();
     smf.write_std(&mut buffer)
         .map_err(|err| format!("Failed to encode SMF: {err}"))?;
@@ -0,0 +1,332 @@
+//! 编辑器交互的集成测试工具
+//!
+//! [`Harness`] 在无窗口的 `egui::Context` 中逐帧运行编辑器，并通过脚本化的 `RawInput`
+//! （指针按下/移动/松开、按键、文本输入）驱动它，测试随后检查编辑器状态和发出的事件。
+//!
+//! ```
+//! use egui::Modifiers;
+//! use egui_midi::ui::MidiEditor;
+//! use integration_tests::Harness;
+//!
+//! let mut harness = Harness::new(MidiEditor::new(None));
+//! let from = harness.editor.tick_key_to_screen(480, 60).unwrap();
+//! let to = harness.editor.tick_key_to_screen(960, 60).unwrap();
+//! harness.drag(from, to, Modifiers::SHIFT);
+//! assert_eq!(harness.editor.state.notes.len(), 1);
+//! ```
+
+use egui::epaint::{ClippedShape, Shape};
+use egui::{CentralPanel, Context, Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, Ui, Vec2, ViewportId};
+use std::time::Duration;
+use egui_midi::ui::MidiEditor;
+use egui_track::TrackEditor;
+
+/// 每帧推进的时间（秒）
+pub const FRAME_DT: f64 = 1.0 / 60.0;
+
+/// 拖拽时插入的中间移动帧数，保证超过 egui 的拖拽判定阈值
+const DRAG_STEPS: usize = 6;
+
+/// 可由 [`Harness`] 驱动的编辑器
+pub trait EditorUi {
+    fn show(&mut self, ui: &mut Ui);
+}
+
+impl EditorUi for MidiEditor {
+    fn show(&mut self, ui: &mut Ui) {
+        self.ui(ui);
+    }
+}
+
+impl EditorUi for TrackEditor {
+    fn show(&mut self, ui: &mut Ui) {
+        self.ui(ui);
+    }
+}
+
+/// 逐帧驱动一个编辑器的测试工具
+///
+/// 编辑器占满整个虚拟屏幕（`CentralPanel`）。每个输入动作至少运行一帧，
+/// 帧与帧之间时间推进 [`FRAME_DT`]，因此单击和双击的判定与真实输入一致。
+pub struct Harness<E> {
+    pub editor: E,
+    pub ctx: Context,
+    /// 模拟的系统剪贴板：编辑器通过 `ctx.copy_text` 写入的最近一段文本
+    pub clipboard: String,
+    screen_rect: Rect,
+    time: f64,
+    modifiers: Modifiers,
+    focused: bool,
+    queued: Vec<Event>,
+    shapes: Vec<ClippedShape>,
+    repaint_delay: Duration,
+}
+
+impl<E: EditorUi> Harness<E> {
+    /// 使用 1200×800 的虚拟屏幕，并先运行两帧完成初始布局
+    pub fn new(editor: E) -> Self {
+        Self::with_screen_size(editor, Vec2::new(1200.0, 800.0))
+    }
+
+    pub fn with_screen_size(editor: E, size: Vec2) -> Self {
+        let mut harness = Self {
+            editor,
+            ctx: Context::default(),
+            clipboard: String::new(),
+            screen_rect: Rect::from_min_size(Pos2::ZERO, size),
+            time: 0.0,
+            modifiers: Modifiers::NONE,
+            focused: true,
+            queued: Vec::new(),
+            shapes: Vec::new(),
+            repaint_delay: Duration::MAX,
+        };
+        harness.step();
+        harness.step();
+        harness
+    }
+
+    /// 运行一帧，发送此前排队的输入事件
+    pub fn step(&mut self) {
+        let input = RawInput {
+            screen_rect: Some(self.screen_rect),
+            time: Some(self.time),
+            modifiers: self.modifiers,
+            focused: self.focused,
+            events: std::mem::take(&mut self.queued),
+            ..Default::default()
+        };
+        let editor = &mut self.editor;
+        let output = self.ctx.run(input, |ctx| {
+            CentralPanel::default().show(ctx, |ui| editor.show(ui));
+        });
+        self.shapes = output.shapes;
+        if !output.platform_output.copied_text.is_empty() {
+            self.clipboard = output.platform_output.copied_text;
+        }
+        self.repaint_delay = output
+            .viewport_output
+            .get(&ViewportId::ROOT)
+            .map_or(Duration::MAX, |viewport| viewport.repaint_delay);
+        self.time += FRAME_DT;
+    }
+
+    /// 上一帧结束时请求的重绘延迟；没有任何重绘请求时为 `Duration::MAX`
+    pub fn repaint_delay(&self) -> Duration {
+        self.repaint_delay
+    }
+
+    pub fn steps(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
+    /// 移动指针（不按键）
+    pub fn hover(&mut self, pos: Pos2) {
+        self.queue_move(pos);
+        self.step();
+    }
+
+    /// 在 `pos` 按下指定按键，修饰键保持到松开为止
+    pub fn press(&mut self, pos: Pos2, button: PointerButton, modifiers: Modifiers) {
+        self.modifiers = modifiers;
+        self.queue_move(pos);
+        self.queued.push(Event::PointerButton {
+            pos,
+            button,
+            pressed: true,
+            modifiers,
+        });
+        self.step();
+    }
+
+    /// 在 `pos` 松开按键并清除修饰键
+    pub fn release(&mut self, pos: Pos2, button: PointerButton) {
+        self.queue_move(pos);
+        self.queued.push(Event::PointerButton {
+            pos,
+            button,
+            pressed: false,
+            modifiers: self.modifiers,
+        });
+        self.step();
+        self.modifiers = Modifiers::NONE;
+    }
+
+    /// 左键单击，之后再运行一帧让弹出的界面完成布局
+    pub fn click(&mut self, pos: Pos2, modifiers: Modifiers) {
+        self.press(pos, PointerButton::Primary, modifiers);
+        self.release(pos, PointerButton::Primary);
+        self.step();
+    }
+
+    /// 右键单击，之后再运行一帧让上下文菜单完成布局
+    pub fn secondary_click(&mut self, pos: Pos2, modifiers: Modifiers) {
+        self.press(pos, PointerButton::Secondary, modifiers);
+        self.release(pos, PointerButton::Secondary);
+        self.step();
+    }
+
+    pub fn double_click(&mut self, pos: Pos2) {
+        for _ in 0..2 {
+            self.press(pos, PointerButton::Primary, Modifiers::NONE);
+            self.release(pos, PointerButton::Primary);
+        }
+        self.step();
+    }
+
+    /// 按住左键从 `from` 拖到 `to`，中间分若干帧移动
+    pub fn drag(&mut self, from: Pos2, to: Pos2, modifiers: Modifiers) {
+        self.press(from, PointerButton::Primary, modifiers);
+        for i in 1..=DRAG_STEPS {
+            let t = i as f32 / DRAG_STEPS as f32;
+            self.queue_move(from.lerp(to, t));
+            self.step();
+        }
+        self.release(to, PointerButton::Primary);
+        self.step();
+    }
+
+    /// 按下并松开一个键
+    pub fn key(&mut self, key: Key, modifiers: Modifiers) {
+        self.modifiers = modifiers;
+        for pressed in [true, false] {
+            self.queued.push(Event::Key {
+                key,
+                physical_key: None,
+                pressed,
+                repeat: false,
+                modifiers,
+            });
+        }
+        self.step();
+        self.modifiers = Modifiers::NONE;
+        self.step();
+    }
+
+    /// 只发送一个按键的按下或松开（`repeat` 模拟按住时的自动重复），用于同时按住多个键
+    pub fn key_event(&mut self, key: Key, pressed: bool, repeat: bool) {
+        self.queued.push(Event::Key {
+            key,
+            physical_key: None,
+            pressed,
+            repeat,
+            modifiers: self.modifiers,
+        });
+        self.step();
+    }
+
+    /// 模拟窗口获得或失去焦点（`RawInput::focused` 与 `Event::WindowFocused`）
+    pub fn set_window_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.queued.push(Event::WindowFocused(focused));
+        self.step();
+    }
+
+    /// 像 egui-winit 处理 Ctrl/Cmd+C 一样发送 `Event::Copy`（不发送按键）
+    pub fn copy(&mut self) {
+        self.clipboard_command(Event::Copy);
+    }
+
+    /// 像 egui-winit 处理 Ctrl/Cmd+V 一样发送带 [`Harness::clipboard`] 内容的 `Event::Paste`（剪贴板为空时不发送）
+    pub fn paste(&mut self) {
+        if !self.clipboard.is_empty() {
+            self.clipboard_command(Event::Paste(self.clipboard.clone()));
+        }
+    }
+
+    fn clipboard_command(&mut self, event: Event) {
+        self.modifiers = Modifiers::COMMAND;
+        self.queued.push(event);
+        self.step();
+        self.modifiers = Modifiers::NONE;
+        self.step();
+    }
+
+    /// 向拥有键盘焦点的控件输入文本
+    pub fn type_text(&mut self, text: &str) {
+        self.queued.push(Event::Text(text.to_owned()));
+        self.step();
+    }
+
+    /// 在上一帧绘制的文本中查找与 `text` 完全相同的一段，返回其屏幕矩形
+    pub fn find_text(&self, text: &str) -> Option<Rect> {
+        self.shapes.iter().find_map(|clipped| find_text_in(&clipped.shape, text))
+    }
+
+    /// 上一帧绘制的所有与 `text` 完全相同的文本的屏幕矩形，按绘制顺序排列
+    pub fn find_all_text(&self, text: &str) -> Vec<Rect> {
+        let mut found = Vec::new();
+        for clipped in &self.shapes {
+            collect_text_in(&clipped.shape, text, &mut found);
+        }
+        found
+    }
+
+    /// 上一帧提交绘制的图形数（`Shape::Vec` 展开计数），用于检查每帧的绘制开销
+    pub fn shape_count(&self) -> usize {
+        self.shapes.iter().map(|clipped| count_shapes(&clipped.shape)).sum()
+    }
+
+    /// 上一帧的某个 `Shape::Mesh` 是否有三角形覆盖 `pos`，用于检查合批绘制的音符
+    pub fn mesh_covers(&self, pos: Pos2) -> bool {
+        self.shapes.iter().any(|clipped| mesh_covers_in(&clipped.shape, pos))
+    }
+
+    /// 单击上一帧中显示 `text` 的控件（例如菜单项）
+    ///
+    /// # Panics
+    ///
+    /// 上一帧没有绘制该文本时 panic。
+    pub fn click_text(&mut self, text: &str) {
+        let rect = self
+            .find_text(text)
+            .unwrap_or_else(|| panic!("text {text:?} was not drawn in the last frame"));
+        self.click(rect.center(), Modifiers::NONE);
+    }
+
+    fn queue_move(&mut self, pos: Pos2) {
+        self.queued.push(Event::PointerMoved(pos));
+    }
+}
+
+fn find_text_in(shape: &Shape, text: &str) -> Option<Rect> {
+    match shape {
+        Shape::Text(text_shape) if text_shape.galley.text() == text => {
+            Some(text_shape.galley.rect.translate(text_shape.pos.to_vec2()))
+        }
+        Shape::Vec(shapes) => shapes.iter().find_map(|shape| find_text_in(shape, text)),
+        _ => None,
+    }
+}
+
+fn mesh_covers_in(shape: &Shape, pos: Pos2) -> bool {
+    match shape {
+        Shape::Mesh(mesh) => mesh.indices.chunks_exact(3).any(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].pos);
+            let side = |p: Pos2, q: Pos2| (q - p).x * (pos - p).y - (q - p).y * (pos - p).x;
+            let (d1, d2, d3) = (side(a, b), side(b, c), side(c, a));
+            !((d1 < 0.0 || d2 < 0.0 || d3 < 0.0) && (d1 > 0.0 || d2 > 0.0 || d3 > 0.0))
+        }),
+        Shape::Vec(shapes) => shapes.iter().any(|shape| mesh_covers_in(shape, pos)),
+        _ => false,
+    }
+}
+
+fn count_shapes(shape: &Shape) -> usize {
+    match shape {
+        Shape::Vec(shapes) => shapes.iter().map(count_shapes).sum(),
+        _ => 1,
+    }
+}
+
+fn collect_text_in(shape: &Shape, text: &str, found: &mut Vec<Rect>) {
+    match shape {
+        Shape::Text(text_shape) if text_shape.galley.text() == text => {
+            found.push(text_shape.galley.rect.translate(text_shape.pos.to_vec2()));
+        }
+        Shape::Vec(shapes) => shapes.iter().for_each(|shape| collect_text_in(shape, text, found)),
+        _ => {}
+    }
+}
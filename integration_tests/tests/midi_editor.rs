@@ -0,0 +1,1223 @@
+use egui::{Color32, Key, KeyboardShortcut, Modifiers, PointerButton, Visuals};
+use egui_midi::audio::PlaybackBackend;
+use egui_midi::demo::{self, DemoKind, STRESS_NOTE_COUNT};
+use egui_midi::editor::{CurveTool, ClipboardPayload, DrumMap, EditorCommand, EditorEvent, LowerLaneView, MidiEditorOptions, MidiEditorTheme, NoteDoubleClickAction, NoteTool, ScaleKind, ShortcutAction, ShortcutMap};
+use egui_midi::structure::{MidiState, MidiTrack, Note};
+use egui_midi::ui::{KeyPreviewState, MidiEditor};
+use integration_tests::Harness;
+use std::sync::{Arc, Mutex};
+
+fn harness_with_notes(notes: Vec<Note>) -> Harness<MidiEditor> {
+    let state = MidiState {
+        notes,
+        ..MidiState::default()
+    };
+    Harness::new(MidiEditor::with_state(state, None))
+}
+
+fn note_pos(harness: &Harness<MidiEditor>, tick: u64, key: u8) -> egui::Pos2 {
+    harness.editor.tick_key_to_screen(tick, key).expect("piano roll laid out")
+}
+
+#[test]
+fn shift_drag_creates_note_spanning_the_drag() {
+    let mut harness = harness_with_notes(Vec::new());
+    let from = note_pos(&harness, 480, 64);
+    let to = note_pos(&harness, 1440, 64);
+    harness.drag(from, to, Modifiers::SHIFT);
+
+    assert_eq!(harness.editor.state.notes.len(), 1);
+    let note = harness.editor.state.notes[0];
+    assert_eq!((note.start, note.duration, note.key), (480, 960, 64));
+    let events = harness.editor.take_events();
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteAdded(n) if n.id == note.id)));
+}
+
+#[test]
+fn box_select_picks_intersecting_notes() {
+    let a = Note::new(0, 480, 64, 100);
+    let b = Note::new(480, 480, 62, 100);
+    let c = Note::new(1920, 480, 70, 100);
+    let mut harness = harness_with_notes(vec![a, b, c]);
+
+    let from = note_pos(&harness, 240, 66);
+    let to = note_pos(&harness, 1200, 61);
+    harness.drag(from, to, Modifiers::NONE);
+
+    let selected: Vec<_> = harness.editor.selected_notes.iter().copied().collect();
+    assert_eq!(selected.len(), 2);
+    assert!(selected.contains(&a.id) && selected.contains(&b.id));
+}
+
+#[test]
+fn dragging_a_selected_note_moves_the_whole_selection() {
+    let a = Note::new(0, 480, 64, 100);
+    let b = Note::new(960, 480, 60, 100);
+    let mut harness = harness_with_notes(vec![a, b]);
+    harness.editor.selected_notes.extend([a.id, b.id]);
+
+    let from = note_pos(&harness, 240, 64);
+    let to = note_pos(&harness, 720, 66);
+    harness.drag(from, to, Modifiers::NONE);
+
+    let moved = |id| *harness.editor.state.notes.iter().find(|n| n.id == id).unwrap();
+    assert_eq!((moved(a.id).start, moved(a.id).key), (480, 66));
+    assert_eq!((moved(b.id).start, moved(b.id).key), (1440, 62));
+}
+
+#[test]
+fn velocity_bars_drag_the_selection_relatively_in_one_undo_step() {
+    let a = Note::new(0, 480, 60, 100);
+    let b = Note::new(960, 480, 62, 80);
+    let c = Note::new(1920, 480, 64, 90);
+    let mut harness = harness_with_notes(vec![a, b, c]);
+    harness.editor.lower_lane_view = LowerLaneView::NoteVelocity;
+    harness.editor.selected_notes.extend([a.id, b.id]);
+    harness.step();
+    harness.editor.take_events();
+    let velocity = |harness: &Harness<MidiEditor>, id| harness.editor.state.notes.iter().find(|n| n.id == id).unwrap().velocity;
+
+    let from = harness.editor.velocity_lane_to_screen(0, 100).expect("velocity lane laid out");
+    let to = harness.editor.velocity_lane_to_screen(0, 60).unwrap();
+    harness.drag(from, to, Modifiers::NONE);
+    let (va, vb) = (velocity(&harness, a.id), velocity(&harness, b.id));
+    assert!((58..=62).contains(&va), "anchor follows the pointer, got {va}");
+    assert_eq!(vb as i32 - 80, va as i32 - 100, "selected notes keep their offset");
+    assert_eq!(velocity(&harness, c.id), 90);
+    let updates = harness.editor.take_events().iter().filter(|e| matches!(e, EditorEvent::NoteUpdated { .. })).count();
+    assert_eq!(updates, 2);
+
+    harness.editor.undo();
+    assert_eq!((velocity(&harness, a.id), velocity(&harness, b.id)), (100, 80));
+
+    // 拖动未选中音符的柱子时只改它自己，并使它成为选区
+    let from = harness.editor.velocity_lane_to_screen(1920, 90).unwrap();
+    let to = harness.editor.velocity_lane_to_screen(1920, 127).unwrap();
+    harness.drag(from, to, Modifiers::NONE);
+    assert_eq!(velocity(&harness, c.id), 127);
+    assert_eq!(velocity(&harness, a.id), 100);
+    assert!(harness.editor.selected_notes.contains(&c.id) && harness.editor.selected_notes.len() == 1);
+}
+
+#[test]
+fn pencil_and_line_tools_draw_curve_points_in_one_undo_step_each() {
+    let mut harness = harness_with_notes(vec![Note::new(0, 1920, 60, 100)]);
+    harness.step();
+    harness.editor.take_events();
+    let lane_id = harness.editor.state.curves[0].id;
+    let lane = |harness: &Harness<MidiEditor>| harness.editor.state.curves.iter().find(|c| c.id == lane_id).unwrap().clone();
+    let before = lane(&harness).points.len();
+
+    harness.editor.curve_tool = CurveTool::Pencil;
+    let from = harness.editor.curve_lane_to_screen(0, 20.0).expect("curve lane laid out");
+    let to = harness.editor.curve_lane_to_screen(1920, 100.0).unwrap();
+    harness.drag(from, to, Modifiers::NONE);
+    let drawn = lane(&harness);
+    assert!(drawn.points.len() >= before + 2);
+    assert!((drawn.value_at(0).unwrap() - 20.0).abs() < 3.0);
+    assert!((drawn.value_at(1920).unwrap() - 100.0).abs() < 3.0);
+    assert!((drawn.value_at(960).unwrap() - 60.0).abs() < 4.0);
+    // 共线的中间点在松开时被清理，事件在松开时成批发出
+    assert!(drawn.points.len() < before + 8, "got {} points", drawn.points.len());
+    let added = harness
+        .editor
+        .take_events()
+        .iter()
+        .filter(|e| matches!(e, EditorEvent::CurvePointAdded { lane_id: id, point_id } if *id == lane_id && drawn.points.iter().any(|p| p.id == *point_id)))
+        .count();
+    assert_eq!(added, drawn.points.len() - before);
+
+    harness.editor.curve_tool = CurveTool::Line;
+    let from = harness.editor.curve_lane_to_screen(480, 120.0).unwrap();
+    let to = harness.editor.curve_lane_to_screen(1440, 0.0).unwrap();
+    harness.drag(from, to, Modifiers::NONE);
+    let ramp = lane(&harness);
+    let inside: Vec<_> = ramp.points.iter().filter(|p| (480..=1440).contains(&p.tick)).collect();
+    assert_eq!(inside.len(), 2);
+    assert!((ramp.value_at(960).unwrap() - 60.0).abs() < 3.0);
+    assert!(harness.editor.take_events().iter().any(|e| matches!(e, EditorEvent::CurvePointRemoved { .. })));
+
+    harness.editor.undo();
+    assert_eq!(lane(&harness).points.len(), drawn.points.len());
+    harness.editor.undo();
+    assert_eq!(lane(&harness).points.len(), before);
+}
+
+#[test]
+fn zoom_shortcuts_bring_all_notes_or_the_selection_into_view() {
+    let low = Note::new(19200, 480, 30, 100);
+    let high = Note::new(38400, 960, 96, 100);
+    let middle = Note::new(24000, 240, 64, 100);
+    let mut harness = harness_with_notes(vec![low, high, middle]);
+    harness.step();
+    let visible = |harness: &Harness<MidiEditor>, note: &Note| {
+        let rect = harness.editor.piano_roll_rect().unwrap();
+        [note.start, note.start + note.duration]
+            .iter()
+            .all(|tick| rect.expand(1.0).contains(harness.editor.tick_key_to_screen(*tick, note.key).unwrap()))
+    };
+    assert!(!visible(&harness, &high));
+
+    harness.key(Key::F, Modifiers::NONE);
+    assert!([low, high, middle].iter().all(|note| visible(&harness, note)));
+    let fit_zoom = (harness.editor.zoom_x, harness.editor.zoom_y);
+
+    harness.editor.selected_notes.insert(middle.id);
+    harness.key(Key::F, Modifiers::SHIFT);
+    assert!(visible(&harness, &middle));
+    assert!(harness.editor.zoom_x > fit_zoom.0 && harness.editor.zoom_y > fit_zoom.1);
+    assert!(!visible(&harness, &high));
+
+    harness.editor.apply_command(EditorCommand::ZoomToFit);
+    harness.step();
+    assert!(visible(&harness, &low) && visible(&harness, &high));
+}
+
+#[test]
+fn minimap_click_scrolls_and_edge_drag_zooms() {
+    let options = MidiEditorOptions { show_minimap: true, ..MidiEditorOptions::default() };
+    let state = MidiState { notes: vec![Note::new(0, 480, 60, 100), Note::new(38400, 480, 72, 100)], ..MidiState::default() };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(state, None, options));
+    harness.step();
+    let view_width = harness.editor.piano_roll_rect().unwrap().width() - 60.0;
+    let visible_tick = |harness: &Harness<MidiEditor>, x: f32| {
+        let origin = harness.editor.tick_key_to_screen(0, 60).unwrap().x;
+        (x - origin) / harness.editor.zoom_x * 480.0
+    };
+
+    let target = harness.editor.minimap_tick_to_screen(28800).expect("minimap laid out");
+    harness.click(target, Modifiers::NONE);
+    let roll = harness.editor.piano_roll_rect().unwrap();
+    let center = visible_tick(&harness, roll.min.x + 60.0 + view_width / 2.0);
+    assert!((center - 28800.0).abs() < 480.0, "viewport centered at {center}");
+
+    // 拖动视口右边缘：左边缘不动，可见范围变宽（缩小）
+    let zoom = harness.editor.zoom_x;
+    let start = visible_tick(&harness, roll.min.x + 60.0);
+    let end = visible_tick(&harness, roll.min.x + 60.0 + view_width);
+    let edge = harness.editor.minimap_tick_to_screen(end as u64).unwrap();
+    let wider = harness.editor.minimap_tick_to_screen(end as u64 + 9600).unwrap();
+    harness.drag(edge, wider, Modifiers::NONE);
+    assert!(harness.editor.zoom_x < zoom);
+    assert!((visible_tick(&harness, roll.min.x + 60.0) - start).abs() < 120.0);
+}
+
+#[test]
+fn drum_map_compresses_rows_and_drags_without_resizing() {
+    let kick = Note::new(480, 960, 36, 100);
+    let drum_map = DrumMap { hide_unmapped: true, ..DrumMap::new([(36, "Kick"), (38, "Snare"), (42, "Closed Hi-Hat")]) };
+    let options = MidiEditorOptions { drum_map: Some(drum_map), ..MidiEditorOptions::default() };
+    let state = MidiState { notes: vec![kick], ..MidiState::default() };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(state, None, options));
+    harness.step();
+
+    // 只保留有名称的三行：36 与 38 相邻，37 没有行
+    let zoom_y = harness.editor.zoom_y;
+    assert_eq!(note_pos(&harness, 0, 36).y - note_pos(&harness, 0, 38).y, zoom_y);
+    assert_eq!(note_pos(&harness, 0, 38).y - note_pos(&harness, 0, 42).y, zoom_y);
+    assert!(harness.editor.tick_key_to_screen(0, 37).is_none());
+
+    // 菱形以起点为中心；拖动它的右缘只移动音符，不改变时值
+    let right_edge = note_pos(&harness, 480, 36) + egui::vec2(zoom_y * 0.5 - 2.0, 0.0);
+    harness.drag(right_edge, right_edge + egui::vec2(harness.editor.zoom_x, 0.0), Modifiers::NONE);
+    let note = harness.editor.state.notes[0];
+    assert_eq!((note.start, note.duration, note.key), (960, 960, 36));
+
+    // 垂直拖动按可见行移动：上移一行从 Kick 到 Snare
+    harness.drag(note_pos(&harness, 960, 36), note_pos(&harness, 960, 38), Modifiers::NONE);
+    assert_eq!(harness.editor.state.notes[0].key, 38);
+
+    // 切换回普通卷帘不改动音符
+    let notes = harness.editor.state.notes.clone();
+    harness.editor.drum_map = None;
+    harness.step();
+    assert_eq!(harness.editor.state.notes, notes);
+    assert!(harness.editor.tick_key_to_screen(0, 37).is_some());
+}
+
+#[test]
+fn theme_follows_the_host_visuals_and_accepts_overrides() {
+    let mut harness = harness_with_notes(vec![Note::new(0, 480, 60, 100)]);
+    assert_eq!(harness.editor.current_theme(), MidiEditorTheme::dark());
+
+    harness.ctx.set_visuals(Visuals::light());
+    harness.step();
+    let light = MidiEditorTheme::from_visuals(&Visuals::light());
+    assert_eq!(harness.editor.current_theme(), light);
+
+    // 只覆盖音符颜色，其余沿用推导出的浅色主题
+    let note = Color32::from_rgb(200, 60, 160);
+    let options = MidiEditorOptions { theme: Some(MidiEditorTheme { note, ..light }), ..MidiEditorOptions::default() };
+    harness.editor.apply_options(&options);
+    harness.step();
+    assert_eq!(harness.editor.current_theme(), MidiEditorTheme { note, ..light });
+}
+
+#[test]
+fn snap_to_scale_moves_the_selection_by_scale_degrees() {
+    let anchor = Note::new(0, 480, 64, 100);
+    let tonic = Note::new(960, 480, 60, 100);
+    let chromatic = Note::new(1920, 480, 66, 100);
+    let options = MidiEditorOptions {
+        scale_highlight: Some((0, ScaleKind::Major)),
+        snap_to_scale: true,
+        ..MidiEditorOptions::default()
+    };
+    let state = MidiState { notes: vec![anchor, tonic, chromatic], ..MidiState::default() };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(state, None, options));
+    harness.editor.selected_notes.extend([anchor.id, tonic.id, chromatic.id]);
+    let key_of = |harness: &Harness<MidiEditor>, id| harness.editor.state.notes.iter().find(|n| n.id == id).unwrap().key;
+
+    // E → G 是上移两级：C → E，F#（不在 C 大调内）按 F → A 移动并保留高出的半音
+    harness.drag(note_pos(&harness, 240, 64), note_pos(&harness, 240, 67), Modifiers::NONE);
+    assert_eq!(key_of(&harness, anchor.id), 67);
+    assert_eq!(key_of(&harness, tonic.id), 64);
+    assert_eq!(key_of(&harness, chromatic.id), 70);
+
+    // G# 与 G、A 等距时取较低的 G，锚点不动
+    harness.drag(note_pos(&harness, 240, 67), note_pos(&harness, 240, 68), Modifiers::NONE);
+    assert_eq!(key_of(&harness, anchor.id), 67);
+
+    // 按住 Alt 按半音移动
+    harness.drag(note_pos(&harness, 240, 67), note_pos(&harness, 240, 68), Modifiers::ALT);
+    assert_eq!(key_of(&harness, anchor.id), 68);
+    assert_eq!(key_of(&harness, tonic.id), 65);
+    assert_eq!(key_of(&harness, chromatic.id), 71);
+}
+
+#[test]
+fn ghost_notes_are_drawn_behind_but_never_selected_or_dragged() {
+    let melody = Note::new(1920, 480, 72, 100);
+    let mut harness = harness_with_notes(vec![melody]);
+    // 幽灵内容使用不同的 TPB：960 tick 即一拍
+    let chord = Note::new(0, 3840, 60, 90);
+    harness.editor.set_ghost_state(Some(MidiState {
+        notes: vec![chord, Note::new(0, 3840, 64, 90)],
+        ticks_per_beat: 960,
+        ..MidiState::default()
+    }));
+    harness.step();
+
+    // 框选覆盖幽灵和弦与旋律，只选中旋律
+    harness.drag(note_pos(&harness, 240, 74), note_pos(&harness, 2640, 58), Modifiers::NONE);
+    assert_eq!(harness.editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![melody.id]);
+
+    // 从幽灵音符上开始拖动只是框选空白区域
+    harness.drag(note_pos(&harness, 480, 60), note_pos(&harness, 1440, 62), Modifiers::NONE);
+    assert!(harness.editor.selected_notes.is_empty());
+    assert_eq!(harness.editor.ghost_notes()[0], chord);
+    assert_eq!(harness.editor.state.notes, vec![melody]);
+
+    harness.editor.set_ghost_state(None);
+    assert!(harness.editor.ghost_notes().is_empty());
+}
+
+#[test]
+fn clicking_the_timeline_seeks_the_playhead() {
+    let mut harness = harness_with_notes(Vec::new());
+    let pos = harness.editor.timeline_tick_to_screen(960).unwrap();
+    harness.click(pos, Modifiers::NONE);
+
+    assert_eq!(harness.editor.last_tick, 960);
+    assert!((harness.editor.current_time - 1.0).abs() < 1e-4);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, EditorEvent::TransportChanged { current_tick: 960, .. })));
+}
+
+#[test]
+fn step_input_enters_notes_from_the_sidebar_at_the_step_cursor() {
+    let mut harness = harness_with_notes(Vec::new());
+    harness.editor.set_snap_interval(480);
+    harness.editor.apply_command(EditorCommand::SetStepInput(true));
+    harness.step();
+
+    // 点击时间轴移动步进光标
+    let timeline = harness.editor.timeline_tick_to_screen(960).unwrap();
+    harness.click(timeline, Modifiers::NONE);
+    assert_eq!(harness.editor.step_cursor(), 960);
+
+    let key = harness.editor.piano_key_to_screen(60, 0.5).unwrap();
+    harness.click(key, Modifiers::NONE);
+    harness.key(Key::ArrowRight, Modifiers::NONE);
+    let key = harness.editor.piano_key_to_screen(62, 0.5).unwrap();
+    harness.click(key, Modifiers::NONE);
+
+    let notes: Vec<_> = harness.editor.state.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+    assert_eq!(notes, vec![(960, 480, 60), (1920, 480, 62)]);
+    assert_eq!(harness.editor.step_cursor(), 2400);
+
+    harness.key(Key::ArrowLeft, Modifiers::NONE);
+    assert_eq!(harness.editor.step_cursor(), 1920);
+}
+
+#[test]
+fn shift_drag_on_timeline_creates_and_moves_loop() {
+    let mut harness = harness_with_notes(Vec::new());
+    let from = harness.editor.timeline_tick_to_screen(960).unwrap();
+    let to = harness.editor.timeline_tick_to_screen(1440).unwrap();
+    harness.drag(from, to, Modifiers::SHIFT);
+
+    assert!(harness.editor.loop_enabled);
+    assert_eq!(harness.editor.loop_start_tick, 1440);
+    assert_eq!(harness.editor.loop_end_tick, 1440 + 1920);
+}
+
+#[test]
+fn timeline_markers_are_added_dragged_removed_and_seeked_to() {
+    let mut harness = harness_with_notes(Vec::new());
+    let markers = |h: &Harness<MidiEditor>| -> Vec<(u64, String)> {
+        h.editor.state.markers.iter().map(|m| (m.tick, m.label.clone())).collect()
+    };
+
+    // 双击时间轴添加标记
+    let at_960 = harness.editor.timeline_tick_to_screen(960).unwrap();
+    harness.double_click(at_960);
+    assert_eq!(markers(&harness), vec![(960, "Marker 1".to_string())]);
+    let marker_id = harness.editor.state.markers[0].id;
+    assert!(harness.editor.take_events().iter().any(|e| matches!(e, EditorEvent::MarkerAdded { marker_id: id } if *id == marker_id)));
+
+    // 拖动标记按吸附移动，不移动播放头
+    let at_1920 = harness.editor.timeline_tick_to_screen(1920).unwrap();
+    harness.drag(at_960, at_1920, Modifiers::NONE);
+    assert_eq!(markers(&harness), vec![(1920, "Marker 1".to_string())]);
+    assert_eq!(harness.editor.last_tick, 960);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, EditorEvent::MarkerMoved { marker_id: id, tick: 1920 } if *id == marker_id)));
+
+    // 命令添加标记，Shift+PageUp/PageDown 在标记之间跳转
+    harness.editor.apply_command(EditorCommand::AddMarker { tick: 3840, label: "Bridge".into(), color: None });
+    harness.key(Key::Home, Modifiers::NONE);
+    let seek = |h: &mut Harness<MidiEditor>, key: Key| {
+        h.editor.take_events();
+        h.key(key, Modifiers::SHIFT);
+        h.editor.take_events().iter().rev().find_map(|e| match e {
+            EditorEvent::TransportChanged { current_tick, .. } => Some(*current_tick),
+            _ => None,
+        })
+    };
+    assert_eq!(seek(&mut harness, Key::PageDown), Some(1920));
+    assert_eq!(seek(&mut harness, Key::PageDown), Some(3840));
+    assert_eq!(seek(&mut harness, Key::PageDown), None);
+    assert_eq!(seek(&mut harness, Key::PageUp), Some(1920));
+
+    // 右键删除标记；撤销依次恢复
+    harness.editor.take_events();
+    harness.secondary_click(at_1920, Modifiers::NONE);
+    assert_eq!(markers(&harness), vec![(3840, "Bridge".to_string())]);
+    assert!(harness.editor.take_events().iter().any(|e| matches!(e, EditorEvent::MarkerRemoved { marker_id: id } if *id == marker_id)));
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(markers(&harness).len(), 2);
+    harness.key(Key::Z, Modifiers::COMMAND);
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(markers(&harness), vec![(960, "Marker 1".to_string())]);
+}
+
+#[test]
+fn double_clicking_a_note_runs_the_configured_action() {
+    let note = Note::new(480, 480, 64, 100);
+    let mut harness = harness_with_notes(vec![note]);
+    let pos = note_pos(&harness, 720, 64);
+    let set_action = |h: &mut Harness<MidiEditor>, action: NoteDoubleClickAction| {
+        h.editor.apply_options(&MidiEditorOptions { note_double_click: action, ..MidiEditorOptions::default() });
+        h.editor.take_events();
+        // 连续的双击之间留出间隔，免得被当作三击
+        h.steps(60);
+        note_pos(h, 720, 64)
+    };
+
+    let baseline = harness.find_all_text("Velocity").len();
+    let popup_open = |h: &Harness<MidiEditor>| h.find_all_text("Velocity").len() > baseline;
+    let double_clicked = |h: &mut Harness<MidiEditor>| {
+        h.editor.take_events().iter().any(|e| matches!(e, EditorEvent::NoteDoubleClicked(id) if *id == note.id))
+    };
+
+    // 默认只发出事件
+    harness.double_click(pos);
+    assert!(double_clicked(&mut harness));
+    assert!(!popup_open(&harness));
+
+    // 属性小窗：Escape 放弃修改
+    let pos = set_action(&mut harness, NoteDoubleClickAction::Properties);
+    harness.double_click(pos);
+    assert!(popup_open(&harness));
+    harness.key(Key::Escape, Modifiers::NONE);
+    assert!(!popup_open(&harness));
+    assert_eq!(harness.editor.state.notes[0], note);
+
+    // Tab 到力度字段输入新值，Enter 提交为一步撤销
+    harness.steps(60);
+    harness.double_click(pos);
+    for _ in 0..3 {
+        harness.key(Key::Tab, Modifiers::NONE);
+    }
+    harness.key(Key::A, Modifiers::COMMAND);
+    harness.type_text("64");
+    harness.key(Key::Enter, Modifiers::NONE);
+    assert!(!popup_open(&harness));
+    assert_eq!(harness.editor.state.notes[0].velocity, 64);
+    assert!(harness.editor.take_events().iter().any(|e| matches!(e, EditorEvent::NoteUpdated { .. })));
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes[0].velocity, 100);
+
+    // 删除
+    let pos = set_action(&mut harness, NoteDoubleClickAction::Delete);
+    harness.double_click(pos);
+    assert!(harness.editor.state.notes.is_empty());
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes, vec![note]);
+}
+
+#[test]
+fn context_menu_quantizes_selected_notes() {
+    let note = Note::new(500, 480, 64, 100);
+    let mut harness = harness_with_notes(vec![note]);
+    harness.editor.selected_notes.insert(note.id);
+
+    let pos = note_pos(&harness, 740, 64);
+    harness.secondary_click(pos, Modifiers::NONE);
+    assert!(harness.editor.context_menu_pos.is_some());
+    harness.click_text("Quantize to snap grid");
+
+    assert_eq!(harness.editor.state.notes[0].start, 480);
+    assert!(harness.editor.context_menu_pos.is_none());
+}
+
+#[test]
+fn ctrl_z_undoes_and_ctrl_shift_z_redoes_note_creation() {
+    let mut harness = harness_with_notes(Vec::new());
+    let from = note_pos(&harness, 480, 60);
+    let to = note_pos(&harness, 960, 60);
+    harness.drag(from, to, Modifiers::SHIFT);
+    assert_eq!(harness.editor.state.notes.len(), 1);
+
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert!(harness.editor.state.notes.is_empty());
+
+    harness.key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+    assert_eq!(harness.editor.state.notes.len(), 1);
+}
+
+#[test]
+fn transport_keys_seek_by_bar_and_beat_and_skip_focused_text_fields() {
+    let state = MidiState {
+        time_signature: (3, 4),
+        notes: vec![Note::new(0, 480, 60, 100), Note::new(1920, 500, 64, 100)],
+        ..MidiState::default()
+    };
+    let mut harness = Harness::new(MidiEditor::with_state(state, None));
+    let seek = |h: &mut Harness<MidiEditor>, key: Key| {
+        h.editor.take_events();
+        h.key(key, Modifiers::NONE);
+        h.editor.take_events().iter().rev().find_map(|e| match e {
+            EditorEvent::TransportChanged { current_tick, .. } => Some(*current_tick),
+            _ => None,
+        })
+    };
+
+    assert_eq!(seek(&mut harness, Key::End), Some(2420));
+    assert_eq!(seek(&mut harness, Key::PageUp), Some(1440));
+    assert_eq!(seek(&mut harness, Key::PageUp), Some(0));
+    assert_eq!(seek(&mut harness, Key::PageDown), Some(1440));
+    assert_eq!(seek(&mut harness, Key::Period), Some(1920));
+    assert_eq!(seek(&mut harness, Key::Comma), Some(1440));
+    assert_eq!(seek(&mut harness, Key::Comma), Some(960));
+    assert_eq!(seek(&mut harness, Key::Home), Some(0));
+
+    // 宿主可以改绑；文本框获得焦点时不触发
+    let mut shortcuts = ShortcutMap::default();
+    shortcuts.unbind(ShortcutAction::SeekToEnd);
+    harness.editor.apply_options(&MidiEditorOptions { shortcuts, ..MidiEditorOptions::default() });
+    assert_eq!(seek(&mut harness, Key::End), None);
+    harness.editor.apply_options(&MidiEditorOptions::default());
+    harness.ctx.memory_mut(|m| m.request_focus(egui::Id::new("host text field")));
+    assert_eq!(seek(&mut harness, Key::PageDown), None);
+}
+
+#[test]
+fn loop_defaults_to_one_measure_and_ctrl_shift_arrows_nudge_it() {
+    let state = MidiState {
+        time_signature: (3, 4),
+        ..MidiState::default()
+    };
+    let options = MidiEditorOptions {
+        loop_enabled: true,
+        ..MidiEditorOptions::default()
+    };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(state, None, options));
+    let loop_range = |h: &Harness<MidiEditor>| (h.editor.loop_start_tick, h.editor.loop_end_tick);
+    assert_eq!(loop_range(&harness), (0, 1440));
+
+    let nudge = Modifiers::COMMAND | Modifiers::SHIFT;
+    harness.key(Key::ArrowRight, nudge);
+    assert_eq!(loop_range(&harness), (1440, 2880));
+    assert!(harness.editor.take_events().iter().any(|e| matches!(
+        e,
+        EditorEvent::TransportChanged { loop_start_tick: 1440, loop_end_tick: 2880, .. }
+    )));
+
+    harness.key(Key::ArrowUp, nudge);
+    assert_eq!(loop_range(&harness), (1440, 4320));
+    harness.key(Key::ArrowLeft, nudge);
+    assert_eq!(loop_range(&harness), (0, 2880));
+    harness.key(Key::ArrowDown, nudge);
+    assert_eq!(loop_range(&harness), (0, 1440));
+
+    // 已在 0 处时不再前移，也不发出事件
+    harness.editor.take_events();
+    harness.key(Key::ArrowLeft, nudge);
+    assert_eq!(loop_range(&harness), (0, 1440));
+    assert!(!harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, EditorEvent::TransportChanged { .. })));
+
+    harness.editor.apply_command(EditorCommand::ScaleLoop { double: false });
+    assert_eq!(loop_range(&harness), (0, 720));
+}
+
+#[test]
+fn hovering_a_note_shows_tooltip_only_when_enabled() {
+    let note = Note::new(480, 360, 64, 100);
+    let state = MidiState {
+        notes: vec![note],
+        ..MidiState::default()
+    };
+    let expected = egui_midi::tooltip::note_tooltip(&note, &state, Default::default());
+
+    let mut harness = Harness::new(MidiEditor::with_state(state.clone(), None));
+    harness.hover(note_pos(&harness, 600, 64));
+    harness.steps(2);
+    assert!(harness.find_text(&expected).is_none());
+
+    let options = MidiEditorOptions {
+        hover_tooltip_delay: Some(0.0),
+        ..MidiEditorOptions::default()
+    };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(state, None, options));
+    harness.hover(note_pos(&harness, 600, 64));
+    harness.steps(2);
+    assert!(harness.find_text(&expected).is_some());
+}
+
+#[derive(Default)]
+struct RecordingPlayback {
+    sent: Mutex<Vec<(u8, Option<u8>)>>,
+}
+
+impl PlaybackBackend for RecordingPlayback {
+    fn note_on(&self, key: u8, velocity: u8) {
+        self.sent.lock().unwrap().push((key, Some(velocity)));
+    }
+    fn note_off(&self, key: u8) {
+        self.sent.lock().unwrap().push((key, None));
+    }
+    fn all_notes_off(&self) {}
+    fn set_volume(&self, _volume: f32) {}
+    fn set_pitch_shift(&self, _semitones: f32) {}
+}
+
+#[test]
+fn losing_window_focus_releases_a_held_preview_key() {
+    let backend = Arc::new(RecordingPlayback::default());
+    let mut harness = Harness::new(MidiEditor::new(Some(backend.clone())));
+    let key = harness.editor.piano_key_to_screen(60, 0.5).unwrap();
+    harness.press(key, PointerButton::Primary, Modifiers::NONE);
+    harness.step();
+    assert!(matches!(harness.editor.key_preview, KeyPreviewState::Held { key: 60, .. }));
+
+    // 切到别的窗口后再也收不到松开事件
+    harness.set_window_focused(false);
+    assert_eq!(harness.editor.key_preview, KeyPreviewState::Idle);
+    assert_eq!(backend.sent.lock().unwrap().last(), Some(&(60, None)));
+}
+
+#[test]
+fn sidebar_key_velocity_follows_click_position_and_glides() {
+    let backend = Arc::new(RecordingPlayback::default());
+    let mut harness = Harness::new(MidiEditor::new(Some(backend.clone())));
+    let key_pos = |harness: &Harness<MidiEditor>, key, t| harness.editor.piano_key_to_screen(key, t).unwrap();
+
+    let soft = key_pos(&harness, 60, 0.0) + egui::vec2(1.0, 0.0);
+    harness.press(soft, PointerButton::Primary, Modifiers::NONE);
+    harness.step();
+    let KeyPreviewState::Held { key: 60, velocity } = harness.editor.key_preview else {
+        panic!("key 60 should be held, got {:?}", harness.editor.key_preview);
+    };
+    assert!(harness.find_text(&format!("Velocity {velocity}")).is_some());
+
+    // 纵向滑到相邻按键：先放开旧键再按下新键；移出键盘列时沿用之前的力度
+    harness.hover(key_pos(&harness, 62, 1.0));
+    let outside = harness.editor.tick_key_to_screen(960, 64).unwrap();
+    harness.hover(outside);
+    harness.release(outside, PointerButton::Primary);
+
+    let sent = backend.sent.lock().unwrap().clone();
+    let ons: Vec<_> = sent.iter().filter_map(|&(key, velocity)| velocity.map(|v| (key, v))).collect();
+    assert_eq!(ons.len(), 3);
+    assert_eq!(ons[0].0, 60);
+    assert!((30..=33).contains(&ons[0].1));
+    assert_eq!(ons[1], (62, 127));
+    assert_eq!(ons[2], (64, 127));
+    assert_eq!(sent.iter().filter(|(_, v)| v.is_none()).count(), 3);
+    assert_eq!(harness.editor.key_preview, KeyPreviewState::Idle);
+    assert!(harness.editor.state.notes.is_empty());
+}
+
+#[test]
+fn computer_keyboard_plays_held_keys_and_reports_live_input() {
+    let backend = Arc::new(RecordingPlayback::default());
+    let options = MidiEditorOptions {
+        computer_keyboard_input: true,
+        ..MidiEditorOptions::default()
+    };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(MidiState::default(), Some(backend.clone()), options));
+
+    // 同时按住 Z 与 E（C3 与 E4），自动重复不重新触发
+    harness.key_event(Key::Z, true, false);
+    harness.key_event(Key::E, true, false);
+    harness.key_event(Key::Z, true, true);
+    // 升八度后松开的仍是原来发声的音高
+    harness.key_event(Key::Equals, true, false);
+    harness.key_event(Key::Z, false, false);
+    harness.key_event(Key::Z, true, false);
+    harness.key_event(Key::E, false, false);
+    harness.key_event(Key::Z, false, false);
+    assert_eq!(harness.editor.computer_keyboard_base(), 60);
+
+    let sent = backend.sent.lock().unwrap().clone();
+    assert_eq!(sent, vec![(48, Some(100)), (64, Some(100)), (48, None), (60, Some(100)), (64, None), (60, None)]);
+    let live: Vec<_> = harness
+        .editor
+        .take_events()
+        .into_iter()
+        .filter_map(|e| match e {
+            EditorEvent::LiveNoteInput { key, pressed, .. } => Some((key, pressed)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(live, vec![(48, true), (64, true), (48, false), (60, true), (64, false), (60, false)]);
+
+    // Space 仍然切换播放，不被键盘弹奏吞掉
+    harness.key(Key::Space, Modifiers::NONE);
+    assert!(harness.editor.is_playing);
+    assert!(harness.editor.state.notes.is_empty());
+}
+
+#[test]
+fn idle_editor_requests_no_repaint() {
+    let options = MidiEditorOptions {
+        debug_repaint_counter: true,
+        ..MidiEditorOptions::default()
+    };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(MidiState::default(), None, options));
+    harness.hover(harness.editor.tick_key_to_screen(960, 60).unwrap());
+    harness.steps(3);
+    assert_eq!(harness.editor.repaint_request_count(), 0);
+    assert_eq!(harness.repaint_delay(), std::time::Duration::MAX);
+
+    harness.editor.apply_command(EditorCommand::SetPlayback(true));
+    harness.steps(3);
+    assert!(harness.editor.repaint_request_count() >= 3);
+    assert_eq!(harness.repaint_delay(), std::time::Duration::ZERO);
+
+    harness.editor.apply_command(EditorCommand::SetPlayback(false));
+    harness.steps(3);
+    let count = harness.editor.repaint_request_count();
+    harness.steps(3);
+    assert_eq!(harness.editor.repaint_request_count(), count);
+    assert_eq!(harness.repaint_delay(), std::time::Duration::MAX);
+}
+
+#[test]
+fn other_tracks_are_ghosts_until_selected_in_the_toolbar() {
+    let lead = Note::new(0, 480, 64, 100);
+    let bass = Note { track: 1, ..Note::new(960, 480, 64, 100) };
+    let state = MidiState {
+        notes: vec![lead, bass],
+        tracks: vec![MidiTrack::default(), MidiTrack { name: Some("Bass".to_string()), ..MidiTrack::default() }],
+        ..MidiState::default()
+    };
+    let mut harness = Harness::new(MidiEditor::with_state(state, None));
+
+    // 框选覆盖两个音符，只选中当前轨道的
+    harness.drag(note_pos(&harness, 240, 66), note_pos(&harness, 1200, 62), Modifiers::NONE);
+    assert_eq!(harness.editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![lead.id]);
+
+    harness.click_text("Track 1");
+    harness.step();
+    harness.click_text("Bass");
+    harness.step();
+    assert_eq!(harness.editor.active_track(), 1);
+    assert!(harness.editor.selected_notes.is_empty());
+
+    harness.click(note_pos(&harness, 1200, 64), Modifiers::NONE);
+    assert_eq!(harness.editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![bass.id]);
+}
+
+#[test]
+fn dense_demo_content_survives_bulk_delete_and_undo() {
+    let mut harness = Harness::new(MidiEditor::with_state(demo::generate(DemoKind::DenseStress), None));
+    harness.step();
+    harness.editor.selected_notes = harness.editor.state.notes.iter().map(|n| n.id).collect();
+    harness.key(Key::Delete, Modifiers::NONE);
+    assert!(harness.editor.state.notes.is_empty());
+
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes.len(), STRESS_NOTE_COUNT);
+}
+
+#[test]
+fn hundred_thousand_notes_paint_as_one_mesh_while_panning() {
+    // 每 60 tick 一个八音和弦，默认视图中可见数百个音符；与同样滚动的空编辑器比较图形数
+    let notes: Vec<Note> = (0..100_000u64).map(|i| Note::new(i / 8 * 60, 120, 40 + (i % 8) as u8 * 6, 100)).collect();
+    let mut harness = harness_with_notes(notes);
+    let mut empty = harness_with_notes(Vec::new());
+    // 网格在内容坐标中缓存并覆盖视口左右各一屏，平移不足一屏时不重建
+    let rebuilds = harness.editor.note_mesh_rebuild_count();
+    for _ in 0..20 {
+        for h in [&mut harness, &mut empty] {
+            h.editor.manual_scroll_x -= 40.0;
+            h.step();
+        }
+        assert!(harness.shape_count() <= empty.shape_count() + 1, "{} shapes", harness.shape_count());
+    }
+    assert_eq!(harness.editor.note_mesh_rebuild_count(), rebuilds);
+    let first = harness.editor.state.notes.iter().find(|n| n.start >= 20 * 480 && n.key == 40).unwrap().start;
+    assert!(harness.mesh_covers(harness.editor.tick_key_to_screen(first + 60, 40).unwrap()));
+
+    // 移出缓存范围后重建一次
+    harness.editor.manual_scroll_x -= 4000.0;
+    harness.step();
+    assert_eq!(harness.editor.note_mesh_rebuild_count(), rebuilds + 1);
+
+    // 选中的音符另外叠加一层
+    let first_visible_tick = (-harness.editor.manual_scroll_x / harness.editor.zoom_x * 480.0) as u64;
+    let visible = harness.editor.state.notes.iter().find(|n| n.start >= first_visible_tick + 480 && n.key == 64).map(|n| n.id).unwrap();
+    harness.editor.selected_notes.insert(visible);
+    harness.step();
+    empty.step();
+    assert_eq!(harness.shape_count(), empty.shape_count() + 3);
+}
+
+#[test]
+fn direct_state_edits_repaint_the_cached_note_mesh() {
+    let mut harness = harness_with_notes(vec![Note::new(480, 480, 60, 100)]);
+    let old = harness.editor.tick_key_to_screen(720, 60).unwrap();
+    let new = harness.editor.tick_key_to_screen(720, 62).unwrap();
+    assert!(harness.mesh_covers(old) && !harness.mesh_covers(new));
+
+    // 宿主直接改写公开的 state，不经过编辑器的编辑接口
+    harness.editor.state.notes[0].key = 62;
+    harness.step();
+    assert!(!harness.mesh_covers(old) && harness.mesh_covers(new));
+}
+
+#[test]
+fn shortcut_map_can_free_and_rebind_keys() {
+    let mut shortcuts = ShortcutMap::default();
+    shortcuts.unbind(ShortcutAction::Delete);
+    let options = MidiEditorOptions { shortcuts, ..MidiEditorOptions::default() };
+    let state = MidiState { notes: vec![Note::new(490, 470, 60, 100)], ..MidiState::default() };
+    let mut harness = Harness::new(MidiEditor::with_state_and_options(state, None, options));
+    harness.editor.selected_notes = harness.editor.state.notes.iter().map(|n| n.id).collect();
+
+    // Delete 已留给宿主，音符不受影响
+    harness.key(Key::Delete, Modifiers::NONE);
+    harness.key(Key::Backspace, Modifiers::NONE);
+    assert_eq!(harness.editor.state.notes.len(), 1);
+
+    // 运行时把未绑定的量化绑到 Q
+    harness.editor.shortcuts_mut().bind(ShortcutAction::Quantize, KeyboardShortcut::new(Modifiers::NONE, Key::Q));
+    harness.key(Key::Q, Modifiers::NONE);
+    assert_eq!(harness.editor.state.notes[0].start, 480);
+
+    // 修饰键需要完全匹配：Ctrl+Shift+Z 是重做而不是撤销
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes[0].start, 490);
+    harness.key(Key::Z, Modifiers::COMMAND | Modifiers::SHIFT);
+    assert_eq!(harness.editor.state.notes[0].start, 480);
+}
+
+#[test]
+fn arrow_keys_nudge_and_transpose_the_selection() {
+    let backend = Arc::new(RecordingPlayback::default());
+    let note = Note::new(480, 240, 60, 100);
+    let state = MidiState { notes: vec![note], ..MidiState::default() };
+    let mut harness = Harness::new(MidiEditor::with_state(state, Some(backend.clone())));
+    harness.editor.selected_notes.insert(note.id);
+    let span = |h: &Harness<MidiEditor>| (h.editor.state.notes[0].start, h.editor.state.notes[0].key);
+
+    harness.key(Key::ArrowRight, Modifiers::NONE);
+    assert_eq!(span(&harness), (600, 60));
+    harness.key(Key::ArrowLeft, Modifiers::ALT);
+    assert_eq!(span(&harness), (599, 60));
+    harness.key(Key::ArrowUp, Modifiers::SHIFT);
+    assert_eq!(span(&harness), (599, 72));
+    assert!(backend.sent.lock().unwrap().contains(&(72, Some(100))), "transposing auditions the new pitch");
+
+    // 按住向下键：自动重复继续移调，但整个手势只占一个撤销步骤
+    harness.key_event(Key::ArrowDown, true, false);
+    harness.key_event(Key::ArrowDown, true, true);
+    harness.key_event(Key::ArrowDown, true, true);
+    harness.key_event(Key::ArrowDown, false, false);
+    assert_eq!(span(&harness), (599, 69));
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(span(&harness), (599, 72));
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(span(&harness), (599, 60));
+}
+
+#[test]
+fn ctrl_alt_drag_moves_a_copy_and_leaves_the_original() {
+    let original = Note::new(480, 480, 60, 100);
+    let mut harness = harness_with_notes(vec![original]);
+    let duplicate = Modifiers::COMMAND | Modifiers::ALT;
+
+    // 没有移动的单击不会留下副本
+    harness.click(note_pos(&harness, 720, 60), duplicate);
+    assert_eq!(harness.editor.state.notes.len(), 1);
+
+    harness.editor.take_events();
+    harness.drag(note_pos(&harness, 720, 60), note_pos(&harness, 1680, 64), duplicate);
+    let notes = harness.editor.state.notes.clone();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(notes[0], original);
+    let copy = notes[1];
+    assert_eq!((copy.start, copy.key, copy.duration), (1440, 64, 480), "the copy still snaps while Alt is held");
+    assert_eq!(harness.editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![copy.id]);
+
+    let events = harness.editor.take_events();
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteAdded(n) if *n == copy)));
+    assert!(!events.iter().any(|e| matches!(e, EditorEvent::NoteUpdated { .. })));
+
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes, vec![original]);
+}
+
+#[test]
+fn clicking_a_note_does_not_mark_the_editor_dirty() {
+    let original = Note::new(480, 480, 60, 100);
+    let mut harness = harness_with_notes(vec![original]);
+    harness.editor.mark_saved();
+
+    harness.click(note_pos(&harness, 720, 60), Modifiers::NONE);
+    assert!(!harness.editor.is_dirty());
+    assert_eq!(harness.editor.undo_depth(), 0);
+
+    harness.drag(note_pos(&harness, 720, 60), note_pos(&harness, 1680, 60), Modifiers::NONE);
+    assert!(harness.editor.is_dirty());
+    let dirty: Vec<bool> = harness
+        .editor
+        .take_events()
+        .into_iter()
+        .filter_map(|e| match e {
+            EditorEvent::DirtyChanged(dirty) => Some(dirty),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(dirty, vec![true]);
+
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes, vec![original]);
+    assert!(!harness.editor.is_dirty());
+}
+
+fn last_view_change(editor: &mut MidiEditor) -> Option<(std::ops::Range<u64>, std::ops::RangeInclusive<u8>)> {
+    editor
+        .take_events()
+        .into_iter()
+        .rev()
+        .find_map(|e| match e {
+            EditorEvent::ViewChanged { visible_tick_range, visible_key_range } => {
+                Some((visible_tick_range, visible_key_range))
+            }
+            _ => None,
+        })
+}
+
+#[test]
+fn view_commands_scroll_and_report_the_visible_range() {
+    let mut harness = harness_with_notes(Vec::new());
+    harness.step();
+    assert!(last_view_change(&mut harness.editor).is_some(), "the first layout reports the view");
+
+    harness.editor.apply_command(EditorCommand::SetView { zoom_x: 100.0, zoom_y: 10.0, scroll_x: -400.0, scroll_y: -200.0 });
+    harness.step();
+    let (ticks, keys) = last_view_change(&mut harness.editor).expect("view changed");
+    assert_eq!(ticks.start, 1920);
+    assert_eq!(*keys.end(), 107);
+    assert_eq!(Some((ticks, keys)), harness.editor.visible_range());
+
+    harness.step();
+    assert!(last_view_change(&mut harness.editor).is_none(), "an unchanged view is not reported again");
+
+    harness.editor.apply_command(EditorCommand::ScrollToTick(4800));
+    harness.editor.apply_command(EditorCommand::ScrollToKey(30));
+    harness.step();
+    let (ticks, keys) = last_view_change(&mut harness.editor).expect("view changed");
+    assert_eq!(ticks.start, 4800);
+    assert_eq!(*keys.start(), 30, "scrolled just far enough to show the key at the bottom edge");
+
+    // 已经可见的音高不会引起滚动
+    harness.editor.apply_command(EditorCommand::ScrollToKey(40));
+    harness.step();
+    assert!(last_view_change(&mut harness.editor).is_none());
+}
+
+#[test]
+fn paste_here_pastes_at_the_right_clicked_tick_and_key() {
+    let original = Note::new(480, 480, 60, 100);
+    let mut harness = harness_with_notes(vec![original]);
+    harness.click(note_pos(&harness, 720, 60), Modifiers::NONE);
+    harness.key(Key::C, Modifiers::COMMAND);
+
+    harness.secondary_click(note_pos(&harness, 1950, 67), Modifiers::NONE);
+    harness.click_text("Paste here");
+    let notes: Vec<(u64, u8)> = harness.editor.state.notes.iter().map(|n| (n.start, n.key)).collect();
+    assert_eq!(notes, vec![(480, 60), (1920, 67)]);
+}
+
+#[test]
+fn notes_round_trip_through_the_system_clipboard_between_editors() {
+    let phrase = vec![Note::new(480, 240, 60, 100), Note::new(720, 720, 64, 90)];
+    let mut source = harness_with_notes(phrase.clone());
+    source.editor.selected_notes = phrase.iter().map(|n| n.id).collect();
+    source.copy();
+    let payload = ClipboardPayload::from_clipboard_text(&source.clipboard).expect("copy writes the system clipboard");
+    assert_eq!((payload.ticks_per_beat, payload.bpm), (480, Some(source.editor.state.bpm)));
+
+    // 目标编辑器的 TPB 是源的两倍：按拍换算，时值与位置在音乐上不变
+    let mut target = Harness::new(MidiEditor::with_state(
+        MidiState { ticks_per_beat: 960, ..MidiState::default() },
+        None,
+    ));
+    target.clipboard = source.clipboard.clone();
+    target.paste();
+    let pasted: Vec<(u64, u64, u8)> = target.editor.state.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+    assert_eq!(pasted, vec![(960, 480, 60), (1440, 1440, 64)]);
+
+    // 其他应用复制的普通文本不影响内部剪贴板
+    target.clipboard = "hello".to_owned();
+    target.editor.apply_command(EditorCommand::SeekSeconds(2.0));
+    target.paste();
+    assert_eq!(target.editor.state.notes.len(), 4);
+    assert_eq!(target.editor.state.notes[2].start, 3840);
+}
+
+/// 事件列表中显示 `text` 的单元格（表头 "#" 以下的第一个匹配），避免与时间轴上的小节号等文本混淆
+fn event_list_cell(harness: &Harness<MidiEditor>, text: &str) -> egui::Pos2 {
+    let header = harness.find_text("#").expect("event list is shown");
+    harness
+        .find_all_text(text)
+        .into_iter()
+        .find(|rect| rect.top() >= header.top())
+        .unwrap_or_else(|| panic!("{text:?} is not in the event list"))
+        .center()
+}
+
+#[test]
+fn event_list_sorts_selects_and_edits_notes_with_undo() {
+    // 五万个音符：列表只布局可见的行
+    let mut notes: Vec<Note> = (0..50_000u64).map(|i| Note::new(i * 120, 120, 48 + (i % 24) as u8, 100)).collect();
+    notes[3].velocity = 20;
+    let quiet = notes[3];
+    let far = Note::new(1920 * 100, 480, 90, 64);
+    notes.push(far);
+    let mut harness = harness_with_notes(notes);
+    harness.editor.show_event_list = true;
+    harness.step();
+    assert!(harness.find_text("Start ▲").is_some());
+    assert!(harness.find_all_text("50001").is_empty());
+
+    // 按力度排序后第一行是最轻的音符；单击行号选中它
+    harness.click(event_list_cell(&harness, "Velocity"), Modifiers::NONE);
+    harness.step();
+    assert!(harness.find_text("Velocity ▲").is_some());
+    harness.click(event_list_cell(&harness, "1"), Modifiers::NONE);
+    assert_eq!(harness.editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![quiet.id]);
+
+    // 再次单击同一列切换为降序；按音高降序时第一行是远处的 F#6，选中后钢琴卷帘滚动到它
+    harness.click(event_list_cell(&harness, "Pitch"), Modifiers::NONE);
+    harness.step();
+    harness.click_text("Pitch ▲");
+    harness.step();
+    assert!(harness.find_text("Pitch ▼").is_some());
+    assert!(harness.find_text("F#6").is_some());
+    harness.click(event_list_cell(&harness, "1"), Modifiers::NONE);
+    harness.step();
+    assert_eq!(harness.editor.selected_notes.iter().copied().collect::<Vec<_>>(), vec![far.id]);
+    let (ticks, keys) = harness.editor.visible_range().expect("piano roll laid out");
+    assert!(ticks.contains(&far.start) && keys.contains(&far.key), "{ticks:?} {keys:?}");
+
+    // 在单元格里输入 "小节.拍.tick" 修改起点，可以撤销
+    harness.click_text("101.1.000");
+    harness.step();
+    harness.key(Key::A, Modifiers::COMMAND);
+    harness.type_text("102.2.0");
+    harness.key(Key::Enter, Modifiers::NONE);
+    let moved = harness.editor.state.notes.iter().find(|n| n.id == far.id).unwrap();
+    assert_eq!(moved.start, 101 * 1920 + 480);
+    harness.editor.undo();
+    let restored = harness.editor.state.notes.iter().find(|n| n.id == far.id).unwrap();
+    assert_eq!(restored.start, far.start);
+}
+
+#[test]
+fn toolbar_and_transport_events_report_bar_beat_tick() {
+    let mut harness = harness_with_notes(vec![Note::new(480, 480, 64, 100)]);
+    harness.editor.apply_command(EditorCommand::SeekToTick(1920 * 4 + 480 + 120));
+    harness.step();
+    assert!(harness.find_text("5.2.120").is_some(), "toolbar shows the playhead position");
+    let position = harness.editor.take_events().into_iter().rev().find_map(|e| match e {
+        EditorEvent::TransportChanged { musical_time, .. } => Some(musical_time),
+        _ => None,
+    });
+    assert_eq!(position.map(|p| (p.bar, p.beat, p.tick)), Some((5, 2, 120)));
+}
+
+#[test]
+fn auto_trim_shortens_and_removes_notes_under_a_dragged_note() {
+    let earlier = Note::new(0, 480, 64, 100);
+    let covered = Note::new(300, 100, 64, 100);
+    let other_pitch = Note::new(240, 480, 65, 100);
+    let dragged = Note::new(960, 480, 64, 100);
+    let mut harness = harness_with_notes(vec![earlier, covered, other_pitch, dragged]);
+
+    // 默认关闭：拖到其他音符上只会叠在一起
+    harness.drag(note_pos(&harness, 1200, 64), note_pos(&harness, 480, 64), Modifiers::NONE);
+    assert_eq!(harness.editor.state.notes.len(), 4);
+    harness.editor.undo();
+
+    harness.editor.apply_options(&MidiEditorOptions { auto_trim_overlaps: true, ..MidiEditorOptions::default() });
+    harness.editor.take_events();
+    harness.drag(note_pos(&harness, 1200, 64), note_pos(&harness, 480, 64), Modifiers::NONE);
+    let notes: Vec<(u64, u64, u8)> = harness.editor.state.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+    assert_eq!(notes, vec![(0, 240, 64), (240, 480, 65), (240, 480, 64)]);
+    let events = harness.editor.take_events();
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteUpdated { before, .. } if before.id == earlier.id)));
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteDeleted(note) if note.id == covered.id)));
+
+    // 修剪与拖动是同一步撤销
+    harness.editor.undo();
+    let mut restored = harness.editor.state.notes.clone();
+    restored.sort_by_key(|n| n.id);
+    assert_eq!(restored, vec![earlier, covered, other_pitch, dragged]);
+}
+
+#[test]
+fn draw_and_erase_tools_stamp_and_remove_notes_in_one_undo_step() {
+    let existing = Note::new(240, 120, 60, 100);
+    let other_pitch = Note::new(0, 960, 64, 100);
+    let mut harness = harness_with_notes(vec![existing, other_pitch]);
+    let snap = harness.editor.snap_ticks();
+    assert_eq!(snap, 120);
+
+    harness.editor.note_tool = NoteTool::Draw;
+    harness.editor.take_events();
+    harness.drag(note_pos(&harness, 10, 60), note_pos(&harness, 590, 60), Modifiers::NONE);
+    let mut drawn: Vec<(u64, u64)> =
+        harness.editor.state.notes.iter().filter(|n| n.key == 60).map(|n| (n.start, n.duration)).collect();
+    drawn.sort();
+    assert_eq!(drawn, vec![(0, 120), (120, 120), (240, 120), (360, 120), (480, 120)]);
+    let events = harness.editor.take_events();
+    assert_eq!(events.iter().filter(|e| matches!(e, EditorEvent::NoteAdded(_))).count(), 4);
+    // 整次拖动是一步撤销
+    harness.editor.undo();
+    assert_eq!(harness.editor.state.notes.len(), 2);
+
+    // 擦除工具只删除指针经过的音高上的音符
+    harness.editor.note_tool = NoteTool::Erase;
+    harness.drag(note_pos(&harness, 100, 60), note_pos(&harness, 500, 60), Modifiers::NONE);
+    assert_eq!(harness.editor.state.notes, vec![other_pitch]);
+    let events = harness.editor.take_events();
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteDeleted(note) if note.id == existing.id)));
+    harness.editor.undo();
+    assert_eq!(harness.editor.state.notes.len(), 2);
+
+    // 选择工具保持原有行为：拖动移动音符
+    harness.editor.note_tool = NoteTool::Select;
+    harness.drag(note_pos(&harness, 300, 60), note_pos(&harness, 540, 60), Modifiers::NONE);
+    assert!(harness.editor.state.notes.iter().any(|n| n.id == existing.id && n.start == 480));
+}
+
+#[test]
+fn slice_tool_cuts_notes_under_the_pointer() {
+    let low = Note::new(0, 960, 60, 90);
+    let middle = Note::new(0, 960, 62, 70);
+    let high = Note::new(0, 960, 64, 50);
+    let outside = Note::new(0, 960, 66, 100);
+    let mut harness = harness_with_notes(vec![low, middle, high, outside]);
+    harness.editor.note_tool = NoteTool::Slice;
+    harness.editor.take_events();
+
+    // 单击切开按下的音符，切点按吸附取整，两半保留力度
+    harness.click(note_pos(&harness, 250, 60), Modifiers::NONE);
+    let halves: Vec<(u64, u64, u8)> =
+        harness.editor.state.notes.iter().filter(|n| n.key == 60).map(|n| (n.start, n.duration, n.velocity)).collect();
+    assert_eq!(halves, vec![(0, 240, 90), (240, 720, 90)]);
+    let events = harness.editor.take_events();
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteUpdated { before, .. } if before.id == low.id)));
+    assert!(events.iter().any(|e| matches!(e, EditorEvent::NoteAdded(note) if note.start == 240 && note.key == 60)));
+    harness.editor.undo();
+    assert_eq!(harness.editor.state.notes.len(), 4);
+
+    // 切点落在音符边缘时不产生零长度的半段
+    harness.click(note_pos(&harness, 10, 60), Modifiers::NONE);
+    assert_eq!(harness.editor.state.notes.len(), 4);
+    assert!(harness.editor.take_events().iter().all(|e| !matches!(e, EditorEvent::NoteAdded(_))));
+
+    // 竖直拖动切开切线经过的每个音高，整次拖动是一步撤销
+    harness.drag(note_pos(&harness, 490, 60), note_pos(&harness, 490, 64), Modifiers::NONE);
+    let cut: Vec<(u64, u8)> = harness.editor.state.notes.iter().map(|n| (n.start, n.key)).collect();
+    assert_eq!(cut.iter().filter(|(start, _)| *start == 480).count(), 3);
+    assert!(!cut.contains(&(480, 66)));
+    harness.editor.undo();
+    assert_eq!(harness.editor.state.notes, vec![low, middle, high, outside]);
+}
+
+#[test]
+fn dragging_a_five_thousand_note_selection_moves_every_note() {
+    let notes: Vec<Note> = (0..5_000u64)
+        .map(|i| Note::new(i / 4 * 120, 120, 60 + (i % 4) as u8 * 2, 100))
+        .collect();
+    let anchor = notes[2];
+    assert_eq!((anchor.start, anchor.key), (0, 64));
+    let mut harness = harness_with_notes(notes.clone());
+    harness.editor.apply_options(&MidiEditorOptions { max_pending_events: 0, ..MidiEditorOptions::default() });
+    harness.editor.selected_notes.extend(notes.iter().map(|n| n.id));
+    harness.step();
+    harness.editor.take_events();
+
+    let from = note_pos(&harness, 60, 64);
+    let to = note_pos(&harness, 540, 66);
+    harness.drag(from, to, Modifiers::NONE);
+
+    // 与原来逐个线性查找的结果一致：每个音符平移，整体仍按起点（再按 ID）排序
+    let mut expected: Vec<Note> = notes
+        .iter()
+        .map(|n| Note { start: n.start + 480, key: n.key + 2, ..*n })
+        .collect();
+    expected.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.id.0.cmp(&b.id.0)));
+    assert_eq!(harness.editor.state.notes, expected);
+
+    let events = harness.editor.take_events();
+    let updates: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            EditorEvent::NoteUpdated { before, after } => Some((*before, *after)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(updates.len(), notes.len());
+    assert!(updates.iter().all(|(before, after)| notes.contains(before) && expected.contains(after)));
+
+    harness.key(Key::Z, Modifiers::COMMAND);
+    assert_eq!(harness.editor.state.notes, notes);
+}
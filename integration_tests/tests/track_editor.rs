@@ -0,0 +1,655 @@
+use egui::{Key, Modifiers};
+use egui_track::demo::{self, DemoProject};
+use egui_track::{ClipType, ConfirmStrategy, ContextArea, DestructiveAction, MidiClipData, TimelineState, TrackEditor, TrackEditorCommand, TrackEditorEvent, TrackEditorOptions};
+use integration_tests::Harness;
+
+/// 两条轨道，第一条轨道上有一个 [start, start + duration) 的剪辑
+fn harness_with_clip(start: f64, duration: f64) -> Harness<TrackEditor> {
+    harness_with_midi_clip(start, duration, None)
+}
+
+fn harness_with_midi_clip(start: f64, duration: f64, midi_data: Option<MidiClipData>) -> Harness<TrackEditor> {
+    let mut editor = TrackEditor::new(TrackEditorOptions::default());
+    for name in ["Drums", "Bass"] {
+        editor.execute_command(TrackEditorCommand::CreateTrack { name: name.to_string() });
+    }
+    let track_id = editor.tracks()[0].id;
+    editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id,
+        start,
+        duration,
+        clip_type: ClipType::Midi { midi_data },
+    });
+    let mut harness = Harness::new(editor);
+    harness.editor.take_events();
+    harness
+}
+
+#[test]
+fn clicking_the_timeline_seeks_the_playhead() {
+    let mut harness = harness_with_clip(1.0, 1.0);
+    let pos = harness.editor.timeline_time_to_screen(2.0).unwrap();
+    harness.click(pos, Modifiers::NONE);
+
+    assert!((harness.editor.timeline().playhead_position - 2.0).abs() < 1e-6);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::PlayheadChanged { position } if (*position - 2.0).abs() < 1e-6)));
+}
+
+#[test]
+fn dragging_left_edge_resizes_clip_from_start() {
+    let mut harness = harness_with_clip(1.0, 2.0);
+    let edge = harness.editor.time_to_screen(1.0, 0).unwrap() + egui::vec2(2.0, 0.0);
+    let target = harness.editor.time_to_screen(0.5, 0).unwrap();
+    harness.drag(edge, target, Modifiers::NONE);
+
+    let clip = &harness.editor.tracks()[0].clips[0];
+    assert!((clip.start_time - 0.5).abs() < 1e-6);
+    assert!((clip.duration - 2.5).abs() < 1e-6);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::ClipResized { .. })));
+}
+
+#[test]
+fn dragging_an_overlapped_edge_grabs_the_topmost_clip() {
+    let mut harness = harness_with_clip(1.0, 1.0);
+    let track_id = harness.editor.tracks()[0].id;
+    // 第二个剪辑从第一个剪辑结束前 4 像素处开始，两者的边缘区重叠
+    let px_per_second = harness.editor.time_to_screen(2.0, 0).unwrap().x - harness.editor.time_to_screen(1.0, 0).unwrap().x;
+    harness.editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id,
+        start: 2.0 - 4.0 / px_per_second as f64,
+        duration: 1.0,
+        clip_type: ClipType::Midi { midi_data: None },
+    });
+    harness.step();
+    let edge = harness.editor.time_to_screen(2.0, 0).unwrap() - egui::vec2(2.0, 0.0);
+    let target = harness.editor.time_to_screen(1.5, 0).unwrap();
+    harness.drag(edge, target, Modifiers::NONE);
+
+    let clips = &harness.editor.tracks()[0].clips;
+    assert!((clips[0].start_time - 1.0).abs() < 1e-6);
+    assert!((clips[0].duration - 1.0).abs() < 1e-6);
+    assert!((clips[1].start_time - 1.5).abs() < 1e-6);
+}
+
+#[test]
+fn dragging_clip_body_moves_it_to_another_track() {
+    let mut harness = harness_with_clip(1.0, 1.0);
+    let clip_id = harness.editor.tracks()[0].clips[0].id;
+    let from = harness.editor.time_to_screen(1.5, 0).unwrap();
+    let to = harness.editor.time_to_screen(2.5, 1).unwrap();
+    harness.drag(from, to, Modifiers::NONE);
+
+    let tracks = harness.editor.tracks();
+    assert!(tracks[0].clips.is_empty());
+    assert_eq!(tracks[1].clips.len(), 1);
+    assert_eq!(tracks[1].clips[0].id, clip_id);
+    assert!((tracks[1].clips[0].start_time - 2.0).abs() < 1e-6);
+    let bass_id = tracks[1].id;
+    assert!(harness.editor.take_events().iter().any(|e| matches!(
+        e,
+        TrackEditorEvent::ClipMoved { clip_id: id, new_track_id, .. } if *id == clip_id && *new_track_id == bass_id
+    )));
+}
+
+#[test]
+fn escape_cancels_inline_track_rename() {
+    let mut harness = harness_with_clip(1.0, 1.0);
+    let name = harness.find_text("Drums").expect("track name drawn");
+    harness.double_click(name.center());
+    harness.type_text("Kick");
+    harness.key(Key::Escape, Modifiers::NONE);
+
+    assert_eq!(harness.editor.tracks()[0].name, "Drums");
+    assert!(harness.find_text("Drums").is_some());
+    assert!(!harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::TrackRenamed { .. })));
+}
+
+#[test]
+fn committing_inline_track_rename_emits_event() {
+    let mut harness = harness_with_clip(1.0, 1.0);
+    let name = harness.find_text("Drums").expect("track name drawn");
+    harness.double_click(name.center());
+    harness.type_text("Kick");
+    harness.key(Key::Enter, Modifiers::NONE);
+
+    let renamed = harness.editor.tracks()[0].name.clone();
+    assert_ne!(renamed, "Drums");
+    assert!(renamed.contains("Kick"));
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::TrackRenamed { new_name, .. } if *new_name == renamed)));
+}
+
+#[test]
+fn clip_content_can_be_embedded_from_menu_and_extracted_to_file() {
+    let dir = std::env::temp_dir().join(format!("egui_track_clip_content_{}", std::process::id()));
+    let source = dir.join("source.midiclip");
+    let extracted = dir.join("extracted.midiclip");
+    let state = egui_midi::structure::MidiState {
+        notes: vec![egui_midi::structure::Note::new(0, 480, 60, 100)],
+        ..Default::default()
+    };
+    egui_track::preview_loader::save_clip_state(&source, &state).unwrap();
+
+    let mut harness = harness_with_midi_clip(
+        1.0,
+        1.0,
+        Some(MidiClipData {
+            midi_file_path: Some(source.to_string_lossy().to_string()),
+            preview_notes: Vec::new(),
+            midi_state: None,
+            source_file_path: None,
+        }),
+    );
+    let clip_id = harness.editor.tracks()[0].clips[0].id;
+
+    let pos = harness.editor.time_to_screen(1.5, 0).unwrap();
+    harness.secondary_click(pos, Modifiers::NONE);
+    harness.click_text("Make self-contained");
+
+    let midi_data = |harness: &Harness<TrackEditor>| match &harness.editor.tracks()[0].clips[0].clip_type {
+        ClipType::Midi { midi_data: Some(midi_data) } => midi_data.clone(),
+        other => panic!("unexpected clip type {other:?}"),
+    };
+    let embedded = midi_data(&harness);
+    assert!(embedded.is_embedded());
+    assert_eq!(embedded.midi_state.as_ref().unwrap().notes.len(), 1);
+    assert_eq!(embedded.preview_notes.len(), 1);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::ClipContentChanged { clip_id: id } if *id == clip_id)));
+
+    harness.editor.execute_command(TrackEditorCommand::ExtractClipContent {
+        clip_id,
+        path: extracted.to_string_lossy().to_string(),
+    });
+    let file_backed = midi_data(&harness);
+    assert!(file_backed.midi_state.is_none());
+    assert_eq!(file_backed.midi_file_path.as_deref(), Some(&*extracted.to_string_lossy()));
+    let reloaded = egui_track::preview_loader::load_clip_state(&extracted).unwrap();
+    assert_eq!(reloaded.notes.len(), 1);
+    assert_eq!(reloaded.notes[0].key, 60);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn embedded_curve_point_interpolation_survives_project_save_and_load() {
+    use egui_midi::structure::{CurveInterpolation, CurveLaneType, MidiState};
+
+    let dir = std::env::temp_dir().join(format!("egui_track_curve_interp_{}", std::process::id()));
+    let mut state = MidiState::default();
+    let lane_id = state.add_curve_lane(CurveLaneType::ControlChange(11));
+    let lane = state.curves.iter_mut().find(|lane| lane.id == lane_id).unwrap();
+    let step = lane.insert_point(0, 0.0).id;
+    let smooth = lane.insert_point(480, 100.0).id;
+    lane.insert_point(960, 20.0);
+    lane.set_point_interpolation(step, Some(CurveInterpolation::Step));
+    lane.set_point_interpolation(smooth, Some(CurveInterpolation::Smooth));
+    let expected: Vec<f32> = [0, 240, 600, 900, 2000].iter().map(|tick| lane.value_at(*tick).unwrap()).collect();
+
+    let mut track = egui_track::Track::new("Lead".to_string());
+    let mut clip = egui_track::Clip::new_midi(track.id, 0.0, 2.0, "Swell".to_string());
+    if let ClipType::Midi { midi_data: Some(midi_data) } = &mut clip.clip_type {
+        midi_data.midi_state = Some(state);
+    }
+    track.clips.push(clip);
+    let path = dir.join("project.tracks");
+    egui_track::ProjectFile::new(TimelineState::default(), vec![track]).save_to_path(&path).unwrap();
+
+    let loaded = egui_track::ProjectFile::load_from_path(&path).unwrap();
+    let ClipType::Midi { midi_data: Some(midi_data) } = &loaded.tracks[0].clips[0].clip_type else {
+        panic!("clip lost its MIDI data");
+    };
+    let lane = midi_data.midi_state.as_ref().unwrap().curves.iter().find(|lane| lane.id == lane_id).unwrap();
+    let modes: Vec<_> = lane.points.iter().map(|point| point.interpolation).collect();
+    assert_eq!(modes, vec![Some(CurveInterpolation::Step), Some(CurveInterpolation::Smooth), None]);
+    let reloaded: Vec<f32> = [0, 240, 600, 900, 2000].iter().map(|tick| lane.value_at(*tick).unwrap()).collect();
+    assert_eq!(reloaded, expected);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn alt_click_on_clip_title_locates_playhead_without_renaming() {
+    let mut harness = harness_with_clip(1.5, 1.0);
+    let lane_middle = harness.editor.time_to_screen(2.0, 0).unwrap();
+    let title = lane_middle - egui::vec2(0.0, harness.editor.timeline().zoom_y * 0.5 - 9.0);
+    harness.click(title, Modifiers::ALT);
+
+    assert!((harness.editor.timeline().playhead_position - 1.5).abs() < 1e-6);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::PlayheadChanged { position } if (*position - 1.5).abs() < 1e-6)));
+}
+
+#[test]
+fn grid_context_menu_locates_playhead_at_snapped_time() {
+    let mut harness = harness_with_clip(0.5, 0.5);
+    // 默认 120 BPM、吸附 1 拍：2.6 秒吸附到 2.5 秒
+    let pos = harness.editor.time_to_screen(2.6, 1).unwrap();
+    harness.secondary_click(pos, Modifiers::NONE);
+    harness.click_text("Locate playhead here");
+
+    assert!((harness.editor.timeline().playhead_position - 2.5).abs() < 1e-6);
+    assert!(harness.find_text("Locate playhead here").is_none());
+}
+
+#[test]
+fn comma_and_period_step_through_clip_boundaries() {
+    let mut harness = harness_with_clip(1.0, 1.0);
+    let bass_id = harness.editor.tracks()[1].id;
+    harness.editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id: bass_id,
+        start: 1.5,
+        duration: 2.0,
+        clip_type: ClipType::Midi { midi_data: None },
+    });
+
+    let mut visited = Vec::new();
+    for _ in 0..5 {
+        harness.key(Key::Period, Modifiers::NONE);
+        visited.push(harness.editor.timeline().playhead_position);
+    }
+    assert_eq!(visited, vec![1.0, 1.5, 2.0, 3.5, 3.5]);
+
+    harness.key(Key::Comma, Modifiers::NONE);
+    assert!((harness.editor.timeline().playhead_position - 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn range_export_clips_offsets_and_skips_muted_clips() {
+    use egui_midi::structure::{MidiState, Note};
+
+    // 120 BPM、480 TPB：1 拍 = 0.5 秒 = 480 tick
+    let content = MidiState {
+        notes: vec![Note::new(0, 960, 60, 100), Note::new(960, 480, 64, 90)],
+        ..Default::default()
+    };
+    let midi_data = MidiClipData {
+        midi_file_path: None,
+        preview_notes: Vec::new(),
+        midi_state: Some(content),
+        source_file_path: None,
+    };
+    let mut harness = harness_with_midi_clip(1.0, 2.0, Some(midi_data.clone()));
+    let drums = harness.editor.tracks()[0].id;
+    let bass = harness.editor.tracks()[1].id;
+    harness.editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id: bass,
+        start: 1.0,
+        duration: 2.0,
+        clip_type: ClipType::Midi { midi_data: Some(midi_data) },
+    });
+    let muted = harness.editor.tracks()[1].clips[0].id;
+    harness.editor.execute_command(TrackEditorCommand::SetClipMute { clip_id: muted, muted: true });
+
+    // 范围 1.5s..1.75s 截断第一个音符（1.0s..2.0s），第二个音符（2.0s..2.5s）在范围之外
+    let stems = harness.editor.export_range_smf(1.5, 1.75, &[drums, bass]).unwrap();
+    assert_eq!(stems.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![drums, bass]);
+    let drum_notes = MidiState::from_smf(&stems[0].1).notes;
+    assert_eq!(drum_notes.len(), 1);
+    assert_eq!((drum_notes[0].start, drum_notes[0].duration, drum_notes[0].key), (0, 240, 60));
+    assert!(MidiState::from_smf(&stems[1].1).notes.is_empty());
+
+    let whole = MidiState::from_smf(&harness.editor.export_track_smf(drums).unwrap());
+    let spans: Vec<_> = whole.notes.iter().map(|n| (n.start, n.duration, n.key)).collect();
+    assert_eq!(spans, vec![(960, 960, 60), (1920, 480, 64)]);
+
+    assert!(matches!(
+        harness.editor.export_range_smf(2.0, 1.0, &[drums]),
+        Err(egui_track::ExportError::EmptyRange { .. })
+    ));
+    assert_eq!(egui_track::stem_file_name("Lead: Synth/Pad.", "Track 1"), "Lead_ Synth_Pad");
+    assert_eq!(egui_track::stem_file_name(" .. ", "Track 1"), "Track 1");
+}
+
+#[test]
+fn idle_editor_requests_no_repaint() {
+    let options = TrackEditorOptions {
+        debug_repaint_counter: true,
+        ..TrackEditorOptions::default()
+    };
+    let mut editor = TrackEditor::new(options);
+    editor.execute_command(TrackEditorCommand::CreateTrack { name: "Drums".to_string() });
+    let mut harness = Harness::new(editor);
+    harness.hover(harness.editor.time_to_screen(1.0, 0).unwrap());
+    harness.steps(3);
+    assert_eq!(harness.editor.repaint_request_count(), 0);
+    assert_eq!(harness.repaint_delay(), std::time::Duration::MAX);
+
+    harness.editor.execute_command(TrackEditorCommand::SetPlayback { is_playing: true });
+    harness.steps(3);
+    assert!(harness.editor.repaint_request_count() >= 3);
+}
+
+/// 第一条轨道上有三个剪辑，超过 2 个剪辑的删除需要确认
+fn harness_with_three_clips() -> Harness<TrackEditor> {
+    let options = TrackEditorOptions {
+        confirm_delete_threshold: 2,
+        ..TrackEditorOptions::default()
+    };
+    let mut editor = TrackEditor::new(options);
+    for name in ["Drums", "Bass"] {
+        editor.execute_command(TrackEditorCommand::CreateTrack { name: name.to_string() });
+    }
+    let track_id = editor.tracks()[0].id;
+    for start in [0.0, 2.0, 4.0] {
+        editor.execute_command(TrackEditorCommand::CreateClip {
+            track_id,
+            start,
+            duration: 1.0,
+            clip_type: ClipType::Midi { midi_data: None },
+        });
+    }
+    let mut harness = Harness::new(editor);
+    harness.editor.take_events();
+    harness
+}
+
+fn open_track_menu_and_delete(harness: &mut Harness<TrackEditor>) {
+    // 名称标签自己响应点击，在其下方的轨道头空白处右键
+    let name = harness.find_text("Drums").expect("track name drawn");
+    harness.secondary_click(name.left_bottom() + egui::vec2(0.0, 40.0), Modifiers::NONE);
+    harness.step();
+    harness.click_text("Delete Track");
+    harness.step();
+}
+
+#[test]
+fn deleting_track_above_threshold_waits_for_confirmation() {
+    let mut harness = harness_with_three_clips();
+    let track_id = harness.editor.tracks()[0].id;
+    open_track_menu_and_delete(&mut harness);
+    assert_eq!(
+        harness.editor.pending_confirmation(),
+        Some(DestructiveAction::DeleteTrack { track_id, clip_count: 3 })
+    );
+    assert_eq!(harness.editor.tracks().len(), 2);
+
+    harness.click_text("Cancel");
+    assert!(harness.editor.pending_confirmation().is_none());
+    assert_eq!(harness.editor.tracks().len(), 2);
+    assert!(harness.editor.take_events().is_empty());
+
+    open_track_menu_and_delete(&mut harness);
+    harness.click_text("Delete");
+    assert!(harness.editor.pending_confirmation().is_none());
+    assert_eq!(harness.editor.tracks().len(), 1);
+    assert!(harness
+        .editor
+        .take_events()
+        .iter()
+        .any(|e| matches!(e, TrackEditorEvent::TrackDeleted { track_id: id } if *id == track_id)));
+}
+
+#[test]
+fn confirm_hook_decides_without_the_dialog() {
+    let mut harness = harness_with_three_clips();
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let record = seen.clone();
+    harness.editor.set_destructive_action_confirm(move |action| {
+        record.borrow_mut().push(action);
+        ConfirmStrategy::Cancel
+    });
+    open_track_menu_and_delete(&mut harness);
+    assert!(harness.editor.pending_confirmation().is_none());
+    assert_eq!(harness.editor.tracks().len(), 2);
+    assert!(harness.editor.take_events().is_empty());
+    assert_eq!(seen.borrow()[0].clip_count(), 3);
+
+    harness.editor.set_destructive_action_confirm(|_| ConfirmStrategy::Proceed);
+    open_track_menu_and_delete(&mut harness);
+    assert_eq!(harness.editor.tracks().len(), 1);
+
+    // 程序化命令不经过确认
+    harness.editor.set_destructive_action_confirm(|_| ConfirmStrategy::Cancel);
+    harness.editor.execute_command(TrackEditorCommand::ClearAll);
+    assert!(harness.editor.tracks().is_empty());
+}
+
+#[test]
+fn clip_tooltip_reports_position_source_and_cached_note_count() {
+    let midi_data = MidiClipData {
+        midi_file_path: Some("song/verse.mid".to_string()),
+        preview_notes: vec![egui_track::PreviewNote { start: 0.0, duration: 0.5, key: 60, velocity: 100 }],
+        midi_state: None,
+        source_file_path: None,
+    };
+    // 120 BPM、4/4：一小节 2 秒
+    let mut harness = harness_with_midi_clip(2.0, 4.0, Some(midi_data));
+    let clip = harness.editor.tracks()[0].clips[0].clone();
+    let text = egui_track::clip_tooltip(&clip, harness.editor.timeline());
+    assert_eq!(
+        text,
+        format!("{}\nStart 2.1.000\nEnd 4.1.000\nLength 2 measures\nSource song/verse.mid\nNotes 1", clip.name)
+    );
+
+    // 默认关闭；打开后悬停显示
+    let pos = harness.editor.time_to_screen(3.0, 0).unwrap() + egui::vec2(0.0, 30.0);
+    harness.hover(pos);
+    harness.steps(2);
+    assert!(harness.find_text(&text).is_none());
+}
+
+#[test]
+fn hovering_a_clip_shows_tooltip_after_delay() {
+    let options = TrackEditorOptions {
+        hover_tooltip_delay: Some(0.0),
+        ..TrackEditorOptions::default()
+    };
+    let mut editor = TrackEditor::new(options);
+    editor.execute_command(TrackEditorCommand::CreateTrack { name: "Drums".to_string() });
+    let track_id = editor.tracks()[0].id;
+    editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id,
+        start: 0.0,
+        duration: 2.0,
+        clip_type: ClipType::Midi { midi_data: None },
+    });
+    let mut harness = Harness::new(editor);
+    let clip = harness.editor.tracks()[0].clips[0].clone();
+    let text = egui_track::clip_tooltip(&clip, harness.editor.timeline());
+    assert!(text.ends_with("Source none\nNotes 0"));
+
+    let pos = harness.editor.time_to_screen(1.0, 0).unwrap() + egui::vec2(0.0, 30.0);
+    harness.hover(pos);
+    harness.steps(2);
+    assert!(harness.find_text(&text).is_some());
+}
+
+#[test]
+fn right_clicking_the_timeline_requests_a_host_menu() {
+    let mut harness = harness_with_clip(0.5, 0.5);
+    let pos = harness.editor.timeline_time_to_screen(2.0).unwrap();
+    harness.secondary_click(pos, Modifiers::NONE);
+
+    assert!(harness.editor.take_events().iter().any(|e| matches!(
+        e,
+        TrackEditorEvent::ContextRequested { area: ContextArea::Timeline, time, screen_pos }
+            if (*time - 2.0).abs() < 0.01 && *screen_pos == pos
+    )));
+    // 时间轴右键不移动播放头
+    assert_eq!(harness.editor.timeline().playhead_position, 0.0);
+}
+
+#[test]
+fn disabling_builtin_menus_forwards_clip_and_header_right_clicks() {
+    let mut editor = TrackEditor::new(TrackEditorOptions {
+        builtin_context_menus: false,
+        ..TrackEditorOptions::default()
+    });
+    for name in ["Drums", "Bass"] {
+        editor.execute_command(TrackEditorCommand::CreateTrack { name: name.to_string() });
+    }
+    let track_id = editor.tracks()[0].id;
+    editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id,
+        start: 1.0,
+        duration: 1.0,
+        clip_type: ClipType::Midi { midi_data: None },
+    });
+    let mut harness = Harness::new(editor);
+    let clip_id = harness.editor.tracks()[0].clips[0].id;
+
+    let clip_pos = harness.editor.time_to_screen(1.5, 0).unwrap() + egui::vec2(0.0, 30.0);
+    harness.secondary_click(clip_pos, Modifiers::NONE);
+    harness.step();
+    assert!(harness.find_text("Delete").is_none());
+    assert!(harness.editor.take_events().iter().any(|e| matches!(
+        e,
+        TrackEditorEvent::ContextRequested { area: ContextArea::Clip(id), time, .. }
+            if *id == clip_id && (*time - 1.5).abs() < 0.01
+    )));
+
+    let name = harness.find_text("Drums").expect("track name drawn");
+    harness.secondary_click(name.left_bottom() + egui::vec2(0.0, 40.0), Modifiers::NONE);
+    harness.step();
+    assert!(harness.find_text("Delete Track").is_none());
+    assert!(harness.editor.take_events().iter().any(|e| matches!(
+        e,
+        TrackEditorEvent::ContextRequested { area: ContextArea::Header(id), .. } if *id == track_id
+    )));
+
+    let lane_pos = harness.editor.time_to_screen(3.0, 1).unwrap();
+    harness.secondary_click(lane_pos, Modifiers::NONE);
+    harness.step();
+    assert!(harness.find_text("Locate playhead here").is_none());
+    let lane_id = harness.editor.tracks()[1].id;
+    assert!(harness.editor.take_events().iter().any(|e| matches!(
+        e,
+        TrackEditorEvent::ContextRequested { area: ContextArea::TrackLane(id), .. } if *id == lane_id
+    )));
+}
+
+#[test]
+fn pending_events_are_capped_without_starving_the_listener() {
+    let mut editor = TrackEditor::new(TrackEditorOptions {
+        max_pending_events: 2,
+        ..TrackEditorOptions::default()
+    });
+    let heard = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let log = heard.clone();
+    editor.set_event_listener(Box::new(move |event: &TrackEditorEvent| {
+        if let TrackEditorEvent::TrackCreated { track_id } = event {
+            log.borrow_mut().push(*track_id);
+        }
+    }));
+    for i in 0..4 {
+        editor.execute_command(TrackEditorCommand::CreateTrack { name: format!("Track {i}") });
+    }
+
+    let ids: Vec<_> = editor.tracks().iter().map(|t| t.id).collect();
+    assert_eq!(*heard.borrow(), ids);
+    let events = editor.take_events();
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[1], TrackEditorEvent::TrackCreated { track_id } if track_id == ids[3]));
+}
+
+/// 173.3 BPM 下第 500 小节的起点（秒数无法被整除，最容易暴露取整误差）
+fn bar_500_timeline() -> (TimelineState, u64) {
+    let timeline = TimelineState { bpm: 173.3, ..TimelineState::default() };
+    let bar_tick = 499 * timeline.ticks_per_measure();
+    (timeline, bar_tick)
+}
+
+#[test]
+fn tick_time_and_pixel_conversions_round_trip_at_bar_500() {
+    let (mut timeline, bar_tick) = bar_500_timeline();
+    for tick in bar_tick - 5..bar_tick + 5 {
+        assert_eq!(timeline.time_to_tick(timeline.tick_to_time(tick)), tick);
+    }
+    for offset in [-0.013, -0.001, 0.0, 0.0004, 0.0117] {
+        let snapped = timeline.snap_time(timeline.tick_to_time(bar_tick) + offset);
+        assert_eq!(timeline.snap_time(snapped), snapped);
+        assert_eq!(timeline.time_to_tick(snapped) % timeline.snap_interval, 0);
+    }
+
+    // 滚动到第 500 小节附近，屏幕坐标换算回来仍落在同一个 tick 上
+    timeline.manual_scroll_x = -(timeline.tick_to_beat(bar_tick) as f32 * timeline.zoom_x) + 200.0;
+    let x = timeline.tick_to_screen_x(bar_tick, 60.0);
+    assert!((x - 260.0).abs() < 0.01);
+    assert_eq!(timeline.screen_x_to_ticks(x, 60.0).round() as u64, bar_tick);
+    let playhead_x = timeline.time_to_screen_x(timeline.tick_to_time(bar_tick), 60.0);
+    assert!((playhead_x - x).abs() < 0.01);
+}
+
+#[test]
+fn clips_edited_far_along_the_timeline_stay_on_the_grid() {
+    let (timeline, bar_tick) = bar_500_timeline();
+    let mut editor = TrackEditor::new(TrackEditorOptions::default());
+    editor.execute_command(TrackEditorCommand::SetBPM { bpm: 173.3 });
+    editor.execute_command(TrackEditorCommand::CreateTrack { name: "Lead".to_string() });
+    let track_id = editor.tracks()[0].id;
+    let start = timeline.tick_to_time(bar_tick);
+    editor.execute_command(TrackEditorCommand::CreateClip {
+        track_id,
+        start,
+        duration: timeline.tick_to_time(timeline.ticks_per_measure()),
+        clip_type: ClipType::Midi { midi_data: None },
+    });
+    let clip_id = editor.tracks()[0].clips[0].id;
+
+    for round in 0..20 {
+        let jitter = if round % 2 == 0 { 0.0031 } else { -0.0027 };
+        let clip = &editor.tracks()[0].clips[0];
+        editor.execute_command(TrackEditorCommand::MoveClip {
+            clip_id,
+            new_track_id: track_id,
+            new_start: clip.start_time + jitter,
+            disable_snap: false,
+        });
+        let duration = editor.tracks()[0].clips[0].duration;
+        editor.execute_command(TrackEditorCommand::ResizeClip {
+            clip_id,
+            new_duration: duration + jitter,
+            resize_from_start: round % 3 == 0,
+            disable_snap: false,
+        });
+    }
+
+    let clip = &editor.tracks()[0].clips[0];
+    let timeline = editor.timeline();
+    assert_eq!(clip.start_time, timeline.tick_to_time(bar_tick));
+    assert_eq!(timeline.time_to_tick(clip.end_time()), bar_tick + timeline.ticks_per_measure());
+    assert_eq!(timeline.snap_time(clip.start_time), clip.start_time);
+}
+
+#[test]
+fn demo_projects_load_into_the_editor() {
+    let mut harness = Harness::new(TrackEditor::new(TrackEditorOptions::default()));
+    harness.editor.load_project(demo::generate_project(DemoProject::Grid));
+    harness.step();
+    let tracks = harness.editor.tracks();
+    assert_eq!(tracks.len(), 8);
+    assert!(tracks.iter().all(|t| t.clips.len() == 16));
+
+    let clip_id = harness.editor.tracks()[2].clips[1].id;
+    let pos = harness.editor.time_to_screen(3.0, 2).unwrap() + egui::vec2(0.0, 40.0);
+    harness.click(pos, Modifiers::NONE);
+    assert!(harness.editor.selected_clips().contains(&clip_id));
+
+    harness.editor.load_project(demo::generate_project(DemoProject::SongForm));
+    harness.step();
+    assert_eq!(harness.editor.tracks().len(), 4);
+    assert_eq!(harness.editor.timeline().markers.len(), 8);
+    assert!(harness.find_text("Intro").is_some());
+}
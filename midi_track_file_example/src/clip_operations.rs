@@ -28,6 +28,7 @@ pub fn export_midi_to_clip(
             midi_file_path: None,
             preview_notes: Vec::new(),
             midi_state: None,
+            source_file_path: None,
         });
         
         // 保存完整的 MIDI 状态
@@ -51,30 +52,28 @@ pub fn load_clip_to_midi_editor(
     clip: &Clip,
     editor: &mut MidiEditor,
 ) -> Result<(), io::Error> {
-    if let egui_track::ClipType::Midi { ref midi_data } = clip.clip_type {
-        if let Some(midi_data) = midi_data {
-            // 只从文件路径加载
-            if let Some(ref file_path) = midi_data.midi_file_path {
-                let path = std::path::Path::new(file_path);
-                if path.exists() {
-                    match crate::midiclip::load_midiclip_file(path) {
-                        Ok(state) => {
-                            editor.replace_state(state);
-                            return Ok(());
-                        }
-                        Err(e) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidData,
-                                format!("Failed to load MIDI file: {:?}", e)
-                            ));
-                        }
+    if let egui_track::ClipType::Midi { midi_data: Some(ref midi_data) } = clip.clip_type {
+        // 只从文件路径加载
+        if let Some(ref file_path) = midi_data.midi_file_path {
+            let path = std::path::Path::new(file_path);
+            if path.exists() {
+                match crate::midiclip::load_midiclip_file(path) {
+                    Ok(state) => {
+                        editor.replace_state(state);
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Failed to load MIDI file: {:?}", e)
+                        ));
                     }
-                } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::NotFound,
-                        format!("MIDI file not found: {:?}", file_path)
-                    ));
                 }
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("MIDI file not found: {:?}", file_path)
+                ));
             }
         }
     }
@@ -85,6 +84,22 @@ pub fn load_clip_to_midi_editor(
     ))
 }
 
+/// 读取剪辑的 MIDI 内容：优先从文件加载，没有文件时使用嵌入的数据
+pub fn clip_midi_state(clip: &Clip) -> Option<MidiState> {
+    let egui_track::ClipType::Midi { midi_data: Some(ref midi_data) } = clip.clip_type else {
+        return None;
+    };
+    let file_path = midi_data.midi_file_path.as_deref()
+        .map(std::path::Path::new)
+        .filter(|path| path.exists());
+    match file_path {
+        Some(path) => crate::midiclip::load_midiclip_file(path)
+            .map_err(|e| log::warn!("Failed to load MIDI file {:?}: {:?}", path, e))
+            .ok(),
+        None => midi_data.midi_state.clone(),
+    }
+}
+
 /// 计算 MIDI 数据的持续时间（秒）
 #[allow(dead_code)]
 fn calculate_midi_duration(midi_state: &MidiState) -> f64 {
@@ -126,40 +141,8 @@ pub fn ticks_to_seconds(ticks: u64, bpm: f32, ticks_per_beat: u16) -> f64 {
 }
 
 /// 秒转换为 MIDI ticks
-#[allow(dead_code)]
 pub fn seconds_to_ticks(seconds: f64, bpm: f32, ticks_per_beat: u16) -> u64 {
     let beats = seconds * bpm as f64 / 60.0;
     (beats * ticks_per_beat as f64) as u64
 }
 
-/// 从 .midiclip 文件生成预览音符数据
-/// 
-/// 这个函数从文件中加载 MIDI 数据并生成预览音符。
-/// 为了性能考虑，可能会限制预览音符的数量。
-pub fn generate_preview_notes_from_file(file_path: &std::path::Path) -> Result<Vec<egui_track::PreviewNote>, io::Error> {
-    // 加载 MIDI 文件
-    let midi_state = crate::midiclip::load_midiclip_file(file_path)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to load MIDI file: {:?}", e)))?;
-    
-    // 生成预览音符
-    let preview_notes = generate_preview_notes(&midi_state);
-    
-    // 性能优化：如果音符太多，进行采样
-    const MAX_PREVIEW_NOTES: usize = 1000;
-    let preview_notes = if preview_notes.len() > MAX_PREVIEW_NOTES {
-        log::warn!("Too many notes ({}), sampling to {} for preview", preview_notes.len(), MAX_PREVIEW_NOTES);
-        // 均匀采样
-        let step = preview_notes.len() / MAX_PREVIEW_NOTES;
-        preview_notes.into_iter()
-            .enumerate()
-            .filter(|(i, _)| i % step == 0)
-            .map(|(_, note)| note)
-            .take(MAX_PREVIEW_NOTES)
-            .collect()
-    } else {
-        preview_notes
-    };
-    
-    Ok(preview_notes)
-}
-
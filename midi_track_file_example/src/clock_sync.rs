@@ -0,0 +1,74 @@
+//! 外部 MIDI Clock 同步演示
+//!
+//! 后台线程按固定 BPM 生成 Start / Clock / Stop 消息，写入共享的 `AtomicTransport`，
+//! 编辑器通过 `TransportSync` 跟随。接入真实设备时，只需在 midir 的输入回调中转发消息：
+//!
+//! ```ignore
+//! let transport = clock.transport();
+//! midi_in.connect(&port, "clock-in", move |stamp, message, _| {
+//!     transport.handle_midi_message(stamp, message);
+//! }, ())?;
+//! ```
+
+use egui_track::{AtomicTransport, TransportSync};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 模拟的外部 MIDI Clock 发送端
+pub struct DemoClock {
+    transport: Arc<AtomicTransport>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DemoClock {
+    /// 以 `bpm` 开始发送时钟（每拍 24 个 Clock 消息）
+    pub fn start(bpm: f32) -> Self {
+        let transport = Arc::new(AtomicTransport::new(bpm));
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let transport = Arc::clone(&transport);
+            let running = Arc::clone(&running);
+            thread::spawn(move || {
+                let origin = Instant::now();
+                let interval = Duration::from_secs_f64(60.0 / (bpm as f64 * 24.0));
+                let stamp = || origin.elapsed().as_micros() as u64;
+                transport.handle_midi_message(stamp(), &[0xFA]);
+                let mut next = Instant::now();
+                while running.load(Ordering::Acquire) {
+                    next += interval;
+                    if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                        thread::sleep(wait);
+                    }
+                    transport.handle_midi_message(stamp(), &[0xF8]);
+                }
+                transport.handle_midi_message(stamp(), &[0xFC]);
+            })
+        };
+        Self {
+            transport,
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn transport(&self) -> Arc<AtomicTransport> {
+        Arc::clone(&self.transport)
+    }
+
+    /// 供编辑器使用的同步源
+    pub fn sync_source(&self) -> Box<TransportSync> {
+        Box::new(TransportSync(self.transport()))
+    }
+}
+
+impl Drop for DemoClock {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -4,10 +4,11 @@ mod clip_operations;
 mod playback;
 mod midiclip;
 mod audio;
+mod clock_sync;
 
 use eframe::egui;
 use egui_track::{TrackEditor, TrackEditorOptions, ClipId};
-use egui_midi::{ui::MidiEditor, audio::{AudioEngine, PlaybackBackend}, structure::MidiState};
+use egui_midi::{ui::MidiEditor, audio::{AudioEngine, PlaybackBackend}, editor::{EditorEvent, ImportMergeOptions}, loader::{MidiLoader, SmfLoadMode}, structure::{ImportWarning, MidiState}};
 use egui_file_tree::FileTree;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -54,6 +55,13 @@ impl MidiEditorTab {
     }
 }
 
+/// 已有活动 MIDI 标签页时打开 .mid 文件：等待用户选择替换还是合并
+pub struct PendingMidiImport {
+    path: PathBuf,
+    state: MidiState,
+    options: ImportMergeOptions,
+}
+
 pub struct MidiTrackFileApp {
     // Top tabs
     top_active_tab: TopTab,
@@ -82,10 +90,14 @@ pub struct MidiTrackFileApp {
     // Playback
     playback_engine: playback::MultiTrackPlaybackEngine,
     is_playing: bool,
+    demo_clock: Option<clock_sync::DemoClock>,  // 外部 MIDI Clock 同步演示
     
     // File tree context menu
     file_tree_context_menu_path: Option<PathBuf>,
     file_tree_context_menu_pos: Option<egui::Pos2>,
+
+    // Timeline context menu（由 TrackEditorEvent::ContextRequested 打开：位置与对应时间）
+    timeline_context_menu: Option<(egui::Pos2, f64)>,
     
     // Drag and drop
     dragging_file_path: Option<PathBuf>,
@@ -94,13 +106,27 @@ pub struct MidiTrackFileApp {
     new_project_dialog_open: bool,
     new_project_parent_dir: Option<PathBuf>,
     new_project_name: String,
+    
+    // Replace / Merge prompt
+    pending_midi_import: Option<PendingMidiImport>,
+
+    // 宽松导入 .mid 文件时跳过或修补的内容（文件路径与警告列表）
+    midi_import_warnings: Option<(PathBuf, Vec<ImportWarning>)>,
+
+    // 正在后台解析的 .mid 文件；完成后按原来的流程打开或提示合并
+    midi_loading: Option<(PathBuf, MidiLoader)>,
+
+    // 关闭有未保存改动的 MIDI 标签页前的确认
+    pending_tab_close: Option<usize>,
 }
 
 impl MidiTrackFileApp {
     fn new() -> Self {
         let options = TrackEditorOptions::default();
-        let track_editor = TrackEditor::new(options);
+        let mut track_editor = TrackEditor::new(options);
+        track_editor.set_name_validator(midiclip::validate_file_name);
         
+        #[allow(clippy::arc_with_non_send_sync)]
         let audio: Arc<dyn PlaybackBackend> = Arc::new(AudioEngine::new());
         
         // Initialize file tree with current directory
@@ -122,12 +148,39 @@ impl MidiTrackFileApp {
             dragging_horizontal_splitter: false,
             playback_engine: playback::MultiTrackPlaybackEngine::new(1),  // 初始 1 个轨道，会根据实际轨道数量动态扩展
             is_playing: false,
+            demo_clock: None,
             file_tree_context_menu_path: None,
             file_tree_context_menu_pos: None,
+            timeline_context_menu: None,
             dragging_file_path: None,
             new_project_dialog_open: false,
             new_project_parent_dir: None,
             new_project_name: String::new(),
+            pending_midi_import: None,
+            midi_import_warnings: None,
+            midi_loading: None,
+            pending_tab_close: None,
+        }
+    }
+
+    /// 开启或关闭外部 MIDI Clock 同步演示
+    fn set_demo_clock_sync(&mut self, enabled: bool) {
+        if enabled {
+            // 同步期间由时钟决定播放，停止内部播放引擎
+            self.playback_engine.stop();
+            self.is_playing = false;
+            let clock = clock_sync::DemoClock::start(self.track_editor.timeline().bpm);
+            self.track_editor.set_sync(Some(clock.sync_source()));
+            for tab in &mut self.midi_editors {
+                tab.editor.set_sync(Some(clock.sync_source()));
+            }
+            self.demo_clock = Some(clock);
+        } else {
+            self.demo_clock = None;
+            self.track_editor.set_sync(None);
+            for tab in &mut self.midi_editors {
+                tab.editor.set_sync(None);
+            }
         }
     }
 
@@ -170,45 +223,109 @@ impl MidiTrackFileApp {
         }
     }
 
+    /// 读取 .mid 文件并在工作线程上宽松导入，结果由 `poll_midi_loading` 取回
     fn open_midi_file(&mut self, path: &PathBuf) {
-        match std::fs::read(path) {
-            Ok(data) => {
-                match midly::Smf::parse(&data) {
-                    Ok(smf) => {
-                        match MidiState::from_smf_strict(&smf) {
-                            Ok(state) => {
-                                // Create a new MIDI editor tab with the loaded state
-                                let id = self.next_midi_tab_id;
-                                self.next_midi_tab_id += 1;
-                                let file_name = path.file_name()
-                                    .and_then(|n| n.to_str())
-                                    .unwrap_or("MIDI")
-                                    .to_string();
-                                let name = format!("{}", file_name);
-                                
-                                let mut tab = MidiEditorTab::new(id, name, Arc::clone(&self.audio_engine));
-                                tab.editor.replace_state(state);
-                                tab.file_path = Some(path.clone());
-                                self.midi_editors.push(tab);
-                                self.active_midi_tab = Some(self.midi_editors.len() - 1);
-                                log::info!("Opened MIDI file: {:?}", path);
-                            }
-                            Err(e) => {
-                                log::error!("Failed to parse MIDI file: {}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Failed to parse MIDI file: {:?}", e);
-                    }
-                }
-            }
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
             Err(e) => {
                 log::error!("Failed to read file: {:?}", e);
+                return;
+            }
+        };
+        if let Some((previous, _)) = &self.midi_loading {
+            log::warn!("Abandoning the import of {:?}", previous);
+        }
+        self.midi_loading = Some((path.clone(), MidiLoader::spawn(data, SmfLoadMode::Lenient)));
+    }
+
+    /// 每帧检查后台导入；仍在进行时继续请求重绘以刷新进度
+    fn poll_midi_loading(&mut self, ctx: &egui::Context) {
+        let Some((path, loader)) = self.midi_loading.as_mut() else {
+            return;
+        };
+        let Some(result) = loader.poll() else {
+            ctx.request_repaint_after(std::time::Duration::from_millis(50));
+            return;
+        };
+        let path = path.clone();
+        self.midi_loading = None;
+        match result {
+            Ok(loaded) => self.finish_open_midi_file(&path, loaded.state, loaded.warnings),
+            Err(e) => log::error!("Failed to load MIDI file {:?}: {}", path, e),
+        }
+    }
+
+    /// 导入完成：有活动标签页时询问替换或合并，否则在新标签页中打开
+    fn finish_open_midi_file(&mut self, path: &PathBuf, state: MidiState, warnings: Vec<ImportWarning>) {
+        if !warnings.is_empty() {
+            for warning in &warnings {
+                log::warn!("{:?}: {}", path, warning);
             }
+            self.midi_import_warnings = Some((path.clone(), warnings));
+        }
+        if self.active_midi_tab.is_some() {
+            self.pending_midi_import = Some(PendingMidiImport {
+                path: path.clone(),
+                state,
+                options: ImportMergeOptions::default(),
+            });
+        } else {
+            self.open_midi_tab(path, state);
+        }
+    }
+
+    /// "Import into current clip…"：选择 .mid 文件合并到活动标签页
+    fn import_into_current_clip(&mut self) {
+        if self.active_midi_tab.is_none() {
+            log::warn!("No active MIDI editor to import into");
+            return;
+        }
+        if let Some(path) = FileDialog::new()
+            .add_filter("Standard MIDI", &["mid", "midi"])
+            .pick_file()
+        {
+            self.open_midi_file(&path);
         }
     }
 
+    /// 回答替换 / 合并提示
+    fn finish_midi_import(&mut self, merge: bool) {
+        let Some(pending) = self.pending_midi_import.take() else {
+            return;
+        };
+        let Some(tab) = self.active_midi_tab.and_then(|index| self.midi_editors.get_mut(index)) else {
+            self.open_midi_tab(&pending.path, pending.state);
+            return;
+        };
+        if merge {
+            let count = tab.editor.import_notes(pending.state, pending.options);
+            log::info!("Merged {} notes from {:?} into {}", count, pending.path, tab.name);
+        } else {
+            tab.editor.replace_state(pending.state);
+            tab.editor.zoom_to_fit();
+            log::info!("Replaced {} with {:?}", tab.name, pending.path);
+        }
+    }
+
+    /// 在新标签页中打开 MIDI 内容
+    fn open_midi_tab(&mut self, path: &PathBuf, state: MidiState) {
+        let id = self.next_midi_tab_id;
+        self.next_midi_tab_id += 1;
+        let name = path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("MIDI")
+            .to_string();
+        
+        let mut tab = MidiEditorTab::new(id, name, Arc::clone(&self.audio_engine));
+        tab.editor.replace_state(state);
+        tab.editor.mark_saved();
+        tab.editor.zoom_to_fit();
+        tab.file_path = Some(path.clone());
+        self.midi_editors.push(tab);
+        self.active_midi_tab = Some(self.midi_editors.len() - 1);
+        log::info!("Opened MIDI file: {:?}", path);
+    }
+
     /// 打开 .midiclip 文件到 MIDI 编辑器
     fn open_midiclip_file(&mut self, path: &PathBuf) {
         match midiclip::load_midiclip_file(path) {
@@ -219,10 +336,11 @@ impl MidiTrackFileApp {
                     .and_then(|n| n.to_str())
                     .unwrap_or("MIDI Clip")
                     .to_string();
-                let name = format!("{}", file_name);
+                let name = file_name.to_string();
                 
                 let mut tab = MidiEditorTab::new(id, name, Arc::clone(&self.audio_engine));
                 tab.editor.replace_state(state);
+                tab.editor.mark_saved();
                 tab.file_path = Some(path.clone());
                 self.midi_editors.push(tab);
                 self.active_midi_tab = Some(self.midi_editors.len() - 1);
@@ -242,6 +360,7 @@ impl MidiTrackFileApp {
                 midiclip::save_midiclip_file(file_path, &state)
                     .map_err(|e| format!("Failed to save file: {:?}", e))?;
                 log::info!("Saved MIDI clip file: {:?}", file_path);
+                tab.editor.mark_saved();
                 
                 // 刷新所有使用该文件的剪辑预览
                 let file_path_str = file_path.to_string_lossy().to_string();
@@ -279,6 +398,72 @@ impl MidiTrackFileApp {
         }
     }
 
+    /// 按关联剪辑的长度限制 MIDI 编辑器的可编辑区域（剪辑内容从 tick 0 开始）
+    fn sync_editor_bounds_with_clip(&mut self, editor_index: usize) {
+        let Some(clip_id) = self.get_clip_for_editor(editor_index) else {
+            return;
+        };
+        let duration = self
+            .track_editor
+            .tracks()
+            .iter()
+            .flat_map(|track| track.clips.iter())
+            .find(|clip| clip.id == clip_id)
+            .map(|clip| clip.duration);
+        let bpm = self.track_editor.timeline().bpm;
+        if let (Some(duration), Some(tab)) = (duration, self.midi_editors.get_mut(editor_index)) {
+            let ticks_per_beat = tab.editor.midi_state().ticks_per_beat;
+            let end = crate::clip_operations::seconds_to_ticks(duration, bpm, ticks_per_beat);
+            tab.editor.set_edit_bounds(Some((0, end)));
+        }
+    }
+
+    /// 把其他轨道上与关联剪辑时间重叠的 MIDI 剪辑作为幽灵音符显示在编辑器中（例如编辑旋律时参照和弦）
+    fn sync_editor_ghost_with_clip(&mut self, editor_index: usize) {
+        let Some(clip_id) = self.get_clip_for_editor(editor_index) else {
+            return;
+        };
+        let tracks = self.track_editor.tracks();
+        let Some(clip) = tracks.iter().flat_map(|track| track.clips.iter()).find(|clip| clip.id == clip_id) else {
+            return;
+        };
+        let (clip_start, clip_end) = (clip.start_time, clip.start_time + clip.duration);
+        let bpm = self.track_editor.timeline().bpm;
+        let Some(tab) = self.midi_editors.get_mut(editor_index) else {
+            return;
+        };
+        let ticks_per_beat = tab.editor.midi_state().ticks_per_beat;
+
+        let mut notes = Vec::new();
+        for other in tracks
+            .iter()
+            .filter(|track| !track.clips.iter().any(|c| c.id == clip_id))
+            .flat_map(|track| track.clips.iter())
+            .filter(|other| other.start_time < clip_end && other.start_time + other.duration > clip_start)
+        {
+            let Some(state) = crate::clip_operations::clip_midi_state(other) else {
+                continue;
+            };
+            for note in &state.notes {
+                // 先换算到时间轴上的秒，再换算为本剪辑内的 tick
+                let start = other.start_time
+                    + crate::clip_operations::ticks_to_seconds(note.start, state.bpm, state.ticks_per_beat);
+                let duration = crate::clip_operations::ticks_to_seconds(note.duration, state.bpm, state.ticks_per_beat);
+                if start < clip_start || start >= clip_end || start >= other.start_time + other.duration {
+                    continue;
+                }
+                notes.push(egui_midi::structure::Note::new(
+                    crate::clip_operations::seconds_to_ticks(start - clip_start, bpm, ticks_per_beat),
+                    crate::clip_operations::seconds_to_ticks(duration, bpm, ticks_per_beat).max(1),
+                    note.key,
+                    note.velocity,
+                ));
+            }
+        }
+        let ghost = (!notes.is_empty()).then(|| MidiState { notes, ticks_per_beat, ..MidiState::default() });
+        tab.editor.set_ghost_state(ghost);
+    }
+
     /// 获取编辑器关联的剪辑 ID
     pub fn get_clip_for_editor(&self, editor_index: usize) -> Option<ClipId> {
         self.midi_editors.get(editor_index)
@@ -342,6 +527,7 @@ impl MidiTrackFileApp {
                 midi_file_path: Some(file_path_str),
                 preview_notes: Vec::new(),
                 midi_state: None,  // 优先使用文件路径
+                source_file_path: None,
             });
             
             self.track_editor.execute_command(TrackEditorCommand::CreateClip {
@@ -387,8 +573,8 @@ impl MidiTrackFileApp {
         
         log::info!("[CLIP] Using file path: {:?} (original: {:?})", file_path_to_use, file_path);
         
-        // 尝试从文件加载 MIDI 数据以获取持续时间和预览数据
-        let (duration, preview_notes) = match midiclip::load_midiclip_file(&file_path_to_use) {
+        // 尝试从文件加载 MIDI 数据以获取持续时间（预览音符由编辑器在后台加载）
+        let duration = match midiclip::load_midiclip_file(&file_path_to_use) {
             Ok(state) => {
                 // 计算持续时间（秒）
                 let duration = if state.notes.is_empty() {
@@ -401,23 +587,11 @@ impl MidiTrackFileApp {
                     crate::clip_operations::ticks_to_seconds(max_end_tick, state.bpm, state.ticks_per_beat)
                 };
                 
-                // 生成预览音符
-                let preview_notes = match crate::clip_operations::generate_preview_notes_from_file(&file_path_to_use) {
-                    Ok(notes) => {
-                        log::info!("[CLIP] Generated {} preview notes", notes.len());
-                        notes
-                    }
-                    Err(e) => {
-                        log::warn!("[CLIP] Failed to generate preview notes: {:?}, using empty preview", e);
-                        Vec::new()
-                    }
-                };
-                
-                (duration, preview_notes)
+                duration
             }
             Err(e) => {
                 log::warn!("[CLIP] Failed to load MIDI file: {:?}, using default duration", e);
-                (4.0, Vec::new())  // 默认 4 秒，无预览
+                4.0  // 默认 4 秒
             }
         };
         
@@ -432,8 +606,9 @@ impl MidiTrackFileApp {
         
         let midi_data = Some(MidiClipData {
             midi_file_path: Some(file_path_str.clone()),
-            preview_notes,
+            preview_notes: Vec::new(),
             midi_state: None,  // 优先使用文件路径
+            source_file_path: None,
         });
         
         log::info!("[CLIP] MidiClipData created with midi_file_path: {:?}", midi_data.as_ref().and_then(|d| d.midi_file_path.as_ref()));
@@ -486,7 +661,7 @@ impl MidiTrackFileApp {
             .find(|clip| clip.id == clip_id)
             .and_then(|clip| {
                 if let egui_track::ClipType::Midi { midi_data: Some(midi_data) } = &clip.clip_type {
-                    midi_data.midi_file_path.as_ref().map(|p| p.clone())
+                    midi_data.midi_file_path.clone()
                 } else {
                     None
                 }
@@ -594,37 +769,33 @@ impl MidiTrackFileApp {
         }
     }
     
-    /// 刷新剪辑预览
+    /// 刷新剪辑预览（在后台重新读取文件）
     fn refresh_clip_preview(&mut self, clip_id: egui_track::ClipId) {
-        use egui_track::TrackEditorCommand;
-        use crate::clip_operations;
-        
-        // 找到剪辑并重新加载预览数据
-        for track in self.track_editor.tracks() {
-            if let Some(clip) = track.clips.iter().find(|c| c.id == clip_id) {
-                if let egui_track::ClipType::Midi { midi_data: Some(midi_data) } = &clip.clip_type {
-                    if let Some(ref file_path) = midi_data.midi_file_path {
-                        let path = std::path::Path::new(file_path);
-                        if path.exists() {
-                            // 重新生成预览音符
-                            match clip_operations::generate_preview_notes_from_file(path) {
-                                Ok(preview_notes) => {
-                                    log::info!("Refreshed preview for clip {:?}: {} notes", clip_id, preview_notes.len());
-                                    // 使用 UpdateClipPreview 命令来更新预览
-                                    self.track_editor.execute_command(TrackEditorCommand::UpdateClipPreview {
-                                        clip_id,
-                                        preview_notes,
-                                    });
-                                }
-                                Err(e) => {
-                                    log::warn!("Failed to generate preview notes: {:?}", e);
-                                }
-                            }
-                        }
-                    }
-                }
-                break;
+        self.track_editor.execute_command(egui_track::TrackEditorCommand::ReloadClipPreview { clip_id });
+    }
+
+    /// 弹出保存对话框，把剪辑嵌入的内容写入所选文件
+    fn save_clip_content_as(&mut self, clip_id: egui_track::ClipId) {
+        let clip_name = self.track_editor.tracks().iter()
+            .flat_map(|track| track.clips.iter())
+            .find(|clip| clip.id == clip_id)
+            .map(|clip| clip.name.clone())
+            .unwrap_or_else(|| "clip".to_string());
+        let mut dialog = FileDialog::new()
+            .add_filter("MIDI Clip", &["midiclip"])
+            .set_title("Save Clip Content As")
+            .set_file_name(format!("{}.midiclip", clip_name));
+        if let Some(tree) = &self.file_tree {
+            dialog = dialog.set_directory(tree.root_path());
+        }
+        if let Some(mut path) = dialog.save_file() {
+            if !midiclip::is_midiclip_file(&path) {
+                path.set_extension("midiclip");
             }
+            self.track_editor.execute_command(egui_track::TrackEditorCommand::ExtractClipContent {
+                clip_id,
+                path: path.to_string_lossy().to_string(),
+            });
         }
     }
     
@@ -637,6 +808,13 @@ impl eframe::App for MidiTrackFileApp {
         let timeline_bpm = self.track_editor.timeline().bpm;
         self.playback_engine.update(current_time, self.track_editor.tracks(), timeline_bpm);
         
+        // 同步演示开启时，新打开的 MIDI 编辑器也跟随时钟
+        if let Some(clock) = &self.demo_clock {
+            for tab in self.midi_editors.iter_mut().filter(|tab| !tab.editor.is_synced()) {
+                tab.editor.set_sync(Some(clock.sync_source()));
+            }
+        }
+        
         // Update playback position in track editor
         // 使用 playback_engine.is_playing() 来判断，而不是 self.is_playing
         if self.playback_engine.is_playing() {
@@ -649,11 +827,17 @@ impl eframe::App for MidiTrackFileApp {
             self.is_playing = false;  // 同步状态
         }
 
+        self.poll_midi_loading(ctx);
+
         // Render UI components
         self.render_menu_bar(ctx);
         self.render_status_bar(ctx);
         self.render_main_content(ctx);
         self.render_new_project_dialog(ctx);
+        self.render_midi_import_dialog(ctx);
+        self.render_midi_import_warnings(ctx);
+        self.render_close_tab_dialog(ctx);
+        self.render_timeline_context_menu(ctx);
 
         // Handle track editor events
         for event in self.track_editor.take_events() {
@@ -725,6 +909,12 @@ impl eframe::App for MidiTrackFileApp {
                                 if path.exists() {
                                     self.open_midiclip_file(&path);
                                     log::info!("Opened MIDI clip from double-click: {:?}", path);
+                                    if let Some(index) = self.active_midi_tab {
+                                        self.associate_midi_editor_with_clip(index, clip_id);
+                                        self.sync_editor_bounds_with_clip(index);
+                                        self.sync_editor_ghost_with_clip(index);
+                                        self.midi_editors[index].editor.zoom_to_fit();
+                                    }
                                 }
                             }
                         }
@@ -733,6 +923,32 @@ impl eframe::App for MidiTrackFileApp {
                 }
             }
             
+            // 剪辑长度变化时更新已打开编辑器的可编辑区域
+            if let egui_track::TrackEditorEvent::ClipResized { clip_id, .. } = event {
+                if let Some(index) = self.get_editor_for_clip(clip_id) {
+                    self.sync_editor_bounds_with_clip(index);
+                }
+            }
+            
+            // 剪辑菜单 "Save content as…"：选择文件后把嵌入内容提取为 .midiclip
+            if let egui_track::TrackEditorEvent::ClipContentSaveRequested { clip_id } = event {
+                self.save_clip_content_as(clip_id);
+            }
+
+            if let egui_track::TrackEditorEvent::ClipContentFailed { clip_id, ref error } = event {
+                log::error!("Failed to convert clip {:?}: {}", clip_id, error);
+            }
+
+            // 时间轴右键：轨道编辑器没有内置菜单，由宿主提供
+            if let egui_track::TrackEditorEvent::ContextRequested {
+                area: egui_track::ContextArea::Timeline,
+                time,
+                screen_pos,
+            } = event
+            {
+                self.timeline_context_menu = Some((screen_pos, time));
+            }
+
             // 处理剪辑重命名事件
             if let egui_track::TrackEditorEvent::ClipRenamed { clip_id, new_name } = event {
                 self.handle_clip_renamed(clip_id, new_name);
@@ -742,7 +958,12 @@ impl eframe::App for MidiTrackFileApp {
         // Handle MIDI editor events
         for tab in &mut self.midi_editors {
             for event in tab.editor.take_events() {
-                log::info!("[MidiEditorEvent] {:?}", event);
+                match event {
+                    EditorEvent::StateReplaced(state) => {
+                        log::info!("[MidiEditorEvent] StateReplaced ({} notes)", state.notes.len())
+                    }
+                    event => log::info!("[MidiEditorEvent] {:?}", event),
+                }
             }
         }
         
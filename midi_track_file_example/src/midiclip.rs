@@ -3,7 +3,7 @@
 //! 处理 .midiclip 文件的创建、转换、加载、保存等操作。
 //! .midiclip 文件使用标准 MIDI 格式（.mid）存储。
 
-use egui_midi::structure::MidiState;
+use egui_midi::structure::{MidiState, SmfImportOptions};
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io;
@@ -52,8 +52,11 @@ pub fn load_midiclip_file(path: &Path) -> Result<MidiState, io::Error> {
     let smf = midly::Smf::parse(&data)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid MIDI file: {:?}", e)))?;
     
-    MidiState::from_smf_strict(&smf)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Not a single-track MIDI file: {}", e)))
+    // .midiclip 由本程序写出，文本事件即音符标签
+    let state = MidiState::from_smf_with_options(&smf, SmfImportOptions { text_events_as_labels: true });
+    state.validate_single_track()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Not a single-track MIDI file: {}", e)))?;
+    Ok(state)
 }
 
 /// 保存 MIDI 数据到 .midiclip 文件（标准 MIDI 格式）
@@ -63,8 +66,9 @@ pub fn save_midiclip_file(path: &Path, state: &MidiState) -> Result<(), io::Erro
         fs::create_dir_all(parent)?;
     }
     
-    // 导出为标准 MIDI 格式
-    let smf = state.to_single_track_smf()
+    // 导出为标准 MIDI 格式（音符分组等编辑器数据写入 sequencer-specific 事件）
+    let arena = midly::Arena::new();
+    let smf = state.to_single_track_smf_in(&arena)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to export MIDI: {}", e)))?;
     
     // 写入缓冲区
@@ -94,3 +98,15 @@ pub fn is_midi_file(path: &Path) -> bool {
         .unwrap_or(false)
 }
 
+
+/// 校验剪辑/轨道名称能否作为文件名（.midiclip 文件名由剪辑名称派生）
+pub fn validate_file_name(name: &str) -> Result<(), String> {
+    const ILLEGAL: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+    if let Some(c) = name.chars().find(|c| ILLEGAL.contains(c) || c.is_control()) {
+        return Err(format!("Name cannot contain '{}'", c.escape_default()));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err("Name cannot end with '.' or a space".to_string());
+    }
+    Ok(())
+}
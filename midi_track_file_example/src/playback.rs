@@ -162,45 +162,55 @@ impl MultiTrackPlaybackEngine {
 
             // 遍历轨道的所有剪辑
             for clip in &track.clips {
-                if let ClipType::Midi { ref midi_data } = clip.clip_type {
-                    if let Some(ref midi_data) = midi_data {
-                        // 只从文件路径加载 MIDI 数据
-                        if let Some(ref file_path) = midi_data.midi_file_path {
-                            let path = std::path::Path::new(file_path);
-                            if path.exists() {
-                                match crate::midiclip::load_midiclip_file(path) {
-                                    Ok(midi_state) => {
-                                        // 计算剪辑在当前播放位置的相对时间
-                                        let clip_start = clip.start_time;
-                                        let clip_end = clip.start_time + clip.duration;
-                                        
-                                        // 如果剪辑与时间窗口有重叠，就调度它
-                                        // 这包括：剪辑在当前播放位置之前开始但仍在时间窗口内的情况
-                                        let clip_overlaps_window = clip_start <= max_time && clip_end >= min_time;
-                                        
-                                        if clip_overlaps_window {
-                                            let relative_time = if self.playback_position >= clip_start {
-                                                self.playback_position - clip_start
-                                            } else {
-                                                0.0  // 如果播放位置在剪辑之前，从剪辑开始处计算
-                                            };
-                                            self.schedule_clip_events(
-                                                &midi_state,
-                                                relative_time,
-                                                clip_start,
-                                                track.id,
-                                                track_index,
-                                                min_time,
-                                                max_time,
-                                            );
-                                        }
-                                    }
-                                    Err(e) => {
-                                        log::warn!("Failed to load MIDI file for playback: {:?}", e);
-                                    }
-                                }
+                if clip.muted {
+                    continue;
+                }
+                if let ClipType::Midi { midi_data: Some(ref midi_data) } = clip.clip_type {
+                    // 优先从文件路径加载，没有文件时使用嵌入的 MIDI 数据
+                    let loaded;
+                    let file_path = midi_data.midi_file_path.as_deref()
+                        .map(std::path::Path::new)
+                        .filter(|path| path.exists());
+                    let midi_state = if let Some(path) = file_path {
+                        match crate::midiclip::load_midiclip_file(path) {
+                            Ok(state) => {
+                                loaded = state;
+                                &loaded
+                            }
+                            Err(e) => {
+                                log::warn!("Failed to load MIDI file for playback: {:?}", e);
+                                continue;
                             }
                         }
+                    } else if let Some(state) = &midi_data.midi_state {
+                        state
+                    } else {
+                        continue;
+                    };
+
+                    // 计算剪辑在当前播放位置的相对时间
+                    let clip_start = clip.start_time;
+                    let clip_end = clip.start_time + clip.duration;
+
+                    // 如果剪辑与时间窗口有重叠，就调度它
+                    // 这包括：剪辑在当前播放位置之前开始但仍在时间窗口内的情况
+                    let clip_overlaps_window = clip_start <= max_time && clip_end >= min_time;
+
+                    if clip_overlaps_window {
+                        let relative_time = if self.playback_position >= clip_start {
+                            self.playback_position - clip_start
+                        } else {
+                            0.0  // 如果播放位置在剪辑之前，从剪辑开始处计算
+                        };
+                        self.schedule_clip_events(
+                            midi_state,
+                            relative_time,
+                            clip_start,
+                            track.id,
+                            track_index,
+                            min_time,
+                            max_time,
+                        );
                     }
                 }
             }
@@ -214,6 +224,7 @@ impl MultiTrackPlaybackEngine {
     }
 
     /// 调度单个剪辑的 MIDI 事件
+    #[allow(clippy::too_many_arguments)]
     fn schedule_clip_events(
         &mut self,
         midi_state: &egui_midi::structure::MidiState,
@@ -266,22 +277,23 @@ impl MultiTrackPlaybackEngine {
             }
             
             // 检查音符是否应该结束
-            if note_end_time >= min_time && note_end_time <= max_time {
-                if note_end_time > self.playback_position && note_end_time <= max_time {
-                    // 检查是否已经调度过这个事件（使用毫秒精度避免浮点误差）
-                    let time_ms = (note_end_time * 1000.0) as u64;
-                    let event_key = (track_id, note.key, time_ms);
-                    if !self.processed_events.contains(&event_key) {
-                        // 音符结束
-                        self.event_queue.push_back(ScheduledEvent {
-                            time: note_end_time,
-                            event: MidiEvent::NoteOff {
-                                track_index,
-                                key: note.key,
-                            },
-                        });
-                        self.processed_events.insert(event_key);
-                    }
+            if note_end_time >= min_time
+                && note_end_time <= max_time
+                && note_end_time > self.playback_position
+            {
+                // 检查是否已经调度过这个事件（使用毫秒精度避免浮点误差）
+                let time_ms = (note_end_time * 1000.0) as u64;
+                let event_key = (track_id, note.key, time_ms);
+                if !self.processed_events.contains(&event_key) {
+                    // 音符结束
+                    self.event_queue.push_back(ScheduledEvent {
+                        time: note_end_time,
+                        event: MidiEvent::NoteOff {
+                            track_index,
+                            key: note.key,
+                        },
+                    });
+                    self.processed_events.insert(event_key);
                 }
             }
         }
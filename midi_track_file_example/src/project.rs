@@ -3,7 +3,8 @@
 //! 处理项目的加载、保存、导出等文件操作。
 
 use crate::MidiTrackFileApp;
-use egui_track::{TrackEditor, ProjectFile};
+use egui_track::{stem_file_name, TrackEditor, TrackId, ProjectFile};
+use std::collections::HashSet;
 use std::path::PathBuf;
 use rfd::FileDialog;
 
@@ -28,17 +29,11 @@ impl MidiTrackFileApp {
                 // 暂时使用 execute_command 逐个创建轨道和剪辑
                 // TODO: 当 TrackEditor 提供批量设置方法时优化
                 
-                // 清除现有轨道
-                let existing_tracks: Vec<_> = self.track_editor.tracks().iter()
-                    .map(|t| t.id)
-                    .collect();
-                for track_id in existing_tracks {
-                    use egui_track::TrackEditorCommand;
-                    self.track_editor.execute_command(TrackEditorCommand::DeleteTrack { track_id });
-                }
+                // 清除现有轨道（程序化命令不经过删除确认）
+                use egui_track::TrackEditorCommand;
+                self.track_editor.execute_command(TrackEditorCommand::ClearAll);
                 
                 // 恢复轨道和剪辑
-                use egui_track::TrackEditorCommand;
                 for track in &project_file.tracks {
                     self.track_editor.execute_command(TrackEditorCommand::CreateTrack {
                         name: track.name.clone(),
@@ -62,6 +57,9 @@ impl MidiTrackFileApp {
                         track_id,
                         pan: track.pan,
                     });
+                    if let Some(color) = track.color {
+                        self.track_editor.execute_command(TrackEditorCommand::SetTrackColor { track_id, color });
+                    }
                     
                     // 恢复剪辑
                     for clip in &track.clips {
@@ -71,6 +69,19 @@ impl MidiTrackFileApp {
                             duration: clip.duration,
                             clip_type: clip.clip_type.clone(),
                         });
+                        let restored_id = self.track_editor.tracks().iter()
+                            .find(|t| t.id == track_id)
+                            .and_then(|t| t.clips.last())
+                            .map(|c| c.id);
+                        if let Some(clip_id) = restored_id {
+                            self.track_editor.execute_command(TrackEditorCommand::SetClipColor {
+                                clip_ids: vec![clip_id],
+                                color: clip.color,
+                            });
+                            if clip.muted {
+                                self.track_editor.execute_command(TrackEditorCommand::SetClipMute { clip_id, muted: true });
+                            }
+                        }
                     }
                 }
                 
@@ -125,6 +136,7 @@ impl MidiTrackFileApp {
             // 清除所有状态
             let options = egui_track::TrackEditorOptions::default();
             self.track_editor = TrackEditor::new(options);
+            self.track_editor.set_name_validator(crate::midiclip::validate_file_name);
             
             // 清除所有 MIDI 编辑器标签页
             self.midi_editors.clear();
@@ -193,11 +205,9 @@ impl MidiTrackFileApp {
         let mut tracks = self.track_editor.tracks().to_vec();
         for track in &mut tracks {
             for clip in &mut track.clips {
-                if let egui_track::ClipType::Midi { ref mut midi_data } = clip.clip_type {
-                    if let Some(ref mut midi_data) = midi_data {
-                        // 只使用文件路径，不保存 midi_state
-                        midi_data.midi_state = None;
-                    }
+                if let egui_track::ClipType::Midi { midi_data: Some(ref mut midi_data) } = clip.clip_type {
+                    // 只使用文件路径，不保存 midi_state
+                    midi_data.midi_state = None;
                 }
             }
         }
@@ -230,5 +240,86 @@ impl MidiTrackFileApp {
             log::info!("Project exported to: {:?}", path);
         }
     }
-}
 
+    /// 导出选中剪辑所在的第一条轨道为 .mid 文件
+    pub fn export_selected_track(&mut self) {
+        let Some(track_id) = self.track_editor.selected_tracks().first().copied() else {
+            log::warn!("Select a clip to choose the track to export");
+            return;
+        };
+        let smf = match self.track_editor.export_track_smf(track_id) {
+            Ok(smf) => smf,
+            Err(e) => {
+                log::error!("Failed to export track: {}", e);
+                return;
+            }
+        };
+        let file_name = format!("{}.mid", self.stem_name(track_id));
+        let mut dialog = FileDialog::new()
+            .add_filter("MIDI", &["mid"])
+            .set_title("Export Track")
+            .set_file_name(file_name);
+        if let Some(path) = &self.current_project_path {
+            dialog = dialog.set_directory(ProjectFile::get_export_dir(path));
+        }
+        if let Some(path) = dialog.save_file() {
+            match smf.save(&path) {
+                Ok(()) => log::info!("Track exported to: {:?}", path),
+                Err(e) => log::error!("Failed to write {:?}: {}", path, e),
+            }
+        }
+    }
+
+    /// 将选中剪辑覆盖的时间范围导出为分轨 .mid 文件（每条轨道一个，文件名由轨道名称生成）
+    pub fn export_selection_range_stems(&mut self) {
+        let selected: Vec<_> = self.track_editor.tracks().iter()
+            .flat_map(|track| track.clips.iter())
+            .filter(|clip| self.track_editor.selected_clips().contains(&clip.id))
+            .map(|clip| (clip.start_time, clip.end_time()))
+            .collect();
+        if selected.is_empty() {
+            log::warn!("Select clips to define the range to export");
+            return;
+        }
+        let start = selected.iter().map(|(start, _)| *start).fold(f64::INFINITY, f64::min);
+        let end = selected.iter().map(|(_, end)| *end).fold(0.0, f64::max);
+        let track_ids: Vec<TrackId> = self.track_editor.tracks().iter().map(|track| track.id).collect();
+        let stems = match self.track_editor.export_range_smf(start, end, &track_ids) {
+            Ok(stems) => stems,
+            Err(e) => {
+                log::error!("Failed to export stems: {}", e);
+                return;
+            }
+        };
+
+        let mut dialog = FileDialog::new().set_title("Export Stems To Folder");
+        if let Some(path) = &self.current_project_path {
+            dialog = dialog.set_directory(ProjectFile::get_export_dir(path));
+        }
+        let Some(dir) = dialog.pick_folder() else {
+            return;
+        };
+        let mut used_names = HashSet::new();
+        for (track_id, smf) in stems {
+            let base = self.stem_name(track_id);
+            let mut name = base.clone();
+            let mut suffix = 2;
+            while !used_names.insert(name.to_lowercase()) {
+                name = format!("{} ({})", base, suffix);
+                suffix += 1;
+            }
+            let path = dir.join(format!("{}.mid", name));
+            if let Err(e) = smf.save(&path) {
+                log::error!("Failed to write {:?}: {}", path, e);
+                return;
+            }
+        }
+        log::info!("Stems for {:.2}s - {:.2}s exported to: {:?}", start, end, dir);
+    }
+
+    fn stem_name(&self, track_id: TrackId) -> String {
+        let index = self.track_editor.tracks().iter().position(|track| track.id == track_id).unwrap_or(0);
+        let name = self.track_editor.tracks().get(index).map(|track| track.name.as_str()).unwrap_or("");
+        stem_file_name(name, &format!("Track {}", index + 1))
+    }
+}
@@ -7,6 +7,7 @@ use crate::midiclip;
 use eframe::egui;
 use egui_track::format_time;
 use egui_file_tree::FileTreeEvent;
+use egui_midi::editor::{ImportPlacement, NoteDragPayload};
 
 impl MidiTrackFileApp {
     /// 渲染新项目对话框
@@ -44,6 +45,171 @@ impl MidiTrackFileApp {
             });
     }
     
+    /// 渲染时间轴右键菜单（宿主自定义菜单示例）
+    pub fn render_timeline_context_menu(&mut self, ctx: &egui::Context) {
+        let Some((menu_pos, time)) = self.timeline_context_menu else {
+            return;
+        };
+        let menu_response = egui::Area::new(egui::Id::new("timeline_context_menu"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(menu_pos)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    if ui.button("Add marker here").clicked() {
+                        let position = self.track_editor.timeline().snap_time(time);
+                        let name = format!("Marker {}", self.track_editor.timeline().markers.len() + 1);
+                        self.track_editor.execute_command(egui_track::TrackEditorCommand::AddMarker {
+                            position,
+                            name,
+                            color: None,
+                        });
+                        self.timeline_context_menu = None;
+                    }
+                    if ui.button("Locate playhead here").clicked() {
+                        self.track_editor.execute_command(egui_track::TrackEditorCommand::SetPlayhead {
+                            position: time.max(0.0),
+                        });
+                        self.timeline_context_menu = None;
+                    }
+                });
+            });
+
+        // Close menu on click outside
+        if ctx.input(|i| i.pointer.primary_clicked() || i.pointer.secondary_clicked()) {
+            if let Some(click_pos) = ctx.input(|i| i.pointer.interact_pos()) {
+                if !menu_response.response.rect.contains(click_pos) {
+                    self.timeline_context_menu = None;
+                }
+            }
+        }
+    }
+
+    /// 渲染打开 .mid 文件时的替换 / 合并提示
+    pub fn render_midi_import_dialog(&mut self, ctx: &egui::Context) {
+        let Some(pending) = self.pending_midi_import.as_mut() else {
+            return;
+        };
+        let file_name = pending.path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("MIDI")
+            .to_string();
+        let mut answer = None;
+        
+        egui::Window::new("Open MIDI File")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("A MIDI editor is already active. Replace its content with {} or merge into it?", file_name));
+                ui.add_space(6.0);
+                
+                let options = &mut pending.options;
+                let mut at_playhead = options.placement == ImportPlacement::Playhead;
+                if ui.checkbox(&mut at_playhead, "Place at playhead").changed() {
+                    options.placement = if at_playhead { ImportPlacement::Playhead } else { ImportPlacement::Start };
+                }
+                ui.checkbox(&mut options.rescale_ticks, "Rescale to the clip's ticks per beat");
+                ui.horizontal(|ui| {
+                    ui.label("Transpose:");
+                    ui.add(egui::DragValue::new(&mut options.transpose).range(-48..=48).suffix(" st"));
+                });
+                ui.checkbox(&mut options.include_curves, "Include curve lanes");
+                
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Merge").clicked() {
+                        answer = Some(Some(true));
+                    }
+                    if ui.button("Replace").clicked() {
+                        answer = Some(Some(false));
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(None);
+                    }
+                });
+            });
+        
+        match answer {
+            Some(Some(merge)) => self.finish_midi_import(merge),
+            Some(None) => self.pending_midi_import = None,
+            None => {}
+        }
+    }
+    
+    /// 渲染关闭有未保存改动的标签页时的保存 / 放弃提示
+    pub fn render_close_tab_dialog(&mut self, ctx: &egui::Context) {
+        let Some(tab) = self.pending_tab_close.and_then(|index| self.midi_editors.get(index)) else {
+            self.pending_tab_close = None;
+            return;
+        };
+        let name = tab.name.clone();
+        let can_save = tab.file_path.is_some();
+        let mut answer = None;
+        
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} has unsaved changes. Save them before closing?", name));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if can_save && ui.button("Save").clicked() {
+                        answer = Some(Some(true));
+                    }
+                    if ui.button("Don't Save").clicked() {
+                        answer = Some(Some(false));
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(None);
+                    }
+                });
+            });
+        
+        let Some(answer) = answer else {
+            return;
+        };
+        let index = self.pending_tab_close.take().unwrap_or_default();
+        match answer {
+            Some(true) => match self.save_midi_editor(index) {
+                Ok(()) => self.close_midi_editor(index),
+                Err(e) => log::error!("Failed to save: {}", e),
+            },
+            Some(false) => self.close_midi_editor(index),
+            None => {}
+        }
+    }
+    
+    /// 渲染宽松导入 .mid 文件后跳过或修补内容的列表
+    pub fn render_midi_import_warnings(&mut self, ctx: &egui::Context) {
+        let Some((path, warnings)) = self.midi_import_warnings.as_ref() else {
+            return;
+        };
+        let file_name = path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("MIDI");
+        let mut close = false;
+        
+        egui::Window::new("MIDI Import Warnings")
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.label(format!("{} was opened, but some content was skipped or repaired:", file_name));
+                ui.add_space(6.0);
+                egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                    for warning in warnings {
+                        ui.label(format!("• {}", warning));
+                    }
+                });
+                ui.add_space(10.0);
+                if ui.button("OK").clicked() {
+                    close = true;
+                }
+            });
+        
+        if close {
+            self.midi_import_warnings = None;
+        }
+    }
+    
     /// 渲染顶部菜单栏
     pub fn render_menu_bar(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
@@ -70,6 +236,14 @@ impl MidiTrackFileApp {
                         self.export_project();
                         ui.close_menu();
                     }
+                    if ui.button("Export selected track…").clicked() {
+                        self.export_selected_track();
+                        ui.close_menu();
+                    }
+                    if ui.button("Export selection range as stems…").clicked() {
+                        self.export_selection_range_stems();
+                        ui.close_menu();
+                    }
                 });
                 
                 ui.menu_button("MIDI", |ui| {
@@ -77,11 +251,25 @@ impl MidiTrackFileApp {
                         self.add_midi_editor();
                         ui.close_menu();
                     }
+                    if ui.add_enabled(self.active_midi_tab.is_some(), egui::Button::new("Import into current clip…")).clicked() {
+                        self.import_into_current_clip();
+                        ui.close_menu();
+                    }
+                });
+                
+                ui.menu_button("Sync", |ui| {
+                    let mut synced = self.demo_clock.is_some();
+                    if ui.checkbox(&mut synced, "External MIDI clock (demo)").changed() {
+                        self.set_demo_clock_sync(synced);
+                        ui.close_menu();
+                    }
                 });
                 
                 // Playback controls
                 ui.separator();
-                if self.is_playing {
+                if self.demo_clock.is_some() {
+                    ui.label("⏱ Synced to external clock");
+                } else if self.is_playing {
                     if ui.button("⏸ Pause").clicked() {
                         self.playback_engine.pause();
                         self.is_playing = false;
@@ -137,6 +325,15 @@ impl MidiTrackFileApp {
 
                 let pos = self.track_editor.timeline().playhead_position;
                 ui.label(format!("Position: {}", format_time(pos)));
+
+                if let Some((path, loader)) = &self.midi_loading {
+                    ui.separator();
+                    let file_name = path.file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("MIDI");
+                    ui.label(format!("Loading {}", file_name));
+                    ui.add(egui::ProgressBar::new(loader.progress()).desired_width(120.0).show_percentage());
+                }
             });
         });
     }
@@ -213,7 +410,7 @@ impl MidiTrackFileApp {
                     }
                     
                     if let Some(dragging_path) = &self.dragging_file_path.clone() {
-                        if midiclip::is_midiclip_file(&dragging_path) {
+                        if midiclip::is_midiclip_file(dragging_path) {
                             let response = &track_editor_response.response;
                             
                             // 检查鼠标状态
@@ -601,25 +798,25 @@ impl MidiTrackFileApp {
                                                 self.file_tree_context_menu_path = None;
                                                 self.file_tree_context_menu_pos = None;
                                             }
-                                        } else if path.is_dir() {
-                                            if ui.button("New MIDI Clip").clicked() {
-                                                let mut new_path = path.join("clip.midiclip");
-                                                let mut counter = 1;
-                                                while new_path.exists() {
-                                                    new_path = path.join(format!("clip_{}.midiclip", counter));
-                                                    counter += 1;
+                                        } else if path.is_dir()
+                                            && ui.button("New MIDI Clip").clicked()
+                                        {
+                                            let mut new_path = path.join("clip.midiclip");
+                                            let mut counter = 1;
+                                            while new_path.exists() {
+                                                new_path = path.join(format!("clip_{}.midiclip", counter));
+                                                counter += 1;
+                                            }
+                                            match midiclip::create_midiclip_file(&new_path) {
+                                                Ok(_) => {
+                                                    log::info!("Created new MIDI clip: {:?}", new_path);
                                                 }
-                                                match midiclip::create_midiclip_file(&new_path) {
-                                                    Ok(_) => {
-                                                        log::info!("Created new MIDI clip: {:?}", new_path);
-                                                    }
-                                                    Err(e) => {
-                                                        log::error!("Failed to create MIDI clip: {:?}", e);
-                                                    }
+                                                Err(e) => {
+                                                    log::error!("Failed to create MIDI clip: {:?}", e);
                                                 }
-                                                self.file_tree_context_menu_path = None;
-                                                self.file_tree_context_menu_pos = None;
                                             }
+                                            self.file_tree_context_menu_path = None;
+                                            self.file_tree_context_menu_pos = None;
                                         }
                                     });
                                 });
@@ -706,13 +903,27 @@ impl MidiTrackFileApp {
                         
                         // Tab button with close button
                         ui.horizontal(|ui| {
-                            if ui.selectable_label(is_active, &tab.name).clicked() {
+                            // 有未保存的改动时在名称前显示 "*"
+                            let label = if tab.editor.is_dirty() { format!("*{}", tab.name) } else { tab.name.clone() };
+                            let tab_response = ui.selectable_label(is_active, label);
+                            if tab_response.clicked() {
+                                self.active_midi_tab = Some(index);
+                            }
+                            // 拖动音符（Ctrl+Shift 拖出编辑器）时悬停标签即切换，以便放到其他编辑器
+                            if !is_active
+                                && tab_response.contains_pointer()
+                                && egui::DragAndDrop::has_payload_of_type::<NoteDragPayload>(ui.ctx())
+                            {
                                 self.active_midi_tab = Some(index);
                             }
                             
                             // Close button
                             if ui.small_button("✕").clicked() {
-                                to_remove = Some(index);
+                                if tab.editor.is_dirty() {
+                                    self.pending_tab_close = Some(index);
+                                } else {
+                                    to_remove = Some(index);
+                                }
                             }
                         });
                     }
@@ -721,10 +932,14 @@ impl MidiTrackFileApp {
                     
                     // Save button (for active editor)
                     if let Some(active_index) = self.active_midi_tab {
-                        if self.midi_editors.get(active_index).and_then(|t| t.file_path.as_ref()).is_some() {
-                            if ui.button("💾 Save").clicked() {
-                                to_save = Some(active_index);
-                            }
+                        if self
+                            .midi_editors
+                            .get(active_index)
+                            .and_then(|t| t.file_path.as_ref())
+                            .is_some()
+                            && ui.button("💾 Save").clicked()
+                        {
+                            to_save = Some(active_index);
                         }
                     }
                     